@@ -0,0 +1,121 @@
+//! Measures the blocks/s of a `simplestats` run, to catch regressions in reading and
+//! decoding blocks. The binary parses a generated bootstrap file of full blocks,
+//! so the numbers include the process start and the index scan (a few milliseconds).
+//!
+//! `cargo bench --bench simplestats`
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use bitcoin::absolute::LockTime;
+use bitcoin::block::{Header, Version};
+use bitcoin::consensus::serialize;
+use bitcoin::hash_types::TxMerkleNode;
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    Block, BlockHash, CompactTarget, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
+};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+const N_BLOCKS: u64 = 100;
+/// Most transactions spend one or two inputs and create two outputs
+const TXS_PER_BLOCK: u32 = 2000;
+const MAGIC: u32 = 0xd9b4bef9;
+
+fn p2wpkh(seed: u32) -> ScriptBuf {
+    let mut script = vec![0x00, 0x14];
+    script.extend(seed.to_le_bytes().repeat(5));
+    ScriptBuf::from(script)
+}
+
+fn tx(height: u32, index: u32) -> Transaction {
+    let coinbase = index == 0;
+    let input = |vout: u32| TxIn {
+        previous_output: match coinbase {
+            true => OutPoint::null(),
+            false => OutPoint::new(Txid::from_byte_array([(index % 251) as u8; 32]), vout),
+        },
+        script_sig: match coinbase {
+            true => ScriptBuf::from(height.to_le_bytes().to_vec()),
+            false => ScriptBuf::new(),
+        },
+        sequence: Sequence::MAX,
+        witness: match coinbase {
+            true => Witness::new(),
+            false => Witness::from_slice(&[vec![0x30; 71], vec![0x02; 33]]),
+        },
+    };
+    let n_inputs = if coinbase { 1 } else { 1 + index % 2 };
+    Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: (0..n_inputs).map(input).collect(),
+        output: (0..2)
+            .map(|i| TxOut {
+                value: 10_000 + index as u64,
+                script_pubkey: p2wpkh(height ^ index ^ i),
+            })
+            .collect(),
+    }
+}
+
+/// Writes a chain of `N_BLOCKS` blocks in the format of bootstrap.dat
+fn write_bootstrap(path: &Path) {
+    let mut writer = BufWriter::new(File::create(path).unwrap());
+    let mut prev_blockhash = BlockHash::all_zeros();
+    for height in 0..N_BLOCKS as u32 {
+        let mut block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_600_000_000 + height * 600,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata: (0..TXS_PER_BLOCK).map(|i| tx(height, i)).collect(),
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        prev_blockhash = block.block_hash();
+
+        let data = serialize(&block);
+        writer.write_all(&MAGIC.to_le_bytes()).unwrap();
+        writer
+            .write_all(&(data.len() as u32).to_le_bytes())
+            .unwrap();
+        writer.write_all(&data).unwrap();
+    }
+    writer.flush().unwrap();
+}
+
+fn simplestats(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bootstrap.dat");
+    write_bootstrap(&path);
+
+    let mut group = c.benchmark_group("simplestats");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+    group.throughput(Throughput::Elements(N_BLOCKS));
+    group.bench_function("blocks", |b| {
+        b.iter(|| {
+            let status = Command::new(env!("CARGO_BIN_EXE_rusty-blockparser"))
+                .arg("--bootstrap")
+                .arg(&path)
+                .arg("simplestats")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, simplestats);
+criterion_main!(benches);
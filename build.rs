@@ -0,0 +1,15 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // The protobuf types are generated from the schema, so both can't drift apart
+    #[cfg(feature = "protobuf")]
+    {
+        println!("cargo:rerun-if-changed=proto/blockchain.proto");
+        // A protoc from PATH would need to be installed, PROTOC still takes precedence
+        if std::env::var_os("PROTOC").is_none() {
+            let protoc = protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc");
+            std::env::set_var("PROTOC", protoc);
+        }
+        prost_build::compile_protos(&["proto/blockchain.proto"], &["proto"])
+            .expect("unable to generate the protobuf types");
+    }
+}
@@ -22,6 +22,7 @@ CREATE TABLE `blocks` (
   `nTime` 			int(10) unsigned                    NOT NULL,
   `nBits` 			int(10) unsigned                    NOT NULL,
   `nNonce` 			int(10) unsigned                    NOT NULL,
+  `chainWork` 		binary(32)                          NULL,
 
   PRIMARY KEY (`id`)
 ) ENGINE=InnoDB;
@@ -35,6 +36,15 @@ CREATE TABLE `transactions` (
   `hashBlock`       binary(32)                          NOT NULL,
   `version`         int(11) unsigned               		NOT NULL,
   `lockTime`        int(10) unsigned     				NOT NULL,
+  `inputValue`      bigint(8) unsigned            		DEFAULT NULL,
+  `outputValue`     bigint(8) unsigned            		NOT NULL,
+  `fee`             bigint(8) unsigned            		DEFAULT NULL,
+  `size`            int(10) unsigned                    NOT NULL,
+  `vsize`           int(10) unsigned                    NOT NULL,
+  `weight`          int(10) unsigned                    NOT NULL,
+  `height`          int(10) unsigned                    NOT NULL,
+  `txIndex`         int(10) unsigned                    NOT NULL,
+  `isCoinbase`      tinyint(1) unsigned                 NOT NULL,
 
   PRIMARY KEY (`id`)
 ) ENGINE=InnoDB;
@@ -64,6 +74,7 @@ CREATE TABLE `tx_in` (
   `indexPrevOut`    int(10) unsigned                    NOT NULL,
   `scriptSig`       blob                                NOT NULL,
   `sequence`        int(10) unsigned                    NOT NULL,
+  `pattern`         varchar(40)                         NOT NULL,
 
   PRIMARY KEY (`id`)
 ) ENGINE=InnoDB;
@@ -97,10 +108,11 @@ LOAD DATA INFILE '/media/tmp/dump/blocks-0-393489.csv'
 INTO TABLE blocks
 FIELDS TERMINATED BY ';'
 LINES TERMINATED BY '\n'
-(@hash, height, version, blocksize, @hashPrev, @hashMerkleRoot, nTime, nBits, nNonce)
+(@hash, height, version, blocksize, @hashPrev, @hashMerkleRoot, nTime, nBits, nNonce, @chainWork)
 SET hash = unhex(@hash),
 	hashPrev = unhex(@hashPrev),
-    hashMerkleRoot = unhex(@hashMerkleRoot);
+    hashMerkleRoot = unhex(@hashMerkleRoot),
+    chainWork = unhex(NULLIF(@chainWork, ''));
 COMMIT;
 
 
@@ -110,9 +122,11 @@ LOAD DATA INFILE '/media/tmp/dump/transactions-0-393489.csv'
 INTO TABLE transactions
 FIELDS TERMINATED BY ';'
 LINES TERMINATED BY '\n'
-(@txid, @hashBlock, version, lockTime)
+(@txid, @hashBlock, version, lockTime, @inputValue, outputValue, @fee, size, vsize, weight, height, txIndex, isCoinbase)
 SET txid = unhex(@txid),
-	hashBlock = unhex(@hashBlock);
+	hashBlock = unhex(@hashBlock),
+	inputValue = nullif(@inputValue, ''),
+	fee = nullif(@fee, '');
 COMMIT;
 
 
@@ -134,7 +148,7 @@ LOAD DATA INFILE '/media/tmp/dump/tx_in-0-393489.csv'
 INTO TABLE tx_in
 FIELDS TERMINATED BY ';'
 LINES TERMINATED BY '\n'
-(@txid, @hashPrevOut, indexPrevOut, scriptSig, sequence)
+(@txid, @hashPrevOut, indexPrevOut, scriptSig, sequence, pattern)
 SET txid = unhex(@txid),
 	hashPrevOut = unhex(@hashPrevOut);
 COMMIT;
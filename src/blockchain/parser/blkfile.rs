@@ -1,43 +1,170 @@
 use std::collections::HashMap;
 use std::convert::From;
 use std::fs::{self, DirEntry, File};
-use std::io::{self, Seek, SeekFrom};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
 use byteorder::{LittleEndian, ReadBytesExt};
 use seek_bufread::BufReader;
 
-use crate::blockchain::parser::reader::BlockchainRead;
+use crate::blockchain::parser::reader::{BlockchainRead, ReadLimits};
 use crate::blockchain::parser::types::CoinType;
-use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::varuint::VarUint;
+use crate::blockchain::proto::{BlockHash, Hashed};
+use crate::common::utils;
 use crate::errors::{OpError, OpErrorKind, OpResult};
 
+/// Name of the file holding the key blk and rev files are obfuscated with (Bitcoin Core 28+)
+const XOR_FILE_NAME: &str = "xor.dat";
+
+/// Selects the key to deobfuscate blk and rev files with (see `--xor-key` and `--no-xor`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum XorKey {
+    /// Uses xor.dat from the blocks directory if present
+    Auto,
+    Key([u8; 8]),
+    Disabled,
+}
+
+impl XorKey {
+    /// Returns the effective key for the given blocks directory, None if the files are not obfuscated
+    fn resolve(&self, path: &Path) -> OpResult<Option<[u8; 8]>> {
+        let key = match self {
+            XorKey::Auto => {
+                let xor_path = path.join(XOR_FILE_NAME);
+                if !xor_path.is_file() {
+                    return Ok(None);
+                }
+                let key: [u8; 8] = fs::read(&xor_path)?.try_into().map_err(|_| {
+                    let msg = format!("{} must contain exactly 8 bytes", xor_path.display());
+                    OpError::new(OpErrorKind::RuntimeError).join_msg(&msg)
+                })?;
+                key
+            }
+            XorKey::Key(key) => *key,
+            XorKey::Disabled => return Ok(None),
+        };
+        // An all-zero key is a no-op
+        Ok(Some(key).filter(|key| key.iter().any(|b| *b != 0)))
+    }
+}
+
+/// File which is transparently deobfuscated with a repeating 8 byte key while reading
+#[derive(Debug)]
+struct XorFile<R = File> {
+    file: R,
+    key: Option<[u8; 8]>,
+    pos: u64,
+}
+
+impl<R: Read> Read for XorFile<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read(buf)?;
+        if let Some(key) = &self.key {
+            for (i, byte) in buf[..n].iter_mut().enumerate() {
+                *byte ^= key[((self.pos + i as u64) % 8) as usize];
+            }
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for XorFile<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = self.file.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// Block found by scanning a file sequentially, see `BlkFile::scan`
+pub struct ScannedBlock {
+    pub header: Hashed<BlockHeader>,
+    pub data_offset: u64, // offset of the block data, behind magic and size
+}
+
 /// Holds all necessary data about a raw blk file
 #[derive(Debug)]
 pub struct BlkFile {
     pub path: PathBuf,
     pub size: u64,
-    reader: Option<BufReader<File>>,
+    xor_key: Option<[u8; 8]>,
+    reader: Option<BufReader<XorFile>>,
 }
 
 impl BlkFile {
-    fn new(path: PathBuf, size: u64) -> BlkFile {
+    fn new(path: PathBuf, size: u64, xor_key: Option<[u8; 8]>) -> BlkFile {
         BlkFile {
             path,
             size,
+            xor_key,
             reader: None,
         }
     }
 
     /// Opens the file handle (does nothing if the file has been opened already)
-    fn open(&mut self) -> OpResult<&mut BufReader<File>> {
+    fn open(&mut self) -> OpResult<&mut BufReader<XorFile>> {
         if self.reader.is_none() {
             debug!(target: "blkfile", "Opening {} ...", &self.path.display());
-            self.reader = Some(BufReader::new(File::open(&self.path)?));
+            let file = XorFile {
+                file: File::open(&self.path)?,
+                key: self.xor_key,
+                pos: 0,
+            };
+            self.reader = Some(BufReader::new(file));
         }
         Ok(self.reader.as_mut().unwrap())
     }
 
+    /// Seeks to the record at the given offset, checks the magic in front of it and returns its size.
+    /// Offsets are u64 throughout, so files larger than 4 GiB work as well. The size field
+    /// of a record is a u32 by format, which limits a single record to 4 GiB.
+    /// Sizes beyond the end of the file are rejected, so a damaged size field can't make
+    /// the caller allocate up to 4 GiB.
+    fn seek_record(&mut self, offset: u64, magic: u32) -> OpResult<u32> {
+        let record_offset = offset.checked_sub(8).ok_or_else(|| {
+            let msg = format!("Invalid offset {} in {}", offset, self.path.display());
+            OpError::new(OpErrorKind::ValidationError).join_msg(&msg)
+        })?;
+        let obfuscated = self.xor_key.is_some();
+        let reader = self.open()?;
+        reader.seek(SeekFrom::Start(record_offset))?;
+        let found = reader.read_u32::<LittleEndian>()?;
+        if found != magic {
+            let hint = match obfuscated {
+                true => "the xor key is probably wrong, check --xor-key or try --no-xor",
+                false => "the file is probably obfuscated, copy xor.dat into the blocks directory or pass --xor-key",
+            };
+            let msg = format!(
+                "Invalid magic {:#010x} in {} at offset {} (expected {:#010x}), {}",
+                found,
+                self.path.display(),
+                record_offset,
+                magic,
+                hint
+            );
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
+        let size = reader.read_u32::<LittleEndian>()?;
+        if offset + size as u64 > self.size {
+            // The node may have appended to the file since it was listed
+            self.size = fs::metadata(&self.path)?.len();
+            if offset + size as u64 > self.size {
+                let msg = format!(
+                    "Record size {} at offset {} exceeds the end of {} ({} bytes)",
+                    size,
+                    record_offset,
+                    self.path.display(),
+                    self.size
+                );
+                return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+            }
+        }
+        Ok(size)
+    }
+
     /// Closes the file handle
     pub fn close(&mut self) {
         debug!(target: "blkfile", "Closing {} ...", &self.path.display());
@@ -46,16 +173,198 @@ impl BlkFile {
         }
     }
 
-    pub fn read_block(&mut self, offset: u64, coin: &CoinType) -> OpResult<Block> {
+    /// Reads the raw block at the given offset into `buf` and returns its size.
+    /// The buffer is reused by the caller, so its allocation is amortized over all blocks.
+    /// Records larger than `coin.max_block_size` are rejected before anything is allocated.
+    pub fn read_raw_block(
+        &mut self,
+        offset: u64,
+        coin: &CoinType,
+        buf: &mut Vec<u8>,
+    ) -> OpResult<u32> {
+        let block_size = self.seek_record(offset, coin.magic)?;
+        if block_size > coin.max_block_size {
+            let msg = format!(
+                "Block size {} in {} at offset {} exceeds the maximum of {} bytes for {}",
+                block_size,
+                self.path.display(),
+                offset,
+                coin.max_block_size,
+                coin.name
+            );
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
+        buf.clear();
+        buf.resize(block_size as usize, 0);
+        self.open()?.read_exact(buf)?;
+        Ok(block_size)
+    }
+
+    /// Reads only the header of the block at the given offset along with the block size
+    /// and the tx count. AuxPow data in between is skipped.
+    pub fn read_block_header(
+        &mut self,
+        offset: u64,
+        coin: &CoinType,
+    ) -> OpResult<(u32, BlockHeader, VarUint)> {
+        let block_size = self.seek_record(offset, coin.magic)?;
+        let reader = self.open()?;
+        let header = reader.read_coin_block_header(&coin.header_layout)?;
+        if let Some(version) = coin.aux_pow_activation_version {
+            if header.version >= version {
+                reader.read_aux_pow_extension(coin.version_id, &ReadLimits::from(coin))?;
+            }
+        }
+        let tx_count = VarUint::read_from(reader)?;
+        Ok((block_size, header, tx_count))
+    }
+
+    /// Reads the raw undo data at the given offset of a rev file into `buf`.
+    /// If `prev_hash` is given, the checksum stored after the undo data is verified.
+    pub fn read_raw_block_undo(
+        &mut self,
+        offset: u64,
+        magic: u32,
+        buf: &mut Vec<u8>,
+        prev_hash: Option<&BlockHash>,
+    ) -> OpResult<()> {
+        let undo_size = self.seek_record(offset, magic)?;
         let reader = self.open()?;
-        reader.seek(SeekFrom::Start(offset - 4))?;
-        let block_size = reader.read_u32::<LittleEndian>()?;
-        reader.read_block(block_size, coin)
+        buf.clear();
+        buf.resize(undo_size as usize, 0);
+        reader.read_exact(buf)?;
+
+        if let Some(prev_hash) = prev_hash {
+            // The checksum commits to the previous block hash and the undo data
+            let checksum = sha256d::Hash::from_byte_array(reader.read_256hash()?);
+            let mut engine = sha256d::Hash::engine();
+            engine.input(prev_hash.as_byte_array());
+            engine.input(buf);
+            let expected = sha256d::Hash::from_engine(engine);
+            if checksum != expected {
+                let msg = format!(
+                    "Undo checksum in {} doesn't match!\n  -> expected: {}\n  -> got: {}\n",
+                    &self.path.display(),
+                    &expected,
+                    &checksum
+                );
+                return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads all blocks of the file sequentially and returns their headers along with the offsets.
+    /// Stops at the end of the file or at zero padding, a truncated last block is skipped.
+    pub fn scan(&mut self, coin: &CoinType) -> OpResult<Vec<ScannedBlock>> {
+        let path = self.path.clone();
+        let file_size = self.size;
+        let reader = self.open()?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= file_size {
+            let magic = reader.read_u32::<LittleEndian>()?;
+            if magic == 0 {
+                debug!(target: "blkfile", "Found zero padding in {} at offset {}", path.display(), offset);
+                break;
+            }
+            if magic != coin.magic {
+                let msg = format!(
+                    "Invalid magic {:#010x} in {} at offset {} (expected {:#010x})",
+                    magic,
+                    path.display(),
+                    offset,
+                    coin.magic
+                );
+                return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+            }
+            let size = reader.read_u32::<LittleEndian>()?;
+            let data_offset = offset + 8;
+            if data_offset + size as u64 > file_size {
+                warn!(target: "blkfile", "Skipping truncated block in {} at offset {}", path.display(), offset);
+                break;
+            }
+
+            let header = reader.read_coin_block_header(&coin.header_layout)?;
+            blocks.push(ScannedBlock {
+                header: Hashed::with(header, coin.block_hash),
+                data_offset,
+            });
+
+            offset = data_offset + size as u64;
+            reader.seek(SeekFrom::Start(offset))?;
+        }
+        self.close();
+        Ok(blocks)
+    }
+
+    /// Opens a single file with concatenated blocks, e.g. a bootstrap.dat.
+    /// Such files are never obfuscated.
+    pub fn from_file(path: &Path) -> OpResult<BlkFile> {
+        if !path.is_file() {
+            let msg = format!("{} is not a file", path.display());
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+        }
+        let size = fs::metadata(path)?.len();
+        Ok(BlkFile::new(path.to_path_buf(), size, None))
+    }
+
+    /// Opens a single blk file outside of a blocks directory, e.g. a damaged copy.
+    /// With `XorKey::Auto` the xor.dat next to the file is used.
+    pub fn standalone(path: &Path, xor_key: XorKey) -> OpResult<BlkFile> {
+        let mut blk_file = BlkFile::from_file(path)?;
+        blk_file.xor_key = xor_key.resolve(path.parent().unwrap_or(Path::new("")))?;
+        Ok(blk_file)
+    }
+
+    /// Reads the whole deobfuscated file
+    pub fn read_all(&mut self) -> OpResult<Vec<u8>> {
+        let capacity = usize::try_from(self.size).map_err(|_| {
+            let msg = format!(
+                "{} is too large to be read into memory",
+                self.path.display()
+            );
+            OpError::new(OpErrorKind::RuntimeError).join_msg(&msg)
+        })?;
+        let mut data = Vec::with_capacity(capacity);
+        let reader = self.open()?;
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_to_end(&mut data)?;
+        self.close();
+        Ok(data)
     }
 
     /// Collects all blk*.dat paths in the given directory
-    pub fn from_path(path: &Path) -> OpResult<HashMap<u64, BlkFile>> {
-        info!(target: "blkfile", "Reading files from {} ...", path.display());
+    pub fn from_path(path: &Path, xor_key: XorKey) -> OpResult<HashMap<u64, BlkFile>> {
+        BlkFile::collect(path, "blk", ".dat", xor_key)
+    }
+
+    /// Collects all rev*.dat paths in the given directory
+    pub fn undo_from_path(path: &Path, xor_key: XorKey) -> OpResult<HashMap<u64, BlkFile>> {
+        BlkFile::collect(path, "rev", ".dat", xor_key)
+    }
+
+    /// Collects all *.fdb paths of btcd's ffldb in the given directory.
+    /// Each block is followed by a crc32 checksum, the files are never obfuscated.
+    pub fn btcd_from_path(path: &Path) -> OpResult<HashMap<u64, BlkFile>> {
+        BlkFile::collect(path, "", ".fdb", XorKey::Disabled)
+    }
+
+    /// Collects all {prefix}*{ext} paths in the given directory
+    fn collect(
+        path: &Path,
+        prefix: &str,
+        ext: &str,
+        xor_key: XorKey,
+    ) -> OpResult<HashMap<u64, BlkFile>> {
+        let pattern = format!("{}*{}", prefix, ext);
+        info!(target: "blkfile", "Reading {} files from {} ...", pattern, path.display());
+        let xor_key = xor_key.resolve(path)?;
+        if let Some(key) = &xor_key {
+            info!(target: "blkfile", "Deobfuscating {} files with key {}", pattern, utils::arr_to_hex(key));
+        }
         let mut collected = HashMap::with_capacity(4000);
 
         for entry in fs::read_dir(path)? {
@@ -66,25 +375,34 @@ impl BlkFile {
                         continue;
                     }
 
-                    let file_name =
+                    let mut file_name =
                         String::from(transform!(path.as_path().file_name().unwrap().to_str()));
+                    // File names are case-insensitive on Windows
+                    if cfg!(windows) {
+                        file_name.make_ascii_lowercase();
+                    }
                     // Check if it's a valid blk file
-                    if let Some(index) = BlkFile::parse_blk_index(&file_name, "blk", ".dat") {
+                    if let Some(index) = BlkFile::parse_blk_index(&file_name, prefix, ext) {
                         // Build BlkFile structures
                         let size = fs::metadata(path.as_path())?.len();
                         trace!(target: "blkfile", "Adding {} ... (index: {}, size: {})", path.display(), index, size);
-                        collected.insert(index, BlkFile::new(path, size));
+                        collected.insert(index, BlkFile::new(path, size, xor_key));
                     }
                 }
                 Err(msg) => {
-                    warn!(target: "blkfile", "Unable to read blk file!: {}", msg);
+                    warn!(target: "blkfile", "Unable to read {} file!: {}", pattern, msg);
                 }
             }
         }
 
-        trace!(target: "blkfile", "Found {} blk files", collected.len());
+        trace!(target: "blkfile", "Found {} {} files", collected.len(), pattern);
         if collected.is_empty() {
-            Err(OpError::new(OpErrorKind::RuntimeError).join_msg("No blk files found!"))
+            let msg = format!(
+                "Directory {} exists but contains no {} files",
+                path.display(),
+                pattern
+            );
+            Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg))
         } else {
             Ok(collected)
         }
@@ -116,7 +434,10 @@ impl BlkFile {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+
     use super::*;
+    use crate::common::fixtures::MiniChain;
 
     #[test]
     fn test_parse_blk_index() {
@@ -148,4 +469,199 @@ mod tests {
             BlkFile::parse_blk_index("invalid.dat", prefix, ext).is_none()
         );
     }
+
+    #[test]
+    fn test_collect_no_blk_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("blk00000.tmp"), b"").unwrap();
+        let err = BlkFile::from_path(dir.path(), XorKey::Auto).err().unwrap();
+        assert!(err.to_string().contains("contains no blk*.dat files"));
+
+        fs::write(dir.path().join("blk00000.dat"), b"").unwrap();
+        assert_eq!(
+            BlkFile::from_path(dir.path(), XorKey::Auto).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_xor_key() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(XorKey::Auto.resolve(dir.path()).unwrap(), None);
+
+        let key = [1, 2, 3, 4, 5, 6, 7, 8];
+        fs::write(dir.path().join(XOR_FILE_NAME), key).unwrap();
+        assert_eq!(XorKey::Auto.resolve(dir.path()).unwrap(), Some(key));
+        assert_eq!(XorKey::Disabled.resolve(dir.path()).unwrap(), None);
+        assert_eq!(XorKey::Key([0; 8]).resolve(dir.path()).unwrap(), None);
+
+        fs::write(dir.path().join(XOR_FILE_NAME), [1, 2, 3]).unwrap();
+        assert!(XorKey::Auto.resolve(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_xor_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blk00000.dat");
+        let key = [1, 2, 3, 4, 5, 6, 7, 8];
+        let data: Vec<u8> = (0..20u8).map(|b| b ^ key[b as usize % 8]).collect();
+        fs::write(&path, data).unwrap();
+
+        let mut file = XorFile {
+            file: File::open(&path).unwrap(),
+            key: Some(key),
+            pos: 0,
+        };
+        file.seek(SeekFrom::Start(13)).unwrap();
+        let mut buf = [0; 5];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [13, 14, 15, 16, 17]);
+    }
+
+    /// Reads as zeros up to `offset`, followed by `data`, without holding the zeros in memory
+    struct SparseReader {
+        offset: u64,
+        data: Vec<u8>,
+        pos: u64,
+    }
+
+    impl Read for SparseReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = match self.pos.checked_sub(self.offset) {
+                Some(start) => {
+                    let rest = self.data.get(start as usize..).unwrap_or_default();
+                    let n = rest.len().min(buf.len());
+                    buf[..n].copy_from_slice(&rest[..n]);
+                    n
+                }
+                None => {
+                    let n = (self.offset - self.pos).min(buf.len() as u64) as usize;
+                    buf[..n].fill(0);
+                    n
+                }
+            };
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for SparseReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            let len = self.offset + self.data.len() as u64;
+            self.pos = match pos {
+                SeekFrom::Start(pos) => pos,
+                SeekFrom::End(delta) => len.checked_add_signed(delta).unwrap(),
+                SeekFrom::Current(delta) => self.pos.checked_add_signed(delta).unwrap(),
+            };
+            Ok(self.pos)
+        }
+    }
+
+    #[test]
+    fn test_xor_offset_above_4gib() {
+        let chain = MiniChain::generate(1);
+        let record = fs::read(chain.path().join("blk00000.dat")).unwrap();
+
+        // Genesis record behind the first 4 GiB, at an offset which is not a multiple
+        // of the key length
+        let key = [1, 2, 3, 4, 5, 6, 7, 8];
+        let record_offset = (4 << 30) + 13;
+        let obfuscated: Vec<u8> = (record_offset..)
+            .zip(&record)
+            .map(|(pos, b)| b ^ key[(pos % 8) as usize])
+            .collect();
+        let file = XorFile {
+            file: SparseReader {
+                offset: record_offset,
+                data: obfuscated,
+                pos: 0,
+            },
+            key: Some(key),
+            pos: 0,
+        };
+        let mut reader = BufReader::new(file);
+        assert_eq!(
+            reader.seek(SeekFrom::End(0)).unwrap(),
+            record_offset + record.len() as u64
+        );
+        reader.seek(SeekFrom::Start(record_offset)).unwrap();
+        assert_eq!(reader.read_u32::<LittleEndian>().unwrap(), 0xd9b4bef9);
+        let size = reader.read_u32::<LittleEndian>().unwrap();
+        assert_eq!(size as usize, record.len() - 8);
+        let header = reader.read_block_header().unwrap();
+        assert_eq!(header.bits, 0x207fffff);
+
+        // Relative seeks keep the key position in sync
+        reader.seek(SeekFrom::Current(-80)).unwrap();
+        let mut buf = vec![0; size as usize];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, record[8..]);
+    }
+
+    #[test]
+    #[ignore = "writes a sparse file of 4 GiB, which takes that much disk space on filesystems without sparse files"]
+    fn test_offset_above_4gib() {
+        let chain = MiniChain::generate(1);
+        let record = fs::read(chain.path().join("blk00000.dat")).unwrap();
+        let coin = CoinType::default();
+
+        // Sparse file with the genesis record behind the first 4 GiB, at an offset which
+        // is not a multiple of the key length
+        let dir = tempfile::tempdir().unwrap();
+        let key = [1, 2, 3, 4, 5, 6, 7, 8];
+        let record_offset = (4 << 30) + 13;
+        let mut file = File::create(dir.path().join("blk00000.dat")).unwrap();
+        file.seek(SeekFrom::Start(record_offset)).unwrap();
+        let obfuscated: Vec<u8> = (record_offset..)
+            .zip(&record)
+            .map(|(pos, b)| b ^ key[(pos % 8) as usize])
+            .collect();
+        file.write_all(&obfuscated).unwrap();
+        drop(file);
+
+        let mut blk_files = BlkFile::from_path(dir.path(), XorKey::Key(key)).unwrap();
+        let blk_file = blk_files.get_mut(&0).unwrap();
+        assert_eq!(blk_file.size, record_offset + record.len() as u64);
+
+        let mut buf = Vec::new();
+        let size = blk_file
+            .read_raw_block(record_offset + 8, &coin, &mut buf)
+            .unwrap();
+        assert_eq!(size as usize, record.len() - 8);
+        assert_eq!(buf, record[8..]);
+        let (_, header, tx_count) = blk_file
+            .read_block_header(record_offset + 8, &coin)
+            .unwrap();
+        assert_eq!(header.bits, 0x207fffff);
+        assert_eq!(tx_count.value, 1);
+
+        // Offsets in front of the first record can't be valid
+        assert!(blk_file.read_raw_block(4, &coin, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_record_beyond_end() {
+        let chain = MiniChain::generate(1);
+        let mut record = fs::read(chain.path().join("blk00000.dat")).unwrap();
+        let coin = CoinType::default();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blk00000.dat");
+
+        let size = record.len() as u32 - 8 + 1;
+        record[4..8].copy_from_slice(&size.to_le_bytes());
+        fs::write(&path, &record).unwrap();
+        let mut blk_file = BlkFile::from_file(&path).unwrap();
+        let mut buf = Vec::new();
+        let err = blk_file.read_raw_block(8, &coin, &mut buf).err().unwrap();
+        assert!(err.to_string().contains("exceeds the end of"));
+
+        // Undo records have no size limit of their own
+        record[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &record).unwrap();
+        let mut blk_file = BlkFile::from_file(&path).unwrap();
+        assert!(blk_file
+            .read_raw_block_undo(8, coin.magic, &mut buf, None)
+            .is_err());
+        assert!(buf.capacity() < record.len());
+    }
 }
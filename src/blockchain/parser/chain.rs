@@ -1,55 +1,364 @@
-use std::collections::HashMap;
+use bitcoin::hashes::Hash;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Instant;
 
 use crate::blockchain::parser::blkfile::BlkFile;
-use crate::blockchain::parser::index::ChainIndex;
-use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::parser::index::manifest::Manifest;
+use crate::blockchain::parser::index::{self, ChainIndex};
+use crate::blockchain::parser::reader::{BlockchainRead, ReadLimits};
+use crate::blockchain::parser::types::{CoinType, PowAlgorithm};
+use crate::blockchain::parser::unknown::UnknownConstructs;
+use crate::blockchain::parser::Timings;
 use crate::blockchain::proto::block::Block;
+use crate::common::utils;
 use crate::errors::{OpError, OpErrorKind, OpResult};
 use crate::ParserOptions;
 
+/// Initial capacity of the raw block buffer, large enough for most blocks
+const BLOCK_BUFFER_CAPACITY: usize = 4 * 1024 * 1024;
+
+/// Block which could not be read or parsed
+pub struct CorruptBlock {
+    pub height: u64,
+    pub path: PathBuf, // blk or rev file
+    pub offset: u64,
+    pub error: OpError,
+}
+
+impl CorruptBlock {
+    fn new(height: u64, file: &BlkFile, offset: u64, error: OpError) -> Self {
+        Self {
+            height,
+            path: file.path.clone(),
+            offset,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for CorruptBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Corrupt block {} in {} at offset {}: {}",
+            self.height,
+            self.path.display(),
+            self.offset,
+            self.error
+        )
+    }
+}
+
+/// What to do if a block doesn't contain the number of transactions recorded in the block index,
+/// see `--check-tx-count`. A mismatch means the block was parsed wrong, e.g. a misread AuxPoW header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxCountCheck {
+    // Logs the height and reports all mismatches at the end
+    Warn,
+    // Treats the block as corrupt
+    Fail,
+}
+
+/// Compares the number of parsed transactions with the block index, if it records them.
+/// Mismatching heights are collected in `mismatches` in warn mode.
+fn check_tx_count(
+    mode: Option<TxCountCheck>,
+    mismatches: &mut Vec<u64>,
+    block: &Block,
+    expected: Option<u64>,
+    height: u64,
+) -> OpResult<()> {
+    let (Some(mode), Some(expected)) = (mode, expected) else {
+        return Ok(());
+    };
+    if block.tx_count.value == expected {
+        return Ok(());
+    }
+    let msg = format!(
+        "Block {} at height {} contains {} transactions, but the block index records {}",
+        &block.header.hash, height, block.tx_count.value, expected
+    );
+    match mode {
+        TxCountCheck::Warn => {
+            warn!(target: "chain", "{}", msg);
+            mismatches.push(height);
+            Ok(())
+        }
+        TxCountCheck::Fail => Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg)),
+    }
+}
+
 /// Manages the index and data of longest valid chain
 pub struct ChainStorage {
     chain_index: ChainIndex,
     blk_files: HashMap<u64, BlkFile>, // maps blk_index to BlkFile
+    undo_files: Option<HashMap<u64, BlkFile>>, // maps blk_index to rev file, if enabled
     coin: CoinType,
     verify: bool,
+    headers_only: bool,   // skips transactions and undo data
+    eval_addresses: bool, // evaluates the addresses of outputs
+    buffer: Vec<u8>,      // reusable buffer for raw block data
+    timings: Timings,     // time spent reading and decoding blocks
+    bytes_read: u64,      // block and undo data read so far
+    unknown: UnknownConstructs,
+    strict_parse: bool, // treats blocks with unknown constructs as corrupt
+    skipped_pow: BTreeMap<String, u64>, // blocks per algorithm whose proof of work can't be verified
+    check_tx_count: Option<TxCountCheck>,
+    tx_count_mismatches: Vec<u64>, // heights of blocks not matching the tx count of the index
 }
 
 impl ChainStorage {
     pub fn new(options: &ParserOptions) -> OpResult<Self> {
+        let (blk_files, undo_files) = match &options.bootstrap {
+            // All blocks of a bootstrap file are indexed as blk_index 0, there is no undo data
+            Some(path) => (HashMap::from([(0, BlkFile::from_file(path)?)]), None),
+            None => {
+                utils::check_dir(&options.blockchain_dir)?;
+                let dir = options.blockchain_dir.as_path();
+                // Look for blk files first, reading the index takes a while
+                if index::btcd::is_btcd_dir(dir) {
+                    if options.use_undo_files {
+                        let msg = "btcd stores no undo data, --use-undo-files is not supported";
+                        return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(msg));
+                    }
+                    if options.manifest.is_some() {
+                        let msg =
+                            "Manifests only cover blk files, --manifest is not supported for btcd";
+                        return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(msg));
+                    }
+                    (BlkFile::btcd_from_path(dir)?, None)
+                } else {
+                    let blk_files = BlkFile::from_path(dir, options.xor_key)?;
+                    if let Some(path) = &options.manifest {
+                        Manifest::load(path)?.verify(&blk_files)?;
+                    }
+                    let undo_files = match options.use_undo_files {
+                        true => Some(BlkFile::undo_from_path(dir, options.xor_key)?),
+                        false => None,
+                    };
+                    (blk_files, undo_files)
+                }
+            }
+        };
         Ok(Self {
-            chain_index: ChainIndex::new(options)?,
-            blk_files: BlkFile::from_path(options.blockchain_dir.as_path())?,
+            chain_index: ChainIndex::new(options, &blk_files)?,
+            blk_files,
+            undo_files,
             coin: options.coin.clone(),
             verify: options.verify,
+            headers_only: options.callback.headers_only(),
+            // The script audit reports outputs without an address, so it needs them too
+            eval_addresses: options.callback.wants_addresses()
+                || options.strict_scripts
+                || options.filter.as_ref().is_some_and(|f| f.uses_address()),
+            buffer: Vec::with_capacity(BLOCK_BUFFER_CAPACITY),
+            timings: Timings::default(),
+            bytes_read: 0,
+            unknown: UnknownConstructs::default(),
+            strict_parse: options.strict_parse,
+            skipped_pow: BTreeMap::new(),
+            check_tx_count: options.check_tx_count,
+            tx_count_mismatches: Vec::new(),
         })
     }
 
-    /// Returns the next block and its height
-    pub fn get_block(&mut self, height: u64) -> Option<Block> {
+    /// Returns the block at the given height, None if there are no more blocks.
+    /// Blocks which can't be read or parsed are returned as `CorruptBlock`.
+    pub fn get_block(&mut self, height: u64) -> Option<Result<Block, CorruptBlock>> {
+        if self.headers_only {
+            return self.get_block_header(height);
+        }
+
         // Read block
         let block_meta = self.chain_index.get(height)?;
         let blk_file = self.blk_files.get_mut(&block_meta.blk_index)?;
-        let block = blk_file
-            .read_block(block_meta.data_offset, &self.coin)
-            .ok()?;
+        let started = Instant::now();
+        let size = blk_file.read_raw_block(block_meta.data_offset, &self.coin, &mut self.buffer);
+        self.timings.read += started.elapsed();
+        if let Ok(size) = &size {
+            self.bytes_read += *size as u64;
+        }
+        let started = Instant::now();
+        let result = size.and_then(|size| {
+            self.buffer
+                .as_slice()
+                .read_block(size, &self.coin, self.eval_addresses)
+        });
+        self.timings.decode += started.elapsed();
+        let mut block = match result {
+            Ok(block) => block,
+            Err(error) => {
+                return Some(Err(CorruptBlock::new(
+                    height,
+                    blk_file,
+                    block_meta.data_offset,
+                    error,
+                )))
+            }
+        };
+        block.chain_work = block_meta.chain_work;
+        block.context = Some(block_meta.context());
+        if let Err(error) = check_tx_count(
+            self.check_tx_count,
+            &mut self.tx_count_mismatches,
+            &block,
+            block_meta.tx_count,
+            height,
+        ) {
+            return Some(Err(CorruptBlock::new(
+                height,
+                blk_file,
+                block_meta.data_offset,
+                error,
+            )));
+        }
+        if let Some(msg) = self.unknown.check(&block, height) {
+            if self.strict_parse {
+                let error = OpError::new(OpErrorKind::ValidationError).join_msg(&msg);
+                return Some(Err(CorruptBlock::new(
+                    height,
+                    blk_file,
+                    block_meta.data_offset,
+                    error,
+                )));
+            }
+        }
+
+        // Resolve spent outputs from rev files if enabled
+        let mut undo_file = None;
+        if let Some(undo_files) = self.undo_files.as_mut() {
+            match (
+                block_meta.undo_offset,
+                undo_files.get_mut(&block_meta.blk_index),
+            ) {
+                (Some(offset), Some(file)) => {
+                    let prev_hash = block.header.value.prev_hash;
+                    let prev_hash = if self.verify { Some(&prev_hash) } else { None };
+                    let started = Instant::now();
+                    let result = file.read_raw_block_undo(
+                        offset,
+                        self.coin.magic,
+                        &mut self.buffer,
+                        prev_hash,
+                    );
+                    self.timings.read += started.elapsed();
+                    if result.is_ok() {
+                        self.bytes_read += self.buffer.len() as u64;
+                    }
+                    let started = Instant::now();
+                    let result = result
+                        .and_then(|_| {
+                            self.buffer.as_slice().read_block_undo(
+                                self.coin.version_id,
+                                &ReadLimits::from(&self.coin),
+                                self.eval_addresses,
+                            )
+                        })
+                        .and_then(|undo| block.apply_undo(undo));
+                    self.timings.decode += started.elapsed();
+                    if let Err(error) = result {
+                        return Some(Err(CorruptBlock::new(height, file, offset, error)));
+                    }
+                    undo_file = Some(file);
+                }
+                // The index records undo data, but its rev file is gone
+                (Some(offset), None) => {
+                    let msg = format!("No rev file found for blk file {}", block_meta.blk_index);
+                    let error = OpError::new(OpErrorKind::ValidationError).join_msg(&msg);
+                    return Some(Err(CorruptBlock::new(height, blk_file, offset, error)));
+                }
+                // The genesis block has no undo data
+                _ if height == 0 => {}
+                _ => {
+                    warn!(target: "chain", "No undo data available for block {}", height);
+                }
+            }
+        }
 
         // Check if blk file can be closed
         if height == self.chain_index.max_height_by_blk(block_meta.blk_index) {
-            blk_file.close()
+            blk_file.close();
+            if let Some(undo_file) = undo_file {
+                undo_file.close();
+            }
+        }
+
+        let (blk_index, offset) = (block_meta.blk_index, block_meta.data_offset);
+        self.verify_block(block, height, blk_index, offset)
+    }
+
+    /// Returns the block at the given height without transactions.
+    /// Only the block header and the tx count are read from disk.
+    fn get_block_header(&mut self, height: u64) -> Option<Result<Block, CorruptBlock>> {
+        let block_meta = self.chain_index.get(height)?;
+        let blk_file = self.blk_files.get_mut(&block_meta.blk_index)?;
+        let started = Instant::now();
+        let result = blk_file.read_block_header(block_meta.data_offset, &self.coin);
+        self.timings.read += started.elapsed();
+        let (size, header, tx_count) = match result {
+            Ok(header) => header,
+            Err(error) => {
+                return Some(Err(CorruptBlock::new(
+                    height,
+                    blk_file,
+                    block_meta.data_offset,
+                    error,
+                )))
+            }
+        };
+        let mut block = Block::new(size, header, None, tx_count, Vec::new(), &self.coin);
+        block.chain_work = block_meta.chain_work;
+        block.context = Some(block_meta.context());
+        if let Err(error) = check_tx_count(
+            self.check_tx_count,
+            &mut self.tx_count_mismatches,
+            &block,
+            block_meta.tx_count,
+            height,
+        ) {
+            return Some(Err(CorruptBlock::new(
+                height,
+                blk_file,
+                block_meta.data_offset,
+                error,
+            )));
         }
 
-        if self.verify {
-            self.verify(&block, height).unwrap();
+        // Check if blk file can be closed
+        if height == self.chain_index.max_height_by_blk(block_meta.blk_index) {
+            blk_file.close();
         }
 
-        Some(block)
+        let (blk_index, offset) = (block_meta.blk_index, block_meta.data_offset);
+        self.verify_block(block, height, blk_index, offset)
+    }
+
+    /// Verifies the given block if enabled, a failed verification is returned as corrupt block
+    fn verify_block(
+        &mut self,
+        block: Block,
+        height: u64,
+        blk_index: u64,
+        offset: u64,
+    ) -> Option<Result<Block, CorruptBlock>> {
+        if !self.verify {
+            return Some(Ok(block));
+        }
+        if let Err(error) = self.verify(&block, height) {
+            let blk_file = self.blk_files.get(&blk_index)?;
+            return Some(Err(CorruptBlock::new(height, blk_file, offset, error)));
+        }
+        Some(Ok(block))
     }
 
     /// Verifies the given block in a chain.
-    /// Panics if not valid
-    fn verify(&self, block: &Block, height: u64) -> OpResult<()> {
-        block.verify_merkle_root()?;
+    fn verify(&mut self, block: &Block, height: u64) -> OpResult<()> {
+        // Transactions are not available in headers only mode
+        if !self.headers_only {
+            block.verify_merkle_root()?;
+        }
         if height == 0 {
             if block.header.hash != self.coin.genesis_hash {
                 let msg = format!(
@@ -72,10 +381,235 @@ impl ChainStorage {
                 return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
             }
         }
+        self.verify_pow(block)
+    }
+
+    /// Verifies the proof of work if the algorithm of the block is supported.
+    /// Skipped blocks are counted per algorithm, only header linkage and merkle root are checked for them.
+    fn verify_pow(&mut self, block: &Block) -> OpResult<()> {
+        let header = &block.header.value;
+        let algorithm = self.coin.pow_algorithms.of(header.version);
+        // Merged mined blocks are solved by the parent chain
+        let hash = match (&block.aux_pow_extension, algorithm) {
+            (Some(_), _) => None,
+            // The block hash of these coins is the proof of work hash, see `Coin::block_hash`
+            (None, PowAlgorithm::Sha256d | PowAlgorithm::Groestl) => {
+                Some(block.header.hash.to_byte_array())
+            }
+            (None, _) => None,
+        };
+        let Some(hash) = hash else {
+            let name = match block.aux_pow_extension {
+                Some(_) => String::from("auxpow"),
+                None => algorithm.to_string(),
+            };
+            let skipped = self.skipped_pow.entry(name.clone()).or_insert(0);
+            if *skipped == 0 {
+                warn!(target: "chain", "Unable to verify the proof of work of {} blocks, only header linkage and merkle root are checked for them (first: {})",
+                      name, &block.header.hash);
+            }
+            *skipped += 1;
+            return Ok(());
+        };
+        if !utils::meets_target(hash, header.bits) {
+            let msg = format!(
+                "proof of work of block {} doesn't match its target!\n  -> bits: {:#010x}\n  -> algorithm: {}\n",
+                &block.header.hash, header.bits, algorithm
+            );
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
         Ok(())
     }
 
+    /// Returns the heights to parse if only a subset of the range was selected
+    pub(crate) fn selection(&self) -> Option<Vec<u64>> {
+        self.chain_index.selection().map(<[u64]>::to_vec)
+    }
+
+    /// Returns the time spent reading and decoding blocks so far
+    pub(crate) fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    /// Returns the number of bytes of block and undo data read so far
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Returns the heights of the blocks which didn't match the tx count of the block index
+    pub(crate) fn tx_count_mismatches(&self) -> &[u64] {
+        &self.tx_count_mismatches
+    }
+
+    /// Returns the number of blocks per algorithm whose proof of work wasn't verified
+    pub(crate) fn skipped_pow(&self) -> &BTreeMap<String, u64> {
+        &self.skipped_pow
+    }
+
+    /// Returns the unknown constructs found so far
+    pub(crate) fn unknown(&self) -> &UnknownConstructs {
+        &self.unknown
+    }
+
     pub(crate) fn max_height(&self) -> u64 {
         self.chain_index.max_height()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::str::FromStr;
+
+    use bitcoin::hashes::sha256d;
+
+    use super::*;
+    use crate::blockchain::parser::types::Groestlcoin;
+    use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::blockchain::proto::BlockHash;
+    use crate::common::fixtures::MiniChain;
+    use crate::{command, parse_args};
+
+    #[test]
+    fn test_verify_groestlcoin_genesis() {
+        let chain = MiniChain::generate(1);
+        let argv = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(argv)).unwrap();
+        let mut storage = ChainStorage::new(&options).unwrap();
+        storage.coin = CoinType::from(Groestlcoin);
+        storage.headers_only = true;
+
+        // Header of the Groestlcoin genesis block
+        let mut header = BlockHeader {
+            version: 112,
+            prev_hash: BlockHash::all_zeros(),
+            merkle_root: sha256d::Hash::from_str(
+                "3ce968df58f9c8a752306c4b7264afab93149dbc578bd08a42c446caaa6628bb",
+            )
+            .unwrap(),
+            timestamp: 1395342829,
+            bits: 0x1e0fffff,
+            nonce: 220035,
+            extension: Vec::new(),
+        };
+        let block = Block::new(
+            81,
+            header.clone(),
+            None,
+            VarUint::from(1u8),
+            Vec::new(),
+            &storage.coin,
+        );
+        assert_eq!(block.header.hash, storage.coin.genesis_hash);
+        assert!(storage.verify(&block, 0).is_ok());
+
+        header.nonce += 1;
+        let block = Block::new(
+            81,
+            header,
+            None,
+            VarUint::from(1u8),
+            Vec::new(),
+            &storage.coin,
+        );
+        assert!(storage.verify_pow(&block).is_err());
+    }
+
+    #[test]
+    fn test_verify_error() {
+        let chain = MiniChain::generate(2);
+        let argv = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "--verify",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(argv)).unwrap();
+        let mut storage = ChainStorage::new(&options).unwrap();
+        // The generated genesis block doesn't match the Bitcoin genesis hash
+        let corrupt = storage.get_block(0).unwrap().err().unwrap();
+        assert_eq!(corrupt.path, chain.path().join("blk00000.dat"));
+        assert_eq!(corrupt.offset, 8);
+        assert!(corrupt.error.to_string().contains("Genesis block hash"));
+
+        storage.headers_only = true;
+        let corrupt = storage.get_block_header(0).unwrap().err().unwrap();
+        assert!(corrupt.error.to_string().contains("Genesis block hash"));
+    }
+
+    fn undo_storage(chain: &MiniChain) -> ChainStorage {
+        let argv = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "--use-undo-files",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(argv)).unwrap();
+        ChainStorage::new(&options).unwrap()
+    }
+
+    #[test]
+    fn test_undo_errors() {
+        let chain = MiniChain::generate_with_undo(3);
+        let mut storage = undo_storage(&chain);
+        let block = storage.get_block(2).unwrap().ok().unwrap();
+        let prevout = block.txs[1].value.inputs[0].prevout.as_ref().unwrap();
+        assert_eq!((prevout.height, prevout.is_coinbase), (1, true));
+        assert_eq!(prevout.out.out.value, 5000000000);
+
+        // The last record is followed by its checksum, the compressed script type is 20 bytes
+        // before it. A long raw script reads past the end of the undo data of block 2.
+        let rev_path = chain.path().join("rev00000.dat");
+        let mut rev = fs::read(&rev_path).unwrap();
+        let script_type = rev.len() - 32 - 21;
+        rev[script_type] = 0x1f;
+        fs::write(&rev_path, &rev).unwrap();
+        let mut storage = undo_storage(&chain);
+        assert!(storage.get_block(1).unwrap().is_ok());
+        let corrupt = storage.get_block(2).unwrap().err().unwrap();
+        assert_eq!(corrupt.path, rev_path);
+
+        fs::remove_file(&rev_path).unwrap();
+        fs::write(chain.path().join("rev00001.dat"), []).unwrap();
+        let mut storage = undo_storage(&chain);
+        let corrupt = storage.get_block(1).unwrap().err().unwrap();
+        assert!(corrupt.error.to_string().contains("No rev file found"));
+    }
+
+    #[test]
+    fn test_check_tx_count() {
+        let chain = MiniChain::generate(3);
+        let argv = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "--check-tx-count",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(argv)).unwrap();
+        assert_eq!(options.check_tx_count, Some(TxCountCheck::Fail));
+        let mut storage = ChainStorage::new(&options).unwrap();
+        let block = storage.get_block(1).unwrap().ok().unwrap();
+        assert_eq!(block.context.as_ref().unwrap().tx_count, Some(2));
+
+        let mut mismatches = Vec::new();
+        let fail = Some(TxCountCheck::Fail);
+        assert!(check_tx_count(fail, &mut mismatches, &block, Some(2), 1).is_ok());
+        // Indexes without tx counts are not checked
+        assert!(check_tx_count(fail, &mut mismatches, &block, None, 1).is_ok());
+        assert!(check_tx_count(fail, &mut mismatches, &block, Some(3), 1).is_err());
+        assert!(mismatches.is_empty());
+
+        let warn = Some(TxCountCheck::Warn);
+        assert!(check_tx_count(warn, &mut mismatches, &block, Some(3), 1).is_ok());
+        assert_eq!(mismatches, [1]);
+    }
+}
@@ -0,0 +1,526 @@
+use std::fmt;
+
+use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxOut};
+use crate::blockchain::proto::Hashed;
+
+/// Fields which can be used in filter expressions.
+/// Output fields refer to the output the expression is evaluated for, the others to its transaction.
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+enum Field {
+    // Output
+    Value,
+    ScriptType,
+    Address,
+    Index,
+    // Transaction
+    Txid,
+    Height,
+    Version,
+    Locktime,
+    Inputs,
+    Outputs,
+    Vsize,
+    Weight,
+    Fee,
+    OutputValue,
+    Coinbase,
+    Segwit,
+}
+
+const FIELDS: [(&str, Field); 16] = [
+    ("value", Field::Value),
+    ("script_type", Field::ScriptType),
+    ("address", Field::Address),
+    ("index", Field::Index),
+    ("txid", Field::Txid),
+    ("height", Field::Height),
+    ("version", Field::Version),
+    ("locktime", Field::Locktime),
+    ("inputs", Field::Inputs),
+    ("outputs", Field::Outputs),
+    ("vsize", Field::Vsize),
+    ("weight", Field::Weight),
+    ("fee", Field::Fee),
+    ("output_value", Field::OutputValue),
+    ("coinbase", Field::Coinbase),
+    ("segwit", Field::Segwit),
+];
+
+impl Field {
+    fn kind(&self) -> Kind {
+        match self {
+            Field::ScriptType | Field::Address | Field::Txid => Kind::Str,
+            Field::Coinbase | Field::Segwit => Kind::Bool,
+            _ => Kind::Int,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+enum Kind {
+    Int,
+    Str,
+    Bool,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Int => write!(f, "number"),
+            Kind::Str => write!(f, "string"),
+            Kind::Bool => write!(f, "boolean"),
+        }
+    }
+}
+
+/// Value of a field or literal, `Null` if a field is unknown (e.g. the fee without undo files)
+#[derive(PartialEq, PartialOrd)]
+enum Value {
+    Null,
+    Int(u64),
+    Str(String),
+    Bool(bool),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+enum Expr {
+    Int(u64),
+    Str(String),
+    Bool(bool),
+    Field(Field),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cmp(Box<Expr>, CmpOp, Box<Expr>),
+}
+
+#[derive(Clone, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+enum Token {
+    Int(u64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+}
+
+/// Splits the expression into tokens
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    const OPS: [&str; 13] = [
+        "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")", "=", "&",
+    ];
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '_'))
+                .unwrap_or(rest.len());
+            let number = rest[..end].replace('_', "");
+            let number = number
+                .parse()
+                .map_err(|_| format!("Invalid number: {}", &rest[..end]))?;
+            tokens.push(Token::Int(number));
+            rest = &rest[end..];
+        } else if c == '\'' || c == '"' {
+            let end = rest[1..]
+                .find(c)
+                .ok_or_else(|| format!("Unterminated string: {}", rest))?;
+            tokens.push(Token::Str(String::from(&rest[1..end + 1])));
+            rest = &rest[end + 2..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(String::from(&rest[..end])));
+            rest = &rest[end..];
+        } else {
+            let op = OPS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("Unexpected character: {}", c))?;
+            if *op == "=" || *op == "&" {
+                return Err(format!("Unknown operator {}, use {}{}", op, op, op));
+            }
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, from the lowest to the highest precedence:
+/// `||`, `&&`, `!`, comparisons, parentheses and operands
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        match self.peek() {
+            Some(Token::Op(o)) if *o == op => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        match self.eat("!") {
+            true => Ok(Expr::Not(Box::new(self.not()?))),
+            false => self.cmp(),
+        }
+    }
+
+    fn cmp(&mut self) -> Result<Expr, String> {
+        let left = self.operand()?;
+        let op = match self.peek() {
+            Some(Token::Op("==")) => CmpOp::Eq,
+            Some(Token::Op("!=")) => CmpOp::Ne,
+            Some(Token::Op("<")) => CmpOp::Lt,
+            Some(Token::Op("<=")) => CmpOp::Le,
+            Some(Token::Op(">")) => CmpOp::Gt,
+            Some(Token::Op(">=")) => CmpOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.operand()?;
+        Ok(Expr::Cmp(Box::new(left), op, Box::new(right)))
+    }
+
+    fn operand(&mut self) -> Result<Expr, String> {
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err(String::from("Missing )"));
+            }
+            return Ok(expr);
+        }
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| String::from("Unexpected end of expression"))?;
+        self.pos += 1;
+        match token {
+            Token::Int(value) => Ok(Expr::Int(value)),
+            Token::Str(value) => Ok(Expr::Str(value)),
+            Token::Ident(name) if name == "true" => Ok(Expr::Bool(true)),
+            Token::Ident(name) if name == "false" => Ok(Expr::Bool(false)),
+            Token::Ident(name) => FIELDS
+                .iter()
+                .find(|(field, _)| *field == name)
+                .map(|(_, field)| Expr::Field(*field))
+                .ok_or_else(|| {
+                    let names: Vec<&str> = FIELDS.iter().map(|(name, _)| *name).collect();
+                    format!("Unknown field {}, known fields: {}", name, names.join(", "))
+                }),
+            Token::Op(op) => Err(format!("Unexpected {}", op)),
+        }
+    }
+}
+
+impl Expr {
+    /// Returns the type of the expression or an error if operands don't match
+    fn kind(&self) -> Result<Kind, String> {
+        match self {
+            Expr::Int(_) => Ok(Kind::Int),
+            Expr::Str(_) => Ok(Kind::Str),
+            Expr::Bool(_) => Ok(Kind::Bool),
+            Expr::Field(field) => Ok(field.kind()),
+            Expr::Not(expr) => match expr.kind()? {
+                Kind::Bool => Ok(Kind::Bool),
+                kind => Err(format!("! needs a boolean, got a {}", kind)),
+            },
+            Expr::And(left, right) | Expr::Or(left, right) => match (left.kind()?, right.kind()?) {
+                (Kind::Bool, Kind::Bool) => Ok(Kind::Bool),
+                (l, r) => Err(format!("&& and || need booleans, got {} and {}", l, r)),
+            },
+            Expr::Cmp(left, op, right) => {
+                let (l, r) = (left.kind()?, right.kind()?);
+                if l != r {
+                    return Err(format!("Can't compare {} with {}", l, r));
+                }
+                if l == Kind::Bool && !matches!(op, CmpOp::Eq | CmpOp::Ne) {
+                    return Err(String::from("Booleans can only be compared with == and !="));
+                }
+                Ok(Kind::Bool)
+            }
+        }
+    }
+
+    fn uses(&self, field: Field) -> bool {
+        match self {
+            Expr::Field(f) => *f == field,
+            Expr::Not(expr) => expr.uses(field),
+            Expr::And(left, right) | Expr::Or(left, right) | Expr::Cmp(left, _, right) => {
+                left.uses(field) || right.uses(field)
+            }
+            _ => false,
+        }
+    }
+
+    fn eval(&self, ctx: &Context) -> Value {
+        match self {
+            Expr::Int(value) => Value::Int(*value),
+            Expr::Str(value) => Value::Str(value.clone()),
+            Expr::Bool(value) => Value::Bool(*value),
+            Expr::Field(field) => ctx.get(*field),
+            Expr::Not(expr) => Value::Bool(expr.eval(ctx) == Value::Bool(false)),
+            Expr::And(left, right) => Value::Bool(
+                left.eval(ctx) == Value::Bool(true) && right.eval(ctx) == Value::Bool(true),
+            ),
+            Expr::Or(left, right) => Value::Bool(
+                left.eval(ctx) == Value::Bool(true) || right.eval(ctx) == Value::Bool(true),
+            ),
+            Expr::Cmp(left, op, right) => {
+                let (left, right) = (left.eval(ctx), right.eval(ctx));
+                // Comparisons with unknown values are false
+                if left == Value::Null || right == Value::Null {
+                    return Value::Bool(false);
+                }
+                Value::Bool(match op {
+                    CmpOp::Eq => left == right,
+                    CmpOp::Ne => left != right,
+                    CmpOp::Lt => left < right,
+                    CmpOp::Le => left <= right,
+                    CmpOp::Gt => left > right,
+                    CmpOp::Ge => left >= right,
+                })
+            }
+        }
+    }
+}
+
+/// Transaction and output an expression is evaluated for
+struct Context<'a> {
+    tx: &'a Hashed<EvaluatedTx>,
+    height: u64,
+    // None for transactions without outputs
+    output: Option<(usize, &'a EvaluatedTxOut)>,
+}
+
+impl Context<'_> {
+    fn get(&self, field: Field) -> Value {
+        let tx = &self.tx.value;
+        match (field, self.output) {
+            (Field::Value, Some((_, out))) => Value::Int(out.out.value),
+            (Field::ScriptType, Some((_, out))) => Value::Str(out.script.pattern.to_string()),
+            (Field::Address, Some((_, out))) => {
+                Value::Str(out.script.address.clone().unwrap_or_default())
+            }
+            (Field::Index, Some((index, _))) => Value::Int(index as u64),
+            (Field::Value | Field::ScriptType | Field::Address | Field::Index, None) => Value::Null,
+            (Field::Txid, _) => Value::Str(self.tx.hash.to_string()),
+            (Field::Height, _) => Value::Int(self.height),
+            (Field::Version, _) => Value::Int(tx.version as u64),
+            (Field::Locktime, _) => Value::Int(tx.locktime as u64),
+            (Field::Inputs, _) => Value::Int(tx.inputs.len() as u64),
+            (Field::Outputs, _) => Value::Int(tx.outputs.len() as u64),
+            (Field::Vsize, _) => Value::Int(tx.vsize()),
+            (Field::Weight, _) => Value::Int(tx.weight()),
+            (Field::Fee, _) => tx.fee().map_or(Value::Null, Value::Int),
+            (Field::OutputValue, _) => Value::Int(tx.output_value()),
+            (Field::Coinbase, _) => Value::Bool(tx.is_coinbase()),
+            (Field::Segwit, _) => Value::Bool(tx.has_witness()),
+        }
+    }
+}
+
+/// Filter expression, see `--filter`. It is evaluated for each output of a transaction,
+/// the transaction is passed to the callback if any of its outputs matches.
+#[derive(Clone)]
+#[cfg_attr(test, derive(Debug))]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {} after expression", token_str(token)));
+        }
+        match expr.kind()? {
+            Kind::Bool => Ok(Self { expr }),
+            kind => Err(format!("The filter must be a condition, got a {}", kind)),
+        }
+    }
+
+    /// Returns true if addresses are needed to evaluate the filter
+    pub fn uses_address(&self) -> bool {
+        self.expr.uses(Field::Address)
+    }
+
+    pub fn matches(&self, tx: &Hashed<EvaluatedTx>, height: u64) -> bool {
+        let eval = |output| {
+            let ctx = Context { tx, height, output };
+            self.expr.eval(&ctx) == Value::Bool(true)
+        };
+        match tx.value.outputs.is_empty() {
+            true => eval(None),
+            false => tx.value.outputs.iter().enumerate().any(|o| eval(Some(o))),
+        }
+    }
+
+    /// Removes all transactions which don't match.
+    /// Returns the positions of the remaining transactions in the original block.
+    pub fn retain(&self, txs: &mut Vec<Hashed<EvaluatedTx>>, height: u64) -> Vec<usize> {
+        let mut positions = Vec::new();
+        let mut i = 0;
+        txs.retain(|tx| {
+            let keep = self.matches(tx, height);
+            if keep {
+                positions.push(i);
+            }
+            i += 1;
+            keep
+        });
+        positions
+    }
+}
+
+fn token_str(token: &Token) -> String {
+    match token {
+        Token::Int(value) => value.to_string(),
+        Token::Str(value) => format!("'{}'", value),
+        Token::Ident(name) => name.clone(),
+        Token::Op(op) => String::from(*op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::tx::{TxInputs, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+
+    fn tx(values: &[u64]) -> Hashed<EvaluatedTx> {
+        let outputs = values
+            .iter()
+            .map(|value| {
+                let mut script = vec![0x76, 0xa9, 0x14];
+                script.extend([0; 20]);
+                script.extend([0x88, 0xac]);
+                let out = TxOutput {
+                    value: *value,
+                    script_len: VarUint::from(script.len() as u8),
+                    script_pubkey: script,
+                };
+                EvaluatedTxOut::eval_script(out, 0x00, true)
+            })
+            .collect();
+        Hashed::double_sha256(EvaluatedTx {
+            version: 2,
+            flags: 0,
+            in_count: VarUint::from(0u8),
+            inputs: TxInputs::new(),
+            out_count: VarUint::from(values.len() as u64),
+            outputs,
+            locktime: 0,
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        let filter = Filter::parse("value > 1_000 && !(height < 5 || coinbase)").unwrap();
+        assert_eq!(
+            filter.expr,
+            Expr::And(
+                Box::new(Expr::Cmp(
+                    Box::new(Expr::Field(Field::Value)),
+                    CmpOp::Gt,
+                    Box::new(Expr::Int(1000))
+                )),
+                Box::new(Expr::Not(Box::new(Expr::Or(
+                    Box::new(Expr::Cmp(
+                        Box::new(Expr::Field(Field::Height)),
+                        CmpOp::Lt,
+                        Box::new(Expr::Int(5))
+                    )),
+                    Box::new(Expr::Field(Field::Coinbase))
+                ))))
+            )
+        );
+        assert!(Filter::parse("script_type == \"Pay2Taproot\"").is_ok());
+        assert!(Filter::parse("address == 'bc1q' || segwit")
+            .unwrap()
+            .uses_address());
+
+        for invalid in [
+            "value",
+            "value > 'a'",
+            "value = 5",
+            "foo > 1",
+            "(value > 1",
+            "value > 1 height",
+            "coinbase < true",
+            "address == 'unterminated",
+            "value > 1 && 2",
+        ] {
+            assert!(Filter::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let tx = tx(&[100, 5000]);
+        let matches = |expr: &str| Filter::parse(expr).unwrap().matches(&tx, 10);
+        assert!(matches("value > 1000"));
+        assert!(!matches("value > 5000"));
+        assert!(matches("value == 100 && index == 0"));
+        assert!(!matches("value == 100 && index == 1"));
+        assert!(matches(
+            "script_type == 'Pay2PublicKeyHash' && outputs == 2"
+        ));
+        assert!(matches("output_value >= 5100 && height == 10 && !coinbase"));
+        assert!(matches("address != ''"));
+        // The fee is unknown without undo data
+        assert!(!matches("fee >= 0"));
+        assert!(!matches("fee < 0 || fee >= 0"));
+
+        let mut txs = vec![self::tx(&[100]), self::tx(&[1]), self::tx(&[1, 20])];
+        let positions = Filter::parse("value > 10").unwrap().retain(&mut txs, 0);
+        assert_eq!(positions, [0, 2]);
+        assert_eq!(txs.len(), 2);
+    }
+}
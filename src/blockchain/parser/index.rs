@@ -1,30 +1,102 @@
-use bitcoin::hashes::{sha256d, Hash};
-use std::collections::HashMap;
+use bitcoin::hashes::Hash;
+use bitcoin::pow::Work;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::convert::TryInto;
 use std::fmt;
 use std::io::Cursor;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
 
-use byteorder::ReadBytesExt;
-use rusty_leveldb::{LdbIterator, Options, DB};
+use rusty_leveldb::{LdbIterator, Options, StatusCode, DB};
 
-use crate::errors::OpResult;
-use crate::ParserOptions;
+use crate::blockchain::parser::blkfile::{BlkFile, ScannedBlock};
+use crate::blockchain::parser::reader::BlockchainRead;
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::proto::block::BlockContext;
+use crate::blockchain::proto::BlockHash;
+use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::{BlockHeightRange, ParserOptions};
 
-const BLOCK_VALID_CHAIN: u64 = 4;
-const BLOCK_HAVE_DATA: u64 = 8;
+pub mod btcd;
+pub mod compare;
+pub mod manifest;
+pub mod stats;
+pub mod sync;
+
+// Status flags of the block index records, see `BlockStatus` in Bitcoin Core's chain.h
+pub const BLOCK_VALID_CHAIN: u64 = 4;
+pub const BLOCK_HAVE_DATA: u64 = 8;
+pub const BLOCK_HAVE_UNDO: u64 = 16;
+pub const BLOCK_FAILED_VALID: u64 = 32;
+pub const BLOCK_FAILED_CHILD: u64 = 64;
+
+/// Provides the records of the longest chain by height.
+/// Implemented for each supported layout of the block data.
+pub trait ChainIndexSource {
+    fn read(&self, coin: &CoinType) -> OpResult<HashMap<u64, BlockIndexRecord>>;
+}
+
+/// LevelDB block index of Bitcoin Core and its forks (`blocks/index`)
+struct CoreIndex {
+    path: PathBuf,
+    copy: bool, // reads a temporary copy, see `--copy-index-to-tmp`
+}
+
+impl ChainIndexSource for CoreIndex {
+    fn read(&self, _: &CoinType) -> OpResult<HashMap<u64, BlockIndexRecord>> {
+        read_index(&self.path, self.copy, get_block_index)
+    }
+}
+
+/// Index built by scanning a file with concatenated blocks (see `--bootstrap`)
+struct BootstrapIndex {
+    path: PathBuf,
+}
+
+impl ChainIndexSource for BootstrapIndex {
+    fn read(&self, coin: &CoinType) -> OpResult<HashMap<u64, BlockIndexRecord>> {
+        get_bootstrap_index(&self.path, coin)
+    }
+}
+
+/// Selects the index source for the given options
+fn index_source(options: &ParserOptions) -> Box<dyn ChainIndexSource> {
+    if let Some(path) = &options.bootstrap {
+        return Box::new(BootstrapIndex { path: path.clone() });
+    }
+    let is_btcd = btcd::is_btcd_dir(&options.blockchain_dir);
+    let path = match &options.index_snapshot {
+        Some(path) => path.clone(),
+        None if is_btcd => options.blockchain_dir.join(btcd::METADATA_DIR),
+        None => options.blockchain_dir.join("index"),
+    };
+    match is_btcd {
+        true => Box::new(btcd::BtcdIndex::new(path, options.copy_index)),
+        false => Box::new(CoreIndex {
+            path,
+            copy: options.copy_index,
+        }),
+    }
+}
 
 /// Holds the index of longest valid chain
 pub struct ChainIndex {
     max_height: u64,
     block_index: HashMap<u64, BlockIndexRecord>,
     max_height_blk_index: HashMap<u64, u64>, // Maps blk_index to max_height found in the file
+    selection: Option<Vec<u64>>, // Heights to parse if restricted by blk files or a heights file
 }
 
 impl ChainIndex {
-    pub fn new(options: &ParserOptions) -> OpResult<Self> {
-        let path = options.blockchain_dir.join("index");
-        let mut block_index = get_block_index(&path)?;
+    pub fn new(options: &ParserOptions, blk_files: &HashMap<u64, BlkFile>) -> OpResult<Self> {
+        let mut block_index = index_source(options).read(&options.coin)?;
+        sync::check(
+            &block_index,
+            blk_files,
+            &options.coin,
+            options.allow_partial_sync,
+        )?;
         let mut max_height_blk_index = HashMap::new();
 
         for (height, index_record) in &block_index {
@@ -39,15 +111,43 @@ impl ChainIndex {
             }
         }
 
-        let min_height = options.range.start;
         let max_known_height = *block_index.keys().max().unwrap();
-        let max_height = match options.range.end {
-            Some(height) if height < max_known_height => height,
-            Some(_) | None => max_known_height,
+        accumulate_chain_work(&mut block_index, max_known_height);
+        let mut range = options.range;
+        if options.start_time.is_some() || options.end_time.is_some() {
+            range = resolve_dates(&block_index, range, options.start_time, options.end_time)?;
+        }
+        let is_default = range.is_default();
+        let range = range.resolve(max_known_height, options.strict_range)?;
+        info!(target: "index", "Resolved block range: {}", range);
+        let min_height = range.start;
+        let max_height = range.end.unwrap_or(max_known_height);
+
+        // A block requested by hash is resolved to its height
+        let heights = match options.block_hash {
+            Some(hash) => Some(BTreeSet::from([find_height(&block_index, &hash)?])),
+            None => options.heights.clone(),
+        };
+        let selection = match options.blk_files.is_some() || heights.is_some() {
+            true => Some(select_heights(
+                &block_index,
+                options,
+                heights.as_ref(),
+                min_height,
+                max_height,
+            )?),
+            false => None,
         };
 
         // Filter to only keep relevant block index
-        if !options.range.is_default() {
+        if let Some(selection) = &selection {
+            // Keep the predecessors as well, they are needed for verification
+            let keep: HashSet<u64> = selection
+                .iter()
+                .flat_map(|height| [height.saturating_sub(1), *height])
+                .collect();
+            block_index.retain(|height, _| keep.contains(height));
+        } else if !is_default {
             info!(target: "index", "Trimming block index from height {} to {} ...", min_height, max_height);
             block_index.retain(|height, _| {
                 *height >= min_height.saturating_sub(1) && *height <= max_height
@@ -58,9 +158,16 @@ impl ChainIndex {
             max_height,
             block_index,
             max_height_blk_index,
+            selection,
         })
     }
 
+    /// Returns the ascending heights to parse, if only a subset of the range was selected
+    /// (see `--only-blk-files` and `--heights-file`)
+    pub fn selection(&self) -> Option<&[u64]> {
+        self.selection.as_deref()
+    }
+
     /// Returns the `BlockIndexRecord` for the given height
     pub fn get(&self, height: u64) -> Option<&BlockIndexRecord> {
         self.block_index.get(&height)
@@ -80,13 +187,17 @@ impl ChainIndex {
 /// Holds the metadata where the block data is stored,
 /// See https://bitcoin.stackexchange.com/questions/28168/what-are-the-keys-used-in-the-blockchain-leveldb-ie-what-are-the-keyvalue-pair
 pub struct BlockIndexRecord {
-    pub block_hash: sha256d::Hash,
+    pub block_hash: BlockHash,
     pub blk_index: u64,
-    pub data_offset: u64, // offset within the blk file
-    version: u64,
-    height: u64,
-    status: u64,
-    tx_count: u64,
+    pub data_offset: u64,         // offset within the blk file
+    pub undo_offset: Option<u64>, // offset within the rev file, if undo data is available
+    pub timestamp: u32,           // block timestamp taken from the header stored in the record
+    pub chain_work: Option<Work>, // accumulated work up to this block, None if predecessors are missing
+    pub bits: u32,
+    pub version: u64, // client version (Bitcoin Core) or block version
+    pub height: u64,
+    pub status: u64,           // validity and storage flags, see `BLOCK_*`
+    pub tx_count: Option<u64>, // number of transactions, if stored in the index
 }
 
 impl BlockIndexRecord {
@@ -94,21 +205,30 @@ impl BlockIndexRecord {
         let mut reader = Cursor::new(values);
 
         let block_hash: [u8; 32] = key.try_into().expect("leveldb: malformed blockhash");
-        let version = read_varint(&mut reader)?;
-        let height = read_varint(&mut reader)?;
-        let status = read_varint(&mut reader)?;
-        let tx_count = read_varint(&mut reader)?;
-        let blk_index = read_varint(&mut reader)?;
-        let data_offset = read_varint(&mut reader)?;
+        let version = reader.read_varint()?;
+        let height = reader.read_varint()?;
+        let status = reader.read_varint()?;
+        let tx_count = reader.read_varint()?;
+        let blk_index = reader.read_varint()?;
+        let data_offset = reader.read_varint()?;
+        let undo_offset = match status & BLOCK_HAVE_UNDO {
+            0 => None,
+            _ => Some(reader.read_varint()?),
+        };
+        let header = reader.read_block_header()?;
 
         Ok(BlockIndexRecord {
-            block_hash: sha256d::Hash::from_byte_array(block_hash),
+            block_hash: BlockHash::from_byte_array(block_hash),
             version,
             height,
             status,
-            tx_count,
+            tx_count: Some(tx_count),
+            timestamp: header.timestamp,
+            chain_work: None,
+            bits: header.bits,
             blk_index,
             data_offset,
+            undo_offset,
         })
     }
 }
@@ -123,53 +243,374 @@ impl fmt::Debug for BlockIndexRecord {
             .field("n_tx", &self.tx_count)
             .field("n_file", &self.blk_index)
             .field("n_data_pos", &self.data_offset)
+            .field("n_undo_pos", &self.undo_offset)
+            .field("n_time", &self.timestamp)
+            .field("n_bits", &self.bits)
             .finish()
     }
 }
 
-pub fn get_block_index(path: &Path) -> OpResult<HashMap<u64, BlockIndexRecord>> {
+impl BlockIndexRecord {
+    /// Returns the metadata passed to the callbacks along with the block
+    pub fn context(&self) -> BlockContext {
+        BlockContext {
+            index_version: self.version,
+            status: self.status,
+            tx_count: self.tx_count,
+            blk_index: self.blk_index,
+            data_offset: self.data_offset,
+        }
+    }
+}
+
+/// Sets the accumulated chain work of each record, as reported by bitcoind's `getblockheader`.
+/// Needs all blocks from genesis on, so this happens before the index is trimmed.
+fn accumulate_chain_work(block_index: &mut HashMap<u64, BlockIndexRecord>, max_height: u64) {
+    let mut chain_work = Work::from_be_bytes([0; 32]);
+    for height in 0..=max_height {
+        match block_index.get_mut(&height) {
+            Some(record) => {
+                chain_work = chain_work + utils::bits_to_work(record.bits);
+                record.chain_work = Some(chain_work);
+            }
+            None => {
+                warn!(target: "index", "Block {} is missing in the index, chain work is unknown from there on", height);
+                return;
+            }
+        }
+    }
+}
+
+/// Resolves the range from the given timestamps (see `--start-date` and `--end-date`).
+/// The range starts at the lowest height with a timestamp >= `start_time` and ends at the
+/// highest height with a timestamp < `end_time`. Block timestamps are not strictly monotonic,
+/// so all blocks are scanned.
+fn resolve_dates(
+    block_index: &HashMap<u64, BlockIndexRecord>,
+    range: BlockHeightRange,
+    start_time: Option<u32>,
+    end_time: Option<u32>,
+) -> OpResult<BlockHeightRange> {
+    let mut start = range.start;
+    let mut end = range.end;
+    if let Some(time) = start_time {
+        start = block_index
+            .values()
+            .filter(|r| r.timestamp >= time)
+            .map(|r| r.height)
+            .min()
+            .ok_or_else(|| {
+                OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg("No blocks found on or after --start-date")
+            })?;
+    }
+    if let Some(time) = end_time {
+        let height = block_index
+            .values()
+            .filter(|r| r.timestamp < time)
+            .map(|r| r.height)
+            .max()
+            .ok_or_else(|| {
+                OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg("No blocks found on or before --end-date")
+            })?;
+        end = Some(end.map_or(height, |end| end.min(height)));
+    }
+    info!(target: "index", "Resolved dates to heights {}..{}", start, end.map_or(String::from("HEAD"), |e| e.to_string()));
+    BlockHeightRange::new(start, end)
+}
+
+/// Returns the height of the block with the given hash in the longest chain
+fn find_height(block_index: &HashMap<u64, BlockIndexRecord>, hash: &BlockHash) -> OpResult<u64> {
+    match block_index
+        .iter()
+        .find(|(_, record)| &record.block_hash == hash)
+    {
+        Some((height, _)) => Ok(*height),
+        None => {
+            let msg = format!("Block {} is not in the chain index", hash);
+            Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg))
+        }
+    }
+}
+
+/// Selects all heights within the given range which are stored in the requested blk files
+/// and contained in the given heights, if any.
+fn select_heights(
+    block_index: &HashMap<u64, BlockIndexRecord>,
+    options: &ParserOptions,
+    heights: Option<&BTreeSet<u64>>,
+    min_height: u64,
+    max_height: u64,
+) -> OpResult<Vec<u64>> {
+    if let Some(heights) = heights {
+        let n_unknown = heights
+            .iter()
+            .filter(|height| !block_index.contains_key(height))
+            .count();
+        if n_unknown > 0 {
+            let msg = format!(
+                "{} heights of --heights-file are not in the chain index",
+                n_unknown
+            );
+            if options.strict_range {
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+            }
+            warn!(target: "index", "{}", msg);
+        }
+    }
+    let mut selection: Vec<u64> = block_index
+        .iter()
+        .filter(|(height, _)| **height >= min_height && **height <= max_height)
+        .filter(|(_, record)| {
+            options
+                .blk_files
+                .is_none_or(|(start, end)| record.blk_index >= start && record.blk_index <= end)
+        })
+        .filter(|(height, _)| heights.is_none_or(|h| h.contains(height)))
+        .map(|(height, _)| *height)
+        .collect();
+    if selection.is_empty() {
+        return Err(OpError::new(OpErrorKind::InvalidArgsError)
+            .join_msg("No blocks match the given blk files or heights"));
+    }
+    selection.sort_unstable();
+    info!(target: "index", "Selected {} blocks from height {} to {}", selection.len(), selection[0], selection[selection.len() - 1]);
+    Ok(selection)
+}
+
+/// Reads the index at the given path with `read`, from a temporary copy if `copy` is set
+fn read_index<T>(path: &Path, copy: bool, read: impl FnOnce(&Path) -> OpResult<T>) -> OpResult<T> {
+    if !copy {
+        return read(path);
+    }
+    let copy = copy_index(path)?;
+    let result = read(&copy);
+    if let Err(why) = fs::remove_dir_all(&copy) {
+        warn!(target: "index", "Unable to remove {}: {}", copy.display(), why);
+    }
+    result
+}
+
+/// Opens the LevelDB database at the given path, with a hint if a running node holds the lock
+fn open_db(path: &Path) -> OpResult<DB> {
+    if !path.is_dir() {
+        let msg = format!("No block index found at {}", path.display());
+        return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+    }
     info!(target: "index", "Reading index from {} ...", path.display());
+    DB::open(path, Options::default()).map_err(|status| match status.code {
+        StatusCode::LockError => {
+            let msg = format!(
+                "The block index at {} is locked, probably by a running node. \
+                 Stop the node, pass --copy-index-to-tmp to read a copy of the index, \
+                 or point --index-snapshot to a copy made beforehand.",
+                path.display()
+            );
+            OpError::new(OpErrorKind::RuntimeError).join_msg(&msg)
+        }
+        _ => OpError::from(status),
+    })
+}
 
+fn get_block_index(path: &Path) -> OpResult<HashMap<u64, BlockIndexRecord>> {
     let mut block_index = HashMap::with_capacity(900000);
-    let mut db_iter = DB::open(path, Options::default())?.new_iter()?;
+    for record in read_records(path)? {
+        if record.status & (BLOCK_VALID_CHAIN | BLOCK_HAVE_DATA) > 0 {
+            block_index.insert(record.height, record);
+        }
+    }
+    if block_index.is_empty() {
+        let msg = format!("Block index at {} contains no blocks", path.display());
+        return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+    }
+    info!(target: "index", "Got longest chain with {} blocks ...", block_index.len());
+    Ok(block_index)
+}
+
+/// Reads all block index records, including stale and invalid blocks and headers without data
+fn read_records(path: &Path) -> OpResult<Vec<BlockIndexRecord>> {
+    let mut records = Vec::with_capacity(900000);
+    let mut db_iter = open_db(path)?.new_iter()?;
     let (mut key, mut value) = (vec![], vec![]);
 
     while db_iter.advance() {
         db_iter.current(&mut key, &mut value);
         if is_block_index_record(&key) {
-            let record = BlockIndexRecord::from(&key[1..], &value)?;
-            if record.status & (BLOCK_VALID_CHAIN | BLOCK_HAVE_DATA) > 0 {
-                block_index.insert(record.height, record);
-            }
+            records.push(BlockIndexRecord::from(&key[1..], &value)?);
+        }
+    }
+    Ok(records)
+}
+
+/// Builds the index of the longest chain from a file with concatenated blocks (see `--bootstrap`).
+/// Blocks may be stored in any order, they are linked via prev_hash starting at the block without
+/// predecessor. Forks are resolved by the accumulated work. All blocks are assigned to blk_index 0.
+fn get_bootstrap_index(path: &Path, coin: &CoinType) -> OpResult<HashMap<u64, BlockIndexRecord>> {
+    info!(target: "index", "Scanning blocks in {} ...", path.display());
+    let blocks = BlkFile::from_file(path)?.scan(coin)?;
+
+    // Link all blocks to their predecessor, duplicates are ignored
+    let mut seen = HashSet::with_capacity(blocks.len());
+    let mut children: HashMap<BlockHash, Vec<usize>> = HashMap::with_capacity(blocks.len());
+    let mut roots = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        if !seen.insert(block.header.hash) {
+            continue;
+        }
+        match block.header.value.prev_hash {
+            prev_hash if prev_hash == BlockHash::all_zeros() => roots.push(i),
+            prev_hash => children.entry(prev_hash).or_default().push(i),
+        }
+    }
+    let root = match roots.as_slice() {
+        [root] => *root,
+        [] => {
+            let msg = format!("{} contains no genesis block", path.display());
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+        }
+        _ => {
+            let msg = format!("{} contains {} genesis blocks", path.display(), roots.len());
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+        }
+    };
+
+    // Walk all branches from the genesis block and keep the tip with the most work
+    let mut prev = vec![None; blocks.len()];
+    let work = |i: usize| utils::bits_to_work(blocks[i].header.value.bits);
+    let mut queue = vec![(root, work(root))];
+    let mut tip = queue[0];
+    let mut n_linked = 0;
+    while let Some((i, chain_work)) = queue.pop() {
+        n_linked += 1;
+        if chain_work > tip.1 {
+            tip = (i, chain_work);
+        }
+        for child in children.remove(&blocks[i].header.hash).unwrap_or_default() {
+            prev[child] = Some(i);
+            queue.push((child, chain_work + work(child)));
         }
     }
+    if n_linked < seen.len() {
+        warn!(target: "index", "Ignoring {} blocks which are not connected to the genesis block", seen.len() - n_linked);
+    }
+
+    let mut chain = vec![tip.0];
+    while let Some(i) = prev[*chain.last().unwrap()] {
+        chain.push(i);
+    }
+    let mut blocks: Vec<Option<ScannedBlock>> = blocks.into_iter().map(Some).collect();
+    let mut block_index = HashMap::with_capacity(chain.len());
+    for (height, i) in chain.into_iter().rev().enumerate() {
+        let block = blocks[i].take().unwrap();
+        let header = block.header.value;
+        let record = BlockIndexRecord {
+            block_hash: block.header.hash,
+            blk_index: 0,
+            data_offset: block.data_offset,
+            undo_offset: None,
+            timestamp: header.timestamp,
+            chain_work: None,
+            bits: header.bits,
+            version: header.version as u64,
+            height: height as u64,
+            status: BLOCK_VALID_CHAIN | BLOCK_HAVE_DATA,
+            tx_count: None,
+        };
+        block_index.insert(height as u64, record);
+    }
     info!(target: "index", "Got longest chain with {} blocks ...", block_index.len());
     Ok(block_index)
 }
 
+/// Copies the block index to a temporary directory, so it can be read while a node holds the lock.
+/// The LOCK file itself is skipped.
+fn copy_index(path: &Path) -> OpResult<PathBuf> {
+    if !path.is_dir() {
+        let msg = format!("No block index found at {}", path.display());
+        return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+    }
+    let copy = env::temp_dir().join(format!("rusty-blockparser-index-{}", process::id()));
+    info!(target: "index", "Copying index from {} to {} ...", path.display(), copy.display());
+    fs::create_dir_all(&copy)?;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_name() != "LOCK" && entry.file_type()?.is_file() {
+            fs::copy(entry.path(), copy.join(entry.file_name()))?;
+        }
+    }
+    Ok(copy)
+}
+
 #[inline]
 fn is_block_index_record(data: &[u8]) -> bool {
     *data.first().unwrap() == b'b'
 }
 
-/// TODO: this is a wonky 1:1 translation from https://github.com/bitcoin/bitcoin
-/// It is NOT the same as CompactSize.
-fn read_varint(reader: &mut Cursor<&[u8]>) -> OpResult<u64> {
-    let mut n = 0;
-    loop {
-        let ch_data = reader.read_u8()?;
-        if n > u64::MAX >> 7 {
-            panic!("size too large");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::types::Bitcoin;
+    use crate::common::fixtures::{build_index_record, MiniChain};
+
+    #[test]
+    fn test_block_context() {
+        let chain = MiniChain::generate(3);
+        let block_index = get_block_index(&chain.path().join("index")).unwrap();
+        // The genesis block only has the coinbase, all others spend the previous one
+        let context = block_index.get(&0).unwrap().context();
+        assert_eq!(context.tx_count, Some(1));
+        assert_eq!(context.status & BLOCK_HAVE_DATA, BLOCK_HAVE_DATA);
+        let context = block_index.get(&2).unwrap().context();
+        assert_eq!(context.tx_count, Some(2));
+        assert_eq!((context.blk_index, context.index_version), (0, 259900));
+    }
+
+    #[test]
+    fn test_record_offset_above_4gib() {
+        let data_offset = (5 << 30) + 8;
+        let values = build_index_record(7, 1, data_offset, &[0; 80]);
+        let record = BlockIndexRecord::from(&[0; 32], &values).unwrap();
+        assert_eq!((record.height, record.data_offset), (7, data_offset));
+    }
+
+    #[test]
+    fn test_bootstrap_index() {
+        let chain = MiniChain::generate(5);
+        let data = fs::read(chain.path().join("blk00000.dat")).unwrap();
+        // Split into records of magic, size and block
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < data.len() {
+            let size = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap());
+            records.push(&data[offset..offset + 8 + size as usize]);
+            offset += 8 + size as usize;
         }
-        n = (n << 7) | (ch_data & 0x7F) as u64;
-        if ch_data & 0x80 > 0 {
-            if n == u64::MAX {
-                panic!("size too large");
-            }
-            n += 1;
-        } else {
-            break;
+
+        // Store the blocks in reverse order with a duplicate and zero padding
+        let mut bootstrap = records[3].to_vec();
+        records
+            .iter()
+            .rev()
+            .for_each(|record| bootstrap.extend(*record));
+        bootstrap.extend([0; 16]);
+        let path = chain.path().join("bootstrap.dat");
+        fs::write(&path, &bootstrap).unwrap();
+
+        let block_index = get_bootstrap_index(&path, &CoinType::from(Bitcoin)).unwrap();
+        assert_eq!(block_index.len(), 5);
+        for (height, hash) in chain.hashes.iter().enumerate() {
+            let record = block_index.get(&(height as u64)).unwrap();
+            assert_eq!(record.block_hash.to_raw_hash(), *hash);
         }
+        // The genesis block is stored last
+        assert_eq!(
+            block_index.get(&0).unwrap().data_offset as usize,
+            bootstrap.len() - 16 - records[0].len() + 8
+        );
+
+        // Without genesis block, the chain can't be linked
+        fs::write(&path, records[1..].concat()).unwrap();
+        assert!(get_bootstrap_index(&path, &CoinType::from(Bitcoin)).is_err());
     }
-    Ok(n)
 }
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use bitcoin::hashes::Hash;
+use rusty_leveldb::{LdbIterator, DB};
+
+use crate::blockchain::parser::index::{
+    open_db, read_index, BlockIndexRecord, ChainIndexSource, BLOCK_HAVE_DATA, BLOCK_VALID_CHAIN,
+};
+use crate::blockchain::parser::reader::BlockchainRead;
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::BlockHash;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Name of the LevelDB database within btcd's `blocks_ffldb` directory
+pub const METADATA_DIR: &str = "metadata";
+/// Keys of nested buckets are prefixed with the 4 byte id of the bucket,
+/// the ids are stored under `bidx` + parent id + bucket name
+const BUCKET_INDEX_PREFIX: &[u8] = b"bidx";
+const METADATA_BUCKET_ID: [u8; 4] = [0; 4];
+/// Block hash -> (file number, offset, length) of the block in the *.fdb files
+const BLOCK_IDX_BUCKET: &[u8] = b"ffldb-blockidx";
+/// Height -> block hash of the main chain
+const HEIGHT_IDX_BUCKET: &[u8] = b"heightidx";
+/// Height + block hash -> header and status of all known blocks
+const HEADER_IDX_BUCKET: &[u8] = b"blockheaderidx";
+
+/// Returns true if the given directory has the layout of btcd's `blocks_ffldb`:
+/// a LevelDB database in `metadata` and the blocks in 000000000.fdb, 000000001.fdb, ...
+pub fn is_btcd_dir(path: &Path) -> bool {
+    path.join(METADATA_DIR).is_dir() && !path.join("index").is_dir()
+}
+
+/// Block index of btcd's ffldb database (`blocks_ffldb/metadata`)
+pub struct BtcdIndex {
+    path: PathBuf,
+    copy: bool, // reads a temporary copy, see `--copy-index-to-tmp`
+}
+
+impl BtcdIndex {
+    pub fn new(path: PathBuf, copy: bool) -> Self {
+        Self { path, copy }
+    }
+}
+
+impl ChainIndexSource for BtcdIndex {
+    fn read(&self, _: &CoinType) -> OpResult<HashMap<u64, BlockIndexRecord>> {
+        read_index(&self.path, self.copy, get_block_index)
+    }
+}
+
+/// Returns the id of the given bucket within the metadata bucket
+fn bucket_id(db: &mut DB, name: &[u8]) -> OpResult<[u8; 4]> {
+    let mut key = BUCKET_INDEX_PREFIX.to_vec();
+    key.extend(METADATA_BUCKET_ID);
+    key.extend(name);
+    match db.get(&key).and_then(|id| id.as_slice().try_into().ok()) {
+        Some(id) => Ok(id),
+        None => {
+            let msg = format!(
+                "Bucket {} not found, this is no btcd block database",
+                String::from_utf8_lossy(name)
+            );
+            Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg))
+        }
+    }
+}
+
+fn get_block_index(path: &Path) -> OpResult<HashMap<u64, BlockIndexRecord>> {
+    let mut db = open_db(path)?;
+    let block_idx = bucket_id(&mut db, BLOCK_IDX_BUCKET)?;
+    let height_idx = bucket_id(&mut db, HEIGHT_IDX_BUCKET)?;
+    let header_idx = bucket_id(&mut db, HEADER_IDX_BUCKET)?;
+
+    // (blk_index, offset) by block hash
+    let mut locations: HashMap<BlockHash, (u64, u64)> = HashMap::with_capacity(900000);
+    let mut main_chain: Vec<(u64, BlockHash)> = Vec::with_capacity(900000);
+    let mut headers: HashMap<BlockHash, BlockHeader> = HashMap::with_capacity(900000);
+    let mut db_iter = db.new_iter()?;
+    let (mut key, mut value) = (vec![], vec![]);
+    while db_iter.advance() {
+        db_iter.current(&mut key, &mut value);
+        if key.len() < 4 {
+            continue;
+        }
+        let (bucket, key) = key.split_at(4);
+        if bucket == block_idx && key.len() == 32 && value.len() == 12 {
+            let file = u32::from_le_bytes(value[0..4].try_into().unwrap());
+            let offset = u32::from_le_bytes(value[4..8].try_into().unwrap());
+            locations.insert(hash_from(key), (file as u64, offset as u64));
+        } else if bucket == height_idx && key.len() == 4 && value.len() == 32 {
+            let height = u32::from_le_bytes(key.try_into().unwrap());
+            main_chain.push((height as u64, hash_from(&value)));
+        } else if bucket == header_idx && key.len() == 36 {
+            let header = Cursor::new(&value).read_block_header()?;
+            headers.insert(hash_from(&key[4..]), header);
+        }
+    }
+
+    let mut block_index = HashMap::with_capacity(main_chain.len());
+    for (height, hash) in main_chain {
+        let (location, header) = match (locations.get(&hash), headers.get(&hash)) {
+            (Some(location), Some(header)) => (location, header),
+            _ => {
+                warn!(target: "index", "Block {} at height {} is not stored, skipping", hash, height);
+                continue;
+            }
+        };
+        let record = BlockIndexRecord {
+            block_hash: hash,
+            blk_index: location.0,
+            // Skip network and length in front of the block
+            data_offset: location.1 + 8,
+            undo_offset: None,
+            timestamp: header.timestamp,
+            chain_work: None,
+            bits: header.bits,
+            version: header.version as u64,
+            height,
+            status: BLOCK_VALID_CHAIN | BLOCK_HAVE_DATA,
+            tx_count: None,
+        };
+        block_index.insert(height, record);
+    }
+    if block_index.is_empty() {
+        let msg = format!("Block index at {} contains no blocks", path.display());
+        return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+    }
+    info!(target: "index", "Got longest chain with {} blocks ...", block_index.len());
+    Ok(block_index)
+}
+
+#[inline]
+fn hash_from(bytes: &[u8]) -> BlockHash {
+    BlockHash::from_byte_array(bytes.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rusty_leveldb::Options;
+
+    use super::*;
+    use crate::blockchain::parser::chain::ChainStorage;
+    use crate::common::fixtures::MiniChain;
+    use crate::{command, parse_args};
+
+    /// Rewrites the mini-chain into btcd's layout
+    fn write_btcd_dir(chain: &MiniChain, path: &Path) {
+        let data = fs::read(chain.path().join("blk00000.dat")).unwrap();
+        let mut fdb = Vec::new();
+        let mut db = DB::open(path.join(METADATA_DIR), Options::default()).unwrap();
+        for (name, id) in [
+            (BLOCK_IDX_BUCKET, 1u8),
+            (HEIGHT_IDX_BUCKET, 2),
+            (HEADER_IDX_BUCKET, 3),
+        ] {
+            let mut key = BUCKET_INDEX_PREFIX.to_vec();
+            key.extend(METADATA_BUCKET_ID);
+            key.extend(name);
+            db.put(&key, &[0, 0, 0, id]).unwrap();
+        }
+
+        let mut offset = 0;
+        for (height, hash) in chain.hashes.iter().enumerate() {
+            let size = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap());
+            let record = &data[offset..offset + 8 + size as usize];
+            let mut location = (fdb.len() as u32).to_le_bytes().to_vec();
+            location.splice(0..0, 0u32.to_le_bytes());
+            location.extend(size.to_le_bytes());
+            // Records are followed by a crc32 checksum
+            fdb.extend(record);
+            fdb.extend([0; 4]);
+            offset += record.len();
+
+            let mut key = vec![0, 0, 0, 1];
+            key.extend(hash.as_byte_array());
+            db.put(&key, &location).unwrap();
+            let mut key = vec![0, 0, 0, 2];
+            key.extend((height as u32).to_le_bytes());
+            db.put(&key, hash.as_byte_array()).unwrap();
+            let mut key = vec![0, 0, 0, 3];
+            key.extend((height as u32).to_be_bytes());
+            key.extend(hash.as_byte_array());
+            let mut value = record[8..88].to_vec();
+            value.push(3);
+            db.put(&key, &value).unwrap();
+        }
+        db.flush().unwrap();
+        fs::write(path.join("000000000.fdb"), fdb).unwrap();
+    }
+
+    #[test]
+    fn test_btcd_index() {
+        let chain = MiniChain::generate(5);
+        let dir = tempfile::tempdir().unwrap();
+        write_btcd_dir(&chain, dir.path());
+        assert!(is_btcd_dir(dir.path()));
+        assert!(!is_btcd_dir(chain.path()));
+
+        let block_index = get_block_index(&dir.path().join(METADATA_DIR)).unwrap();
+        assert_eq!(block_index.len(), 5);
+        assert_eq!(block_index.get(&0).unwrap().data_offset, 8);
+
+        let args = [
+            "rusty-blockparser",
+            "-d",
+            dir.path().to_str().unwrap(),
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        let mut storage = ChainStorage::new(&options).unwrap();
+        for (height, hash) in chain.hashes.iter().enumerate() {
+            match storage.get_block(height as u64) {
+                Some(Ok(block)) => assert_eq!(block.header.hash.to_raw_hash(), *hash),
+                _ => panic!("block {} not found", height),
+            }
+        }
+        assert!(storage.get_block(5).is_none());
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use clap::{Arg, Command};
+
+use crate::blockchain::parser::index::{get_block_index, read_index, BlockIndexRecord};
+use crate::blockchain::proto::BlockHash;
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Tip of a chain: height, hash and timestamp
+type Tip = (u64, BlockHash, u32);
+
+/// Compares the longest chains of two block indexes without reading any blocks,
+/// e.g. to find the split of a fork (Bitcoin and Bitcoin Cash) or to check that
+/// a mirrored archive holds the same chain as the node it was copied from.
+#[cfg_attr(test, derive(PartialEq, Debug))]
+struct Comparison {
+    tips: [Option<Tip>; 2],
+    // Highest block both chains agree on
+    ancestor: Option<Tip>,
+    // Blocks after the common ancestor, by chain
+    n_diverged: [u64; 2],
+    // First block of each chain after the common ancestor
+    first_diverged: [Option<Tip>; 2],
+    // Heights below the common ancestor missing in one of the indexes
+    n_missing: [u64; 2],
+}
+
+pub struct CompareChains;
+
+impl CompareChains {
+    pub fn build_subcommand() -> Command {
+        Command::new("comparechains")
+            .about("Reports the common ancestor and divergence of the chains in two block indexes without parsing blocks")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("other-dir")
+                    .help("Blockchain directory to compare --blockchain-dir with, its index is read from <other-dir>/index")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    /// Reads both indexes and prints the comparison to stdout
+    pub fn run(first_index: &Path, second_index: &Path, copy: bool) -> OpResult<()> {
+        let first = read_index(first_index, copy, get_block_index)?;
+        let second = read_index(second_index, copy, get_block_index)?;
+        let comparison = Comparison::new(&first, &second);
+
+        let mut writer = io::stdout().lock();
+        comparison.write(&mut writer, [first_index, second_index])?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn tip(record: &BlockIndexRecord) -> Tip {
+    (record.height, record.block_hash, record.timestamp)
+}
+
+impl Comparison {
+    fn new(
+        first: &HashMap<u64, BlockIndexRecord>,
+        second: &HashMap<u64, BlockIndexRecord>,
+    ) -> Self {
+        let chains = [first, second];
+        let tips = chains.map(|chain| chain.values().max_by_key(|r| r.height).map(tip));
+        // Blocks commit to their predecessors, so the chains agree on everything below
+        // the highest height with the same hash
+        let ancestor = first
+            .values()
+            .filter(|r| second.get(&r.height).map(|o| o.block_hash) == Some(r.block_hash))
+            .max_by_key(|r| r.height)
+            .map(tip);
+        let start = ancestor.map_or(0, |(height, _, _)| height + 1);
+        let n_diverged = chains.map(|chain| chain.keys().filter(|h| **h >= start).count() as u64);
+        let first_diverged = chains.map(|chain| chain.get(&start).map(tip));
+        let n_missing =
+            chains.map(|chain| (0..start).filter(|h| !chain.contains_key(h)).count() as u64);
+        Comparison {
+            tips,
+            ancestor,
+            n_diverged,
+            first_diverged,
+            n_missing,
+        }
+    }
+
+    fn write<W: Write>(&self, writer: &mut W, paths: [&Path; 2]) -> io::Result<()> {
+        let or_dash = |tip: &Option<Tip>| match tip {
+            Some((height, hash, timestamp)) => {
+                format!("{} {} ({})", height, hash, utils::day_of(*timestamp))
+            }
+            None => String::from("-"),
+        };
+        writeln!(writer, "CompareChains:")?;
+        for (i, path) in paths.iter().enumerate() {
+            writeln!(writer, "   -> chain {}:\t\t{}", i + 1, path.display())?;
+            writeln!(writer, "        tip:\t\t{}", or_dash(&self.tips[i]))?;
+        }
+        writeln!(
+            writer,
+            "   -> common ancestor:\t{}",
+            or_dash(&self.ancestor)
+        )?;
+        for i in 0..2 {
+            writeln!(
+                writer,
+                "   -> chain {} diverged:\t{} blocks",
+                i + 1,
+                self.n_diverged[i]
+            )?;
+            writeln!(
+                writer,
+                "        first:\t\t{}",
+                or_dash(&self.first_diverged[i])
+            )?;
+        }
+        let status = match (self.n_diverged, self.ancestor) {
+            (_, None) => String::from("no common blocks"),
+            ([0, 0], _) => String::from("identical"),
+            ([0, n], _) => format!("chain 1 is behind by {} blocks", n),
+            ([n, 0], _) => format!("chain 2 is behind by {} blocks", n),
+            _ => String::from("forked"),
+        };
+        writeln!(writer, "   -> status:\t\t{}", status)?;
+        if self.n_missing != [0, 0] {
+            writeln!(
+                writer,
+                "   -> missing below ancestor:\t{} and {} heights",
+                self.n_missing[0], self.n_missing[1]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::common::fixtures::MiniChain;
+
+    fn chain(hashes: &[u8]) -> HashMap<u64, BlockIndexRecord> {
+        hashes
+            .iter()
+            .enumerate()
+            .map(|(height, hash)| {
+                let record = BlockIndexRecord {
+                    block_hash: BlockHash::from_byte_array([*hash; 32]),
+                    blk_index: 0,
+                    data_offset: 0,
+                    undo_offset: None,
+                    timestamp: height as u32,
+                    chain_work: None,
+                    bits: 0,
+                    version: 1,
+                    height: height as u64,
+                    status: 3 | 8,
+                    tx_count: None,
+                };
+                (height as u64, record)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_comparison() {
+        let comparison = Comparison::new(&chain(&[0, 1, 2, 3, 4]), &chain(&[0, 1, 2, 5]));
+        let block = |height, hash| {
+            Some((
+                height,
+                BlockHash::from_byte_array([hash; 32]),
+                height as u32,
+            ))
+        };
+        assert_eq!(comparison.ancestor, block(2, 2));
+        assert_eq!(comparison.tips, [block(4, 4), block(3, 5)]);
+        assert_eq!(comparison.n_diverged, [2, 1]);
+        assert_eq!(comparison.first_diverged, [block(3, 3), block(3, 5)]);
+        assert_eq!(comparison.n_missing, [0, 0]);
+
+        let comparison = Comparison::new(&chain(&[0, 1]), &chain(&[2, 3]));
+        assert_eq!(comparison.ancestor, None);
+        assert_eq!(comparison.n_diverged, [2, 2]);
+    }
+
+    #[test]
+    fn test_compare_chains() {
+        let (first, second) = (MiniChain::generate(5), MiniChain::generate(3));
+        let first = get_block_index(&first.path().join("index")).unwrap();
+        let second = get_block_index(&second.path().join("index")).unwrap();
+        let comparison = Comparison::new(&first, &second);
+        assert_eq!(comparison.ancestor.map(|(height, _, _)| height), Some(2));
+        assert_eq!(comparison.n_diverged, [2, 0]);
+
+        let mut out = Vec::new();
+        comparison
+            .write(&mut out, [Path::new("a"), Path::new("b")])
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("status:\t\tchain 2 is behind by 2 blocks\n"));
+    }
+}
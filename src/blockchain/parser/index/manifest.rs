@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use clap::{Arg, Command};
+use rayon::prelude::*;
+
+use crate::blockchain::parser::blkfile::{BlkFile, XorKey};
+use crate::blockchain::parser::index::{read_index, read_records, BLOCK_HAVE_DATA};
+use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Format version of the manifest file
+const MANIFEST_VERSION: u64 = 1;
+/// Chunk size for hashing files
+const HASH_CHUNK_SIZE: usize = 1 << 20;
+
+/// Recorded state of a single blk file
+#[cfg_attr(test, derive(PartialEq, Debug))]
+struct Entry {
+    file: String,
+    size: u64,
+    sha256: String,
+    // Number of blocks with data in this file and their heights, according to the block index
+    n_blocks: u64,
+    heights: Option<(u64, u64)>,
+}
+
+impl Entry {
+    fn as_json(&self) -> String {
+        let (first, last) = match self.heights {
+            Some((first, last)) => (first.to_string(), last.to_string()),
+            None => (String::from("null"), String::from("null")),
+        };
+        format!(
+            "{{\"file\": \"{}\", \"size\": {}, \"sha256\": \"{}\", \"blocks\": {}, \"firstHeight\": {}, \"lastHeight\": {}}}",
+            self.file, self.size, self.sha256, self.n_blocks, first, last
+        )
+    }
+
+    /// Parses a line written by `as_json`
+    fn from_json(line: &str) -> Option<Entry> {
+        let number = |key| field(line, key).and_then(|v| v.parse::<u64>().ok());
+        let heights = match (number("firstHeight"), number("lastHeight")) {
+            (Some(first), Some(last)) => Some((first, last)),
+            _ => None,
+        };
+        Some(Entry {
+            file: String::from(field(line, "file")?),
+            size: number("size")?,
+            sha256: String::from(field(line, "sha256")?),
+            n_blocks: number("blocks")?,
+            heights,
+        })
+    }
+}
+
+/// Returns the value of `"key": value` in a flat JSON object without the quotes of strings.
+/// The manifest only contains file names, hex and numbers, so this saves a JSON parser.
+fn field<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let pattern = format!("\"{}\"", key);
+    let start = json.find(&pattern)? + pattern.len();
+    let rest = json[start..].trim_start().strip_prefix(':')?.trim_start();
+    match rest.strip_prefix('"') {
+        Some(string) => string.split('"').next(),
+        None => rest.split([',', '}']).next().map(str::trim),
+    }
+}
+
+/// Hashes the first `len` bytes of the file as stored on disk, i.e. still obfuscated
+fn hash_file(path: &Path, len: u64) -> io::Result<String> {
+    let mut reader = File::open(path)?.take(len);
+    let mut engine = sha256::Hash::engine();
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        engine.input(&buffer[..n]);
+    }
+    Ok(utils::arr_to_hex(
+        &sha256::Hash::from_engine(engine).to_byte_array(),
+    ))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Sizes and SHA-256 hashes of all blk files along with the heights of the blocks they contain.
+/// Runs with `--manifest` check the blk files against it before parsing, so files which
+/// were silently changed or truncated since are detected before producing corrupted dumps.
+/// Bitcoin Core appends to the last blk files, so files may have grown since.
+pub struct Manifest {
+    entries: BTreeMap<u64, Entry>,
+}
+
+impl Manifest {
+    pub fn build_subcommand() -> Command {
+        Command::new("manifest")
+            .about("Writes the sizes, SHA-256 hashes and block heights of all blk files to a manifest for --manifest")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("file")
+                    .help("File to write the manifest to, e.g. manifest.json")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    /// Hashes all blk files in `blocks_dir` and writes the manifest to `path`.
+    /// The heights are taken from the block index in `index_dir`.
+    pub fn run(blocks_dir: &Path, index_dir: &Path, copy: bool, path: &Path) -> OpResult<()> {
+        let blk_files = BlkFile::from_path(blocks_dir, XorKey::Disabled)?;
+        let records = read_index(index_dir, copy, read_records)?;
+        let mut heights: HashMap<u64, (u64, u64, u64)> = HashMap::new();
+        for record in records.iter().filter(|r| r.status & BLOCK_HAVE_DATA > 0) {
+            let (n, first, last) =
+                heights
+                    .entry(record.blk_index)
+                    .or_insert((0, record.height, record.height));
+            *n += 1;
+            *first = record.height.min(*first);
+            *last = record.height.max(*last);
+        }
+
+        info!(target: "manifest", "Hashing {} blk files ...", blk_files.len());
+        let entries = blk_files
+            .par_iter()
+            .map(|(index, blk_file)| {
+                let heights = heights.get(index);
+                let entry = Entry {
+                    file: file_name(&blk_file.path),
+                    size: blk_file.size,
+                    sha256: hash_file(&blk_file.path, blk_file.size)?,
+                    n_blocks: heights.map_or(0, |(n, _, _)| *n),
+                    heights: heights.map(|(_, first, last)| (*first, *last)),
+                };
+                Ok((*index, entry))
+            })
+            .collect::<OpResult<BTreeMap<u64, Entry>>>()?;
+        let manifest = Manifest { entries };
+        fs::write(path, manifest.to_json())?;
+        info!(target: "manifest", "Wrote manifest of {} blk files to {}", manifest.entries.len(), path.display());
+        Ok(())
+    }
+
+    fn to_json(&self) -> String {
+        let files: Vec<String> = self
+            .entries
+            .values()
+            .map(|entry| format!("    {}", entry.as_json()))
+            .collect();
+        format!(
+            "{{\n  \"version\": {},\n  \"files\": [\n{}\n  ]\n}}\n",
+            MANIFEST_VERSION,
+            files.join(",\n")
+        )
+    }
+
+    /// Reads a manifest written by the `manifest` subcommand
+    pub fn load(path: &Path) -> OpResult<Self> {
+        let content = fs::read_to_string(path)?;
+        let invalid = |msg: &str| {
+            let msg = format!("{} is no valid manifest: {}", path.display(), msg);
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg)
+        };
+        match field(&content, "version") {
+            Some(version) if version == MANIFEST_VERSION.to_string() => (),
+            _ => return Err(invalid("unsupported version")),
+        }
+        let mut entries = BTreeMap::new();
+        // Each file is written on its own line
+        for line in content.lines().filter(|line| line.contains("\"file\"")) {
+            let entry = Entry::from_json(line).ok_or_else(|| invalid(line.trim()))?;
+            let index = entry
+                .file
+                .strip_prefix("blk")
+                .and_then(|name| name.strip_suffix(".dat"))
+                .and_then(|index| index.parse::<u64>().ok())
+                .ok_or_else(|| invalid(&entry.file))?;
+            entries.insert(index, entry);
+        }
+        Ok(Manifest { entries })
+    }
+
+    /// Checks that all files of the manifest exist, are not smaller than recorded and
+    /// that the recorded part is unchanged. Files which are not in the manifest are ignored.
+    pub fn verify(&self, blk_files: &HashMap<u64, BlkFile>) -> OpResult<()> {
+        info!(target: "manifest", "Verifying {} blk files against the manifest ...", self.entries.len());
+        let mut problems: Vec<String> = self
+            .entries
+            .par_iter()
+            .filter_map(|(index, entry)| {
+                let blk_file = match blk_files.get(index) {
+                    Some(blk_file) if file_name(&blk_file.path) == entry.file => blk_file,
+                    _ => return Some(format!("{} is missing", entry.file)),
+                };
+                if blk_file.size < entry.size {
+                    return Some(format!(
+                        "{} was truncated from {} to {} bytes",
+                        entry.file, entry.size, blk_file.size
+                    ));
+                }
+                match hash_file(&blk_file.path, entry.size) {
+                    Ok(hash) if hash == entry.sha256 => None,
+                    Ok(_) => Some(format!("{} has changed", entry.file)),
+                    Err(why) => Some(format!("{} can't be read: {}", entry.file, why)),
+                }
+            })
+            .collect();
+        if problems.is_empty() {
+            return Ok(());
+        }
+        problems.sort();
+        for problem in &problems {
+            error!(target: "manifest", "{}", problem);
+        }
+        let msg = format!(
+            "{} of {} blk files don't match the manifest",
+            problems.len(),
+            self.entries.len()
+        );
+        Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::common::fixtures::MiniChain;
+
+    #[test]
+    fn test_manifest() {
+        let chain = MiniChain::generate(5);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manifest.json");
+        Manifest::run(chain.path(), &chain.path().join("index"), false, &path).unwrap();
+
+        let manifest = Manifest::load(&path).unwrap();
+        let entry = &manifest.entries[&0];
+        assert_eq!(entry.file, "blk00000.dat");
+        assert_eq!(entry.n_blocks, 5);
+        assert_eq!(entry.heights, Some((0, 4)));
+        assert_eq!(Entry::from_json(&entry.as_json()).as_ref(), Some(entry));
+
+        // Appended blocks are fine, changed or truncated data is not
+        let blk_path = chain.path().join("blk00000.dat");
+        let mut file = fs::OpenOptions::new().append(true).open(&blk_path).unwrap();
+        file.write_all(&[0; 16]).unwrap();
+        let blk_files = BlkFile::from_path(chain.path(), XorKey::Disabled).unwrap();
+        assert!(manifest.verify(&blk_files).is_ok());
+
+        let mut data = fs::read(&blk_path).unwrap();
+        data[100] ^= 1;
+        fs::write(&blk_path, &data).unwrap();
+        let blk_files = BlkFile::from_path(chain.path(), XorKey::Disabled).unwrap();
+        assert!(manifest.verify(&blk_files).is_err());
+
+        fs::write(&blk_path, &data[..entry.size as usize - 1]).unwrap();
+        let blk_files = BlkFile::from_path(chain.path(), XorKey::Disabled).unwrap();
+        assert!(manifest.verify(&blk_files).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::path::Path;
+
+use clap::Command;
+
+use crate::blockchain::parser::index::{
+    read_index, read_records, BlockIndexRecord, BLOCK_FAILED_CHILD, BLOCK_FAILED_VALID,
+    BLOCK_HAVE_DATA, BLOCK_HAVE_UNDO,
+};
+use crate::errors::OpResult;
+
+/// The lowest bits of the status hold the validation level (see BlockStatus in Core's chain.h)
+const BLOCK_VALID_MASK: u64 = 7;
+const VALIDITY_LEVELS: [&str; 6] = [
+    "unknown",
+    "header",
+    "tree",
+    "transactions",
+    "chain",
+    "scripts",
+];
+/// Maximum number of gaps to list
+const MAX_GAPS: usize = 10;
+
+/// Summarizes the block index without reading any blocks,
+/// e.g. to find out why a run processes fewer blocks than expected.
+#[derive(Default)]
+pub struct IndexStats {
+    n_records: u64,
+    max_height: Option<u64>,
+    // Heights of all blocks with data
+    heights: BTreeSet<u64>,
+    // Number of blocks with data by blk file
+    blk_files: BTreeMap<u64, u64>,
+    // Number of records by validation level
+    levels: BTreeMap<u64, u64>,
+    n_have_undo: u64,
+    n_failed_valid: u64,
+    n_failed_child: u64,
+}
+
+impl IndexStats {
+    pub fn build_subcommand() -> Command {
+        Command::new("indexstats")
+            .about("Reports heights, blk files, gaps and status flags of the block index without parsing blocks")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+    }
+
+    /// Reads the index in `index_dir` and prints the statistics to stdout.
+    /// blk files are looked up in `blocks_dir`.
+    pub fn run(blocks_dir: &Path, index_dir: &Path, copy: bool) -> OpResult<()> {
+        let records = read_index(index_dir, copy, read_records)?;
+        let mut stats = IndexStats::default();
+        records.iter().for_each(|record| stats.add(record));
+
+        let mut writer = io::stdout().lock();
+        stats.write(&mut writer, blocks_dir)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn add(&mut self, record: &BlockIndexRecord) {
+        self.n_records += 1;
+        self.max_height = self.max_height.max(Some(record.height));
+        *self
+            .levels
+            .entry(record.status & BLOCK_VALID_MASK)
+            .or_insert(0) += 1;
+        if record.status & BLOCK_HAVE_DATA > 0 {
+            self.heights.insert(record.height);
+            *self.blk_files.entry(record.blk_index).or_insert(0) += 1;
+        }
+        if record.status & BLOCK_HAVE_UNDO > 0 {
+            self.n_have_undo += 1;
+        }
+        if record.status & BLOCK_FAILED_VALID > 0 {
+            self.n_failed_valid += 1;
+        }
+        if record.status & BLOCK_FAILED_CHILD > 0 {
+            self.n_failed_child += 1;
+        }
+    }
+
+    /// Returns the ranges of heights below the highest block with data which have no data
+    fn gaps(&self) -> Vec<(u64, u64)> {
+        let mut gaps = Vec::new();
+        let mut next = 0;
+        for height in &self.heights {
+            if *height > next {
+                gaps.push((next, height - 1));
+            }
+            next = height + 1;
+        }
+        gaps
+    }
+
+    fn write<W: Write>(&self, writer: &mut W, blocks_dir: &Path) -> io::Result<()> {
+        let or_dash = |value: Option<&u64>| value.map_or(String::from("-"), u64::to_string);
+        writeln!(writer, "IndexStats:")?;
+        writeln!(writer, "   -> records:\t\t\t{}", self.n_records)?;
+        writeln!(
+            writer,
+            "   -> max height:\t\t{}",
+            or_dash(self.max_height.as_ref())
+        )?;
+        writeln!(writer, "   -> blocks with data:\t\t{}", self.heights.len())?;
+        writeln!(
+            writer,
+            "   -> max height with data:\t{}",
+            or_dash(self.heights.last())
+        )?;
+
+        let missing: Vec<u64> = self
+            .blk_files
+            .keys()
+            .filter(|i| !blocks_dir.join(format!("blk{:05}.dat", i)).is_file())
+            .copied()
+            .collect();
+        writeln!(
+            writer,
+            "   -> blk files:\t\t{} ({} to {}), {} missing on disk",
+            self.blk_files.len(),
+            or_dash(self.blk_files.keys().next()),
+            or_dash(self.blk_files.keys().last()),
+            missing.len()
+        )?;
+        for i in missing.iter().take(MAX_GAPS) {
+            writeln!(
+                writer,
+                "        blk{:05}.dat\t\t{} blocks",
+                i, self.blk_files[i]
+            )?;
+        }
+
+        let gaps = self.gaps();
+        let n_missing: u64 = gaps.iter().map(|(start, end)| end - start + 1).sum();
+        writeln!(
+            writer,
+            "   -> gaps:\t\t\t{} ({} heights without data)",
+            gaps.len(),
+            n_missing
+        )?;
+        for (start, end) in gaps.iter().take(MAX_GAPS) {
+            writeln!(writer, "        {} to {}", start, end)?;
+        }
+
+        writeln!(writer, "   -> status:")?;
+        for (level, count) in &self.levels {
+            let name = VALIDITY_LEVELS.get(*level as usize).unwrap_or(&"invalid");
+            writeln!(writer, "        valid {}:\t\t{}", name, count)?;
+        }
+        writeln!(writer, "        have data:\t\t{}", self.heights.len())?;
+        writeln!(writer, "        have undo:\t\t{}", self.n_have_undo)?;
+        writeln!(writer, "        failed:\t\t\t{}", self.n_failed_valid)?;
+        writeln!(writer, "        failed parent:\t\t{}", self.n_failed_child)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::blockchain::proto::BlockHash;
+    use crate::common::fixtures::MiniChain;
+
+    fn record(height: u64, blk_index: u64, status: u64) -> BlockIndexRecord {
+        BlockIndexRecord {
+            block_hash: BlockHash::all_zeros(),
+            blk_index,
+            data_offset: 0,
+            undo_offset: None,
+            timestamp: 0,
+            chain_work: None,
+            bits: 0,
+            version: 1,
+            height,
+            status,
+            tx_count: None,
+        }
+    }
+
+    #[test]
+    fn test_index_stats() {
+        let chain = MiniChain::generate(5);
+        let mut stats = IndexStats::default();
+        for record in read_records(&chain.path().join("index")).unwrap() {
+            stats.add(&record);
+        }
+        let mut out = Vec::new();
+        stats.write(&mut out, chain.path()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("records:\t\t\t5\n"));
+        assert!(out.contains("max height:\t\t4\n"));
+        assert!(out.contains("blk files:\t\t1 (0 to 0), 0 missing on disk\n"));
+        assert!(out.contains("gaps:\t\t\t0 (0 heights without data)\n"));
+        assert!(out.contains("valid transactions:\t\t5\n"));
+    }
+
+    #[test]
+    fn test_gaps() {
+        let mut stats = IndexStats::default();
+        for (height, blk_index) in [(0, 0), (1, 0), (4, 1), (5, 1), (8, 2)] {
+            stats.add(&record(height, blk_index, 3 | BLOCK_HAVE_DATA));
+        }
+        // Headers without data and a failed block
+        stats.add(&record(9, 0, 1));
+        stats.add(&record(3, 0, 1 | BLOCK_FAILED_VALID));
+        assert_eq!(stats.gaps(), [(2, 3), (6, 7)]);
+        assert_eq!(stats.max_height, Some(9));
+        assert_eq!(stats.levels[&1], 2);
+        assert_eq!(stats.n_failed_valid, 1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut out = Vec::new();
+        stats.write(&mut out, dir.path()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("3 missing on disk\n        blk00000.dat\t\t2 blocks\n"));
+        assert!(out.contains("gaps:\t\t\t2 (4 heights without data)\n        2 to 3\n"));
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{TimeZone, Utc};
+
+use crate::blockchain::parser::blkfile::BlkFile;
+use crate::blockchain::parser::index::BlockIndexRecord;
+use crate::blockchain::parser::types::{CoinType, COIN_NAMES};
+use crate::blockchain::proto::BlockHash;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Tips older than this are reported as stale
+const STALE_TIP_SECS: u64 = 24 * 60 * 60;
+/// Number of blk files after the last indexed one that are tolerated,
+/// a node may have started the next file before its blocks are indexed.
+const MAX_UNINDEXED_FILES: usize = 1;
+
+/// Checks the block index against the selected coin and the blk files before anything is parsed,
+/// so an unsynced node or mismatching copies fail with an explanation instead of yielding a few blocks.
+/// Errors about a partial sync are logged as warnings if `allow_partial` is set.
+pub fn check(
+    block_index: &HashMap<u64, BlockIndexRecord>,
+    blk_files: &HashMap<u64, BlkFile>,
+    coin: &CoinType,
+    allow_partial: bool,
+) -> OpResult<()> {
+    check_genesis(block_index, coin)?;
+    let problems = missing_data(block_index, blk_files)
+        .into_iter()
+        .chain(unindexed_files(block_index, blk_files));
+    for problem in problems {
+        if !allow_partial {
+            let msg = format!(
+                "{}\nPass --allow-partial-sync to parse the indexed blocks anyway.",
+                problem
+            );
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+        }
+        warn!(target: "index", "{}", problem);
+    }
+    warn_stale_tip(block_index);
+    Ok(())
+}
+
+/// The genesis block of another supported coin means `--coin` is wrong.
+/// An unknown one is accepted as custom chain, e.g. regtest.
+fn check_genesis(block_index: &HashMap<u64, BlockIndexRecord>, coin: &CoinType) -> OpResult<()> {
+    let genesis = match block_index.get(&0) {
+        Some(record) => record.block_hash,
+        None => {
+            let msg = "The block index contains no genesis block, the blocks directory is probably incomplete";
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(msg));
+        }
+    };
+    if genesis == coin.genesis_hash {
+        return Ok(());
+    }
+    match coin_of_genesis(&genesis) {
+        Some(name) => {
+            let msg = format!(
+                "The block index belongs to {} instead of {}, use `--coin {}`",
+                name, coin.name, name
+            );
+            Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg))
+        }
+        None => {
+            warn!(target: "index", "Genesis block {} doesn't match {}, assuming a custom chain", genesis, coin.name);
+            Ok(())
+        }
+    }
+}
+
+/// Returns the name of the supported coin with the given genesis block
+fn coin_of_genesis(genesis: &BlockHash) -> Option<&'static str> {
+    COIN_NAMES
+        .iter()
+        .copied()
+        .find(|name| CoinType::from_str(name).is_ok_and(|coin| coin.genesis_hash == *genesis))
+}
+
+/// The index refers to blocks beyond the available blk data if the blk files were copied or
+/// restored separately from the index, or deleted by pruning.
+fn missing_data(
+    block_index: &HashMap<u64, BlockIndexRecord>,
+    blk_files: &HashMap<u64, BlkFile>,
+) -> Option<String> {
+    let (height, record) = block_index
+        .iter()
+        .filter(|(_, record)| {
+            blk_files
+                .get(&record.blk_index)
+                .is_none_or(|file| record.data_offset >= file.size)
+        })
+        .min_by_key(|(height, _)| **height)?;
+    let location = match blk_files.get(&record.blk_index) {
+        Some(file) => format!(
+            "{} ends at offset {}, before {}",
+            file.path.display(),
+            file.size,
+            record.data_offset
+        ),
+        None => format!("blk file {} doesn't exist", record.blk_index),
+    };
+    Some(format!(
+        "The block index is newer than the blk files, the data from height {} on is missing ({}). \
+         Were the blk files copied separately from the index, or is the node pruned?",
+        height, location
+    ))
+}
+
+/// Blk files after the last one referenced by the index hold blocks the node hasn't indexed yet,
+/// e.g. while it is still syncing or reindexing.
+fn unindexed_files(
+    block_index: &HashMap<u64, BlockIndexRecord>,
+    blk_files: &HashMap<u64, BlkFile>,
+) -> Option<String> {
+    let mut n_blocks: BTreeMap<u64, u64> = BTreeMap::new();
+    for record in block_index.values() {
+        *n_blocks.entry(record.blk_index).or_insert(0) += 1;
+    }
+    let last_indexed = *n_blocks.keys().next_back()?;
+    let n_unindexed = blk_files.keys().filter(|i| **i > last_indexed).count();
+    if n_unindexed <= MAX_UNINDEXED_FILES {
+        return None;
+    }
+    let max_height = block_index.keys().max().copied().unwrap_or_default();
+    Some(format!(
+        "The block index only covers {} blocks up to height {}, stored in {} of {} blk files. \
+         The node is probably still syncing or reindexing, wait until it is done and stop it before parsing.",
+        block_index.len(),
+        max_height,
+        n_blocks.len(),
+        blk_files.len()
+    ))
+}
+
+/// Logs a warning if the tip is older than a day, the node may not be fully synced.
+/// Not an error, as parsing old copies or stopped chains is fine.
+fn warn_stale_tip(block_index: &HashMap<u64, BlockIndexRecord>) {
+    let Some((height, tip)) = block_index.iter().max_by_key(|(height, _)| **height) else {
+        return;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let age = now.saturating_sub(tip.timestamp as u64);
+    if age > STALE_TIP_SECS {
+        let time = Utc
+            .timestamp_opt(tip.timestamp as i64, 0)
+            .single()
+            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        warn!(target: "index", "The tip at height {} is from {} ({} days ago), the node may not be fully synced",
+              height, time, age / STALE_TIP_SECS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::blkfile::XorKey;
+    use crate::blockchain::parser::types::{Bitcoin, Litecoin};
+    use crate::common::fixtures::MiniChain;
+    use crate::{command, parse_args};
+    use std::fs;
+
+    fn read(chain: &MiniChain) -> (HashMap<u64, BlockIndexRecord>, HashMap<u64, BlkFile>) {
+        let args = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        let block_index = super::super::index_source(&options)
+            .read(&options.coin)
+            .unwrap();
+        let blk_files = BlkFile::from_path(chain.path(), XorKey::Auto).unwrap();
+        (block_index, blk_files)
+    }
+
+    #[test]
+    fn test_check() {
+        let chain = MiniChain::generate(5);
+        let coin = CoinType::from(Bitcoin);
+        let (block_index, blk_files) = read(&chain);
+        assert!(check(&block_index, &blk_files, &coin, false).is_ok());
+
+        // Blocks after the last indexed blk file
+        let blk = chain.path().join("blk00000.dat");
+        for i in 1..3 {
+            fs::copy(&blk, chain.path().join(format!("blk{:05}.dat", i))).unwrap();
+        }
+        let (block_index, blk_files) = read(&chain);
+        let problem = unindexed_files(&block_index, &blk_files).unwrap();
+        assert!(problem.contains("5 blocks up to height 4, stored in 1 of 3 blk files"));
+        assert!(check(&block_index, &blk_files, &coin, false).is_err());
+        assert!(check(&block_index, &blk_files, &coin, true).is_ok());
+
+        // Blk data shorter than the index expects
+        let data = fs::read(&blk).unwrap();
+        fs::write(&blk, &data[..data.len() / 2]).unwrap();
+        let (block_index, blk_files) = read(&chain);
+        let problem = missing_data(&block_index, &blk_files).unwrap();
+        assert!(problem.contains("the data from height 3 on is missing"));
+    }
+
+    #[test]
+    fn test_check_genesis() {
+        let mut block_index = HashMap::new();
+        assert!(check_genesis(&block_index, &CoinType::from(Bitcoin)).is_err());
+
+        let chain = MiniChain::generate(1);
+        block_index = read(&chain).0;
+        // The generated chain has its own genesis block
+        assert!(check_genesis(&block_index, &CoinType::from(Bitcoin)).is_ok());
+        let bitcoin = CoinType::from(Bitcoin).genesis_hash;
+        block_index.get_mut(&0).unwrap().block_hash = bitcoin;
+        let err = check_genesis(&block_index, &CoinType::from(Litecoin)).unwrap_err();
+        assert!(err.to_string().contains("use `--coin bitcoin`"));
+        assert_eq!(coin_of_genesis(&bitcoin), Some("bitcoin"));
+    }
+}
@@ -1,41 +1,94 @@
+use std::fmt;
 use std::time::{Duration, Instant};
 
-use crate::blockchain::parser::chain::ChainStorage;
+use crate::blockchain::parser::chain::{ChainStorage, CorruptBlock};
+use crate::blockchain::parser::filter::Filter;
+use crate::blockchain::parser::progress::{Progress, ProgressHandle};
+use crate::blockchain::parser::scripts::ScriptAudit;
 use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script;
 use crate::callbacks::Callback;
-use crate::errors::OpResult;
+use crate::errors::{OpError, OpErrorKind, OpResult};
 use crate::ParserOptions;
 
-mod blkfile;
+pub mod blkfile;
 pub mod chain;
-mod index;
+pub mod filter;
+pub mod index;
+pub mod progress;
 pub mod reader;
+pub mod scanfile;
+mod scripts;
 pub mod types;
+mod unknown;
 
 /// Small struct to hold statistics together
 struct WorkerStats {
     pub started_at: Instant,
-    pub last_log: Instant,
+    pub last_report: Instant,
     pub last_height: u64,
-    pub measure_frame: Duration,
 }
 
 impl WorkerStats {
     fn new(start_range: u64) -> Self {
         Self {
             started_at: Instant::now(),
-            last_log: Instant::now(),
+            last_report: Instant::now(),
             last_height: start_range,
-            measure_frame: Duration::from_secs(10),
         }
     }
 }
 
+/// Wall time spent in each stage of the pipeline
+#[derive(Default, Clone, Copy)]
+pub struct Timings {
+    pub read: Duration,     // reading blk and rev files
+    pub decode: Duration,   // parsing blocks and undo data, evaluating scripts
+    pub audit: Duration,    // auditing scripts, see `--strict-scripts`
+    pub callback: Duration, // all calls to the callback
+}
+
+impl Timings {
+    fn total(&self) -> Duration {
+        self.read + self.decode + self.audit + self.callback
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total().as_secs_f64().max(f64::EPSILON);
+        let stages = [
+            ("read", self.read),
+            ("decode", self.decode),
+            ("audit", self.audit),
+            ("callback", self.callback),
+        ];
+        let stages: Vec<String> = stages
+            .iter()
+            .filter(|(name, time)| *name != "audit" || !time.is_zero())
+            .map(|(name, time)| {
+                let secs = time.as_secs_f64();
+                format!("{}: {:.2}s ({:.1}%)", name, secs, secs / total * 100.0)
+            })
+            .collect();
+        write!(f, "{}", stages.join(", "))
+    }
+}
+
 pub struct BlockchainParser {
     chain_storage: ChainStorage, // Hash storage with the longest chain
     stats: WorkerStats,          // struct for thread management & statistics
     callback: Box<dyn Callback>,
     cur_height: u64,
+    last_completed: Option<u64>, // last height the callback handled completely
+    script_audit: Option<ScriptAudit>, // Only set with --strict-scripts
+    filter: Option<Filter>,      // transactions not matching are removed, see `--filter`
+    skip_corrupt: bool,
+    corrupt_blocks: Vec<CorruptBlock>,
+    timings: Timings, // audit and callback time, see ChainStorage for the rest
+    heartbeat: Option<Duration>, // interval to log the timings in, see `--heartbeat`
+    last_heartbeat: Instant,
+    progress: ProgressHandle,
 }
 
 impl BlockchainParser {
@@ -47,20 +100,91 @@ impl BlockchainParser {
             stats: WorkerStats::new(options.range.start),
             callback: options.callback,
             cur_height: options.range.start,
+            last_completed: None,
+            script_audit: match options.strict_scripts {
+                true => Some(ScriptAudit::new(options.bad_scripts_file)),
+                false => None,
+            },
+            filter: options.filter,
+            skip_corrupt: options.skip_corrupt,
+            corrupt_blocks: Vec::new(),
+            timings: Timings::default(),
+            heartbeat: options.heartbeat,
+            last_heartbeat: Instant::now(),
+            progress: ProgressHandle::default(),
         }
     }
 
+    /// Reports the progress to the handle and stops once it is cancelled
+    pub fn with_progress(mut self, progress: ProgressHandle) -> Self {
+        self.progress = progress;
+        self
+    }
+
     pub fn start(&mut self) -> OpResult<()> {
         debug!(target: "parser", "Starting worker ...");
 
+        if let Some(heights) = self.chain_storage.selection() {
+            return self.start_selection(heights);
+        }
         self.on_start(self.cur_height)?;
-        while let Some(block) = self.chain_storage.get_block(self.cur_height) {
-            self.on_block(&block, self.cur_height)?;
+        while !self.progress.is_cancelled() {
+            match self.chain_storage.get_block(self.cur_height) {
+                Some(result) => {
+                    if let Err(err) = self.process(result, self.cur_height) {
+                        return Err(self.on_abort(err));
+                    }
+                }
+                None => break,
+            }
             self.cur_height += 1;
         }
         self.on_complete(self.cur_height.saturating_sub(1))
     }
 
+    /// Only parses the given heights, see `--only-blk-files` and `--heights-file`.
+    /// Callbacks see gaps between the heights.
+    fn start_selection(&mut self, heights: Vec<u64>) -> OpResult<()> {
+        let (first, last) = match (heights.first(), heights.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Ok(()),
+        };
+        self.cur_height = first;
+        self.on_start(first)?;
+        let mut last_processed = last;
+        for height in heights {
+            if self.progress.is_cancelled() {
+                last_processed = self.cur_height;
+                break;
+            }
+            self.cur_height = height;
+            let result = self.chain_storage.get_block(height).ok_or_else(|| {
+                let msg = format!("Block {} is not available", height);
+                OpError::new(OpErrorKind::RuntimeError).join_msg(&msg)
+            });
+            if let Err(err) = result.and_then(|result| self.process(result, height)) {
+                return Err(self.on_abort(err));
+            }
+        }
+        self.on_complete(last_processed)
+    }
+
+    /// Passes a block to the callback or records it as corrupt
+    fn process(&mut self, result: Result<Block, CorruptBlock>, height: u64) -> OpResult<()> {
+        match result {
+            Ok(block) => self.on_block(block, height),
+            Err(corrupt) if self.skip_corrupt => {
+                warn!(target: "parser", "{}, skipping", corrupt);
+                self.corrupt_blocks.push(corrupt);
+                Ok(())
+            }
+            Err(corrupt) => {
+                let msg = format!("{}\nUse --skip-corrupt to continue anyway", corrupt);
+                Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg))
+            }
+        }
+    }
+
     /// Returns number of remaining blocks
     pub fn remaining(&self) -> u64 {
         self.chain_storage
@@ -72,42 +196,288 @@ impl BlockchainParser {
     fn on_start(&mut self, height: u64) -> OpResult<()> {
         let now = Instant::now();
         self.stats.started_at = now;
-        self.stats.last_log = now;
+        self.stats.last_report = now;
+        self.last_heartbeat = now;
         info!(target: "parser", "Processing blocks starting from height {} ...", height);
+        if let Some(audit) = self.script_audit.as_mut() {
+            audit.on_start()?;
+        }
+        let started = Instant::now();
         self.callback.on_start(height)?;
+        self.timings.callback += started.elapsed();
         trace!(target: "parser", "on_start() called");
         Ok(())
     }
 
     /// Triggers the on_block() callback and updates statistics.
-    fn on_block(&mut self, block: &Block, height: u64) -> OpResult<()> {
-        self.callback.on_block(block, height)?;
+    /// The script audit sees all transactions, the callback only those matching the filter.
+    fn on_block(&mut self, mut block: Block, height: u64) -> OpResult<()> {
+        if let Some(audit) = self.script_audit.as_mut() {
+            let started = Instant::now();
+            audit.on_block(&block, height)?;
+            self.timings.audit += started.elapsed();
+        }
+        let started = Instant::now();
+        let positions = self
+            .filter
+            .as_ref()
+            .map(|filter| filter.retain(&mut block.txs, height));
+        for (i, tx) in block.txs.iter().enumerate() {
+            let index = positions.as_ref().map_or(i, |positions| positions[i]);
+            self.callback.on_transaction(tx, height, index)?;
+        }
+        self.callback.on_block(&block, height)?;
+        self.last_completed = Some(height);
+        self.timings.callback += started.elapsed();
         trace!(target: "parser", "on_block(height={}) called", height);
-        if self.callback.show_progress() {
-            self.print_progress(height);
+        self.report_progress(height);
+        if let Some(interval) = self.heartbeat {
+            if self.last_heartbeat.elapsed() >= interval {
+                info!(target: "parser", "Heartbeat: block {}, {}", height, self.timings());
+                self.last_heartbeat = Instant::now();
+            }
         }
         Ok(())
     }
 
+    /// Returns the time spent in each stage so far
+    fn timings(&self) -> Timings {
+        let chain_timings = self.chain_storage.timings();
+        Timings {
+            read: chain_timings.read,
+            decode: chain_timings.decode,
+            ..self.timings
+        }
+    }
+
     /// Triggers the on_complete() callback and updates statistics.
     fn on_complete(&mut self, height: u64) -> OpResult<()> {
+        if self.progress.is_cancelled() {
+            warn!(target: "parser", "Cancelled, the output only covers the blocks up to height {}", height);
+        }
         info!(target: "parser", "Done. Processed blocks up to height {} in {:.2} minutes.",
         height, (Instant::now() - self.stats.started_at).as_secs_f32() / 60.0);
 
+        if let Some((hits, misses)) = script::cache_stats() {
+            let ratio = hits as f64 / (hits + misses).max(1) as f64 * 100.0;
+            debug!(target: "parser", "Script cache: {} hits, {} misses ({:.2}% hit ratio)", hits, misses, ratio);
+        }
+        if let Some(audit) = self.script_audit.as_mut() {
+            audit.on_complete()?;
+        }
+        if !self.corrupt_blocks.is_empty() {
+            let mut report = format!("Skipped {} corrupt blocks:", self.corrupt_blocks.len());
+            for corrupt in &self.corrupt_blocks {
+                report.push_str(&format!("\n   -> {}", corrupt));
+            }
+            warn!(target: "parser", "{}", report);
+        }
+        let unknown = self.chain_storage.unknown();
+        if !unknown.is_empty() {
+            warn!(target: "parser", "Found unknown constructs ({}), use --strict-parse to abort on them", unknown);
+        }
+        let mismatches = self.chain_storage.tx_count_mismatches();
+        if !mismatches.is_empty() {
+            let heights: Vec<String> = mismatches.iter().take(10).map(u64::to_string).collect();
+            warn!(target: "parser", "{} blocks don't match the tx count of the block index (heights: {}{})",
+                  mismatches.len(), heights.join(", "), if mismatches.len() > 10 { ", ..." } else { "" });
+        }
+        let skipped_pow = self.chain_storage.skipped_pow();
+        if !skipped_pow.is_empty() {
+            let skipped: Vec<String> = skipped_pow
+                .iter()
+                .map(|(algorithm, n)| format!("{}: {}", algorithm, n))
+                .collect();
+            warn!(target: "parser", "Skipped proof of work verification of blocks ({})", skipped.join(", "));
+        }
+
+        let started = Instant::now();
         self.callback.on_complete(height)?;
+        self.timings.callback += started.elapsed();
         trace!(target: "parser", "on_complete() called");
+        info!(target: "parser", "Time spent: {}", self.timings());
         Ok(())
     }
 
-    fn print_progress(&mut self, height: u64) {
+    /// Lets the callback clean up after a failed block and returns the error
+    fn on_abort(&mut self, err: OpError) -> OpError {
+        match self.last_completed {
+            Some(height) => {
+                error!(target: "parser", "Aborted at height {}, blocks up to height {} were handled completely", self.cur_height, height)
+            }
+            None => {
+                error!(target: "parser", "Aborted at height {}, no block was handled completely", self.cur_height)
+            }
+        }
+        self.callback.on_abort(self.last_completed);
+        err
+    }
+
+    /// Passes the progress to the handle once per interval
+    fn report_progress(&mut self, height: u64) {
         let now = Instant::now();
-        let blocks_speed = (height - self.stats.last_height) / self.stats.measure_frame.as_secs();
+        let elapsed = now - self.stats.last_report;
+        if elapsed < self.progress.interval() {
+            return;
+        }
+        let progress = Progress {
+            height,
+            remaining: self.remaining(),
+            blocks_per_sec: height.saturating_sub(self.stats.last_height) as f64
+                / elapsed.as_secs_f64(),
+            bytes_read: self.chain_storage.bytes_read(),
+            elapsed: now - self.stats.started_at,
+        };
+        self.progress.report(&progress);
+        self.stats.last_report = now;
+        self.stats.last_height = height;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
 
-        if now - self.stats.last_log > self.stats.measure_frame {
-            info!(target: "parser", "Status: {:7} Blocks processed. (remaining: {:7}, speed: {:5.2} blocks/s)",
-              height, self.remaining(), blocks_speed);
-            self.stats.last_log = now;
-            self.stats.last_height = height;
+    use clap::{ArgMatches, Command};
+
+    use super::*;
+    use crate::blockchain::proto::tx::EvaluatedTx;
+    use crate::blockchain::proto::Hashed;
+    use crate::callbacks::CallbackContext;
+    use crate::common::fixtures::MiniChain;
+    use crate::{command, parse_args};
+
+    /// Calls as (height, tx index), the tx index is None for on_block
+    type Calls = Rc<RefCell<Vec<(u64, Option<usize>)>>>;
+
+    /// Records the order of all calls
+    struct Recorder(Calls);
+
+    impl Callback for Recorder {
+        fn build_subcommand() -> Command {
+            Command::new("recorder")
         }
+
+        fn new(_: &ArgMatches, _: &CallbackContext) -> OpResult<Self> {
+            Ok(Recorder(Calls::default()))
+        }
+
+        fn on_start(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+
+        fn on_transaction(
+            &mut self,
+            _: &Hashed<EvaluatedTx>,
+            block_height: u64,
+            index: usize,
+        ) -> OpResult<()> {
+            self.0.borrow_mut().push((block_height, Some(index)));
+            Ok(())
+        }
+
+        fn on_block(&mut self, _: &Block, block_height: u64) -> OpResult<()> {
+            self.0.borrow_mut().push((block_height, None));
+            Ok(())
+        }
+
+        fn on_complete(&mut self, _: u64) -> OpResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_on_transaction() {
+        let chain = MiniChain::generate(2);
+        let argv = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "simplestats",
+        ];
+        let mut options = parse_args(command().get_matches_from(argv)).unwrap();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        options.callback = Box::new(Recorder(calls.clone()));
+        let chain_storage = ChainStorage::new(&options).unwrap();
+        BlockchainParser::new(options, chain_storage)
+            .start()
+            .unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            [
+                (0, Some(0)),
+                (0, None),
+                (1, Some(0)),
+                (1, Some(1)),
+                (1, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_progress_cancel() {
+        let chain = MiniChain::generate(5);
+        let argv = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "simplestats",
+        ];
+        let mut options = parse_args(command().get_matches_from(argv)).unwrap();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        options.callback = Box::new(Recorder(calls.clone()));
+        let chain_storage = ChainStorage::new(&options).unwrap();
+
+        // Cancels from the listener once block 1 is processed
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let progress = ProgressHandle::default().with_interval(Duration::ZERO);
+        let handle = progress.clone();
+        let progress = progress.on_progress({
+            let reports = reports.clone();
+            move |progress| {
+                reports
+                    .lock()
+                    .unwrap()
+                    .push((progress.height, progress.bytes_read));
+                if progress.height == 1 {
+                    handle.cancel();
+                }
+            }
+        });
+        BlockchainParser::new(options, chain_storage)
+            .with_progress(progress)
+            .start()
+            .unwrap();
+        let blocks: Vec<u64> = calls
+            .borrow()
+            .iter()
+            .filter(|(_, index)| index.is_none())
+            .map(|(height, _)| *height)
+            .collect();
+        assert_eq!(blocks, [0, 1]);
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert!(reports[1].1 > reports[0].1);
+    }
+
+    #[test]
+    fn test_timings_display() {
+        let mut timings = Timings {
+            read: Duration::from_secs(1),
+            decode: Duration::from_secs(3),
+            audit: Duration::ZERO,
+            callback: Duration::from_secs(4),
+        };
+        assert_eq!(
+            timings.to_string(),
+            "read: 1.00s (12.5%), decode: 3.00s (37.5%), callback: 4.00s (50.0%)"
+        );
+        timings.audit = Duration::from_secs(2);
+        assert!(timings.to_string().contains("audit: 2.00s (20.0%)"));
+        assert!(Timings::default()
+            .to_string()
+            .starts_with("read: 0.00s (0.0%)"));
     }
 }
@@ -0,0 +1,90 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default interval between two progress reports
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Snapshot of a running parser, passed to the listener of a `ProgressHandle`
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub height: u64,
+    // Blocks up to the end of the range
+    pub remaining: u64,
+    // Speed since the previous report
+    pub blocks_per_sec: f64,
+    // Block and undo data read from disk so far
+    pub bytes_read: u64,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:7} Blocks processed. (remaining: {:7}, speed: {:5.2} blocks/s, read: {:.1} MiB)",
+            self.height,
+            self.remaining,
+            self.blocks_per_sec,
+            self.bytes_read as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+type Listener = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Lets embedders follow and stop a run, e.g. to render a progress bar or to stop a service
+/// without killing the process. Clones share the cancellation token, so a clone can be moved
+/// to another thread and cancel the run from there. A cancelled run stops after the current
+/// block and completes the callback, so its output covers the blocks processed until then.
+#[derive(Clone)]
+pub struct ProgressHandle {
+    cancelled: Arc<AtomicBool>,
+    listener: Option<Listener>,
+    interval: Duration,
+}
+
+impl Default for ProgressHandle {
+    fn default() -> Self {
+        ProgressHandle {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            listener: None,
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+}
+
+impl ProgressHandle {
+    /// Calls the listener with the progress every interval (default: 1s)
+    pub fn on_progress(mut self, listener: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Stops the run after the current block.
+    /// Part of the embedding API, the command line interface never cancels.
+    #[allow(dead_code)]
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub(crate) fn report(&self, progress: &Progress) {
+        if let Some(listener) = &self.listener {
+            listener(progress);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Cursor, Write};
+use std::path::Path;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::parser::blkfile::{BlkFile, XorKey};
+use crate::blockchain::parser::reader::{BlockchainRead, ReadLimits};
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::proto::header::BlockHeader;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::varuint::VarUint;
+use crate::blockchain::proto::{BlockHash, Hashed};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Block record found at a magic boundary
+struct Record {
+    offset: u64, // offset of the magic
+    size: u32,
+    header: Option<Hashed<BlockHeader>>,
+    // Transactions along with their offset in the file, if the whole block could be parsed
+    txs: Option<Vec<(u64, Hashed<EvaluatedTx>)>>,
+    status: String,
+}
+
+/// Scans a single blk file without the block index, e.g. a damaged copy.
+/// Every record behind a magic is parsed on its own, so blocks after damaged data and blocks
+/// which are not part of the main chain are recovered as well.
+pub struct ScanFile;
+
+impl ScanFile {
+    pub fn build_subcommand() -> Command {
+        Command::new("scanfile")
+            .about("Recovers all parseable blocks of a single (damaged) blk file without the block index and lists them with their offsets")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("path")
+                    .help("blk file to scan")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("start-offset")
+                    .long("start-offset")
+                    .value_name("BYTES")
+                    .value_parser(clap::value_parser!(u64))
+                    .default_value("0")
+                    .help("Starts looking for the magic at this offset of the file"),
+            )
+            .arg(
+                Arg::new("txs")
+                    .long("txs")
+                    .value_name("FILE")
+                    .help("Writes the transactions of all recovered blocks with their offsets to this csv file"),
+            )
+    }
+
+    /// Lists the records of the file as csv to stdout, the xor key is resolved next to the file
+    pub fn run(matches: &ArgMatches, coin: &CoinType, xor_key: XorKey) -> OpResult<()> {
+        let path = Path::new(matches.get_one::<String>("path").unwrap());
+        let start = *matches.get_one::<u64>("start-offset").unwrap();
+        let data = BlkFile::standalone(path, xor_key)?.read_all()?;
+        info!(target: "scanfile", "Scanning {} ({} bytes) from offset {} ...", path.display(), data.len(), start);
+        let records = scan(&data, start, coin);
+
+        let mut writer = io::stdout().lock();
+        write_records(&mut writer, &records)?;
+        writer.flush()?;
+        if let Some(txs_path) = matches.get_one::<String>("txs") {
+            let mut writer = BufWriter::new(File::create(txs_path)?);
+            write_txs(&mut writer, &records)?;
+            writer.flush()?;
+        }
+        let n_ok = records.iter().filter(|r| r.status == "ok").count();
+        info!(target: "scanfile", "Found {} records, {} of them intact.", records.len(), n_ok);
+        Ok(())
+    }
+}
+
+/// Finds all records from `start` on. After a damaged record the search continues
+/// right behind its magic, as its size may be damaged as well.
+fn scan(data: &[u8], start: u64, coin: &CoinType) -> Vec<Record> {
+    let magic = coin.magic.to_le_bytes();
+    let mut records = Vec::new();
+    let mut pos = usize::try_from(start).unwrap_or(usize::MAX);
+    while pos < data.len() {
+        let found = data[pos..].windows(4).position(|w| w == magic);
+        let end = found.map_or(data.len(), |i| pos + i);
+        if data[pos..end].iter().any(|b| *b != 0) {
+            warn!(target: "scanfile", "Skipped {} bytes without magic at offset {}", end - pos, pos);
+        }
+        if found.is_none() {
+            break;
+        }
+        let record = read_record(data, end, coin);
+        pos = match &record.txs {
+            Some(_) => end + 8 + record.size as usize,
+            None => end + 1,
+        };
+        records.push(record);
+    }
+    records
+}
+
+/// Parses the record with the magic at `offset`
+fn read_record(data: &[u8], offset: usize, coin: &CoinType) -> Record {
+    let size_bytes = data.get(offset + 4..offset + 8).unwrap_or_default();
+    let size = size_bytes.try_into().map_or(0, u32::from_le_bytes);
+    let block_start = (offset + 8).min(data.len());
+    let block_end = block_start.saturating_add(size as usize);
+    let mut record = Record {
+        offset: offset as u64,
+        size,
+        header: None,
+        txs: None,
+        status: String::from("ok"),
+    };
+    let block = match data.get(block_start..block_end) {
+        Some(block) => block,
+        None => {
+            record.header = (&data[block_start..])
+                .read_coin_block_header(&coin.header_layout)
+                .ok()
+                .map(|header| Hashed::with(header, coin.block_hash));
+            record.status = String::from("truncated");
+            return record;
+        }
+    };
+    match read_block(block, block_start as u64, coin) {
+        Ok((header, txs)) => {
+            let txids = txs.iter().map(|(_, tx)| tx.hash.to_raw_hash()).collect();
+            if utils::merkle_root(txids) != header.value.merkle_root {
+                record.status = String::from("bad merkle root");
+            }
+            record.header = Some(header);
+            record.txs = Some(txs);
+        }
+        Err(why) => {
+            record.header = (&block[..])
+                .read_coin_block_header(&coin.header_layout)
+                .ok()
+                .map(|header| Hashed::with(header, coin.block_hash));
+            record.status = format!("damaged: {}", why).replace(['\n', ';'], " ");
+        }
+    }
+    record
+}
+
+/// Reads the header and all transactions of a block, `offset` is the position of the block in the file
+#[allow(clippy::type_complexity)]
+fn read_block(
+    block: &[u8],
+    offset: u64,
+    coin: &CoinType,
+) -> OpResult<(Hashed<BlockHeader>, Vec<(u64, Hashed<EvaluatedTx>)>)> {
+    let mut reader = Cursor::new(block);
+    let limits = ReadLimits::from(coin);
+    let header = reader.read_coin_block_header(&coin.header_layout)?;
+    if let Some(version) = coin.aux_pow_activation_version {
+        if header.version >= version {
+            reader.read_aux_pow_extension(coin.version_id, &limits)?;
+        }
+    }
+    let tx_count = VarUint::read_from(&mut reader)?;
+    let mut txs = Vec::new();
+    for _ in 0..tx_count.value {
+        let tx_offset = offset + reader.position();
+        let tx = reader.read_tx(coin.version_id, &limits)?;
+        txs.push((tx_offset, Hashed::with(EvaluatedTx::from(tx), coin.tx_hash)));
+    }
+    Ok((Hashed::with(header, coin.block_hash), txs))
+}
+
+fn write_records<W: Write>(writer: &mut W, records: &[Record]) -> io::Result<()> {
+    writeln!(
+        writer,
+        "offset;size;status;blockHash;prevHash;timestamp;txCount;prevInFile"
+    )?;
+    // Blocks whose predecessor is not in the file hint at a gap or a stale branch
+    let hashes: HashSet<BlockHash> = records
+        .iter()
+        .filter_map(|r| r.header.as_ref().map(|h| h.hash))
+        .collect();
+    for record in records {
+        let (hash, prev_hash, timestamp, prev_in_file) = match &record.header {
+            Some(header) => (
+                header.hash.to_string(),
+                header.value.prev_hash.to_string(),
+                header.value.timestamp.to_string(),
+                hashes.contains(&header.value.prev_hash).to_string(),
+            ),
+            None => Default::default(),
+        };
+        let tx_count = record
+            .txs
+            .as_ref()
+            .map_or(String::new(), |txs| txs.len().to_string());
+        writeln!(
+            writer,
+            "{};{};{};{};{};{};{};{}",
+            record.offset,
+            record.size,
+            record.status,
+            hash,
+            prev_hash,
+            timestamp,
+            tx_count,
+            prev_in_file
+        )?;
+    }
+    Ok(())
+}
+
+fn write_txs<W: Write>(writer: &mut W, records: &[Record]) -> io::Result<()> {
+    writeln!(
+        writer,
+        "blockHash;txOffset;txIndex;txid;inputs;outputs;value"
+    )?;
+    for record in records {
+        let (Some(header), Some(txs)) = (&record.header, &record.txs) else {
+            continue;
+        };
+        for (i, (offset, tx)) in txs.iter().enumerate() {
+            let value: u64 = tx.value.outputs.iter().map(|o| o.out.value).sum();
+            writeln!(
+                writer,
+                "{};{};{};{};{};{};{}",
+                header.hash,
+                offset,
+                i,
+                tx.hash,
+                tx.value.in_count.value,
+                tx.value.out_count.value,
+                value
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::types::Bitcoin;
+    use crate::common::fixtures::MiniChain;
+    use std::fs;
+
+    #[test]
+    fn test_scan() {
+        let chain = MiniChain::generate(4);
+        let mut data = fs::read(chain.path().join("blk00000.dat")).unwrap();
+        let coin = CoinType::from(Bitcoin);
+        let records = scan(&data, 0, &coin);
+        assert_eq!(records.len(), 4);
+        assert!(records.iter().all(|r| r.status == "ok"));
+        let second = records[1].offset as usize;
+        let third = records[2].offset as usize;
+
+        // Damage the tx count of the second block and cut off the last one
+        data[second + 8 + 80] = 9;
+        data.truncate(records[3].offset as usize + 100);
+        let records = scan(&data, 0, &coin);
+        let statuses: Vec<&str> = records.iter().map(|r| r.status.as_str()).collect();
+        assert!(statuses[1].starts_with("damaged"));
+        // The header of the damaged block is still known and the following block is recovered
+        assert_eq!(
+            records[1].header.as_ref().unwrap().hash.to_raw_hash(),
+            chain.hashes[1]
+        );
+        assert_eq!(records[2].offset as usize, third);
+        assert_eq!(records[2].status, "ok");
+        assert_eq!(records[3].status, "truncated");
+
+        // Starting within the second block skips to the third
+        let records = scan(&data, second as u64 + 1, &coin);
+        assert_eq!(records[0].offset as usize, third);
+
+        let mut out = Vec::new();
+        write_txs(&mut out, &records).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let txid = chain.txids[2][0].to_string();
+        assert!(out.contains(&format!(";0;{};1;", txid)));
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::{EvaluatedScript, ScriptPattern};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Counts output scripts which could not be classified or have no address,
+/// and optionally dumps them to a csv file for investigation (see `--strict-scripts`).
+pub struct ScriptAudit {
+    path: Option<PathBuf>,
+    writer: Option<BufWriter<File>>,
+    n_unrecognised: u64,
+    n_no_address: u64,
+}
+
+impl ScriptAudit {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            writer: None,
+            n_unrecognised: 0,
+            n_no_address: 0,
+        }
+    }
+
+    /// Creates the dump file if configured
+    pub fn on_start(&mut self) -> OpResult<()> {
+        if let Some(path) = &self.path {
+            info!(target: "parser", "Dumping unrecognised scripts to {} ...", path.display());
+            let mut writer = BufWriter::with_capacity(4000000, File::create(path)?);
+            writer.write_all("height;txid;vout;reason;script\n".as_bytes())?;
+            self.writer = Some(writer);
+        }
+        Ok(())
+    }
+
+    pub fn on_block(&mut self, block: &Block, height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            for (i, o) in tx.value.outputs.iter().enumerate() {
+                let reason = match check_script(&o.script) {
+                    Some(reason) => reason,
+                    None => continue,
+                };
+                match reason {
+                    "unrecognised" => self.n_unrecognised += 1,
+                    _ => self.n_no_address += 1,
+                }
+                if let Some(writer) = self.writer.as_mut() {
+                    // (height, @txid, vout, reason, script)
+                    writer.write_all(
+                        format!(
+                            "{};{};{};{};{}\n",
+                            height,
+                            &tx.hash,
+                            i,
+                            reason,
+                            utils::arr_to_hex(&o.out.script_pubkey)
+                        )
+                        .as_bytes(),
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn on_complete(&mut self) -> OpResult<()> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        info!(target: "parser", "Scripts failed to classify: {}, without address: {}",
+              self.n_unrecognised, self.n_no_address);
+        Ok(())
+    }
+}
+
+/// Returns the reason if the script failed classification or address extraction
+fn check_script(script: &EvaluatedScript) -> Option<&'static str> {
+    match script.pattern {
+        ScriptPattern::NotRecognised | ScriptPattern::Error(_) => Some("unrecognised"),
+        // These scripts never have an address
+        ScriptPattern::OpReturn(_)
+        | ScriptPattern::Unspendable
+        | ScriptPattern::Pay2MultiSig
+        | ScriptPattern::Template(_) => None,
+        _ if script.address.is_none() => Some("no address"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::ScriptError;
+
+    #[test]
+    fn test_check_script() {
+        let address = Some(String::from("1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn"));
+        let eval =
+            |address: &Option<String>, pattern| EvaluatedScript::new(address.clone(), pattern);
+
+        assert_eq!(
+            check_script(&eval(&address, ScriptPattern::Pay2PublicKeyHash)),
+            None
+        );
+        assert_eq!(
+            check_script(&eval(&None, ScriptPattern::Pay2PublicKeyHash)),
+            Some("no address")
+        );
+        assert_eq!(
+            check_script(&eval(&None, ScriptPattern::OpReturn(String::new()))),
+            None
+        );
+        assert_eq!(
+            check_script(&eval(&None, ScriptPattern::NotRecognised)),
+            Some("unrecognised")
+        );
+        assert_eq!(
+            check_script(&eval(
+                &None,
+                ScriptPattern::Error(ScriptError::UnexpectedEof)
+            )),
+            Some("unrecognised")
+        );
+    }
+}
@@ -1,10 +1,67 @@
-use bitcoin::hashes::sha256d;
 use std::convert::From;
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use bitcoin::hashes::{sha256, sha256d, Hash};
+
+use crate::blockchain::proto::BlockHash;
+use crate::common::groestl;
 use crate::errors::{OpError, OpErrorKind, OpResult};
 
+/// Maximum serialized block size including witness data (BIP141)
+pub const MAX_BLOCK_SIZE: u32 = 4_000_000;
+
+/// Hash function identifying serialized data, i.e. transactions (txid) or block headers (block hash)
+pub type HashFn = fn(&[u8]) -> sha256d::Hash;
+
+pub fn double_sha256(data: &[u8]) -> sha256d::Hash {
+    sha256d::Hash::hash(data)
+}
+
+pub fn single_sha256(data: &[u8]) -> sha256d::Hash {
+    sha256d::Hash::from_byte_array(sha256::Hash::hash(data).to_byte_array())
+}
+
+pub fn groestl512d(data: &[u8]) -> sha256d::Hash {
+    sha256d::Hash::from_byte_array(groestl::groestl512d(data))
+}
+
+/// Smallest units per coin
+const COIN: u64 = 100_000_000;
+
+/// Block subsidy in the smallest unit at the given height, None if it is not known
+pub type SubsidyFn = fn(u64) -> Option<u64>;
+
+/// Returns `initial` halved every `interval` blocks
+fn halving(initial: u64, interval: u64, height: u64) -> u64 {
+    u32::try_from(height / interval)
+        .ok()
+        .and_then(|halvings| initial.checked_shr(halvings))
+        .unwrap_or(0)
+}
+
+pub fn bitcoin_subsidy(height: u64) -> Option<u64> {
+    Some(halving(50 * COIN, 210_000, height))
+}
+
+pub fn litecoin_subsidy(height: u64) -> Option<u64> {
+    Some(halving(50 * COIN, 840_000, height))
+}
+
+/// The subsidy of the first 145000 blocks was random
+pub fn dogecoin_subsidy(height: u64) -> Option<u64> {
+    match height {
+        0..=144_999 => None,
+        145_000..=599_999 => Some(halving(500_000 * COIN, 100_000, height)),
+        _ => Some(10_000 * COIN),
+    }
+}
+
+pub fn unknown_subsidy(_height: u64) -> Option<u64> {
+    None
+}
+
 /// Trait to specify the underlying coin of a blockchain
 /// Needs a proper magic value and a network id for address prefixes
 pub trait Coin {
@@ -15,13 +72,167 @@ pub trait Coin {
     // https://en.bitcoin.it/wiki/List_of_address_prefixes
     fn version_id(&self) -> u8;
     // Returns genesis hash
-    fn genesis(&self) -> sha256d::Hash;
+    fn genesis(&self) -> BlockHash;
     // Activates AuxPow for the returned version and above
     fn aux_pow_activation_version(&self) -> Option<u32> {
         None
     }
     // Default working directory to look for datadir, for example .bitcoin
     fn default_folder(&self) -> PathBuf;
+    // Number of blocks between difficulty retargets
+    fn retarget_interval(&self) -> u64 {
+        2016
+    }
+    // Expected time between blocks in seconds
+    fn target_spacing(&self) -> u64 {
+        600
+    }
+    // Well-known addresses without a known private key, used for proof-of-burn
+    fn burn_addresses(&self) -> &'static [&'static str] {
+        &[]
+    }
+    // Number of blocks until coinbase outputs can be spent
+    fn coinbase_maturity(&self) -> u64 {
+        100
+    }
+    // Size of the block headers, for coins which append fields to the 80 bytes header
+    fn header_layout(&self) -> HeaderLayout {
+        HeaderLayout::default()
+    }
+    // Proof of work algorithms, multi-algo coins select one per block.
+    // The proof of work of coins without a known algorithm is not verified.
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Other("unknown"))
+    }
+    // Upper bound for the size of a block record, larger ones are rejected as corrupt
+    fn max_block_size(&self) -> u32 {
+        MAX_BLOCK_SIZE
+    }
+    // Human readable part of bech32 addresses, for coins with segwit
+    fn bech32_hrp(&self) -> Option<&'static str> {
+        None
+    }
+    // Version byte of P2SH addresses
+    fn script_version_id(&self) -> u8 {
+        0x05
+    }
+    // Version byte of P2SH addresses before the coin introduced its own, see `--legacy-addresses`
+    fn legacy_script_version_id(&self) -> u8 {
+        self.script_version_id()
+    }
+    // Block subsidy per height, coins without it are not checked by `inflationcheck`
+    fn subsidy(&self) -> SubsidyFn {
+        unknown_subsidy
+    }
+    // Maximum value of a single output or transaction in the smallest unit (MAX_MONEY)
+    fn max_money(&self) -> u64 {
+        21_000_000 * COIN
+    }
+    // Hash of a serialized transaction without witness data, i.e. the txid
+    fn tx_hash(&self) -> HashFn {
+        double_sha256
+    }
+    // Hash of a serialized block header, i.e. the block hash
+    fn block_hash(&self) -> HashFn {
+        double_sha256
+    }
+}
+
+/// Proof of work algorithm of a block
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PowAlgorithm {
+    Sha256d,
+    Scrypt,
+    // Double Groestl-512 (Groestlcoin)
+    Groestl,
+    // Groestl-512 followed by SHA256 (Myriadcoin)
+    MyrGroestl,
+    Skein,
+    Qubit,
+    Yescrypt,
+    Argon2d,
+    Odo,
+    // Algorithms without a name here, e.g. unknown version bits
+    Other(&'static str),
+}
+
+impl fmt::Display for PowAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PowAlgorithm::Sha256d => "sha256d",
+            PowAlgorithm::Scrypt => "scrypt",
+            PowAlgorithm::Groestl => "groestl",
+            PowAlgorithm::MyrGroestl => "myr-groestl",
+            PowAlgorithm::Skein => "skein",
+            PowAlgorithm::Qubit => "qubit",
+            PowAlgorithm::Yescrypt => "yescrypt",
+            PowAlgorithm::Argon2d => "argon2d",
+            PowAlgorithm::Odo => "odo",
+            PowAlgorithm::Other(name) => name,
+        };
+        f.write_str(name)
+    }
+}
+
+/// Proof of work algorithms of a coin
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PowAlgorithms {
+    Single(PowAlgorithm),
+    // Multi-algo coins encode the algorithm of a block in the bits of its version given by `mask`
+    Versioned {
+        mask: u32,
+        algorithms: &'static [(u32, PowAlgorithm)],
+    },
+}
+
+impl PowAlgorithms {
+    /// Returns the algorithm of a block with the given version
+    pub fn of(&self, version: u32) -> PowAlgorithm {
+        match self {
+            PowAlgorithms::Single(algorithm) => *algorithm,
+            PowAlgorithms::Versioned { mask, algorithms } => algorithms
+                .iter()
+                .find(|(bits, _)| version & mask == *bits)
+                .map_or(PowAlgorithm::Other("unknown"), |(_, algorithm)| *algorithm),
+        }
+    }
+}
+
+/// Size of the block headers of a coin.
+/// Coins with larger headers append their fields (e.g. the accumulator checkpoint of
+/// Quark-style chains) to the 80 bytes Bitcoin header, they are kept as raw bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderLayout {
+    // Size in bytes of the extended headers
+    pub size: usize,
+    // Headers with a lower version have the Bitcoin size
+    pub activation_version: u32,
+}
+
+impl HeaderLayout {
+    /// Size of a Bitcoin block header
+    pub const BASE_SIZE: usize = 80;
+
+    pub fn new(size: usize, activation_version: u32) -> Self {
+        HeaderLayout {
+            size,
+            activation_version,
+        }
+    }
+
+    /// Returns the number of bytes following the 80 bytes header for the given header version
+    pub fn extension_len(&self, version: u32) -> usize {
+        match version >= self.activation_version {
+            true => self.size.saturating_sub(Self::BASE_SIZE),
+            false => 0,
+        }
+    }
+}
+
+impl Default for HeaderLayout {
+    fn default() -> Self {
+        HeaderLayout::new(Self::BASE_SIZE, 0)
+    }
 }
 
 // Implemented blockchain types.
@@ -29,12 +240,17 @@ pub trait Coin {
 // and add the coin name to from_str() below
 pub struct Bitcoin;
 pub struct TestNet3;
+pub struct TestNet4;
+pub struct Signet;
 pub struct Namecoin;
 pub struct Litecoin;
 pub struct Dogecoin;
 pub struct Myriadcoin;
 pub struct Unobtanium;
 pub struct NoteBlockchain;
+pub struct Vertcoin;
+pub struct DigiByte;
+pub struct Groestlcoin;
 //pub struct Dash;
 
 impl Coin for Bitcoin {
@@ -47,13 +263,26 @@ impl Coin for Bitcoin {
     fn version_id(&self) -> u8 {
         0x00
     }
-    fn genesis(&self) -> sha256d::Hash {
-        sha256d::Hash::from_str("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f")
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f")
             .unwrap()
     }
     fn default_folder(&self) -> PathBuf {
         Path::new(".bitcoin").join("blocks")
     }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Sha256d)
+    }
+    fn subsidy(&self) -> SubsidyFn {
+        bitcoin_subsidy
+    }
+    fn burn_addresses(&self) -> &'static [&'static str] {
+        &[
+            "1111111111111111111114oLvT2",
+            "1BitcoinEaterAddressDontSendf59kuE",
+            "1CounterpartyXXXXXXXXXXXXXXXUWLpVr",
+        ]
+    }
 }
 
 /// Bitcoin testnet3
@@ -67,13 +296,75 @@ impl Coin for TestNet3 {
     fn version_id(&self) -> u8 {
         0x6f
     }
-    fn genesis(&self) -> sha256d::Hash {
-        sha256d::Hash::from_str("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943")
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943")
             .unwrap()
     }
     fn default_folder(&self) -> PathBuf {
         Path::new(".bitcoin").join("testnet3")
     }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Sha256d)
+    }
+    fn subsidy(&self) -> SubsidyFn {
+        bitcoin_subsidy
+    }
+    fn burn_addresses(&self) -> &'static [&'static str] {
+        &["mvCounterpartyXXXXXXXXXXXXXXW24Hef"]
+    }
+}
+
+/// Bitcoin testnet4 (BIP94)
+impl Coin for TestNet4 {
+    fn name(&self) -> String {
+        String::from("TestNet4")
+    }
+    fn magic(&self) -> u32 {
+        0x283f161c
+    }
+    fn version_id(&self) -> u8 {
+        0x6f
+    }
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043")
+            .unwrap()
+    }
+    fn default_folder(&self) -> PathBuf {
+        Path::new(".bitcoin").join("testnet4").join("blocks")
+    }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Sha256d)
+    }
+    fn subsidy(&self) -> SubsidyFn {
+        bitcoin_subsidy
+    }
+}
+
+/// Bitcoin default signet (BIP325).
+/// Custom signets use a magic derived from their challenge and are not supported.
+impl Coin for Signet {
+    fn name(&self) -> String {
+        String::from("Signet")
+    }
+    fn magic(&self) -> u32 {
+        0x40cf030a
+    }
+    fn version_id(&self) -> u8 {
+        0x6f
+    }
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6")
+            .unwrap()
+    }
+    fn default_folder(&self) -> PathBuf {
+        Path::new(".bitcoin").join("signet").join("blocks")
+    }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Sha256d)
+    }
+    fn subsidy(&self) -> SubsidyFn {
+        bitcoin_subsidy
+    }
 }
 
 impl Coin for Namecoin {
@@ -86,8 +377,8 @@ impl Coin for Namecoin {
     fn version_id(&self) -> u8 {
         0x34
     }
-    fn genesis(&self) -> sha256d::Hash {
-        sha256d::Hash::from_str("000000000062b72c5e2ceb45fbc8587e807c155b0da735e6483dfba2f0a9c770")
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("000000000062b72c5e2ceb45fbc8587e807c155b0da735e6483dfba2f0a9c770")
             .unwrap()
     }
     fn aux_pow_activation_version(&self) -> Option<u32> {
@@ -96,6 +387,15 @@ impl Coin for Namecoin {
     fn default_folder(&self) -> PathBuf {
         PathBuf::from(".namecoin")
     }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Sha256d)
+    }
+    fn subsidy(&self) -> SubsidyFn {
+        bitcoin_subsidy
+    }
+    fn bech32_hrp(&self) -> Option<&'static str> {
+        Some("nc")
+    }
 }
 
 impl Coin for Litecoin {
@@ -108,13 +408,35 @@ impl Coin for Litecoin {
     fn version_id(&self) -> u8 {
         0x30
     }
-    fn genesis(&self) -> sha256d::Hash {
-        sha256d::Hash::from_str("12a765e31ffd4059bada1e25190f6e98c99d9714d334efa41a195a7e7e04bfe2")
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("12a765e31ffd4059bada1e25190f6e98c99d9714d334efa41a195a7e7e04bfe2")
             .unwrap()
     }
     fn default_folder(&self) -> PathBuf {
         Path::new(".litecoin").join("blocks")
     }
+    fn target_spacing(&self) -> u64 {
+        150
+    }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Scrypt)
+    }
+    fn subsidy(&self) -> SubsidyFn {
+        litecoin_subsidy
+    }
+    fn max_money(&self) -> u64 {
+        84_000_000 * COIN
+    }
+    fn bech32_hrp(&self) -> Option<&'static str> {
+        Some("ltc")
+    }
+    // "M..." addresses, formerly "3..."
+    fn script_version_id(&self) -> u8 {
+        0x32
+    }
+    fn legacy_script_version_id(&self) -> u8 {
+        0x05
+    }
 }
 
 impl Coin for Dogecoin {
@@ -127,8 +449,8 @@ impl Coin for Dogecoin {
     fn version_id(&self) -> u8 {
         0x1e
     }
-    fn genesis(&self) -> sha256d::Hash {
-        sha256d::Hash::from_str("1a91e3dace36e2be3bf030a65679fe821aa1d6ef92e7c9902eb318182c355691")
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("1a91e3dace36e2be3bf030a65679fe821aa1d6ef92e7c9902eb318182c355691")
             .unwrap()
     }
     fn aux_pow_activation_version(&self) -> Option<u32> {
@@ -137,6 +459,30 @@ impl Coin for Dogecoin {
     fn default_folder(&self) -> PathBuf {
         Path::new(".dogecoin").join("blocks")
     }
+    // DigiShield retargets on every block
+    fn retarget_interval(&self) -> u64 {
+        1
+    }
+    fn target_spacing(&self) -> u64 {
+        60
+    }
+    fn burn_addresses(&self) -> &'static [&'static str] {
+        &["DDogepartyxxxxxxxxxxxxxxxxxxw1dfzr"]
+    }
+    // 30 blocks before height 145000, the current rule is applied to the whole chain
+    fn coinbase_maturity(&self) -> u64 {
+        240
+    }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Scrypt)
+    }
+    fn subsidy(&self) -> SubsidyFn {
+        dogecoin_subsidy
+    }
+    // There is no cap on the supply, only on the value of a transaction
+    fn max_money(&self) -> u64 {
+        10_000_000_000 * COIN
+    }
 }
 
 impl Coin for Myriadcoin {
@@ -149,13 +495,37 @@ impl Coin for Myriadcoin {
     fn version_id(&self) -> u8 {
         0x32
     }
-    fn genesis(&self) -> sha256d::Hash {
-        sha256d::Hash::from_str("00000ffde4c020b5938441a0ea3d314bf619eff0b38f32f78f7583cffa1ea485")
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("00000ffde4c020b5938441a0ea3d314bf619eff0b38f32f78f7583cffa1ea485")
             .unwrap()
     }
     fn default_folder(&self) -> PathBuf {
         Path::new(".myriadcoin").join("blocks")
     }
+    // Each algorithm retargets on every block
+    fn retarget_interval(&self) -> u64 {
+        1
+    }
+    fn target_spacing(&self) -> u64 {
+        60
+    }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Versioned {
+            mask: 15 << 9,
+            algorithms: &[
+                (0, PowAlgorithm::Scrypt),
+                (1 << 9, PowAlgorithm::Sha256d),
+                (2 << 9, PowAlgorithm::MyrGroestl),
+                (3 << 9, PowAlgorithm::Skein),
+                (4 << 9, PowAlgorithm::Qubit),
+                (5 << 9, PowAlgorithm::Yescrypt),
+                (6 << 9, PowAlgorithm::Argon2d),
+            ],
+        }
+    }
+    fn bech32_hrp(&self) -> Option<&'static str> {
+        Some("my")
+    }
 }
 
 impl Coin for Unobtanium {
@@ -168,13 +538,16 @@ impl Coin for Unobtanium {
     fn version_id(&self) -> u8 {
         0x82
     }
-    fn genesis(&self) -> sha256d::Hash {
-        sha256d::Hash::from_str("000004c2fc5fffb810dccc197d603690099a68305232e552d96ccbe8e2c52b75")
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("000004c2fc5fffb810dccc197d603690099a68305232e552d96ccbe8e2c52b75")
             .unwrap()
     }
     fn default_folder(&self) -> PathBuf {
         Path::new(".unobtanium").join("blocks")
     }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Sha256d)
+    }
 }
 
 impl Coin for NoteBlockchain {
@@ -187,13 +560,140 @@ impl Coin for NoteBlockchain {
     fn version_id(&self) -> u8 {
         0x35
     }
-    fn genesis(&self) -> sha256d::Hash {
-        sha256d::Hash::from_str("270f3e7b185c412d57ba913d10658df54f15201a67d736cb4071a4ec4eb54836")
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("270f3e7b185c412d57ba913d10658df54f15201a67d736cb4071a4ec4eb54836")
             .unwrap()
     }
     fn default_folder(&self) -> PathBuf {
         Path::new(".notecoin").join("blocks")
     }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Scrypt)
+    }
+}
+
+impl Coin for Vertcoin {
+    fn name(&self) -> String {
+        String::from("Vertcoin")
+    }
+    fn magic(&self) -> u32 {
+        0xdab5bffa
+    }
+    fn version_id(&self) -> u8 {
+        0x47
+    }
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("4d96a915f49d40b1e5c2844d1ee2dccb90013a990ccea12c492d22110489f0c4")
+            .unwrap()
+    }
+    fn default_folder(&self) -> PathBuf {
+        Path::new(".vertcoin").join("blocks")
+    }
+    // Kimoto Gravity Well retargets on every block
+    fn retarget_interval(&self) -> u64 {
+        1
+    }
+    fn target_spacing(&self) -> u64 {
+        150
+    }
+    // Scrypt-N, Lyra2RE and Verthash over time
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Other("vertcoin"))
+    }
+    fn bech32_hrp(&self) -> Option<&'static str> {
+        Some("vtc")
+    }
+}
+
+/// DigiByte, difficulty figures ignore the algorithm bits in the block version
+/// and therefore mix the targets of all five algorithms.
+impl Coin for DigiByte {
+    fn name(&self) -> String {
+        String::from("DigiByte")
+    }
+    fn magic(&self) -> u32 {
+        0xdab6c3fa
+    }
+    fn version_id(&self) -> u8 {
+        0x1e
+    }
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("7497ea1b465eb39f1c8f507bc877078fe016d6fcb6dfad3a64c98dcc6e1e8496")
+            .unwrap()
+    }
+    fn default_folder(&self) -> PathBuf {
+        Path::new(".digibyte").join("blocks")
+    }
+    // MultiShield retargets on every block
+    fn retarget_interval(&self) -> u64 {
+        1
+    }
+    fn target_spacing(&self) -> u64 {
+        15
+    }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Versioned {
+            mask: 15 << 8,
+            algorithms: &[
+                (0, PowAlgorithm::Scrypt),
+                (2 << 8, PowAlgorithm::Sha256d),
+                (4 << 8, PowAlgorithm::MyrGroestl),
+                (6 << 8, PowAlgorithm::Skein),
+                (8 << 8, PowAlgorithm::Qubit),
+                (14 << 8, PowAlgorithm::Odo),
+            ],
+        }
+    }
+    fn bech32_hrp(&self) -> Option<&'static str> {
+        Some("dgb")
+    }
+    // "S..." addresses, formerly "3..."
+    fn script_version_id(&self) -> u8 {
+        0x3f
+    }
+    fn legacy_script_version_id(&self) -> u8 {
+        0x05
+    }
+}
+
+/// Groestlcoin, addresses are encoded with Groestl checksums.
+/// Block hashes are double Groestl-512 and txids a single SHA256.
+impl Coin for Groestlcoin {
+    fn name(&self) -> String {
+        String::from("Groestlcoin")
+    }
+    fn magic(&self) -> u32 {
+        0xd4b4bef9
+    }
+    fn version_id(&self) -> u8 {
+        0x24
+    }
+    fn genesis(&self) -> BlockHash {
+        BlockHash::from_str("00000ac5927c594d49cc0bdb81759d0da8297eb614683d3acb62f0703b639023")
+            .unwrap()
+    }
+    fn default_folder(&self) -> PathBuf {
+        Path::new(".groestlcoin").join("blocks")
+    }
+    // Dark Gravity Wave retargets on every block
+    fn retarget_interval(&self) -> u64 {
+        1
+    }
+    fn target_spacing(&self) -> u64 {
+        60
+    }
+    fn pow_algorithms(&self) -> PowAlgorithms {
+        PowAlgorithms::Single(PowAlgorithm::Groestl)
+    }
+    fn bech32_hrp(&self) -> Option<&'static str> {
+        Some("grs")
+    }
+    fn tx_hash(&self) -> HashFn {
+        single_sha256
+    }
+    fn block_hash(&self) -> HashFn {
+        groestl512d
+    }
 }
 
 /* TODO: implement X11
@@ -201,19 +701,50 @@ impl Coin for Dash {
     fn name(&self)        -> String { String::from("Dash") }
     fn magic(&self)       -> u32 { 0xbd6b0cbf }
     fn version_id(&self)  -> u8  { 0x4c }
-    fn genesis(&self)     -> [u8; 32] { hex_to_arr32_swapped("000007d91d1254d60e2dd1ae580383070a4ddffa4c64c2eeb4a2f9ecc0414343") }
+    fn genesis(&self)     -> BlockHash { BlockHash::from_str("000007d91d1254d60e2dd1ae580383070a4ddffa4c64c2eeb4a2f9ecc0414343").unwrap() }
     fn default_folder(&self) -> PathBuf { Path::new(".dash").join("blocks") }
 }*/
 
+/// Names of all supported coins, as accepted by `--coin`
+pub const COIN_NAMES: [&str; 13] = [
+    "bitcoin",
+    "testnet3",
+    "testnet4",
+    "signet",
+    "namecoin",
+    "litecoin",
+    "dogecoin",
+    "myriadcoin",
+    "unobtanium",
+    "noteblockchain",
+    "vertcoin",
+    "digibyte",
+    "groestlcoin",
+];
+
 #[derive(Clone)]
 // Holds the selected coin type information
 pub struct CoinType {
     pub name: String,
     pub magic: u32,
     pub version_id: u8,
-    pub genesis_hash: sha256d::Hash,
+    pub genesis_hash: BlockHash,
     pub aux_pow_activation_version: Option<u32>,
     pub default_folder: PathBuf,
+    pub retarget_interval: u64,
+    pub target_spacing: u64,
+    pub burn_addresses: &'static [&'static str],
+    pub coinbase_maturity: u64,
+    pub header_layout: HeaderLayout,
+    pub pow_algorithms: PowAlgorithms,
+    pub max_block_size: u32,
+    pub bech32_hrp: Option<&'static str>,
+    pub script_version_id: u8,
+    pub legacy_script_version_id: u8,
+    pub subsidy: SubsidyFn,
+    pub max_money: u64,
+    pub tx_hash: HashFn,
+    pub block_hash: HashFn,
 }
 
 impl Default for CoinType {
@@ -231,6 +762,20 @@ impl<T: Coin> From<T> for CoinType {
             genesis_hash: coin.genesis(),
             aux_pow_activation_version: coin.aux_pow_activation_version(),
             default_folder: coin.default_folder(),
+            retarget_interval: coin.retarget_interval(),
+            target_spacing: coin.target_spacing(),
+            burn_addresses: coin.burn_addresses(),
+            coinbase_maturity: coin.coinbase_maturity(),
+            header_layout: coin.header_layout(),
+            pow_algorithms: coin.pow_algorithms(),
+            max_block_size: coin.max_block_size(),
+            bech32_hrp: coin.bech32_hrp(),
+            script_version_id: coin.script_version_id(),
+            legacy_script_version_id: coin.legacy_script_version_id(),
+            subsidy: coin.subsidy(),
+            max_money: coin.max_money(),
+            tx_hash: coin.tx_hash(),
+            block_hash: coin.block_hash(),
         }
     }
 }
@@ -241,12 +786,17 @@ impl FromStr for CoinType {
         match coin_name {
             "bitcoin" => Ok(CoinType::from(Bitcoin)),
             "testnet3" => Ok(CoinType::from(TestNet3)),
+            "testnet4" => Ok(CoinType::from(TestNet4)),
+            "signet" => Ok(CoinType::from(Signet)),
             "namecoin" => Ok(CoinType::from(Namecoin)),
             "litecoin" => Ok(CoinType::from(Litecoin)),
             "dogecoin" => Ok(CoinType::from(Dogecoin)),
             "myriadcoin" => Ok(CoinType::from(Myriadcoin)),
             "unobtanium" => Ok(CoinType::from(Unobtanium)),
             "noteblockchain" => Ok(CoinType::from(NoteBlockchain)),
+            "vertcoin" => Ok(CoinType::from(Vertcoin)),
+            "digibyte" => Ok(CoinType::from(DigiByte)),
+            "groestlcoin" => Ok(CoinType::from(Groestlcoin)),
             n => {
                 let e = OpError::new(OpErrorKind::InvalidArgsError)
                     .join_msg(&format!("There is no impl for `{}`!", n));
@@ -255,3 +805,59 @@ impl FromStr for CoinType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subsidy() {
+        assert_eq!(bitcoin_subsidy(209_999), Some(50 * COIN));
+        assert_eq!(bitcoin_subsidy(210_000), Some(25 * COIN));
+        assert_eq!(bitcoin_subsidy(64 * 210_000), Some(0));
+        assert_eq!(litecoin_subsidy(210_000), Some(50 * COIN));
+        assert_eq!(litecoin_subsidy(840_000), Some(25 * COIN));
+        assert_eq!(dogecoin_subsidy(144_999), None);
+        assert_eq!(dogecoin_subsidy(145_000), Some(250_000 * COIN));
+        assert_eq!(dogecoin_subsidy(599_999), Some(15_625 * COIN));
+        assert_eq!(dogecoin_subsidy(600_000), Some(10_000 * COIN));
+        assert_eq!((CoinType::from(Vertcoin).subsidy)(1), None);
+        assert_eq!(CoinType::from(Dogecoin).max_money, 10_000_000_000 * COIN);
+    }
+
+    #[test]
+    fn test_pow_algorithms() {
+        assert_eq!(
+            Bitcoin.pow_algorithms().of(0x20000000),
+            PowAlgorithm::Sha256d
+        );
+        let myriadcoin = Myriadcoin.pow_algorithms();
+        assert_eq!(myriadcoin.of(0x20000002), PowAlgorithm::Scrypt);
+        assert_eq!(myriadcoin.of(0x20000402), PowAlgorithm::MyrGroestl);
+        assert_eq!(myriadcoin.of(0x20000602), PowAlgorithm::Skein);
+        assert_eq!(myriadcoin.of(0x20001e02), PowAlgorithm::Other("unknown"));
+        let digibyte = DigiByte.pow_algorithms();
+        assert_eq!(digibyte.of(0x20000202), PowAlgorithm::Sha256d);
+        assert_eq!(digibyte.of(0x20000e02), PowAlgorithm::Odo);
+        assert_eq!(PowAlgorithm::MyrGroestl.to_string(), "myr-groestl");
+        assert_eq!(Unobtanium.pow_algorithms().of(1), PowAlgorithm::Sha256d);
+        // The genesis hash of NoteBlockchain is not a sha256d proof of work
+        assert_eq!(NoteBlockchain.pow_algorithms().of(1), PowAlgorithm::Scrypt);
+    }
+
+    #[test]
+    fn test_hashes() {
+        let (bitcoin, groestlcoin) = (CoinType::from(Bitcoin), CoinType::from(Groestlcoin));
+        let data = b"rusty-blockparser";
+        assert_eq!((bitcoin.tx_hash)(data), sha256d::Hash::hash(data));
+        assert_eq!((bitcoin.block_hash)(data), sha256d::Hash::hash(data));
+        assert_eq!(
+            (groestlcoin.tx_hash)(data).to_byte_array(),
+            sha256::Hash::hash(data).to_byte_array()
+        );
+        assert_eq!(
+            (groestlcoin.block_hash)(data).to_byte_array(),
+            groestl::groestl512d(data)
+        );
+    }
+}
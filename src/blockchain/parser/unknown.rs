@@ -0,0 +1,107 @@
+use std::fmt;
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptPattern;
+
+/// Highest transaction version with known semantics (v3 = TRUC, BIP431)
+const MAX_KNOWN_TX_VERSION: u32 = 3;
+/// The only defined bit of the segwit flag byte (BIP144)
+const WITNESS_FLAG: u8 = 1;
+
+/// Counts constructs the parser has no semantics for, e.g. data of future soft forks.
+/// They are parsed as far as possible, logged and reported when done.
+/// With `--strict-parse` blocks containing any of them are treated as corrupt instead.
+#[derive(Default)]
+pub struct UnknownConstructs {
+    tx_versions: u64,     // transactions with a version other than 1 to 3
+    tx_flags: u64,        // transactions with unknown bits in the segwit flag byte
+    witness_outputs: u64, // outputs to unknown witness versions or program lengths
+}
+
+impl UnknownConstructs {
+    /// Counts the unknown constructs in the given block.
+    /// Returns a description of the first one, None if the block contains none.
+    pub fn check(&mut self, block: &Block, height: u64) -> Option<String> {
+        let mut first = None;
+        for tx in &block.txs {
+            let tx_version = tx.value.version;
+            if tx_version == 0 || tx_version > MAX_KNOWN_TX_VERSION {
+                self.tx_versions += 1;
+                first.get_or_insert_with(|| {
+                    format!("tx {} has unknown version {}", tx.hash, tx_version)
+                });
+            }
+            if tx.value.flags & !WITNESS_FLAG != 0 {
+                self.tx_flags += 1;
+                first.get_or_insert_with(|| {
+                    format!(
+                        "tx {} has unknown segwit flags 0x{:02x}",
+                        tx.hash, tx.value.flags
+                    )
+                });
+            }
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                if let ScriptPattern::WitnessProgram = output.script.pattern {
+                    self.witness_outputs += 1;
+                    first.get_or_insert_with(|| {
+                        format!(
+                            "output {}:{} pays to an unknown witness program",
+                            tx.hash, i
+                        )
+                    });
+                }
+            }
+        }
+        if let Some(msg) = &first {
+            debug!(target: "parser", "Block {}: {}", height, msg);
+        }
+        first
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tx_versions == 0 && self.tx_flags == 0 && self.witness_outputs == 0
+    }
+}
+
+impl fmt::Display for UnknownConstructs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "tx versions: {}, segwit flags: {}, witness programs: {}",
+            self.tx_versions, self.tx_flags, self.witness_outputs
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::blockchain::parser::types::CoinType;
+    use crate::common::fixtures::MiniChain;
+
+    #[test]
+    fn test_unknown_constructs() {
+        let chain = MiniChain::generate(2);
+        let data = std::fs::read(chain.path().join("blk00000.dat")).unwrap();
+        let size = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        let mut block = (&data[8..])
+            .read_block(size, &CoinType::default(), false)
+            .unwrap();
+
+        let mut unknown = UnknownConstructs::default();
+        assert!(unknown.check(&block, 0).is_none());
+        assert!(unknown.is_empty());
+
+        block.txs[0].value.version = 4;
+        block.txs[0].value.flags = 3;
+        let msg = unknown.check(&block, 0).unwrap();
+        assert!(msg.contains("unknown version 4"));
+        assert_eq!(unknown.tx_versions, 1);
+        assert_eq!(unknown.tx_flags, 1);
+        assert_eq!(
+            unknown.to_string(),
+            "tx versions: 1, segwit flags: 1, witness programs: 0"
+        );
+    }
+}
@@ -1,12 +1,15 @@
 use bitcoin::hashes::sha256d;
+use bitcoin::pow::Work;
 use std::fmt;
 
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
+use crate::blockchain::parser::types::CoinType;
 use crate::blockchain::proto::header::BlockHeader;
 use crate::blockchain::proto::tx::{EvaluatedTx, RawTx};
+use crate::blockchain::proto::undo::BlockUndo;
 use crate::blockchain::proto::varuint::VarUint;
-use crate::blockchain::proto::{Hashed, MerkleBranch};
+use crate::blockchain::proto::{BlockHash, Hashed, MerkleBranch, ToRaw};
 use crate::common::utils;
 use crate::errors::{OpError, OpErrorKind, OpResult};
 
@@ -17,6 +20,24 @@ pub struct Block {
     pub aux_pow_extension: Option<AuxPowExtension>,
     pub tx_count: VarUint,
     pub txs: Vec<Hashed<EvaluatedTx>>,
+    /// Accumulated work of the chain up to this block, taken from the block index if available
+    pub chain_work: Option<Work>,
+    /// Metadata of the block index record, None if the block wasn't read through the index
+    pub context: Option<BlockContext>,
+}
+
+/// Metadata of a block which is only stored in the block index, not in the block data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockContext {
+    /// Client version which wrote the record (Bitcoin Core), else the block version
+    pub index_version: u64,
+    /// Validity and storage flags, see `index::BLOCK_*`
+    pub status: u64,
+    /// Number of transactions as stored in the index, None if the index doesn't store it (btcd, bootstrap)
+    pub tx_count: Option<u64>,
+    /// Number of the blk file and offset of the block data within it
+    pub blk_index: u64,
+    pub data_offset: u64,
 }
 
 impl Block {
@@ -26,34 +47,39 @@ impl Block {
         aux_pow_extension: Option<AuxPowExtension>,
         tx_count: VarUint,
         txs: Vec<RawTx>,
+        coin: &CoinType,
     ) -> Block {
         let txs = txs
             .into_par_iter()
-            .map(|raw| Hashed::double_sha256(EvaluatedTx::from(raw)))
+            .map(|raw| Hashed::with(EvaluatedTx::from(raw), coin.tx_hash))
             .collect();
         Block {
             size,
-            header: Hashed::double_sha256(header),
+            header: Hashed::with(header, coin.block_hash),
             aux_pow_extension,
             tx_count,
             txs,
+            chain_work: None,
+            context: None,
         }
     }
 
+    /// Returns the block weight as defined in BIP141, an AuxPoW extension is not counted
+    pub fn weight(&self) -> u64 {
+        let header_size = 80 + self.header.value.extension.len() as u64;
+        let base_size = header_size + self.tx_count.to_bytes().len() as u64;
+        base_size * 4 + self.txs.iter().map(|tx| tx.value.weight()).sum::<u64>()
+    }
+
     /// Computes merkle root for all containing transactions
     pub fn compute_merkle_root(&self) -> sha256d::Hash {
-        let hashes = self
-            .txs
-            .iter()
-            .map(|tx| tx.hash)
-            .collect::<Vec<sha256d::Hash>>();
-        utils::merkle_root(hashes)
+        utils::merkle_root(self.tx_hashes())
     }
 
     /// Calculates merkle root and verifies it against the field in BlockHeader.
-    /// panics if not valid.
+    /// Large blocks are hashed in parallel.
     pub fn verify_merkle_root(&self) -> OpResult<()> {
-        let merkle_root = self.compute_merkle_root();
+        let merkle_root = utils::merkle_root_par(self.tx_hashes());
 
         if merkle_root == self.header.value.merkle_root {
             Ok(())
@@ -65,6 +91,43 @@ impl Block {
             Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg))
         }
     }
+
+    /// Collects all txids into a buffer, which is reused for every level of the merkle tree
+    fn tx_hashes(&self) -> Vec<sha256d::Hash> {
+        let mut hashes = Vec::with_capacity(self.txs.len());
+        hashes.extend(self.txs.iter().map(|tx| tx.hash.to_raw_hash()));
+        hashes
+    }
+
+    /// Attaches the spent outputs from the given undo data to the inputs of all transactions.
+    /// The coinbase transaction has no undo entry, so it is skipped.
+    pub fn apply_undo(&mut self, undo: BlockUndo) -> OpResult<()> {
+        if undo.txs.len() + 1 != self.txs.len() {
+            let msg = format!(
+                "Undo data doesn't match block {}!\n  -> expected txs: {}\n  -> got: {}\n",
+                &self.header.hash,
+                self.txs.len().saturating_sub(1),
+                undo.txs.len()
+            );
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
+        for (tx, tx_undo) in self.txs.iter_mut().skip(1).zip(undo.txs) {
+            if tx.value.inputs.len() != tx_undo.prevouts.len() {
+                let msg = format!(
+                    "Undo data doesn't match tx {}!\n  -> expected inputs: {}\n  -> got: {}\n",
+                    &tx.hash,
+                    tx.value.inputs.len(),
+                    tx_undo.prevouts.len()
+                );
+                return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+            }
+            for (input, prevout) in tx.value.inputs.iter_mut().zip(tx_undo.prevouts) {
+                input.prevout = Some(prevout);
+                input.eval_pattern();
+            }
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Debug for Block {
@@ -80,7 +143,7 @@ impl fmt::Debug for Block {
 /// see https://en.bitcoin.it/wiki/Merged_mining_specification
 pub struct AuxPowExtension {
     pub coinbase_tx: RawTx,
-    pub block_hash: sha256d::Hash,
+    pub block_hash: BlockHash,
     pub coinbase_branch: MerkleBranch,
     pub blockchain_branch: MerkleBranch,
     pub parent_block: BlockHeader,
@@ -1,28 +1,36 @@
 use bitcoin::hashes::{sha256d, Hash};
 use std::fmt;
 
-use crate::blockchain::proto::ToRaw;
+use crate::blockchain::proto::{BlockHash, Identified, ToRaw};
+use crate::common::utils;
 
-/// Block Header definition. Exact 80 bytes long
+/// Block Header definition. 80 bytes long unless the coin appends further fields
+/// (see `Coin::header_layout`), which are kept in `extension`.
 #[derive(Clone)]
 pub struct BlockHeader {
     pub version: u32,
-    pub prev_hash: sha256d::Hash,
+    pub prev_hash: BlockHash,
     pub merkle_root: sha256d::Hash,
     pub timestamp: u32,
     pub bits: u32,
     pub nonce: u32,
+    pub extension: Vec<u8>,
+}
+
+impl Identified for BlockHeader {
+    type Id = BlockHash;
 }
 
 impl ToRaw for BlockHeader {
     fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(80);
+        let mut bytes = Vec::with_capacity(80 + self.extension.len());
         bytes.extend_from_slice(&self.version.to_le_bytes());
         bytes.extend_from_slice(self.prev_hash.as_byte_array());
         bytes.extend_from_slice(self.merkle_root.as_byte_array());
         bytes.extend_from_slice(&self.timestamp.to_le_bytes());
         bytes.extend_from_slice(&self.bits.to_le_bytes());
         bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        bytes.extend_from_slice(&self.extension);
         bytes
     }
 }
@@ -36,6 +44,7 @@ impl fmt::Debug for BlockHeader {
             .field("timestamp", &self.timestamp)
             .field("bits", &self.bits)
             .field("nonce", &self.nonce)
+            .field("extension", &utils::arr_to_hex(&self.extension))
             .finish()
     }
 }
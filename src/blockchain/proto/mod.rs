@@ -1,32 +1,59 @@
-use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::hashes::{hash_newtype, sha256d};
 use std::fmt;
 
+use crate::blockchain::parser::types::{self, HashFn};
+
 pub mod block;
 pub mod header;
 pub mod script;
 pub mod tx;
+pub mod undo;
 pub mod varuint;
 
 /// Trait to serialize defined structures
 pub trait ToRaw {
     fn to_bytes(&self) -> Vec<u8>;
+
+    /// Serializes including witness data (BIP144).
+    /// Types without witness data serialize the same way as with `to_bytes`.
+    fn to_bytes_with_witness(&self) -> Vec<u8> {
+        self.to_bytes()
+    }
+}
+
+hash_newtype! {
+    /// Transaction id, displayed in RPC (reversed) byte order
+    pub struct TxId(sha256d::Hash);
+
+    /// Block hash, displayed in RPC (reversed) byte order
+    pub struct BlockHash(sha256d::Hash);
+}
+
+/// Structures which are identified by the double sha256 of their serialization
+pub trait Identified: ToRaw {
+    type Id: From<sha256d::Hash> + fmt::Debug;
 }
 
 /// Wrapper to hold a 32 byte verification hash along the data type T
-pub struct Hashed<T> {
-    pub hash: sha256d::Hash,
+pub struct Hashed<T: Identified> {
+    pub hash: T::Id,
     pub value: T,
 }
 
-impl<T: ToRaw> Hashed<T> {
+impl<T: Identified> Hashed<T> {
     /// encapsulates T and creates double sha256 as hash
     pub fn double_sha256(value: T) -> Hashed<T> {
-        let hash = sha256d::Hash::hash(&value.to_bytes());
+        Hashed::with(value, types::double_sha256)
+    }
+
+    /// encapsulates T and creates the hash with the given function, see `CoinType::tx_hash`
+    pub fn with(value: T, hash: HashFn) -> Hashed<T> {
+        let hash = T::Id::from(hash(&value.to_bytes()));
         Hashed { hash, value }
     }
 }
 
-impl<T: fmt::Debug> fmt::Debug for Hashed<T> {
+impl<T: Identified + fmt::Debug> fmt::Debug for Hashed<T> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("Hashed")
             .field("hash", &self.hash)
@@ -37,7 +64,7 @@ impl<T: fmt::Debug> fmt::Debug for Hashed<T> {
 
 /// https://en.bitcoin.it/wiki/Merged_mining_specification#Merkle_Branch
 pub struct MerkleBranch {
-    pub hashes: Vec<[u8; 32]>,
+    pub hashes: Vec<sha256d::Hash>,
     // Bitmask of which side of the merkle hash function the branch_hash element should go on.
     // Zero means it goes on the right, One means on the left.
     // It is equal to the index of the starting hash within the widest level
@@ -46,7 +73,31 @@ pub struct MerkleBranch {
 }
 
 impl MerkleBranch {
-    pub fn new(hashes: Vec<[u8; 32]>, side_mask: u32) -> Self {
+    pub fn new(hashes: Vec<sha256d::Hash>, side_mask: u32) -> Self {
         Self { hashes, side_mask }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_txid_rpc_order() {
+        // Coinbase of the genesis block
+        let txid =
+            TxId::from_str("4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b")
+                .unwrap();
+        assert_eq!(txid.to_byte_array()[0], 0x3b);
+        assert_eq!(
+            txid.to_string(),
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"
+        );
+        assert_eq!(
+            txid.to_raw_hash(),
+            sha256d::Hash::from_byte_array(txid.to_byte_array())
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::blockchain::proto::script::EvaluatedScript;
+
+const N_SHARDS: usize = 64;
+
+/// Approximated LRU cache which maps raw scripts to their evaluated form.
+/// Each shard holds two generations: lookups hit in the old generation are promoted
+/// to the current one, and once the current generation is full the old one gets dropped.
+/// This keeps hot scripts (exchange wallets, etc.) alive with O(1) bookkeeping.
+pub struct ScriptCache {
+    shards: Vec<Mutex<Shard>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Shard {
+    capacity: usize,
+    current: HashMap<Vec<u8>, EvaluatedScript>,
+    previous: HashMap<Vec<u8>, EvaluatedScript>,
+}
+
+impl Shard {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            current: HashMap::with_capacity(capacity),
+            previous: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &[u8]) -> Option<EvaluatedScript> {
+        if let Some(script) = self.current.get(key) {
+            return Some(script.clone());
+        }
+        let script = self.previous.remove(key)?;
+        self.insert(key.to_vec(), script.clone());
+        Some(script)
+    }
+
+    fn insert(&mut self, key: Vec<u8>, script: EvaluatedScript) {
+        if self.current.len() >= self.capacity {
+            self.previous = mem::replace(&mut self.current, HashMap::with_capacity(self.capacity));
+        }
+        self.current.insert(key, script);
+    }
+}
+
+impl ScriptCache {
+    /// Creates a cache which holds roughly up to `capacity` evaluated scripts
+    pub fn new(capacity: usize) -> Self {
+        // Each shard holds two generations, so split the capacity accordingly
+        let shard_capacity = (capacity / N_SHARDS / 2).max(1);
+        Self {
+            shards: (0..N_SHARDS)
+                .map(|_| Mutex::new(Shard::new(shard_capacity)))
+                .collect(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cached script or evaluates and caches it with the given function
+    pub fn get_or_eval<F>(&self, bytes: &[u8], version_id: u8, eval: F) -> EvaluatedScript
+    where
+        F: FnOnce() -> EvaluatedScript,
+    {
+        let mut key = Vec::with_capacity(bytes.len() + 1);
+        key.push(version_id);
+        key.extend_from_slice(bytes);
+
+        let shard = &self.shards[Self::shard_index(&key)];
+        if let Some(script) = shard.lock().unwrap().get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return script;
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        // Evaluate without holding the lock
+        let script = eval();
+        shard.lock().unwrap().insert(key, script.clone());
+        script
+    }
+
+    /// Returns the number of cache hits and misses
+    pub fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    fn shard_index(key: &[u8]) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % N_SHARDS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::ScriptPattern;
+
+    fn script(address: &str) -> EvaluatedScript {
+        EvaluatedScript::new(
+            Some(String::from(address)),
+            ScriptPattern::Pay2PublicKeyHash,
+        )
+    }
+
+    #[test]
+    fn test_script_cache_hit() {
+        let cache = ScriptCache::new(1024);
+        let first = cache.get_or_eval(&[0x01, 0x02], 0x00, || script("a"));
+        let second = cache.get_or_eval(&[0x01, 0x02], 0x00, || script("b"));
+        assert_eq!(first.address, second.address);
+        assert_eq!(cache.stats(), (1, 1));
+
+        // Same bytes with a different version must not collide
+        let other = cache.get_or_eval(&[0x01, 0x02], 0x1e, || script("c"));
+        assert_eq!(other.address, Some(String::from("c")));
+        assert_eq!(cache.stats(), (1, 2));
+    }
+
+    #[test]
+    fn test_script_cache_shard_eviction() {
+        let mut shard = Shard::new(2);
+        shard.insert(vec![1], script("1"));
+        shard.insert(vec![2], script("2"));
+        // Rotates the first two entries into the previous generation
+        shard.insert(vec![3], script("3"));
+        assert!(shard.get(&[1]).is_some());
+        // Promoting [1] rotated again, so [2] is gone now
+        shard.insert(vec![4], script("4"));
+        assert!(shard.get(&[2]).is_none());
+        assert!(shard.get(&[4]).is_some());
+    }
+}
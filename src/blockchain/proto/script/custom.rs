@@ -1,7 +1,10 @@
 /// This custom Script implementation is for all networks other than Bitcoin and Bitcoin Testnet
-use crate::blockchain::proto::script::{EvaluatedScript, ScriptError, ScriptPattern};
-use crate::common::utils;
+use crate::blockchain::proto::script::{
+    address_format, decode_name_op, AddressFormat, EvaluatedScript, ScriptError, ScriptPattern,
+};
+use crate::common::{groestl, utils};
 use bitcoin::base58;
+use bitcoin::bech32::{self, u5, ToBase32, Variant};
 use bitcoin::hashes::{hash160, sha256d, Hash};
 use bitcoin::opcodes::{all, All, Class, ClassifyContext};
 use std::fmt;
@@ -119,33 +122,19 @@ impl<'a> ScriptEvaluator<'a> {
         let data_len = if let Class::PushBytes(n) = opcode_class {
             n as usize
         } else {
-            match opcode {
-                all::OP_PUSHDATA1 => {
-                    if self.ip + 1 > self.n_bytes {
-                        return Err(ScriptError::UnexpectedEof);
-                    }
-                    let val = ScriptEvaluator::read_uint(&self.bytes[self.ip..], 1)?;
-                    self.ip += 1;
-                    val
-                }
-                all::OP_PUSHDATA2 => {
-                    if self.ip + 2 > self.n_bytes {
-                        return Err(ScriptError::UnexpectedEof);
-                    }
-                    let val = ScriptEvaluator::read_uint(&self.bytes[self.ip..], 2)?;
-                    self.ip += 2;
-                    val
-                }
-                all::OP_PUSHDATA4 => {
-                    if self.ip + 4 > self.n_bytes {
-                        return Err(ScriptError::UnexpectedEof);
-                    }
-                    let val = ScriptEvaluator::read_uint(&self.bytes[self.ip..], 4)?;
-                    self.ip += 4;
-                    val
-                }
-                _ => 0,
+            let size = match opcode {
+                all::OP_PUSHDATA1 => 1,
+                all::OP_PUSHDATA2 => 2,
+                all::OP_PUSHDATA4 => 4,
+                _ => return Ok(0),
+            };
+            // The length follows the opcode
+            if self.ip + 1 + size > self.n_bytes {
+                return Err(ScriptError::UnexpectedEof);
             }
+            let val = ScriptEvaluator::read_uint(&self.bytes[self.ip + 1..], size)?;
+            self.ip += size;
+            val
         };
         Ok(data_len)
     }
@@ -164,12 +153,19 @@ impl<'a> ScriptEvaluator<'a> {
         }
 
         // Pay to Public Key (p2pk)
+        // The Genesis Block, self-payments, and pay-by-IP-address payments look like:
+        // <65 bytes uncompressed key> OP_CHECKSIG, later ones use a compressed 33 bytes key
         let p2pk = [
             StackElement::Data(Vec::new()),
             StackElement::Op(all::OP_CHECKSIG),
         ];
         if ScriptEvaluator::match_stack_pattern(elements, &p2pk) {
-            return ScriptPattern::Pay2PublicKey;
+            return match elements[0] {
+                StackElement::Data(ref pub_key) if is_public_key(pub_key) => {
+                    ScriptPattern::Pay2PublicKey
+                }
+                _ => ScriptPattern::NotRecognised,
+            };
         }
 
         // Pay to Script Hash (p2sh)
@@ -195,6 +191,18 @@ impl<'a> ScriptEvaluator<'a> {
             };
         }
 
+        // Witness programs (BIP141): a version opcode followed by a 2 to 40 bytes program
+        if let [version, StackElement::Data(program)] = elements {
+            match (witness_version(version), program.len()) {
+                (Some(0), 20) => return ScriptPattern::Pay2WitnessPublicKeyHash,
+                (Some(0), 32) => return ScriptPattern::Pay2WitnessScriptHash,
+                (Some(0), _) => return ScriptPattern::NotRecognised,
+                (Some(1), 32) => return ScriptPattern::Pay2Taproot,
+                (Some(_), 2..=40) => return ScriptPattern::WitnessProgram,
+                _ => (),
+            }
+        }
+
         //TODO: implement n to m multisig
         let multisig_2n3 = [
             StackElement::Op(all::OP_PUSHNUM_2),
@@ -207,14 +215,6 @@ impl<'a> ScriptEvaluator<'a> {
         if ScriptEvaluator::match_stack_pattern(elements, &multisig_2n3) {
             return ScriptPattern::Pay2MultiSig;
         }
-        /* TODO:
-        // The Genesis Block, self-payments, and pay-by-IP-address payments look like:
-        // 65 BYTES:... CHECKSIG
-        let gen_block_pattern = [StackElement::Op(opcodes::All::OP_CHECKSIG)];
-        if ScriptEvaluator::match_stack_pattern(&elements, &gen_block_pattern) {
-
-         }*/
-
         ScriptPattern::NotRecognised
     }
 
@@ -247,9 +247,53 @@ impl<'a> ScriptEvaluator<'a> {
     }
 }
 
+/// Version id of Namecoin, whose scripts may be prefixed with a name operation
+const NAMECOIN_VERSION_ID: u8 = 0x34;
+/// Version id of Groestlcoin, whose addresses use Groestl instead of double SHA256 checksums
+const GROESTLCOIN_VERSION_ID: u8 = 0x24;
+
+/// Computes the 4 byte checksum of a base58 encoded address
+type Checksum = fn(&[u8]) -> [u8; 4];
+
+/// Returns the address checksum function of the coin with the given version id
+fn checksum_of(version_id: u8) -> Checksum {
+    match version_id {
+        GROESTLCOIN_VERSION_ID => groestl_checksum,
+        _ => sha256d_checksum,
+    }
+}
+
+fn sha256d_checksum(data: &[u8]) -> [u8; 4] {
+    let mut checksum = [0u8; 4];
+    checksum.copy_from_slice(&sha256d::Hash::hash(data)[0..4]);
+    checksum
+}
+
+fn groestl_checksum(data: &[u8]) -> [u8; 4] {
+    let mut checksum = [0u8; 4];
+    checksum.copy_from_slice(&groestl::groestl512d(data)[0..4]);
+    checksum
+}
+
 pub fn eval_from_bytes_custom(bytes: &[u8], version_id: u8) -> EvaluatedScript {
+    eval_custom(bytes, version_id, address_format(), true)
+}
+
+/// Evaluates the script, the address is only extracted if `with_address` is set
+pub(super) fn eval_custom(
+    bytes: &[u8],
+    version_id: u8,
+    format: AddressFormat,
+    with_address: bool,
+) -> EvaluatedScript {
+    // Strip the name operation to evaluate the address script
+    if version_id == NAMECOIN_VERSION_ID {
+        if let Some((_, prefix_len)) = decode_name_op(bytes) {
+            return eval_custom(&bytes[prefix_len..], version_id, format, with_address);
+        }
+    }
     match ScriptEvaluator::new(bytes).eval() {
-        Ok(stack) => eval_from_stack(stack, version_id),
+        Ok(stack) => eval_from_stack(stack, version_id, format, with_address),
         Err(ScriptError::UnexpectedEof) => EvaluatedScript {
             address: None,
             pattern: ScriptPattern::NotRecognised,
@@ -261,27 +305,57 @@ pub fn eval_from_bytes_custom(bytes: &[u8], version_id: u8) -> EvaluatedScript {
     }
 }
 
+/// Returns the elements of the script, None if it is malformed
+pub(super) fn eval_elements(bytes: &[u8]) -> Option<Vec<StackElement>> {
+    ScriptEvaluator::new(bytes)
+        .eval()
+        .ok()
+        .map(|stack| stack.elements)
+}
+
 /// Extracts evaluated address from script stack
-fn compute_stack(stack: Stack, version_id: u8) -> Result<EvaluatedScript, ScriptError> {
+fn compute_stack(
+    stack: Stack,
+    version_id: u8,
+    format: AddressFormat,
+    with_address: bool,
+) -> Result<EvaluatedScript, ScriptError> {
+    let checksum = checksum_of(version_id);
     let script = match stack.pattern {
         ref p @ ScriptPattern::Pay2PublicKey => {
             let pub_key = stack.elements[0].data()?;
             EvaluatedScript {
-                address: Some(public_key_to_addr(&pub_key, version_id)),
+                address: with_address.then(|| public_key_to_addr(&pub_key, version_id, checksum)),
                 pattern: p.clone(),
             }
         }
         ref p @ ScriptPattern::Pay2PublicKeyHash => {
             let h160 = stack.elements[2].data()?;
             EvaluatedScript {
-                address: Some(hash_160_to_address(&h160, version_id)),
+                address: with_address.then(|| hash_160_to_address(&h160, version_id, checksum)),
                 pattern: p.clone(),
             }
         }
         ref p @ ScriptPattern::Pay2ScriptHash => {
             let h160 = stack.elements[1].data()?;
             EvaluatedScript {
-                address: Some(hash_160_to_address(&h160, 5)),
+                address: with_address
+                    .then(|| hash_160_to_address(&h160, format.script_version_id, checksum)),
+                pattern: p.clone(),
+            }
+        }
+        ref p @ (ScriptPattern::Pay2WitnessPublicKeyHash
+        | ScriptPattern::Pay2WitnessScriptHash
+        | ScriptPattern::Pay2Taproot
+        | ScriptPattern::WitnessProgram) => {
+            let version = witness_version(&stack.elements[0]).ok_or(ScriptError::InvalidFormat)?;
+            let program = stack.elements[1].data()?;
+            let address = match (with_address, format.bech32_hrp) {
+                (true, Some(hrp)) => witness_program_to_address(hrp, version, &program),
+                _ => None,
+            };
+            EvaluatedScript {
+                address,
                 pattern: p.clone(),
             }
         }
@@ -309,8 +383,13 @@ fn compute_stack(stack: Stack, version_id: u8) -> Result<EvaluatedScript, Script
 }
 
 /// Extracts evaluated address from script stack
-fn eval_from_stack(stack: Stack, version_id: u8) -> EvaluatedScript {
-    match compute_stack(stack, version_id) {
+fn eval_from_stack(
+    stack: Stack,
+    version_id: u8,
+    format: AddressFormat,
+    with_address: bool,
+) -> EvaluatedScript {
+    match compute_stack(stack, version_id, format, with_address) {
         Ok(script) => script,
         Err(ScriptError::UnexpectedEof) => EvaluatedScript {
             address: None,
@@ -323,27 +402,68 @@ fn eval_from_stack(stack: Stack, version_id: u8) -> EvaluatedScript {
     }
 }
 
-/// Takes full ECDSA public key (65 bytes) and a version id
-fn public_key_to_addr(pub_key: &[u8], version: u8) -> String {
+/// Returns true for compressed (33 bytes) and uncompressed or hybrid (65 bytes) public keys
+fn is_public_key(data: &[u8]) -> bool {
+    match data.first() {
+        Some(0x02) | Some(0x03) => data.len() == 33,
+        Some(0x04) | Some(0x06) | Some(0x07) => data.len() == 65,
+        _ => false,
+    }
+}
+
+/// Takes a compressed (33 bytes) or uncompressed (65 bytes) ECDSA public key, a version id
+/// and the checksum function of the coin
+fn public_key_to_addr(pub_key: &[u8], version: u8, checksum: Checksum) -> String {
     let hash = hash160::Hash::hash(pub_key);
-    hash_160_to_address(hash.as_byte_array(), version)
+    hash_160_to_address(hash.as_byte_array(), version, checksum)
 }
 
-/// Takes 20 byte public key and version id
-fn hash_160_to_address(h160: &[u8], version: u8) -> String {
+/// Returns the witness version of OP_0 to OP_16
+fn witness_version(element: &StackElement) -> Option<u8> {
+    match element {
+        StackElement::Op(all::OP_PUSHBYTES_0) => Some(0),
+        StackElement::Op(op) => {
+            let code = op.to_u8();
+            (all::OP_PUSHNUM_1.to_u8()..=all::OP_PUSHNUM_16.to_u8())
+                .contains(&code)
+                .then(|| code - all::OP_PUSHNUM_1.to_u8() + 1)
+        }
+        StackElement::Data(_) => None,
+    }
+}
+
+/// Encodes a witness program as bech32 (version 0) or bech32m (BIP350) address
+fn witness_program_to_address(hrp: &str, version: u8, program: &[u8]) -> Option<String> {
+    let variant = match version {
+        0 => Variant::Bech32,
+        _ => Variant::Bech32m,
+    };
+    let mut data = vec![u5::try_from_u8(version).ok()?];
+    data.extend(program.to_base32());
+    bech32::encode(hrp, data, variant).ok()
+}
+
+/// Takes 20 byte public key, version id and the checksum function of the coin
+fn hash_160_to_address(h160: &[u8], version: u8, checksum: Checksum) -> String {
     let mut hash = Vec::with_capacity(h160.len() + 5);
     hash.push(version);
     hash.extend_from_slice(h160);
 
-    let checksum = &sha256d::Hash::hash(&hash)[0..4];
-    hash.extend_from_slice(checksum);
+    let checksum = checksum(&hash);
+    hash.extend_from_slice(&checksum);
     base58::encode(&hash)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{eval_from_bytes_custom, eval_from_stack, ScriptEvaluator, ScriptPattern};
-    use crate::common::utils;
+    use super::{
+        eval_custom, eval_from_bytes_custom, eval_from_stack, AddressFormat, ScriptEvaluator,
+        ScriptPattern,
+    };
+    use crate::blockchain::parser::types::{CoinType, Litecoin};
+    use crate::common::{groestl, utils};
+    use bitcoin::base58;
+    use bitcoin::hashes::{sha256d, Hash};
 
     #[test]
     fn test_bitcoin_script_p2pkh() {
@@ -360,7 +480,7 @@ mod tests {
             format!("{:?}", stack)
         );
 
-        let script = eval_from_stack(stack, 0x00);
+        let script = eval_from_stack(stack, 0x00, AddressFormat::default(), true);
         assert_eq!(
             script.address,
             Some(String::from("12higDjoCCNXSA95xZMWUdPvXNmkAduhWv"))
@@ -386,7 +506,7 @@ mod tests {
         assert_eq!("044bca633a91de10df85a63d0a24cb09783148fe0e16c92e937fc4491580c860757148effa0595a955f44078b48ba67fa198782e8bb68115da0daa8fde5301f7f9 OP_CHECKSIG",
             format!("{:?}", stack));
 
-        let script = eval_from_stack(stack, 0x00);
+        let script = eval_from_stack(stack, 0x00, AddressFormat::default(), true);
         assert_eq!(
             script.address,
             Some(String::from("1LEWwJkDj8xriE87ALzQYcHjTmD8aqDj1f"))
@@ -438,7 +558,7 @@ mod tests {
             format!("{:?}", stack)
         );
 
-        let script = eval_from_stack(stack, 0x00);
+        let script = eval_from_stack(stack, 0x00, AddressFormat::default(), true);
         assert_eq!(
             script.address,
             Some(String::from("3P14159f73E4gFr7JterCCQh9QjiTjiZrG"))
@@ -461,7 +581,7 @@ mod tests {
             format!("{:?}", stack)
         );
 
-        let script = eval_from_stack(stack, 0x00);
+        let script = eval_from_stack(stack, 0x00, AddressFormat::default(), true);
         assert_eq!(script.address, None);
         assert_eq!(
             script.pattern,
@@ -481,7 +601,7 @@ mod tests {
             format!("{:?}", stack)
         );
 
-        let script = eval_from_stack(stack, 0x00);
+        let script = eval_from_stack(stack, 0x00, AddressFormat::default(), true);
         assert_eq!(script.address, None);
         assert_eq!(script.pattern, ScriptPattern::NotRecognised);
     }
@@ -494,20 +614,81 @@ mod tests {
         assert_eq!(script.pattern, ScriptPattern::NotRecognised);
     }
 
-    /*
-    FIXME: assertion failed
-        Left:  Some("NCAzVGKq8JrsETxAkgw3MsDPinAEPwsTfn")
-        Right: Some("N3Jpya157nc2d48EPaxtcsbRr9V19U4hfW")
     #[test]
     fn test_namecoin_coinbase_script() {
         let script_pubkey = utils::hex_to_vec("41046a77fa46493d61985c1157a6e3e498b3b97c878c9c23e5b4729d354b574eb33a20c0483551308e2bd08295ce238e8ad09a7a2477732eb2e995a3e20455e9d137ac");
         let script = eval_from_bytes_custom(&script_pubkey, 0x34);
         assert_eq!(
             script.address,
-            Some(String::from("N3Jpya157nc2d48EPaxtcsbRr9V19U4hfW")),
+            Some(String::from("NCAzVGKq8JrsETxAkgw3MsDPinAEPwsTfn")),
         );
+        assert_eq!(script.pattern, ScriptPattern::Pay2PublicKey);
+    }
+
+    #[test]
+    fn test_namecoin_compressed_p2pk_script() {
+        let script_pubkey = utils::hex_to_vec(
+            "210338bf57d51a50184cf5ef0dc42ecd519fb19e24574c057620262cc1df94da2ae5ac",
+        );
+        let script = eval_from_bytes_custom(&script_pubkey, 0x34);
+        assert_eq!(
+            script.address,
+            Some(String::from("NBcGTFwYxBcxs2gMnjkND7FPkNm2H6hqTj")),
+        );
+        assert_eq!(script.pattern, ScriptPattern::Pay2PublicKey);
+
+        // The key may also be pushed with OP_PUSHDATA1
+        let mut script_pubkey = script_pubkey;
+        script_pubkey.insert(0, 0x4c);
+        let script = eval_from_bytes_custom(&script_pubkey, 0x34);
+        assert_eq!(
+            script.address,
+            Some(String::from("NBcGTFwYxBcxs2gMnjkND7FPkNm2H6hqTj")),
+        );
+    }
+
+    #[test]
+    fn test_groestlcoin_address_checksum() {
+        let script_pubkey = utils::hex_to_vec("76a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac");
+        let script = eval_from_bytes_custom(&script_pubkey, 0x24);
+        assert_eq!(script.pattern, ScriptPattern::Pay2PublicKeyHash);
+        let address = script.address.unwrap();
+        assert!(address.starts_with('F'));
+
+        let decoded = base58::decode(&address).unwrap();
+        let (payload, checksum) = decoded.split_at(21);
+        assert_eq!(payload[0], 0x24);
+        assert_eq!(&payload[1..], &script_pubkey[3..23]);
+        assert_eq!(checksum, &groestl::groestl512d(payload)[0..4]);
+        assert_ne!(checksum, &sha256d::Hash::hash(payload)[0..4]);
+    }
+
+    #[test]
+    fn test_invalid_p2pk_script() {
+        // 20 bytes and a 33 bytes key with an invalid prefix followed by OP_CHECKSIG
+        for hex in [
+            "1412ab8dc588ca9d5787dde7eb29569da63c3a238cac",
+            "210538bf57d51a50184cf5ef0dc42ecd519fb19e24574c057620262cc1df94da2ae5ac",
+        ] {
+            let script = eval_from_bytes_custom(&utils::hex_to_vec(hex), 0x34);
+            assert_eq!(script.address, None);
+            assert_eq!(script.pattern, ScriptPattern::NotRecognised);
+        }
+    }
+
+    #[test]
+    fn test_namecoin_name_script() {
+        // OP_NAME_UPDATE "d/bitcoin" "{}" OP_2DROP OP_DROP <p2pkh>
+        let script_pubkey = utils::hex_to_vec(
+            "5309642f626974636f696e027b7d6d7576a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac",
+        );
+        let script = eval_from_bytes_custom(&script_pubkey, 0x34);
+        assert_eq!(
+            script.address,
+            Some(String::from("MxH5ssEn7aU5xhPbENg5h9YqFcAo6nzXTd")),
+        );
+        assert_eq!(script.pattern, ScriptPattern::Pay2PublicKeyHash);
     }
-    */
 
     #[test]
     fn test_litecoin_coinbase_script() {
@@ -530,4 +711,49 @@ mod tests {
             Some(String::from("DLAznsPDLDRgsVcTFWRMYMG5uH6GddDtv8")),
         );
     }
+
+    #[test]
+    fn test_address_formats() {
+        let litecoin = CoinType::from(Litecoin);
+        let modern = AddressFormat::of(&litecoin, false);
+        let legacy = AddressFormat::of(&litecoin, true);
+        let eval = |hex, format| eval_custom(&utils::hex_to_vec(hex), 0x30, format, true);
+
+        let p2sh = "a914e9c3dd0c07aac76179ebc76a6c78d4d67c6c160a87";
+        let script = eval(p2sh, modern);
+        assert_eq!(script.pattern, ScriptPattern::Pay2ScriptHash);
+        assert!(script.address.unwrap().starts_with('M'));
+        assert_eq!(
+            eval(p2sh, legacy).address,
+            Some(String::from("3P14159f73E4gFr7JterCCQh9QjiTjiZrG"))
+        );
+
+        // Test vectors of BIP173 and BIP350
+        let p2wpkh = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+        let script = eval(p2wpkh, modern);
+        assert_eq!(script.pattern, ScriptPattern::Pay2WitnessPublicKeyHash);
+        assert!(script.address.unwrap().starts_with("ltc1q"));
+        assert_eq!(eval(p2wpkh, legacy).address, None);
+        let bech32 = AddressFormat {
+            bech32_hrp: Some("bc"),
+            ..modern
+        };
+        assert_eq!(
+            eval(p2wpkh, bech32).address,
+            Some(String::from("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"))
+        );
+        let p2tr = "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let script = eval(p2tr, bech32);
+        assert_eq!(script.pattern, ScriptPattern::Pay2Taproot);
+        assert_eq!(
+            script.address,
+            Some(String::from(
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+            ))
+        );
+        assert_eq!(
+            eval("0010751e76e8199196d454941c45d1b3a323", modern).pattern,
+            ScriptPattern::NotRecognised
+        );
+    }
 }
@@ -0,0 +1,269 @@
+use std::fmt;
+
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::Script;
+
+use crate::blockchain::proto::script::ScriptPattern;
+
+/// Classifies how an output gets spent, based on the scriptSig and the witness of an input.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum InputPattern {
+    /// Input of a coinbase transaction, which doesn't spend anything
+    Coinbase,
+
+    /// Signature script: <sig>
+    Pay2PublicKey,
+
+    /// Signature script: <sig> <pubkey>
+    Pay2PublicKeyHash,
+
+    /// Signature script: OP_0 <A sig>[B sig][C sig...]
+    Pay2MultiSig,
+
+    /// Signature script: <sig>[sig][sig...] <redeemScript>
+    Pay2ScriptHash,
+
+    /// P2SH-wrapped segwit v0 key hash spend (BIP141)
+    /// Signature script: <0 <20-byte-key-hash>>, Witness: <sig> <pubkey>
+    Pay2ScriptHashWitnessPublicKeyHash,
+
+    /// P2SH-wrapped segwit v0 script hash spend (BIP141)
+    /// Signature script: <0 <32-byte-hash>>, Witness: <items...> <witnessScript>
+    Pay2ScriptHashWitnessScriptHash,
+
+    /// Native segwit v0 key hash spend. Witness: <sig> <pubkey>
+    Pay2WitnessPublicKeyHash,
+
+    /// Native segwit v0 script hash spend. Witness: <items...> <witnessScript>
+    Pay2WitnessScriptHash,
+
+    /// Taproot key path spend (BIP341). Witness: <sig> [annex]
+    Pay2TaprootKeyPath,
+
+    /// Taproot script path spend (BIP341). Witness: <items...> <script> <control block> [annex]
+    Pay2TaprootScriptPath,
+
+    /// The input does not conform to the standard templates
+    NotRecognised,
+}
+
+impl fmt::Display for InputPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Classifies the given scriptSig and witness.
+/// If the pattern of the spent output is known (see `--use-undo-files`),
+/// it is used to resolve otherwise ambiguous inputs.
+pub fn eval_input(
+    script_sig: &[u8],
+    witness: &[Vec<u8>],
+    prevout: Option<&ScriptPattern>,
+) -> InputPattern {
+    let pushes = match parse_pushes(script_sig) {
+        Some(pushes) => pushes,
+        None => return InputPattern::NotRecognised,
+    };
+
+    if witness.is_empty() {
+        return eval_legacy(&pushes, prevout);
+    }
+    match pushes.as_slice() {
+        [] => eval_native_witness(witness, prevout),
+        [program] if program.len() == 22 && program[..2] == [0x00, 0x14] => {
+            InputPattern::Pay2ScriptHashWitnessPublicKeyHash
+        }
+        [program] if program.len() == 34 && program[..2] == [0x00, 0x20] => {
+            InputPattern::Pay2ScriptHashWitnessScriptHash
+        }
+        _ => InputPattern::NotRecognised,
+    }
+}
+
+/// Classifies inputs without witness data
+fn eval_legacy(pushes: &[&[u8]], prevout: Option<&ScriptPattern>) -> InputPattern {
+    match prevout {
+        Some(ScriptPattern::Pay2PublicKey) => return InputPattern::Pay2PublicKey,
+        Some(ScriptPattern::Pay2PublicKeyHash) => return InputPattern::Pay2PublicKeyHash,
+        Some(ScriptPattern::Pay2MultiSig) => return InputPattern::Pay2MultiSig,
+        Some(ScriptPattern::Pay2ScriptHash) => return InputPattern::Pay2ScriptHash,
+        _ => {}
+    }
+    match pushes {
+        [sig] if is_signature(sig) => InputPattern::Pay2PublicKey,
+        [sig, pubkey] if is_signature(sig) && is_pubkey(pubkey) => InputPattern::Pay2PublicKeyHash,
+        // OP_0 <sigs...> <redeemScript with OP_CHECKMULTISIG>
+        [dummy, .., redeem_script] if dummy.is_empty() && redeem_script.last() == Some(&0xae) => {
+            InputPattern::Pay2ScriptHash
+        }
+        [dummy, sigs @ ..] if dummy.is_empty() && !sigs.is_empty() => {
+            if sigs.iter().all(|s| is_signature(s)) {
+                InputPattern::Pay2MultiSig
+            } else {
+                InputPattern::NotRecognised
+            }
+        }
+        _ => InputPattern::NotRecognised,
+    }
+}
+
+/// Classifies native segwit inputs (empty scriptSig)
+fn eval_native_witness(witness: &[Vec<u8>], prevout: Option<&ScriptPattern>) -> InputPattern {
+    match prevout {
+        Some(ScriptPattern::Pay2WitnessPublicKeyHash) => {
+            return InputPattern::Pay2WitnessPublicKeyHash
+        }
+        Some(ScriptPattern::Pay2WitnessScriptHash) => return InputPattern::Pay2WitnessScriptHash,
+        Some(ScriptPattern::Pay2Taproot) => return eval_taproot(witness),
+        _ => {}
+    }
+    match witness {
+        [sig, pubkey] if is_signature(sig) && pubkey.len() == 33 => {
+            InputPattern::Pay2WitnessPublicKeyHash
+        }
+        _ => match eval_taproot(witness) {
+            InputPattern::NotRecognised => InputPattern::Pay2WitnessScriptHash,
+            pattern => pattern,
+        },
+    }
+}
+
+/// Distinguishes taproot key path from script path spends
+fn eval_taproot(witness: &[Vec<u8>]) -> InputPattern {
+    // Strip the annex if present
+    let witness = match witness {
+        [rest @ .., annex] if !rest.is_empty() && annex.first() == Some(&0x50) => rest,
+        _ => witness,
+    };
+    match witness {
+        [sig] if sig.len() == 64 || sig.len() == 65 => InputPattern::Pay2TaprootKeyPath,
+        [_, .., control_block] if is_control_block(control_block) => {
+            InputPattern::Pay2TaprootScriptPath
+        }
+        _ => InputPattern::NotRecognised,
+    }
+}
+
+/// Returns all data pushes of the given script, None if it contains other opcodes
+pub(super) fn parse_pushes(script_sig: &[u8]) -> Option<Vec<&[u8]>> {
+    Script::from_bytes(script_sig)
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// DER encoded signature followed by the sighash type
+#[inline]
+fn is_signature(data: &[u8]) -> bool {
+    (9..=73).contains(&data.len()) && data[0] == 0x30
+}
+
+#[inline]
+fn is_pubkey(data: &[u8]) -> bool {
+    match data.len() {
+        33 => data[0] == 0x02 || data[0] == 0x03,
+        65 => data[0] == 0x04,
+        _ => false,
+    }
+}
+
+/// Control block: leaf version and parity byte, internal key and the merkle path
+#[inline]
+fn is_control_block(data: &[u8]) -> bool {
+    data.len() >= 33 && data.len() % 32 == 1 && data[0] & 0xfe == 0xc0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sig() -> Vec<u8> {
+        let mut sig = vec![0x30; 71];
+        sig[70] = 0x01;
+        sig
+    }
+
+    #[test]
+    fn test_eval_input_legacy() {
+        let mut script_sig = vec![0x47];
+        script_sig.extend_from_slice(&sig());
+        assert_eq!(
+            eval_input(&script_sig, &[], None),
+            InputPattern::Pay2PublicKey
+        );
+
+        script_sig.push(0x21);
+        script_sig.push(0x02);
+        script_sig.extend_from_slice(&[0x11; 32]);
+        assert_eq!(
+            eval_input(&script_sig, &[], None),
+            InputPattern::Pay2PublicKeyHash
+        );
+
+        // Non-push opcodes are not allowed
+        assert_eq!(
+            eval_input(&[0x76, 0xa9], &[], None),
+            InputPattern::NotRecognised
+        );
+        // The spent output takes precedence
+        assert_eq!(
+            eval_input(&[0x01, 0x51], &[], Some(&ScriptPattern::Pay2ScriptHash)),
+            InputPattern::Pay2ScriptHash
+        );
+    }
+
+    #[test]
+    fn test_eval_input_segwit() {
+        let pubkey = vec![0x02; 33];
+        assert_eq!(
+            eval_input(&[], &[sig(), pubkey.clone()], None),
+            InputPattern::Pay2WitnessPublicKeyHash
+        );
+
+        let mut script_sig = vec![0x16, 0x00, 0x14];
+        script_sig.extend_from_slice(&[0x11; 20]);
+        assert_eq!(
+            eval_input(&script_sig, &[sig(), pubkey], None),
+            InputPattern::Pay2ScriptHashWitnessPublicKeyHash
+        );
+
+        let mut script_sig = vec![0x22, 0x00, 0x20];
+        script_sig.extend_from_slice(&[0x11; 32]);
+        assert_eq!(
+            eval_input(&script_sig, &[vec![], sig(), vec![0xae]], None),
+            InputPattern::Pay2ScriptHashWitnessScriptHash
+        );
+        assert_eq!(
+            eval_input(&[], &[vec![], sig(), vec![0xae]], None),
+            InputPattern::Pay2WitnessScriptHash
+        );
+    }
+
+    #[test]
+    fn test_eval_input_taproot() {
+        assert_eq!(
+            eval_input(&[], &[vec![0x11; 64]], None),
+            InputPattern::Pay2TaprootKeyPath
+        );
+        // With annex
+        assert_eq!(
+            eval_input(&[], &[vec![0x11; 65], vec![0x50, 0x00]], None),
+            InputPattern::Pay2TaprootKeyPath
+        );
+
+        let mut control_block = vec![0xc1];
+        control_block.extend_from_slice(&[0x11; 64]);
+        assert_eq!(
+            eval_input(
+                &[],
+                &[vec![0x11; 64], vec![0x20, 0xac], control_block],
+                Some(&ScriptPattern::Pay2Taproot)
+            ),
+            InputPattern::Pay2TaprootScriptPath
+        );
+    }
+}
@@ -1,10 +1,23 @@
+mod cache;
 mod custom;
+mod input;
+mod namecoin;
+pub mod signature;
+mod template;
 
 use std::convert::From;
 use std::error::Error;
 use std::fmt;
-
-use crate::blockchain::proto::script::custom::eval_from_bytes_custom;
+use std::sync::OnceLock;
+
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::proto::script::cache::ScriptCache;
+use crate::blockchain::proto::script::custom::{
+    eval_custom, eval_elements, eval_from_bytes_custom,
+};
+pub use crate::blockchain::proto::script::input::{eval_input, InputPattern};
+pub use crate::blockchain::proto::script::namecoin::{decode_name_op, NameOp};
+pub use crate::blockchain::proto::script::template::ScriptTemplates;
 use bitcoin::address::Payload;
 use bitcoin::blockdata::script::Instruction;
 use bitcoin::hashes::{hash160, Hash};
@@ -69,6 +82,9 @@ pub enum ScriptPattern {
 
     Unspendable,
 
+    /// Matched a user supplied template, holds its label (see `--script-templates`)
+    Template(&'static str),
+
     /// The script is valid but does not conform to the standard templates.
     /// Such scripts are always accepted if they are mined into blocks, but
     /// transactions with non-standard scripts may not be forwarded by peers.
@@ -90,6 +106,7 @@ impl fmt::Display for ScriptPattern {
             ScriptPattern::WitnessProgram => write!(f, "WitnessProgram"),
             ScriptPattern::Pay2Taproot => write!(f, "Pay2Taproot"),
             ScriptPattern::Unspendable => write!(f, "Unspendable"),
+            ScriptPattern::Template(label) => write!(f, "{}", label),
             ScriptPattern::NotRecognised => write!(f, "NotRecognised"),
             ScriptPattern::Error(ref err) => write!(f, "ScriptError: {}", err),
         }
@@ -108,16 +125,133 @@ impl EvaluatedScript {
     }
 }
 
+/// Process wide cache for evaluated scripts, disabled unless initialized
+static SCRIPT_CACHE: OnceLock<ScriptCache> = OnceLock::new();
+
+/// Addresses of coins other than Bitcoin, see `AddressFormat`
+static ADDRESS_FORMAT: OnceLock<AddressFormat> = OnceLock::new();
+
+/// Selects how the custom engine encodes P2SH and witness program addresses.
+/// Coins like Litecoin changed their P2SH prefix and added bech32 addresses over time,
+/// explorers show the modern formats while `--legacy-addresses` keeps the old ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AddressFormat {
+    pub script_version_id: u8,
+    /// Witness programs have no address without a human readable part
+    pub bech32_hrp: Option<&'static str>,
+}
+
+impl Default for AddressFormat {
+    /// Base58 P2SH addresses with version 5 and no addresses for witness programs
+    fn default() -> Self {
+        AddressFormat {
+            script_version_id: 0x05,
+            bech32_hrp: None,
+        }
+    }
+}
+
+impl AddressFormat {
+    pub fn of(coin: &CoinType, legacy: bool) -> Self {
+        match legacy {
+            true => AddressFormat {
+                script_version_id: coin.legacy_script_version_id,
+                bech32_hrp: None,
+            },
+            false => AddressFormat {
+                script_version_id: coin.script_version_id,
+                bech32_hrp: coin.bech32_hrp,
+            },
+        }
+    }
+}
+
+/// Sets the address format of the custom engine for this process.
+/// Has no effect if it has been initialized already, the default format is used until then.
+pub fn init_address_format(format: AddressFormat) {
+    if ADDRESS_FORMAT.set(format).is_ok() {
+        debug!(target: "script", "Initialized address format: {:?}", format);
+    }
+}
+
+pub(super) fn address_format() -> AddressFormat {
+    ADDRESS_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// User supplied templates for scripts the builtin rules don't recognise
+static TEMPLATES: OnceLock<ScriptTemplates> = OnceLock::new();
+
+/// Sets the script templates for this process, has no effect if they have been initialized already
+pub fn init_templates(templates: ScriptTemplates) {
+    let count = templates.count();
+    if TEMPLATES.set(templates).is_ok() {
+        debug!(target: "script", "Initialized {} script templates", count);
+    }
+}
+
+/// Labels unrecognised scripts with the first matching template, if any
+fn apply_templates(script: EvaluatedScript, bytes: &[u8]) -> EvaluatedScript {
+    if script.pattern != ScriptPattern::NotRecognised {
+        return script;
+    }
+    let label = TEMPLATES
+        .get()
+        .and_then(|templates| templates.label_of(&eval_elements(bytes)?));
+    match label {
+        Some(label) => EvaluatedScript::new(None, ScriptPattern::Template(label)),
+        None => script,
+    }
+}
+
+/// Enables the script evaluation cache with the given capacity.
+/// Has no effect if the capacity is zero or the cache has already been initialized.
+pub fn init_cache(capacity: usize) {
+    if capacity > 0 && SCRIPT_CACHE.set(ScriptCache::new(capacity)).is_ok() {
+        debug!(target: "script", "Initialized script cache with {} entries", capacity);
+    }
+}
+
+/// Returns the number of cache hits and misses if the cache is enabled
+pub fn cache_stats() -> Option<(u64, u64)> {
+    SCRIPT_CACHE.get().map(|cache| cache.stats())
+}
+
 /// Extracts evaluated address from ScriptPubKey
 pub fn eval_from_bytes(bytes: &[u8], version_id: u8) -> EvaluatedScript {
-    match version_id {
+    match SCRIPT_CACHE.get() {
+        // OP_RETURN scripts are (almost) always unique, don't pollute the cache with them
+        Some(cache) if bytes.first() != Some(&0x6a) => cache.get_or_eval(bytes, version_id, || {
+            eval_from_bytes_uncached(bytes, version_id)
+        }),
+        _ => eval_from_bytes_uncached(bytes, version_id),
+    }
+}
+
+fn eval_from_bytes_uncached(bytes: &[u8], version_id: u8) -> EvaluatedScript {
+    let script = match version_id {
         0x00 | 0x6f => eval_from_bytes_bitcoin(bytes, version_id),
         _ => eval_from_bytes_custom(bytes, version_id),
-    }
+    };
+    apply_templates(script, bytes)
+}
+
+/// Only classifies the ScriptPubKey without extracting the address.
+/// Encoding the addresses is a large part of the evaluation costs, which callbacks
+/// can skip if they don't need them (see `Callback::wants_addresses`).
+pub fn eval_pattern_from_bytes(bytes: &[u8], version_id: u8) -> EvaluatedScript {
+    let script = match version_id {
+        0x00 | 0x6f => eval_bitcoin(bytes, version_id, false),
+        _ => eval_custom(bytes, version_id, address_format(), false),
+    };
+    apply_templates(script, bytes)
 }
 
 /// Extracts evaluated address from script using `rust_bitcoin`
 pub fn eval_from_bytes_bitcoin(bytes: &[u8], version_id: u8) -> EvaluatedScript {
+    eval_bitcoin(bytes, version_id, true)
+}
+
+fn eval_bitcoin(bytes: &[u8], version_id: u8, with_address: bool) -> EvaluatedScript {
     let network = match version_id {
         0x00 => Network::Bitcoin,
         0x6f => Network::Testnet,
@@ -136,21 +270,25 @@ pub fn eval_from_bytes_bitcoin(bytes: &[u8], version_id: u8) -> EvaluatedScript
         return EvaluatedScript::new(None, ScriptPattern::Unspendable);
     }
 
-    let address = match Address::from_script(script, network) {
-        Ok(address) => Some(format!("{}", address)),
-        Err(err) => {
-            if err != address::Error::UnrecognizedScript {
-                warn!(target: "script", "Unable to extract evaluated address: {}", err)
+    let address = match with_address {
+        true => match Address::from_script(script, network) {
+            Ok(address) => Some(format!("{}", address)),
+            Err(err) => {
+                if err != address::Error::UnrecognizedScript {
+                    warn!(target: "script", "Unable to extract evaluated address: {}", err)
+                }
+                None
             }
-            None
-        }
+        },
+        false => None,
     };
 
     if script.is_p2pk() {
-        EvaluatedScript::new(
-            p2pk_to_string(script, network),
-            ScriptPattern::Pay2PublicKey,
-        )
+        let address = match with_address {
+            true => p2pk_to_string(script, network),
+            false => None,
+        };
+        EvaluatedScript::new(address, ScriptPattern::Pay2PublicKey)
     } else if script.is_p2pkh() {
         EvaluatedScript::new(address, ScriptPattern::Pay2PublicKeyHash)
     } else if script.is_p2sh() {
@@ -189,7 +327,7 @@ fn p2pk_to_string(script: &Script, network: Network) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::ScriptPattern;
-    use crate::blockchain::proto::script::eval_from_bytes_bitcoin;
+    use crate::blockchain::proto::script::{eval_from_bytes_bitcoin, eval_pattern_from_bytes};
 
     #[test]
     fn test_bitcoin_script_p2pkh() {
@@ -205,6 +343,13 @@ mod tests {
             Some(String::from("12higDjoCCNXSA95xZMWUdPvXNmkAduhWv"))
         );
         assert_eq!(result.pattern, ScriptPattern::Pay2PublicKeyHash);
+
+        // Same pattern without address, also for custom coins
+        for version_id in [0x00, 0x30] {
+            let result = eval_pattern_from_bytes(&bytes, version_id);
+            assert_eq!(result.address, None);
+            assert_eq!(result.pattern, ScriptPattern::Pay2PublicKeyHash);
+        }
     }
 
     #[test]
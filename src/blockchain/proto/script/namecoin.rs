@@ -0,0 +1,137 @@
+/// Decoding of Namecoin name operations.
+/// Name outputs prefix a regular script with the operation and its arguments, which are dropped again:
+///   OP_NAME_NEW:         OP_1 <hash> OP_2DROP <script>
+///   OP_NAME_FIRSTUPDATE: OP_2 <name> <rand> <value> OP_2DROP OP_2DROP <script>
+///   OP_NAME_UPDATE:      OP_3 <name> <value> OP_2DROP OP_DROP <script>
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::opcodes::{all, All};
+use bitcoin::Script;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameOp {
+    /// Commits to a name by hash(rand + name) without revealing it
+    New { hash: Vec<u8> },
+    /// Reveals and registers the name committed to by OP_NAME_NEW
+    FirstUpdate {
+        name: Vec<u8>,
+        rand: Vec<u8>,
+        value: Vec<u8>,
+    },
+    /// Updates the value of a name and renews it
+    Update { name: Vec<u8>, value: Vec<u8> },
+}
+
+impl NameOp {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NameOp::New { .. } => "name_new",
+            NameOp::FirstUpdate { .. } => "name_firstupdate",
+            NameOp::Update { .. } => "name_update",
+        }
+    }
+
+    /// Returns the name, which is not known for OP_NAME_NEW
+    pub fn name(&self) -> Option<&[u8]> {
+        match self {
+            NameOp::New { .. } => None,
+            NameOp::FirstUpdate { name, .. } | NameOp::Update { name, .. } => Some(name),
+        }
+    }
+
+    pub fn value(&self) -> Option<&[u8]> {
+        match self {
+            NameOp::New { .. } => None,
+            NameOp::FirstUpdate { value, .. } | NameOp::Update { value, .. } => Some(value),
+        }
+    }
+}
+
+/// Decodes the name operation prefix of a script.
+/// Returns the operation and the length of the prefix, the remaining bytes are the address script.
+pub fn decode_name_op(bytes: &[u8]) -> Option<(NameOp, usize)> {
+    let mut instructions = Script::from_bytes(bytes).instruction_indices();
+    let (n_args, drops): (usize, &[All]) = match instructions.next()?.ok()?.1 {
+        Instruction::Op(all::OP_PUSHNUM_1) => (1, &[all::OP_2DROP]),
+        Instruction::Op(all::OP_PUSHNUM_2) => (3, &[all::OP_2DROP, all::OP_2DROP]),
+        Instruction::Op(all::OP_PUSHNUM_3) => (2, &[all::OP_2DROP, all::OP_DROP]),
+        _ => return None,
+    };
+
+    let mut args = Vec::with_capacity(n_args);
+    for _ in 0..n_args {
+        match instructions.next()?.ok()?.1 {
+            Instruction::PushBytes(data) => args.push(data.as_bytes().to_vec()),
+            Instruction::Op(_) => return None,
+        }
+    }
+    let mut prefix_len = 0;
+    for drop in drops {
+        match instructions.next()?.ok()? {
+            (index, Instruction::Op(op)) if op == *drop => prefix_len = index + 1,
+            _ => return None,
+        }
+    }
+
+    let mut args = args.into_iter();
+    let mut arg = || args.next().unwrap();
+    let op = match n_args {
+        1 => NameOp::New { hash: arg() },
+        3 => NameOp::FirstUpdate {
+            name: arg(),
+            rand: arg(),
+            value: arg(),
+        },
+        _ => NameOp::Update {
+            name: arg(),
+            value: arg(),
+        },
+    };
+    Some((op, prefix_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils;
+
+    #[test]
+    fn test_decode_name_update() {
+        // OP_3 "d/bitcoin" "{}" OP_2DROP OP_DROP OP_DUP OP_HASH160 <hash> OP_EQUALVERIFY OP_CHECKSIG
+        let bytes = utils::hex_to_vec(
+            "5309642f626974636f696e027b7d6d7576a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac",
+        );
+        let (op, prefix_len) = decode_name_op(&bytes).unwrap();
+        assert_eq!(
+            op,
+            NameOp::Update {
+                name: b"d/bitcoin".to_vec(),
+                value: b"{}".to_vec()
+            }
+        );
+        assert_eq!(op.kind(), "name_update");
+        assert_eq!(prefix_len, 16);
+        assert_eq!(bytes[prefix_len], 0x76);
+    }
+
+    #[test]
+    fn test_decode_name_new() {
+        let mut bytes = vec![0x51, 0x14];
+        bytes.extend([0xab; 20]);
+        bytes.extend([0x6d, 0x51]);
+        let (op, prefix_len) = decode_name_op(&bytes).unwrap();
+        assert_eq!(op.name(), None);
+        assert_eq!(op.kind(), "name_new");
+        assert_eq!(prefix_len, 23);
+
+        // Not followed by the expected drops
+        assert_eq!(decode_name_op(&[0x51, 0x01, 0xab, 0x75]), None);
+        // Regular scripts
+        assert_eq!(decode_name_op(&[0x51]), None);
+        assert_eq!(
+            decode_name_op(&utils::hex_to_vec(
+                "76a91412ab8dc588ca9d5787dde7eb29569da63c3a238c88ac"
+            )),
+            None
+        );
+    }
+}
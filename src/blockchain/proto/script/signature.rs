@@ -0,0 +1,191 @@
+use std::fmt;
+
+use crate::blockchain::proto::script::input::parse_pushes;
+use crate::blockchain::proto::script::InputPattern;
+use crate::blockchain::proto::tx::TxInput;
+
+/// Half of the secp256k1 group order, larger S values are malleable (BIP146)
+const HALF_ORDER: [u8; 32] = [
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x5d, 0x57, 0x6e, 0x73, 0x57, 0xa4, 0x50, 0x1d, 0xdf, 0xe9, 0x2f, 0x46, 0x68, 0x1b, 0x20, 0xa0,
+];
+/// Sighash type of 64 bytes Schnorr signatures (BIP341)
+pub const SIGHASH_DEFAULT: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SignatureKind {
+    /// DER encoded ECDSA signature followed by the sighash type
+    Ecdsa,
+    /// BIP340 Schnorr signature, 64 bytes or 65 bytes with an explicit sighash type
+    Schnorr,
+}
+
+impl fmt::Display for SignatureKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureKind::Ecdsa => write!(f, "ecdsa"),
+            SignatureKind::Schnorr => write!(f, "schnorr"),
+        }
+    }
+}
+
+/// Properties of a signature found in the scriptSig or witness of an input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    pub kind: SignatureKind,
+    pub sighash: u8,
+    // Size in bytes including the sighash type
+    pub len: usize,
+    // R is encoded in at most 32 bytes (ECDSA only), e.g. by Bitcoin Core since 0.17
+    pub low_r: bool,
+    // S is at most half the group order (ECDSA only)
+    pub low_s: bool,
+}
+
+impl Signature {
+    /// Parses a strictly DER encoded ECDSA signature with sighash type as defined in BIP66
+    pub fn parse_ecdsa(data: &[u8]) -> Option<Signature> {
+        let len = data.len();
+        if !(9..=73).contains(&len) || data[0] != 0x30 || data[1] as usize != len - 3 {
+            return None;
+        }
+        let r_len = data[3] as usize;
+        if data[2] != 0x02 || 5 + r_len >= len {
+            return None;
+        }
+        let s_len = data[5 + r_len] as usize;
+        if r_len + s_len + 7 != len || data[4 + r_len] != 0x02 {
+            return None;
+        }
+        let r = &data[4..4 + r_len];
+        let s = &data[6 + r_len..6 + r_len + s_len];
+        if !is_der_integer(r) || !is_der_integer(s) {
+            return None;
+        }
+        Some(Signature {
+            kind: SignatureKind::Ecdsa,
+            sighash: data[len - 1],
+            len,
+            low_r: r_len <= 32,
+            low_s: is_low_s(s),
+        })
+    }
+
+    /// Parses a Schnorr signature as used in taproot spends (BIP341)
+    pub fn parse_schnorr(data: &[u8]) -> Option<Signature> {
+        let sighash = match data.len() {
+            64 => SIGHASH_DEFAULT,
+            // SIGHASH_DEFAULT must not be given explicitly
+            65 if matches!(data[64], 0x01..=0x03 | 0x81..=0x83) => data[64],
+            _ => return None,
+        };
+        Some(Signature {
+            kind: SignatureKind::Schnorr,
+            sighash,
+            len: data.len(),
+            low_r: false,
+            low_s: false,
+        })
+    }
+}
+
+/// Returns the signatures of the given input.
+/// Schnorr signatures are only expected in taproot spends, ECDSA signatures anywhere else.
+pub fn extract_signatures(input: &TxInput) -> Vec<Signature> {
+    match input.pattern {
+        InputPattern::Coinbase => Vec::new(),
+        InputPattern::Pay2TaprootKeyPath => input
+            .witness
+            .first()
+            .and_then(|sig| Signature::parse_schnorr(sig))
+            .into_iter()
+            .collect(),
+        // Script inputs are followed by the script and control block
+        InputPattern::Pay2TaprootScriptPath => input
+            .witness
+            .iter()
+            .filter_map(|item| Signature::parse_schnorr(item))
+            .collect(),
+        _ => parse_pushes(&input.script_sig)
+            .unwrap_or_default()
+            .into_iter()
+            .chain(input.witness.iter().map(Vec::as_slice))
+            .filter_map(Signature::parse_ecdsa)
+            .collect(),
+    }
+}
+
+/// Positive integer without excess padding
+fn is_der_integer(data: &[u8]) -> bool {
+    match data {
+        [] => false,
+        [first, ..] if first & 0x80 != 0 => false,
+        [0x00, second, ..] => second & 0x80 != 0,
+        _ => true,
+    }
+}
+
+fn is_low_s(s: &[u8]) -> bool {
+    let s = match s.iter().position(|b| *b != 0) {
+        Some(start) => &s[start..],
+        None => return true,
+    };
+    match s.len() {
+        len if len < 32 => true,
+        32 => s <= &HALF_ORDER[..],
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils;
+
+    #[test]
+    fn test_parse_ecdsa() {
+        // Input of the first bitcoin transaction (block 170)
+        let sig = utils::hex_to_vec(
+            "304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd41\
+             0220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901",
+        );
+        let parsed = Signature::parse_ecdsa(&sig).unwrap();
+        assert_eq!(parsed.kind, SignatureKind::Ecdsa);
+        assert_eq!(parsed.sighash, 0x01);
+        assert_eq!(parsed.len, 71);
+        assert!(parsed.low_r);
+        assert!(parsed.low_s);
+
+        // Padded R (high R) and truncated data
+        let mut high_r = utils::hex_to_vec("3026022100");
+        high_r.extend_from_slice(&[0x80; 32]);
+        high_r.extend_from_slice(&[0x02, 0x01, 0x01, 0x81]);
+        let parsed = Signature::parse_ecdsa(&high_r).unwrap();
+        assert!(!parsed.low_r);
+        assert_eq!(parsed.sighash, 0x81);
+        assert!(Signature::parse_ecdsa(&sig[..70]).is_none());
+        assert!(Signature::parse_schnorr(&sig).is_none());
+    }
+
+    #[test]
+    fn test_parse_schnorr() {
+        assert_eq!(
+            Signature::parse_schnorr(&[0x11; 64]).unwrap().sighash,
+            SIGHASH_DEFAULT
+        );
+        let mut sig = vec![0x11; 65];
+        sig[64] = 0x83;
+        assert_eq!(Signature::parse_schnorr(&sig).unwrap().sighash, 0x83);
+        sig[64] = 0x00;
+        assert!(Signature::parse_schnorr(&sig).is_none());
+    }
+
+    #[test]
+    fn test_is_low_s() {
+        assert!(is_low_s(&HALF_ORDER));
+        let mut high = HALF_ORDER;
+        high[31] += 1;
+        assert!(!is_low_s(&high));
+        assert!(is_low_s(&[0x01]));
+    }
+}
@@ -0,0 +1,319 @@
+use std::fs;
+use std::path::Path;
+
+use bitcoin::opcodes::{all, All};
+use toml::{Table, Value};
+
+use crate::blockchain::proto::script::custom::StackElement;
+use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Single element of a compiled template
+#[cfg_attr(test, derive(PartialEq, Debug))]
+enum Token {
+    Op(All),
+    /// Data push with a length in the given range, starting with the given bytes
+    Push {
+        min_len: usize,
+        max_len: usize,
+        prefix: Vec<u8>,
+    },
+    /// Any number of remaining elements
+    Rest,
+}
+
+impl Token {
+    fn matches(&self, element: &StackElement) -> bool {
+        match (self, element) {
+            (Token::Op(op), StackElement::Op(other)) => op == other,
+            (
+                Token::Push {
+                    min_len,
+                    max_len,
+                    prefix,
+                },
+                StackElement::Data(data),
+            ) => (*min_len..=*max_len).contains(&data.len()) && data.starts_with(prefix),
+            _ => false,
+        }
+    }
+}
+
+struct ScriptTemplate {
+    label: &'static str,
+    tokens: Vec<Token>,
+}
+
+impl ScriptTemplate {
+    fn matches(&self, elements: &[StackElement]) -> bool {
+        let (tokens, rest) = match self.tokens.split_last() {
+            Some((Token::Rest, tokens)) => (tokens, true),
+            _ => (&self.tokens[..], false),
+        };
+        match rest {
+            true if elements.len() < tokens.len() => return false,
+            false if elements.len() != tokens.len() => return false,
+            _ => (),
+        }
+        tokens.iter().zip(elements).all(|(t, e)| t.matches(e))
+    }
+}
+
+/// User supplied script templates to label scripts the builtin rules don't recognise,
+/// e.g. scripts of new protocols or of a single organization (see `--script-templates`).
+/// Templates are read from a TOML file and tried in the order of the file:
+///
+/// ```toml
+/// [[template]]
+/// label = "Omni"
+/// script = "OP_RETURN <0x6f6d6e69...>"
+///
+/// [[template]]
+/// label = "HashLock"
+/// script = "OP_SHA256 <32> OP_EQUAL"
+/// ```
+/// A script is a whitespace separated list of opcodes (`OP_DUP`, `OP_0` to `OP_16`) and data pushes:
+/// `<data>` matches any push, `<20>` a push of 20 bytes, `<1-75>` a push of 1 to 75 bytes,
+/// `<0x6a6b>` a push of exactly these bytes and `<0x6a6b...>` a push starting with them.
+/// A trailing `...` matches any further elements.
+pub struct ScriptTemplates {
+    templates: Vec<ScriptTemplate>,
+}
+
+impl ScriptTemplates {
+    pub fn from_path(path: &Path) -> OpResult<Self> {
+        let content = fs::read_to_string(path)?;
+        ScriptTemplates::parse(&content).map_err(|err| {
+            let msg = format!("{}: {}", path.display(), err.message);
+            OpError::new(err.kind).join_msg(&msg)
+        })
+    }
+
+    fn parse(content: &str) -> OpResult<Self> {
+        let config: Table = content.parse().map_err(|err: toml::de::Error| {
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(err.message())
+        })?;
+        if let Some(key) = config.keys().find(|key| key.as_str() != "template") {
+            return Err(invalid("config", &format!("unknown key '{}'", key)));
+        }
+        let templates = match config.get("template") {
+            Some(Value::Array(templates)) => templates
+                .iter()
+                .map(|template| match template {
+                    Value::Table(table) => ScriptTemplate::from_table(table),
+                    _ => Err(invalid("config", "template must be a table")),
+                })
+                .collect::<OpResult<Vec<ScriptTemplate>>>()?,
+            _ => return Err(invalid("config", "no [[template]] entries found")),
+        };
+        Ok(ScriptTemplates { templates })
+    }
+
+    pub fn count(&self) -> usize {
+        self.templates.len()
+    }
+
+    /// Returns the label of the first template matching the evaluated script
+    pub(super) fn label_of(&self, elements: &[StackElement]) -> Option<&'static str> {
+        self.templates
+            .iter()
+            .find(|template| template.matches(elements))
+            .map(|template| template.label)
+    }
+}
+
+impl ScriptTemplate {
+    fn from_table(table: &Table) -> OpResult<Self> {
+        let label = match table.get("label").and_then(Value::as_str) {
+            Some(label) if !label.trim().is_empty() => label,
+            _ => return Err(invalid("template", "label must be a non-empty string")),
+        };
+        let script = table
+            .get("script")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid(label, "script must be a string"))?;
+        if let Some(key) = table
+            .keys()
+            .find(|key| !["label", "script"].contains(&key.as_str()))
+        {
+            return Err(invalid(label, &format!("unknown key '{}'", key)));
+        }
+        let tokens = compile(script).map_err(|msg| invalid(label, &msg))?;
+        Ok(ScriptTemplate {
+            // Labels are kept for the lifetime of the process and end up in every matching script
+            label: Box::leak(label.to_owned().into_boxed_str()),
+            tokens,
+        })
+    }
+}
+
+/// Compiles the script of a template into tokens
+fn compile(script: &str) -> Result<Vec<Token>, String> {
+    let words: Vec<&str> = script.split_whitespace().collect();
+    if words.is_empty() {
+        return Err(String::from("script is empty"));
+    }
+    let mut tokens = Vec::with_capacity(words.len());
+    for (i, word) in words.iter().enumerate() {
+        let token = match *word {
+            "..." if i + 1 == words.len() => Token::Rest,
+            "..." => return Err(String::from("'...' is only allowed at the end")),
+            word if word.starts_with('<') && word.ends_with('>') => {
+                parse_push(&word[1..word.len() - 1])
+                    .ok_or_else(|| format!("invalid data push '{}'", word))?
+            }
+            word => {
+                Token::Op(parse_opcode(word).ok_or_else(|| format!("unknown opcode '{}'", word))?)
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Parses the inner part of `<data>`, `<20>`, `<1-75>`, `<0x6a6b>` and `<0x6a6b...>`
+fn parse_push(push: &str) -> Option<Token> {
+    if push == "data" {
+        return Some(Token::Push {
+            min_len: 0,
+            max_len: usize::MAX,
+            prefix: Vec::new(),
+        });
+    }
+    if let Some(hex) = push.strip_prefix("0x") {
+        let (hex, open) = match hex.strip_suffix("...") {
+            Some(hex) => (hex, true),
+            None => (hex, false),
+        };
+        if hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let prefix = utils::hex_to_vec(hex);
+        return Some(Token::Push {
+            min_len: prefix.len(),
+            max_len: if open { usize::MAX } else { prefix.len() },
+            prefix,
+        });
+    }
+    let (min_len, max_len) = match push.split_once('-') {
+        Some((min, max)) => (min.parse().ok()?, max.parse().ok()?),
+        None => (push.parse().ok()?, push.parse().ok()?),
+    };
+    match min_len <= max_len {
+        true => Some(Token::Push {
+            min_len,
+            max_len,
+            prefix: Vec::new(),
+        }),
+        false => None,
+    }
+}
+
+/// Parses an opcode by its name as printed by rust-bitcoin (e.g. `OP_PUSHNUM_1`),
+/// or by the common aliases `OP_0` to `OP_16`, `OP_FALSE` and `OP_TRUE`
+fn parse_opcode(name: &str) -> Option<All> {
+    match name {
+        "OP_0" | "OP_FALSE" => return Some(all::OP_PUSHBYTES_0),
+        "OP_TRUE" => return Some(all::OP_PUSHNUM_1),
+        _ => (),
+    }
+    if let Some(n) = name
+        .strip_prefix("OP_")
+        .and_then(|n| n.parse::<u8>().ok())
+        .filter(|n| (1..=16).contains(n))
+    {
+        return Some(All::from(all::OP_PUSHNUM_1.to_u8() + n - 1));
+    }
+    (0..=u8::MAX)
+        .map(All::from)
+        .find(|op| format!("{:?}", op) == name)
+}
+
+fn invalid(name: &str, msg: &str) -> OpError {
+    OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!("{}: {}", name, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATES: &str = r#"
+[[template]]
+label = "Omni"
+script = "OP_RETURN <0x6f6d6e69...>"
+
+[[template]]
+label = "HashLock"
+script = "OP_SHA256 <32> OP_EQUAL"
+
+[[template]]
+label = "Anything after OP_1"
+script = "OP_1 ..."
+"#;
+
+    #[test]
+    fn test_compile() {
+        assert_eq!(
+            compile("OP_DUP <1-75> OP_16 ...").unwrap(),
+            [
+                Token::Op(all::OP_DUP),
+                Token::Push {
+                    min_len: 1,
+                    max_len: 75,
+                    prefix: Vec::new()
+                },
+                Token::Op(all::OP_PUSHNUM_16),
+                Token::Rest
+            ]
+        );
+        assert_eq!(
+            compile("<0x6a6b>").unwrap(),
+            [Token::Push {
+                min_len: 2,
+                max_len: 2,
+                prefix: vec![0x6a, 0x6b]
+            }]
+        );
+        assert!(compile("OP_FOO").is_err());
+        assert!(compile("... OP_DUP").is_err());
+        assert!(compile("<5-1>").is_err());
+        assert!(compile("<0xzz>").is_err());
+        assert!(compile("").is_err());
+    }
+
+    #[test]
+    fn test_templates() {
+        let templates = ScriptTemplates::parse(TEMPLATES).unwrap();
+        assert_eq!(templates.count(), 3);
+
+        let omni = [
+            StackElement::Op(all::OP_RETURN),
+            StackElement::Data(vec![0x6f, 0x6d, 0x6e, 0x69, 0x00]),
+        ];
+        assert_eq!(templates.label_of(&omni), Some("Omni"));
+        let hash_lock = [
+            StackElement::Op(all::OP_SHA256),
+            StackElement::Data(vec![0; 32]),
+            StackElement::Op(all::OP_EQUAL),
+        ];
+        assert_eq!(templates.label_of(&hash_lock), Some("HashLock"));
+        let short_hash = [
+            StackElement::Op(all::OP_SHA256),
+            StackElement::Data(vec![0; 20]),
+            StackElement::Op(all::OP_EQUAL),
+        ];
+        assert_eq!(templates.label_of(&short_hash), None);
+        let rest = [
+            StackElement::Op(all::OP_PUSHNUM_1),
+            StackElement::Op(all::OP_DROP),
+        ];
+        assert_eq!(templates.label_of(&rest), Some("Anything after OP_1"));
+
+        assert!(ScriptTemplates::parse("[[template]]\nlabel = \"x\"").is_err());
+        assert!(ScriptTemplates::parse(
+            "[[template]]\nlabel = \"x\"\nscript = \"OP_DUP\"\nfoo = 1"
+        )
+        .is_err());
+        assert!(ScriptTemplates::parse("foo = 1").is_err());
+    }
+}
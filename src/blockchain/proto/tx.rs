@@ -1,65 +1,151 @@
 use bitcoin::hashes::{sha256d, Hash};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use smallvec::SmallVec;
 use std::fmt;
 
 use crate::blockchain::proto::script;
+use crate::blockchain::proto::undo::SpentOutput;
 use crate::blockchain::proto::varuint::VarUint;
-use crate::blockchain::proto::ToRaw;
+use crate::blockchain::proto::{Identified, ToRaw, TxId};
 use crate::common::utils;
 
+/// Most transactions spend one or two inputs and create up to two outputs,
+/// so these are stored inline and only larger transactions allocate.
+pub type TxInputs = SmallVec<[TxInput; 2]>;
+pub type TxOutputs = SmallVec<[TxOutput; 2]>;
+pub type EvaluatedTxOuts = SmallVec<[EvaluatedTxOut; 2]>;
+
 pub struct RawTx {
     pub version: u32,
+    pub flags: u8, // segwit flag byte, 0 if the marker is missing
     pub in_count: VarUint,
-    pub inputs: Vec<TxInput>,
+    pub inputs: TxInputs,
     pub out_count: VarUint,
-    pub outputs: Vec<TxOutput>,
+    pub outputs: TxOutputs,
     pub locktime: u32,
     pub version_id: u8,
+    // Addresses of the outputs are only extracted if set, see `Callback::wants_addresses`
+    pub eval_addresses: bool,
 }
 
 /// Simple transaction struct
 /// Please note: The txid is not stored here. See Hashed.
 pub struct EvaluatedTx {
     pub version: u32,
+    pub flags: u8, // segwit flag byte, 0 if the marker is missing
     pub in_count: VarUint,
-    pub inputs: Vec<TxInput>,
+    pub inputs: TxInputs,
     pub out_count: VarUint,
-    pub outputs: Vec<EvaluatedTxOut>,
+    pub outputs: EvaluatedTxOuts,
     pub locktime: u32,
 }
 
 impl EvaluatedTx {
-    pub fn new(
-        version: u32,
-        in_count: VarUint,
-        inputs: Vec<TxInput>,
-        out_count: VarUint,
-        outputs: Vec<TxOutput>,
-        locktime: u32,
-        version_id: u8,
-    ) -> Self {
-        // Evaluate and wrap all outputs to process them later
-        let outputs = outputs
-            .into_par_iter()
-            .map(|o| EvaluatedTxOut::eval_script(o, version_id))
-            .collect();
-        EvaluatedTx {
-            version,
-            in_count,
-            inputs,
-            out_count,
-            outputs,
-            locktime,
+    /// Returns true if at least one input carries witness data
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|i| !i.witness.is_empty())
+    }
+
+    /// Computes the witness txid. Equals the txid for transactions without witness data.
+    /// The wtxid of the coinbase is all zeros by definition (BIP141), its witness commitment
+    /// can't include itself.
+    pub fn wtxid(&self) -> sha256d::Hash {
+        if self.is_coinbase() {
+            return sha256d::Hash::all_zeros();
         }
+        sha256d::Hash::hash(&self.to_bytes_with_witness())
     }
 
-    pub fn is_coinbase(&self) -> bool {
-        if self.in_count.value == 1 {
-            let input = self.inputs.first().unwrap();
-            return input.outpoint.txid.as_ref() == [0u8; 32] && input.outpoint.index == 0xFFFFFFFF;
+    /// Returns the size of the serialization without witness data, see `to_bytes`.
+    /// The size is summed up from the fields, nothing is serialized.
+    pub fn base_size(&self) -> u64 {
+        let inputs: usize = self
+            .inputs
+            .iter()
+            .map(|i| 36 + i.script_len.encoded_len() + i.script_sig.len() + 4)
+            .sum();
+        let outputs: usize = self
+            .outputs
+            .iter()
+            .map(|o| 8 + o.out.script_len.encoded_len() + o.out.script_pubkey.len())
+            .sum();
+        (4 + self.in_count.encoded_len() + inputs + self.out_count.encoded_len() + outputs + 4)
+            as u64
+    }
+
+    /// Returns the size of the segwit marker, flag and witness stacks,
+    /// which `to_bytes_with_witness` adds to the base size. Zero without witness data.
+    pub fn witness_size(&self) -> u64 {
+        if !self.has_witness() {
+            return 0;
+        }
+        let stacks: usize = self
+            .inputs
+            .iter()
+            .map(|i| {
+                let items: usize = i
+                    .witness
+                    .iter()
+                    .map(|item| VarUint::compact_len(item.len() as u64) + item.len())
+                    .sum();
+                VarUint::compact_len(i.witness.len() as u64) + items
+            })
+            .sum();
+        (2 + stacks) as u64
+    }
+
+    /// Returns the transaction weight as defined in BIP141
+    pub fn weight(&self) -> u64 {
+        let base_size = self.base_size();
+        base_size * 4 + self.witness_size()
+    }
+
+    /// Returns the virtual size, which is the weight divided by 4 and rounded up
+    pub fn vsize(&self) -> u64 {
+        self.weight().div_ceil(4)
+    }
+
+    /// Returns the sum of all spent outputs.
+    /// Only available if the prevouts of all inputs have been resolved (see `--use-undo-files`)
+    /// and the sum doesn't overflow.
+    pub fn input_value(&self) -> Option<u64> {
+        self.inputs.iter().try_fold(0u64, |sum, i| {
+            sum.checked_add(i.prevout.as_ref()?.out.out.value)
+        })
+    }
+
+    /// Returns the sum of all outputs, saturating at `u64::MAX`
+    pub fn output_value(&self) -> u64 {
+        self.checked_output_value().unwrap_or(u64::MAX)
+    }
+
+    /// Returns the sum of all outputs, None if it overflows
+    pub fn checked_output_value(&self) -> Option<u64> {
+        self.outputs
+            .iter()
+            .try_fold(0u64, |sum, o| sum.checked_add(o.out.value))
+    }
+
+    /// Returns the fee paid by this transaction, None for coinbase transactions
+    /// or if the prevouts are not known.
+    pub fn fee(&self) -> Option<u64> {
+        if self.is_coinbase() {
+            return None;
         }
-        false
+        self.input_value()?.checked_sub(self.output_value())
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        is_coinbase(&self.inputs)
+    }
+}
+
+fn is_coinbase(inputs: &[TxInput]) -> bool {
+    if inputs.len() == 1 {
+        let input = inputs.first().unwrap();
+        return input.outpoint.txid.as_ref() == [0u8; 32] && input.outpoint.index == 0xFFFFFFFF;
     }
+    false
 }
 
 impl fmt::Debug for EvaluatedTx {
@@ -75,18 +161,49 @@ impl fmt::Debug for EvaluatedTx {
 
 impl From<RawTx> for EvaluatedTx {
     fn from(tx: RawTx) -> Self {
-        Self::new(
-            tx.version,
-            tx.in_count,
-            tx.inputs,
-            tx.out_count,
-            tx.outputs,
-            tx.locktime,
-            tx.version_id,
-        )
+        // Classify all inputs, the prevouts are not known at this point.
+        // Only transactions exceeding the inline capacity are worth distributing across threads.
+        let mut inputs = tx.inputs;
+        if is_coinbase(&inputs) {
+            inputs[0].pattern = script::InputPattern::Coinbase;
+        } else if inputs.spilled() {
+            inputs.par_iter_mut().for_each(|i| i.eval_pattern());
+        } else {
+            inputs.iter_mut().for_each(|i| i.eval_pattern());
+        }
+
+        // Evaluate and wrap all outputs to process them later
+        let (version_id, eval_addresses) = (tx.version_id, tx.eval_addresses);
+        let outputs = if tx.outputs.spilled() {
+            // Reuses the heap allocation of the spilled SmallVec
+            tx.outputs
+                .into_vec()
+                .into_par_iter()
+                .map(|o| EvaluatedTxOut::eval_script(o, version_id, eval_addresses))
+                .collect::<Vec<_>>()
+                .into()
+        } else {
+            tx.outputs
+                .into_iter()
+                .map(|o| EvaluatedTxOut::eval_script(o, version_id, eval_addresses))
+                .collect()
+        };
+        EvaluatedTx {
+            version: tx.version,
+            flags: tx.flags,
+            in_count: tx.in_count,
+            inputs,
+            out_count: tx.out_count,
+            outputs,
+            locktime: tx.locktime,
+        }
     }
 }
 
+impl Identified for EvaluatedTx {
+    type Id = TxId;
+}
+
 impl ToRaw for EvaluatedTx {
     fn to_bytes(&self) -> Vec<u8> {
         let mut bytes =
@@ -108,17 +225,50 @@ impl ToRaw for EvaluatedTx {
         bytes.extend_from_slice(&self.locktime.to_le_bytes());
         bytes
     }
+
+    fn to_bytes_with_witness(&self) -> Vec<u8> {
+        if !self.has_witness() {
+            return self.to_bytes();
+        }
+        let mut bytes =
+            Vec::with_capacity((4 + 2 + self.in_count.value + self.out_count.value + 4) as usize);
+
+        // Serialize version, followed by segwit marker and flag
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend_from_slice(&[0x00, 0x01]);
+        // Serialize all TxInputs
+        bytes.extend_from_slice(&self.in_count.to_bytes());
+        for i in &self.inputs {
+            bytes.extend_from_slice(&i.to_bytes());
+        }
+        // Serialize all TxOutputs
+        bytes.extend_from_slice(&self.out_count.to_bytes());
+        for o in &self.outputs {
+            bytes.extend_from_slice(&o.out.to_bytes());
+        }
+        // Serialize witness stack for each input
+        for i in &self.inputs {
+            bytes.extend_from_slice(&VarUint::compact(i.witness.len() as u64).to_bytes());
+            for item in &i.witness {
+                bytes.extend_from_slice(&VarUint::compact(item.len() as u64).to_bytes());
+                bytes.extend_from_slice(item);
+            }
+        }
+        // Serialize locktime
+        bytes.extend_from_slice(&self.locktime.to_le_bytes());
+        bytes
+    }
 }
 
 /// TxOutpoint references an existing transaction output
-#[derive(PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct TxOutpoint {
-    pub txid: sha256d::Hash,
+    pub txid: TxId,
     pub index: u32, // 0-based offset within tx
 }
 
 impl TxOutpoint {
-    pub fn new(txid: sha256d::Hash, index: u32) -> Self {
+    pub fn new(txid: TxId, index: u32) -> Self {
         Self { txid, index }
     }
 }
@@ -147,6 +297,20 @@ pub struct TxInput {
     pub script_len: VarUint,
     pub script_sig: Vec<u8>,
     pub seq_no: u32,
+    // Witness stack items, empty for non-segwit inputs
+    pub witness: Vec<Vec<u8>>,
+    // Resolved output this input spends, only available with undo data
+    pub prevout: Option<SpentOutput>,
+    // Classification of scriptSig and witness
+    pub pattern: script::InputPattern,
+}
+
+impl TxInput {
+    /// Classifies the input, uses the prevout if available
+    pub fn eval_pattern(&mut self) {
+        let prevout = self.prevout.as_ref().map(|p| &p.out.script.pattern);
+        self.pattern = script::eval_input(&self.script_sig, &self.witness, prevout);
+    }
 }
 
 impl ToRaw for TxInput {
@@ -167,6 +331,9 @@ impl fmt::Debug for TxInput {
             .field("script_len", &self.script_len)
             .field("script_sig", &self.script_sig)
             .field("seq_no", &self.seq_no)
+            .field("witness", &self.witness)
+            .field("prevout", &self.prevout)
+            .field("pattern", &self.pattern)
             .finish()
     }
 }
@@ -178,11 +345,13 @@ pub struct EvaluatedTxOut {
 }
 
 impl EvaluatedTxOut {
-    pub fn eval_script(out: TxOutput, version_id: u8) -> EvaluatedTxOut {
-        EvaluatedTxOut {
-            script: script::eval_from_bytes(&out.script_pubkey, version_id),
-            out,
-        }
+    /// Classifies the script, the address is only extracted if `eval_address` is set
+    pub fn eval_script(out: TxOutput, version_id: u8, eval_address: bool) -> EvaluatedTxOut {
+        let script = match eval_address {
+            true => script::eval_from_bytes(&out.script_pubkey, version_id),
+            false => script::eval_pattern_from_bytes(&out.script_pubkey, version_id),
+        };
+        EvaluatedTxOut { script, out }
     }
 }
 
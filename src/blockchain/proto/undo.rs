@@ -0,0 +1,32 @@
+use std::fmt;
+
+use crate::blockchain::proto::tx::EvaluatedTxOut;
+
+/// Holds the spent outputs of all transactions in a block (except the coinbase),
+/// as stored in Bitcoin Core's rev*.dat files.
+/// See https://github.com/bitcoin/bitcoin/blob/master/src/undo.h
+pub struct BlockUndo {
+    pub txs: Vec<TxUndo>,
+}
+
+/// Spent outputs for each input of a single transaction
+pub struct TxUndo {
+    pub prevouts: Vec<SpentOutput>,
+}
+
+/// Output which got spent by an input, along with the height it was created at
+pub struct SpentOutput {
+    pub height: u64,
+    pub is_coinbase: bool,
+    pub out: EvaluatedTxOut,
+}
+
+impl fmt::Debug for SpentOutput {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SpentOutput")
+            .field("height", &self.height)
+            .field("is_coinbase", &self.is_coinbase)
+            .field("out", &self.out.out)
+            .finish()
+    }
+}
@@ -30,6 +30,31 @@ impl VarUint {
         };
         Ok(vint)
     }
+
+    /// Creates a VarUint with the shortest possible encoding for the given value
+    pub fn compact(value: u64) -> VarUint {
+        match value {
+            0x00..=0xfc => VarUint::from(value as u8),
+            0xfd..=0xffff => VarUint::from(value as u16),
+            0x10000..=0xffffffff => VarUint::from(value as u32),
+            _ => VarUint::from(value),
+        }
+    }
+
+    /// Returns the number of bytes of the shortest encoding of the given value, see `compact`
+    pub fn compact_len(value: u64) -> usize {
+        match value {
+            0x00..=0xfc => 1,
+            0xfd..=0xffff => 3,
+            0x10000..=0xffffffff => 5,
+            _ => 9,
+        }
+    }
+
+    /// Returns the number of bytes of the encoding as read
+    pub fn encoded_len(&self) -> usize {
+        self.buf.len()
+    }
 }
 
 impl From<u8> for VarUint {
@@ -142,4 +167,15 @@ mod tests {
         let test = VarUint::read_from(&mut cursor);
         assert_eq!(vec![0xfe, 0x55, 0xa1, 0xae, 0xc6], test.unwrap().to_bytes());
     }
+
+    #[test]
+    fn test_varuint_compact() {
+        assert_eq!(vec![0xfc], VarUint::compact(0xfc).to_bytes());
+        assert_eq!(vec![0xfd, 0xfd, 0x00], VarUint::compact(0xfd).to_bytes());
+        assert_eq!(
+            vec![0xfe, 0x00, 0x00, 0x01, 0x00],
+            VarUint::compact(0x10000).to_bytes()
+        );
+        assert_eq!(9, VarUint::compact(0x100000000).to_bytes().len());
+    }
 }
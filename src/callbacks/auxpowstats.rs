@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use bitcoin::hashes::Hash;
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::{AuxPowExtension, Block};
+use crate::blockchain::proto::{BlockHash, ToRaw};
+use crate::callbacks::common::extract_miner_tag;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Shows statistics about merged mining (AuxPoW) for coins like Namecoin or Dogecoin,
+/// and dumps the parent chain data of each merged mined block to a csv file.
+pub struct AuxPowStats {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    start_height: u64,
+    n_blocks: u64,
+    n_auxpow_blocks: u64,
+    first_auxpow_height: Option<u64>,
+
+    /// Blocks per miner tag found in the parent coinbase
+    miners: HashMap<String, u64>,
+    /// Blocks per number of chains merged mined with the same parent block
+    chain_tree_sizes: BTreeMap<u64, u64>,
+    /// (blocks, auxpow blocks) per month
+    monthly: BTreeMap<String, (u64, u64)>,
+}
+
+impl AuxPowStats {
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "AuxPowStats:")?;
+        writeln!(buffer, "   -> blocks:\t\t\t{}", self.n_blocks)?;
+        writeln!(
+            buffer,
+            "   -> auxpow blocks:\t\t{} ({:.2}%)",
+            self.n_auxpow_blocks,
+            self.n_auxpow_blocks as f64 / self.n_blocks.max(1) as f64 * 100.00
+        )?;
+        match self.first_auxpow_height {
+            Some(height) => writeln!(buffer, "   -> first auxpow block:\t#{}", height)?,
+            None => writeln!(buffer, "   -> first auxpow block:\tnone")?,
+        }
+        Ok(())
+    }
+
+    fn print_parent_chains(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "Merged mined chains per parent block:")?;
+        for (size, count) in &self.chain_tree_sizes {
+            writeln!(
+                buffer,
+                "   -> up to {} chains: {} ({:.2}%)",
+                size,
+                count,
+                *count as f64 / self.n_auxpow_blocks.max(1) as f64 * 100.00
+            )?;
+        }
+        Ok(())
+    }
+
+    fn print_miners(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let mut miners: Vec<(&String, &u64)> = self.miners.iter().collect();
+        miners.sort_unstable_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+        writeln!(buffer, "Top parent chain miners:")?;
+        for (tag, count) in miners.iter().take(25) {
+            writeln!(
+                buffer,
+                "   -> {}: {} ({:.2}%)",
+                tag,
+                count,
+                **count as f64 / self.n_auxpow_blocks.max(1) as f64 * 100.00
+            )?;
+        }
+        Ok(())
+    }
+
+    fn print_adoption(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "AuxPoW adoption per month:")?;
+        for (month, (blocks, auxpow_blocks)) in &self.monthly {
+            writeln!(
+                buffer,
+                "   -> {}: {}/{} ({:.2}%)",
+                month,
+                auxpow_blocks,
+                blocks,
+                *auxpow_blocks as f64 / *blocks as f64 * 100.00
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl AuxPowExtension {
+    fn as_csv(&self, block: &Block, block_height: u64, miner: &str) -> String {
+        // (height, @hash, @parentHash, parentTimestamp, chainTreeSize, miner)
+        format!(
+            "{};{};{};{};{};{}\n",
+            &block_height,
+            &block.header.hash,
+            &BlockHash::hash(&self.parent_block.to_bytes()),
+            &self.parent_block.timestamp,
+            &self.chain_tree_size(),
+            &miner
+        )
+    }
+
+    /// Returns the maximum number of chains merged mined along with this block
+    fn chain_tree_size(&self) -> u64 {
+        1 << self.blockchain_branch.hashes.len()
+    }
+}
+
+impl Callback for AuxPowStats {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("auxpowstats")
+            .about("Shows merged mining stats and dumps the parent chain data to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = AuxPowStats {
+            writer: output.open("auxpow")?,
+            output,
+            start_height: 0,
+            n_blocks: 0,
+            n_auxpow_blocks: 0,
+            first_auxpow_height: None,
+            miners: HashMap::new(),
+            chain_tree_sizes: BTreeMap::new(),
+            monthly: BTreeMap::new(),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing auxpowstats with dump folder: {} ...", &self.output);
+        self.writer
+            .write_all("height;hash;parentHash;parentTimestamp;chainTreeSize;miner\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.n_blocks += 1;
+        let month = utils::month_of(block.header.value.timestamp);
+        let monthly = self.monthly.entry(month).or_insert((0, 0));
+        monthly.0 += 1;
+
+        let aux_pow = match &block.aux_pow_extension {
+            Some(aux_pow) => aux_pow,
+            None => return Ok(()),
+        };
+        monthly.1 += 1;
+        self.n_auxpow_blocks += 1;
+        self.first_auxpow_height.get_or_insert(block_height);
+        *self
+            .chain_tree_sizes
+            .entry(aux_pow.chain_tree_size())
+            .or_insert(0) += 1;
+
+        let miner = aux_pow
+            .coinbase_tx
+            .inputs
+            .first()
+            .and_then(|input| extract_miner_tag(&input.script_sig))
+            .unwrap_or_default();
+        self.writer
+            .write_all(aux_pow.as_csv(block, block_height, &miner).as_bytes())?;
+        if !miner.is_empty() {
+            *self.miners.entry(miner).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        writeln!(&mut buffer)?;
+        self.print_parent_chains(&mut buffer)?;
+        writeln!(&mut buffer)?;
+        self.print_miners(&mut buffer)?;
+        writeln!(&mut buffer)?;
+        self.print_adoption(&mut buffer)?;
+        info!(target: "auxpowstats", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Dumps every change of the balance of the given addresses along with the running balance,
+/// one row per transaction and address.
+/// Only the outputs of these addresses are tracked, so the memory usage stays small.
+pub struct BalanceHistory {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    addresses: Vec<String>,
+    // Maps the addresses to their index in `addresses`
+    ids: HashMap<String, usize>,
+    // Address index and value of the unspent outputs of the given addresses
+    unspents: HashMap<TxOutpoint, (usize, u64)>,
+    // Current balance by address index
+    balances: Vec<u64>,
+
+    start_height: u64,
+    n_changes: u64,
+}
+
+impl BalanceHistory {
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "BalanceHistory:")?;
+        writeln!(buffer, "   -> changes:\t\t{}", self.n_changes)?;
+        for (address, balance) in self.addresses.iter().zip(&self.balances) {
+            writeln!(buffer, "   -> {}:\t{:.8}", address, *balance as f64 * 1E-8)?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for BalanceHistory {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("balancehistory")
+            .about("Dumps each change of the balance of the given addresses to a CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv files")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("address")
+                    .help("Addresses to track")
+                    .index(2)
+                    .num_args(1..)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let mut addresses: Vec<String> = Vec::new();
+        for address in matches.get_many::<String>("address").unwrap() {
+            if !addresses.contains(address) {
+                addresses.push(address.clone());
+            }
+        }
+        let ids = addresses
+            .iter()
+            .enumerate()
+            .map(|(id, address)| (address.clone(), id))
+            .collect();
+        let cb = BalanceHistory {
+            writer: output.open("balancehistory")?,
+            output,
+            balances: vec![0; addresses.len()],
+            addresses,
+            ids,
+            unspents: HashMap::new(),
+            start_height: 0,
+            n_changes: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing balancehistory for {} addresses with dump folder: {} ...",
+              self.addresses.len(), &self.output);
+        if block_height > 0 {
+            warn!(target: "callback", "Balances are only tracked from block {}.", block_height);
+        }
+        self.writer
+            .write_all("height;txid;address;delta;balance\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_transaction(
+        &mut self,
+        tx: &Hashed<EvaluatedTx>,
+        block_height: u64,
+        _: usize,
+    ) -> OpResult<()> {
+        // Spent and received value by address index
+        let mut changes: BTreeMap<usize, (u64, u64)> = BTreeMap::new();
+        for input in &tx.value.inputs {
+            if let Some((id, value)) = self.unspents.remove(&input.outpoint) {
+                changes.entry(id).or_default().0 += value;
+            }
+        }
+        for (i, output) in tx.value.outputs.iter().enumerate() {
+            let id = match output.script.address.as_ref().and_then(|a| self.ids.get(a)) {
+                Some(id) => *id,
+                None => continue,
+            };
+            self.unspents
+                .insert(TxOutpoint::new(tx.hash, i as u32), (id, output.out.value));
+            changes.entry(id).or_default().1 += output.out.value;
+        }
+
+        for (id, (spent, received)) in changes {
+            let balance = &mut self.balances[id];
+            *balance = *balance + received - spent;
+            // (height, txid, address, delta, balance)
+            self.writer.write_all(
+                format!(
+                    "{};{};{};{};{}\n",
+                    block_height,
+                    &tx.hash,
+                    self.addresses[id],
+                    received as i64 - spent as i64,
+                    balance
+                )
+                .as_bytes(),
+            )?;
+            self.n_changes += 1;
+        }
+        Ok(())
+    }
+
+    fn on_block(&mut self, _: &Block, _: u64) -> OpResult<()> {
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "balancehistory", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+}
@@ -1,32 +1,232 @@
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{BufWriter, Write};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::Write;
 use std::path::PathBuf;
 
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 
 use crate::blockchain::proto::block::Block;
-use crate::callbacks::{common, Callback};
-use crate::errors::OpResult;
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::common::ImmatureOutputs;
+use crate::callbacks::labels::Labels;
+use crate::callbacks::sink::Output;
+use crate::callbacks::sorter::RowSorter;
+use crate::callbacks::{common, warmstart, Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
-/// Dumps all addresses with non-zero balance in a csv file
+/// Script of an address as seen in its first output, see `--include-script-type` and `--include-script`
+struct AddressScript {
+    pattern: ScriptPattern,
+    // Only kept for outputs without address, their key is the hash of the script
+    script: Option<Box<[u8]>>,
+}
+
+/// Unspent outputs of an address as of a dump
+#[derive(Default)]
+struct AddressState {
+    balance: u64,
+    immature: u64,
+    n_unspents: u64,
+    last_received: u64,
+}
+
+/// Dumps all addresses with non-zero balance in a csv file.
+/// Outputs without address are grouped by the sha256 of their script_pubkey instead.
 pub struct Balances {
-    dump_folder: PathBuf,
-    writer: BufWriter<File>,
+    output: Output,
+    labels: Option<Labels>,
+    // Sorted heights to dump the balances at, dumps only at the end if empty
+    snapshot_heights: Vec<u64>,
+    // Only dumps the addresses with the highest balances if set
+    top: Option<usize>,
+    // Sorts the rows by address if set, the ranked list is always sorted
+    sorter: Option<RowSorter>,
+    immature: ImmatureOutputs,
+    coinbase_maturity: u64,
+    // Optional columns
+    include_script_type: bool,
+    include_script: bool,
+    include_utxo_count: bool,
+    include_last_activity: bool,
+    // Scripts by address id, only tracked for `--include-script-type` and `--include-script`
+    scripts: HashMap<u64, AddressScript>,
+    // Height of the last spend by address id, only tracked for `--include-last-activity`
+    last_spent: HashMap<u64, u64>,
 
-    // key: txid + index
-    unspents: HashMap<Vec<u8>, common::UnspentValue>,
+    unspents: HashMap<TxOutpoint, common::UnspentValue>,
+    interner: common::AddressInterner,
+    // Range of the unspent outputs loaded with `--load-unspent`
+    loaded_range: Option<(u64, u64)>,
 
     start_height: u64,
-    end_height: u64,
 }
 
 impl Balances {
-    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
-        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    /// Returns the last height balances are dumped at, if `--snapshot-height` was specified.
+    /// There is no need to parse any blocks beyond.
+    pub fn last_snapshot_height(&self) -> Option<u64> {
+        self.snapshot_heights.last().copied()
+    }
+
+    /// Returns the last height of the unspent outputs loaded with `--load-unspent`.
+    /// Parsing continues at the next block.
+    pub fn loaded_height(&self) -> Option<u64> {
+        self.loaded_range.map(|(_, end)| end)
+    }
+
+    fn tracks_scripts(&self) -> bool {
+        self.include_script_type || self.include_script
+    }
+
+    /// Remembers the script of each new address in the transaction
+    fn track_scripts(&mut self, tx: &Hashed<EvaluatedTx>) {
+        for output in &tx.value.outputs {
+            let key = match common::output_key(output) {
+                Some(key) => key,
+                None => continue,
+            };
+            let id = self.interner.intern(&key);
+            self.scripts.entry(id).or_insert_with(|| AddressScript {
+                pattern: output.script.pattern.clone(),
+                script: match output.script.address {
+                    Some(_) => None,
+                    None => Some(output.out.script_pubkey.clone().into_boxed_slice()),
+                },
+            });
+        }
+    }
+
+    /// Dumps the balances of all addresses as of the given height
+    fn dump(&mut self, block_height: u64) -> OpResult<()> {
+        // Collect balances for each address id
+        let mut states: HashMap<u64, AddressState> = HashMap::new();
+        for unspent in self.unspents.values() {
+            let mature = unspent.is_mature(block_height, self.coinbase_maturity);
+            if !mature && self.immature == ImmatureOutputs::Exclude {
+                continue;
+            }
+            let state = states.entry(unspent.address_id).or_default();
+            state.balance += unspent.value;
+            // Immature coinbase value per address, only written with `--immature flag`
+            if !mature {
+                state.immature += unspent.value;
+            }
+            state.n_unspents += 1;
+            state.last_received = state.last_received.max(unspent.block_height);
+        }
+        let balances: HashMap<u64, u64> = states
+            .iter()
+            .map(|(id, state)| (*id, state.balance))
+            .collect();
+
+        let (mut writer, header, rows) = match self.top {
+            Some(n) => {
+                let rows = top_balances(&balances, n);
+                (
+                    self.output.open("richlist")?,
+                    "rank;address;balance;scriptHash",
+                    rows,
+                )
+            }
+            None => {
+                let rows = balances.into_iter().collect();
+                (
+                    self.output.open("balances")?,
+                    "address;balance;scriptHash",
+                    rows,
+                )
+            }
+        };
+        let mut header = String::from(header);
+        if self.immature == ImmatureOutputs::Flag {
+            header.push_str(";immature");
+        }
+        if self.include_script_type {
+            header.push_str(";scriptType");
+        }
+        if self.include_script {
+            header.push_str(";script");
+        }
+        if self.include_utxo_count {
+            header.push_str(";utxos");
+        }
+        if self.include_last_activity {
+            header.push_str(";lastActivity");
+        }
+        if self.labels.is_some() {
+            header.push_str(";label");
+        }
+        writer.write_all(format!("{}\n", header).as_bytes())?;
+
+        for (i, (id, balance)) in rows.iter().enumerate() {
+            let key = self.interner.address(*id);
+            let (address, script_hash) = common::key_columns(key);
+            let mut line = match self.top {
+                Some(_) => format!("{};{};{};{}", i + 1, address, balance, script_hash),
+                None => format!("{};{};{}", address, balance, script_hash),
+            };
+            let state = &states[id];
+            if self.immature == ImmatureOutputs::Flag {
+                line.push_str(&format!(";{}", state.immature));
+            }
+            // Outputs loaded with `--load-unspent` have no script
+            let script = self.scripts.get(id);
+            if self.include_script_type {
+                let pattern = script.map(|s| s.pattern.to_string()).unwrap_or_default();
+                line.push_str(&format!(";{}", pattern));
+            }
+            if self.include_script {
+                let hex = script
+                    .and_then(|s| s.script.as_deref())
+                    .map(utils::arr_to_hex)
+                    .unwrap_or_default();
+                line.push_str(&format!(";{}", hex));
+            }
+            if self.include_utxo_count {
+                line.push_str(&format!(";{}", state.n_unspents));
+            }
+            if self.include_last_activity {
+                let last_spent = self.last_spent.get(id).copied().unwrap_or(0);
+                line.push_str(&format!(";{}", state.last_received.max(last_spent)));
+            }
+            if let Some(labels) = &self.labels {
+                line.push_str(&format!(";{}", labels.get(address).unwrap_or_default()));
+            }
+            line.push('\n');
+            match &mut self.sorter {
+                Some(sorter) if self.top.is_none() => sorter.push(String::from(key), line)?,
+                _ => writer.write_all(line.as_bytes())?,
+            }
+        }
+        if let Some(sorter) = &mut self.sorter {
+            sorter.write_to(&mut writer)?;
+        }
+        writer.finish(self.start_height, block_height)?;
+
+        info!(target: "callback", "Dumped {} addresses at height {}.", rows.len(), block_height);
+        Ok(())
     }
 }
 
+/// Returns the `n` address ids with the highest balance in descending order.
+/// Only a min-heap of `n` entries is kept, ties are broken by the lower address id.
+fn top_balances(balances: &HashMap<u64, u64>, n: usize) -> Vec<(u64, u64)> {
+    let mut heap: BinaryHeap<Reverse<(u64, Reverse<u64>)>> = BinaryHeap::with_capacity(n + 1);
+    for (id, balance) in balances {
+        heap.push(Reverse((*balance, Reverse(*id))));
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((balance, Reverse(id)))| (id, balance))
+        .collect()
+}
+
 impl Callback for Balances {
     fn build_subcommand() -> Command
     where
@@ -42,26 +242,118 @@ impl Callback for Balances {
                     .index(1)
                     .required(true),
             )
+            .arg(Labels::build_arg())
+            .arg(common::AddressInterner::build_arg())
+            .arg(warmstart::build_arg())
+            .arg(RowSorter::build_arg())
+            .arg(ImmatureOutputs::build_arg())
+            .arg(
+                Arg::new("snapshot-height")
+                    .long("snapshot-height")
+                    .value_name("HEIGHT")
+                    .value_delimiter(',')
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Dumps the balances as of the given heights into separate files (e.g. 210000,420000), parsing stops at the last one"),
+            )
+            .arg(
+                Arg::new("include-script-type")
+                    .long("include-script-type")
+                    .action(ArgAction::SetTrue)
+                    .help("Appends the script type of each address"),
+            )
+            .arg(
+                Arg::new("include-script")
+                    .long("include-script")
+                    .action(ArgAction::SetTrue)
+                    .help("Appends the script hex of outputs without address (e.g. non-standard scripts)"),
+            )
+            .arg(
+                Arg::new("include-utxo-count")
+                    .long("include-utxo-count")
+                    .action(ArgAction::SetTrue)
+                    .help("Appends the number of unspent outputs of each address"),
+            )
+            .arg(
+                Arg::new("include-last-activity")
+                    .long("include-last-activity")
+                    .action(ArgAction::SetTrue)
+                    .help("Appends the height of the last block each address received or spent in"),
+            )
+            .arg(
+                Arg::new("top")
+                    .long("top")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("Only dumps the N addresses with the highest balance as ranked list"),
+            )
     }
 
-    fn new(matches: &ArgMatches) -> OpResult<Self>
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
     where
         Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let output = Output::from_matches(matches)?;
+        let mut snapshot_heights: Vec<u64> = matches
+            .get_many::<u64>("snapshot-height")
+            .map(|heights| heights.copied().collect())
+            .unwrap_or_default();
+        snapshot_heights.sort_unstable();
+        snapshot_heights.dedup();
+        if let (Output::Stdout(_), 2..) = (&output, snapshot_heights.len()) {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("Multiple snapshots are written to separate files and need a folder"));
+        }
+        let mut unspents = HashMap::with_capacity(10000000);
+        let mut interner = common::AddressInterner::from_matches(matches)?;
+        let loaded_range = match matches.get_one::<String>("load-unspent") {
+            Some(path) => Some(warmstart::load_unspents(
+                &PathBuf::from(path),
+                &mut unspents,
+                &mut interner,
+            )?),
+            None => None,
+        };
         let cb = Balances {
-            dump_folder: PathBuf::from(dump_folder),
-            writer: Balances::create_writer(4000000, dump_folder.join("balances.csv.tmp"))?,
-            unspents: HashMap::with_capacity(10000000),
+            output,
+            labels: Labels::from_matches(matches)?,
+            snapshot_heights,
+            top: matches.get_one::<usize>("top").copied(),
+            sorter: RowSorter::from_matches(matches),
+            immature: ImmatureOutputs::from_matches(matches),
+            coinbase_maturity: context.coin.coinbase_maturity,
+            include_script_type: matches.get_flag("include-script-type"),
+            include_script: matches.get_flag("include-script"),
+            include_utxo_count: matches.get_flag("include-utxo-count"),
+            include_last_activity: matches.get_flag("include-last-activity"),
+            scripts: HashMap::new(),
+            last_spent: HashMap::new(),
+            unspents,
+            interner,
+            loaded_range,
             start_height: 0,
-            end_height: 0,
         };
         Ok(cb)
     }
 
     fn on_start(&mut self, block_height: u64) -> OpResult<()> {
         self.start_height = block_height;
-        info!(target: "callback", "Executing balances with dump folder: {} ...", &self.dump_folder.display());
+        if let Some((start, end)) = self.loaded_range {
+            if block_height != end + 1 {
+                let msg = format!(
+                    "The loaded unspent outputs end at height {}, parsing has to start at {} instead of {}",
+                    end,
+                    end + 1,
+                    block_height
+                );
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+            }
+            // The balances cover the range of the loaded dump as well
+            self.start_height = start;
+        }
+        info!(target: "callback", "Executing balances with dump folder: {} ...", &self.output);
+        for height in self.snapshot_heights.iter().filter(|h| **h < block_height) {
+            warn!(target: "callback", "Snapshot height {} is below the start height, skipping", height);
+        }
         Ok(())
     }
 
@@ -74,40 +366,46 @@ impl Callback for Balances {
     ///   * address
     fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
         for tx in &block.txs {
+            if self.include_last_activity {
+                for input in &tx.value.inputs {
+                    if let Some(unspent) = self.unspents.get(&input.outpoint) {
+                        self.last_spent.insert(unspent.address_id, block_height);
+                    }
+                }
+            }
             common::remove_unspents(tx, &mut self.unspents);
-            common::insert_unspents(tx, block_height, &mut self.unspents);
+            common::insert_unspents(tx, block_height, &mut self.unspents, &mut self.interner);
+            if self.tracks_scripts() {
+                self.track_scripts(tx);
+            }
+        }
+        if self.snapshot_heights.binary_search(&block_height).is_ok() {
+            self.dump(block_height)?;
         }
         Ok(())
     }
 
     fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
-        self.end_height = block_height;
-
-        self.writer
-            .write_all(format!("{};{}\n", "address", "balance").as_bytes())?;
-
-        // Collect balances for each address
-        let mut balances: HashMap<&str, u64> = HashMap::new();
-        for unspent in self.unspents.values() {
-            let entry = balances.entry(&unspent.address).or_insert(0);
-            *entry += unspent.value
+        if self.snapshot_heights.is_empty() {
+            self.dump(block_height)?;
         }
-
-        for (address, balance) in balances.iter() {
-            self.writer
-                .write_all(format!("{};{}\n", address, balance).as_bytes())?;
+        for height in self.snapshot_heights.iter().filter(|h| **h > block_height) {
+            warn!(target: "callback", "Snapshot height {} is beyond the last parsed block {}, skipping", height, block_height);
         }
+        self.interner.persist()?;
+        info!(target: "callback", "Done.");
+        Ok(())
+    }
+}
 
-        fs::rename(
-            self.dump_folder.as_path().join("balances.csv.tmp"),
-            self.dump_folder.as_path().join(format!(
-                "balances-{}-{}.csv",
-                self.start_height, self.end_height
-            )),
-        )
-        .expect("Unable to rename tmp file!");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        info!(target: "callback", "Done.\nDumped {} addresses.", balances.len());
-        Ok(())
+    #[test]
+    fn test_top_balances() {
+        let balances = HashMap::from([(0, 5), (1, 20), (2, 10), (3, 20), (4, 1)]);
+        assert_eq!(top_balances(&balances, 3), [(1, 20), (3, 20), (2, 10)]);
+        assert_eq!(top_balances(&balances, 10).len(), 5);
     }
 }
@@ -0,0 +1,151 @@
+use std::fs;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::logger;
+use crate::errors::OpResult;
+
+/// Parses the range without doing anything with the blocks and reports the throughput,
+/// to compare the performance of releases and machines. The range is selected with
+/// `--start` and `--end` as usual, e.g. `-s 600000 -e 610000`.
+pub struct Bench {
+    wants_addresses: bool,
+    started_at: Option<Instant>,
+
+    n_blocks: u64,
+    n_txs: u64,
+    n_inputs: u64,
+    n_outputs: u64,
+    n_bytes: u64,
+}
+
+/// Returns the peak resident memory of the process in bytes, only available on Linux
+fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+impl Bench {
+    fn write_report<W: Write>(&self, writer: &mut W, secs: f64) -> io::Result<()> {
+        let per_sec = |n: f64| n / secs.max(f64::EPSILON);
+        let mib = self.n_bytes as f64 / (1024.0 * 1024.0);
+        let peak = peak_memory().map_or(String::new(), |bytes| {
+            format!("{:.1}", bytes as f64 / (1024.0 * 1024.0))
+        });
+        writeln!(
+            writer,
+            "blocks;txs;inputs;outputs;bytes;seconds;blocksPerSec;txsPerSec;mibPerSec;peakMemoryMib"
+        )?;
+        writeln!(
+            writer,
+            "{};{};{};{};{};{:.3};{:.2};{:.2};{:.2};{}",
+            self.n_blocks,
+            self.n_txs,
+            self.n_inputs,
+            self.n_outputs,
+            self.n_bytes,
+            secs,
+            per_sec(self.n_blocks as f64),
+            per_sec(self.n_txs as f64),
+            per_sec(mib),
+            peak
+        )
+    }
+}
+
+impl Callback for Bench {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("bench")
+            .about("Parses the range without a callback and reports blocks/s, txs/s, MiB/s and the peak memory")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("skip-addresses")
+                    .long("skip-addresses")
+                    .action(ArgAction::SetTrue)
+                    .help("Only classifies the output scripts, like callbacks which don't need addresses"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        // stdout is reserved for the report
+        logger::redirect_to_stderr();
+        Ok(Bench {
+            wants_addresses: !matches.get_flag("skip-addresses"),
+            started_at: None,
+            n_blocks: 0,
+            n_txs: 0,
+            n_inputs: 0,
+            n_outputs: 0,
+            n_bytes: 0,
+        })
+    }
+
+    fn on_start(&mut self, _: u64) -> OpResult<()> {
+        info!(target: "callback", "Executing bench ...");
+        self.started_at = Some(Instant::now());
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, _: u64) -> OpResult<()> {
+        self.n_blocks += 1;
+        self.n_bytes += block.size as u64;
+        for tx in &block.txs {
+            self.n_txs += 1;
+            self.n_inputs += tx.value.inputs.len() as u64;
+            self.n_outputs += tx.value.outputs.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        let secs = self
+            .started_at
+            .map_or(0.0, |started_at| started_at.elapsed().as_secs_f64());
+        let mut stdout = io::stdout().lock();
+        self.write_report(&mut stdout, secs)?;
+        stdout.flush()?;
+        info!(target: "callback", "Done.\nParsed {} blocks up to height {} in {:.2}s.", self.n_blocks, block_height, secs);
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        self.wants_addresses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let bench = Bench {
+            wants_addresses: true,
+            started_at: None,
+            n_blocks: 10,
+            n_txs: 40,
+            n_inputs: 50,
+            n_outputs: 60,
+            n_bytes: 2 * 1024 * 1024,
+        };
+        let mut out = Vec::new();
+        bench.write_report(&mut out, 2.0).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("10;40;50;60;2097152;2.000;5.00;20.00;1.00;"));
+    }
+}
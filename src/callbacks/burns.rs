@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::Script;
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::{EvaluatedScript, ScriptPattern};
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Returns why the given output is provably unspendable, if it is.
+/// Known burn addresses of the coin take precedence over the generic patterns.
+pub(crate) fn classify_burn(
+    script: &EvaluatedScript,
+    script_pubkey: &[u8],
+    burn_addresses: &[&str],
+) -> Option<&'static str> {
+    if let Some(address) = &script.address {
+        if burn_addresses.contains(&address.as_str()) {
+            return Some("address");
+        }
+    }
+    match script.pattern {
+        ScriptPattern::OpReturn(_) => return Some("opreturn"),
+        ScriptPattern::Unspendable => return Some("unspendable"),
+        _ => {}
+    }
+    if has_zero_hash(&script.pattern, script_pubkey) {
+        return Some("zerohash");
+    }
+    if ends_with_false(script_pubkey) {
+        return Some("opfalse");
+    }
+    None
+}
+
+/// Checks if the script pays to a hash consisting of zeros only.
+/// Nobody knows a preimage of such a hash, e.g. 1111111111111111111114oLvT2 on Bitcoin.
+fn has_zero_hash(pattern: &ScriptPattern, script_pubkey: &[u8]) -> bool {
+    let hash = match pattern {
+        ScriptPattern::Pay2PublicKeyHash => script_pubkey.get(3..23),
+        ScriptPattern::Pay2ScriptHash | ScriptPattern::Pay2WitnessPublicKeyHash => {
+            script_pubkey.get(2..22)
+        }
+        ScriptPattern::Pay2WitnessScriptHash => script_pubkey.get(2..34),
+        _ => None,
+    };
+    matches!(hash, Some(hash) if hash.iter().all(|b| *b == 0))
+}
+
+/// Checks if the last opcode of the script is OP_FALSE,
+/// which always leaves false on the stack and makes the script fail.
+fn ends_with_false(script_pubkey: &[u8]) -> bool {
+    let mut last = None;
+    for instruction in Script::from_bytes(script_pubkey).instructions() {
+        match instruction {
+            Ok(instruction) => last = Some(instruction),
+            Err(_) => return false,
+        }
+    }
+    matches!(last, Some(Instruction::PushBytes(bytes)) if bytes.is_empty())
+}
+
+/// Dumps provably unspendable outputs and accumulates the burned value
+pub struct Burns {
+    output: Output,
+    writer: Box<dyn Sink>,
+    coin_name: String,
+    burn_addresses: &'static [&'static str],
+
+    // (outputs, value) by reason
+    burned: HashMap<&'static str, (u64, u64)>,
+    total_burned: u64,
+    n_zero_value: u64,
+    start_height: u64,
+}
+
+impl Burns {
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "Burns ({}):", self.coin_name)?;
+        writeln!(
+            buffer,
+            "   -> total burned:\t\t{:.8}",
+            self.total_burned as f64 * 1E-8
+        )?;
+        let mut burned: Vec<(&&str, &(u64, u64))> = self.burned.iter().collect();
+        burned.sort_unstable_by(|a, b| b.1 .1.cmp(&a.1 .1).then(a.0.cmp(b.0)));
+        for (reason, (n_outputs, value)) in burned {
+            writeln!(
+                buffer,
+                "   -> {}:\t\t\t{:.8} in {} outputs",
+                reason,
+                *value as f64 * 1E-8,
+                n_outputs
+            )?;
+        }
+        writeln!(buffer, "   -> zero-value outputs:\t{}", self.n_zero_value)?;
+        Ok(())
+    }
+}
+
+impl Callback for Burns {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("burns")
+            .about("Dumps outputs with value sent to provably unspendable scripts to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = Burns {
+            writer: output.open("burns")?,
+            output,
+            coin_name: context.coin.name.clone(),
+            burn_addresses: context.coin.burn_addresses,
+            burned: HashMap::new(),
+            total_burned: 0,
+            n_zero_value: 0,
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing burns with dump folder: {} ...", &self.output);
+        self.writer
+            .write_all("height;txid;indexOut;value;reason;address;totalBurned\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                let value = output.out.value;
+                if value == 0 {
+                    // Value-less OP_RETURN outputs are not worth counting
+                    if !matches!(output.script.pattern, ScriptPattern::OpReturn(_)) {
+                        self.n_zero_value += 1;
+                    }
+                    continue;
+                }
+                let reason = match classify_burn(
+                    &output.script,
+                    &output.out.script_pubkey,
+                    self.burn_addresses,
+                ) {
+                    Some(reason) => reason,
+                    None => continue,
+                };
+
+                let entry = self.burned.entry(reason).or_insert((0, 0));
+                entry.0 += 1;
+                entry.1 += value;
+                self.total_burned += value;
+                // (height, @txid, indexOut, value, reason, address, totalBurned)
+                self.writer.write_all(
+                    format!(
+                        "{};{};{};{};{};{};{}\n",
+                        block_height,
+                        &tx.hash,
+                        i,
+                        value,
+                        reason,
+                        output.script.address.as_deref().unwrap_or_default(),
+                        self.total_burned
+                    )
+                    .as_bytes(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "burns", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::proto::script::eval_from_bytes_bitcoin;
+
+    #[test]
+    fn test_classify_burn() {
+        let classify = |script: &[u8], burn_addresses: &[&str]| {
+            classify_burn(
+                &eval_from_bytes_bitcoin(script, 0x00),
+                script,
+                burn_addresses,
+            )
+        };
+
+        // P2PKH of 1111111111111111111114oLvT2
+        let mut zero_p2pkh = vec![0x76, 0xa9, 0x14];
+        zero_p2pkh.extend([0x00; 20]);
+        zero_p2pkh.extend([0x88, 0xac]);
+        assert_eq!(classify(&zero_p2pkh, &[]), Some("zerohash"));
+        assert_eq!(
+            classify(&zero_p2pkh, &["1111111111111111111114oLvT2"]),
+            Some("address")
+        );
+
+        assert_eq!(classify(&[0x6a, 0x01, 0xff], &[]), Some("opreturn"));
+        // OP_TRUE OP_FALSE
+        assert_eq!(classify(&[0x51, 0x00], &[]), Some("opfalse"));
+        // Push of a single zero byte is not OP_FALSE
+        assert_eq!(classify(&[0x51, 0x01, 0x00], &[]), None);
+
+        let mut p2wpkh = vec![0x00, 0x14];
+        p2wpkh.extend([0x01; 20]);
+        assert_eq!(classify(&p2wpkh, &[]), None);
+    }
+}
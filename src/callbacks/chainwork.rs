@@ -0,0 +1,125 @@
+use std::io::{self, Write};
+
+use bitcoin::pow::Work;
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Dumps the work and accumulated chain work of each block, comparable to the `chainwork`
+/// reported by bitcoind's `getblockheader`. Only the block headers are read.
+pub struct ChainWork {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    start_height: u64,
+    n_blocks: u64,
+    n_unknown: u64,
+    last: Option<(u64, Work)>,
+}
+
+impl ChainWork {
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "ChainWork:")?;
+        writeln!(buffer, "   -> blocks:\t\t{}", self.n_blocks)?;
+        writeln!(buffer, "   -> unknown:\t\t{}", self.n_unknown)?;
+        if let Some((height, chain_work)) = self.last {
+            writeln!(
+                buffer,
+                "   -> chain work:\t{} (2^{:.2}) at height {}",
+                utils::work_to_hex(chain_work),
+                chain_work.log2(),
+                height
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for ChainWork {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("chainwork")
+            .about("Dumps the work and accumulated chain work per block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = ChainWork {
+            writer: output.open("chainwork")?,
+            output,
+            start_height: 0,
+            n_blocks: 0,
+            n_unknown: 0,
+            last: None,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing chainwork with dump folder: {} ...", &self.output);
+        self.writer
+            .write_all("height;hash;time;bits;difficulty;work;chainWork\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let header = &block.header.value;
+        let chain_work = match block.chain_work {
+            Some(chain_work) => {
+                self.last = Some((block_height, chain_work));
+                utils::work_to_hex(chain_work)
+            }
+            None => {
+                self.n_unknown += 1;
+                String::new()
+            }
+        };
+        // (height, hash, time, bits, difficulty, work, chainWork)
+        self.writer.write_all(
+            format!(
+                "{};{};{};{:08x};{:.8};{};{}\n",
+                block_height,
+                &block.header.hash,
+                header.timestamp,
+                header.bits,
+                utils::bits_to_difficulty(header.bits),
+                utils::work_to_hex(utils::bits_to_work(header.bits)),
+                chain_work
+            )
+            .as_bytes(),
+        )?;
+        self.n_blocks += 1;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "chainwork", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn headers_only(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,181 @@
+use crate::blockchain::proto::script::{InputPattern, ScriptPattern};
+use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxOut, TxInput};
+
+/// Outputs with a value that is a multiple of this are considered round (0.0001 BTC)
+const ROUND_UNIT: u64 = 10_000;
+
+/// Guesses the change output of a transaction by round payment amounts.
+/// Payments are usually round numbers while the change absorbs the fee,
+/// so if all but one output are round, the remaining one is most likely the change.
+pub fn by_round_amount(tx: &EvaluatedTx) -> Option<usize> {
+    let outputs = spendable_outputs(tx);
+    if outputs.len() < 2 {
+        return None;
+    }
+    let mut non_round = outputs
+        .iter()
+        .filter(|(_, o)| !o.out.value.is_multiple_of(ROUND_UNIT));
+    match (non_round.next(), non_round.next()) {
+        (Some((index, _)), None) => Some(*index),
+        _ => None,
+    }
+}
+
+/// Guesses the change output of a transaction by script type.
+/// Wallets usually send the change to the same script type they spend from,
+/// so if all inputs share a type and exactly one output matches it, that one is most likely the change.
+pub fn by_script_type(tx: &EvaluatedTx) -> Option<usize> {
+    let outputs = spendable_outputs(tx);
+    if outputs.len() < 2 {
+        return None;
+    }
+    let mut input_types = tx.inputs.iter().map(spent_type);
+    let input_type = input_types.next()??;
+    if !input_types.all(|t| t.as_ref() == Some(&input_type)) {
+        return None;
+    }
+    let mut matching = outputs
+        .iter()
+        .filter(|(_, o)| o.script.pattern == input_type);
+    match (matching.next(), matching.next()) {
+        (Some((index, _)), None) => Some(*index),
+        _ => None,
+    }
+}
+
+/// Returns the outputs which could be a payment or change along with their index
+fn spendable_outputs(tx: &EvaluatedTx) -> Vec<(usize, &EvaluatedTxOut)> {
+    tx.outputs
+        .iter()
+        .enumerate()
+        .filter(|(_, o)| {
+            o.out.value > 0
+                && !matches!(
+                    o.script.pattern,
+                    ScriptPattern::OpReturn(_) | ScriptPattern::Unspendable
+                )
+        })
+        .collect()
+}
+
+/// Returns the script type of the output spent by the given input.
+/// Uses the prevout if available, otherwise the type is derived from the input pattern.
+fn spent_type(input: &TxInput) -> Option<ScriptPattern> {
+    if let Some(prevout) = &input.prevout {
+        return Some(prevout.out.script.pattern.clone());
+    }
+    let pattern = match input.pattern {
+        InputPattern::Pay2PublicKey => ScriptPattern::Pay2PublicKey,
+        InputPattern::Pay2PublicKeyHash => ScriptPattern::Pay2PublicKeyHash,
+        InputPattern::Pay2MultiSig => ScriptPattern::Pay2MultiSig,
+        InputPattern::Pay2ScriptHash
+        | InputPattern::Pay2ScriptHashWitnessPublicKeyHash
+        | InputPattern::Pay2ScriptHashWitnessScriptHash => ScriptPattern::Pay2ScriptHash,
+        InputPattern::Pay2WitnessPublicKeyHash => ScriptPattern::Pay2WitnessPublicKeyHash,
+        InputPattern::Pay2WitnessScriptHash => ScriptPattern::Pay2WitnessScriptHash,
+        InputPattern::Pay2TaprootKeyPath | InputPattern::Pay2TaprootScriptPath => {
+            ScriptPattern::Pay2Taproot
+        }
+        InputPattern::Coinbase | InputPattern::NotRecognised => return None,
+    };
+    Some(pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    use crate::blockchain::proto::script::EvaluatedScript;
+    use crate::blockchain::proto::tx::{TxInputs, TxOutpoint, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::blockchain::proto::TxId;
+
+    fn output(value: u64, pattern: ScriptPattern) -> EvaluatedTxOut {
+        EvaluatedTxOut {
+            script: EvaluatedScript::new(None, pattern),
+            out: TxOutput {
+                value,
+                script_len: VarUint::from(0u8),
+                script_pubkey: Vec::new(),
+            },
+        }
+    }
+
+    fn tx(input_patterns: &[InputPattern], outputs: Vec<EvaluatedTxOut>) -> EvaluatedTx {
+        let inputs = input_patterns
+            .iter()
+            .map(|pattern| TxInput {
+                outpoint: TxOutpoint::new(TxId::all_zeros(), 0),
+                script_len: VarUint::from(0u8),
+                script_sig: Vec::new(),
+                seq_no: 0xffffffff,
+                witness: Vec::new(),
+                prevout: None,
+                pattern: pattern.clone(),
+            })
+            .collect::<TxInputs>();
+        EvaluatedTx {
+            version: 1,
+            flags: 0,
+            in_count: VarUint::from(inputs.len() as u64),
+            inputs,
+            out_count: VarUint::from(outputs.len() as u64),
+            outputs: outputs.into(),
+            locktime: 0,
+        }
+    }
+
+    #[test]
+    fn test_by_round_amount() {
+        let p2pkh = || ScriptPattern::Pay2PublicKeyHash;
+        let t = tx(
+            &[InputPattern::Pay2PublicKeyHash],
+            vec![output(1_000_000, p2pkh()), output(1_234_567, p2pkh())],
+        );
+        assert_eq!(by_round_amount(&t), Some(1));
+
+        // Ambiguous
+        let t = tx(
+            &[InputPattern::Pay2PublicKeyHash],
+            vec![output(1_000_001, p2pkh()), output(1_234_567, p2pkh())],
+        );
+        assert_eq!(by_round_amount(&t), None);
+
+        // OP_RETURN outputs are ignored
+        let t = tx(
+            &[InputPattern::Pay2PublicKeyHash],
+            vec![
+                output(1_234_567, p2pkh()),
+                output(0, ScriptPattern::OpReturn(String::new())),
+            ],
+        );
+        assert_eq!(by_round_amount(&t), None);
+    }
+
+    #[test]
+    fn test_by_script_type() {
+        let outputs = || {
+            vec![
+                output(1_000_000, ScriptPattern::Pay2PublicKeyHash),
+                output(1_234_567, ScriptPattern::Pay2WitnessPublicKeyHash),
+            ]
+        };
+        let t = tx(&[InputPattern::Pay2WitnessPublicKeyHash], outputs());
+        assert_eq!(by_script_type(&t), Some(1));
+
+        // Mixed input types
+        let t = tx(
+            &[
+                InputPattern::Pay2WitnessPublicKeyHash,
+                InputPattern::Pay2PublicKeyHash,
+            ],
+            outputs(),
+        );
+        assert_eq!(by_script_type(&t), None);
+
+        // No output matches
+        let t = tx(&[InputPattern::Pay2TaprootKeyPath], outputs());
+        assert_eq!(by_script_type(&t), None);
+    }
+}
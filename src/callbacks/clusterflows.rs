@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::TxOutpoint;
+use crate::callbacks::clusters::Clusters;
+use crate::callbacks::common::AddressInterner;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Unspent output along with the id of the receiving address
+struct ClusterUnspent {
+    address_id: u64,
+    value: u64,
+}
+
+/// Aggregated values of a cluster
+#[derive(Default)]
+struct ClusterFlow {
+    n_addresses: u64,
+    inflow: u64,
+    outflow: u64,
+    balance: u64,
+}
+
+/// Clusters addresses by common input ownership and dumps the aggregated
+/// inflows, outflows and balances per cluster to a csv file.
+pub struct ClusterFlows {
+    output: Output,
+    writer: Box<dyn Sink>,
+    top: usize,
+
+    clusters: Clusters,
+    unspents: HashMap<TxOutpoint, ClusterUnspent>,
+    // Indexed by address id
+    inflows: Vec<u64>,
+    outflows: Vec<u64>,
+
+    start_height: u64,
+}
+
+impl ClusterFlows {
+    /// Aggregates the per address values by cluster
+    fn aggregate(&mut self) -> HashMap<u64, ClusterFlow> {
+        let mut flows: HashMap<u64, ClusterFlow> = HashMap::new();
+        for id in 0..self.clusters.len() as u64 {
+            let flow = flows.entry(self.clusters.find(id)).or_default();
+            flow.n_addresses += 1;
+            flow.inflow += self.inflows[id as usize];
+            flow.outflow += self.outflows[id as usize];
+        }
+        for unspent in self.unspents.values() {
+            let root = self.clusters.find(unspent.address_id);
+            flows.get_mut(&root).unwrap().balance += unspent.value;
+        }
+        flows
+    }
+
+    fn print_top_clusters(
+        &self,
+        flows: &HashMap<u64, ClusterFlow>,
+        buffer: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let mut sorted: Vec<(&u64, &ClusterFlow)> = flows.iter().collect();
+        sorted.sort_unstable_by(|a, b| b.1.balance.cmp(&a.1.balance).then(a.0.cmp(b.0)));
+
+        writeln!(buffer, "Top {} clusters by balance:", self.top)?;
+        for (id, flow) in sorted.iter().take(self.top) {
+            writeln!(
+                buffer,
+                "   -> {} ({} addresses): balance {:.8}, received {:.8}, sent {:.8}",
+                self.clusters.address(**id),
+                flow.n_addresses,
+                flow.balance as f64 * 1E-8,
+                flow.inflow as f64 * 1E-8,
+                flow.outflow as f64 * 1E-8
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for ClusterFlows {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("clusterflows")
+            .about("Dumps inflows, outflows and balances per address cluster to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("top")
+                    .long("top")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(usize))
+                    .default_value("25")
+                    .help("Number of clusters with the highest balance to show"),
+            )
+            .arg(AddressInterner::build_arg())
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let clusters = Clusters::new(AddressInterner::from_matches(matches)?);
+        let cb = ClusterFlows {
+            writer: output.open("clusterflows")?,
+            output,
+            top: *matches.get_one::<usize>("top").unwrap(),
+            unspents: HashMap::with_capacity(10000000),
+            inflows: vec![0; clusters.len()],
+            outflows: vec![0; clusters.len()],
+            clusters,
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing clusterflows with dump folder: {} ...", &self.output);
+        Ok(())
+    }
+
+    /// For each transaction in the block
+    ///   1. remove spent outputs and merge the clusters of all input addresses
+    ///   2. add new outputs and account them as inflow of the receiving address
+    fn on_block(&mut self, block: &Block, _: u64) -> OpResult<()> {
+        let mut input_ids = Vec::new();
+        for tx in &block.txs {
+            input_ids.clear();
+            for input in &tx.value.inputs {
+                if let Some(unspent) = self.unspents.remove(&input.outpoint) {
+                    self.outflows[unspent.address_id as usize] += unspent.value;
+                    input_ids.push(unspent.address_id);
+                }
+            }
+            self.clusters.merge(&input_ids);
+
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                if let Some(address) = &output.script.address {
+                    let address_id = self.clusters.get_or_insert(address);
+                    if address_id as usize == self.inflows.len() {
+                        self.inflows.push(0);
+                        self.outflows.push(0);
+                    }
+                    self.inflows[address_id as usize] += output.out.value;
+
+                    let unspent = ClusterUnspent {
+                        address_id,
+                        value: output.out.value,
+                    };
+                    let key = TxOutpoint::new(tx.hash, i as u32);
+                    self.unspents.insert(key, unspent);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer
+            .write_all("cluster;addresses;inflow;outflow;balance\n".as_bytes())?;
+
+        let flows = self.aggregate();
+        for (id, flow) in &flows {
+            // Clusters are identified by one of their addresses
+            self.writer.write_all(
+                format!(
+                    "{};{};{};{};{}\n",
+                    self.clusters.address(*id),
+                    flow.n_addresses,
+                    flow.inflow,
+                    flow.outflow,
+                    flow.balance
+                )
+                .as_bytes(),
+            )?;
+        }
+        self.writer.flush()?;
+
+        self.writer.finish(self.start_height, block_height)?;
+        self.clusters.interner().persist()?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_top_clusters(&flows, &mut buffer)?;
+        info!(target: "callback", "Done.\nDumped {} clusters with {} addresses.", flows.len(), self.clusters.len());
+        info!(target: "clusterflows", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+}
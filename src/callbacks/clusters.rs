@@ -0,0 +1,123 @@
+use crate::callbacks::common::AddressInterner;
+
+/// Groups addresses into clusters using the common-input-ownership heuristic,
+/// i.e. all addresses spent together in a transaction are assumed to belong to the same entity.
+/// Addresses are interned, the clusters are tracked with a union-find structure.
+pub struct Clusters {
+    interner: AddressInterner,
+    parents: Vec<u64>,
+    sizes: Vec<u32>,
+}
+
+impl Clusters {
+    pub fn new(interner: AddressInterner) -> Self {
+        // Previously interned addresses start as single address clusters
+        let n_addresses = interner.len() as u64;
+        Clusters {
+            interner,
+            parents: (0..n_addresses).collect(),
+            sizes: vec![1; n_addresses as usize],
+        }
+    }
+
+    /// Returns the id of the given address, creates a new single address cluster if unknown
+    pub fn get_or_insert(&mut self, address: &str) -> u64 {
+        let id = self.interner.intern(address);
+        if id as usize == self.parents.len() {
+            self.parents.push(id);
+            self.sizes.push(1);
+        }
+        id
+    }
+
+    /// Merges the clusters of all given address ids,
+    /// returns the number of clusters which were joined with another one
+    pub fn merge(&mut self, ids: &[u64]) -> u64 {
+        let mut n_merges = 0;
+        if let Some((first, rest)) = ids.split_first() {
+            for id in rest {
+                if self.union(*first, *id) {
+                    n_merges += 1;
+                }
+            }
+        }
+        n_merges
+    }
+
+    /// Returns the cluster id of the given address id
+    pub fn find(&mut self, id: u64) -> u64 {
+        let mut root = id;
+        while self.parents[root as usize] != root {
+            root = self.parents[root as usize];
+        }
+        // Path compression
+        let mut id = id;
+        while self.parents[id as usize] != root {
+            let next = self.parents[id as usize];
+            self.parents[id as usize] = root;
+            id = next;
+        }
+        root
+    }
+
+    /// Returns the address with the given id
+    #[inline]
+    pub fn address(&self, id: u64) -> &str {
+        self.interner.address(id)
+    }
+
+    /// Returns the number of known addresses
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    /// Returns the interner holding all known addresses
+    pub fn interner(&self) -> &AddressInterner {
+        &self.interner
+    }
+
+    /// Returns false if both addresses already belong to the same cluster
+    fn union(&mut self, a: u64, b: u64) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        // Attach the smaller cluster to the bigger one
+        let (root, child) = match self.sizes[a as usize] >= self.sizes[b as usize] {
+            true => (a, b),
+            false => (b, a),
+        };
+        self.parents[child as usize] = root;
+        self.sizes[root as usize] += self.sizes[child as usize];
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clusters_merge() {
+        let mut clusters = Clusters::new(AddressInterner::new());
+        let a = clusters.get_or_insert("a");
+        let b = clusters.get_or_insert("b");
+        let c = clusters.get_or_insert("c");
+        let d = clusters.get_or_insert("d");
+        assert_eq!(clusters.get_or_insert("a"), a);
+        assert_eq!(clusters.len(), 4);
+        assert_ne!(clusters.find(a), clusters.find(b));
+
+        assert_eq!(clusters.merge(&[a, b]), 1);
+        assert_eq!(clusters.merge(&[c, d, c]), 1);
+        assert_eq!(clusters.find(a), clusters.find(b));
+        assert_ne!(clusters.find(a), clusters.find(c));
+
+        assert_eq!(clusters.merge(&[d, b]), 1);
+        assert_eq!(clusters.merge(&[a, c]), 0);
+        let root = clusters.find(a);
+        assert!([a, b, c, d].iter().all(|id| clusters.find(*id) == root));
+        assert_eq!(clusters.address(c), "c");
+    }
+}
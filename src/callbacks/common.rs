@@ -1,25 +1,209 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::Script;
+use clap::{Arg, ArgMatches};
+
+use crate::blockchain::proto::script::ScriptPattern;
 use crate::blockchain::proto::tx::EvaluatedTx;
-use crate::blockchain::proto::tx::TxOutpoint;
+use crate::blockchain::proto::tx::{EvaluatedTxOut, TxOutpoint};
 use crate::blockchain::proto::Hashed;
-use crate::blockchain::proto::ToRaw;
+use crate::callbacks::tempoutput::TempOutput;
+use crate::errors::OpResult;
+
+/// Minimum length of a printable string in a coinbase script to be considered as miner tag
+const MIN_TAG_LEN: usize = 4;
+/// Interned keys of outputs without address start with this prefix, see `output_key`
+const SCRIPT_KEY_PREFIX: &str = "script:";
 
 pub struct UnspentValue {
     pub block_height: u64,
     pub value: u64,
-    pub address: String,
+    pub address_id: u64, // see `AddressInterner`
+    pub is_coinbase: bool,
+}
+
+impl UnspentValue {
+    /// Returns true if the output can be spent in the block following `tip_height`.
+    /// Coinbase outputs need `maturity` confirmations first.
+    #[inline]
+    pub fn is_mature(&self, tip_height: u64, maturity: u64) -> bool {
+        !self.is_coinbase || tip_height + 1 >= self.block_height + maturity
+    }
+}
+
+/// How coinbase outputs which can't be spent yet are treated in balance and unspent dumps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImmatureOutputs {
+    Include,
+    // Adds a column with the immature value
+    Flag,
+    Exclude,
+}
+
+impl ImmatureOutputs {
+    /// Builds the `--immature` argument for callbacks that dump unspent outputs
+    pub fn build_arg() -> Arg {
+        Arg::new("immature")
+            .long("immature")
+            .value_name("MODE")
+            .value_parser(["include", "flag", "exclude"])
+            .default_value("include")
+            .help("Treatment of coinbase outputs younger than the coinbase maturity: include, flag (adds a column) or exclude")
+    }
+
+    pub fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.get_one::<String>("immature").map(String::as_str) {
+            Some("flag") => ImmatureOutputs::Flag,
+            Some("exclude") => ImmatureOutputs::Exclude,
+            _ => ImmatureOutputs::Include,
+        }
+    }
+}
+
+/// Maps addresses to dense integer ids and back.
+/// Each address is stored only once, callbacks keep the ids in their maps instead of owned Strings.
+/// The ids can be persisted to a file with one address per line, so they stay stable across runs.
+pub struct AddressInterner {
+    ids: HashMap<Rc<str>, u64>,
+    addresses: Vec<Rc<str>>,
+    path: Option<PathBuf>,
+}
+
+impl AddressInterner {
+    pub fn new() -> Self {
+        AddressInterner {
+            ids: HashMap::new(),
+            addresses: Vec::new(),
+            path: None,
+        }
+    }
+
+    /// Builds the optional `--address-ids` argument for callbacks that intern addresses
+    pub fn build_arg() -> Arg {
+        Arg::new("address-ids")
+            .long("address-ids")
+            .value_name("FILE")
+            .help(
+                "Loads address ids from the given file if it exists and saves them there when done",
+            )
+    }
+
+    /// Loads the ids from the file given by `--address-ids`, if it exists
+    pub fn from_matches(matches: &ArgMatches) -> OpResult<Self> {
+        let path = match matches.get_one::<String>("address-ids") {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(AddressInterner::new()),
+        };
+        let mut interner = match path.exists() {
+            true => AddressInterner::from_path(&path)?,
+            false => AddressInterner::new(),
+        };
+        interner.path = Some(path);
+        Ok(interner)
+    }
+
+    /// Reads a file with one address per line, the line number is the id
+    pub fn from_path(path: &Path) -> OpResult<Self> {
+        info!(target: "callback", "Reading address ids from {} ...", path.display());
+        let mut interner = AddressInterner::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            interner.push(&line?);
+        }
+        info!(target: "callback", "Loaded {} address ids.", interner.len());
+        Ok(interner)
+    }
+
+    /// Writes all addresses ordered by id to the file given by `--address-ids`, if any
+    pub fn persist(&self) -> OpResult<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        // Write to a temporary file first to keep the old ids if anything fails
+        let mut writer = BufWriter::new(TempOutput::create(path.with_extension("tmp"))?);
+        for address in &self.addresses {
+            writeln!(writer, "{}", address)?;
+        }
+        writer.flush()?;
+        writer.get_mut().commit(path)?;
+        info!(target: "callback", "Saved {} address ids to {}.", self.len(), path.display());
+        Ok(())
+    }
+
+    /// Returns the id of the given address, assigns the next free id if unknown
+    pub fn intern(&mut self, address: &str) -> u64 {
+        match self.ids.get(address) {
+            Some(id) => *id,
+            None => self.push(address),
+        }
+    }
+
+    /// Returns the address with the given id
+    #[inline]
+    pub fn address(&self, id: u64) -> &str {
+        &self.addresses[id as usize]
+    }
+
+    /// Returns the number of known addresses
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    fn push(&mut self, address: &str) -> u64 {
+        let id = self.addresses.len() as u64;
+        let address: Rc<str> = Rc::from(address);
+        self.ids.insert(Rc::clone(&address), id);
+        self.addresses.push(address);
+        id
+    }
+}
+
+/// Returns the key an output is tracked by: its address, or the sha256 of its script_pubkey
+/// if no address can be derived (e.g. non-standard scripts). Provably unspendable outputs have no key.
+pub fn output_key(output: &EvaluatedTxOut) -> Option<Cow<'_, str>> {
+    match (&output.script.address, &output.script.pattern) {
+        (Some(address), _) => Some(Cow::Borrowed(address)),
+        (None, ScriptPattern::OpReturn(_) | ScriptPattern::Unspendable) => None,
+        (None, _) => {
+            let hash = sha256::Hash::hash(&output.out.script_pubkey);
+            Some(Cow::Owned(format!("{}{}", SCRIPT_KEY_PREFIX, hash)))
+        }
+    }
+}
+
+/// Splits a key returned by `output_key` into the address and script hash column,
+/// exactly one of them is non-empty
+pub fn key_columns(key: &str) -> (&str, &str) {
+    match key.strip_prefix(SCRIPT_KEY_PREFIX) {
+        Some(hash) => ("", hash),
+        None => (key, ""),
+    }
+}
+
+/// Builds the key of an output from the address and script hash column, inverse of `key_columns`
+pub fn column_key<'a>(address: &'a str, script_hash: &str) -> Cow<'a, str> {
+    match address {
+        "" => Cow::Owned(format!("{}{}", SCRIPT_KEY_PREFIX, script_hash)),
+        address => Cow::Borrowed(address),
+    }
 }
 
 /// Iterates over transaction inputs and removes spent outputs from HashMap.
 /// Returns the total number of processed inputs.
 pub fn remove_unspents(
     tx: &Hashed<EvaluatedTx>,
-    unspents: &mut HashMap<Vec<u8>, UnspentValue>,
+    unspents: &mut HashMap<TxOutpoint, UnspentValue>,
 ) -> u64 {
     for input in &tx.value.inputs {
-        let key = input.outpoint.to_bytes();
-        unspents.remove(&key);
+        unspents.remove(&input.outpoint);
     }
     tx.value.in_count.value
 }
@@ -29,19 +213,21 @@ pub fn remove_unspents(
 pub fn insert_unspents(
     tx: &Hashed<EvaluatedTx>,
     block_height: u64,
-    unspents: &mut HashMap<Vec<u8>, UnspentValue>,
+    unspents: &mut HashMap<TxOutpoint, UnspentValue>,
+    interner: &mut AddressInterner,
 ) -> u64 {
     let mut count = 0;
     for (i, output) in tx.value.outputs.iter().enumerate() {
-        match &output.script.address {
-            Some(address) => {
+        match output_key(output) {
+            Some(key) => {
                 let unspent = UnspentValue {
                     block_height,
-                    address: address.clone(),
+                    address_id: interner.intern(&key),
                     value: output.out.value,
+                    is_coinbase: tx.value.is_coinbase(),
                 };
 
-                let key = TxOutpoint::new(tx.hash, i as u32).to_bytes();
+                let key = TxOutpoint::new(tx.hash, i as u32);
                 unspents.insert(key, unspent);
                 count += 1;
             }
@@ -57,27 +243,56 @@ pub fn insert_unspents(
     count
 }
 
+/// Extracts the longest printable ASCII string from a coinbase script,
+/// most pools put their name there.
+pub fn extract_miner_tag(script_sig: &[u8]) -> Option<String> {
+    script_sig
+        .split(|b| !(b.is_ascii_graphic() || *b == b' ') || *b == b';')
+        .filter(|s| s.len() >= MIN_TAG_LEN)
+        .max_by_key(|s| s.len())
+        .map(|s| String::from_utf8_lossy(s).trim().to_string())
+}
+
+/// Returns the concatenated data pushes of the given script
+pub fn pushed_data(script: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for instruction in Script::from_bytes(script).instructions() {
+        match instruction {
+            Ok(Instruction::PushBytes(bytes)) => data.extend(bytes.as_bytes()),
+            Ok(Instruction::Op(_)) => {}
+            Err(_) => break,
+        }
+    }
+    data
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::blockchain::parser::reader::BlockchainRead;
+    use crate::blockchain::parser::reader::{BlockchainRead, ReadLimits};
+    use crate::blockchain::parser::types::CoinType;
     use crate::blockchain::proto::block::Block;
     use crate::blockchain::proto::header::BlockHeader;
+    use crate::blockchain::proto::tx::TxOutput;
     use crate::blockchain::proto::varuint::VarUint;
+    use crate::common::utils;
 
+    use crate::blockchain::proto::BlockHash;
     use bitcoin::hashes::{sha256d, Hash};
     use std::io::{BufReader, Cursor};
 
     #[test]
     fn test_callback() {
-        let mut unspents: HashMap<Vec<u8>, UnspentValue> = HashMap::new();
+        let mut unspents: HashMap<TxOutpoint, UnspentValue> = HashMap::new();
+        let mut interner = AddressInterner::new();
         let header = BlockHeader {
             version: 0,
-            prev_hash: sha256d::Hash::all_zeros(),
+            prev_hash: BlockHash::all_zeros(),
             merkle_root: sha256d::Hash::all_zeros(),
             timestamp: 0,
             bits: 0,
             nonce: 0,
+            extension: Vec::new(),
         };
 
         // Create a mock of txid fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4,
@@ -104,19 +319,29 @@ mod tests {
             0x7c, 0x88, 0xac, 0x00, 0x00, 0x00, 0x00,
         ];
         let mut reader = BufReader::new(Cursor::new(raw_data));
-        let txs = reader.read_txs(1, 0x00).unwrap();
-        let block1 = Block::new(0, header.clone(), None, VarUint::from(1u8), txs);
+        let txs = reader.read_txs(1, 0x00, &ReadLimits::default()).unwrap();
+        let block1 = Block::new(
+            0,
+            header.clone(),
+            None,
+            VarUint::from(1u8),
+            txs,
+            &CoinType::default(),
+        );
 
         for tx in &block1.txs {
             remove_unspents(&tx, &mut unspents);
-            insert_unspents(&tx, 100000, &mut unspents);
+            insert_unspents(&tx, 100000, &mut unspents, &mut interner);
         }
         let value = unspents
-            .get(&TxOutpoint::new(block1.txs[0].hash, 0).to_bytes())
+            .get(&TxOutpoint::new(block1.txs[0].hash, 0))
             .unwrap();
         assert_eq!(value.block_height, 100000);
         assert_eq!(value.value, 556000000);
-        assert_eq!(value.address, "1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn");
+        assert_eq!(
+            interner.address(value.address_id),
+            "1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn"
+        );
 
         // Create a mock of txid 5aa8e36f9423ee5fcf17c1d0d45d6988b8a5773eae8ad25d945bf34352040009,
         // which decreases balance of address 1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn by 5.56 BTC.
@@ -242,25 +467,122 @@ mod tests {
             0x72, 0xdc, 0x35, 0x92, 0x88, 0xac, 0x00, 0x00, 0x00, 0x00,
         ];
         let mut reader = BufReader::new(Cursor::new(raw_data));
-        let txs = reader.read_txs(1, 0x00).unwrap();
-        let block2 = Block::new(0, header.clone(), None, VarUint::from(1u8), txs);
+        let txs = reader.read_txs(1, 0x00, &ReadLimits::default()).unwrap();
+        let block2 = Block::new(
+            0,
+            header.clone(),
+            None,
+            VarUint::from(1u8),
+            txs,
+            &CoinType::default(),
+        );
 
         for tx in &block2.txs {
             remove_unspents(&tx, &mut unspents);
-            insert_unspents(&tx, 105001, &mut unspents);
+            insert_unspents(&tx, 105001, &mut unspents, &mut interner);
         }
 
         // Original unspent should no longer exist in the hashmap
-        assert!(unspents
-            .get(&TxOutpoint::new(block1.txs[0].hash, 0).to_bytes())
-            .is_none());
+        assert!(!unspents.contains_key(&TxOutpoint::new(block1.txs[0].hash, 0)));
 
         let value = unspents
-            .get(&TxOutpoint::new(block2.txs[0].hash, 0).to_bytes())
+            .get(&TxOutpoint::new(block2.txs[0].hash, 0))
             .unwrap();
 
         assert_eq!(value.block_height, 105001);
         assert_eq!(value.value, 9070000000);
-        assert_eq!(value.address, "1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL");
+        assert_eq!(
+            interner.address(value.address_id),
+            "1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL"
+        );
+    }
+
+    #[test]
+    fn test_is_mature() {
+        let unspent = UnspentValue {
+            block_height: 100,
+            value: 5000000000,
+            address_id: 0,
+            is_coinbase: true,
+        };
+        // Spendable in block 200
+        assert!(!unspent.is_mature(198, 100));
+        assert!(unspent.is_mature(199, 100));
+        let unspent = UnspentValue {
+            is_coinbase: false,
+            ..unspent
+        };
+        assert!(unspent.is_mature(100, 100));
+    }
+
+    #[test]
+    fn test_address_interner() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ids.txt");
+        let mut interner = AddressInterner::new();
+        interner.path = Some(path.clone());
+        assert_eq!(interner.intern("a"), 0);
+        assert_eq!(interner.intern("b"), 1);
+        assert_eq!(interner.intern("a"), 0);
+        assert_eq!(interner.len(), 2);
+        interner.persist().unwrap();
+
+        let mut interner = AddressInterner::from_path(&path).unwrap();
+        assert_eq!(interner.address(1), "b");
+        assert_eq!(interner.intern("c"), 2);
+        assert_eq!(interner.intern("a"), 0);
+    }
+
+    #[test]
+    fn test_output_key() {
+        let output = |script_pubkey: Vec<u8>| {
+            let out = TxOutput {
+                value: 1,
+                script_len: VarUint::from(script_pubkey.len() as u8),
+                script_pubkey,
+            };
+            EvaluatedTxOut::eval_script(out, 0x00, true)
+        };
+        // OP_1 OP_EQUAL has no address
+        let nonstandard = output(vec![0x51, 0x87]);
+        let key = output_key(&nonstandard).unwrap();
+        assert_eq!(
+            key_columns(&key),
+            (
+                "",
+                "2715be8e2c2c8febd01a6de9f4651a25c5d5540a022010caab1abd37365b10a5"
+            )
+        );
+        let (address, script_hash) = key_columns(&key);
+        assert_eq!(column_key(address, script_hash), key);
+        let p2pkh = utils::hex_to_vec("76a914c825a1ecf2a6830c4401620c3a16f1995057c2ab88ac");
+        let p2pkh = output(p2pkh);
+        let key = output_key(&p2pkh).unwrap();
+        assert_eq!(
+            key_columns(&key),
+            ("1KFHE7w8BhaENAswwryaoccDb6qcT6DbYY", "")
+        );
+        assert!(output_key(&output(vec![0x6a, 0x01, 0x00])).is_none());
+    }
+
+    #[test]
+    fn test_extract_miner_tag() {
+        let mut script_sig = vec![0x03, 0x8e, 0x2f, 0x0a, 0xfa, 0xbe, 0x6d, 0x6d, 0x01];
+        script_sig.extend_from_slice(b"/ViaBTC/Mined by foo;bar/");
+        script_sig.extend_from_slice(&[0x10, 0x00, 0xff]);
+        assert_eq!(
+            extract_miner_tag(&script_sig),
+            Some(String::from("/ViaBTC/Mined by foo"))
+        );
+        assert_eq!(extract_miner_tag(&[0x03, 0x8e, 0x2f, 0x0a]), None);
+    }
+
+    #[test]
+    fn test_pushed_data() {
+        // OP_RETURN <2 bytes> <3 bytes>
+        let script = [0x6a, 0x02, 0xff, 0xd8, 0x03, 0xff, 0xe0, 0xff];
+        assert_eq!(pushed_data(&script), [0xff, 0xd8, 0xff, 0xe0, 0xff]);
+        // Truncated push
+        assert_eq!(pushed_data(&[0x01, 0xaa, 0x05, 0xbb]), [0xaa]);
     }
 }
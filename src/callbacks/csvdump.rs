@@ -1,24 +1,38 @@
-use std::fs::{self, File};
-use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::io::Write;
 
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 
 use crate::blockchain::proto::block::Block;
 use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxOut, TxInput};
-use crate::blockchain::proto::Hashed;
-use crate::callbacks::Callback;
+use crate::blockchain::proto::{Hashed, ToRaw};
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
 use crate::common::utils;
 use crate::errors::OpResult;
 
 /// Dumps the whole blockchain into csv files
 pub struct CsvDump {
     // Each structure gets stored in a separate csv file
-    dump_folder: PathBuf,
-    block_writer: BufWriter<File>,
-    tx_writer: BufWriter<File>,
-    txin_writer: BufWriter<File>,
-    txout_writer: BufWriter<File>,
+    output: Output,
+    block_writer: Box<dyn Sink>,
+    tx_writer: Box<dyn Sink>,
+    txin_writer: Box<dyn Sink>,
+    txout_writer: Box<dyn Sink>,
+
+    // Optional columns in transactions.csv
+    include_wtxid: bool,
+    include_raw: bool,
+    // Input values and fees are only known if the spent outputs are resolved from the undo data
+    use_undo_files: bool,
+    // Writers are flushed or synced to disk every n blocks
+    flush_interval: Option<u64>,
+    fsync_interval: Option<u64>,
+    // Height up to which all rows were synced to disk
+    synced_height: Option<u64>,
+
+    // Positions of the current block's transactions, they differ from the
+    // enumeration of `block.txs` if transactions were removed by `--filter`
+    tx_positions: Vec<usize>,
 
     start_height: u64,
     tx_count: u64,
@@ -27,8 +41,13 @@ pub struct CsvDump {
 }
 
 impl CsvDump {
-    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
-        Ok(BufWriter::with_capacity(cap, File::create(path)?))
+    fn writers(&mut self) -> [&mut Box<dyn Sink>; 4] {
+        [
+            &mut self.block_writer,
+            &mut self.tx_writer,
+            &mut self.txin_writer,
+            &mut self.txout_writer,
+        ]
     }
 }
 
@@ -47,31 +66,76 @@ impl Callback for CsvDump {
                     .index(1)
                     .required(true),
             )
+            .arg(
+                Arg::new("include-wtxid")
+                    .long("include-wtxid")
+                    .action(ArgAction::SetTrue)
+                    .help("Appends the witness txid to transactions.csv"),
+            )
+            .arg(
+                Arg::new("include-raw")
+                    .long("include-raw")
+                    .action(ArgAction::SetTrue)
+                    .help("Appends the raw transaction hex (including witness data) to transactions.csv"),
+            )
+            .arg(
+                Arg::new("flush-interval")
+                    .long("flush-interval")
+                    .value_name("BLOCKS")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .help("Flushes the buffered rows of all files every n blocks (default: whenever a buffer is full)"),
+            )
+            .arg(
+                Arg::new("fsync-interval")
+                    .long("fsync-interval")
+                    .value_name("BLOCKS")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .help("Flushes all files and waits until they are written to disk every n blocks"),
+            )
     }
 
-    fn new(matches: &ArgMatches) -> OpResult<Self>
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
     where
         Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
-        let cap = 4000000;
+        let output = Output::from_matches(matches)?;
         let cb = CsvDump {
-            dump_folder: PathBuf::from(dump_folder),
-            block_writer: CsvDump::create_writer(cap, dump_folder.join("blocks.csv.tmp"))?,
-            tx_writer: CsvDump::create_writer(cap, dump_folder.join("transactions.csv.tmp"))?,
-            txin_writer: CsvDump::create_writer(cap, dump_folder.join("tx_in.csv.tmp"))?,
-            txout_writer: CsvDump::create_writer(cap, dump_folder.join("tx_out.csv.tmp"))?,
+            block_writer: output.open("blocks")?,
+            tx_writer: output.open("transactions")?,
+            txin_writer: output.open("tx_in")?,
+            txout_writer: output.open("tx_out")?,
+            output,
+            include_wtxid: matches.get_flag("include-wtxid"),
+            include_raw: matches.get_flag("include-raw"),
+            use_undo_files: context.use_undo_files,
+            flush_interval: matches.get_one::<u64>("flush-interval").copied(),
+            fsync_interval: matches.get_one::<u64>("fsync-interval").copied(),
+            synced_height: None,
             start_height: 0,
             tx_count: 0,
             in_count: 0,
             out_count: 0,
+            tx_positions: Vec::new(),
         };
         Ok(cb)
     }
 
     fn on_start(&mut self, block_height: u64) -> OpResult<()> {
         self.start_height = block_height;
-        info!(target: "callback", "Executing csvdump with dump folder: {} ...", &self.dump_folder.display());
+        info!(target: "callback", "Executing csvdump with dump folder: {} ...", &self.output);
+        if !self.use_undo_files {
+            warn!(target: "callback", "Input values are unknown without --use-undo-files, inputValue and fee will be empty.");
+        }
+        Ok(())
+    }
+
+    fn on_transaction(
+        &mut self,
+        _tx: &Hashed<EvaluatedTx>,
+        _block_height: u64,
+        index: usize,
+    ) -> OpResult<()> {
+        self.tx_positions.push(index);
         Ok(())
     }
 
@@ -82,9 +146,12 @@ impl Callback for CsvDump {
 
         // serialize transaction
         let block_hash = format!("{}", &block.header.hash);
-        for tx in &block.txs {
-            self.tx_writer
-                .write_all(tx.as_csv(&block_hash).as_bytes())?;
+        for (i, tx) in block.txs.iter().enumerate() {
+            let position = (block_height, self.tx_positions.get(i).copied().unwrap_or(i));
+            self.tx_writer.write_all(
+                tx.as_csv(&block_hash, position, self.include_wtxid, self.include_raw)
+                    .as_bytes(),
+            )?;
             let txid_str = format!("{}", &tx.hash);
 
             // serialize inputs
@@ -101,20 +168,34 @@ impl Callback for CsvDump {
             }
             self.out_count += tx.value.out_count.value;
         }
-        self.tx_count += block.tx_count.value;
+        self.tx_count += block.txs.len() as u64;
+        self.tx_positions.clear();
+
+        // Bounds the amount of unwritten data if the output disk is slower than the parser
+        let n_blocks = block_height - self.start_height + 1;
+        if self
+            .fsync_interval
+            .is_some_and(|n| n_blocks.is_multiple_of(n))
+        {
+            for writer in self.writers() {
+                writer.sync()?;
+            }
+            self.synced_height = Some(block_height);
+        } else if self
+            .flush_interval
+            .is_some_and(|n| n_blocks.is_multiple_of(n))
+        {
+            for writer in self.writers() {
+                writer.flush()?;
+            }
+        }
         Ok(())
     }
 
     fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
-        // Keep in sync with c'tor
-        for f in ["blocks", "transactions", "tx_in", "tx_out"] {
-            // Rename temp files
-            fs::rename(
-                self.dump_folder.as_path().join(format!("{}.csv.tmp", f)),
-                self.dump_folder
-                    .as_path()
-                    .join(format!("{}-{}-{}.csv", f, self.start_height, block_height)),
-            )?;
+        let start_height = self.start_height;
+        for writer in self.writers() {
+            writer.finish(start_height, block_height)?;
         }
 
         info!(target: "callback", "Done.\nDumped blocks from height {} to {}:\n\
@@ -124,13 +205,38 @@ impl Callback for CsvDump {
              self.start_height, block_height, self.tx_count, self.in_count, self.out_count);
         Ok(())
     }
+
+    /// Keeps the rows up to the last sync, so a failed run (e.g. on a full disk) can be resumed from there
+    fn on_abort(&mut self, _: Option<u64>) {
+        let synced_height = match self.synced_height {
+            Some(height) => height,
+            None => {
+                warn!(target: "callback", "Discarding the csv files, use --fsync-interval to keep the rows written before a failure");
+                return;
+            }
+        };
+        let start_height = self.start_height;
+        let mut n_kept = 0;
+        for writer in self.writers() {
+            match writer.keep_synced(start_height, synced_height) {
+                Ok(true) => n_kept += 1,
+                Ok(false) => (),
+                Err(err) => error!(target: "callback", "Unable to keep synced rows: {}", err),
+            }
+        }
+        if n_kept > 0 {
+            warn!(target: "callback", "Kept the rows of {} csv files up to the last synced height {}", n_kept, synced_height);
+        } else {
+            warn!(target: "callback", "Discarding the csv files, compressed output can't be kept after a failure");
+        }
+    }
 }
 
 impl Block {
     fn as_csv(&self, block_height: u64) -> String {
-        // (@hash, height, version, blocksize, @hashPrev, @hashMerkleRoot, nTime, nBits, nNonce)
+        // (@hash, height, version, blocksize, @hashPrev, @hashMerkleRoot, nTime, nBits, nNonce, @chainWork)
         format!(
-            "{};{};{};{};{};{};{};{};{}\n",
+            "{};{};{};{};{};{};{};{};{};{}\n",
             &self.header.hash,
             &block_height,
             &self.header.value.version,
@@ -139,31 +245,68 @@ impl Block {
             &self.header.value.merkle_root,
             &self.header.value.timestamp,
             &self.header.value.bits,
-            &self.header.value.nonce
+            &self.header.value.nonce,
+            self.chain_work.map(utils::work_to_hex).unwrap_or_default()
         )
     }
 }
 
 impl Hashed<EvaluatedTx> {
-    fn as_csv(&self, block_hash: &str) -> String {
-        // (@txid, @hashBlock, version, lockTime)
-        format!(
-            "{};{};{};{}\n",
-            &self.hash, &block_hash, &self.value.version, &self.value.locktime
-        )
+    /// `position` is the block height and the index of the transaction within the block
+    fn as_csv(
+        &self,
+        block_hash: &str,
+        position: (u64, usize),
+        include_wtxid: bool,
+        include_raw: bool,
+    ) -> String {
+        // See EvaluatedTx::weight(), the transaction is only serialized for the raw column
+        let base_size = self.value.base_size();
+        let size = base_size + self.value.witness_size();
+        let weight = base_size * 3 + size;
+        let optional = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_default();
+
+        // (@txid, @hashBlock, version, lockTime, inputValue, outputValue, fee, size, vsize, weight,
+        //  height, txIndex, isCoinbase [, @wtxid][, @raw])
+        let mut csv = format!(
+            "{};{};{};{};{};{};{};{};{};{};{};{};{}",
+            &self.hash,
+            &block_hash,
+            &self.value.version,
+            &self.value.locktime,
+            optional(self.value.input_value()),
+            self.value.output_value(),
+            optional(self.value.fee()),
+            size,
+            weight.div_ceil(4),
+            weight,
+            position.0,
+            position.1,
+            self.value.is_coinbase() as u8
+        );
+        if include_wtxid {
+            csv.push_str(&format!(";{}", &self.value.wtxid()));
+        }
+        if include_raw {
+            let raw = self.value.to_bytes_with_witness();
+            csv.push_str(&format!(";{}", &utils::arr_to_hex(&raw)));
+        }
+        csv.push('\n');
+        csv
     }
 }
 
 impl TxInput {
     fn as_csv(&self, txid: &str) -> String {
-        // (@txid, @hashPrevOut, indexPrevOut, scriptSig, sequence)
+        // (@txid, @hashPrevOut, indexPrevOut, scriptSig, sequence, pattern)
         format!(
-            "{};{};{};{};{}\n",
+            "{};{};{};{};{};{}\n",
             &txid,
             &self.outpoint.txid,
             &self.outpoint.index,
             &utils::arr_to_hex(&self.script_sig),
-            &self.seq_no
+            &self.seq_no,
+            &self.pattern
         )
     }
 }
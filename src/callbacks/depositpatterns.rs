@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::common::{AddressInterner, UnspentValue};
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Inflows of an address, indexed by address id
+#[derive(Default, Clone)]
+struct DepositAddress {
+    // Number and value of inflows up to `--max-deposit`
+    deposits: u64,
+    received: u64,
+    sweeps: u64,
+}
+
+/// Finds typical exchange deposit addresses: addresses which receive many small inflows
+/// that are later swept together with other deposit addresses in a large consolidation.
+/// Each candidate sweep is dumped along with the number of deposit addresses it spends from,
+/// the swept addresses are dumped to a second file when done.
+pub struct DepositPatterns {
+    output: Output,
+    writer: Box<dyn Sink>,
+    addresses_writer: Box<dyn Sink>,
+
+    // Inflows above this value (in satoshis) are no deposits
+    max_deposit: u64,
+    // Minimum number of deposits an address must have received to be considered
+    min_deposits: u64,
+    // Minimum number of inputs of a consolidation
+    min_inputs: usize,
+
+    unspents: HashMap<TxOutpoint, UnspentValue>,
+    interner: AddressInterner,
+    addresses: Vec<DepositAddress>,
+
+    start_height: u64,
+    n_sweeps: u64,
+    swept_value: u64,
+}
+
+impl DepositPatterns {
+    /// Removes the spent outputs and returns them if the transaction is a candidate sweep
+    fn spend(&mut self, tx: &Hashed<EvaluatedTx>) -> Option<Vec<UnspentValue>> {
+        let spent: Vec<UnspentValue> = tx
+            .value
+            .inputs
+            .iter()
+            .filter_map(|input| self.unspents.remove(&input.outpoint))
+            .collect();
+        // Consolidations pay to a single hot wallet address, optionally with change.
+        // Outputs without address (e.g. OP_RETURN) are ignored.
+        let n_outputs = tx
+            .value
+            .outputs
+            .iter()
+            .filter(|output| output.script.address.is_some())
+            .count();
+        if spent.len() < self.min_inputs || n_outputs > 2 {
+            return None;
+        }
+        Some(spent)
+    }
+
+    /// Dumps a sweep if it spends from at least one deposit address
+    fn write_sweep(
+        &mut self,
+        tx: &Hashed<EvaluatedTx>,
+        spent: &[UnspentValue],
+        block_height: u64,
+    ) -> OpResult<()> {
+        let mut deposit_ids: Vec<u64> = spent
+            .iter()
+            .map(|unspent| unspent.address_id)
+            .filter(|id| self.addresses[*id as usize].deposits >= self.min_deposits)
+            .collect();
+        deposit_ids.sort_unstable();
+        deposit_ids.dedup();
+        if deposit_ids.is_empty() {
+            return Ok(());
+        }
+        for id in &deposit_ids {
+            self.addresses[*id as usize].sweeps += 1;
+        }
+
+        let value: u64 = spent.iter().map(|unspent| unspent.value).sum();
+        let destination = tx
+            .value
+            .outputs
+            .iter()
+            .max_by_key(|output| output.out.value)
+            .and_then(|output| output.script.address.clone())
+            .unwrap_or_default();
+        // (height, txid, inputs, depositAddresses, value, destination)
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{}\n",
+                block_height,
+                &tx.hash,
+                spent.len(),
+                deposit_ids.len(),
+                value,
+                destination
+            )
+            .as_bytes(),
+        )?;
+        self.n_sweeps += 1;
+        self.swept_value += value;
+        Ok(())
+    }
+
+    /// Adds the new outputs and counts small inflows as deposits
+    fn receive(&mut self, tx: &Hashed<EvaluatedTx>, block_height: u64) {
+        for (i, output) in tx.value.outputs.iter().enumerate() {
+            let address = match &output.script.address {
+                Some(address) => address,
+                None => continue,
+            };
+            let address_id = self.interner.intern(address);
+            if address_id as usize == self.addresses.len() {
+                self.addresses.push(DepositAddress::default());
+            }
+            if output.out.value <= self.max_deposit {
+                let entry = &mut self.addresses[address_id as usize];
+                entry.deposits += 1;
+                entry.received += output.out.value;
+            }
+            let unspent = UnspentValue {
+                block_height,
+                value: output.out.value,
+                address_id,
+                is_coinbase: tx.value.is_coinbase(),
+            };
+            self.unspents
+                .insert(TxOutpoint::new(tx.hash, i as u32), unspent);
+        }
+    }
+
+    fn print_summary(&self, n_addresses: usize, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "DepositPatterns:")?;
+        writeln!(buffer, "   -> sweeps:\t\t\t{}", self.n_sweeps)?;
+        writeln!(
+            buffer,
+            "   -> swept value:\t\t{:.8}",
+            self.swept_value as f64 * 1E-8
+        )?;
+        writeln!(buffer, "   -> deposit addresses:\t{}", n_addresses)?;
+        Ok(())
+    }
+}
+
+impl Callback for DepositPatterns {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("depositpatterns")
+            .about("Dumps consolidations sweeping addresses with many small inflows (exchange deposit addresses) to CSV files")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv files")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("max-deposit")
+                    .long("max-deposit")
+                    .value_name("SATS")
+                    .value_parser(clap::value_parser!(u64))
+                    .default_value("10000000")
+                    .help("Largest inflow (in satoshis) which is counted as deposit"),
+            )
+            .arg(
+                Arg::new("min-deposits")
+                    .long("min-deposits")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("3")
+                    .help("Number of deposits an address must have received to be considered as deposit address"),
+            )
+            .arg(
+                Arg::new("min-inputs")
+                    .long("min-inputs")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(usize))
+                    .default_value("10")
+                    .help("Number of inputs a transaction with at most two outputs must have to be considered as sweep"),
+            )
+            .arg(AddressInterner::build_arg())
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let interner = AddressInterner::from_matches(matches)?;
+        let cb = DepositPatterns {
+            writer: output.open("depositsweeps")?,
+            addresses_writer: output.open("depositaddresses")?,
+            output,
+            max_deposit: *matches.get_one::<u64>("max-deposit").unwrap(),
+            min_deposits: *matches.get_one::<u64>("min-deposits").unwrap(),
+            min_inputs: *matches.get_one::<usize>("min-inputs").unwrap(),
+            unspents: HashMap::with_capacity(10000000),
+            addresses: vec![DepositAddress::default(); interner.len()],
+            interner,
+            start_height: 0,
+            n_sweeps: 0,
+            swept_value: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing depositpatterns with dump folder: {} ...", &self.output);
+        self.writer
+            .write_all("height;txid;inputs;depositAddresses;value;destination\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            if let Some(spent) = self.spend(tx) {
+                self.write_sweep(tx, &spent, block_height)?;
+            }
+            self.receive(tx, block_height);
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+
+        self.addresses_writer
+            .write_all("address;deposits;received;sweeps\n".as_bytes())?;
+        let mut n_addresses = 0;
+        for (id, entry) in self.addresses.iter().enumerate() {
+            if entry.sweeps == 0 {
+                continue;
+            }
+            // (address, deposits, received, sweeps)
+            self.addresses_writer.write_all(
+                format!(
+                    "{};{};{};{}\n",
+                    self.interner.address(id as u64),
+                    entry.deposits,
+                    entry.received,
+                    entry.sweeps
+                )
+                .as_bytes(),
+            )?;
+            n_addresses += 1;
+        }
+        self.addresses_writer
+            .finish(self.start_height, block_height)?;
+        self.interner.persist()?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(n_addresses, &mut buffer)?;
+        info!(target: "depositpatterns", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+}
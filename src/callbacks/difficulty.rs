@@ -0,0 +1,215 @@
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::parser::types::CoinType;
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Blocks of a single retarget period seen so far
+struct Period {
+    start_height: u64,
+    start_time: u32,
+    last_time: u32,
+    n_blocks: u64,
+    bits: u32,
+}
+
+/// Dumps block interval and difficulty statistics per retarget period
+pub struct Difficulty {
+    output: Output,
+    writer: Box<dyn Sink>,
+    period_len: u64, // blocks per period, see `default_period_len`
+    target_spacing: u64,
+
+    period: Option<Period>,
+    start_height: u64,
+    n_periods: u64,
+    // (actual, expected) duration of all finished periods
+    total_duration: (u64, u64),
+}
+
+/// Returns the retarget interval of the coin as period length. Coins which retarget
+/// every block (e.g. Dogecoin) get a day of blocks instead, a row per block would only
+/// repeat the block intervals.
+fn default_period_len(coin: &CoinType) -> u64 {
+    match coin.retarget_interval {
+        0 | 1 => (86400 / coin.target_spacing.max(1)).max(1),
+        interval => interval,
+    }
+}
+
+impl Difficulty {
+    /// Writes the stats of the given period.
+    /// `end_time` is the timestamp of the first block of the next period if available.
+    fn write_period(&mut self, period: &Period, end_time: Option<u32>) -> OpResult<()> {
+        let (end_time, n_intervals) = match end_time {
+            Some(end_time) => (end_time, period.n_blocks),
+            None => (period.last_time, period.n_blocks - 1),
+        };
+        if n_intervals == 0 {
+            return Ok(());
+        }
+        let actual = end_time.saturating_sub(period.start_time) as u64;
+        let expected = n_intervals * self.target_spacing;
+        let avg_interval = actual as f64 / n_intervals as f64;
+        let difficulty = utils::bits_to_difficulty(period.bits);
+        let hashrate = match avg_interval > 0.0 {
+            true => difficulty * 2f64.powi(32) / avg_interval,
+            false => 0.0,
+        };
+
+        // (period, startHeight, endHeight, startTime, endTime, avgInterval, actualDuration,
+        //  expectedDuration, bits, difficulty, hashrate)
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{:.2};{};{};{:08x};{:.8};{:.0}\n",
+                period.start_height / self.period_len,
+                period.start_height,
+                period.start_height + period.n_blocks - 1,
+                period.start_time,
+                end_time,
+                avg_interval,
+                actual,
+                expected,
+                period.bits,
+                difficulty,
+                hashrate
+            )
+            .as_bytes(),
+        )?;
+        self.n_periods += 1;
+        self.total_duration.0 += actual;
+        self.total_duration.1 += expected;
+        Ok(())
+    }
+
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let (actual, expected) = self.total_duration;
+        writeln!(buffer, "Difficulty:")?;
+        writeln!(buffer, "   -> retarget periods:\t\t{}", self.n_periods)?;
+        writeln!(
+            buffer,
+            "   -> actual duration:\t\t{:.2} days",
+            actual as f64 / 86400.0
+        )?;
+        writeln!(
+            buffer,
+            "   -> expected duration:\t{:.2} days ({:+.2}%)",
+            expected as f64 / 86400.0,
+            (actual as f64 / expected.max(1) as f64 - 1.0) * 100.0
+        )?;
+        Ok(())
+    }
+}
+
+impl Callback for Difficulty {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("difficulty")
+            .about("Dumps block interval and difficulty stats per retarget period to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("period")
+                    .long("period")
+                    .value_name("BLOCKS")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .help("Blocks per period (default: the retarget interval of the coin, a day of blocks if it retargets every block)"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = Difficulty {
+            writer: output.open("difficulty")?,
+            output,
+            period_len: matches
+                .get_one::<u64>("period")
+                .copied()
+                .unwrap_or_else(|| default_period_len(&context.coin)),
+            target_spacing: context.coin.target_spacing,
+            period: None,
+            start_height: 0,
+            n_periods: 0,
+            total_duration: (0, 0),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing difficulty with dump folder: {} (period: {} blocks, target spacing: {}s) ...",
+              &self.output, self.period_len, self.target_spacing);
+        self.writer.write_all(
+            "period;startHeight;endHeight;startTime;endTime;avgInterval;actualDuration;expectedDuration;bits;difficulty;hashrate\n"
+                .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let timestamp = block.header.value.timestamp;
+        let interval = self.period_len;
+        if let Some(period) = self.period.take() {
+            match period.start_height / interval == block_height / interval {
+                true => self.period = Some(period),
+                false => self.write_period(&period, Some(timestamp))?,
+            }
+        }
+        let period = self.period.get_or_insert(Period {
+            start_height: block_height,
+            start_time: timestamp,
+            last_time: timestamp,
+            n_blocks: 0,
+            bits: block.header.value.bits,
+        });
+        period.last_time = timestamp;
+        period.n_blocks += 1;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        // Last period is most likely incomplete
+        if let Some(period) = self.period.take() {
+            self.write_period(&period, None)?;
+        }
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "difficulty", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::types::{Bitcoin, Dogecoin};
+
+    #[test]
+    fn test_default_period_len() {
+        assert_eq!(default_period_len(&CoinType::from(Bitcoin)), 2016);
+        // Dogecoin retargets every block with a spacing of one minute
+        assert_eq!(default_period_len(&CoinType::from(Dogecoin)), 1440);
+    }
+}
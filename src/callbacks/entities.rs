@@ -0,0 +1,168 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::TxOutpoint;
+use crate::callbacks::clusters::Clusters;
+use crate::callbacks::common::AddressInterner;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Growth of the entities within a month
+#[derive(Default)]
+struct MonthStats {
+    new_addresses: u64,
+    merges: u64,
+    // Ids of all addresses which sent or received in the month
+    active: HashSet<u64>,
+}
+
+/// Clusters addresses by common input ownership like `clusterflows` and dumps
+/// the creation and merges of clusters and the number of active entities per month.
+pub struct Entities {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    clusters: Clusters,
+    // Address ids of unspent outputs
+    unspents: HashMap<TxOutpoint, u64>,
+    month: String,
+    stats: MonthStats,
+    n_merges: u64,
+
+    start_height: u64,
+    n_months: u64,
+}
+
+impl Entities {
+    /// Writes the row of the current month, the clusters are counted as of its last block
+    fn write_month(&mut self) -> OpResult<()> {
+        let active_entities: HashSet<u64> = self
+            .stats
+            .active
+            .iter()
+            .map(|id| self.clusters.find(*id))
+            .collect();
+        // Each address starts as its own cluster, each merge joins two of them
+        let n_clusters = self.clusters.len() as u64 - self.n_merges;
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{}\n",
+                self.month,
+                self.stats.new_addresses,
+                self.stats.merges,
+                n_clusters,
+                self.stats.active.len(),
+                active_entities.len()
+            )
+            .as_bytes(),
+        )?;
+        self.n_months += 1;
+        Ok(())
+    }
+}
+
+impl Callback for Entities {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("entities")
+            .about("Dumps new, merged and active address clusters (entities) per month to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = Entities {
+            writer: output.open("entities")?,
+            output,
+            clusters: Clusters::new(AddressInterner::new()),
+            unspents: HashMap::with_capacity(10000000),
+            month: String::new(),
+            stats: MonthStats::default(),
+            n_merges: 0,
+            start_height: 0,
+            n_months: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        self.writer.write_all(
+            "month;newAddresses;merges;clusters;activeAddresses;activeEntities\n".as_bytes(),
+        )?;
+        info!(target: "callback", "Executing entities with dump folder: {} ...", &self.output);
+        Ok(())
+    }
+
+    /// Clusters the addresses like `clusterflows` and counts
+    ///   1. new addresses, which start as a cluster on their own
+    ///   2. merges of two clusters by a transaction spending from both
+    ///   3. addresses sending or receiving, and the clusters they belong to
+    fn on_block(&mut self, block: &Block, _: u64) -> OpResult<()> {
+        // Block times are not strictly increasing, a month ends with the first block of a later month
+        let month = utils::month_of(block.header.value.timestamp);
+        if month > self.month {
+            if !self.month.is_empty() {
+                self.write_month()?;
+            }
+            self.month = month;
+            self.stats = MonthStats::default();
+        }
+
+        let mut input_ids = Vec::new();
+        for tx in &block.txs {
+            input_ids.clear();
+            for input in &tx.value.inputs {
+                if let Some(address_id) = self.unspents.remove(&input.outpoint) {
+                    input_ids.push(address_id);
+                }
+            }
+            let n_merges = self.clusters.merge(&input_ids);
+            self.stats.merges += n_merges;
+            self.n_merges += n_merges;
+            self.stats.active.extend(&input_ids);
+
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                if let Some(address) = &output.script.address {
+                    let n_addresses = self.clusters.len();
+                    let address_id = self.clusters.get_or_insert(address);
+                    if self.clusters.len() > n_addresses {
+                        self.stats.new_addresses += 1;
+                    }
+                    self.stats.active.insert(address_id);
+                    self.unspents
+                        .insert(TxOutpoint::new(tx.hash, i as u32), address_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        if !self.month.is_empty() {
+            self.write_month()?;
+        }
+        self.writer.flush()?;
+        self.writer.finish(self.start_height, block_height)?;
+        info!(target: "callback", "Done.\nDumped {} months, {} addresses in {} clusters.",
+              self.n_months, self.clusters.len(), self.clusters.len() as u64 - self.n_merges);
+        Ok(())
+    }
+}
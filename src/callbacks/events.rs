@@ -0,0 +1,28 @@
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+
+/// JSON event for a parsed block, shared by the streaming callbacks
+pub fn block_json(block: &Block, block_height: u64) -> String {
+    format!(
+        "{{\"type\":\"block\",\"height\":{},\"hash\":\"{}\",\"time\":{},\"size\":{},\"txs\":{}}}",
+        block_height,
+        &block.header.hash,
+        block.header.value.timestamp,
+        block.size,
+        block.txs.len()
+    )
+}
+
+/// JSON event for a transaction, `value` is the sum of all outputs
+pub fn tx_json(tx: &Hashed<EvaluatedTx>, block_height: u64) -> String {
+    let value: u64 = tx.value.outputs.iter().map(|o| o.out.value).sum();
+    format!(
+        "{{\"type\":\"tx\",\"height\":{},\"txid\":\"{}\",\"inputs\":{},\"outputs\":{},\"value\":{}}}",
+        block_height,
+        &tx.hash,
+        tx.value.inputs.len(),
+        tx.value.outputs.len(),
+        value
+    )
+}
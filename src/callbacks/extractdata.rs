@@ -0,0 +1,251 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::callbacks::common::pushed_data;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// File type, magic bytes and end marker of recognized payloads.
+/// Payloads are only extracted if the end marker is present to avoid false positives.
+const SIGNATURES: [(&str, &[u8], &[u8]); 3] = [
+    (
+        "png",
+        &[0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a],
+        &[0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82],
+    ),
+    ("jpg", &[0xff, 0xd8, 0xff], &[0xff, 0xd9]),
+    ("pdf", b"%PDF-", b"%%EOF"),
+];
+
+/// Payload found in a transaction
+struct Artifact {
+    source: String,
+    offset: usize,
+    extension: &'static str,
+    data: Vec<u8>,
+}
+
+/// Extracts embedded files from OP_RETURN outputs, coinbase scripts and optionally witness data
+pub struct ExtractData {
+    output: Output,
+    writer: Box<dyn Sink>,
+    // Extracted files are always stored as plain files
+    artifact_folder: PathBuf,
+    include_witness: bool,
+
+    start_height: u64,
+    n_artifacts: u64,
+}
+
+impl ExtractData {
+    /// Collects all data which could carry a payload, chunked data is concatenated
+    fn collect_data(&self, tx: &EvaluatedTx) -> Vec<(String, Vec<u8>)> {
+        let mut data = Vec::new();
+        if tx.is_coinbase() {
+            data.push((String::from("coinbase"), tx.inputs[0].script_sig.clone()));
+        }
+
+        let mut op_return = Vec::new();
+        // Early protocols stored data in fake public keys of bare multisig or non-standard outputs
+        let mut outputs = Vec::new();
+        for output in &tx.outputs {
+            match output.script.pattern {
+                ScriptPattern::OpReturn(_) => {
+                    op_return.extend(pushed_data(&output.out.script_pubkey))
+                }
+                ScriptPattern::Pay2MultiSig | ScriptPattern::NotRecognised => {
+                    outputs.extend(pushed_data(&output.out.script_pubkey))
+                }
+                _ => {}
+            }
+        }
+        data.push((String::from("opreturn"), op_return));
+        data.push((String::from("outputs"), outputs));
+
+        if self.include_witness {
+            for (i, input) in tx.inputs.iter().enumerate() {
+                // Pushes inside witness scripts are usually limited to 520 bytes,
+                // so payloads are split across multiple pushes (e.g. inscriptions)
+                let mut witness = Vec::new();
+                for item in &input.witness {
+                    witness.extend(pushed_data(item));
+                }
+                data.push((format!("witness:{}", i), witness));
+            }
+        }
+        data.retain(|(_, data)| !data.is_empty());
+        data
+    }
+
+    fn write_artifact(
+        &mut self,
+        block_height: u64,
+        txid: &str,
+        artifact: &Artifact,
+    ) -> OpResult<()> {
+        let file_name = format!(
+            "{}-{}-{}.{}",
+            block_height,
+            txid,
+            artifact.source.replace(':', "_"),
+            artifact.extension
+        );
+        fs::write(self.artifact_folder.join(&file_name), &artifact.data)?;
+        // (height, @txid, source, offset, size, type, file)
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{}\n",
+                block_height,
+                txid,
+                artifact.source,
+                artifact.offset,
+                artifact.data.len(),
+                artifact.extension,
+                file_name
+            )
+            .as_bytes(),
+        )?;
+        self.n_artifacts += 1;
+        Ok(())
+    }
+}
+
+/// Searches the data for known file signatures
+fn find_artifacts(source: &str, data: &[u8]) -> Vec<Artifact> {
+    let mut artifacts = Vec::new();
+    for (extension, magic, end_marker) in SIGNATURES {
+        let offset = match find(data, magic) {
+            Some(offset) => offset,
+            None => continue,
+        };
+        // Take everything up to the last end marker
+        let end = data[offset..]
+            .windows(end_marker.len())
+            .rposition(|w| w == end_marker)
+            .map(|pos| offset + pos + end_marker.len());
+        if let Some(end) = end {
+            artifacts.push(Artifact {
+                source: String::from(source),
+                offset,
+                extension,
+                data: data[offset..end].to_vec(),
+            });
+        }
+    }
+    artifacts
+}
+
+#[inline]
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len()).position(|w| w == pattern)
+}
+
+impl Callback for ExtractData {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("extractdata")
+            .about("Extracts embedded files (PNG, JPEG, PDF) from OP_RETURN outputs and coinbase scripts")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store the extracted files")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("include-witness")
+                    .long("include-witness")
+                    .action(ArgAction::SetTrue)
+                    .help("Also scans witness data"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let artifact_folder = match output.folder() {
+            Some(folder) => PathBuf::from(folder),
+            None => {
+                return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg("extractdata requires a folder to store the extracted files"))
+            }
+        };
+        let cb = ExtractData {
+            writer: output.open("extractdata")?,
+            artifact_folder,
+            output,
+            include_witness: matches.get_flag("include-witness"),
+            start_height: 0,
+            n_artifacts: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing extractdata with dump folder: {} ...", &self.output);
+        self.writer
+            .write_all("height;txid;source;offset;size;type;file\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            let mut artifacts = Vec::new();
+            for (source, data) in self.collect_data(&tx.value) {
+                artifacts.extend(find_artifacts(&source, &data));
+            }
+            if artifacts.is_empty() {
+                continue;
+            }
+            let txid = tx.hash.to_string();
+            for artifact in &artifacts {
+                info!(target: "callback", "Found {} in {} ({}, {} bytes)", artifact.extension, &txid, artifact.source, artifact.data.len());
+                self.write_artifact(block_height, &txid, artifact)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+        info!(target: "callback", "Done.\nExtracted {} files.", self.n_artifacts);
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_artifacts() {
+        let mut data = vec![0x00, 0x01];
+        data.extend(b"%PDF-1.4 content %%EOF");
+        data.extend([0x02, 0x03]);
+        let artifacts = find_artifacts("opreturn", &data);
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0].extension, "pdf");
+        assert_eq!(artifacts[0].offset, 2);
+        assert_eq!(artifacts[0].data, b"%PDF-1.4 content %%EOF");
+
+        // No end marker
+        assert!(find_artifacts("opreturn", &[0xff, 0xd8, 0xff, 0xe0, 0x00]).is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::parser::types::SubsidyFn;
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::minerstats::MAX_BLOCK_WEIGHT;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Percentiles of the fee rates within a day
+const PERCENTILES: [f64; 3] = [10.0, 50.0, 90.0];
+
+/// Aggregated blocks of a single day
+#[derive(Default)]
+struct Day {
+    date: String,
+    n_blocks: u64,
+    n_txs: u64,
+    weight: u64,
+    n_full_blocks: u64,
+    // Sum of the known subsidies
+    subsidy: u64,
+    // Blocks where the subsidy and the fees of all transactions are known, along with their subsidy
+    n_fee_blocks: u64,
+    fee_block_subsidy: u64,
+    fees: u64,
+    // Fee rates (sat/vB) of all transactions with known fees
+    rates: Vec<f64>,
+}
+
+impl Day {
+    fn new(date: String) -> Self {
+        Day {
+            date,
+            ..Default::default()
+        }
+    }
+
+    fn add_block(&mut self, block: &Block, subsidy: Option<u64>, full_weight: u64) {
+        let weight = block.weight();
+        self.n_blocks += 1;
+        self.n_txs += block.txs.len() as u64;
+        self.weight += weight;
+        self.subsidy += subsidy.unwrap_or(0);
+        if weight >= full_weight {
+            self.n_full_blocks += 1;
+        }
+
+        let mut fees = Some(0u64);
+        for tx in block.txs.iter().filter(|tx| !tx.value.is_coinbase()) {
+            let fee = tx.value.fee();
+            if let Some(fee) = fee {
+                self.rates.push(fee as f64 / tx.value.vsize() as f64);
+            }
+            fees = fees.zip(fee).map(|(sum, fee)| sum + fee);
+        }
+        if let Some((fees, subsidy)) = fees.zip(subsidy) {
+            self.n_fee_blocks += 1;
+            self.fee_block_subsidy += subsidy;
+            self.fees += fees;
+        }
+    }
+
+    /// Returns the average share of the maximum block weight in percent
+    fn utilization(&self) -> f64 {
+        self.weight as f64 / (self.n_blocks * MAX_BLOCK_WEIGHT).max(1) as f64 * 100.00
+    }
+
+    /// Returns the share of the fees in the miner revenue of the blocks with known fees
+    fn fee_share(&self) -> Option<f64> {
+        let revenue = self.fee_block_subsidy + self.fees;
+        match self.n_fee_blocks {
+            0 => None,
+            _ => Some(self.fees as f64 / revenue.max(1) as f64 * 100.00),
+        }
+    }
+
+    /// A day belongs to a full-block era if at least half of its blocks are full
+    fn is_full(&self) -> bool {
+        self.n_full_blocks * 2 >= self.n_blocks.max(1)
+    }
+
+    fn as_csv(&mut self) -> String {
+        self.rates.sort_unstable_by(f64::total_cmp);
+        let percentiles: Vec<String> = PERCENTILES
+            .iter()
+            .map(|p| {
+                utils::percentile(&self.rates, *p)
+                    .map(|rate| format!("{:.2}", rate))
+                    .unwrap_or_default()
+            })
+            .collect();
+        // (date, blocks, txs, avgWeight, utilization, fullBlocks, subsidy, fees, feeBlocks,
+        //  feeShare, p10, p50, p90)
+        format!(
+            "{};{};{};{};{:.2};{};{};{};{};{};{}\n",
+            self.date,
+            self.n_blocks,
+            self.n_txs,
+            self.weight / self.n_blocks.max(1),
+            self.utilization(),
+            self.n_full_blocks,
+            self.subsidy,
+            self.fees,
+            self.n_fee_blocks,
+            format_share(self.fee_share()),
+            percentiles.join(";")
+        )
+    }
+}
+
+/// Consecutive days which are all either full or not
+#[derive(Default)]
+struct Era {
+    start: String,
+    end: String,
+    full: bool,
+    n_days: u64,
+    n_blocks: u64,
+    n_full_blocks: u64,
+    weight: u64,
+    n_fee_blocks: u64,
+    fee_block_subsidy: u64,
+    fees: u64,
+}
+
+impl Era {
+    fn add_day(&mut self, day: &Day) {
+        if self.n_days == 0 {
+            self.start = day.date.clone();
+        }
+        self.end = day.date.clone();
+        self.n_days += 1;
+        self.n_blocks += day.n_blocks;
+        self.n_full_blocks += day.n_full_blocks;
+        self.weight += day.weight;
+        self.n_fee_blocks += day.n_fee_blocks;
+        self.fee_block_subsidy += day.fee_block_subsidy;
+        self.fees += day.fees;
+    }
+
+    fn as_csv(&self) -> String {
+        let kind = match self.full {
+            true => "full",
+            false => "normal",
+        };
+        let fee_share = match self.n_fee_blocks {
+            0 => None,
+            _ => {
+                Some(self.fees as f64 / (self.fee_block_subsidy + self.fees).max(1) as f64 * 100.00)
+            }
+        };
+        // (start, end, days, era, blocks, fullBlocks, utilization, feeShare)
+        format!(
+            "{};{};{};{};{};{};{:.2};{}\n",
+            self.start,
+            self.end,
+            self.n_days,
+            kind,
+            self.n_blocks,
+            self.n_full_blocks,
+            self.weight as f64 / (self.n_blocks * MAX_BLOCK_WEIGHT).max(1) as f64 * 100.00,
+            format_share(fee_share)
+        )
+    }
+}
+
+fn format_share(share: Option<f64>) -> String {
+    share.map(|s| format!("{:.2}", s)).unwrap_or_default()
+}
+
+/// Reconstructs the historical fee market per UTC day: block weight utilization, fee rate
+/// percentiles and the share of fees in the miner revenue (subsidy + fees).
+/// Consecutive days where most blocks are full are merged into eras in a second file.
+/// Fees are only known if the spent outputs are resolved from the undo data.
+pub struct FeeMarket {
+    output: Output,
+    writer: Box<dyn Sink>,
+    eras_writer: Box<dyn Sink>,
+
+    subsidy: SubsidyFn,
+    use_undo_files: bool,
+    start_height: u64,
+    // Minimum weight of a full block
+    full_weight: u64,
+    day: Option<Day>,
+    era: Era,
+    n_days: u64,
+    n_full_days: u64,
+    n_eras: u64,
+}
+
+impl FeeMarket {
+    /// Writes the finished day and adds it to the current era, which is written once the kind changes
+    fn write_day(&mut self, mut day: Day) -> OpResult<()> {
+        self.writer.write_all(day.as_csv().as_bytes())?;
+        self.n_days += 1;
+        if day.is_full() {
+            self.n_full_days += 1;
+        }
+        if self.era.n_days > 0 && self.era.full != day.is_full() {
+            self.write_era()?;
+        }
+        self.era.full = day.is_full();
+        self.era.add_day(&day);
+        Ok(())
+    }
+
+    fn write_era(&mut self) -> OpResult<()> {
+        let era = std::mem::take(&mut self.era);
+        if era.n_days > 0 {
+            self.eras_writer.write_all(era.as_csv().as_bytes())?;
+            self.n_eras += 1;
+        }
+        Ok(())
+    }
+
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "FeeMarket:")?;
+        writeln!(buffer, "   -> days:\t\t{}", self.n_days)?;
+        writeln!(buffer, "   -> full days:\t{}", self.n_full_days)?;
+        writeln!(buffer, "   -> eras:\t\t{}", self.n_eras)?;
+        Ok(())
+    }
+}
+
+impl Callback for FeeMarket {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("feemarket")
+            .about("Dumps block fullness, fee rate percentiles and the fee share of the miner revenue per day, and full-block eras to CSV files")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv files")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("full-threshold")
+                    .long("full-threshold")
+                    .value_name("PERCENT")
+                    .value_parser(clap::value_parser!(u64).range(1..=100))
+                    .default_value("95")
+                    .help("Share of the maximum block weight from which a block counts as full"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let threshold = *matches.get_one::<u64>("full-threshold").unwrap();
+        let cb = FeeMarket {
+            writer: output.open("feemarket")?,
+            eras_writer: output.open("feemarket-eras")?,
+            output,
+            subsidy: context.coin.subsidy,
+            use_undo_files: context.use_undo_files,
+            start_height: 0,
+            full_weight: MAX_BLOCK_WEIGHT * threshold / 100,
+            day: None,
+            era: Era::default(),
+            n_days: 0,
+            n_full_days: 0,
+            n_eras: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing feemarket with dump folder: {} ...", &self.output);
+        if !self.use_undo_files {
+            warn!(target: "callback", "Fees are unknown for blocks with transactions, use --use-undo-files to resolve them.");
+        }
+        self.writer.write_all(
+            "date;blocks;txs;avgWeight;utilization;fullBlocks;subsidy;fees;feeBlocks;feeShare;p10;p50;p90\n"
+                .as_bytes(),
+        )?;
+        self.eras_writer
+            .write_all("start;end;days;era;blocks;fullBlocks;utilization;feeShare\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let date = utils::day_of(block.header.value.timestamp);
+        // Block timestamps are not strictly increasing, late blocks are counted to the current day
+        if self.day.as_ref().is_some_and(|day| date > day.date) {
+            let day = self.day.take().unwrap();
+            self.write_day(day)?;
+        }
+        self.day.get_or_insert_with(|| Day::new(date)).add_block(
+            block,
+            (self.subsidy)(block_height),
+            self.full_weight,
+        );
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        if let Some(day) = self.day.take() {
+            self.write_day(day)?;
+        }
+        self.write_era()?;
+        self.writer.finish(self.start_height, block_height)?;
+        self.eras_writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "feemarket", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
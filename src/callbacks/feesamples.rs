@@ -0,0 +1,148 @@
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Percentiles of the fee rates within a block
+const PERCENTILES: [f64; 5] = [0.0, 25.0, 50.0, 75.0, 100.0];
+
+/// Dumps the distribution of the fee rates (sat/vB) of the confirmed transactions per block,
+/// e.g. to compare fee estimators against the fees that were actually paid.
+/// Fees are only known if the spent outputs are resolved from the undo data.
+pub struct FeeSamples {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    use_undo_files: bool,
+    start_height: u64,
+    n_blocks: u64,
+    n_samples: u64,
+    n_unknown: u64,
+    // Fee rates of the current block, reused to avoid allocations
+    rates: Vec<f64>,
+    // Median fee rate of each block with known fees
+    medians: Vec<f64>,
+}
+
+impl FeeSamples {
+    fn print_summary(&mut self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        self.medians.sort_unstable_by(f64::total_cmp);
+        writeln!(buffer, "FeeSamples:")?;
+        writeln!(buffer, "   -> blocks:\t\t{}", self.n_blocks)?;
+        writeln!(buffer, "   -> samples:\t\t{}", self.n_samples)?;
+        writeln!(buffer, "   -> unknown fees:\t{}", self.n_unknown)?;
+        if let Some(median) = utils::percentile(&self.medians, 50.0) {
+            writeln!(
+                buffer,
+                "   -> median of block medians:\t{:.2} sat/vB",
+                median
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for FeeSamples {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("feesamples")
+            .about("Dumps fee rate percentiles (sat/vB) of the confirmed transactions per block to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = FeeSamples {
+            writer: output.open("feesamples")?,
+            output,
+            use_undo_files: context.use_undo_files,
+            start_height: 0,
+            n_blocks: 0,
+            n_samples: 0,
+            n_unknown: 0,
+            rates: Vec::new(),
+            medians: Vec::new(),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing feesamples with dump folder: {} ...", &self.output);
+        if !self.use_undo_files {
+            warn!(target: "callback", "Fees are unknown without --use-undo-files, all percentiles will be empty.");
+        }
+        self.writer
+            .write_all("height;hash;time;txs;samples;min;p25;p50;p75;max\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.rates.clear();
+        for tx in block.txs.iter().filter(|tx| !tx.value.is_coinbase()) {
+            match tx.value.fee() {
+                Some(fee) => self.rates.push(fee as f64 / tx.value.vsize() as f64),
+                None => self.n_unknown += 1,
+            }
+        }
+        self.rates.sort_unstable_by(f64::total_cmp);
+        if let Some(median) = utils::percentile(&self.rates, 50.0) {
+            self.medians.push(median);
+        }
+
+        let percentiles: Vec<String> = PERCENTILES
+            .iter()
+            .map(|p| {
+                utils::percentile(&self.rates, *p)
+                    .map(|rate| format!("{:.2}", rate))
+                    .unwrap_or_default()
+            })
+            .collect();
+        // (height, hash, time, txs, samples, min, p25, p50, p75, max)
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{}\n",
+                block_height,
+                &block.header.hash,
+                block.header.value.timestamp,
+                block.txs.len(),
+                self.rates.len(),
+                percentiles.join(";")
+            )
+            .as_bytes(),
+        )?;
+        self.n_blocks += 1;
+        self.n_samples += self.rates.len() as u64;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "feesamples", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,320 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use bitcoin::hashes::Hash;
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::{AuxPowExtension, Block, BlockContext};
+use crate::blockchain::proto::tx::{EvaluatedTx, EvaluatedTxOut, TxInput};
+use crate::blockchain::proto::{BlockHash, Hashed, ToRaw};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::{logger, utils};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Block requested by height or hash
+#[derive(Clone, Copy)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub enum BlockRef {
+    Height(u64),
+    Hash(BlockHash),
+}
+
+impl FromStr for BlockRef {
+    type Err = OpError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(height) = s.parse() {
+            return Ok(BlockRef::Height(height));
+        }
+        BlockHash::from_str(s).map(BlockRef::Hash).map_err(|_| {
+            OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("{} is neither a height nor a block hash", s))
+        })
+    }
+}
+
+/// Prints a single block with all decoded transactions as JSON to stdout, e.g. to check the parsed data.
+/// The block is looked up by height or hash in the block index, so only this block is read.
+pub struct GetBlock {
+    block: BlockRef,
+    found: bool,
+}
+
+impl GetBlock {
+    /// Returns the requested block
+    pub fn block(&self) -> BlockRef {
+        self.block
+    }
+}
+
+impl Callback for GetBlock {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("getblock")
+            .visible_alias("dumpblock")
+            .about("Prints a single block with decoded transactions as JSON")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("block")
+                    .help("Height or hash of the block")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let block = matches.get_one::<String>("block").unwrap().parse()?;
+        // stdout is reserved for the block
+        logger::redirect_to_stderr();
+        Ok(GetBlock {
+            block,
+            found: false,
+        })
+    }
+
+    fn on_start(&mut self, _: u64) -> OpResult<()> {
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(block_json(block, block_height).as_bytes())?;
+        stdout.write_all(b"\n")?;
+        self.found = true;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, _: u64) -> OpResult<()> {
+        if !self.found {
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg("Block not found"));
+        }
+        Ok(())
+    }
+
+    fn show_progress(&self) -> bool {
+        false
+    }
+}
+
+fn block_json(block: &Block, block_height: u64) -> String {
+    let header = &block.header.value;
+    let mut fields = vec![
+        ("hash", quote(&block.header.hash.to_string())),
+        ("height", block_height.to_string()),
+        ("version", header.version.to_string()),
+        ("prev_hash", quote(&header.prev_hash.to_string())),
+        ("merkle_root", quote(&header.merkle_root.to_string())),
+        ("time", header.timestamp.to_string()),
+        ("bits", quote(&format!("{:08x}", header.bits))),
+        ("nonce", header.nonce.to_string()),
+        ("size", block.size.to_string()),
+        ("weight", block.weight().to_string()),
+    ];
+    if let Some(chain_work) = block.chain_work {
+        fields.push(("chainwork", quote(&utils::work_to_hex(chain_work))));
+    }
+    if let Some(aux_pow) = &block.aux_pow_extension {
+        fields.push(("auxpow", aux_pow_json(aux_pow)));
+    }
+    if let Some(context) = &block.context {
+        fields.push(("index", context_json(context)));
+    }
+    let txs = block.txs.iter().map(|tx| tx_json(tx, 2)).collect();
+    fields.push(("txs", array(txs, 1)));
+    object(&fields, 0)
+}
+
+/// Merged mining proof, the transactions of the parent block are not included
+fn aux_pow_json(aux_pow: &AuxPowExtension) -> String {
+    let parent = &aux_pow.parent_block;
+    let fields = [
+        (
+            "parent_hash",
+            quote(&BlockHash::hash(&parent.to_bytes()).to_string()),
+        ),
+        ("parent_time", parent.timestamp.to_string()),
+        (
+            "coinbase_branch",
+            aux_pow.coinbase_branch.hashes.len().to_string(),
+        ),
+        (
+            "chain_branch",
+            aux_pow.blockchain_branch.hashes.len().to_string(),
+        ),
+        (
+            "chain_index",
+            aux_pow.blockchain_branch.side_mask.to_string(),
+        ),
+    ];
+    object(&fields, 1)
+}
+
+fn context_json(context: &BlockContext) -> String {
+    let mut fields = vec![
+        ("version", context.index_version.to_string()),
+        ("status", context.status.to_string()),
+    ];
+    if let Some(tx_count) = context.tx_count {
+        fields.push(("tx_count", tx_count.to_string()));
+    }
+    fields.extend([
+        ("file", context.blk_index.to_string()),
+        ("data_pos", context.data_offset.to_string()),
+    ]);
+    object(&fields, 1)
+}
+
+fn tx_json(tx: &Hashed<EvaluatedTx>, indent: usize) -> String {
+    let mut fields = vec![("txid", quote(&tx.hash.to_string()))];
+    if tx.value.has_witness() {
+        fields.push(("wtxid", quote(&tx.value.wtxid().to_string())));
+    }
+    fields.extend([
+        ("version", tx.value.version.to_string()),
+        ("locktime", tx.value.locktime.to_string()),
+        ("size", tx.value.to_bytes_with_witness().len().to_string()),
+        ("weight", tx.value.weight().to_string()),
+    ]);
+    if let Some(fee) = tx.value.fee() {
+        fields.push(("fee", fee.to_string()));
+    }
+    let inputs = tx
+        .value
+        .inputs
+        .iter()
+        .map(|input| input_json(input, indent + 2))
+        .collect();
+    let outputs = tx
+        .value
+        .outputs
+        .iter()
+        .map(|output| output_json(output, indent + 2))
+        .collect();
+    fields.push(("inputs", array(inputs, indent + 1)));
+    fields.push(("outputs", array(outputs, indent + 1)));
+    object(&fields, indent)
+}
+
+fn input_json(input: &TxInput, indent: usize) -> String {
+    let mut fields = vec![
+        ("txid", quote(&input.outpoint.txid.to_string())),
+        ("vout", input.outpoint.index.to_string()),
+        ("script_sig", quote(&utils::arr_to_hex(&input.script_sig))),
+        ("sequence", input.seq_no.to_string()),
+        ("pattern", quote(&input.pattern.to_string())),
+    ];
+    if !input.witness.is_empty() {
+        let items = input
+            .witness
+            .iter()
+            .map(|item| quote(&utils::arr_to_hex(item)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        fields.push(("witness", format!("[{}]", items)));
+    }
+    if let Some(prevout) = &input.prevout {
+        fields.push(("prevout", output_json(&prevout.out, indent + 1)));
+    }
+    object(&fields, indent)
+}
+
+fn output_json(output: &EvaluatedTxOut, indent: usize) -> String {
+    let mut fields = vec![
+        ("value", output.out.value.to_string()),
+        (
+            "script_pubkey",
+            quote(&utils::arr_to_hex(&output.out.script_pubkey)),
+        ),
+        ("pattern", quote(&output.script.pattern.to_string())),
+    ];
+    if let Some(address) = &output.script.address {
+        fields.push(("address", quote(address)));
+    }
+    object(&fields, indent)
+}
+
+/// Formats the already encoded values as JSON object, nested values start at `indent + 1`
+pub(super) fn object(fields: &[(&str, String)], indent: usize) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}\"{}\": {}", "  ".repeat(indent + 1), key, value))
+        .collect();
+    format!("{{\n{}\n{}}}", fields.join(",\n"), "  ".repeat(indent))
+}
+
+/// Formats the already encoded items as JSON array, items are expected at `indent + 1`
+pub(super) fn array(items: Vec<String>, indent: usize) -> String {
+    if items.is_empty() {
+        return String::from("[]");
+    }
+    let pad = "  ".repeat(indent + 1);
+    let items: Vec<String> = items
+        .iter()
+        .map(|item| format!("{}{}", pad, item))
+        .collect();
+    format!("[\n{}\n{}]", items.join(",\n"), "  ".repeat(indent))
+}
+
+pub(super) fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_ref() {
+        assert_eq!("42".parse::<BlockRef>().unwrap(), BlockRef::Height(42));
+        let hash = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        assert_eq!(
+            hash.parse::<BlockRef>().unwrap(),
+            BlockRef::Hash(BlockHash::from_str(hash).unwrap())
+        );
+        assert!("foo".parse::<BlockRef>().is_err());
+    }
+
+    #[test]
+    fn test_json_format() {
+        let fields = [
+            ("a", quote("x\"y")),
+            ("b", array(vec![object(&[("c", String::from("1"))], 2)], 1)),
+        ];
+        assert_eq!(
+            object(&fields, 0),
+            "{\n  \"a\": \"x\\\"y\",\n  \"b\": [\n    {\n      \"c\": 1\n    }\n  ]\n}"
+        );
+    }
+
+    #[test]
+    fn test_context_json() {
+        let context = BlockContext {
+            index_version: 259900,
+            status: 29,
+            tx_count: Some(2),
+            blk_index: 0,
+            data_offset: 8,
+        };
+        assert_eq!(
+            context_json(&context),
+            "{\n    \"version\": 259900,\n    \"status\": 29,\n    \"tx_count\": 2,\n    \"file\": 0,\n    \"data_pos\": 8\n  }"
+        );
+    }
+}
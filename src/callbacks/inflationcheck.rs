@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::parser::types::SubsidyFn;
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+use crate::blockchain::proto::{Hashed, TxId};
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Verifies that no transaction creates more value than it spends and no coinbase claims more
+/// than the subsidy plus fees, and dumps the cumulative coin supply per height.
+/// Violations are dumped to a second file as soon as they are found.
+/// Coinbases are only checked at heights where the subsidy of the coin is known.
+pub struct InflationCheck {
+    output: Output,
+    writer: Box<dyn Sink>,
+    violations_writer: Box<dyn Sink>,
+
+    subsidy: SubsidyFn,
+    // Maximum value of a single output or transaction
+    max_money: u64,
+
+    // Spent outputs are resolved by undo data if available, otherwise by the tracked unspents
+    use_undo_files: bool,
+    // Values of the unspent outputs
+    unspents: HashMap<TxOutpoint, u64>,
+
+    start_height: u64,
+    // Sum of all coinbase outputs
+    supply: u64,
+    // Sum of all outputs which can never be spent (OP_RETURN and provably unspendable scripts)
+    unspendable: u64,
+    n_txs: u64,
+    n_unresolved: u64,
+    n_unknown_subsidy: u64,
+    n_violations: u64,
+}
+
+impl InflationCheck {
+    /// Returns the value of all spent outputs, None if any of them is unknown or the sum overflows
+    fn input_value(&mut self, tx: &EvaluatedTx) -> Option<u64> {
+        if self.use_undo_files {
+            return tx.input_value();
+        }
+        // Remove all spent outputs, even if one of them is unknown
+        let mut in_value = Some(0u64);
+        for input in &tx.inputs {
+            let value = self.unspents.remove(&input.outpoint);
+            in_value = in_value
+                .zip(value)
+                .and_then(|(sum, value)| sum.checked_add(value));
+        }
+        in_value
+    }
+
+    /// Keeps track of the spendable outputs if there is no undo data
+    /// and returns the value of the unspendable ones
+    fn add_outputs(&mut self, tx: &Hashed<EvaluatedTx>) -> u64 {
+        let mut unspendable = 0;
+        for (i, output) in tx.value.outputs.iter().enumerate() {
+            if let ScriptPattern::OpReturn(_) | ScriptPattern::Unspendable = output.script.pattern {
+                unspendable = output.out.value.saturating_add(unspendable);
+                continue;
+            }
+            if !self.use_undo_files {
+                self.unspents
+                    .insert(TxOutpoint::new(tx.hash, i as u32), output.out.value);
+            }
+        }
+        unspendable
+    }
+
+    fn write_violation(
+        &mut self,
+        block_height: u64,
+        txid: &TxId,
+        reason: &str,
+        allowed: u64,
+        value: u64,
+    ) -> OpResult<()> {
+        warn!(target: "inflationcheck", "Block {}: {} in {} creates {} satoshis, only {} are allowed!",
+              block_height, reason, txid, value, allowed);
+        // (height, txid, reason, allowed, value)
+        self.violations_writer.write_all(
+            format!(
+                "{};{};{};{};{}\n",
+                block_height, txid, reason, allowed, value
+            )
+            .as_bytes(),
+        )?;
+        self.n_violations += 1;
+        Ok(())
+    }
+
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "InflationCheck:")?;
+        writeln!(buffer, "   -> transactions:\t\t{}", self.n_txs)?;
+        writeln!(buffer, "   -> unresolved:\t\t{}", self.n_unresolved)?;
+        writeln!(
+            buffer,
+            "   -> unknown subsidy:\t\t{}",
+            self.n_unknown_subsidy
+        )?;
+        writeln!(buffer, "   -> violations:\t\t{}", self.n_violations)?;
+        writeln!(
+            buffer,
+            "   -> supply:\t\t\t{:.8}",
+            self.supply as f64 * 1E-8
+        )?;
+        writeln!(
+            buffer,
+            "   -> unspendable:\t\t{:.8}",
+            self.unspendable as f64 * 1E-8
+        )?;
+        Ok(())
+    }
+}
+
+impl Callback for InflationCheck {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("inflationcheck")
+            .about("Verifies that no transaction creates value and dumps the coin supply per height to CSV files")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv files")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = InflationCheck {
+            writer: output.open("inflationcheck")?,
+            violations_writer: output.open("violations")?,
+            output,
+            subsidy: context.coin.subsidy,
+            max_money: context.coin.max_money,
+            use_undo_files: context.use_undo_files,
+            unspents: HashMap::with_capacity(10000000),
+            start_height: 0,
+            supply: 0,
+            unspendable: 0,
+            n_txs: 0,
+            n_unresolved: 0,
+            n_unknown_subsidy: 0,
+            n_violations: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing inflationcheck with dump folder: {} ...", &self.output);
+        if block_height > 0 {
+            warn!(target: "callback", "Supply is only counted from block {}.", block_height);
+        }
+        self.writer
+            .write_all("height;hash;subsidy;fees;claimed;supply;unspendable\n".as_bytes())?;
+        self.violations_writer
+            .write_all("height;txid;reason;allowed;value\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let mut fees = Some(0u64);
+        let mut claimed = 0;
+        for tx in &block.txs {
+            // An overflowing sum is out of range as well
+            let out_value = tx.value.checked_output_value().unwrap_or(u64::MAX);
+            if let Some(value) = tx
+                .value
+                .outputs
+                .iter()
+                .map(|output| output.out.value)
+                .chain([out_value])
+                .find(|value| *value > self.max_money)
+            {
+                self.write_violation(block_height, &tx.hash, "range", self.max_money, value)?;
+            }
+
+            if tx.value.is_coinbase() {
+                claimed = out_value.saturating_add(claimed);
+            } else {
+                match self.input_value(&tx.value) {
+                    Some(in_value) if out_value > in_value => {
+                        self.write_violation(
+                            block_height,
+                            &tx.hash,
+                            "outputs",
+                            in_value,
+                            out_value,
+                        )?;
+                        fees = None;
+                    }
+                    Some(in_value) => {
+                        fees = fees.and_then(|sum| sum.checked_add(in_value - out_value))
+                    }
+                    None => {
+                        self.n_unresolved += 1;
+                        fees = None;
+                    }
+                }
+            }
+            self.unspendable = self.add_outputs(tx).saturating_add(self.unspendable);
+            self.n_txs += 1;
+        }
+
+        // The coinbase can only be checked if the subsidy and all fees are known
+        let subsidy = (self.subsidy)(block_height);
+        match (subsidy, fees) {
+            (Some(subsidy), Some(fees)) => {
+                let allowed = subsidy.saturating_add(fees);
+                if claimed > allowed {
+                    let coinbase = &block.txs[0].hash;
+                    self.write_violation(block_height, coinbase, "coinbase", allowed, claimed)?;
+                }
+            }
+            (None, _) => {
+                if self.n_unknown_subsidy == 0 {
+                    warn!(target: "inflationcheck", "The subsidy of block {} is not known, its coinbase is not checked", block_height);
+                }
+                self.n_unknown_subsidy += 1;
+            }
+            _ => {}
+        }
+        self.supply = self.supply.saturating_add(claimed);
+
+        // (height, hash, subsidy, fees, claimed, supply, unspendable)
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{}\n",
+                block_height,
+                &block.header.hash,
+                subsidy
+                    .map(|subsidy| subsidy.to_string())
+                    .unwrap_or_default(),
+                fees.map(|fees| fees.to_string()).unwrap_or_default(),
+                claimed,
+                self.supply,
+                self.unspendable
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+        self.violations_writer
+            .finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "inflationcheck", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
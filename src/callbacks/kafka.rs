@@ -0,0 +1,268 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use clap::builder::PossibleValuesParser;
+use clap::{Arg, ArgMatches, Command};
+#[cfg(feature = "protobuf")]
+use prost::Message;
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext};
+use rdkafka::ClientContext;
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::events;
+#[cfg(feature = "protobuf")]
+use crate::callbacks::protodump::schema;
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Maximum time to wait for outstanding messages when parsing is complete
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Values of `--format`, protobuf is only available with the protobuf feature
+const FORMATS: &[&str] = &[
+    "json",
+    #[cfg(feature = "protobuf")]
+    "protobuf",
+];
+
+/// Encoding of the messages
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// See `events`
+    Json,
+    /// `Block` without transactions and `Tx` of proto/blockchain.proto
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+}
+
+impl Format {
+    fn block_message(self, block: &Block, block_height: u64) -> Vec<u8> {
+        match self {
+            Format::Json => events::block_json(block, block_height).into_bytes(),
+            #[cfg(feature = "protobuf")]
+            Format::Protobuf => schema::Block::without_txs(block, block_height).encode_to_vec(),
+        }
+    }
+
+    fn tx_message(self, tx: &Hashed<EvaluatedTx>, block_height: u64) -> Vec<u8> {
+        match self {
+            Format::Json => events::tx_json(tx, block_height).into_bytes(),
+            #[cfg(feature = "protobuf")]
+            Format::Protobuf => schema::Tx::new(tx).encode_to_vec(),
+        }
+    }
+}
+
+/// Counts messages which couldn't be delivered after all retries
+#[derive(Default)]
+struct DeliveryContext {
+    n_failed: AtomicU64,
+}
+
+impl ClientContext for DeliveryContext {}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: Self::DeliveryOpaque) {
+        if let Err((err, _)) = result {
+            error!(target: "kafka", "Unable to deliver message: {}", err);
+            self.n_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Publishes blocks and optionally transactions as JSON or protobuf messages to Kafka (or Redpanda).
+/// The block height is used as message key, so all messages of a block end up in the same partition.
+pub struct Kafka {
+    producer: BaseProducer<DeliveryContext>,
+    brokers: String,
+    blocks_topic: String,
+    txs_topic: Option<String>,
+    format: Format,
+
+    n_messages: u64,
+}
+
+impl Kafka {
+    /// Enqueues a message, waits for the local queue to drain if it is full
+    fn send(&self, topic: &str, key: &str, payload: &[u8]) -> OpResult<()> {
+        let mut record = BaseRecord::to(topic).key(key).payload(payload);
+        loop {
+            match self.producer.send(record) {
+                Ok(()) => return Ok(()),
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), rec)) => {
+                    self.producer.poll(Duration::from_millis(100));
+                    record = rec;
+                }
+                Err((err, _)) => return Err(OpError::from(err)),
+            }
+        }
+    }
+}
+
+impl Callback for Kafka {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("kafka")
+            .about("Publishes blocks and transactions as JSON or protobuf messages to Kafka topics")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("brokers")
+                    .long("brokers")
+                    .value_name("HOST:PORT")
+                    .default_value("localhost:9092")
+                    .help("Comma separated list of bootstrap brokers"),
+            )
+            .arg(
+                Arg::new("blocks-topic")
+                    .long("blocks-topic")
+                    .value_name("TOPIC")
+                    .default_value("blocks")
+                    .help("Topic for block messages"),
+            )
+            .arg(
+                Arg::new("txs-topic")
+                    .long("txs-topic")
+                    .value_name("TOPIC")
+                    .help(
+                        "Topic for transaction messages (default: transactions are not published)",
+                    ),
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_name("FORMAT")
+                    .value_parser(PossibleValuesParser::new(FORMATS))
+                    .default_value("json")
+                    .help("Encoding of the messages, protobuf uses the types of proto/blockchain.proto and requires the protobuf feature"),
+            )
+            .arg(
+                Arg::new("linger-ms")
+                    .long("linger-ms")
+                    .value_name("MS")
+                    .value_parser(clap::value_parser!(u32))
+                    .default_value("50")
+                    .help("Time to wait for more messages before a batch is sent"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let brokers = matches.get_one::<String>("brokers").unwrap().clone();
+        // Idempotence implies acks=all and retries without duplicates or reordering
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("enable.idempotence", "true")
+            .set(
+                "linger.ms",
+                matches.get_one::<u32>("linger-ms").unwrap().to_string(),
+            )
+            .set("compression.type", "lz4")
+            .create_with_context(DeliveryContext::default())?;
+        let cb = Kafka {
+            producer,
+            brokers,
+            blocks_topic: matches.get_one::<String>("blocks-topic").unwrap().clone(),
+            txs_topic: matches.get_one::<String>("txs-topic").cloned(),
+            format: match matches.get_one::<String>("format").unwrap().as_str() {
+                #[cfg(feature = "protobuf")]
+                "protobuf" => Format::Protobuf,
+                _ => Format::Json,
+            },
+            n_messages: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, _: u64) -> OpResult<()> {
+        info!(target: "callback", "Executing kafka with brokers: {} ...", &self.brokers);
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let key = block_height.to_string();
+        self.send(
+            &self.blocks_topic,
+            &key,
+            &self.format.block_message(block, block_height),
+        )?;
+        self.n_messages += 1;
+        if let Some(txs_topic) = &self.txs_topic {
+            for tx in &block.txs {
+                self.send(txs_topic, &key, &self.format.tx_message(tx, block_height))?;
+            }
+            self.n_messages += block.txs.len() as u64;
+        }
+        // Serve delivery callbacks
+        self.producer.poll(Duration::ZERO);
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        info!(target: "callback", "Waiting for outstanding messages to be delivered ...");
+        self.producer.flush(FLUSH_TIMEOUT)?;
+
+        let n_failed = self.producer.context().n_failed.load(Ordering::Relaxed);
+        if n_failed > 0 {
+            let msg = format!(
+                "{} of {} messages could not be delivered",
+                n_failed, self.n_messages
+            );
+            return Err(OpError::new(OpErrorKind::CallbackError).join_msg(&msg));
+        }
+        info!(target: "callback", "Done. Published {} messages up to height {}.", self.n_messages, block_height);
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::chain::ChainStorage;
+    use crate::common::fixtures::MiniChain;
+    use crate::{command, parse_args};
+
+    #[test]
+    fn test_messages() {
+        let chain = MiniChain::generate(2);
+        let argv = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(argv)).unwrap();
+        let mut storage = ChainStorage::new(&options).unwrap();
+        let block = storage.get_block(1).unwrap().ok().unwrap();
+
+        let message = String::from_utf8(Format::Json.block_message(&block, 1)).unwrap();
+        assert!(message.starts_with("{\"type\":\"block\",\"height\":1,"));
+        let message = String::from_utf8(Format::Json.tx_message(&block.txs[1], 1)).unwrap();
+        assert!(message.contains(&format!("\"txid\":\"{}\"", chain.txids[1][1])));
+
+        #[cfg(feature = "protobuf")]
+        {
+            let message = Format::Protobuf.block_message(&block, 1);
+            let decoded = schema::Block::decode(message.as_slice()).unwrap();
+            assert_eq!(decoded, schema::Block::without_txs(&block, 1));
+            assert!(decoded.txs.is_empty());
+            let message = Format::Protobuf.tx_message(&block.txs[1], 1);
+            let decoded = schema::Tx::decode(message.as_slice()).unwrap();
+            assert_eq!(decoded.outputs.len(), 3);
+        }
+    }
+}
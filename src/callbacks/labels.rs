@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use clap::{Arg, ArgMatches};
+
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Maps addresses to labels (exchange names, known entities, etc.) loaded from a csv file.
+/// Labels are interned since a few labels usually cover millions of addresses.
+pub struct Labels {
+    index: HashMap<String, u32>,
+    names: Vec<String>,
+}
+
+impl Labels {
+    /// Builds the optional `--labels` argument for callbacks that support labeling
+    pub fn build_arg() -> Arg {
+        Arg::new("labels")
+            .long("labels")
+            .value_name("FILE")
+            .help("Appends a label column using a csv file with address,label lines")
+    }
+
+    /// Loads the labels if `--labels` was specified
+    pub fn from_matches(matches: &ArgMatches) -> OpResult<Option<Self>> {
+        match matches.get_one::<String>("labels") {
+            Some(path) => Ok(Some(Labels::from_path(Path::new(path))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads a csv file with `address,label` lines.
+    /// Empty lines, lines starting with `#` and an optional header are skipped.
+    pub fn from_path(path: &Path) -> OpResult<Self> {
+        info!(target: "labels", "Reading labels from {} ...", path.display());
+        let mut labels = Labels {
+            index: HashMap::new(),
+            names: Vec::new(),
+        };
+        let mut name_ids: HashMap<String, u32> = HashMap::new();
+
+        let reader = BufReader::new(File::open(path)?);
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (address, label) = match line.split_once(',') {
+                Some((address, label)) => (address.trim(), label.trim()),
+                None => {
+                    let msg = format!("{}:{}: expected address,label", path.display(), n + 1);
+                    return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+                }
+            };
+            if n == 0 && address.eq_ignore_ascii_case("address") {
+                continue;
+            }
+            // Semicolons are used as separator in the csv dumps
+            let label = label.replace(';', ",");
+            let label = label.as_str();
+
+            let id = match name_ids.get(label) {
+                Some(id) => *id,
+                None => {
+                    let id = labels.names.len() as u32;
+                    labels.names.push(String::from(label));
+                    name_ids.insert(String::from(label), id);
+                    id
+                }
+            };
+            labels.index.insert(String::from(address), id);
+        }
+        info!(target: "labels", "Loaded {} labels for {} addresses.", labels.names.len(), labels.index.len());
+        Ok(labels)
+    }
+
+    /// Returns the label for the given address
+    #[inline]
+    pub fn get(&self, address: &str) -> Option<&str> {
+        self.index
+            .get(address)
+            .map(|id| self.names[*id as usize].as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_labels_from_path() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "address,label").unwrap();
+        writeln!(file, "1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn,Exchange A").unwrap();
+        writeln!(file, "# comment").unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, "1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL, Exchange A ").unwrap();
+        writeln!(file, "13gv9XbKJPxxRF8Zm1LsVKeeiMCFguQPqm,Miner, Inc.").unwrap();
+
+        let labels = Labels::from_path(file.path()).unwrap();
+        assert_eq!(labels.names.len(), 2);
+        assert_eq!(
+            labels.get("1JqDybm2nWTENrHvMyafbSXXtTk5Uv5QAn"),
+            Some("Exchange A")
+        );
+        assert_eq!(
+            labels.get("1EYXXHs5gV4pc7QAddmDj5z7m14QPHGvWL"),
+            Some("Exchange A")
+        );
+        assert_eq!(
+            labels.get("13gv9XbKJPxxRF8Zm1LsVKeeiMCFguQPqm"),
+            Some("Miner, Inc.")
+        );
+        assert_eq!(labels.get("address"), None);
+        assert_eq!(labels.get("unknown"), None);
+
+        writeln!(file, "invalid").unwrap();
+        assert!(Labels::from_path(file.path()).is_err());
+    }
+}
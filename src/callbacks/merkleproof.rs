@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::str::FromStr;
+
+use bitcoin::hashes::sha256d;
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::TxId;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Dumps the merkle branch of selected transactions, which is needed for SPV verification
+pub struct MerkleProof {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    // Transactions which were not found yet
+    txids: HashSet<TxId>,
+    start_height: u64,
+    n_proofs: u64,
+}
+
+impl MerkleProof {
+    fn parse_txid(txid: &str) -> OpResult<TxId> {
+        TxId::from_str(txid.trim()).map_err(|e| {
+            OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("Invalid txid {}: {}", txid, e))
+        })
+    }
+
+    /// Collects txids from `--txid` and `--txids-file`
+    fn collect_txids(matches: &ArgMatches) -> OpResult<HashSet<TxId>> {
+        let mut txids = HashSet::new();
+        if let Some(values) = matches.get_many::<String>("txid") {
+            for txid in values {
+                txids.insert(MerkleProof::parse_txid(txid)?);
+            }
+        }
+        if let Some(path) = matches.get_one::<String>("txids-file") {
+            let reader = BufReader::new(File::open(path)?);
+            for line in reader.lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    txids.insert(MerkleProof::parse_txid(&line)?);
+                }
+            }
+        }
+        Ok(txids)
+    }
+}
+
+impl Callback for MerkleProof {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("merkleproof")
+            .about("Dumps merkle proofs for the given transactions to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("txid")
+                    .long("txid")
+                    .value_name("TXID")
+                    .action(ArgAction::Append)
+                    .help("Transaction to create a proof for, can be specified multiple times"),
+            )
+            .arg(
+                Arg::new("txids-file")
+                    .long("txids-file")
+                    .value_name("FILE")
+                    .help("File with one txid per line"),
+            )
+            .group(
+                ArgGroup::new("txids")
+                    .args(["txid", "txids-file"])
+                    .multiple(true)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = MerkleProof {
+            writer: output.open("merkleproofs")?,
+            output,
+            txids: MerkleProof::collect_txids(matches)?,
+            start_height: 0,
+            n_proofs: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing merkleproof for {} transactions with dump folder: {} ...",
+              self.txids.len(), &self.output);
+        self.writer
+            .write_all("txid;height;blockHash;merkleRoot;index;branch;positions\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        if self.txids.is_empty() {
+            return Ok(());
+        }
+        let hashes: Vec<sha256d::Hash> = block.txs.iter().map(|tx| tx.hash.to_raw_hash()).collect();
+        for (index, tx) in block.txs.iter().enumerate() {
+            if !self.txids.remove(&tx.hash) {
+                continue;
+            }
+            let branch = utils::merkle_branch(&hashes, index);
+            let hashes = branch
+                .iter()
+                .map(|(hash, _)| hash.to_string())
+                .collect::<Vec<String>>()
+                .join(",");
+            // 1 if the sibling is on the right side
+            let positions = branch
+                .iter()
+                .map(|(_, is_right)| (*is_right as u8).to_string())
+                .collect::<Vec<String>>()
+                .join(",");
+            // (@txid, height, @blockHash, @merkleRoot, index, branch, positions)
+            self.writer.write_all(
+                format!(
+                    "{};{};{};{};{};{};{}\n",
+                    &tx.hash,
+                    &block_height,
+                    &block.header.hash,
+                    &block.header.value.merkle_root,
+                    &index,
+                    &hashes,
+                    &positions
+                )
+                .as_bytes(),
+            )?;
+            self.n_proofs += 1;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+        for txid in &self.txids {
+            warn!(target: "callback", "Transaction {} not found", txid);
+        }
+        info!(target: "callback", "Done.\nDumped {} merkle proofs.", self.n_proofs);
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::parser::types::SubsidyFn;
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::common::extract_miner_tag;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Maximum block weight as defined in BIP141
+pub(super) const MAX_BLOCK_WEIGHT: u64 = 4000000;
+/// Script prefix of the BIP141 witness commitment in the coinbase
+const WITNESS_COMMITMENT: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+/// Miner of blocks without a coinbase tag
+const UNKNOWN: &str = "Unknown";
+
+/// Aggregated block template figures of a single miner
+#[derive(Default)]
+struct PoolStats {
+    n_blocks: u64,
+    n_txs: u64,
+    weight: u64,
+    // Blocks where the fees of all transactions are known
+    n_fee_blocks: u64,
+    fees: u64,
+    // Subsidy and fees not claimed by the coinbase outputs, for blocks with known subsidy and fees
+    unclaimed: u64,
+    // Weight of OP_RETURN outputs, the witness commitment is counted separately
+    op_return_weight: u64,
+    commitment_weight: u64,
+}
+
+impl PoolStats {
+    fn add_block(&mut self, block: &Block, subsidy: Option<u64>) {
+        self.n_blocks += 1;
+        self.n_txs += block.txs.len() as u64;
+        self.weight += block.weight();
+
+        let mut fees = Some(0u64);
+        let mut claimed = 0;
+        for tx in &block.txs {
+            let is_coinbase = tx.value.is_coinbase();
+            if is_coinbase {
+                claimed += tx.value.output_value();
+            } else {
+                fees = fees.zip(tx.value.fee()).map(|(sum, fee)| sum + fee);
+            }
+            for output in &tx.value.outputs {
+                if !matches!(output.script.pattern, ScriptPattern::OpReturn(_)) {
+                    continue;
+                }
+                // Outputs are not part of the witness, so each byte counts 4 weight units
+                let weight = output.out.to_bytes().len() as u64 * 4;
+                match is_coinbase && output.out.script_pubkey.starts_with(&WITNESS_COMMITMENT) {
+                    true => self.commitment_weight += weight,
+                    false => self.op_return_weight += weight,
+                }
+            }
+        }
+        if let Some(fees) = fees {
+            self.n_fee_blocks += 1;
+            self.fees += fees;
+            if let Some(subsidy) = subsidy {
+                self.unclaimed += (subsidy + fees).saturating_sub(claimed);
+            }
+        }
+    }
+
+    /// Returns the average share of the maximum block weight in percent
+    fn utilization(&self) -> f64 {
+        self.weight as f64 / (self.n_blocks * MAX_BLOCK_WEIGHT).max(1) as f64 * 100.00
+    }
+
+    fn as_csv(&self, miner: &str) -> String {
+        // (miner, blocks, txs, avgWeight, utilization, feeBlocks, fees, unclaimed,
+        //  opReturnWeight, commitmentWeight)
+        format!(
+            "{};{};{};{};{:.2};{};{};{};{};{}\n",
+            miner,
+            self.n_blocks,
+            self.n_txs,
+            self.weight / self.n_blocks.max(1),
+            self.utilization(),
+            self.n_fee_blocks,
+            self.fees,
+            self.unclaimed,
+            self.op_return_weight,
+            self.commitment_weight
+        )
+    }
+}
+
+/// Returns all miners sorted by the number of mined blocks
+fn sorted(miners: &HashMap<String, PoolStats>) -> Vec<(&String, &PoolStats)> {
+    let mut miners: Vec<(&String, &PoolStats)> = miners.iter().collect();
+    miners.sort_unstable_by(|a, b| b.1.n_blocks.cmp(&a.1.n_blocks).then_with(|| a.0.cmp(b.0)));
+    miners
+}
+
+/// Summarizes the block templates per miner, identified by the coinbase tag:
+/// weight utilization, collected fees vs. the claimable reward and the OP_RETURN/commitment overhead.
+pub struct MinerStats {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    subsidy: SubsidyFn,
+    use_undo_files: bool,
+    start_height: u64,
+    miners: HashMap<String, PoolStats>,
+}
+
+impl MinerStats {
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let n_blocks: u64 = self.miners.values().map(|m| m.n_blocks).sum();
+        writeln!(buffer, "MinerStats:")?;
+        writeln!(buffer, "   -> blocks:\t\t\t{}", n_blocks)?;
+        writeln!(buffer, "   -> miners:\t\t\t{}", self.miners.len())?;
+        writeln!(buffer, "   Top miners (blocks, utilization, unclaimed):")?;
+        for (miner, stats) in sorted(&self.miners).iter().take(25) {
+            writeln!(
+                buffer,
+                "     -> {}: {} ({:.2}%), {:.2}%, {:.8}",
+                miner,
+                stats.n_blocks,
+                stats.n_blocks as f64 / n_blocks.max(1) as f64 * 100.00,
+                stats.utilization(),
+                stats.unclaimed as f64 * 1E-8
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for MinerStats {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("minerstats")
+            .about(
+                "Dumps block weight utilization, fees and OP_RETURN overhead per miner to CSV file",
+            )
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = MinerStats {
+            writer: output.open("minerstats")?,
+            output,
+            subsidy: context.coin.subsidy,
+            use_undo_files: context.use_undo_files,
+            start_height: 0,
+            miners: HashMap::new(),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing minerstats with dump folder: {} ...", &self.output);
+        if !self.use_undo_files {
+            warn!(target: "callback", "Fees are unknown for blocks with transactions, use --use-undo-files to resolve them.");
+        }
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let miner = block
+            .txs
+            .first()
+            .and_then(|tx| tx.value.inputs.first())
+            .and_then(|input| extract_miner_tag(&input.script_sig))
+            .unwrap_or_else(|| String::from(UNKNOWN));
+        self.miners
+            .entry(miner)
+            .or_default()
+            .add_block(block, (self.subsidy)(block_height));
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.write_all(
+            "miner;blocks;txs;avgWeight;utilization;feeBlocks;fees;unclaimed;opReturnWeight;commitmentWeight\n"
+                .as_bytes(),
+        )?;
+        for (miner, stats) in sorted(&self.miners) {
+            self.writer.write_all(stats.as_csv(miner).as_bytes())?;
+        }
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "minerstats", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
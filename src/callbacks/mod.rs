@@ -1,17 +1,72 @@
 use clap::{ArgMatches, Command};
 
+use crate::blockchain::parser::types::CoinType;
 use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
 use crate::errors::OpResult;
 
+pub mod auxpowstats;
+pub mod balancehistory;
 pub mod balances;
+pub mod bench;
+pub mod burns;
+pub mod chainwork;
+mod change;
+pub mod clusterflows;
+mod clusters;
 mod common;
 pub mod csvdump;
+pub mod depositpatterns;
+pub mod difficulty;
+pub mod entities;
+mod events;
+pub mod extractdata;
+pub mod feemarket;
+pub mod feesamples;
+pub mod getblock;
+pub mod inflationcheck;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+mod labels;
+pub mod merkleproof;
+pub mod minerstats;
+pub mod namecoinnames;
 pub mod opreturn;
+#[cfg(feature = "protobuf")]
+pub mod protodump;
+pub mod scriptflows;
+pub mod scriptstats;
+pub mod sigstats;
 pub mod simplestats;
+pub(crate) mod sink;
+mod sorter;
+pub mod stream;
+pub mod supply;
+mod tempoutput;
+pub mod trace;
+pub mod txshape;
 pub mod unspentcsvdump;
+mod utxodb;
+pub mod versionbits;
+pub mod visualize;
+pub mod walletfingerprint;
+mod warmstart;
+pub mod watch;
+pub mod witnessstats;
+
+/// Settings of the run a callback may depend on, passed to `Callback::new`
+#[derive(Default)]
+pub struct CallbackContext {
+    pub coin: CoinType,
+    /// Spent outputs are resolved from the rev files (see `--use-undo-files`)
+    pub use_undo_files: bool,
+}
 
 /// Implement this trait for a custom Callback.
 /// The parser ensures that the blocks arrive in the correct order.
+/// If only a subset of blocks is selected (see `--only-blk-files` and `--heights-file`),
+/// the heights are ascending but not necessarily consecutive.
 /// At this stage the main chain is already determined and orphans/stales are removed.
 pub trait Callback {
     /// Builds Command to specify callback name and required args,
@@ -21,21 +76,1375 @@ pub trait Callback {
         Self: Sized;
 
     /// Instantiates callback
-    fn new(matches: &ArgMatches) -> OpResult<Self>
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
     where
         Self: Sized;
 
     /// Gets called shortly before the blocks are parsed.
     fn on_start(&mut self, block_height: u64) -> OpResult<()>;
 
+    /// Gets called for each transaction of a new block, in block order and before `on_block`.
+    /// `index` is the position of the transaction within the block, the coinbase being 0.
+    fn on_transaction(
+        &mut self,
+        _tx: &Hashed<EvaluatedTx>,
+        _block_height: u64,
+        _index: usize,
+    ) -> OpResult<()> {
+        Ok(())
+    }
+
     /// Gets called if a new block is available.
     fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()>;
 
     /// Gets called if the parser has finished and all blocks are handled
     fn on_complete(&mut self, block_height: u64) -> OpResult<()>;
 
+    /// Gets called instead of `on_complete` if the run failed, e.g. because the disk is full.
+    /// `block_height` is the last height which was handled completely, None if there is none.
+    /// Output which was not finished is discarded, unless the callback keeps what is safe to keep.
+    fn on_abort(&mut self, _block_height: Option<u64>) {}
+
     /// Can be used to toggle whether the progress should be shown for specific callbacks or not
     fn show_progress(&self) -> bool {
         true
     }
+
+    /// Callbacks which only need the block headers can return true here.
+    /// The parser skips the transactions then and passes blocks with an empty `txs` vector.
+    fn headers_only(&self) -> bool {
+        false
+    }
+
+    /// Callbacks which never look at `EvaluatedTxOut::script.address` can return false here.
+    /// The parser only classifies the output scripts then and skips the address encoding.
+    fn wants_addresses(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::str::FromStr;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use bitcoin::hashes::sha256d;
+    use rusty_leveldb::LdbIterator;
+
+    use crate::blockchain::parser::chain::ChainStorage;
+    use crate::blockchain::parser::BlockchainParser;
+    use crate::blockchain::proto::script::eval_from_bytes_bitcoin;
+    use crate::common::fixtures::{self, MiniChain};
+    use crate::common::utils;
+    use crate::errors::OpResult;
+    use crate::query::merge::Merge;
+    use crate::{command, parse_args, BlockHeightRange};
+
+    const N_BLOCKS: u64 = 5;
+
+    /// Runs the parser with the given callback args over the mini-chain
+    fn run(chain: &MiniChain, args: &[&str]) {
+        try_run(chain, args).unwrap();
+    }
+
+    fn try_run(chain: &MiniChain, args: &[&str]) -> OpResult<()> {
+        let mut argv = vec!["rusty-blockparser", "-d", chain.path().to_str().unwrap()];
+        argv.extend(args);
+        let options = parse_args(command().get_matches_from(argv))?;
+        let chain_storage = ChainStorage::new(&options)?;
+        BlockchainParser::new(options, chain_storage).start()
+    }
+
+    /// Returns all lines of the csv file with the given name, dumped for the whole mini-chain
+    fn read_csv(dump_folder: &Path, name: &str) -> Vec<String> {
+        let path = dump_folder.join(format!("{}-0-{}.csv", name, N_BLOCKS - 1));
+        let content =
+            fs::read_to_string(&path).unwrap_or_else(|_| panic!("{} not found", path.display()));
+        content.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_csvdump() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["csvdump", dump_dir.path().to_str().unwrap()]);
+
+        let blocks = read_csv(dump_dir.path(), "blocks");
+        assert_eq!(blocks.len(), N_BLOCKS as usize);
+        assert!(blocks[4].starts_with(&format!("{};4;", chain.hashes[4])));
+        // Each block of the mini-chain has a work of 2
+        assert!(blocks[4]
+            .ends_with(";000000000000000000000000000000000000000000000000000000000000000a"));
+        let txs = read_csv(dump_dir.path(), "transactions");
+        assert_eq!(txs.len(), 9);
+        // Input values and fees are unknown without undo data
+        let fields: Vec<&str> = txs[2].split(';').collect();
+        assert_eq!(fields[0], chain.txids[1][1].to_string());
+        assert_eq!(fields[4..7], ["", "5000000000", ""]);
+        let (size, vsize, weight): (u64, u64, u64) = (
+            fields[7].parse().unwrap(),
+            fields[8].parse().unwrap(),
+            fields[9].parse().unwrap(),
+        );
+        // No witness data
+        assert_eq!((vsize, weight), (size, size * 4));
+        // Height, position in the block and coinbase flag
+        assert_eq!(fields[10..13], ["1", "1", "0"]);
+        assert!(txs[1].ends_with(";1;0;1"));
+        assert_eq!(read_csv(dump_dir.path(), "tx_in").len(), 9);
+        assert_eq!(read_csv(dump_dir.path(), "tx_out").len(), 17);
+    }
+
+    #[test]
+    fn test_csvdump_undo() {
+        let chain = MiniChain::generate_with_undo(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_folder = dump_dir.path().to_str().unwrap();
+        run(&chain, &["--use-undo-files", "csvdump", dump_folder]);
+        let txs = read_csv(dump_dir.path(), "transactions");
+        let fields: Vec<&str> = txs[2].split(';').collect();
+        assert_eq!(fields[4..7], ["5000000000", "5000000000", "0"]);
+
+        // A damaged undo record aborts the run instead of ending the dump early
+        let rev_path = chain.path().join("rev00000.dat");
+        let mut rev = fs::read(&rev_path).unwrap();
+        rev.truncate(rev.len() - 40);
+        fs::write(&rev_path, &rev).unwrap();
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_folder = dump_dir.path().to_str().unwrap();
+        let result = try_run(&chain, &["--use-undo-files", "csvdump", dump_folder]);
+        assert!(result.is_err());
+        assert!(!dump_dir
+            .path()
+            .join(format!("transactions-0-{}.csv", N_BLOCKS - 1))
+            .exists());
+    }
+
+    #[test]
+    fn test_csvdump_fsync() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_folder = dump_dir.path().to_str().unwrap();
+        run(
+            &chain,
+            &[
+                "csvdump",
+                dump_folder,
+                "--flush-interval",
+                "1",
+                "--fsync-interval",
+                "2",
+            ],
+        );
+        assert_eq!(read_csv(dump_dir.path(), "blocks").len(), N_BLOCKS as usize);
+        assert_eq!(read_csv(dump_dir.path(), "tx_out").len(), 17);
+    }
+
+    #[test]
+    fn test_balances() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["balances", dump_dir.path().to_str().unwrap()]);
+
+        let lines = read_csv(dump_dir.path(), "balances");
+        assert_eq!(lines[0], "address;balance;scriptHash");
+        let mut balances: Vec<u64> = lines[1..]
+            .iter()
+            .map(|l| l.split(';').nth(1).unwrap().parse().unwrap())
+            .collect();
+        balances.sort_unstable();
+        // 4x P2WPKH outputs, the last coinbase and the shared P2PKH address
+        assert_eq!(
+            balances,
+            [
+                2500000000,
+                2500000000,
+                2500000000,
+                2500000000,
+                5000000000,
+                10000000000
+            ]
+        );
+
+        run(
+            &chain,
+            &["balances", dump_dir.path().to_str().unwrap(), "--top", "2"],
+        );
+        let lines = read_csv(dump_dir.path(), "richlist");
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "rank;address;balance;scriptHash");
+        assert!(lines[1].starts_with("1;") && lines[1].ends_with(";10000000000;"));
+        assert!(lines[2].starts_with("2;") && lines[2].ends_with(";5000000000;"));
+
+        run(
+            &chain,
+            &[
+                "balances",
+                dump_dir.path().to_str().unwrap(),
+                "--include-script-type",
+                "--include-script",
+                "--include-utxo-count",
+                "--include-last-activity",
+            ],
+        );
+        let lines = read_csv(dump_dir.path(), "balances");
+        assert_eq!(
+            lines[0],
+            "address;balance;scriptHash;scriptType;script;utxos;lastActivity"
+        );
+        assert!(lines.contains(&String::from(
+            "1QLbz7JHiBTspS962RLKV8GndWFwi5j6Qr;10000000000;;Pay2PublicKeyHash;;4;4"
+        )));
+        assert!(lines.contains(&String::from(
+            "bc1qqyqszqgpqyqszqgpqyqszqgpqyqszqgpyfl4f3;2500000000;;Pay2WitnessPublicKeyHash;;1;1"
+        )));
+    }
+
+    #[test]
+    fn test_balances_snapshots() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(
+            &chain,
+            &[
+                "balances",
+                dump_dir.path().to_str().unwrap(),
+                "--snapshot-height",
+                "3,1",
+            ],
+        );
+
+        for height in [1u64, 3] {
+            let path = dump_dir.path().join(format!("balances-0-{}.csv", height));
+            let content = fs::read_to_string(path).unwrap();
+            let total: u64 = content
+                .lines()
+                .skip(1)
+                .map(|l| l.split(';').nth(1).unwrap().parse::<u64>().unwrap())
+                .sum();
+            // All coins mined up to the snapshot height
+            assert_eq!(total, (height + 1) * 5000000000);
+        }
+        // Parsing stops at the last snapshot
+        assert!(!dump_dir.path().join("balances-0-4.csv").exists());
+    }
+
+    #[test]
+    fn test_balances_load_unspent() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let full_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["balances", full_dir.path().to_str().unwrap()]);
+        let mut expected = read_csv(full_dir.path(), "balances");
+        expected.sort();
+
+        let unspent_dir = tempfile::tempdir().unwrap();
+        let unspent_folder = unspent_dir.path().to_str().unwrap();
+        run(
+            &chain,
+            &["-e", "2", "unspentcsvdump", unspent_folder, "--leveldb"],
+        );
+        for dump in ["unspent-0-2.csv", "unspent-0-2.ldb"] {
+            let dump_dir = tempfile::tempdir().unwrap();
+            let dump_path = unspent_dir.path().join(dump);
+            // Continues at height 3 and covers the whole chain
+            run(
+                &chain,
+                &[
+                    "balances",
+                    dump_dir.path().to_str().unwrap(),
+                    "--load-unspent",
+                    dump_path.to_str().unwrap(),
+                ],
+            );
+            let mut lines = read_csv(dump_dir.path(), "balances");
+            lines.sort();
+            assert_eq!(lines, expected);
+        }
+
+        // The parsed range has to follow the loaded one
+        let dump_path = unspent_dir.path().join("unspent-0-2.csv");
+        let args = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "-s",
+            "4",
+            "balances",
+            unspent_folder,
+            "--load-unspent",
+            dump_path.to_str().unwrap(),
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        let chain_storage = ChainStorage::new(&options).unwrap();
+        assert!(BlockchainParser::new(options, chain_storage)
+            .start()
+            .is_err());
+    }
+
+    #[test]
+    fn test_unspentcsvdump() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(
+            &chain,
+            &[
+                "unspentcsvdump",
+                dump_dir.path().to_str().unwrap(),
+                "--leveldb",
+                "--age-histogram",
+            ],
+        );
+
+        let lines = read_csv(dump_dir.path(), "unspent");
+        assert_eq!(lines[0], "txid;indexOut;height;value;address;scriptHash");
+        // The last coinbase and two outputs of each spending transaction, OP_RETURN is skipped
+        assert_eq!(lines.len(), 1 + 1 + 2 * 4);
+        let coinbase = format!("{};0;4;5000000000;", chain.txids[4][0]);
+        assert!(lines.iter().any(|l| l.starts_with(&coinbase)));
+
+        // The same outputs are in the database, along with the range
+        let db_path = dump_dir
+            .path()
+            .join(format!("unspent-0-{}.ldb", N_BLOCKS - 1));
+        let mut db = rusty_leveldb::DB::open(db_path, rusty_leveldb::Options::default()).unwrap();
+        let mut iter = db.new_iter().unwrap();
+        let mut keys = Vec::new();
+        while let Some((key, _)) = iter.next() {
+            keys.push(key);
+        }
+        assert_eq!(keys.len(), lines.len());
+        assert_eq!(
+            keys.iter().filter(|key| key[0] == b'u').count(),
+            lines.len() - 1
+        );
+
+        // Blocks are 10 minutes apart, so all outputs are younger than a day
+        let ages = read_csv(dump_dir.path(), "unspent-ages");
+        assert_eq!(ages[0], "age;utxos;value;utxosPercent;valuePercent");
+        assert_eq!(ages.len(), 1 + 12);
+        assert!(ages[1].starts_with("<1d;9;"));
+        assert!(ages[1].ends_with(";100.00;100.00"));
+        assert_eq!(ages[12], ">10y;0;0;0.00;0.00");
+    }
+
+    #[test]
+    fn test_immature() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_folder = dump_dir.path().to_str().unwrap();
+        // Only the last coinbase is unspent, it needs 100 confirmations
+        run(
+            &chain,
+            &["unspentcsvdump", dump_folder, "--immature", "flag"],
+        );
+        let lines = read_csv(dump_dir.path(), "unspent");
+        assert_eq!(
+            lines[0],
+            "txid;indexOut;height;value;address;scriptHash;immature"
+        );
+        let coinbase = format!("{};0;4;5000000000;", chain.txids[4][0]);
+        let immature: Vec<&String> = lines.iter().filter(|l| l.ends_with(";1")).collect();
+        assert_eq!(immature.len(), 1);
+        assert!(immature[0].starts_with(&coinbase));
+
+        run(
+            &chain,
+            &["unspentcsvdump", dump_folder, "--immature", "exclude"],
+        );
+        let lines = read_csv(dump_dir.path(), "unspent");
+        assert_eq!(lines.len(), 1 + 2 * 4);
+        assert!(!lines.iter().any(|l| l.starts_with(&coinbase)));
+
+        run(&chain, &["balances", dump_folder, "--immature", "flag"]);
+        let lines = read_csv(dump_dir.path(), "balances");
+        assert_eq!(lines[0], "address;balance;scriptHash;immature");
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|l| l.ends_with(";5000000000;;5000000000"))
+                .count(),
+            1
+        );
+        run(&chain, &["balances", dump_folder, "--immature", "exclude"]);
+        assert_eq!(read_csv(dump_dir.path(), "balances").len(), 1 + 5);
+    }
+
+    #[test]
+    fn test_sorted() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_folder = dump_dir.path().to_str().unwrap();
+        run(&chain, &["unspentcsvdump", dump_folder, "--sorted"]);
+        run(&chain, &["balances", dump_folder, "--sorted"]);
+
+        let unspent = read_csv(dump_dir.path(), "unspent");
+        let outpoints: Vec<(String, u32)> = unspent[1..]
+            .iter()
+            .map(|l| {
+                let fields: Vec<&str> = l.split(';').collect();
+                (String::from(fields[0]), fields[1].parse().unwrap())
+            })
+            .collect();
+        assert!(outpoints.windows(2).all(|w| w[0] < w[1]));
+        let balances = read_csv(dump_dir.path(), "balances");
+        assert!(balances[1..].windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_merkleproof() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let txid = chain.txids[3][1].to_string();
+        run(
+            &chain,
+            &[
+                "merkleproof",
+                dump_dir.path().to_str().unwrap(),
+                "--txid",
+                &txid,
+            ],
+        );
+
+        let lines = read_csv(dump_dir.path(), "merkleproofs");
+        assert_eq!(lines.len(), 2);
+        let fields: Vec<&str> = lines[1].split(';').collect();
+        assert_eq!(
+            fields[..3],
+            [txid.as_str(), "3", &chain.hashes[3].to_string()]
+        );
+
+        let branch: Vec<(sha256d::Hash, bool)> = fields[5]
+            .split(',')
+            .zip(fields[6].split(','))
+            .map(|(hash, pos)| (sha256d::Hash::from_str(hash).unwrap(), pos == "1"))
+            .collect();
+        let root = utils::merkle_root_from_branch(chain.txids[3][1], &branch);
+        assert_eq!(root.to_string(), fields[3]);
+    }
+
+    #[test]
+    fn test_difficulty() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["difficulty", dump_dir.path().to_str().unwrap()]);
+
+        let lines = read_csv(dump_dir.path(), "difficulty");
+        assert_eq!(lines.len(), 2);
+        // Blocks are exactly 10 minutes apart
+        assert!(lines[1].starts_with("0;0;4;1231006505;1231008905;600.00;2400;2400;207fffff;"));
+
+        run(
+            &chain,
+            &[
+                "difficulty",
+                "--period",
+                "2",
+                dump_dir.path().to_str().unwrap(),
+            ],
+        );
+        let lines = read_csv(dump_dir.path(), "difficulty");
+        // The last period of a single block has no interval
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].starts_with("1;2;3;1231007705;1231008905;600.00;1200;1200;"));
+    }
+
+    #[test]
+    fn test_date_range() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        // All blocks of the mini-chain are mined on 2009-01-03
+        run(
+            &chain,
+            &[
+                "--start-date",
+                "2009-01-03",
+                "--end-date",
+                "2009-01-03",
+                "difficulty",
+                dump_dir.path().to_str().unwrap(),
+            ],
+        );
+        assert_eq!(read_csv(dump_dir.path(), "difficulty").len(), 2);
+
+        let argv = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "--start-date",
+            "2009-01-04",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(argv)).unwrap();
+        assert!(ChainStorage::new(&options).is_err());
+    }
+
+    #[test]
+    fn test_selection() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let heights_file = dump_dir.path().join("heights.txt");
+        fs::write(&heights_file, "3\n1\n").unwrap();
+        run(
+            &chain,
+            &[
+                "--verify",
+                "--heights-file",
+                heights_file.to_str().unwrap(),
+                "csvdump",
+                dump_dir.path().to_str().unwrap(),
+            ],
+        );
+        let blocks = fs::read_to_string(dump_dir.path().join("blocks-1-3.csv")).unwrap();
+        assert_eq!(blocks.lines().count(), 2);
+
+        // The mini-chain is stored in a single blk file
+        run(
+            &chain,
+            &[
+                "--only-blk-files",
+                "0-0",
+                "difficulty",
+                dump_dir.path().to_str().unwrap(),
+            ],
+        );
+        assert_eq!(read_csv(dump_dir.path(), "difficulty").len(), 2);
+
+        let argv = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "--only-blk-files",
+            "1-2",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(argv)).unwrap();
+        assert!(ChainStorage::new(&options).is_err());
+    }
+
+    #[test]
+    fn test_filter() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(
+            &chain,
+            &[
+                "--filter",
+                "!coinbase && script_type == 'Pay2WitnessPublicKeyHash'",
+                "csvdump",
+                dump_dir.path().to_str().unwrap(),
+            ],
+        );
+        // All blocks, but only the spending transactions at their position in the block
+        assert_eq!(read_csv(dump_dir.path(), "blocks").len(), N_BLOCKS as usize);
+        let txs = read_csv(dump_dir.path(), "transactions");
+        assert_eq!(txs.len(), 4);
+        assert!(txs.iter().all(|tx| tx.ends_with(";1;0")));
+        assert_eq!(read_csv(dump_dir.path(), "tx_out").len(), 4 * 3);
+    }
+
+    #[test]
+    fn test_shard() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let full_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["csvdump", full_dir.path().to_str().unwrap()]);
+        run(&chain, &["feesamples", full_dir.path().to_str().unwrap()]);
+
+        let shard_dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        for (i, dir) in shard_dirs.iter().enumerate() {
+            for callback in ["csvdump", "feesamples"] {
+                let shard = format!("{}/2", i + 1);
+                let args = ["-e", "4", "--shard", &shard, callback];
+                run(
+                    &chain,
+                    &[&args[..], &[dir.path().to_str().unwrap()]].concat(),
+                );
+            }
+        }
+        assert!(shard_dirs[0].path().join("blocks-0-2.csv").is_file());
+        assert!(shard_dirs[1].path().join("blocks-3-4.csv").is_file());
+
+        let merged_dir = tempfile::tempdir().unwrap();
+        let argv = [
+            "rusty-blockparser",
+            "merge",
+            merged_dir.path().to_str().unwrap(),
+            shard_dirs[0].path().to_str().unwrap(),
+            shard_dirs[1].path().to_str().unwrap(),
+        ];
+        let matches = command().get_matches_from(argv);
+        Merge::run(matches.subcommand_matches("merge").unwrap()).unwrap();
+        for name in ["blocks", "transactions", "tx_in", "tx_out", "feesamples"] {
+            assert_eq!(
+                read_csv(merged_dir.path(), name),
+                read_csv(full_dir.path(), name)
+            );
+        }
+    }
+
+    #[test]
+    fn test_skip_corrupt() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        // Truncate the last block
+        let blk_path = chain.path().join("blk00000.dat");
+        let size = fs::metadata(&blk_path).unwrap().len();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&blk_path)
+            .unwrap()
+            .set_len(size - 10)
+            .unwrap();
+
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_folder = dump_dir.path().to_str().unwrap();
+        let parse = |args: &[&str]| {
+            let mut argv = vec!["rusty-blockparser", "-d", chain.path().to_str().unwrap()];
+            argv.extend(args);
+            let options = parse_args(command().get_matches_from(argv)).unwrap();
+            let chain_storage = ChainStorage::new(&options).unwrap();
+            BlockchainParser::new(options, chain_storage).start()
+        };
+        assert!(parse(&["csvdump", dump_folder]).is_err());
+        assert_eq!(fs::read_dir(dump_dir.path()).unwrap().count(), 0);
+        // The rows up to the last sync before the failure are kept
+        assert!(parse(&["csvdump", dump_folder, "--fsync-interval", "3"]).is_err());
+        let blocks = fs::read_to_string(dump_dir.path().join("blocks-0-2.csv")).unwrap();
+        assert_eq!(blocks.lines().count(), 3);
+        let tx_out = fs::read_to_string(dump_dir.path().join("tx_out-0-2.csv")).unwrap();
+        assert_eq!(tx_out.lines().count(), 9);
+
+        parse(&["--skip-corrupt", "csvdump", dump_folder]).unwrap();
+        assert_eq!(
+            read_csv(dump_dir.path(), "blocks").len(),
+            N_BLOCKS as usize - 1
+        );
+    }
+
+    #[test]
+    fn test_xor_obfuscation() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let key = [0x5a, 0x01, 0xa5, 0x10, 0xff, 0x00, 0x42, 0x24];
+        let blk_path = chain.path().join("blk00000.dat");
+        let data: Vec<u8> = fs::read(&blk_path)
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ key[i % 8])
+            .collect();
+        fs::write(&blk_path, data).unwrap();
+
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_folder = dump_dir.path().to_str().unwrap();
+        let parse = |args: &[&str]| {
+            let mut argv = vec!["rusty-blockparser", "-d", chain.path().to_str().unwrap()];
+            argv.extend(args);
+            let options = parse_args(command().get_matches_from(argv)).unwrap();
+            let chain_storage = ChainStorage::new(&options).unwrap();
+            BlockchainParser::new(options, chain_storage).start()
+        };
+        let err = parse(&["csvdump", dump_folder]).unwrap_err();
+        assert!(err.to_string().contains("--xor-key"));
+        parse(&["--xor-key", "5a01a510ff004224", "csvdump", dump_folder]).unwrap();
+        assert_eq!(read_csv(dump_dir.path(), "blocks").len(), N_BLOCKS as usize);
+
+        // xor.dat is picked up automatically
+        fs::write(chain.path().join("xor.dat"), key).unwrap();
+        parse(&["csvdump", dump_folder]).unwrap();
+        assert!(parse(&["--no-xor", "csvdump", dump_folder]).is_err());
+    }
+
+    #[test]
+    fn test_index_lock() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let index = chain.path().join("index");
+        // Hold the lock like a running node does
+        let _db = rusty_leveldb::DB::open(&index, rusty_leveldb::Options::default()).unwrap();
+
+        let storage = |args: &[&str]| {
+            let mut argv = vec!["rusty-blockparser", "-d", chain.path().to_str().unwrap()];
+            argv.extend(args);
+            argv.push("simplestats");
+            let options = parse_args(command().get_matches_from(argv)).unwrap();
+            ChainStorage::new(&options)
+        };
+        let err = storage(&[]).err().unwrap();
+        assert!(err.to_string().contains("--copy-index-to-tmp"));
+        storage(&["--copy-index-to-tmp"]).unwrap();
+
+        let snapshot = tempfile::tempdir().unwrap();
+        for entry in fs::read_dir(&index).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_name() != "LOCK" {
+                fs::copy(entry.path(), snapshot.path().join(entry.file_name())).unwrap();
+            }
+        }
+        storage(&["--index-snapshot", snapshot.path().to_str().unwrap()]).unwrap();
+    }
+
+    #[test]
+    fn test_chainwork() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_folder = dump_dir.path().to_str().unwrap();
+        // The chain work is accumulated from genesis, regardless of the start height
+        run(&chain, &["--start", "2", "chainwork", dump_folder]);
+        let path = dump_dir.path().join("chainwork-2-4.csv");
+        let lines: Vec<String> = fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+        assert_eq!(lines.len(), 1 + 3);
+        assert_eq!(
+            lines[1],
+            format!(
+                "2;{};{};207fffff;0.00000000;{:064x};{:064x}",
+                chain.hashes[2],
+                1231006505 + 2 * 600,
+                2,
+                6
+            )
+        );
+    }
+
+    #[test]
+    fn test_getblock() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        run(&chain, &["getblock", "3"]);
+        run(&chain, &["getblock", &chain.hashes[2].to_string()]);
+
+        // Unknown hashes are rejected while reading the block index
+        let unknown = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        let args = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "getblock",
+            unknown,
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(ChainStorage::new(&options).is_err());
+    }
+
+    #[test]
+    fn test_entities() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["entities", dump_dir.path().to_str().unwrap()]);
+
+        let lines = read_csv(dump_dir.path(), "entities");
+        // 5 coinbase addresses, 4 P2WPKH addresses and the shared P2PKH address.
+        // Each transaction spends a single input, so no clusters are merged.
+        assert_eq!(
+            lines,
+            [
+                "month;newAddresses;merges;clusters;activeAddresses;activeEntities",
+                "2009-01;10;0;10;10;10"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let coinbase = format!("{}:0", chain.txids[0][0]);
+        run(&chain, &["trace", &coinbase, "--format", "json"]);
+        run(
+            &chain,
+            &[
+                "trace",
+                &chain.txids[3][1].to_string(),
+                "--ancestors",
+                "--height",
+                "3",
+            ],
+        );
+
+        // The traced transaction must be within the parsed range
+        let args = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "trace",
+            &coinbase,
+            "--height",
+            "2",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.range, BlockHeightRange::new(2, None).unwrap());
+        let chain_storage = ChainStorage::new(&options).unwrap();
+        assert!(BlockchainParser::new(options, chain_storage)
+            .start()
+            .is_err());
+    }
+
+    #[test]
+    fn test_bench() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        run(&chain, &["bench"]);
+        run(&chain, &["-s", "2", "bench", "--skip-addresses"]);
+    }
+
+    #[test]
+    fn test_visualize() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blocks.dot");
+        run(
+            &chain,
+            &["visualize", path.to_str().unwrap(), "--max-blocks", "3"],
+        );
+
+        // Capped to 3 blocks, each block spends the coinbase of the previous one
+        let dot = fs::read_to_string(&path).unwrap();
+        assert!(dot.starts_with("digraph blocks {"));
+        assert_eq!(dot.matches("subgraph cluster_").count(), 3);
+        assert!(dot.contains(&format!(
+            "label=\"coinbase\\n{}\"",
+            &chain.txids[0][0].to_string()[..16]
+        )));
+        assert!(dot.contains("  t0 -> t2 [label=\"5000000000\"];"));
+        assert!(dot.contains("  t1 -> t4 [label=\"5000000000\"];"));
+        assert!(!dot.contains("shape=ellipse"));
+
+        let path = dir.path().join("blocks.mmd");
+        run(
+            &chain,
+            &[
+                "-e",
+                "1",
+                "visualize",
+                path.to_str().unwrap(),
+                "--format",
+                "mermaid",
+                "--addresses",
+            ],
+        );
+        let mermaid = fs::read_to_string(&path).unwrap();
+        assert!(mermaid.starts_with("flowchart LR\n  subgraph b0[\"block 0 "));
+        assert!(mermaid.contains("  t0 -->|5000000000| a0\n"));
+        assert!(mermaid.contains("  a0 -->|5000000000| t2\n"));
+
+        // Too many nodes
+        let args = [
+            "rusty-blockparser",
+            "-d",
+            chain.path().to_str().unwrap(),
+            "visualize",
+            path.to_str().unwrap(),
+            "--max-nodes",
+            "4",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        let chain_storage = ChainStorage::new(&options).unwrap();
+        assert!(BlockchainParser::new(options, chain_storage)
+            .start()
+            .is_err());
+    }
+
+    #[test]
+    fn test_versionbits() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["versionbits", dump_dir.path().to_str().unwrap()]);
+
+        // The mini-chain uses version 1 blocks only
+        let lines = read_csv(dump_dir.path(), "versionbits");
+        assert_eq!(
+            lines,
+            ["window;startHeight;endHeight;blocks;bip9Blocks;bit;count;percent"]
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dir = tempfile::tempdir().unwrap();
+        let coinbase = eval_from_bytes_bitcoin(&fixtures::p2pkh(1), 0x00)
+            .address
+            .unwrap();
+        let watchlist = dir.path().join("watchlist.txt");
+        fs::write(&watchlist, format!("# cold storage\n{},cold\n", coinbase)).unwrap();
+
+        // Collects the bodies of two webhook requests
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let webhook = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(length) = line.strip_prefix("Content-Length: ") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n").unwrap();
+                sender.send(String::from_utf8(body).unwrap()).unwrap();
+            }
+        });
+        run(
+            &chain,
+            &["watch", watchlist.to_str().unwrap(), "--webhook", &webhook],
+        );
+
+        let received = receiver.recv().unwrap();
+        assert!(received.contains(&format!("\"txid\": \"{}\"", chain.txids[1][0])));
+        assert!(received.contains("\"direction\": \"received\""));
+        assert!(received.contains("\"label\": \"cold\""));
+        // The coinbase is spent by the second transaction of the next block
+        let spent = receiver.recv().unwrap();
+        assert!(spent.contains(&format!("\"txid\": \"{}\"", chain.txids[2][1])));
+        assert!(spent.contains("\"direction\": \"spent\""));
+        assert!(spent.contains("\"value\": 5000000000"));
+    }
+
+    #[test]
+    fn test_balancehistory() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let shared = "1QLbz7JHiBTspS962RLKV8GndWFwi5j6Qr";
+        let coinbase = eval_from_bytes_bitcoin(&fixtures::p2pkh(1), 0x00)
+            .address
+            .unwrap();
+        run(
+            &chain,
+            &[
+                "balancehistory",
+                dump_dir.path().to_str().unwrap(),
+                shared,
+                &coinbase,
+            ],
+        );
+
+        let lines = read_csv(dump_dir.path(), "balancehistory");
+        assert_eq!(lines.len(), 1 + 6);
+        assert_eq!(lines[0], "height;txid;address;delta;balance");
+        assert_eq!(
+            lines[1],
+            format!("1;{};{};5000000000;5000000000", chain.txids[1][0], coinbase)
+        );
+        assert_eq!(
+            lines[2],
+            format!("1;{};{};2500000000;2500000000", chain.txids[1][1], shared)
+        );
+        // The second spend pays to the shared address and spends the tracked coinbase
+        assert_eq!(
+            lines[3],
+            format!("2;{};{};2500000000;5000000000", chain.txids[2][1], shared)
+        );
+        assert_eq!(
+            lines[4],
+            format!("2;{};{};-5000000000;0", chain.txids[2][1], coinbase)
+        );
+        assert!(lines[6].starts_with("4;") && lines[6].ends_with(";10000000000"));
+    }
+
+    #[test]
+    fn test_supply() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["supply", dump_dir.path().to_str().unwrap()]);
+
+        // The genesis coinbase pays to a zero hash and counts as burned, although the
+        // mini-chain spends it. All blocks are mined within an hour, so there are no zombie coins.
+        let lines = read_csv(dump_dir.path(), "supply");
+        assert_eq!(lines.len(), 1 + N_BLOCKS as usize);
+        assert_eq!(
+            lines[0],
+            "height;timestamp;supply;burned;circulating;zombie"
+        );
+        assert_eq!(lines[1], "0;1231006505;5000000000;5000000000;0;0");
+        assert_eq!(
+            lines[5],
+            "4;1231008905;30000000000;5000000000;25000000000;0"
+        );
+
+        run(
+            &chain,
+            &[
+                "supply",
+                dump_dir.path().to_str().unwrap(),
+                "--interval",
+                "2",
+            ],
+        );
+        assert_eq!(read_csv(dump_dir.path(), "supply").len(), 1 + 3);
+    }
+
+    #[test]
+    fn test_depositpatterns() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        // Each spend sweeps the single deposit of the previous coinbase address
+        run(
+            &chain,
+            &[
+                "depositpatterns",
+                dump_dir.path().to_str().unwrap(),
+                "--max-deposit",
+                "5000000000",
+                "--min-deposits",
+                "1",
+                "--min-inputs",
+                "1",
+            ],
+        );
+
+        let sweeps = read_csv(dump_dir.path(), "depositsweeps");
+        assert_eq!(sweeps.len(), 1 + 4);
+        // Both outputs have the same value, the last one is taken as destination
+        assert_eq!(
+            sweeps[1],
+            format!(
+                "1;{};1;1;5000000000;1QLbz7JHiBTspS962RLKV8GndWFwi5j6Qr",
+                chain.txids[1][1]
+            )
+        );
+        let addresses = read_csv(dump_dir.path(), "depositaddresses");
+        assert_eq!(addresses.len(), 1 + 4);
+        assert!(addresses[1..]
+            .iter()
+            .all(|line| line.ends_with(";1;5000000000;1")));
+    }
+
+    #[test]
+    fn test_inflationcheck() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(
+            &chain,
+            &["inflationcheck", dump_dir.path().to_str().unwrap()],
+        );
+
+        // Each coinbase claims the full subsidy, spends pay no fees
+        let supply = read_csv(dump_dir.path(), "inflationcheck");
+        assert_eq!(supply.len(), 1 + N_BLOCKS as usize);
+        assert_eq!(
+            supply[5],
+            format!(
+                "4;{};5000000000;0;5000000000;25000000000;0",
+                chain.hashes[4]
+            )
+        );
+        assert_eq!(read_csv(dump_dir.path(), "violations").len(), 1);
+    }
+
+    #[test]
+    fn test_strict_scripts_without_addresses() {
+        // simplestats doesn't evaluate addresses itself, the audit must not report them as missing
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let bad_scripts = dump_dir.path().join("bad.csv");
+        run(
+            &chain,
+            &[
+                "--strict-scripts",
+                "--dump-bad-scripts",
+                bad_scripts.to_str().unwrap(),
+                "simplestats",
+            ],
+        );
+        let content = fs::read_to_string(bad_scripts).unwrap();
+        assert_eq!(content, "height;txid;vout;reason;script\n");
+    }
+
+    #[test]
+    fn test_simplestats() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        run(&chain, &["simplestats"]);
+    }
+
+    #[test]
+    fn test_opreturn() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_folder = dump_dir.path().to_str().unwrap();
+        run(&chain, &["opreturn", "--stats", dump_folder]);
+
+        assert_eq!(
+            read_csv(dump_dir.path(), "opreturnstats"),
+            [
+                "year;protocol;outputs;bytes;unique;duplicates",
+                "2009;unknown;4;68;1;3"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_auxpowstats() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["auxpowstats", dump_dir.path().to_str().unwrap()]);
+
+        assert_eq!(read_csv(dump_dir.path(), "auxpow").len(), 1);
+    }
+
+    #[test]
+    fn test_clusterflows() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["clusterflows", dump_dir.path().to_str().unwrap()]);
+
+        // Coinbase addresses are never spent together, so each address is its own cluster
+        assert_eq!(read_csv(dump_dir.path(), "clusterflows").len(), 1 + 10);
+    }
+
+    #[test]
+    fn test_extractdata() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        let dump_folder = dump_dir.path().to_str().unwrap();
+        run(&chain, &["extractdata", dump_folder, "--include-witness"]);
+
+        assert_eq!(read_csv(dump_dir.path(), "extractdata").len(), 1);
+    }
+
+    #[test]
+    fn test_burns() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["burns", dump_dir.path().to_str().unwrap()]);
+
+        // The genesis coinbase pays to the all-zero pubkey hash, OP_RETURN outputs carry no value
+        let burns = read_csv(dump_dir.path(), "burns");
+        assert_eq!(burns.len(), 2);
+        assert!(burns[1].ends_with(";0;5000000000;address;1111111111111111111114oLvT2;5000000000"));
+    }
+
+    #[test]
+    fn test_txshape() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["txshape", dump_dir.path().to_str().unwrap()]);
+
+        // Without undo files there is no fee. Both outputs are round,
+        // but only the second one matches the P2PKH input
+        let txshape = read_csv(dump_dir.path(), "txshape");
+        assert_eq!(txshape.len(), 1 + 4);
+        let fields: Vec<&str> = txshape[1].split(';').collect();
+        assert_eq!(fields[2..4], ["1", "3"]);
+        assert_eq!(
+            fields[8..],
+            [
+                "Pay2PublicKeyHash",
+                "OpReturn,Pay2PublicKeyHash,Pay2WitnessPublicKeyHash",
+                "",
+                "",
+                "",
+                "1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_namecoin_names() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(
+            &chain,
+            &["namecoin-names", dump_dir.path().to_str().unwrap()],
+        );
+
+        assert_eq!(read_csv(dump_dir.path(), "names").len(), 1);
+    }
+
+    #[test]
+    fn test_walletfingerprint() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(
+            &chain,
+            &["walletfingerprint", dump_dir.path().to_str().unwrap()],
+        );
+
+        // Spends have locktime 0 and outputs sorted by descending value
+        let fingerprints = read_csv(dump_dir.path(), "walletfingerprint");
+        assert_eq!(fingerprints.len(), 1 + 4);
+        assert!(fingerprints[1].ends_with(";1;0;0;0;0;0;Pay2PublicKeyHash;unknown"));
+        let trends = read_csv(dump_dir.path(), "walletfingerprint_trends");
+        assert_eq!(trends.len(), 1 + 1);
+        assert!(trends[1].ends_with(";4;0;0;0;0;0;0;0;0;0;0;4"));
+    }
+
+    #[test]
+    fn test_scriptflows() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["scriptflows", dump_dir.path().to_str().unwrap()]);
+
+        // Each spend moves a P2PKH coinbase output to P2WPKH and P2PKH outputs
+        let flows = read_csv(dump_dir.path(), "scriptflows");
+        assert_eq!(
+            flows[1..],
+            [
+                "2009-01;Coinbase;Pay2PublicKeyHash;25000000000;5",
+                "2009-01;Pay2PublicKeyHash;OpReturn;0;4",
+                "2009-01;Pay2PublicKeyHash;Pay2PublicKeyHash;10000000000;4",
+                "2009-01;Pay2PublicKeyHash;Pay2WitnessPublicKeyHash;10000000000;4"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_minerstats() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["minerstats", dump_dir.path().to_str().unwrap()]);
+
+        // All coinbases carry the same tag, fees are only known for the genesis block
+        let miners = read_csv(dump_dir.path(), "minerstats");
+        assert_eq!(miners.len(), 1 + 1);
+        let fields: Vec<&str> = miners[1].split(';').collect();
+        assert_eq!(fields[..3], ["rusty-blockparser", "5", "9"]);
+        assert_eq!(fields[5..], ["1", "0", "0", "448", "0"]);
+    }
+
+    #[test]
+    fn test_feesamples() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["feesamples", dump_dir.path().to_str().unwrap()]);
+
+        // Fees are unknown without undo data
+        let fees = read_csv(dump_dir.path(), "feesamples");
+        assert_eq!(fees.len(), 1 + N_BLOCKS as usize);
+        assert!(fees[2].ends_with(";2;0;;;;;"));
+    }
+
+    #[test]
+    fn test_feemarket() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["feemarket", dump_dir.path().to_str().unwrap()]);
+
+        // All blocks are mined on the same day, only the genesis block has known fees
+        let market = read_csv(dump_dir.path(), "feemarket");
+        assert_eq!(market.len(), 1 + 1);
+        let fields: Vec<&str> = market[1].split(';').collect();
+        assert_eq!(fields[..3], ["2009-01-03", "5", "9"]);
+        assert_eq!(
+            fields[5..],
+            ["0", "25000000000", "0", "1", "0.00", "", "", ""]
+        );
+        assert_eq!(
+            read_csv(dump_dir.path(), "feemarket-eras")[1..],
+            ["2009-01-03;2009-01-03;1;normal;5;0;0.04;0.00"]
+        );
+    }
+
+    #[test]
+    fn test_sigstats() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["sigstats", dump_dir.path().to_str().unwrap()]);
+
+        // The dummy signatures of the spends are not DER encoded
+        let sigs = read_csv(dump_dir.path(), "sigstats");
+        assert_eq!(sigs[1..], ["2009-01;0;0;0;0;0;0;0;0;0;0;0;0"]);
+        assert_eq!(read_csv(dump_dir.path(), "siglengths").len(), 1);
+    }
+
+    #[test]
+    fn test_scriptstats() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["scriptstats", dump_dir.path().to_str().unwrap()]);
+
+        // The mini-chain has no P2SH outputs
+        assert_eq!(read_csv(dump_dir.path(), "scriptstats").len(), 1);
+    }
+
+    #[test]
+    fn test_witnessstats() {
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["witnessstats", dump_dir.path().to_str().unwrap()]);
+
+        // The fixture has no segwit spends, the P2PKH input spends 106 of 250 bytes on its scriptSig
+        let witnessstats = read_csv(dump_dir.path(), "witnessstats");
+        assert_eq!(witnessstats.len(), 1 + 9);
+        assert!(witnessstats[2].ends_with(";1;105;105;20;0;0;420;105;0"));
+        assert!(witnessstats[3].ends_with(";1;250;250;106;0;0;1000;250;0"));
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[test]
+    fn test_protodump() {
+        use crate::callbacks::protodump::schema;
+        use prost::Message;
+
+        let chain = MiniChain::generate(N_BLOCKS);
+        let dump_dir = tempfile::tempdir().unwrap();
+        run(&chain, &["protodump", dump_dir.path().to_str().unwrap()]);
+
+        let path = dump_dir
+            .path()
+            .join(format!("blocks-0-{}.pb", N_BLOCKS - 1));
+        let data = fs::read(path).unwrap();
+        let mut buf = data.as_slice();
+        let mut blocks = Vec::new();
+        while !buf.is_empty() {
+            blocks.push(schema::Block::decode_length_delimited(&mut buf).unwrap());
+        }
+        assert_eq!(blocks.len(), N_BLOCKS as usize);
+        assert_eq!(blocks[1].height, 1);
+        assert_eq!(blocks[1].prev_hash, blocks[0].hash);
+        let spend = &blocks[1].txs[1];
+        assert_eq!(spend.inputs[0].prev_txid, blocks[0].txs[0].txid);
+        assert_eq!(spend.inputs[0].pattern, "Pay2PublicKeyHash");
+        assert_eq!(spend.outputs.len(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stream() {
+        use std::io::Read;
+        use std::os::unix::net::UnixStream;
+
+        let chain = MiniChain::generate(N_BLOCKS);
+        let socket_dir = tempfile::tempdir().unwrap();
+        let socket = socket_dir.path().join("stream.sock");
+        let consumer = {
+            let socket = socket.clone();
+            std::thread::spawn(move || {
+                // Wait until the parser is listening
+                let mut stream = loop {
+                    match UnixStream::connect(&socket) {
+                        Ok(stream) => break stream,
+                        Err(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+                    }
+                };
+                let mut events = String::new();
+                stream.read_to_string(&mut events).unwrap();
+                events
+            })
+        };
+        let address = format!("unix:{}", socket.display());
+        run(&chain, &["stream", &address, "--txs"]);
+
+        let events = consumer.join().unwrap();
+        let events: Vec<&str> = events.lines().collect();
+        // 5 blocks, a coinbase in each block plus a spend in all but the first and the completion event
+        assert_eq!(events.len(), 5 + 9 + 1);
+        assert!(events[0].starts_with("{\"type\":\"block\",\"height\":0,\"hash\":\""));
+        assert!(events[1].starts_with("{\"type\":\"tx\",\"height\":0,"));
+        assert_eq!(
+            events.last().unwrap(),
+            &"{\"type\":\"complete\",\"height\":4}"
+        );
+        assert!(!socket.exists());
+    }
 }
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::{decode_name_op, NameOp};
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Escapes separators, backslashes and control characters in names and values,
+/// which are arbitrary bytes and often contain JSON spanning multiple lines.
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            ';' | '\\' => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Dumps all Namecoin name operations, which make up the history of the name database
+pub struct NamecoinNames {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    // Number of operations by kind
+    n_ops: HashMap<&'static str, u64>,
+    // Height of the last operation by name
+    names: HashMap<Vec<u8>, u64>,
+    start_height: u64,
+}
+
+impl NamecoinNames {
+    fn write_op(
+        &mut self,
+        op: &NameOp,
+        block_height: u64,
+        txid: &str,
+        index: usize,
+        address: Option<&str>,
+    ) -> OpResult<()> {
+        // The name of OP_NAME_NEW is not revealed yet, so the hash is dumped as value
+        let (name, value) = match op {
+            NameOp::New { hash } => (String::new(), utils::arr_to_hex(hash)),
+            _ => (
+                escape(op.name().unwrap_or_default()),
+                escape(op.value().unwrap_or_default()),
+            ),
+        };
+        // (height, @txid, indexOut, op, name, value, address)
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{}\n",
+                block_height,
+                txid,
+                index,
+                op.kind(),
+                name,
+                value,
+                address.unwrap_or_default()
+            )
+            .as_bytes(),
+        )?;
+        *self.n_ops.entry(op.kind()).or_insert(0) += 1;
+        if let Some(name) = op.name() {
+            self.names.insert(name.to_vec(), block_height);
+        }
+        Ok(())
+    }
+
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let count = |kind| self.n_ops.get(kind).copied().unwrap_or(0);
+        writeln!(buffer, "Namecoin names:")?;
+        writeln!(buffer, "   -> name_new:\t\t\t{}", count("name_new"))?;
+        writeln!(
+            buffer,
+            "   -> name_firstupdate:\t\t{}",
+            count("name_firstupdate")
+        )?;
+        writeln!(buffer, "   -> name_update:\t\t{}", count("name_update"))?;
+        writeln!(buffer, "   -> distinct names:\t\t{}", self.names.len())?;
+        Ok(())
+    }
+}
+
+impl Callback for NamecoinNames {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("namecoin-names")
+            .about("Dumps the history of Namecoin name operations to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = NamecoinNames {
+            writer: output.open("names")?,
+            output,
+            n_ops: HashMap::new(),
+            names: HashMap::new(),
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing namecoin-names with dump folder: {} ...", &self.output);
+        self.writer
+            .write_all("height;txid;indexOut;op;name;value;address\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            let txid = tx.hash.to_string();
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                if let Some((op, _)) = decode_name_op(&output.out.script_pubkey) {
+                    let address = output.script.address.as_deref();
+                    self.write_op(&op, block_height, &txid, i, address)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "namecoin-names", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(b"d/bitcoin"), "d/bitcoin");
+        assert_eq!(escape(b"{\"a\";\n1}"), "{\"a\"\\x3b\\x0a1}");
+        assert_eq!(escape(b"a\\b"), "a\\x5cb");
+    }
+}
@@ -1,12 +1,115 @@
-use clap::{ArgMatches, Command};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+
+use bitcoin::hashes::{sha256, Hash};
+use clap::{Arg, ArgMatches, Command};
 
 use crate::blockchain::proto::block::Block;
 use crate::blockchain::proto::script::ScriptPattern;
-use crate::callbacks::Callback;
+use crate::callbacks::common::pushed_data;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::{logger, utils};
 use crate::errors::OpResult;
 
+/// OP_13, which follows OP_RETURN in runestones
+const OP_13: u8 = 0x5d;
+/// Protocols recognized by the prefix of their payload
+const PROTOCOLS: [(&str, &[u8]); 10] = [
+    ("witness-commitment", &[0xaa, 0x21, 0xa9, 0xed]),
+    ("omni", b"omni"),
+    ("counterparty", b"CNTRPRTY"),
+    ("docproof", b"DOCPROOF"),
+    ("openassets", b"OA\x01\x00"),
+    ("factom", b"Factom!!"),
+    ("coinspark", b"SPK"),
+    ("rsk", b"RSKBLOCK:"),
+    ("stacks", b"X2"),
+    ("blockstack", b"id"),
+];
+
+/// Returns the protocol of an OP_RETURN script and its payload
+fn classify(script: &[u8]) -> (&'static str, Vec<u8>) {
+    let payload = pushed_data(script);
+    if script.get(1) == Some(&OP_13) {
+        return ("runes", payload);
+    }
+    let protocol = match payload.is_empty() {
+        true => "empty",
+        false => PROTOCOLS
+            .iter()
+            .find(|(_, prefix)| payload.starts_with(prefix))
+            .map_or("unknown", |(name, _)| name),
+    };
+    (protocol, payload)
+}
+
+/// Remembers the payloads seen so far by a 128 bit prefix of their hash,
+/// which keeps the memory usage low even for hundreds of millions of payloads.
+#[derive(Default)]
+struct PayloadSet(HashSet<[u8; 16]>);
+
+impl PayloadSet {
+    /// Returns true if the payload wasn't seen before
+    fn insert(&mut self, payload: &[u8]) -> bool {
+        let hash = sha256::Hash::hash(payload);
+        self.0.insert(hash[..16].try_into().unwrap())
+    }
+}
+
 #[derive(Default)]
-pub struct OpReturn;
+struct ProtocolStats {
+    outputs: u64,
+    bytes: u64,
+    unique: u64,
+    duplicates: u64,
+}
+
+impl ProtocolStats {
+    fn add(&mut self, other: &ProtocolStats) {
+        self.outputs += other.outputs;
+        self.bytes += other.bytes;
+        self.unique += other.unique;
+        self.duplicates += other.duplicates;
+    }
+}
+
+#[derive(Default)]
+pub struct OpReturn {
+    // Destination of the report, the statistics are only collected with --stats
+    output: Option<Output>,
+    payloads: PayloadSet,
+    // By year and protocol
+    stats: BTreeMap<(String, &'static str), ProtocolStats>,
+    start_height: u64,
+}
+
+impl OpReturn {
+    fn add_output(&mut self, script: &[u8], year: &str) {
+        let (protocol, payload) = classify(script);
+        let stats = self.stats.entry((year.to_string(), protocol)).or_default();
+        stats.outputs += 1;
+        stats.bytes += payload.len() as u64;
+        match self.payloads.insert(&payload) {
+            true => stats.unique += 1,
+            false => stats.duplicates += 1,
+        }
+    }
+
+    fn write_stats(&self, writer: &mut Box<dyn Sink>) -> OpResult<()> {
+        writer.write_all("year;protocol;outputs;bytes;unique;duplicates\n".as_bytes())?;
+        for ((year, protocol), stats) in &self.stats {
+            writer.write_all(
+                format!(
+                    "{};{};{};{};{};{}\n",
+                    year, protocol, stats.outputs, stats.bytes, stats.unique, stats.duplicates
+                )
+                .as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+}
 
 impl Callback for OpReturn {
     fn build_subcommand() -> Command
@@ -17,42 +120,131 @@ impl Callback for OpReturn {
             .about("Shows embedded OP_RETURN data that is representable as UTF8")
             .version("0.1")
             .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("stats")
+                    .long("stats")
+                    .value_name("DUMP-FOLDER")
+                    .help("Writes the number of outputs, bytes and unique payloads per year and protocol to a CSV file"),
+            )
     }
 
-    fn new(_: &ArgMatches) -> OpResult<Self>
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
     where
         Self: Sized,
     {
-        Ok(OpReturn::default())
+        let output = match matches.get_one::<String>("stats") {
+            Some(uri) => Some(Output::parse(uri)?),
+            None => None,
+        };
+        if let Some(Output::Stdout(_)) = output {
+            logger::redirect_to_stderr();
+        }
+        Ok(OpReturn {
+            output,
+            ..Default::default()
+        })
     }
 
-    fn on_start(&mut self, _: u64) -> OpResult<()> {
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
         info!(target: "callback", "Executing OpReturn ...");
         Ok(())
     }
 
     fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let mut year = utils::month_of(block.header.value.timestamp);
+        year.truncate(4);
         for tx in &block.txs {
             for out in tx.value.outputs.iter() {
                 if let ScriptPattern::OpReturn(data) = &out.script.pattern {
+                    if self.output.is_some() {
+                        self.add_output(&out.out.script_pubkey, &year);
+                    }
                     if data.is_empty() {
                         continue;
                     }
-                    println!(
+                    writeln!(
+                        io::stdout(),
                         "height: {: <9} txid: {}    data: {}",
-                        block_height, &tx.hash, data
-                    );
+                        block_height,
+                        &tx.hash,
+                        data
+                    )?;
                 }
             }
         }
         Ok(())
     }
 
-    fn on_complete(&mut self, _: u64) -> OpResult<()> {
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        let output = match &self.output {
+            Some(output) => output,
+            None => return Ok(()),
+        };
+        let mut writer = output.open("opreturnstats")?;
+        self.write_stats(&mut writer)?;
+        writer.finish(self.start_height, block_height)?;
+
+        let mut totals: BTreeMap<&str, ProtocolStats> = BTreeMap::new();
+        for ((_, protocol), stats) in &self.stats {
+            totals.entry(protocol).or_default().add(stats);
+        }
+        let mut summary =
+            String::from("Done.\nOP_RETURN outputs by protocol (outputs, bytes, duplicates):");
+        for (protocol, stats) in &totals {
+            summary.push_str(&format!(
+                "\n\t-> {: <20} {:12} {:15} {:12}",
+                protocol, stats.outputs, stats.bytes, stats.duplicates
+            ));
+        }
+        info!(target: "callback", "{}", summary);
         Ok(())
     }
 
     fn show_progress(&self) -> bool {
         false
     }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let mut script = vec![0x6a, 0x08];
+        script.extend(b"omni\x00\x00\x00\x00");
+        assert_eq!(
+            classify(&script),
+            ("omni", b"omni\x00\x00\x00\x00".to_vec())
+        );
+        assert_eq!(
+            classify(&[0x6a, 0x04, 0xaa, 0x21, 0xa9, 0xed]).0,
+            "witness-commitment"
+        );
+        assert_eq!(classify(&[0x6a, OP_13, 0x01, 0x00]).0, "runes");
+        assert_eq!(classify(&[0x6a]).0, "empty");
+        assert_eq!(classify(&[0x6a, 0x02, 0x01, 0x02]).0, "unknown");
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut opreturn = OpReturn::default();
+        let script = [0x6a, 0x04, b'o', b'm', b'n', b'i'];
+        opreturn.add_output(&script, "2015");
+        opreturn.add_output(&script, "2016");
+        opreturn.add_output(&[0x6a, 0x01, 0xff], "2016");
+
+        let omni = &opreturn.stats[&(String::from("2016"), "omni")];
+        assert_eq!(
+            (omni.outputs, omni.bytes, omni.unique, omni.duplicates),
+            (1, 4, 0, 1)
+        );
+        let unknown = &opreturn.stats[&(String::from("2016"), "unknown")];
+        assert_eq!((unknown.unique, unknown.duplicates), (1, 0));
+    }
 }
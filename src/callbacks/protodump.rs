@@ -0,0 +1,141 @@
+use bitcoin::hashes::Hash;
+use clap::{Arg, ArgMatches, Command};
+use prost::Message;
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Rust types of proto/blockchain.proto, generated by build.rs
+pub mod schema {
+    include!(concat!(env!("OUT_DIR"), "/rusty_blockparser.rs"));
+}
+
+impl schema::Block {
+    pub fn new(block: &Block, block_height: u64) -> Self {
+        schema::Block {
+            txs: block.txs.iter().map(schema::Tx::new).collect(),
+            ..schema::Block::without_txs(block, block_height)
+        }
+    }
+
+    /// Returns the header fields and the size, e.g. if the transactions are sent separately
+    pub fn without_txs(block: &Block, block_height: u64) -> Self {
+        let header = &block.header.value;
+        schema::Block {
+            height: block_height,
+            hash: block.header.hash.to_byte_array().to_vec(),
+            version: header.version,
+            prev_hash: header.prev_hash.to_byte_array().to_vec(),
+            merkle_root: header.merkle_root.to_byte_array().to_vec(),
+            timestamp: header.timestamp,
+            bits: header.bits,
+            nonce: header.nonce,
+            size: block.size,
+            txs: Vec::new(),
+        }
+    }
+}
+
+impl schema::Tx {
+    pub fn new(tx: &Hashed<EvaluatedTx>) -> Self {
+        schema::Tx {
+            txid: tx.hash.to_byte_array().to_vec(),
+            version: tx.value.version,
+            locktime: tx.value.locktime,
+            inputs: tx
+                .value
+                .inputs
+                .iter()
+                .map(|i| schema::Input {
+                    prev_txid: i.outpoint.txid.to_byte_array().to_vec(),
+                    prev_index: i.outpoint.index,
+                    script_sig: i.script_sig.clone(),
+                    sequence: i.seq_no,
+                    witness: i.witness.clone(),
+                    pattern: i.pattern.to_string(),
+                })
+                .collect(),
+            outputs: tx
+                .value
+                .outputs
+                .iter()
+                .map(|o| schema::Output {
+                    value: o.out.value,
+                    script_pubkey: o.out.script_pubkey.clone(),
+                    address: o.script.address.clone().unwrap_or_default(),
+                    pattern: o.script.pattern.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Dumps all blocks as length delimited protobuf records, see proto/blockchain.proto
+pub struct ProtoDump {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    // Reused across blocks to avoid an allocation per record
+    buffer: Vec<u8>,
+    start_height: u64,
+    n_blocks: u64,
+}
+
+impl Callback for ProtoDump {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("protodump")
+            .about("Dumps the whole blockchain as length delimited protobuf records")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store the protobuf file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = ProtoDump {
+            writer: output.open_with_ext("blocks", "pb")?,
+            output,
+            buffer: Vec::with_capacity(1000000),
+            start_height: 0,
+            n_blocks: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing protodump with dump folder: {} ...", &self.output);
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.buffer.clear();
+        schema::Block::new(block, block_height)
+            .encode_length_delimited(&mut self.buffer)
+            .expect("Vec<u8> has unlimited capacity");
+        self.writer.write_all(&self.buffer)?;
+        self.n_blocks += 1;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+        info!(target: "callback", "Done. Dumped {} blocks.", self.n_blocks);
+        Ok(())
+    }
+}
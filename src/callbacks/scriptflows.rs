@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::ScriptPattern;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+use crate::blockchain::proto::TxId;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Source of newly minted coins
+const COINBASE: &str = "Coinbase";
+/// Source of transactions spending outputs which couldn't be resolved
+const UNKNOWN: &str = "Unknown";
+
+/// Returns the script type without any payload
+fn script_type(pattern: &ScriptPattern) -> String {
+    match pattern {
+        ScriptPattern::Error(_) => String::from("ScriptError"),
+        pattern => pattern.to_string(),
+    }
+}
+
+/// Value moved from a source to a destination script type
+#[derive(Default)]
+struct Flow {
+    value: u64,
+    n_txs: u64,
+}
+
+/// Builds a matrix of the value moved from the script types of spent outputs
+/// to the script types of the created outputs per month, e.g. to track the migration to segwit.
+pub struct ScriptFlows {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    // Spent outputs are resolved by undo data if available, otherwise by the tracked unspents
+    use_undo_files: bool,
+    unspents: HashMap<TxOutpoint, (String, u64)>,
+    // Flows by (month, source, destination)
+    flows: BTreeMap<(String, String, String), Flow>,
+    start_height: u64,
+    n_txs: u64,
+    n_unresolved: u64,
+}
+
+impl ScriptFlows {
+    /// Returns the value by source script type of all inputs.
+    /// If any spent output is unknown, the whole transaction is attributed to `Unknown`.
+    fn sources(&mut self, tx: &EvaluatedTx, out_value: u64) -> BTreeMap<String, u64> {
+        let mut sources = BTreeMap::new();
+        if tx.is_coinbase() {
+            sources.insert(String::from(COINBASE), out_value);
+            return sources;
+        }
+        for input in &tx.inputs {
+            let spent = match &input.prevout {
+                Some(prevout) => Some((
+                    script_type(&prevout.out.script.pattern),
+                    prevout.out.out.value,
+                )),
+                None if self.use_undo_files => None,
+                None => self.unspents.remove(&input.outpoint),
+            };
+            match spent {
+                Some((source, value)) => *sources.entry(source).or_insert(0) += value,
+                None => {
+                    self.n_unresolved += 1;
+                    sources.clear();
+                    sources.insert(String::from(UNKNOWN), out_value);
+                    return sources;
+                }
+            }
+        }
+        sources
+    }
+
+    fn add_tx(&mut self, tx: &EvaluatedTx, txid: TxId, month: &str) {
+        let mut destinations: BTreeMap<String, u64> = BTreeMap::new();
+        for (i, output) in tx.outputs.iter().enumerate() {
+            let destination = script_type(&output.script.pattern);
+            *destinations.entry(destination.clone()).or_insert(0) += output.out.value;
+            // Keep track of spendable outputs if there is no undo data
+            if !self.use_undo_files && output.out.value > 0 {
+                if let ScriptPattern::OpReturn(_) = output.script.pattern {
+                    continue;
+                }
+                let outpoint = TxOutpoint::new(txid, i as u32);
+                self.unspents
+                    .insert(outpoint, (destination, output.out.value));
+            }
+        }
+        let out_value = destinations.values().sum();
+        let sources = self.sources(tx, out_value);
+
+        // Each output is funded by all sources in proportion to their share of the input value
+        let in_value: u64 = sources.values().sum();
+        for (source, source_value) in &sources {
+            for (destination, dest_value) in &destinations {
+                let value = match in_value {
+                    0 => 0,
+                    _ => (*dest_value as u128 * *source_value as u128 / in_value as u128) as u64,
+                };
+                let key = (String::from(month), source.clone(), destination.clone());
+                let flow = self.flows.entry(key).or_default();
+                flow.value += value;
+                flow.n_txs += 1;
+            }
+        }
+        self.n_txs += 1;
+    }
+
+    fn write_flows(&mut self) -> OpResult<()> {
+        for ((month, source, destination), flow) in &self.flows {
+            // (month, from, to, value, txs)
+            self.writer.write_all(
+                format!(
+                    "{};{};{};{};{}\n",
+                    month, source, destination, flow.value, flow.n_txs
+                )
+                .as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let mut totals: BTreeMap<(&str, &str), u64> = BTreeMap::new();
+        for ((_, source, destination), flow) in &self.flows {
+            *totals.entry((source, destination)).or_insert(0) += flow.value;
+        }
+        let mut totals: Vec<_> = totals.into_iter().collect();
+        totals.sort_by_key(|(_, value)| std::cmp::Reverse(*value));
+
+        writeln!(buffer, "Script type flows:")?;
+        writeln!(buffer, "   -> transactions:\t\t{}", self.n_txs)?;
+        writeln!(buffer, "   -> unresolved:\t\t{}", self.n_unresolved)?;
+        writeln!(buffer, "   Largest flows:")?;
+        for ((source, destination), value) in totals.iter().take(10) {
+            writeln!(
+                buffer,
+                "     -> {} -> {}:\t{:.8}",
+                source,
+                destination,
+                *value as f64 * 1E-8
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for ScriptFlows {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("scriptflows")
+            .about("Dumps the value moved between script types per month to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = ScriptFlows {
+            writer: output.open("scriptflows")?,
+            output,
+            use_undo_files: context.use_undo_files,
+            unspents: HashMap::with_capacity(10000000),
+            flows: BTreeMap::new(),
+            start_height: 0,
+            n_txs: 0,
+            n_unresolved: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing scriptflows with dump folder: {} ...", &self.output);
+        if self.start_height > 0 && !self.use_undo_files {
+            warn!(target: "callback", "Outputs created before height {} are unknown, use --use-undo-files to resolve them.", self.start_height);
+        }
+        self.writer
+            .write_all("month;from;to;value;txs\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, _: u64) -> OpResult<()> {
+        let month = utils::month_of(block.header.value.timestamp);
+        for tx in &block.txs {
+            self.add_tx(&tx.value, tx.hash, &month);
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.write_flows()?;
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "scriptflows", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_type() {
+        assert_eq!(script_type(&ScriptPattern::Pay2Taproot), "Pay2Taproot");
+        assert_eq!(
+            script_type(&ScriptPattern::OpReturn(String::from("data"))),
+            "OpReturn"
+        );
+    }
+}
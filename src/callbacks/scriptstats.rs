@@ -0,0 +1,366 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::{InputPattern, ScriptPattern};
+use crate::blockchain::proto::tx::{EvaluatedTx, TxInput, TxOutpoint};
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// What the spend of a P2SH output revealed about its redeem script
+#[derive(Debug, PartialEq)]
+enum P2shSpend {
+    Legacy,
+    WitnessPublicKeyHash,
+    WitnessScriptHash,
+    Other,
+}
+
+/// Classifies the input spending a P2SH output.
+/// The spent output is known to be P2SH, so any input without witness is a legacy spend.
+fn classify_spend(input: &TxInput) -> P2shSpend {
+    match input.pattern {
+        InputPattern::Pay2ScriptHashWitnessPublicKeyHash => P2shSpend::WitnessPublicKeyHash,
+        InputPattern::Pay2ScriptHashWitnessScriptHash => P2shSpend::WitnessScriptHash,
+        _ if input.witness.is_empty() => P2shSpend::Legacy,
+        _ => P2shSpend::Other,
+    }
+}
+
+/// P2SH outputs created in a single month and how they were spent so far
+#[derive(Default)]
+struct P2shStats {
+    created: u64,
+    legacy: u64,
+    p2wpkh: u64,
+    p2wsh: u64,
+    other: u64,
+}
+
+impl P2shStats {
+    fn add_spend(&mut self, spend: &P2shSpend) {
+        match spend {
+            P2shSpend::Legacy => self.legacy += 1,
+            P2shSpend::WitnessPublicKeyHash => self.p2wpkh += 1,
+            P2shSpend::WitnessScriptHash => self.p2wsh += 1,
+            P2shSpend::Other => self.other += 1,
+        }
+    }
+
+    fn spent(&self) -> u64 {
+        self.legacy + self.p2wpkh + self.p2wsh + self.other
+    }
+
+    /// Share of the spent outputs which turned out to be wrapped segwit
+    fn segwit_share(&self) -> f64 {
+        (self.p2wpkh + self.p2wsh) as f64 / self.spent().max(1) as f64
+    }
+
+    fn as_csv(&self, month: &str) -> String {
+        // (month, p2sh, unspent, legacy, p2shP2wpkh, p2shP2wsh, other, segwitShare)
+        format!(
+            "{};{};{};{};{};{};{};{:.4}\n",
+            month,
+            self.created,
+            self.created.saturating_sub(self.spent()),
+            self.legacy,
+            self.p2wpkh,
+            self.p2wsh,
+            self.other,
+            self.segwit_share()
+        )
+    }
+}
+
+/// Tracks P2SH outputs until they are spent and attributes the revealed redeem script type
+/// to the month the output was created in.
+#[derive(Default)]
+struct P2shTracker {
+    // Spent outputs are resolved by undo data if available, otherwise by the tracked unspents
+    use_undo_files: bool,
+    // Creation height of the unspent P2SH outputs
+    unspents: HashMap<TxOutpoint, u64>,
+    // Month by the first height of the month
+    month_starts: BTreeMap<u64, String>,
+    months: BTreeMap<String, P2shStats>,
+    // Spends of outputs created before the first parsed block
+    earlier: P2shStats,
+}
+
+impl P2shTracker {
+    /// Registers a block, heights must be ascending
+    fn add_block(&mut self, height: u64, month: String) {
+        if self.month_starts.values().next_back() != Some(&month) {
+            self.month_starts.insert(height, month);
+        }
+    }
+
+    fn add_tx(&mut self, tx: &Hashed<EvaluatedTx>, height: u64) {
+        if !tx.value.is_coinbase() {
+            for input in &tx.value.inputs {
+                self.spend(input);
+            }
+        }
+        let mut created = 0;
+        for (i, output) in tx.value.outputs.iter().enumerate() {
+            if output.script.pattern != ScriptPattern::Pay2ScriptHash {
+                continue;
+            }
+            created += 1;
+            if !self.use_undo_files {
+                self.unspents
+                    .insert(TxOutpoint::new(tx.hash, i as u32), height);
+            }
+        }
+        if created > 0 {
+            self.stats_at(height).unwrap().created += created;
+        }
+    }
+
+    fn spend(&mut self, input: &TxInput) {
+        let height = match &input.prevout {
+            Some(prevout) if prevout.out.script.pattern == ScriptPattern::Pay2ScriptHash => {
+                prevout.height
+            }
+            Some(_) => return,
+            None if self.use_undo_files => return,
+            None => match self.unspents.remove(&input.outpoint) {
+                Some(height) => height,
+                None => return,
+            },
+        };
+        let spend = classify_spend(input);
+        match self.stats_at(height) {
+            Some(stats) => stats.add_spend(&spend),
+            None => self.earlier.add_spend(&spend),
+        }
+    }
+
+    /// Returns the stats of the month of the given height, None if it is before the first block
+    fn stats_at(&mut self, height: u64) -> Option<&mut P2shStats> {
+        let (_, month) = self.month_starts.range(..=height).next_back()?;
+        Some(self.months.entry(month.clone()).or_default())
+    }
+
+    fn total(&self) -> P2shStats {
+        let mut total = P2shStats::default();
+        for stats in self.months.values().chain([&self.earlier]) {
+            total.created += stats.created;
+            total.legacy += stats.legacy;
+            total.p2wpkh += stats.p2wpkh;
+            total.p2wsh += stats.p2wsh;
+            total.other += stats.other;
+        }
+        total
+    }
+}
+
+/// Dumps the number of P2SH outputs created per month and what their spends revealed:
+/// legacy P2SH, P2SH-wrapped P2WPKH or P2WSH (BIP141). Outputs are attributed to the month
+/// they were created in, so the share of wrapped segwit tracks its adoption over time.
+pub struct ScriptStats {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    tracker: P2shTracker,
+    start_height: u64,
+}
+
+impl ScriptStats {
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let total = self.tracker.total();
+        writeln!(buffer, "ScriptStats:")?;
+        writeln!(buffer, "   -> p2sh outputs:\t\t{}", total.created)?;
+        writeln!(buffer, "   -> spent:\t\t\t{}", total.spent())?;
+        writeln!(buffer, "   -> legacy:\t\t\t{}", total.legacy)?;
+        writeln!(buffer, "   -> p2sh-p2wpkh:\t\t{}", total.p2wpkh)?;
+        writeln!(buffer, "   -> p2sh-p2wsh:\t\t{}", total.p2wsh)?;
+        writeln!(buffer, "   -> other:\t\t\t{}", total.other)?;
+        writeln!(
+            buffer,
+            "   -> wrapped segwit:\t\t{:.2}% of the spent outputs",
+            total.segwit_share() * 100.00
+        )?;
+        Ok(())
+    }
+}
+
+impl Callback for ScriptStats {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("scriptstats")
+            .about("Dumps the share of P2SH outputs which are wrapped segwit, by inspecting their spends, per month to a CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = ScriptStats {
+            writer: output.open("scriptstats")?,
+            output,
+            tracker: P2shTracker {
+                use_undo_files: context.use_undo_files,
+                ..P2shTracker::default()
+            },
+            start_height: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing scriptstats with dump folder: {} ...", &self.output);
+        if self.start_height > 0 && !self.tracker.use_undo_files {
+            warn!(target: "callback", "Outputs created before height {} are unknown, use --use-undo-files to resolve them.", self.start_height);
+        }
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let month = utils::month_of(block.header.value.timestamp);
+        self.tracker.add_block(block_height, month);
+        for tx in &block.txs {
+            self.tracker.add_tx(tx, block_height);
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.write_all(
+            "month;p2sh;unspent;legacy;p2shP2wpkh;p2shP2wsh;other;segwitShare\n".as_bytes(),
+        )?;
+        for (month, stats) in &self.tracker.months {
+            self.writer.write_all(stats.as_csv(month).as_bytes())?;
+        }
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "scriptstats", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+    use crate::blockchain::proto::script::EvaluatedScript;
+    use crate::blockchain::proto::tx::{EvaluatedTxOut, TxInputs, TxOutput};
+    use crate::blockchain::proto::varuint::VarUint;
+    use crate::blockchain::proto::TxId;
+
+    /// Builds a transaction spending the given outpoints with the given input patterns
+    /// and creating one output per script pattern
+    fn tx(
+        id: u8,
+        inputs: &[(TxOutpoint, InputPattern)],
+        outputs: &[ScriptPattern],
+    ) -> Hashed<EvaluatedTx> {
+        let inputs = inputs
+            .iter()
+            .map(|(outpoint, pattern)| TxInput {
+                outpoint: *outpoint,
+                script_len: VarUint::from(0u8),
+                script_sig: Vec::new(),
+                seq_no: 0xffffffff,
+                witness: match pattern {
+                    InputPattern::Pay2ScriptHash => Vec::new(),
+                    _ => vec![vec![0x30; 71], vec![0x02; 33]],
+                },
+                prevout: None,
+                pattern: pattern.clone(),
+            })
+            .collect::<TxInputs>();
+        let outputs: Vec<EvaluatedTxOut> = outputs
+            .iter()
+            .map(|pattern| EvaluatedTxOut {
+                script: EvaluatedScript::new(None, pattern.clone()),
+                out: TxOutput {
+                    value: 1000,
+                    script_len: VarUint::from(0u8),
+                    script_pubkey: Vec::new(),
+                },
+            })
+            .collect();
+        Hashed {
+            hash: TxId::from_byte_array([id; 32]),
+            value: EvaluatedTx {
+                version: 1,
+                flags: 0,
+                in_count: VarUint::from(inputs.len() as u64),
+                inputs,
+                out_count: VarUint::from(outputs.len() as u64),
+                outputs: outputs.into(),
+                locktime: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_p2sh_tracker() {
+        let p2sh = || ScriptPattern::Pay2ScriptHash;
+        let outpoint = |id: u8, index| TxOutpoint::new(TxId::from_byte_array([id; 32]), index);
+        let mut tracker = P2shTracker::default();
+        tracker.add_block(0, String::from("2017-08"));
+        let funding = tx(
+            1,
+            &[(outpoint(0, 0), InputPattern::Pay2PublicKeyHash)],
+            &[p2sh(), p2sh(), p2sh(), ScriptPattern::Pay2PublicKeyHash],
+        );
+        tracker.add_tx(&funding, 0);
+
+        tracker.add_block(1, String::from("2017-09"));
+        let spends = tx(
+            2,
+            &[
+                (
+                    outpoint(1, 0),
+                    InputPattern::Pay2ScriptHashWitnessPublicKeyHash,
+                ),
+                (outpoint(1, 1), InputPattern::Pay2ScriptHash),
+                // Not a P2SH output
+                (outpoint(1, 3), InputPattern::Pay2WitnessPublicKeyHash),
+            ],
+            &[p2sh()],
+        );
+        tracker.add_tx(&spends, 1);
+
+        let months: Vec<String> = tracker
+            .months
+            .iter()
+            .map(|(month, stats)| stats.as_csv(month))
+            .collect();
+        assert_eq!(
+            months,
+            [
+                "2017-08;3;1;1;1;0;0;0.5000\n",
+                "2017-09;1;1;0;0;0;0;0.0000\n"
+            ]
+        );
+        assert_eq!(tracker.total().spent(), 2);
+        assert_eq!(tracker.unspents.len(), 2);
+    }
+}
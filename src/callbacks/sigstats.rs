@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::signature::{self, Signature, SignatureKind};
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Sighash types in the order of the csv columns, anything else is counted as `other`
+const SIGHASH_TYPES: [u8; 7] = [
+    signature::SIGHASH_DEFAULT,
+    0x01,
+    0x02,
+    0x03,
+    0x81,
+    0x82,
+    0x83,
+];
+
+/// Signature counts of a single month
+#[derive(Default)]
+struct MonthStats {
+    ecdsa: u64,
+    schnorr: u64,
+    low_r: u64,
+    high_s: u64,
+    // Counts by index in SIGHASH_TYPES, the last one are the other types
+    sighashes: [u64; SIGHASH_TYPES.len() + 1],
+}
+
+impl MonthStats {
+    fn add(&mut self, sig: &Signature) {
+        match sig.kind {
+            SignatureKind::Ecdsa => {
+                self.ecdsa += 1;
+                self.low_r += sig.low_r as u64;
+                self.high_s += !sig.low_s as u64;
+            }
+            SignatureKind::Schnorr => self.schnorr += 1,
+        }
+        let i = SIGHASH_TYPES
+            .iter()
+            .position(|sighash| *sighash == sig.sighash)
+            .unwrap_or(SIGHASH_TYPES.len());
+        self.sighashes[i] += 1;
+    }
+
+    fn as_csv(&self, month: &str) -> String {
+        let sighashes: Vec<String> = self.sighashes.iter().map(u64::to_string).collect();
+        // (month, ecdsa, schnorr, lowR, highS, default, all, none, single,
+        //  allAcp, noneAcp, singleAcp, other)
+        format!(
+            "{};{};{};{};{};{}\n",
+            month,
+            self.ecdsa,
+            self.schnorr,
+            self.low_r,
+            self.high_s,
+            sighashes.join(";")
+        )
+    }
+}
+
+/// Analyzes the signatures found in the scriptSig and witness of all inputs:
+/// ECDSA vs. Schnorr, sighash types, low-R grinding and malleable high-S signatures per month,
+/// as well as a histogram of the signature sizes.
+pub struct SigStats {
+    output: Output,
+    writer: Box<dyn Sink>,
+    lengths_writer: Box<dyn Sink>,
+
+    start_height: u64,
+    n_inputs: u64,
+    months: BTreeMap<String, MonthStats>,
+    // Number of signatures by kind and size
+    lengths: BTreeMap<(SignatureKind, usize), u64>,
+}
+
+impl SigStats {
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let mut total = MonthStats::default();
+        for stats in self.months.values() {
+            total.ecdsa += stats.ecdsa;
+            total.schnorr += stats.schnorr;
+            total.low_r += stats.low_r;
+            total.high_s += stats.high_s;
+        }
+        writeln!(buffer, "SigStats:")?;
+        writeln!(buffer, "   -> inputs:\t\t{}", self.n_inputs)?;
+        writeln!(buffer, "   -> ecdsa:\t\t{}", total.ecdsa)?;
+        writeln!(buffer, "   -> schnorr:\t\t{}", total.schnorr)?;
+        writeln!(
+            buffer,
+            "   -> low-R:\t\t{} ({:.2}%)",
+            total.low_r,
+            total.low_r as f64 / total.ecdsa.max(1) as f64 * 100.00
+        )?;
+        writeln!(buffer, "   -> high-S:\t\t{}", total.high_s)?;
+        Ok(())
+    }
+}
+
+impl Callback for SigStats {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("sigstats")
+            .about("Dumps signature types, sighash flags and sizes of all inputs to CSV files")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv files")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = SigStats {
+            writer: output.open("sigstats")?,
+            lengths_writer: output.open("siglengths")?,
+            output,
+            start_height: 0,
+            n_inputs: 0,
+            months: BTreeMap::new(),
+            lengths: BTreeMap::new(),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing sigstats with dump folder: {} ...", &self.output);
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, _: u64) -> OpResult<()> {
+        let month = utils::month_of(block.header.value.timestamp);
+        let stats = self.months.entry(month).or_default();
+        for tx in block.txs.iter().filter(|tx| !tx.value.is_coinbase()) {
+            for input in &tx.value.inputs {
+                for sig in signature::extract_signatures(input) {
+                    stats.add(&sig);
+                    *self.lengths.entry((sig.kind, sig.len)).or_insert(0) += 1;
+                }
+                self.n_inputs += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.write_all(
+            "month;ecdsa;schnorr;lowR;highS;default;all;none;single;allAcp;noneAcp;singleAcp;other\n"
+                .as_bytes(),
+        )?;
+        for (month, stats) in &self.months {
+            self.writer.write_all(stats.as_csv(month).as_bytes())?;
+        }
+        self.writer.finish(self.start_height, block_height)?;
+
+        self.lengths_writer
+            .write_all("kind;length;count\n".as_bytes())?;
+        for ((kind, len), count) in &self.lengths {
+            // (kind, length, count)
+            self.lengths_writer
+                .write_all(format!("{};{};{}\n", kind, len, count).as_bytes())?;
+        }
+        self.lengths_writer
+            .finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "sigstats", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
@@ -1,19 +1,19 @@
-use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::hashes::Hash;
 use std::collections::HashMap;
 use std::io::{self, Write};
 
 use clap::{ArgMatches, Command};
 
 use crate::blockchain::proto::block::{self, Block};
-use crate::blockchain::proto::script::ScriptPattern;
-use crate::blockchain::proto::ToRaw;
-use crate::callbacks::Callback;
-use crate::common::utils;
+use crate::blockchain::proto::script::{InputPattern, ScriptPattern};
+use crate::blockchain::proto::{ToRaw, TxId};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::quantile::Distribution;
 use crate::errors::OpResult;
 
 pub struct SimpleStats {
     n_valid_blocks: u64,
-    block_sizes: Vec<u32>,
+    block_sizes: Distribution,
 
     n_tx: u64,
     n_tx_inputs: u64,
@@ -22,17 +22,23 @@ pub struct SimpleStats {
     n_tx_total_volume: u64,
 
     /// Biggest value transaction (value, height, txid)
-    tx_biggest_value: (u64, u64, sha256d::Hash),
+    tx_biggest_value: (u64, u64, TxId),
     /// Biggest size transaction (size, height, txid)
-    tx_biggest_size: (usize, u64, sha256d::Hash),
+    tx_biggest_size: (usize, u64, TxId),
     /// Contains transaction type count
     n_tx_types: HashMap<ScriptPattern, u64>,
     /// First occurence of transaction type
     /// (block_height, txid, index)
-    tx_first_occs: HashMap<ScriptPattern, (u64, sha256d::Hash, u32)>,
+    tx_first_occs: HashMap<ScriptPattern, (u64, TxId, u32)>,
+    /// Contains input type count
+    n_input_types: HashMap<InputPattern, u64>,
+
+    /// Sizes and outputs of all transactions
+    tx_sizes: Distribution,
+    tx_outputs: Distribution,
 
     /// Time stats
-    t_between_blocks: Vec<u32>,
+    t_between_blocks: Distribution,
     last_timestamp: u32,
 }
 
@@ -40,17 +46,20 @@ impl Default for SimpleStats {
     fn default() -> Self {
         SimpleStats {
             n_valid_blocks: 0,
-            block_sizes: vec![],
+            block_sizes: Distribution::default(),
             n_tx: 0,
             n_tx_inputs: 0,
             n_tx_outputs: 0,
             n_tx_total_fee: 0,
             n_tx_total_volume: 0,
-            tx_biggest_value: (0, 0, sha256d::Hash::all_zeros()),
-            tx_biggest_size: (0, 0, sha256d::Hash::all_zeros()),
+            tx_biggest_value: (0, 0, TxId::all_zeros()),
+            tx_biggest_size: (0, 0, TxId::all_zeros()),
             n_tx_types: HashMap::new(),
             tx_first_occs: HashMap::new(),
-            t_between_blocks: vec![],
+            n_input_types: HashMap::new(),
+            tx_sizes: Distribution::default(),
+            tx_outputs: Distribution::default(),
+            t_between_blocks: Distribution::default(),
             last_timestamp: 0,
         }
     }
@@ -62,7 +71,7 @@ impl SimpleStats {
         &mut self,
         script_pattern: ScriptPattern,
         block_height: u64,
-        txid: sha256d::Hash,
+        txid: TxId,
         index: u32,
     ) {
         // Strip exact OP_RETURN bytes
@@ -80,6 +89,22 @@ impl SimpleStats {
         }
     }
 
+    /// Returns the report which is logged when done
+    pub fn report(&self) -> io::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_simple_stats(&mut buffer)?;
+        writeln!(&mut buffer)?;
+        self.print_unusual_transactions(&mut buffer)?;
+        self.print_averages(&mut buffer)?;
+        writeln!(&mut buffer)?;
+        self.print_distributions(&mut buffer)?;
+        writeln!(&mut buffer)?;
+        self.print_transaction_types(&mut buffer)?;
+        writeln!(&mut buffer)?;
+        self.print_input_types(&mut buffer)?;
+        Ok(buffer)
+    }
+
     fn print_simple_stats(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
         writeln!(buffer, "SimpleStats:")?;
         writeln!(buffer, "   -> valid blocks:\t\t{}", self.n_valid_blocks)?;
@@ -106,12 +131,12 @@ impl SimpleStats {
         writeln!(
             buffer,
             "   -> avg block size:\t\t{:.2} KiB",
-            utils::get_mean(&self.block_sizes) / 1024.00
+            self.block_sizes.mean() / 1024.00
         )?;
         writeln!(
             buffer,
             "   -> avg time between blocks:\t{:.2} (minutes)",
-            utils::get_mean(&self.t_between_blocks) / 60.00
+            self.t_between_blocks.mean() / 60.00
         )?;
         writeln!(
             buffer,
@@ -136,6 +161,36 @@ impl SimpleStats {
         Ok(())
     }
 
+    /// Medians and 95th percentiles are estimated while parsing, see `P2Quantile`
+    fn print_distributions(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "Distributions (median, p95):")?;
+        writeln!(
+            buffer,
+            "   -> block size:\t\t{:.2}, {:.2} KiB",
+            self.block_sizes.median() / 1024.00,
+            self.block_sizes.p95() / 1024.00
+        )?;
+        writeln!(
+            buffer,
+            "   -> time between blocks:\t{:.2}, {:.2} (minutes)",
+            self.t_between_blocks.median() / 60.00,
+            self.t_between_blocks.p95() / 60.00
+        )?;
+        writeln!(
+            buffer,
+            "   -> tx size:\t\t\t{:.0}, {:.0} bytes",
+            self.tx_sizes.median(),
+            self.tx_sizes.p95()
+        )?;
+        writeln!(
+            buffer,
+            "   -> outputs per tx:\t\t{:.0}, {:.0}",
+            self.tx_outputs.median(),
+            self.tx_outputs.p95()
+        )?;
+        Ok(())
+    }
+
     fn print_unusual_transactions(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
         let (value, height, txid) = self.tx_biggest_value;
         writeln!(
@@ -161,7 +216,9 @@ impl SimpleStats {
 
     fn print_transaction_types(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
         writeln!(buffer, "Transaction Types:")?;
-        for (pattern, count) in &self.n_tx_types {
+        let mut types: Vec<(&ScriptPattern, &u64)> = self.n_tx_types.iter().collect();
+        types.sort_unstable();
+        for (pattern, count) in types {
             writeln!(
                 buffer,
                 "   -> {:?}: {} ({:.2}%)",
@@ -179,6 +236,22 @@ impl SimpleStats {
         }
         Ok(())
     }
+
+    fn print_input_types(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "Input Types:")?;
+        let mut types: Vec<(&InputPattern, &u64)> = self.n_input_types.iter().collect();
+        types.sort_unstable();
+        for (pattern, count) in types {
+            writeln!(
+                buffer,
+                "   -> {}: {} ({:.2}%)",
+                pattern,
+                count,
+                (*count as f64 / self.n_tx_inputs as f64) * 100.00
+            )?;
+        }
+        Ok(())
+    }
 }
 
 impl Callback for SimpleStats {
@@ -192,7 +265,7 @@ impl Callback for SimpleStats {
             .author("gcarq <egger.m@protonmail.com>")
     }
 
-    fn new(_: &ArgMatches) -> OpResult<Self>
+    fn new(_: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
     where
         Self: Sized,
     {
@@ -207,7 +280,7 @@ impl Callback for SimpleStats {
     fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
         self.n_valid_blocks += 1;
         self.n_tx += block.tx_count.value;
-        self.block_sizes.push(block.size);
+        self.block_sizes.add(block.size as f64);
 
         for tx in &block.txs {
             // Collect fee rewards
@@ -221,6 +294,10 @@ impl Callback for SimpleStats {
 
             self.n_tx_inputs += tx.value.in_count.value;
             self.n_tx_outputs += tx.value.out_count.value;
+            self.tx_outputs.add(tx.value.out_count.value as f64);
+            for i in &tx.value.inputs {
+                *self.n_input_types.entry(i.pattern.clone()).or_insert(0) += 1;
+            }
 
             let mut tx_value = 0;
             for (i, o) in tx.value.outputs.iter().enumerate() {
@@ -236,6 +313,7 @@ impl Callback for SimpleStats {
 
             // Calculate and save biggest size transaction
             let tx_size = tx.value.to_bytes().len();
+            self.tx_sizes.add(tx_size as f64);
             if tx_size > self.tx_biggest_size.0 {
                 self.tx_biggest_size = (tx_size, block_height, tx.hash);
             }
@@ -249,21 +327,19 @@ impl Callback for SimpleStats {
                 .timestamp
                 .checked_sub(self.last_timestamp)
                 .unwrap_or_default();
-            self.t_between_blocks.push(diff);
+            self.t_between_blocks.add(diff as f64);
         }
         self.last_timestamp = block.header.value.timestamp;
         Ok(())
     }
 
     fn on_complete(&mut self, _: u64) -> OpResult<()> {
-        let mut buffer = Vec::with_capacity(4096);
-        self.print_simple_stats(&mut buffer)?;
-        writeln!(&mut buffer)?;
-        self.print_unusual_transactions(&mut buffer)?;
-        self.print_averages(&mut buffer)?;
-        writeln!(&mut buffer)?;
-        self.print_transaction_types(&mut buffer)?;
+        let buffer = self.report()?;
         info!(target: "simplestats", "\n\n{}", String::from_utf8_lossy(&buffer));
         Ok(())
     }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
 }
@@ -0,0 +1,149 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use postgres::{Client, NoTls};
+
+use crate::callbacks::sink::{spool, Sink};
+use crate::callbacks::tempoutput::TempOutput;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Spools the data to the temp directory and loads it with `COPY` into the table named after
+/// the file (`-` replaced by `_`) when finished, all rows in a single transaction.
+/// A first line of column names (letters, digits and `_`) is taken as header and missing tables
+/// are created from it with text columns. Files without header (e.g. of csvdump) need an
+/// existing table with the columns in the order of the file.
+pub struct PostgresTable {
+    writer: BufWriter<TempOutput>,
+    uri: String,
+    table: String,
+}
+
+impl PostgresTable {
+    pub fn create(uri: &str, name: &str, ext: &'static str) -> OpResult<Self> {
+        let table = name.replace('-', "_");
+        if !is_name(&table) {
+            let msg = format!("{} is not a valid table name", table);
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+        }
+        Ok(Self {
+            writer: spool(name, ext)?,
+            uri: String::from(uri),
+            table,
+        })
+    }
+}
+
+impl Write for PostgresTable {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Sink for PostgresTable {
+    fn finish(&mut self, _: u64, _: u64) -> OpResult<()> {
+        self.flush()?;
+        info!(target: "sink", "Loading rows into table {} ...", &self.table);
+        load(&self.uri, &self.table, self.writer.get_ref().path())
+    }
+
+    fn sync(&mut self) -> OpResult<()> {
+        // The rows are only loaded once the file is complete
+        self.flush()?;
+        Ok(())
+    }
+}
+
+/// Returns true if the value can be used as table or column name without escaping
+fn is_name(value: &str) -> bool {
+    value
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns the column names if the line is a header
+fn header_columns(line: &str) -> Option<Vec<&str>> {
+    let columns: Vec<&str> = line.split(';').collect();
+    columns.iter().all(|c| is_name(c)).then_some(columns)
+}
+
+/// Returns the statements to create the table if it's missing and to copy the rows into it
+fn statements(table: &str, columns: Option<&[&str]>) -> (Option<String>, String) {
+    let options = "WITH (FORMAT csv, DELIMITER ';')";
+    match columns {
+        Some(columns) => {
+            let quoted: Vec<String> = columns.iter().map(|c| format!("\"{}\"", c)).collect();
+            let create = format!(
+                "CREATE TABLE IF NOT EXISTS \"{}\" ({} TEXT)",
+                table,
+                quoted.join(" TEXT, ")
+            );
+            let copy = format!(
+                "COPY \"{}\" ({}) FROM STDIN {}",
+                table,
+                quoted.join(", "),
+                options
+            );
+            (Some(create), copy)
+        }
+        None => (None, format!("COPY \"{}\" FROM STDIN {}", table, options)),
+    }
+}
+
+fn load(uri: &str, table: &str, path: &Path) -> OpResult<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut first_line = String::new();
+    reader.read_line(&mut first_line)?;
+    let columns = header_columns(first_line.trim_end());
+    let (create, copy) = statements(table, columns.as_deref());
+
+    let mut client = Client::connect(uri, NoTls)?;
+    let mut transaction = client.transaction()?;
+    if let Some(create) = create {
+        transaction.batch_execute(&create)?;
+    }
+    let mut writer = transaction.copy_in(&copy)?;
+    if columns.is_none() {
+        writer.write_all(first_line.as_bytes())?;
+    }
+    io::copy(&mut reader, &mut writer)?;
+    writer.finish()?;
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statements() {
+        let columns = header_columns("address;balance;scriptHash").unwrap();
+        let (create, copy) = statements("balances", Some(&columns));
+        assert_eq!(
+            create.unwrap(),
+            "CREATE TABLE IF NOT EXISTS \"balances\" (\"address\" TEXT, \"balance\" TEXT, \"scriptHash\" TEXT)"
+        );
+        assert_eq!(
+            copy,
+            "COPY \"balances\" (\"address\", \"balance\", \"scriptHash\") FROM STDIN WITH (FORMAT csv, DELIMITER ';')"
+        );
+
+        // Rows of csvdump
+        assert!(header_columns("00000000839a8e68;1;1;215;5000000000").is_none());
+        assert!(header_columns("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq;100").is_none());
+        let (create, copy) = statements("blocks", None);
+        assert!(create.is_none());
+        assert_eq!(
+            copy,
+            "COPY \"blocks\" FROM STDIN WITH (FORMAT csv, DELIMITER ';')"
+        );
+        assert!(PostgresTable::create("postgres://localhost/db", "x;y", "csv").is_err());
+    }
+}
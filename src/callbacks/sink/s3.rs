@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+
+use crate::callbacks::sink::{spool, Sink};
+use crate::callbacks::tempoutput::TempOutput;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Files up to this size are uploaded at once, larger ones in parts of this size
+const PART_SIZE: usize = 64 * 1024 * 1024;
+
+/// Spools the data to the temp directory and uploads it as `<prefix>/<name>-<start>-<end>.<ext>`
+/// when finished. Credentials and region are taken from the environment like the AWS CLI does
+/// (`AWS_ACCESS_KEY_ID`, `AWS_REGION`, `~/.aws/config`, ...).
+pub struct S3Object {
+    writer: BufWriter<TempOutput>,
+    bucket: String,
+    prefix: String,
+    name: String,
+    ext: &'static str,
+}
+
+impl S3Object {
+    pub fn create(bucket: &str, prefix: &str, name: &str, ext: &'static str) -> OpResult<Self> {
+        Ok(Self {
+            writer: spool(name, ext)?,
+            bucket: String::from(bucket),
+            prefix: String::from(prefix),
+            name: String::from(name),
+            ext,
+        })
+    }
+
+    fn key(&self, start_height: u64, end_height: u64) -> String {
+        let file = format!("{}-{}-{}.{}", self.name, start_height, end_height, self.ext);
+        match self.prefix.as_str() {
+            "" => file,
+            prefix => format!("{}/{}", prefix, file),
+        }
+    }
+}
+
+impl Write for S3Object {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Sink for S3Object {
+    fn finish(&mut self, start_height: u64, end_height: u64) -> OpResult<()> {
+        self.flush()?;
+        let key = self.key(start_height, end_height);
+        info!(target: "sink", "Uploading s3://{}/{} ...", &self.bucket, &key);
+        upload(&self.bucket, &key, self.writer.get_ref().path())
+    }
+
+    fn sync(&mut self) -> OpResult<()> {
+        // The data only leaves the machine once it's complete
+        self.flush()?;
+        Ok(())
+    }
+}
+
+fn s3_error<E: std::error::Error>(err: E) -> OpError {
+    OpError::new(OpErrorKind::S3Error(DisplayErrorContext(err).to_string()))
+}
+
+fn read_part(file: &mut File) -> io::Result<Vec<u8>> {
+    let mut part = Vec::with_capacity(PART_SIZE);
+    Read::by_ref(file)
+        .take(PART_SIZE as u64)
+        .read_to_end(&mut part)?;
+    Ok(part)
+}
+
+/// Uploads the file, objects can only be written at once with a single request up to 5 GiB
+fn upload(bucket: &str, key: &str, path: &Path) -> OpResult<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let client = Client::new(&config);
+        let mut file = File::open(path)?;
+        let part = read_part(&mut file)?;
+        if part.len() < PART_SIZE {
+            client
+                .put_object()
+                .bucket(bucket)
+                .key(key)
+                .body(ByteStream::from(part))
+                .send()
+                .await
+                .map_err(s3_error)?;
+            return Ok(());
+        }
+
+        let upload = client
+            .create_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(s3_error)?;
+        let upload_id = upload.upload_id().unwrap_or_default();
+        let result = upload_parts(&client, bucket, key, upload_id, part, &mut file).await;
+        if result.is_err() {
+            // Otherwise the uploaded parts are kept (and billed) until they expire
+            let _ = client
+                .abort_multipart_upload()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+        }
+        result
+    })
+}
+
+async fn upload_parts(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    mut part: Vec<u8>,
+    file: &mut File,
+) -> OpResult<()> {
+    let mut parts = Vec::new();
+    while !part.is_empty() {
+        let number = parts.len() as i32 + 1;
+        let output = client
+            .upload_part()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(number)
+            .body(ByteStream::from(part))
+            .send()
+            .await
+            .map_err(s3_error)?;
+        parts.push(
+            CompletedPart::builder()
+                .set_e_tag(output.e_tag().map(String::from))
+                .part_number(number)
+                .build(),
+        );
+        part = read_part(file)?;
+    }
+    client
+        .complete_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build(),
+        )
+        .send()
+        .await
+        .map_err(s3_error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        let mut object = S3Object::create("bucket", "dumps/bitcoin", "blocks", "csv").unwrap();
+        assert_eq!(object.key(0, 100), "dumps/bitcoin/blocks-0-100.csv");
+        object.prefix = String::new();
+        assert_eq!(object.key(0, 100), "blocks-0-100.csv");
+    }
+}
@@ -0,0 +1,189 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use clap::{Arg, ArgAction, ArgMatches};
+
+use crate::errors::OpResult;
+
+/// Rows are spilled to a sorted chunk file once they exceed this many bytes
+const CHUNK_SIZE: usize = 256 * 1024 * 1024;
+/// Separates the sort key from the row in chunk files
+const KEY_SEPARATOR: char = '\0';
+
+/// Distinguishes the chunk folders of multiple sorters within a process
+static SORTER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Sorts csv rows by a key before writing them, so dumps of different runs are identical.
+/// Rows which don't fit into memory are written to sorted chunk files in the temp dir
+/// and merged once all rows were pushed.
+pub struct RowSorter {
+    rows: Vec<(String, String)>,
+    n_bytes: usize,
+    chunk_size: usize,
+    chunk_dir: PathBuf,
+    chunks: Vec<PathBuf>,
+}
+
+impl RowSorter {
+    /// Builds the optional `--sorted` argument for callbacks that dump hash map contents
+    pub fn build_arg() -> Arg {
+        Arg::new("sorted")
+            .long("sorted")
+            .action(ArgAction::SetTrue)
+            .help("Sorts the rows by address or outpoint, so dumps of different runs can be compared line by line")
+    }
+
+    /// Returns a sorter if `--sorted` was specified
+    pub fn from_matches(matches: &ArgMatches) -> Option<Self> {
+        match matches.get_flag("sorted") {
+            true => Some(RowSorter::new()),
+            false => None,
+        }
+    }
+
+    pub fn new() -> Self {
+        let id = SORTER_ID.fetch_add(1, Ordering::Relaxed);
+        RowSorter {
+            rows: Vec::new(),
+            n_bytes: 0,
+            chunk_size: CHUNK_SIZE,
+            chunk_dir: env::temp_dir().join(format!(
+                "rusty-blockparser-sort-{}-{}",
+                process::id(),
+                id
+            )),
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Adds a row (including the trailing newline) which is ordered by the given key
+    pub fn push(&mut self, key: String, row: String) -> OpResult<()> {
+        self.n_bytes += key.len() + row.len();
+        self.rows.push((key, row));
+        if self.n_bytes >= self.chunk_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Writes all pushed rows in ascending key order and resets the sorter
+    pub fn write_to<W: Write + ?Sized>(&mut self, writer: &mut W) -> OpResult<()> {
+        self.rows.sort_unstable();
+        if self.chunks.is_empty() {
+            for (_, row) in self.rows.drain(..) {
+                writer.write_all(row.as_bytes())?;
+            }
+            self.n_bytes = 0;
+            return Ok(());
+        }
+        self.spill()?;
+        info!(target: "sorter", "Merging {} sorted chunks ...", self.chunks.len());
+
+        let mut chunks = Vec::with_capacity(self.chunks.len());
+        let mut heap = BinaryHeap::with_capacity(self.chunks.len());
+        for (i, path) in self.chunks.iter().enumerate() {
+            let mut lines = BufReader::new(File::open(path)?).lines();
+            if let Some(entry) = next_entry(&mut lines)? {
+                heap.push(Reverse((entry, i)));
+            }
+            chunks.push(lines);
+        }
+        while let Some(Reverse(((_, row), i))) = heap.pop() {
+            writer.write_all(row.as_bytes())?;
+            writer.write_all(b"\n")?;
+            if let Some(entry) = next_entry(&mut chunks[i])? {
+                heap.push(Reverse((entry, i)));
+            }
+        }
+        self.remove_chunks();
+        Ok(())
+    }
+
+    /// Writes the sorted rows to a new chunk file
+    fn spill(&mut self) -> OpResult<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.chunk_dir)?;
+        let path = self.chunk_dir.join(format!("{}.chunk", self.chunks.len()));
+        debug!(target: "sorter", "Writing {} rows to {} ...", self.rows.len(), path.display());
+        self.rows.sort_unstable();
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for (key, row) in self.rows.drain(..) {
+            write!(writer, "{}{}{}", key, KEY_SEPARATOR, row)?;
+        }
+        writer.flush()?;
+        self.chunks.push(path);
+        self.n_bytes = 0;
+        Ok(())
+    }
+
+    fn remove_chunks(&mut self) {
+        if self.chunks.is_empty() {
+            return;
+        }
+        if let Err(why) = fs::remove_dir_all(&self.chunk_dir) {
+            warn!(target: "sorter", "Unable to remove {}: {}", self.chunk_dir.display(), why);
+        }
+        self.chunks.clear();
+    }
+}
+
+impl Drop for RowSorter {
+    fn drop(&mut self) {
+        self.remove_chunks();
+    }
+}
+
+/// Reads the next key and row (without newline) from a chunk file
+fn next_entry(lines: &mut Lines<BufReader<File>>) -> OpResult<Option<(String, String)>> {
+    match lines.next() {
+        Some(line) => {
+            let line = line?;
+            let (key, row) = line.split_once(KEY_SEPARATOR).unwrap_or((&line, ""));
+            Ok(Some((String::from(key), String::from(row))))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sort(sorter: &mut RowSorter, keys: &[&str]) -> String {
+        for key in keys {
+            sorter
+                .push(String::from(*key), format!("{};row\n", key))
+                .unwrap();
+        }
+        let mut out = Vec::new();
+        sorter.write_to(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_sort_in_memory() {
+        let mut sorter = RowSorter::new();
+        assert_eq!(sort(&mut sorter, &["c", "a", "b"]), "a;row\nb;row\nc;row\n");
+        assert!(!sorter.chunk_dir.exists());
+    }
+
+    #[test]
+    fn test_sort_chunked() {
+        let mut sorter = RowSorter::new();
+        sorter.chunk_size = 16;
+        let keys = ["e", "b", "g", "a", "d", "f", "c"];
+        assert_eq!(
+            sort(&mut sorter, &keys),
+            "a;row\nb;row\nc;row\nd;row\ne;row\nf;row\ng;row\n"
+        );
+        assert!(!sorter.chunk_dir.exists());
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt;
+#[cfg(unix)]
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::events;
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Address a single consumer connects to.
+/// Parsed from the `address` argument:
+///   * `tcp:<host>:<port>`: TCP listener
+///   * `unix:<path>`: Unix domain socket, the file is removed again when parsing ends
+enum Address {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Address {
+    fn parse(address: &str) -> OpResult<Self> {
+        let address = match address.split_once(':') {
+            Some(("tcp", addr)) => Address::Tcp(String::from(addr)),
+            #[cfg(unix)]
+            Some(("unix", path)) => Address::Unix(PathBuf::from(path)),
+            #[cfg(not(unix))]
+            Some(("unix", _)) => {
+                return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg("Unix domain sockets are not supported on this platform"))
+            }
+            _ => {
+                let msg = format!(
+                    "Invalid stream address: {}. Expected tcp:<host>:<port> or unix:<path>",
+                    address
+                );
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+            }
+        };
+        Ok(address)
+    }
+
+    fn bind(&self) -> OpResult<Listener> {
+        let listener = match self {
+            Address::Tcp(addr) => Listener::Tcp(TcpListener::bind(addr)?),
+            #[cfg(unix)]
+            Address::Unix(path) => Listener::Unix(UnixListener::bind(path)?, path.clone()),
+        };
+        Ok(listener)
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// Blocks until a consumer connects and returns the connection
+    fn accept(&self) -> OpResult<Connection> {
+        let conn = match self {
+            Listener::Tcp(listener) => Connection::Tcp(listener.accept()?.0),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Connection::Unix(listener.accept()?.0),
+        };
+        Ok(conn)
+    }
+
+    /// Removes the socket file of Unix domain sockets
+    fn close(&self) -> OpResult<()> {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "tcp:{}", addr),
+                Err(_) => write!(f, "tcp"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(_, path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    fn shutdown(&self) -> OpResult<()> {
+        match self {
+            Connection::Tcp(stream) => stream.shutdown(Shutdown::Write)?,
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.shutdown(Shutdown::Write)?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Streams parsed blocks and optionally transactions as newline delimited JSON to a consumer.
+/// Writes block until the consumer has read the previous events, so a slow consumer slows down the parser.
+pub struct Stream {
+    address: Address,
+    // Bound in on_start, so no socket file is left behind if parsing never starts
+    listener: Option<Listener>,
+    writer: Option<BufWriter<Connection>>,
+    include_txs: bool,
+}
+
+impl Stream {
+    /// Stops listening, removes the socket file of Unix domain sockets
+    fn close(&mut self) -> OpResult<()> {
+        match self.listener.take() {
+            Some(listener) => listener.close(),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the completion event and closes the connection
+    fn complete(&mut self, block_height: u64) -> OpResult<()> {
+        let event = format!("{{\"type\":\"complete\",\"height\":{}}}\n", block_height);
+        let writer = self.writer();
+        writer.write_all(event.as_bytes())?;
+        writer.flush()?;
+        writer.get_ref().shutdown()?;
+        Ok(())
+    }
+
+    fn writer(&mut self) -> &mut BufWriter<Connection> {
+        self.writer
+            .as_mut()
+            .expect("on_start must be called before streaming events")
+    }
+
+    fn write_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let event = events::block_json(block, block_height);
+        writeln!(self.writer(), "{}", event)?;
+        if self.include_txs {
+            for tx in &block.txs {
+                let event = events::tx_json(tx, block_height);
+                writeln!(self.writer(), "{}", event)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Callback for Stream {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("stream")
+            .about("Streams blocks and transactions as NDJSON to a TCP or Unix domain socket")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("address")
+                    .help("Address to listen on for a consumer (tcp:<host>:<port> or unix:<path>)")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("txs")
+                    .long("txs")
+                    .action(ArgAction::SetTrue)
+                    .help("Emits an event for each transaction in addition to blocks"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let cb = Stream {
+            address: Address::parse(matches.get_one::<String>("address").unwrap())?,
+            listener: None,
+            writer: None,
+            include_txs: matches.get_flag("txs"),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, _: u64) -> OpResult<()> {
+        let listener = self.address.bind()?;
+        info!(target: "callback", "Executing stream, waiting for a consumer on {} ...", &listener);
+        let conn = listener.accept();
+        self.listener = Some(listener);
+        match conn {
+            Ok(conn) => self.writer = Some(BufWriter::new(conn)),
+            Err(err) => {
+                self.close()?;
+                return Err(err);
+            }
+        }
+        info!(target: "callback", "Consumer connected, streaming events ...");
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        self.write_block(block, block_height)?;
+        // Hand each block to the consumer immediately
+        self.writer().flush()?;
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        let result = self.complete(block_height);
+        self.close()?;
+        result?;
+        info!(target: "callback", "Done. Streamed all blocks up to height {}.", block_height);
+        Ok(())
+    }
+
+    fn on_abort(&mut self, _: Option<u64>) {
+        // The consumer sees the end of the stream without a completion event
+        self.writer = None;
+        if let Err(err) = self.close() {
+            warn!(target: "callback", "Unable to remove the socket: {}", err);
+        }
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_lifecycle() {
+        use crate::command;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("stream.sock");
+        let address = format!("unix:{}", socket.display());
+        let matches = command().get_matches_from(["rusty-blockparser", "stream", &address]);
+        let mut stream = Stream::new(
+            matches.subcommand_matches("stream").unwrap(),
+            &CallbackContext::default(),
+        )
+        .unwrap();
+        assert!(!socket.exists());
+
+        stream.listener = Some(stream.address.bind().unwrap());
+        assert!(socket.exists());
+        stream.on_abort(None);
+        assert!(!socket.exists());
+        assert!(stream.listener.is_none());
+
+        assert!(Address::parse("udp:localhost:9000").is_err());
+    }
+}
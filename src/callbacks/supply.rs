@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+use crate::blockchain::proto::Hashed;
+use crate::callbacks::burns;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Average length of a year in seconds
+const YEAR: u64 = 31557600;
+
+/// Unspent value by the height it was created at.
+/// Outputs created before the cutoff height are zombies, the cutoff only moves forward.
+#[derive(Default)]
+struct UtxoAges {
+    // Unspent value and block timestamp by creation height
+    created: Vec<(u64, u32)>,
+    cutoff: usize,
+    zombie: u64,
+}
+
+impl UtxoAges {
+    /// Registers a block, heights must be ascending
+    fn add_block(&mut self, height: u64, timestamp: u32) {
+        let height = height as usize;
+        if self.created.len() <= height {
+            self.created.resize(height + 1, (0, 0));
+        }
+        self.created[height].1 = timestamp;
+    }
+
+    fn add(&mut self, height: u64, value: u64) {
+        self.created[height as usize].0 += value;
+    }
+
+    /// Removes a spent output, unknown heights are ignored
+    fn spend(&mut self, height: u64, value: u64) {
+        if let Some(entry) = self.created.get_mut(height as usize) {
+            entry.0 = entry.0.saturating_sub(value);
+            if (height as usize) < self.cutoff {
+                self.zombie = self.zombie.saturating_sub(value);
+            }
+        }
+    }
+
+    /// Moves the cutoff to the first block which is younger than `max_age` seconds at `now`
+    /// and returns the value of all older unspent outputs.
+    fn zombie(&mut self, now: u32, max_age: u64) -> u64 {
+        while let Some((value, timestamp)) = self.created.get(self.cutoff) {
+            if *timestamp as u64 + max_age > now as u64 {
+                break;
+            }
+            self.zombie += value;
+            self.cutoff += 1;
+        }
+        self.zombie
+    }
+}
+
+/// Dumps the coin supply per height: the value of all outputs, the provably burned value,
+/// the circulating supply and the value of zombie coins, i.e. unspent outputs which were not
+/// moved for more than the given number of years. Zombie coins are a common estimate for lost coins.
+pub struct Supply {
+    output: Output,
+    writer: Box<dyn Sink>,
+    burn_addresses: &'static [&'static str],
+    // Dumps a row every n blocks
+    interval: u64,
+    // Age in seconds from which on unspent outputs are counted as zombies
+    zombie_age: u64,
+
+    // Spent outputs are resolved by undo data if available, otherwise by the tracked unspents
+    use_undo_files: bool,
+    // Height and value of the unspent outputs
+    unspents: HashMap<TxOutpoint, (u64, u64)>,
+    ages: UtxoAges,
+
+    start_height: u64,
+    // Value of all spendable unspent outputs
+    unspent: u64,
+    burned: u64,
+    zombie: u64,
+    n_unresolved: u64,
+}
+
+impl Supply {
+    /// Removes the spent outputs from the unspent value
+    fn spend(&mut self, tx: &EvaluatedTx) {
+        for input in &tx.inputs {
+            let spent = match self.use_undo_files {
+                true => input
+                    .prevout
+                    .as_ref()
+                    .map(|prevout| (prevout.height, prevout.out.out.value)),
+                false => self.unspents.remove(&input.outpoint),
+            };
+            match spent {
+                Some((height, value)) => {
+                    self.unspent = self.unspent.saturating_sub(value);
+                    self.ages.spend(height, value);
+                }
+                None => self.n_unresolved += 1,
+            }
+        }
+    }
+
+    /// Adds the new outputs to the unspent or burned value
+    fn receive(&mut self, tx: &Hashed<EvaluatedTx>, block_height: u64) {
+        for (i, output) in tx.value.outputs.iter().enumerate() {
+            let value = output.out.value;
+            if burns::classify_burn(
+                &output.script,
+                &output.out.script_pubkey,
+                self.burn_addresses,
+            )
+            .is_some()
+            {
+                self.burned += value;
+                continue;
+            }
+            self.unspent += value;
+            self.ages.add(block_height, value);
+            if !self.use_undo_files {
+                self.unspents
+                    .insert(TxOutpoint::new(tx.hash, i as u32), (block_height, value));
+            }
+        }
+    }
+
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let supply = self.unspent + self.burned;
+        writeln!(buffer, "Supply:")?;
+        writeln!(buffer, "   -> supply:\t\t{:.8}", supply as f64 * 1E-8)?;
+        writeln!(buffer, "   -> burned:\t\t{:.8}", self.burned as f64 * 1E-8)?;
+        writeln!(
+            buffer,
+            "   -> circulating:\t{:.8}",
+            self.unspent as f64 * 1E-8
+        )?;
+        writeln!(
+            buffer,
+            "   -> zombie:\t\t{:.8} ({:.2}%)",
+            self.zombie as f64 * 1E-8,
+            self.zombie as f64 / self.unspent.max(1) as f64 * 100.00
+        )?;
+        writeln!(buffer, "   -> unresolved inputs:\t{}", self.n_unresolved)?;
+        Ok(())
+    }
+}
+
+impl Callback for Supply {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("supply")
+            .about("Dumps the circulating supply, burned and zombie coins per height to a CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("interval")
+                    .long("interval")
+                    .value_name("BLOCKS")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("1")
+                    .help("Dumps a row every BLOCKS blocks"),
+            )
+            .arg(
+                Arg::new("zombie-years")
+                    .long("zombie-years")
+                    .value_name("YEARS")
+                    .value_parser(clap::value_parser!(u64).range(1..))
+                    .default_value("5")
+                    .help("Counts unspent outputs older than YEARS years as zombie coins"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = Supply {
+            writer: output.open("supply")?,
+            output,
+            burn_addresses: context.coin.burn_addresses,
+            interval: *matches.get_one::<u64>("interval").unwrap(),
+            zombie_age: *matches.get_one::<u64>("zombie-years").unwrap() * YEAR,
+            use_undo_files: context.use_undo_files,
+            unspents: HashMap::with_capacity(10000000),
+            ages: UtxoAges::default(),
+            start_height: 0,
+            unspent: 0,
+            burned: 0,
+            zombie: 0,
+            n_unresolved: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing supply with dump folder: {} ...", &self.output);
+        if block_height > 0 {
+            warn!(target: "callback", "Supply is only counted from block {}.", block_height);
+        }
+        self.writer
+            .write_all("height;timestamp;supply;burned;circulating;zombie\n".as_bytes())?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let timestamp = block.header.value.timestamp;
+        self.ages.add_block(block_height, timestamp);
+        for tx in &block.txs {
+            if !tx.value.is_coinbase() {
+                self.spend(&tx.value);
+            }
+            self.receive(tx, block_height);
+        }
+        self.zombie = self.ages.zombie(timestamp, self.zombie_age);
+
+        if block_height.is_multiple_of(self.interval) {
+            // (height, timestamp, supply, burned, circulating, zombie)
+            self.writer.write_all(
+                format!(
+                    "{};{};{};{};{};{}\n",
+                    block_height,
+                    timestamp,
+                    self.unspent + self.burned,
+                    self.burned,
+                    self.unspent,
+                    self.zombie
+                )
+                .as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "supply", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utxo_ages() {
+        let mut ages = UtxoAges::default();
+        for height in 0..4 {
+            ages.add_block(height, height as u32 * 100);
+            ages.add(height, 10);
+        }
+        // Outputs of height 0 and 1 are at least 200 seconds old at 300
+        assert_eq!(ages.zombie(300, 200), 20);
+        ages.spend(1, 10);
+        ages.spend(3, 10);
+        assert_eq!(ages.zombie(300, 200), 10);
+        // The spent output doesn't come back once height 3 gets old
+        assert_eq!(ages.zombie(1000, 200), 20);
+        // Unknown heights are ignored
+        ages.spend(10, 10);
+        assert_eq!(ages.zombie(1000, 200), 20);
+    }
+}
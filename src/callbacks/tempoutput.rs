@@ -0,0 +1,157 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A file which is written under a temporary name and moved to its final path by `commit()`.
+/// The data is synced to disk before the rename, so the final path is either missing or complete,
+/// even if the process crashes. If it is dropped without commit, e.g. because the callback failed,
+/// the temporary file is removed.
+pub struct TempOutput {
+    file: File,
+    path: PathBuf,
+    committed: bool,
+    written: u64, // bytes written so far
+    synced: u64,  // bytes written up to the last sync
+}
+
+impl TempOutput {
+    /// Creates the temporary file at `path`,
+    /// a file left over by a crashed run is removed first.
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        remove_orphan(&path)?;
+        let file = File::create(&path)?;
+        Ok(Self {
+            file,
+            path,
+            committed: false,
+            written: 0,
+            synced: 0,
+        })
+    }
+
+    /// Returns the temporary path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Waits until all data written so far is on disk
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.synced = self.written;
+        Ok(())
+    }
+
+    /// Syncs the data and atomically renames the file to `target`
+    pub fn commit(&mut self, target: &Path) -> io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.path, target)?;
+        self.committed = true;
+        sync_parent(target)
+    }
+
+    /// Drops everything written after the last sync and commits the rest to `target`,
+    /// e.g. to keep the complete rows of a run which failed in the middle of a row
+    pub fn commit_synced(&mut self, target: &Path) -> io::Result<()> {
+        self.file.set_len(self.synced)?;
+        self.commit(target)
+    }
+}
+
+impl Write for TempOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Buffered data of a failed writer must not end up behind the committed data
+        if self.committed {
+            return Err(io::Error::other("the file was already committed"));
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for TempOutput {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        if let Err(why) = fs::remove_file(&self.path) {
+            warn!(target: "callback", "Unable to remove {}: {}", self.path.display(), why);
+        }
+    }
+}
+
+/// Removes the temporary file at `path` if it was left over by a crashed run
+pub fn remove_orphan(path: &Path) -> io::Result<()> {
+    if path.is_file() {
+        warn!(target: "callback", "Removing {}, which was left over by a previous run", path.display());
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Persists the rename by syncing the directory entry (only supported on unix)
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        Some(parent) => File::open(parent)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let tmp_path = dir.path().join("test.csv.tmp");
+        let target = dir.path().join("test-0-1.csv");
+
+        let mut output = TempOutput::create(tmp_path.clone()).unwrap();
+        output.write_all(b"a;b\n").unwrap();
+        output.commit(&target).unwrap();
+        drop(output);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "a;b\n");
+        assert!(!tmp_path.exists());
+
+        // Removed on drop without commit
+        let mut output = TempOutput::create(tmp_path.clone()).unwrap();
+        output.write_all(b"a;b\n").unwrap();
+        assert!(tmp_path.exists());
+        drop(output);
+        assert!(!tmp_path.exists());
+
+        // Files left over by a crashed run are replaced
+        fs::write(&tmp_path, "stale").unwrap();
+        let output = TempOutput::create(tmp_path.clone()).unwrap();
+        assert_eq!(fs::read_to_string(output.path()).unwrap(), "");
+    }
+
+    #[test]
+    fn test_commit_synced() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("test-0-1.csv");
+
+        let mut output = TempOutput::create(dir.path().join("test.csv.tmp")).unwrap();
+        output.write_all(b"a;b\n").unwrap();
+        output.sync().unwrap();
+        output.write_all(b"1;").unwrap();
+        output.commit_synced(&target).unwrap();
+        assert!(output.write_all(b"2\n").is_err());
+        drop(output);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "a;b\n");
+    }
+}
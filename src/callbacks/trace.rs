@@ -0,0 +1,437 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxOutpoint};
+use crate::blockchain::proto::{Hashed, TxId};
+use crate::callbacks::getblock::{array, object, quote};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::logger;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Dot,
+    Json,
+}
+
+/// Transaction of the traced subgraph, `hops` is its distance to the traced transaction
+struct Node {
+    txid: TxId,
+    height: Option<u64>, // unknown for ancestors before the parsed range
+    hops: u64,
+}
+
+/// Output of `from` which is spent by `to`
+struct Edge {
+    from: TxOutpoint,
+    to: TxId,
+    value: Option<u64>,
+}
+
+/// Spent outputs and output values of a transaction, kept for ancestor traces
+struct Links {
+    height: u64,
+    inputs: Box<[TxOutpoint]>, // empty for coinbases
+    values: Box<[u64]>,
+}
+
+/// Follows the outputs of a transaction to the transactions spending them (descendants)
+/// or its inputs back to the transactions creating them (ancestors) for a number of hops.
+/// The subgraph is printed as DOT or JSON to stdout.
+pub struct Trace {
+    txid: TxId,
+    vout: Option<u32>, // only this output is followed to the descendants
+    ancestors: bool,
+    hops: u64,
+    height: Option<u64>,
+    format: Format,
+
+    // Descendants: unspent outputs of the subgraph with the hops of their transaction and value
+    tracked: HashMap<TxOutpoint, (u64, u64)>,
+    // Ancestors: spend links of all parsed transactions
+    links: HashMap<TxId, Links>,
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+impl Trace {
+    /// Height of the block containing the traced transaction, if given
+    pub fn height(&self) -> Option<u64> {
+        self.height
+    }
+
+    /// True if the inputs are traced back instead of the outputs forward
+    pub fn ancestors(&self) -> bool {
+        self.ancestors
+    }
+
+    /// Parses `<txid>` or `<txid>:<vout>`
+    fn parse_outpoint(s: &str) -> OpResult<(TxId, Option<u32>)> {
+        let invalid = |msg: String| OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg);
+        let (txid, vout) = match s.split_once(':') {
+            Some((txid, vout)) => {
+                let vout = vout
+                    .parse()
+                    .map_err(|_| invalid(format!("Invalid output index in {}", s)))?;
+                (txid, Some(vout))
+            }
+            None => (s, None),
+        };
+        let txid =
+            TxId::from_str(txid).map_err(|e| invalid(format!("Invalid txid {}: {}", txid, e)))?;
+        Ok((txid, vout))
+    }
+
+    fn add_node(&mut self, txid: TxId, height: Option<u64>, hops: u64) {
+        self.nodes.push(Node { txid, height, hops });
+    }
+
+    /// Follows the tracked outputs spent by `tx` and tracks its outputs if hops are left
+    fn on_descendant(&mut self, tx: &Hashed<EvaluatedTx>, block_height: u64) {
+        let mut hops = None;
+        if tx.hash == self.txid && self.nodes.is_empty() {
+            hops = Some(0);
+        } else if !self.tracked.is_empty() {
+            for input in &tx.value.inputs {
+                if let Some((parent_hops, value)) = self.tracked.remove(&input.outpoint) {
+                    self.edges.push(Edge {
+                        from: input.outpoint,
+                        to: tx.hash,
+                        value: Some(value),
+                    });
+                    hops = Some(hops.map_or(parent_hops + 1, |h| h.min(parent_hops + 1)));
+                }
+            }
+        }
+        let hops = match hops {
+            Some(hops) => hops,
+            None => return,
+        };
+        self.add_node(tx.hash, Some(block_height), hops);
+        if hops == self.hops {
+            return;
+        }
+        for (index, output) in tx.value.outputs.iter().enumerate() {
+            let index = index as u32;
+            if hops == 0 && self.vout.is_some_and(|vout| vout != index) {
+                continue;
+            }
+            self.tracked
+                .insert(TxOutpoint::new(tx.hash, index), (hops, output.out.value));
+        }
+    }
+
+    /// Walks from the traced transaction back through the recorded spend links
+    fn resolve_ancestors(&mut self) {
+        let root_height = self.links.get(&self.txid).map(|links| links.height);
+        self.add_node(self.txid, root_height, 0);
+        let mut visited = HashSet::from([self.txid]);
+        let mut queue = VecDeque::from([(self.txid, 0)]);
+        let mut n_outside = 0;
+        while let Some((txid, hops)) = queue.pop_front() {
+            let inputs = match self.links.get(&txid) {
+                Some(links) => links.inputs.clone(),
+                None => continue,
+            };
+            for outpoint in inputs.iter() {
+                let parent = self.links.get(&outpoint.txid);
+                self.edges.push(Edge {
+                    from: *outpoint,
+                    to: txid,
+                    value: parent.and_then(|p| p.values.get(outpoint.index as usize).copied()),
+                });
+                let height = parent.map(|p| p.height);
+                if !visited.insert(outpoint.txid) {
+                    continue;
+                }
+                if height.is_none() {
+                    n_outside += 1;
+                }
+                self.add_node(outpoint.txid, height, hops + 1);
+                if hops + 1 < self.hops {
+                    queue.push_back((outpoint.txid, hops + 1));
+                }
+            }
+        }
+        if n_outside > 0 {
+            warn!(target: "callback", "{} ancestors were created before the parsed range, lower --start to trace them", n_outside);
+        }
+    }
+
+    fn write_dot(&self, writer: &mut impl Write) -> OpResult<()> {
+        writeln!(writer, "digraph trace {{")?;
+        writeln!(writer, "  rankdir=LR;")?;
+        for node in &self.nodes {
+            let height = node.height.map_or(String::from("?"), |h| h.to_string());
+            writeln!(
+                writer,
+                "  \"{}\" [label=\"{}\\nheight {}, hop {}\"];",
+                node.txid,
+                &node.txid.to_string()[..16],
+                height,
+                node.hops
+            )?;
+        }
+        for edge in &self.edges {
+            let value = edge.value.map_or(String::from("?"), |v| v.to_string());
+            writeln!(
+                writer,
+                "  \"{}\" -> \"{}\" [label=\"{}: {}\"];",
+                edge.from.txid, edge.to, edge.from.index, value
+            )?;
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut impl Write) -> OpResult<()> {
+        let optional = |value: Option<u64>| value.map_or(String::from("null"), |v| v.to_string());
+        let root = match self.vout {
+            Some(vout) => format!("{}:{}", self.txid, vout),
+            None => self.txid.to_string(),
+        };
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let fields = [
+                    ("txid", quote(&node.txid.to_string())),
+                    ("height", optional(node.height)),
+                    ("hops", node.hops.to_string()),
+                ];
+                object(&fields, 2)
+            })
+            .collect();
+        let edges = self
+            .edges
+            .iter()
+            .map(|edge| {
+                let fields = [
+                    ("txid", quote(&edge.from.txid.to_string())),
+                    ("vout", edge.from.index.to_string()),
+                    ("spent_by", quote(&edge.to.to_string())),
+                    ("value", optional(edge.value)),
+                ];
+                object(&fields, 2)
+            })
+            .collect();
+        let direction = match self.ancestors {
+            true => "ancestors",
+            false => "descendants",
+        };
+        let fields = [
+            ("root", quote(&root)),
+            ("direction", quote(direction)),
+            ("nodes", array(nodes, 1)),
+            ("edges", array(edges, 1)),
+        ];
+        writeln!(writer, "{}", object(&fields, 0))?;
+        Ok(())
+    }
+}
+
+impl Callback for Trace {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("trace")
+            .about("Prints the descendants or ancestors of a transaction as DOT or JSON graph")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("outpoint")
+                    .help("Transaction to trace as <txid> or <txid>:<vout> to only follow one output")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("ancestors")
+                    .long("ancestors")
+                    .action(ArgAction::SetTrue)
+                    .help("Traces the inputs back instead of the outputs forward. Keeps the inputs of all parsed transactions in memory, limit the range with --start"),
+            )
+            .arg(
+                Arg::new("hops")
+                    .long("hops")
+                    .value_name("N")
+                    .value_parser(value_parser!(u64).range(1..))
+                    .default_value("3")
+                    .help("Number of transactions to follow from the traced one"),
+            )
+            .arg(
+                Arg::new("height")
+                    .long("height")
+                    .value_name("HEIGHT")
+                    .value_parser(value_parser!(u64))
+                    .help("Height of the block containing the transaction, skips the blocks before (descendants) or after it (ancestors)"),
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_parser(["dot", "json"])
+                    .default_value("dot")
+                    .help("Output format of the graph"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let (txid, vout) = Trace::parse_outpoint(matches.get_one::<String>("outpoint").unwrap())?;
+        let format = match matches.get_one::<String>("format").map(String::as_str) {
+            Some("json") => Format::Json,
+            _ => Format::Dot,
+        };
+        // stdout is reserved for the graph
+        logger::redirect_to_stderr();
+        Ok(Trace {
+            txid,
+            vout,
+            ancestors: matches.get_flag("ancestors"),
+            hops: *matches.get_one::<u64>("hops").unwrap(),
+            height: matches.get_one::<u64>("height").copied(),
+            format,
+            tracked: HashMap::new(),
+            links: HashMap::new(),
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        })
+    }
+
+    fn on_start(&mut self, _: u64) -> OpResult<()> {
+        info!(target: "callback", "Executing Trace ...");
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            if !self.ancestors {
+                self.on_descendant(tx, block_height);
+                continue;
+            }
+            let inputs = match tx.value.is_coinbase() {
+                true => Box::default(),
+                false => tx.value.inputs.iter().map(|input| input.outpoint).collect(),
+            };
+            let values = tx.value.outputs.iter().map(|o| o.out.value).collect();
+            self.links.insert(
+                tx.hash,
+                Links {
+                    height: block_height,
+                    inputs,
+                    values,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, _: u64) -> OpResult<()> {
+        let found = match self.ancestors {
+            true => self.links.contains_key(&self.txid),
+            false => !self.nodes.is_empty(),
+        };
+        if !found {
+            let msg = format!("Transaction {} not found", self.txid);
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+        }
+        if self.ancestors {
+            self.resolve_ancestors();
+        }
+        let mut stdout = io::stdout().lock();
+        match self.format {
+            Format::Dot => self.write_dot(&mut stdout)?,
+            Format::Json => self.write_json(&mut stdout)?,
+        }
+        info!(target: "callback", "Done.\nTraced {} transactions and {} spends.", self.nodes.len(), self.edges.len());
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_outpoint() {
+        let txid = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        assert_eq!(
+            Trace::parse_outpoint(&format!("{}:1", txid)).unwrap(),
+            (TxId::from_str(txid).unwrap(), Some(1))
+        );
+        assert_eq!(Trace::parse_outpoint(txid).unwrap().1, None);
+        assert!(Trace::parse_outpoint(&format!("{}:x", txid)).is_err());
+        assert!(Trace::parse_outpoint("foo").is_err());
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let txid = |n: u8| TxId::from_byte_array([n; 32]);
+        let matches = Trace::build_subcommand().get_matches_from([
+            "trace",
+            &txid(3).to_string(),
+            "--ancestors",
+            "--hops",
+            "1",
+        ]);
+        let mut trace = Trace::new(&matches, &CallbackContext::default()).unwrap();
+        // 1 <- 2 <- 3, where 3 also spends an output of 0 which is outside of the range
+        let links = |height, inputs: &[TxOutpoint]| Links {
+            height,
+            inputs: inputs.into(),
+            values: Box::new([100, 200]),
+        };
+        trace.links.insert(txid(1), links(1, &[]));
+        trace
+            .links
+            .insert(txid(2), links(2, &[TxOutpoint::new(txid(1), 0)]));
+        trace.links.insert(
+            txid(3),
+            links(
+                3,
+                &[TxOutpoint::new(txid(2), 1), TxOutpoint::new(txid(0), 0)],
+            ),
+        );
+        trace.resolve_ancestors();
+
+        let nodes: Vec<(TxId, Option<u64>, u64)> = trace
+            .nodes
+            .iter()
+            .map(|node| (node.txid, node.height, node.hops))
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                (txid(3), Some(3), 0),
+                (txid(2), Some(2), 1),
+                (txid(0), None, 1)
+            ]
+        );
+        let mut dot = Vec::new();
+        trace.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\" [label=\"1: 200\"];",
+            txid(2),
+            txid(3)
+        )));
+        assert!(dot.contains(&format!(
+            "\"{}\" -> \"{}\" [label=\"0: ?\"];",
+            txid(0),
+            txid(3)
+        )));
+        assert_eq!(dot.matches("->").count(), 2);
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxInput};
+use crate::blockchain::proto::varuint::VarUint;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{change, Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Dumps the shape of each transaction (input/output counts, sizes and script types),
+/// the fee rate and the results of change detection heuristics for wallet fingerprinting research.
+pub struct TxShape {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    start_height: u64,
+    n_txs: u64,
+    n_with_fee: u64,
+    // Transactions where the change was detected by (round amount, script type, both agree)
+    n_change: (u64, u64, u64),
+}
+
+impl TxShape {
+    fn write_tx(&mut self, tx: &EvaluatedTx, txid: &str, block_height: u64) -> OpResult<()> {
+        let vsize = tx.vsize();
+        let in_size: usize = tx.inputs.iter().map(input_size).sum();
+        let out_size: usize = tx.outputs.iter().map(|o| o.out.to_bytes().len()).sum();
+        let input_types: BTreeSet<String> =
+            tx.inputs.iter().map(|i| i.pattern.to_string()).collect();
+        let output_types: BTreeSet<String> = tx
+            .outputs
+            .iter()
+            .map(|o| o.script.pattern.to_string())
+            .collect();
+        let (fee, fee_rate) = match tx.fee() {
+            Some(fee) => {
+                self.n_with_fee += 1;
+                (fee.to_string(), format!("{:.2}", fee as f64 / vsize as f64))
+            }
+            None => (String::new(), String::new()),
+        };
+
+        let round = change::by_round_amount(tx);
+        let script_type = change::by_script_type(tx);
+        if round.is_some() {
+            self.n_change.0 += 1;
+        }
+        if script_type.is_some() {
+            self.n_change.1 += 1;
+            if round == script_type {
+                self.n_change.2 += 1;
+            }
+        }
+        let index = |i: Option<usize>| i.map(|i| i.to_string()).unwrap_or_default();
+
+        // (@txid, height, inputs, outputs, size, vsize, inSize, outSize, inputTypes, outputTypes,
+        //  fee, feeRate, changeRound, changeScriptType)
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{};{};{};{};{};{};{};{}\n",
+                txid,
+                block_height,
+                tx.inputs.len(),
+                tx.outputs.len(),
+                tx.to_bytes_with_witness().len(),
+                vsize,
+                in_size,
+                out_size,
+                input_types.into_iter().collect::<Vec<_>>().join(","),
+                output_types.into_iter().collect::<Vec<_>>().join(","),
+                fee,
+                fee_rate,
+                index(round),
+                index(script_type)
+            )
+            .as_bytes(),
+        )?;
+        self.n_txs += 1;
+        Ok(())
+    }
+
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let percent = |n: u64| n as f64 / self.n_txs.max(1) as f64 * 100.0;
+        let (round, script_type, agree) = self.n_change;
+        writeln!(buffer, "Transaction shapes:")?;
+        writeln!(buffer, "   -> transactions:\t\t{}", self.n_txs)?;
+        writeln!(
+            buffer,
+            "   -> with fee:\t\t\t{} ({:.2}%)",
+            self.n_with_fee,
+            percent(self.n_with_fee)
+        )?;
+        writeln!(
+            buffer,
+            "   -> change by round amount:\t{} ({:.2}%)",
+            round,
+            percent(round)
+        )?;
+        writeln!(
+            buffer,
+            "   -> change by script type:\t{} ({:.2}%)",
+            script_type,
+            percent(script_type)
+        )?;
+        writeln!(
+            buffer,
+            "   -> both heuristics agree:\t{} ({:.2}%)",
+            agree,
+            percent(agree)
+        )?;
+        Ok(())
+    }
+}
+
+/// Returns the serialized size of the input including its witness
+fn input_size(input: &TxInput) -> usize {
+    let witness_size: usize = input
+        .witness
+        .iter()
+        .map(|item| VarUint::compact(item.len() as u64).to_bytes().len() + item.len())
+        .sum();
+    input.to_bytes().len() + witness_size
+}
+
+impl Callback for TxShape {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("txshape")
+            .about("Dumps input/output profiles and change detection results per transaction to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = TxShape {
+            writer: output.open("txshape")?,
+            output,
+            start_height: 0,
+            n_txs: 0,
+            n_with_fee: 0,
+            n_change: (0, 0, 0),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing txshape with dump folder: {} ...", &self.output);
+        self.writer.write_all(
+            "txid;height;inputs;outputs;size;vsize;inSize;outSize;inputTypes;outputTypes;fee;feeRate;changeRound;changeScriptType\n"
+                .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        // Coinbase transactions are created by miners, not by wallets
+        for tx in block.txs.iter().skip(1) {
+            self.write_tx(&tx.value, &tx.hash.to_string(), block_height)?;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "txshape", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
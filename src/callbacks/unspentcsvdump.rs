@@ -1,23 +1,93 @@
-use bitcoin::hashes::{sha256d, Hash};
 use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::io::Write;
 
-use byteorder::{LittleEndian, ReadBytesExt};
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 
 use crate::blockchain::proto::block::Block;
-use crate::callbacks::{common, Callback};
-use crate::errors::OpResult;
+use crate::blockchain::proto::tx::TxOutpoint;
+use crate::callbacks::common::ImmatureOutputs;
+use crate::callbacks::labels::Labels;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::sorter::RowSorter;
+use crate::callbacks::utxodb::UtxoDb;
+use crate::callbacks::{common, Callback, CallbackContext};
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
-/// Dumps the UTXOs along with address in a csv file
+const DAY: u32 = 24 * 60 * 60;
+/// Upper bounds of the age buckets, as in the common HODL waves charts
+const AGE_BUCKETS: [(&str, u32); 12] = [
+    ("<1d", DAY),
+    ("1d-1w", 7 * DAY),
+    ("1w-1m", 30 * DAY),
+    ("1m-3m", 91 * DAY),
+    ("3m-6m", 182 * DAY),
+    ("6m-12m", 365 * DAY),
+    ("1y-2y", 2 * 365 * DAY),
+    ("2y-3y", 3 * 365 * DAY),
+    ("3y-5y", 5 * 365 * DAY),
+    ("5y-7y", 7 * 365 * DAY),
+    ("7y-10y", 10 * 365 * DAY),
+    (">10y", u32::MAX),
+];
+
+/// Number and value of the unspent outputs by age at the end of the range (see `--age-histogram`).
+/// The age is the time between the block of the output and the last block.
+struct AgeHistogram {
+    writer: Box<dyn Sink>,
+    block_times: HashMap<u64, u32>,
+    counts: [u64; AGE_BUCKETS.len()],
+    values: [u64; AGE_BUCKETS.len()],
+}
+
+impl AgeHistogram {
+    fn add(&mut self, unspent: &common::UnspentValue, tip_time: u32) {
+        let time = self.block_times[&unspent.block_height];
+        let age = tip_time.saturating_sub(time);
+        let bucket = AGE_BUCKETS
+            .iter()
+            .position(|(_, max_age)| age < *max_age)
+            .unwrap_or(AGE_BUCKETS.len() - 1);
+        self.counts[bucket] += 1;
+        self.values[bucket] += unspent.value;
+    }
+
+    fn finish(mut self, start_height: u64, end_height: u64) -> OpResult<()> {
+        let total_count = self.counts.iter().sum::<u64>().max(1) as f64;
+        let total_value = self.values.iter().sum::<u64>().max(1) as f64;
+        self.writer
+            .write_all(b"age;utxos;value;utxosPercent;valuePercent\n")?;
+        for (i, (age, _)) in AGE_BUCKETS.iter().enumerate() {
+            self.writer.write_all(
+                format!(
+                    "{};{};{};{:.2};{:.2}\n",
+                    age,
+                    self.counts[i],
+                    self.values[i],
+                    self.counts[i] as f64 / total_count * 100.0,
+                    self.values[i] as f64 / total_value * 100.0
+                )
+                .as_bytes(),
+            )?;
+        }
+        self.writer.finish(start_height, end_height)
+    }
+}
+
+/// Dumps the UTXOs along with address in a csv file.
+/// Outputs without address are identified by the sha256 of their script_pubkey instead.
+/// With `--leveldb` they are also written into a database keyed by outpoint (see `UtxoDb`).
 pub struct UnspentCsvDump {
-    dump_folder: PathBuf,
-    writer: BufWriter<File>,
+    output: Output,
+    writer: Box<dyn Sink>,
+    labels: Option<Labels>,
+    sorter: Option<RowSorter>,
+    utxo_db: Option<UtxoDb>,
+    ages: Option<AgeHistogram>,
+    immature: ImmatureOutputs,
+    coinbase_maturity: u64,
 
-    // key: txid + index
-    unspents: HashMap<Vec<u8>, common::UnspentValue>,
+    unspents: HashMap<TxOutpoint, common::UnspentValue>,
+    interner: common::AddressInterner,
 
     start_height: u64,
     tx_count: u64,
@@ -25,12 +95,6 @@ pub struct UnspentCsvDump {
     out_count: u64,
 }
 
-impl UnspentCsvDump {
-    fn create_writer(cap: usize, path: PathBuf) -> OpResult<BufWriter<File>> {
-        Ok(BufWriter::with_capacity(cap, File::create(path)?))
-    }
-}
-
 impl Callback for UnspentCsvDump {
     fn build_subcommand() -> Command
     where
@@ -46,17 +110,51 @@ impl Callback for UnspentCsvDump {
                     .index(1)
                     .required(true),
             )
+            .arg(Labels::build_arg())
+            .arg(RowSorter::build_arg())
+            .arg(ImmatureOutputs::build_arg())
+            .arg(UtxoDb::build_arg())
+            .arg(
+                Arg::new("age-histogram")
+                    .long("age-histogram")
+                    .action(ArgAction::SetTrue)
+                    .help("Additionally dumps the number and value of the unspent outputs by age (HODL waves)"),
+            )
     }
 
-    fn new(matches: &ArgMatches) -> OpResult<Self>
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
     where
         Self: Sized,
     {
-        let dump_folder = &PathBuf::from(matches.get_one::<String>("dump-folder").unwrap());
+        let output = Output::from_matches(matches)?;
+        let utxo_db = match (matches.get_flag("leveldb"), output.folder()) {
+            (false, _) => None,
+            (true, Some(folder)) => Some(UtxoDb::create(folder)?),
+            (true, None) => {
+                return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg("--leveldb needs a dump folder"))
+            }
+        };
+        let ages = match matches.get_flag("age-histogram") {
+            true => Some(AgeHistogram {
+                writer: output.open("unspent-ages")?,
+                block_times: HashMap::new(),
+                counts: [0; AGE_BUCKETS.len()],
+                values: [0; AGE_BUCKETS.len()],
+            }),
+            false => None,
+        };
         let cb = UnspentCsvDump {
-            dump_folder: PathBuf::from(dump_folder),
-            writer: UnspentCsvDump::create_writer(4000000, dump_folder.join("unspent.csv.tmp"))?,
+            writer: output.open("unspent")?,
+            output,
+            labels: Labels::from_matches(matches)?,
+            sorter: RowSorter::from_matches(matches),
+            utxo_db,
+            ages,
+            immature: ImmatureOutputs::from_matches(matches),
+            coinbase_maturity: context.coin.coinbase_maturity,
             unspents: HashMap::with_capacity(10000000),
+            interner: common::AddressInterner::new(),
             start_height: 0,
             tx_count: 0,
             in_count: 0,
@@ -67,7 +165,7 @@ impl Callback for UnspentCsvDump {
 
     fn on_start(&mut self, block_height: u64) -> OpResult<()> {
         self.start_height = block_height;
-        info!(target: "callback", "Executing unspentcsvdump with dump folder: {} ...", &self.dump_folder.display());
+        info!(target: "callback", "Executing unspentcsvdump with dump folder: {} ...", &self.output);
         Ok(())
     }
 
@@ -77,47 +175,78 @@ impl Callback for UnspentCsvDump {
     /// For each address, retain:
     ///   * block height as "last modified"
     ///   * output_val
-    ///   * address
+    ///   * address, or the script hash if there is no address
     fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
         for tx in &block.txs {
             self.in_count += common::remove_unspents(tx, &mut self.unspents);
-            self.out_count += common::insert_unspents(tx, block_height, &mut self.unspents);
+            self.out_count +=
+                common::insert_unspents(tx, block_height, &mut self.unspents, &mut self.interner);
         }
         self.tx_count += block.tx_count.value;
+        if let Some(ages) = &mut self.ages {
+            ages.block_times
+                .insert(block_height, block.header.value.timestamp);
+        }
         Ok(())
     }
 
     fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
-        self.writer.write_all(
-            format!(
-                "{};{};{};{};{}\n",
-                "txid", "indexOut", "height", "value", "address"
-            )
-            .as_bytes(),
-        )?;
-        for (key, value) in self.unspents.iter() {
-            let txid = sha256d::Hash::from_slice(&key[0..32]).unwrap();
-            let mut index = &key[32..];
-            self.writer.write_all(
-                format!(
-                    "{};{};{};{};{}\n",
-                    txid,
-                    index.read_u32::<LittleEndian>()?,
-                    value.block_height,
-                    value.value,
-                    value.address
-                )
-                .as_bytes(),
-            )?;
+        let mut header = String::from("txid;indexOut;height;value;address;scriptHash");
+        if self.immature == ImmatureOutputs::Flag {
+            header.push_str(";immature");
+        }
+        if self.labels.is_some() {
+            header.push_str(";label");
+        }
+        self.writer.write_all(format!("{}\n", header).as_bytes())?;
+        let tip_time = self
+            .ages
+            .as_ref()
+            .and_then(|ages| ages.block_times.get(&block_height).copied())
+            .unwrap_or_default();
+        for (outpoint, value) in self.unspents.iter() {
+            let is_mature = value.is_mature(block_height, self.coinbase_maturity);
+            if !is_mature && self.immature == ImmatureOutputs::Exclude {
+                continue;
+            }
+            if let Some(ages) = &mut self.ages {
+                ages.add(value, tip_time);
+            }
+            let (txid, index) = (outpoint.txid, outpoint.index);
+            let key = self.interner.address(value.address_id);
+            if let Some(utxo_db) = &mut self.utxo_db {
+                utxo_db.put(outpoint, value, key)?;
+            }
+            let (address, script_hash) = common::key_columns(key);
+            let mut row = format!(
+                "{};{};{};{};{};{}",
+                txid, index, value.block_height, value.value, address, script_hash
+            );
+            if self.immature == ImmatureOutputs::Flag {
+                row.push_str(&format!(";{}", !is_mature as u8));
+            }
+            if let Some(labels) = &self.labels {
+                row.push_str(&format!(";{}", labels.get(address).unwrap_or_default()));
+            }
+            row.push('\n');
+            match &mut self.sorter {
+                // Ordered by outpoint
+                Some(sorter) => sorter.push(format!("{}{:010}", txid, index), row)?,
+                None => self.writer.write_all(row.as_bytes())?,
+            }
+        }
+        if let Some(sorter) = &mut self.sorter {
+            sorter.write_to(&mut self.writer)?;
         }
 
-        fs::rename(
-            self.dump_folder.as_path().join("unspent.csv.tmp"),
-            self.dump_folder.as_path().join(format!(
-                "unspent-{}-{}.csv",
-                self.start_height, block_height
-            )),
-        )?;
+        self.writer.finish(self.start_height, block_height)?;
+        if let Some(ages) = self.ages.take() {
+            ages.finish(self.start_height, block_height)?;
+        }
+        if let Some(utxo_db) = self.utxo_db.take() {
+            let path = utxo_db.finish(self.start_height, block_height)?;
+            info!(target: "callback", "Wrote the unspent outputs to {}", path.display());
+        }
 
         info!(target: "callback", "Done.\nDumped blocks from height {} to {}:\n\
                                    \t-> transactions: {:9}\n\
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bitcoin::hashes::Hash;
+use clap::{Arg, ArgAction};
+use rusty_leveldb::{LdbIterator, Options, WriteBatch, DB};
+
+use crate::blockchain::proto::tx::TxOutpoint;
+use crate::blockchain::proto::TxId;
+use crate::callbacks::common::{AddressInterner, UnspentValue};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Prefix of the keys of unspent outputs
+const UTXO_PREFIX: u8 = b'u';
+/// Key of the range the set was dumped for
+const RANGE_KEY: &[u8] = b"range";
+/// Number of puts collected in a batch before it is written
+const BATCH_SIZE: u32 = 100000;
+
+/// Writes the unspent outputs into a LevelDB database, so they can be looked up by outpoint
+/// without reading the whole csv file. The database is built in `unspent.ldb.tmp` and renamed
+/// to `unspent-<start>-<end>.ldb` when done, like the csv files.
+///
+/// Layout (all integers little endian):
+///   * `'u' + txid (32 bytes, internal byte order) + index (u32)`:
+///     `height (u64) + value (u64) + coinbase (u8) + address`,
+///     outputs without address store `script:<sha256 of script_pubkey>` instead
+///   * `"range"`: `start height (u64) + end height (u64)`
+pub struct UtxoDb {
+    db: DB,
+    batch: WriteBatch,
+    folder: PathBuf,
+}
+
+impl UtxoDb {
+    pub fn build_arg() -> Arg {
+        Arg::new("leveldb")
+            .long("leveldb")
+            .action(ArgAction::SetTrue)
+            .help(
+                "Additionally writes the unspent outputs into a LevelDB database keyed by outpoint",
+            )
+    }
+
+    /// Creates an empty database in the given folder,
+    /// a database left over by a crashed run is removed first.
+    pub fn create(folder: &Path) -> OpResult<Self> {
+        let path = Self::tmp_path(folder);
+        if path.is_dir() {
+            warn!(target: "callback", "Removing {}, which was left over by a previous run", path.display());
+            fs::remove_dir_all(&path)?;
+        }
+        Ok(Self {
+            db: DB::open(&path, Options::default())?,
+            batch: WriteBatch::new(),
+            folder: PathBuf::from(folder),
+        })
+    }
+
+    fn tmp_path(folder: &Path) -> PathBuf {
+        folder.join("unspent.ldb.tmp")
+    }
+
+    pub fn put(
+        &mut self,
+        outpoint: &TxOutpoint,
+        value: &UnspentValue,
+        address: &str,
+    ) -> OpResult<()> {
+        self.batch
+            .put(&encode_key(outpoint), &encode_value(value, address));
+        if self.batch.count() >= BATCH_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> OpResult<()> {
+        let batch = std::mem::replace(&mut self.batch, WriteBatch::new());
+        self.db.write(batch, false)?;
+        Ok(())
+    }
+
+    /// Stores the range, syncs the database and moves it to its final name
+    pub fn finish(mut self, start_height: u64, end_height: u64) -> OpResult<PathBuf> {
+        let mut range = start_height.to_le_bytes().to_vec();
+        range.extend(end_height.to_le_bytes());
+        self.batch.put(RANGE_KEY, &range);
+        let batch = std::mem::replace(&mut self.batch, WriteBatch::new());
+        self.db.write(batch, true)?;
+        self.db.close()?;
+
+        let target = self
+            .folder
+            .join(format!("unspent-{}-{}.ldb", start_height, end_height));
+        if target.exists() {
+            let msg = format!("{} already exists", target.display());
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+        }
+        fs::rename(Self::tmp_path(&self.folder), &target)?;
+        Ok(target)
+    }
+}
+
+/// Reads all unspent outputs of a database written by `UtxoDb` into `unspents`,
+/// returns the start and end height of the range it was dumped for
+pub fn load(
+    path: &Path,
+    unspents: &mut HashMap<TxOutpoint, UnspentValue>,
+    interner: &mut AddressInterner,
+) -> OpResult<(u64, u64)> {
+    let invalid = |msg: &str| {
+        let msg = format!("{}: {}", path.display(), msg);
+        OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg)
+    };
+    let options = Options {
+        create_if_missing: false,
+        ..Options::default()
+    };
+    let mut db = DB::open(path, options)?;
+    let range = match db.get(RANGE_KEY) {
+        Some(range) if range.len() == 16 => (
+            u64::from_le_bytes(range[..8].try_into().unwrap()),
+            u64::from_le_bytes(range[8..].try_into().unwrap()),
+        ),
+        _ => return Err(invalid("range is missing, the database is incomplete")),
+    };
+    let mut iter = db.new_iter()?;
+    let (mut key, mut value) = (Vec::new(), Vec::new());
+    while iter.advance() {
+        iter.current(&mut key, &mut value);
+        if key.first() != Some(&UTXO_PREFIX) {
+            continue;
+        }
+        let (outpoint, unspent) = decode(&key, &value, interner)
+            .ok_or_else(|| invalid("unexpected entry, is this an unspent output database?"))?;
+        unspents.insert(outpoint, unspent);
+    }
+    Ok(range)
+}
+
+fn decode(
+    key: &[u8],
+    value: &[u8],
+    interner: &mut AddressInterner,
+) -> Option<(TxOutpoint, UnspentValue)> {
+    if key.len() != 37 || value.len() < 17 {
+        return None;
+    }
+    let txid = TxId::from_byte_array(key[1..33].try_into().ok()?);
+    let index = u32::from_le_bytes(key[33..].try_into().ok()?);
+    let address = std::str::from_utf8(&value[17..]).ok()?;
+    let unspent = UnspentValue {
+        block_height: u64::from_le_bytes(value[..8].try_into().ok()?),
+        value: u64::from_le_bytes(value[8..16].try_into().ok()?),
+        address_id: interner.intern(address),
+        is_coinbase: value[16] == 1,
+    };
+    Some((TxOutpoint::new(txid, index), unspent))
+}
+
+fn encode_key(outpoint: &TxOutpoint) -> Vec<u8> {
+    let mut key = Vec::with_capacity(37);
+    key.push(UTXO_PREFIX);
+    key.extend(outpoint.txid.as_byte_array());
+    key.extend(outpoint.index.to_le_bytes());
+    key
+}
+
+fn encode_value(value: &UnspentValue, address: &str) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(17 + address.len());
+    buf.extend(value.block_height.to_le_bytes());
+    buf.extend(value.value.to_le_bytes());
+    buf.push(value.is_coinbase as u8);
+    buf.extend(address.as_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utxo_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let outpoint = TxOutpoint {
+            txid: TxId::hash(b"tx"),
+            index: 1,
+        };
+        let value = UnspentValue {
+            block_height: 7,
+            value: 5000000000,
+            address_id: 0,
+            is_coinbase: true,
+        };
+        // A database left over by a crashed run is replaced
+        DB::open(UtxoDb::tmp_path(dir.path()), Options::default()).unwrap();
+        let mut db = UtxoDb::create(dir.path()).unwrap();
+        db.put(&outpoint, &value, "1address").unwrap();
+        let path = db.finish(0, 9).unwrap();
+        assert_eq!(path, dir.path().join("unspent-0-9.ldb"));
+        assert!(!UtxoDb::tmp_path(dir.path()).exists());
+
+        let mut db = DB::open(&path, Options::default()).unwrap();
+        let stored = db.get(&encode_key(&outpoint)).unwrap();
+        assert_eq!(stored[..8], 7u64.to_le_bytes());
+        assert_eq!(stored[8..16], 5000000000u64.to_le_bytes());
+        assert_eq!(stored[16..], *b"\x011address");
+        assert_eq!(db.get(RANGE_KEY).unwrap()[8..], 9u64.to_le_bytes());
+        drop(db);
+
+        let (mut unspents, mut interner) = (HashMap::new(), AddressInterner::new());
+        assert_eq!(load(&path, &mut unspents, &mut interner).unwrap(), (0, 9));
+        let loaded = &unspents[&outpoint];
+        assert_eq!(
+            (loaded.block_height, loaded.value, loaded.is_coinbase),
+            (7, 5000000000, true)
+        );
+        assert_eq!(interner.address(loaded.address_id), "1address");
+        assert!(load(&dir.path().join("missing"), &mut unspents, &mut interner).is_err());
+    }
+}
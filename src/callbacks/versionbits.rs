@@ -0,0 +1,298 @@
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Top 3 bits of the block version which indicate BIP9 signalling
+const VERSIONBITS_TOP_MASK: u32 = 0xE0000000;
+const VERSIONBITS_TOP_BITS: u32 = 0x20000000;
+/// Number of bits available for deployments
+const VERSIONBITS_NUM_BITS: usize = 29;
+
+/// Signalling of a single retarget window
+struct Window {
+    start_height: u64,
+    n_blocks: u64,
+    n_bip9: u64,
+    counts: [u64; VERSIONBITS_NUM_BITS],
+}
+
+impl Window {
+    fn new(start_height: u64) -> Self {
+        Self {
+            start_height,
+            n_blocks: 0,
+            n_bip9: 0,
+            counts: [0; VERSIONBITS_NUM_BITS],
+        }
+    }
+
+    fn add(&mut self, version: u32) {
+        self.n_blocks += 1;
+        if version & VERSIONBITS_TOP_MASK != VERSIONBITS_TOP_BITS {
+            return;
+        }
+        self.n_bip9 += 1;
+        for (bit, count) in self.counts.iter_mut().enumerate() {
+            if version & (1 << bit) != 0 {
+                *count += 1;
+            }
+        }
+    }
+}
+
+/// Deployment inferred from a window in which the threshold was reached
+struct Deployment {
+    bit: usize,
+    lock_in_height: u64,
+    activation_height: u64,
+}
+
+/// Tracks BIP9 version bits signalling per retarget window.
+/// Only the block headers are needed, so transactions are not parsed at all.
+pub struct VersionBits {
+    output: Output,
+    writer: Box<dyn Sink>,
+    retarget_interval: u64,
+    threshold: u64,
+
+    window: Option<Window>,
+    // Whether the threshold was reached in the last complete window, by bit
+    reached: [bool; VERSIONBITS_NUM_BITS],
+    // Highest share of signalling blocks by bit, as (percent, window)
+    max_percent: [(f64, u64); VERSIONBITS_NUM_BITS],
+    deployments: Vec<Deployment>,
+    start_height: u64,
+    n_windows: u64,
+    n_blocks: u64,
+    n_bip9: u64,
+}
+
+impl VersionBits {
+    /// Number of signalling blocks required within a window to lock in
+    fn threshold_blocks(&self) -> u64 {
+        (self.retarget_interval * self.threshold).div_ceil(100)
+    }
+
+    /// Writes a row for each signalled bit and checks for lock-ins
+    fn finish_window(&mut self, window: &Window) -> OpResult<()> {
+        let index = window.start_height / self.retarget_interval;
+        let complete = window.n_blocks == self.retarget_interval;
+        let threshold = self.threshold_blocks();
+
+        for (bit, &count) in window.counts.iter().enumerate() {
+            let reached = complete && count >= threshold;
+            // BIP9: LOCKED_IN in the window after the threshold was reached, ACTIVE one window later
+            if reached && !self.reached[bit] {
+                self.deployments.push(Deployment {
+                    bit,
+                    lock_in_height: (index + 1) * self.retarget_interval,
+                    activation_height: (index + 2) * self.retarget_interval,
+                });
+            }
+            self.reached[bit] = reached;
+            if count == 0 {
+                continue;
+            }
+
+            let percent = count as f64 / window.n_blocks as f64 * 100.0;
+            if percent > self.max_percent[bit].0 {
+                self.max_percent[bit] = (percent, index);
+            }
+            // (window, startHeight, endHeight, blocks, bip9Blocks, bit, count, percent)
+            self.writer.write_all(
+                format!(
+                    "{};{};{};{};{};{};{};{:.2}\n",
+                    index,
+                    window.start_height,
+                    window.start_height + window.n_blocks - 1,
+                    window.n_blocks,
+                    window.n_bip9,
+                    bit,
+                    count,
+                    percent
+                )
+                .as_bytes(),
+            )?;
+        }
+        self.n_windows += 1;
+        self.n_blocks += window.n_blocks;
+        self.n_bip9 += window.n_bip9;
+        Ok(())
+    }
+
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        writeln!(buffer, "Version bits:")?;
+        writeln!(buffer, "   -> windows:\t\t\t{}", self.n_windows)?;
+        writeln!(
+            buffer,
+            "   -> BIP9 blocks:\t\t{} ({:.2}%)",
+            self.n_bip9,
+            self.n_bip9 as f64 / self.n_blocks.max(1) as f64 * 100.0
+        )?;
+        writeln!(
+            buffer,
+            "   -> lock-in threshold:\t{} of {} blocks",
+            self.threshold_blocks(),
+            self.retarget_interval
+        )?;
+        for (bit, (percent, window)) in self.max_percent.iter().enumerate() {
+            if *percent > 0.0 {
+                writeln!(
+                    buffer,
+                    "   -> bit {:2}:\t\t\tmax {:.2}% in window {}",
+                    bit, percent, window
+                )?;
+            }
+        }
+        writeln!(buffer, "\nInferred deployments:")?;
+        for deployment in &self.deployments {
+            writeln!(
+                buffer,
+                "   -> bit {:2}:\t\t\tlocked in at {}, active at {}",
+                deployment.bit, deployment.lock_in_height, deployment.activation_height
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for VersionBits {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("versionbits")
+            .about("Dumps BIP9 version bits signalling per retarget window to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("threshold")
+                    .long("threshold")
+                    .value_name("PERCENT")
+                    .value_parser(clap::value_parser!(u64).range(1..=100))
+                    .default_value("95")
+                    .help("Share of signalling blocks within a window required to lock in"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, context: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = VersionBits {
+            writer: output.open("versionbits")?,
+            output,
+            retarget_interval: context.coin.retarget_interval,
+            threshold: *matches.get_one::<u64>("threshold").unwrap(),
+            window: None,
+            reached: [false; VERSIONBITS_NUM_BITS],
+            max_percent: [(0.0, 0); VERSIONBITS_NUM_BITS],
+            deployments: Vec::new(),
+            start_height: 0,
+            n_windows: 0,
+            n_blocks: 0,
+            n_bip9: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing versionbits with dump folder: {} (window: {} blocks) ...",
+              &self.output, self.retarget_interval);
+        self.writer.write_all(
+            "window;startHeight;endHeight;blocks;bip9Blocks;bit;count;percent\n".as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let interval = self.retarget_interval;
+        if let Some(window) = self.window.take() {
+            match window.start_height / interval == block_height / interval {
+                true => self.window = Some(window),
+                false => self.finish_window(&window)?,
+            }
+        }
+        self.window
+            .get_or_insert_with(|| Window::new(block_height))
+            .add(block.header.value.version);
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        if let Some(window) = self.window.take() {
+            self.finish_window(&window)?;
+        }
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "versionbits", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn headers_only(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_add() {
+        let mut window = Window::new(0);
+        window.add(1);
+        window.add(0x20000002);
+        window.add(0x20000003);
+        // Top bits don't match BIP9
+        window.add(0x40000002);
+        assert_eq!(window.n_blocks, 4);
+        assert_eq!(window.n_bip9, 2);
+        assert_eq!(window.counts[0], 1);
+        assert_eq!(window.counts[1], 2);
+        assert_eq!(window.counts[2], 0);
+    }
+
+    #[test]
+    fn test_lock_in() {
+        let dump_dir = tempfile::tempdir().unwrap();
+        let matches = VersionBits::build_subcommand()
+            .get_matches_from(["versionbits", dump_dir.path().to_str().unwrap()]);
+        let mut cb = VersionBits::new(&matches, &CallbackContext::default()).unwrap();
+        cb.retarget_interval = 20;
+        assert_eq!(cb.threshold_blocks(), 19);
+
+        for (start_height, n_signalling) in [(0, 10), (20, 19), (40, 20), (60, 19)] {
+            let mut window = Window::new(start_height);
+            (0..20).for_each(|i| {
+                window.add(if i < n_signalling {
+                    0x20000002
+                } else {
+                    0x20000000
+                })
+            });
+            cb.finish_window(&window).unwrap();
+        }
+        // Consecutive windows above the threshold belong to the same deployment
+        assert_eq!(cb.deployments.len(), 1);
+        assert_eq!(cb.deployments[0].bit, 1);
+        assert_eq!(cb.deployments[0].lock_in_height, 40);
+        assert_eq!(cb.deployments[0].activation_height, 60);
+        assert_eq!(cb.max_percent[1], (100.0, 2));
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::TxOutpoint;
+use crate::blockchain::proto::{BlockHash, TxId};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::logger;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Dot,
+    Mermaid,
+}
+
+struct TxNode {
+    id: String,
+    txid: TxId,
+    coinbase: bool,
+}
+
+/// Block with its transactions, drawn as a cluster (DOT) or subgraph (mermaid)
+struct BlockNode {
+    height: u64,
+    hash: BlockHash,
+    txs: Vec<TxNode>,
+}
+
+/// Value moved from a transaction or address to another one
+struct Edge {
+    from: String,
+    to: String,
+    value: u64,
+}
+
+/// Draws the blocks of a small range with their transactions and the spends between them
+/// as Graphviz DOT or mermaid graph, e.g. to illustrate how coins flow for teaching.
+/// With `--addresses`, outputs flow through the addresses they pay to.
+/// The range and the number of nodes are limited, as larger graphs can't be rendered anyway.
+pub struct Visualize {
+    format: Format,
+    addresses: bool,
+    max_blocks: u64,
+    max_nodes: usize,
+    writer: Box<dyn Write>,
+
+    blocks: Vec<BlockNode>,
+    n_txs: usize,
+    // Address nodes by address
+    address_ids: HashMap<String, String>,
+    address_order: Vec<String>,
+    // Unspent outputs of the range with the node they belong to and their value
+    outputs: HashMap<TxOutpoint, (String, u64)>,
+    edges: Vec<Edge>,
+    // Inputs spending outputs created before the range
+    n_outside: u64,
+}
+
+impl Visualize {
+    /// Maximum number of blocks to draw, the parsed range is capped accordingly
+    pub fn max_blocks(&self) -> u64 {
+        self.max_blocks
+    }
+
+    fn n_nodes(&self) -> usize {
+        self.n_txs + self.address_order.len()
+    }
+
+    /// Returns the node of the address, creating it on first use
+    fn address_node(&mut self, address: &str) -> String {
+        if let Some(id) = self.address_ids.get(address) {
+            return id.clone();
+        }
+        let id = format!("a{}", self.address_order.len());
+        self.address_ids.insert(String::from(address), id.clone());
+        self.address_order.push(String::from(address));
+        id
+    }
+
+    fn write_dot(&self, writer: &mut dyn Write) -> OpResult<()> {
+        writeln!(writer, "digraph blocks {{")?;
+        writeln!(writer, "  rankdir=LR;")?;
+        writeln!(writer, "  node [shape=box];")?;
+        for block in &self.blocks {
+            writeln!(writer, "  subgraph cluster_{} {{", block.height)?;
+            writeln!(
+                writer,
+                "    label=\"block {}\\n{}\";",
+                block.height,
+                short(&block.hash.to_string())
+            )?;
+            for tx in &block.txs {
+                writeln!(writer, "    {} [label=\"{}\"];", tx.id, tx_label(tx, "\\n"))?;
+            }
+            writeln!(writer, "  }}")?;
+        }
+        for (address, id) in self.address_order.iter().zip(0..) {
+            writeln!(writer, "  a{} [label=\"{}\", shape=ellipse];", id, address)?;
+        }
+        for edge in &self.edges {
+            writeln!(
+                writer,
+                "  {} -> {} [label=\"{}\"];",
+                edge.from, edge.to, edge.value
+            )?;
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    fn write_mermaid(&self, writer: &mut dyn Write) -> OpResult<()> {
+        writeln!(writer, "flowchart LR")?;
+        for block in &self.blocks {
+            writeln!(
+                writer,
+                "  subgraph b{}[\"block {} {}\"]",
+                block.height,
+                block.height,
+                short(&block.hash.to_string())
+            )?;
+            for tx in &block.txs {
+                writeln!(writer, "    {}[\"{}\"]", tx.id, tx_label(tx, "<br/>"))?;
+            }
+            writeln!(writer, "  end")?;
+        }
+        for (address, id) in self.address_order.iter().zip(0..) {
+            writeln!(writer, "  a{}([\"{}\"])", id, address)?;
+        }
+        for edge in &self.edges {
+            writeln!(writer, "  {} -->|{}| {}", edge.from, edge.value, edge.to)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hashes are shortened to keep the nodes readable
+fn short(hash: &str) -> &str {
+    &hash[..16]
+}
+
+fn tx_label(tx: &TxNode, line_break: &str) -> String {
+    let kind = match tx.coinbase {
+        true => "coinbase",
+        false => "tx",
+    };
+    format!("{}{}{}", kind, line_break, short(&tx.txid.to_string()))
+}
+
+impl Callback for Visualize {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("visualize")
+            .about("Draws the blocks of a small range with their transactions and spends as DOT or mermaid graph")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("file")
+                    .help("File to write the graph to, prints to stdout if omitted")
+                    .index(1),
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_parser(["dot", "mermaid"])
+                    .default_value("dot")
+                    .help("Output format of the graph"),
+            )
+            .arg(
+                Arg::new("addresses")
+                    .long("addresses")
+                    .action(ArgAction::SetTrue)
+                    .help("Routes the outputs through the addresses they pay to"),
+            )
+            .arg(
+                Arg::new("max-blocks")
+                    .long("max-blocks")
+                    .value_name("N")
+                    .value_parser(value_parser!(u64).range(1..))
+                    .default_value("1000")
+                    .help("Maximum number of blocks to draw, the range is capped to it"),
+            )
+            .arg(
+                Arg::new("max-nodes")
+                    .long("max-nodes")
+                    .value_name("N")
+                    .value_parser(value_parser!(usize))
+                    .default_value("5000")
+                    .help("Aborts if the graph would contain more transactions and addresses"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let format = match matches.get_one::<String>("format").map(String::as_str) {
+            Some("mermaid") => Format::Mermaid,
+            _ => Format::Dot,
+        };
+        let writer: Box<dyn Write> = match matches.get_one::<String>("file") {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => {
+                // stdout is reserved for the graph
+                logger::redirect_to_stderr();
+                Box::new(io::stdout())
+            }
+        };
+        Ok(Visualize {
+            format,
+            addresses: matches.get_flag("addresses"),
+            max_blocks: *matches.get_one::<u64>("max-blocks").unwrap(),
+            max_nodes: *matches.get_one::<usize>("max-nodes").unwrap(),
+            writer,
+            blocks: Vec::new(),
+            n_txs: 0,
+            address_ids: HashMap::new(),
+            address_order: Vec::new(),
+            outputs: HashMap::new(),
+            edges: Vec::new(),
+            n_outside: 0,
+        })
+    }
+
+    fn on_start(&mut self, _: u64) -> OpResult<()> {
+        info!(target: "callback", "Executing Visualize ...");
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let mut txs = Vec::with_capacity(block.txs.len());
+        for tx in &block.txs {
+            let id = format!("t{}", self.n_txs);
+            self.n_txs += 1;
+            let coinbase = tx.value.is_coinbase();
+            if !coinbase {
+                for input in &tx.value.inputs {
+                    match self.outputs.remove(&input.outpoint) {
+                        Some((from, value)) => self.edges.push(Edge {
+                            from,
+                            to: id.clone(),
+                            value,
+                        }),
+                        None => self.n_outside += 1,
+                    }
+                }
+            }
+            for (index, output) in tx.value.outputs.iter().enumerate() {
+                let value = output.out.value;
+                let node = match (self.addresses, &output.script.address) {
+                    (true, Some(address)) => {
+                        let address = self.address_node(address);
+                        self.edges.push(Edge {
+                            from: id.clone(),
+                            to: address.clone(),
+                            value,
+                        });
+                        address
+                    }
+                    _ => id.clone(),
+                };
+                self.outputs
+                    .insert(TxOutpoint::new(tx.hash, index as u32), (node, value));
+            }
+            txs.push(TxNode {
+                id,
+                txid: tx.hash,
+                coinbase,
+            });
+            if self.n_nodes() > self.max_nodes {
+                let msg = format!(
+                    "The graph exceeds {} nodes at height {}, narrow the range with --start and --end or raise --max-nodes",
+                    self.max_nodes, block_height
+                );
+                return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+            }
+        }
+        self.blocks.push(BlockNode {
+            height: block_height,
+            hash: block.header.hash,
+            txs,
+        });
+        Ok(())
+    }
+
+    fn on_complete(&mut self, _: u64) -> OpResult<()> {
+        let mut writer = std::mem::replace(&mut self.writer, Box::new(io::sink()));
+        match self.format {
+            Format::Dot => self.write_dot(&mut writer)?,
+            Format::Mermaid => self.write_mermaid(&mut writer)?,
+        }
+        writer.flush()?;
+        if self.n_outside > 0 {
+            info!(target: "callback", "{} inputs spend outputs created before the range and are not drawn", self.n_outside);
+        }
+        info!(target: "callback", "Done.\nDrew {} blocks, {} transactions, {} addresses and {} edges.",
+              self.blocks.len(), self.n_txs, self.address_order.len(), self.edges.len());
+        Ok(())
+    }
+
+    fn show_progress(&self) -> bool {
+        false
+    }
+
+    fn wants_addresses(&self) -> bool {
+        self.addresses
+    }
+}
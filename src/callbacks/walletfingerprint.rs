@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::hashes::Hash;
+use bitcoin::Script;
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::script::InputPattern;
+use crate::blockchain::proto::tx::{EvaluatedTx, TxInput};
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::utils;
+use crate::errors::OpResult;
+
+/// Locktimes below this are block heights, above are unix timestamps
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// Wallets with anti fee sniping set the locktime to the current height, sometimes up to 100 blocks back
+const FEE_SNIPING_WINDOW: u64 = 100;
+/// Inputs with a lower sequence number signal opt-in replace-by-fee (BIP125)
+const RBF_SEQUENCE: u32 = 0xfffffffe;
+
+/// Wallet families that can be told apart by the heuristics below.
+/// This is a guess, many wallets share the same behaviour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Family {
+    /// Uncompressed public keys, used by early clients and brain wallets
+    Legacy,
+    /// Anti fee sniping and BIP69 ordering
+    Electrum,
+    /// Anti fee sniping with random ordering
+    BitcoinCore,
+    /// BIP69 ordering without anti fee sniping, e.g. older mobile wallets
+    Bip69,
+    Unknown,
+}
+
+impl Family {
+    const ALL: [Family; 5] = [
+        Family::Legacy,
+        Family::Electrum,
+        Family::BitcoinCore,
+        Family::Bip69,
+        Family::Unknown,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Family::Legacy => "legacy",
+            Family::Electrum => "electrum",
+            Family::BitcoinCore => "bitcoin-core",
+            Family::Bip69 => "bip69",
+            Family::Unknown => "unknown",
+        }
+    }
+}
+
+/// Wallet specific features of a transaction
+#[derive(Debug, PartialEq)]
+struct Fingerprint {
+    version: u32,
+    locktime_height: bool,
+    anti_fee_sniping: bool,
+    rbf: bool,
+    // None if the transaction has a single input and output, which is trivially sorted
+    bip69: Option<bool>,
+    uncompressed: bool,
+}
+
+impl Fingerprint {
+    fn new(tx: &EvaluatedTx, block_height: u64) -> Self {
+        let locktime_height = tx.locktime > 0 && tx.locktime < LOCKTIME_THRESHOLD;
+        let locktime = tx.locktime as u64;
+        Fingerprint {
+            version: tx.version,
+            locktime_height,
+            anti_fee_sniping: locktime_height
+                && locktime <= block_height
+                && locktime + FEE_SNIPING_WINDOW >= block_height,
+            rbf: tx.inputs.iter().any(|i| i.seq_no < RBF_SEQUENCE),
+            bip69: match tx.inputs.len() > 1 || tx.outputs.len() > 1 {
+                true => Some(is_bip69_sorted(tx)),
+                false => None,
+            },
+            uncompressed: tx.inputs.iter().any(has_uncompressed_key),
+        }
+    }
+
+    fn family(&self) -> Family {
+        if self.uncompressed {
+            Family::Legacy
+        } else if self.anti_fee_sniping && self.bip69 == Some(true) {
+            Family::Electrum
+        } else if self.anti_fee_sniping {
+            Family::BitcoinCore
+        } else if self.bip69 == Some(true) {
+            Family::Bip69
+        } else {
+            Family::Unknown
+        }
+    }
+}
+
+/// Returns true if inputs are sorted by previous txid (as displayed) and index,
+/// and outputs by value and scriptPubKey as specified in BIP69
+fn is_bip69_sorted(tx: &EvaluatedTx) -> bool {
+    let inputs: Vec<_> = tx
+        .inputs
+        .iter()
+        .map(|i| {
+            let mut txid = i.outpoint.txid.to_byte_array();
+            txid.reverse();
+            (txid, i.outpoint.index)
+        })
+        .collect();
+    let outputs: Vec<_> = tx
+        .outputs
+        .iter()
+        .map(|o| (o.out.value, &o.out.script_pubkey))
+        .collect();
+    inputs.windows(2).all(|w| w[0] <= w[1]) && outputs.windows(2).all(|w| w[0] <= w[1])
+}
+
+/// Returns true if the input reveals an uncompressed (65 byte) public key
+fn has_uncompressed_key(input: &TxInput) -> bool {
+    let pubkey_len = match input.pattern {
+        // <sig> <pubkey>
+        InputPattern::Pay2PublicKeyHash => {
+            match Script::from_bytes(&input.script_sig).instructions().last() {
+                Some(Ok(Instruction::PushBytes(pubkey))) => pubkey.len(),
+                _ => return false,
+            }
+        }
+        InputPattern::Pay2WitnessPublicKeyHash
+        | InputPattern::Pay2ScriptHashWitnessPublicKeyHash => match input.witness.get(1) {
+            Some(pubkey) => pubkey.len(),
+            None => return false,
+        },
+        _ => return false,
+    };
+    pubkey_len == 65
+}
+
+/// Feature and family counts of a month
+#[derive(Default)]
+struct Trend {
+    n_txs: u64,
+    n_version2: u64,
+    n_locktime_height: u64,
+    n_anti_fee_sniping: u64,
+    n_rbf: u64,
+    n_bip69: u64,
+    n_uncompressed: u64,
+    n_families: BTreeMap<Family, u64>,
+}
+
+impl Trend {
+    fn add(&mut self, fp: &Fingerprint) {
+        self.n_txs += 1;
+        self.n_version2 += (fp.version >= 2) as u64;
+        self.n_locktime_height += fp.locktime_height as u64;
+        self.n_anti_fee_sniping += fp.anti_fee_sniping as u64;
+        self.n_rbf += fp.rbf as u64;
+        self.n_bip69 += (fp.bip69 == Some(true)) as u64;
+        self.n_uncompressed += fp.uncompressed as u64;
+        *self.n_families.entry(fp.family()).or_insert(0) += 1;
+    }
+}
+
+/// Guesses the wallet family that created each transaction by known heuristics
+/// (nLockTime, BIP69 ordering, RBF signalling, compressed keys) and aggregates adoption per month.
+pub struct WalletFingerprint {
+    output: Output,
+    writer: Box<dyn Sink>,
+    trends_writer: Box<dyn Sink>,
+
+    start_height: u64,
+    trends: BTreeMap<String, Trend>,
+    total: Trend,
+}
+
+impl WalletFingerprint {
+    fn write_tx(
+        &mut self,
+        tx: &EvaluatedTx,
+        txid: &str,
+        block_height: u64,
+        month: &str,
+    ) -> OpResult<()> {
+        let fp = Fingerprint::new(tx, block_height);
+        let input_types: BTreeSet<String> =
+            tx.inputs.iter().map(|i| i.pattern.to_string()).collect();
+        let bip69 = match fp.bip69 {
+            Some(sorted) => (sorted as u8).to_string(),
+            None => String::new(),
+        };
+
+        // (@txid, height, version, locktime, antiFeeSniping, rbf, bip69, uncompressed, inputTypes, family)
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{};{};{};{}\n",
+                txid,
+                block_height,
+                tx.version,
+                tx.locktime,
+                fp.anti_fee_sniping as u8,
+                fp.rbf as u8,
+                bip69,
+                fp.uncompressed as u8,
+                input_types.into_iter().collect::<Vec<_>>().join(","),
+                fp.family().name()
+            )
+            .as_bytes(),
+        )?;
+        self.trends.entry(String::from(month)).or_default().add(&fp);
+        self.total.add(&fp);
+        Ok(())
+    }
+
+    fn write_trends(&mut self) -> OpResult<()> {
+        let families: Vec<&str> = Family::ALL.iter().map(|f| f.name()).collect();
+        self.trends_writer.write_all(
+            format!(
+                "month;txs;version2;locktimeHeight;antiFeeSniping;rbf;bip69;uncompressed;{}\n",
+                families.join(";")
+            )
+            .as_bytes(),
+        )?;
+        for (month, trend) in &self.trends {
+            let families: Vec<String> = Family::ALL
+                .iter()
+                .map(|f| trend.n_families.get(f).copied().unwrap_or(0).to_string())
+                .collect();
+            // (month, txs, version2, locktimeHeight, antiFeeSniping, rbf, bip69, uncompressed, families...)
+            self.trends_writer.write_all(
+                format!(
+                    "{};{};{};{};{};{};{};{};{}\n",
+                    month,
+                    trend.n_txs,
+                    trend.n_version2,
+                    trend.n_locktime_height,
+                    trend.n_anti_fee_sniping,
+                    trend.n_rbf,
+                    trend.n_bip69,
+                    trend.n_uncompressed,
+                    families.join(";")
+                )
+                .as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let total = &self.total;
+        let percent = |n: u64| n as f64 / total.n_txs.max(1) as f64 * 100.0;
+        writeln!(buffer, "Wallet fingerprints:")?;
+        writeln!(buffer, "   -> transactions:\t\t{}", total.n_txs)?;
+        writeln!(
+            buffer,
+            "   -> anti fee sniping:\t\t{} ({:.2}%)",
+            total.n_anti_fee_sniping,
+            percent(total.n_anti_fee_sniping)
+        )?;
+        writeln!(
+            buffer,
+            "   -> rbf signalling:\t\t{} ({:.2}%)",
+            total.n_rbf,
+            percent(total.n_rbf)
+        )?;
+        writeln!(
+            buffer,
+            "   -> bip69 sorted:\t\t{} ({:.2}%)",
+            total.n_bip69,
+            percent(total.n_bip69)
+        )?;
+        writeln!(
+            buffer,
+            "   -> uncompressed keys:\t{} ({:.2}%)",
+            total.n_uncompressed,
+            percent(total.n_uncompressed)
+        )?;
+        writeln!(buffer, "   Families:")?;
+        for family in Family::ALL {
+            let n = total.n_families.get(&family).copied().unwrap_or(0);
+            writeln!(
+                buffer,
+                "     -> {:<16}{} ({:.2}%)",
+                family.name(),
+                n,
+                percent(n)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Callback for WalletFingerprint {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("walletfingerprint")
+            .about("Guesses the creating wallet family per transaction and dumps monthly adoption trends to CSV files")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv files")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = WalletFingerprint {
+            writer: output.open("walletfingerprint")?,
+            trends_writer: output.open("walletfingerprint_trends")?,
+            output,
+            start_height: 0,
+            trends: BTreeMap::new(),
+            total: Trend::default(),
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing walletfingerprint with dump folder: {} ...", &self.output);
+        self.writer.write_all(
+            "txid;height;version;locktime;antiFeeSniping;rbf;bip69;uncompressed;inputTypes;family\n"
+                .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        let month = utils::month_of(block.header.value.timestamp);
+        // Coinbase transactions are created by miners, not by wallets
+        for tx in block.txs.iter().skip(1) {
+            self.write_tx(&tx.value, &tx.hash.to_string(), block_height, &month)?;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+        self.write_trends()?;
+        self.trends_writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "walletfingerprint", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(anti_fee_sniping: bool, bip69: Option<bool>, uncompressed: bool) -> Fingerprint {
+        Fingerprint {
+            version: 2,
+            locktime_height: anti_fee_sniping,
+            anti_fee_sniping,
+            rbf: false,
+            bip69,
+            uncompressed,
+        }
+    }
+
+    #[test]
+    fn test_family() {
+        assert_eq!(
+            fingerprint(true, Some(true), false).family(),
+            Family::Electrum
+        );
+        assert_eq!(
+            fingerprint(true, Some(false), false).family(),
+            Family::BitcoinCore
+        );
+        assert_eq!(fingerprint(true, None, false).family(), Family::BitcoinCore);
+        assert_eq!(
+            fingerprint(false, Some(true), false).family(),
+            Family::Bip69
+        );
+        assert_eq!(fingerprint(true, Some(true), true).family(), Family::Legacy);
+        assert_eq!(fingerprint(false, None, false).family(), Family::Unknown);
+    }
+}
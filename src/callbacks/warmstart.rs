@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::path::Path;
+use std::str::FromStr;
+
+use clap::Arg;
+
+use crate::blockchain::proto::tx::TxOutpoint;
+use crate::blockchain::proto::TxId;
+use crate::callbacks::common::{self, AddressInterner, UnspentValue};
+use crate::callbacks::utxodb;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::query::{self, merge::DumpFile};
+
+/// Columns of the unspent csv file written by `unspentcsvdump`, followed by the optional ones
+const UNSPENT_HEADER: &str = "txid;indexOut;height;value;address;scriptHash";
+
+/// Builds the `--load-unspent` argument for callbacks which can continue from a previous run
+pub fn build_arg() -> Arg {
+    Arg::new("load-unspent")
+        .long("load-unspent")
+        .value_name("FILE")
+        .help("Starts with the unspent outputs of a previous unspentcsvdump run (csv file or --leveldb database) and only parses the blocks after it")
+}
+
+/// Loads the unspent outputs of a previous `unspentcsvdump` run from its csv file
+/// or its `--leveldb` database. Returns the start and end height of the dump.
+pub fn load_unspents(
+    path: &Path,
+    unspents: &mut HashMap<TxOutpoint, UnspentValue>,
+    interner: &mut AddressInterner,
+) -> OpResult<(u64, u64)> {
+    info!(target: "callback", "Loading unspent outputs from {} ...", path.display());
+    let range = match path.is_dir() {
+        true => utxodb::load(path, unspents, interner)?,
+        false => load_csv(path, unspents, interner)?,
+    };
+    info!(target: "callback", "Loaded {} unspent outputs up to height {}.", unspents.len(), range.1);
+    Ok(range)
+}
+
+/// The csv file has no coinbase flag, only immature coinbase outputs are known with `--immature flag`.
+/// All others are treated as mature, which they are unless the dump is more recent than the maturity window.
+fn load_csv(
+    path: &Path,
+    unspents: &mut HashMap<TxOutpoint, UnspentValue>,
+    interner: &mut AddressInterner,
+) -> OpResult<(u64, u64)> {
+    let invalid = |msg: &str| {
+        let msg = format!("{}: {}", path.display(), msg);
+        OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg)
+    };
+    let range = DumpFile::from_path(path)
+        .map(|file| (file.start, file.end))
+        .ok_or_else(|| invalid("expected a file named unspent-<start>-<end>.csv[.gz]"))?;
+    let mut lines = query::open_dump(path)?.lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    if !header.starts_with(UNSPENT_HEADER) {
+        return Err(invalid("not an unspent csv file"));
+    }
+    let immature_column = header.split(';').position(|column| column == "immature");
+    for (n, line) in lines.enumerate() {
+        let line = line?;
+        let (outpoint, unspent) = parse_row(&line, immature_column, interner)
+            .ok_or_else(|| invalid(&format!("invalid row {}: {}", n + 2, line)))?;
+        unspents.insert(outpoint, unspent);
+    }
+    Ok(range)
+}
+
+fn parse_row(
+    row: &str,
+    immature_column: Option<usize>,
+    interner: &mut AddressInterner,
+) -> Option<(TxOutpoint, UnspentValue)> {
+    let fields: Vec<&str> = row.split(';').collect();
+    if fields.len() < 6 {
+        return None;
+    }
+    let txid = TxId::from_str(fields[0]).ok()?;
+    let key = common::column_key(fields[4], fields[5]);
+    let unspent = UnspentValue {
+        block_height: fields[2].parse().ok()?,
+        value: fields[3].parse().ok()?,
+        address_id: interner.intern(&key),
+        is_coinbase: immature_column.and_then(|i| fields.get(i)) == Some(&"1"),
+    };
+    Some((TxOutpoint::new(txid, fields[1].parse().ok()?), unspent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_row() {
+        let mut interner = AddressInterner::new();
+        let txid = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        let row = format!("{};1;7;5000;;ab12;1;label", txid);
+        let (outpoint, unspent) = parse_row(&row, Some(6), &mut interner).unwrap();
+        assert_eq!(outpoint, TxOutpoint::new(TxId::from_str(txid).unwrap(), 1));
+        assert_eq!(
+            (unspent.block_height, unspent.value, unspent.is_coinbase),
+            (7, 5000, true)
+        );
+        assert_eq!(
+            common::key_columns(interner.address(unspent.address_id)),
+            ("", "ab12")
+        );
+        let row = format!("{};1;7;5000;1address;", txid);
+        let (_, unspent) = parse_row(&row, None, &mut interner).unwrap();
+        assert!(!unspent.is_coinbase);
+        assert!(parse_row("foo;1;7;5000;1address;", None, &mut interner).is_none());
+    }
+}
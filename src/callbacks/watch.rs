@@ -0,0 +1,273 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::{EvaluatedTxOut, TxOutpoint};
+use crate::blockchain::proto::TxId;
+use crate::callbacks::getblock::{object, quote};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::{logger, utils};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::query::verifybalances::http_post;
+
+/// Watched address or script, along with its optional label
+struct Watched {
+    name: String,
+    label: String,
+}
+
+/// Activity of a watched address or script
+struct Alert<'a> {
+    height: u64,
+    block: &'a Block,
+    txid: &'a TxId,
+    // Output index for received value, input index for spent value
+    index: usize,
+    spent: bool,
+    value: u64,
+    entry: usize,
+}
+
+/// Prints an alert line to stdout for each output received by and each input spending from
+/// the addresses or scripts of a watchlist, and optionally posts them as JSON to a webhook.
+/// Spends of outputs created before the parsed range are only detected with `--use-undo-files`.
+pub struct Watch {
+    // Watched entries by address or lowercase script hex
+    watchlist: HashMap<String, usize>,
+    entries: Vec<Watched>,
+    // Host and path of the webhook
+    webhook: Option<(String, String)>,
+
+    // Outputs to watched entries created within the parsed range, with their entry and value
+    unspents: HashMap<TxOutpoint, (usize, u64)>,
+    n_alerts: u64,
+    n_webhook_errors: u64,
+}
+
+impl Watch {
+    /// Reads `<address or script hex>[,label]` lines, empty lines and lines starting with `#` are skipped
+    fn read_watchlist(path: &Path) -> OpResult<(HashMap<String, usize>, Vec<Watched>)> {
+        let content = fs::read_to_string(path)?;
+        let mut watchlist = HashMap::new();
+        let mut entries = Vec::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, label) = line.split_once(',').unwrap_or((line, ""));
+            let name = name.trim();
+            // Script hex is matched case insensitive, addresses as they are
+            let key = match name.chars().all(|c| c.is_ascii_hexdigit()) && name.len() % 2 == 0 {
+                true => name.to_lowercase(),
+                false => String::from(name),
+            };
+            if let Entry::Vacant(entry) = watchlist.entry(key) {
+                entry.insert(entries.len());
+                entries.push(Watched {
+                    name: String::from(name),
+                    label: String::from(label.trim()),
+                });
+            }
+        }
+        if entries.is_empty() {
+            let msg = format!("{} contains no addresses or scripts", path.display());
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+        }
+        Ok((watchlist, entries))
+    }
+
+    /// Splits `http://host:port/path` into host and path, TLS is not supported
+    fn parse_webhook(url: &str) -> OpResult<(String, String)> {
+        match url.strip_prefix("http://") {
+            Some(rest) => {
+                let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+                Ok((String::from(host), format!("/{}", path)))
+            }
+            None => {
+                let msg = format!(
+                    "Unsupported webhook {}, use http://host:port/path (TLS is not supported)",
+                    url
+                );
+                Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg))
+            }
+        }
+    }
+
+    /// Returns the watched entry the output pays to
+    fn find(&self, out: &EvaluatedTxOut) -> Option<usize> {
+        let by_address = out
+            .script
+            .address
+            .as_ref()
+            .and_then(|address| self.watchlist.get(address));
+        by_address
+            .or_else(|| {
+                self.watchlist
+                    .get(&utils::arr_to_hex(&out.out.script_pubkey))
+            })
+            .copied()
+    }
+
+    fn alert(&mut self, alert: Alert) -> OpResult<()> {
+        self.n_alerts += 1;
+        let watched = &self.entries[alert.entry];
+        let direction = match alert.spent {
+            true => "spent",
+            false => "received",
+        };
+        let block_hash = alert.block.header.hash.to_string();
+        let time = alert.block.header.value.timestamp;
+        // (height;blockHash;time;txid;index;direction;watched;value;label)
+        let line = format!(
+            "{};{};{};{};{};{};{};{};{}\n",
+            alert.height,
+            block_hash,
+            time,
+            alert.txid,
+            alert.index,
+            direction,
+            watched.name,
+            alert.value,
+            watched.label
+        );
+        io::stdout().write_all(line.as_bytes())?;
+
+        if let Some((host, path)) = &self.webhook {
+            let body = object(
+                &[
+                    ("height", alert.height.to_string()),
+                    ("blockHash", quote(&block_hash)),
+                    ("time", time.to_string()),
+                    ("txid", quote(&alert.txid.to_string())),
+                    ("index", alert.index.to_string()),
+                    ("direction", quote(direction)),
+                    ("watched", quote(&watched.name)),
+                    ("value", alert.value.to_string()),
+                    ("label", quote(&watched.label)),
+                ],
+                0,
+            );
+            // An unreachable webhook must not stop the monitoring, the alerts are on stdout as well
+            if let Err(why) = http_post(host, path, &body) {
+                self.n_webhook_errors += 1;
+                warn!(target: "callback", "Unable to post alert for {}: {}", alert.txid, why);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Callback for Watch {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("watch")
+            .about("Prints alert lines for transactions paying to or spending from a watchlist of addresses or scripts")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("watchlist")
+                    .help("File with <address or script hex>[,label] lines")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("webhook")
+                    .long("webhook")
+                    .value_name("URL")
+                    .help("Posts each alert as JSON to the given http://host:port/path"),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let (watchlist, entries) =
+            Watch::read_watchlist(Path::new(matches.get_one::<String>("watchlist").unwrap()))?;
+        let webhook = match matches.get_one::<String>("webhook") {
+            Some(url) => Some(Watch::parse_webhook(url)?),
+            None => None,
+        };
+        // stdout is reserved for the alerts
+        logger::redirect_to_stderr();
+        Ok(Watch {
+            watchlist,
+            entries,
+            webhook,
+            unspents: HashMap::new(),
+            n_alerts: 0,
+            n_webhook_errors: 0,
+        })
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        info!(target: "callback", "Executing watch for {} addresses and scripts ...", self.entries.len());
+        if block_height > 0 {
+            info!(target: "callback", "Spends of outputs before block {} are only detected with --use-undo-files.", block_height);
+        }
+        io::stdout().write_all(
+            "height;blockHash;time;txid;index;direction;watched;value;label\n".as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            for (i, input) in tx.value.inputs.iter().enumerate() {
+                // Undo data resolves spends of outputs from before the parsed range
+                let spent = match self.unspents.remove(&input.outpoint) {
+                    Some(spent) => Some(spent),
+                    None => input.prevout.as_ref().and_then(|prevout| {
+                        self.find(&prevout.out)
+                            .map(|entry| (entry, prevout.out.out.value))
+                    }),
+                };
+                if let Some((entry, value)) = spent {
+                    self.alert(Alert {
+                        height: block_height,
+                        block,
+                        txid: &tx.hash,
+                        index: i,
+                        spent: true,
+                        value,
+                        entry,
+                    })?;
+                }
+            }
+            for (i, output) in tx.value.outputs.iter().enumerate() {
+                if let Some(entry) = self.find(output) {
+                    self.unspents.insert(
+                        TxOutpoint::new(tx.hash, i as u32),
+                        (entry, output.out.value),
+                    );
+                    self.alert(Alert {
+                        height: block_height,
+                        block,
+                        txid: &tx.hash,
+                        index: i,
+                        spent: false,
+                        value: output.out.value,
+                        entry,
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        io::stdout().flush()?;
+        if self.n_webhook_errors > 0 {
+            warn!(target: "callback", "{} alerts couldn't be posted to the webhook.", self.n_webhook_errors);
+        }
+        info!(target: "callback", "Done.\nFound {} alerts up to height {}.", self.n_alerts, block_height);
+        Ok(())
+    }
+}
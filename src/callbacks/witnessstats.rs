@@ -0,0 +1,157 @@
+use std::io::{self, Write};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::blockchain::proto::block::Block;
+use crate::blockchain::proto::tx::EvaluatedTx;
+use crate::blockchain::proto::ToRaw;
+use crate::callbacks::sink::{Output, Sink};
+use crate::callbacks::{Callback, CallbackContext};
+use crate::errors::OpResult;
+
+/// Dumps how much space of each transaction goes to scriptSigs and witness data,
+/// and how many virtual bytes the witness discount saves, to research the block space
+/// used by signatures compared to payloads over time.
+pub struct WitnessStats {
+    output: Output,
+    writer: Box<dyn Sink>,
+
+    start_height: u64,
+    n_txs: u64,
+    n_witness_txs: u64,
+    size: u64,
+    script_sig_size: u64,
+    witness_size: u64,
+    savings: u64,
+}
+
+impl WitnessStats {
+    fn write_tx(&mut self, tx: &EvaluatedTx, txid: &str, block_height: u64) -> OpResult<()> {
+        let base_size = tx.to_bytes().len() as u64;
+        let size = tx.to_bytes_with_witness().len() as u64;
+        // Includes the segwit marker and flag and the item counts
+        let witness_size = size - base_size;
+        let weight = base_size * 3 + size;
+        let vsize = weight.div_ceil(4);
+        let script_sig_size: u64 = tx.inputs.iter().map(|i| i.script_sig.len() as u64).sum();
+        let witness_items: usize = tx.inputs.iter().map(|i| i.witness.len()).sum();
+
+        self.n_txs += 1;
+        if witness_size > 0 {
+            self.n_witness_txs += 1;
+        }
+        self.size += size;
+        self.script_sig_size += script_sig_size;
+        self.witness_size += witness_size;
+        self.savings += size - vsize;
+
+        // (@txid, height, size, baseSize, scriptSigSize, witnessSize, witnessItems, weight, vsize,
+        //  witnessSavings)
+        self.writer.write_all(
+            format!(
+                "{};{};{};{};{};{};{};{};{};{}\n",
+                txid,
+                block_height,
+                size,
+                base_size,
+                script_sig_size,
+                witness_size,
+                witness_items,
+                weight,
+                vsize,
+                size - vsize
+            )
+            .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn print_summary(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let percent = |n: u64| n as f64 / self.size.max(1) as f64 * 100.0;
+        writeln!(buffer, "Witness stats:")?;
+        writeln!(buffer, "   -> transactions:\t\t{}", self.n_txs)?;
+        writeln!(buffer, "   -> with witness:\t\t{}", self.n_witness_txs)?;
+        writeln!(buffer, "   -> total size:\t\t{} bytes", self.size)?;
+        writeln!(
+            buffer,
+            "   -> scriptSigs:\t\t{} bytes ({:.2}%)",
+            self.script_sig_size,
+            percent(self.script_sig_size)
+        )?;
+        writeln!(
+            buffer,
+            "   -> witness data:\t\t{} bytes ({:.2}%)",
+            self.witness_size,
+            percent(self.witness_size)
+        )?;
+        writeln!(buffer, "   -> witness discount:\t\t{} vbytes", self.savings)?;
+        Ok(())
+    }
+}
+
+impl Callback for WitnessStats {
+    fn build_subcommand() -> Command
+    where
+        Self: Sized,
+    {
+        Command::new("witnessstats")
+            .about("Dumps scriptSig and witness sizes and the witness discount per transaction to CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store csv file")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn new(matches: &ArgMatches, _: &CallbackContext) -> OpResult<Self>
+    where
+        Self: Sized,
+    {
+        let output = Output::from_matches(matches)?;
+        let cb = WitnessStats {
+            writer: output.open("witnessstats")?,
+            output,
+            start_height: 0,
+            n_txs: 0,
+            n_witness_txs: 0,
+            size: 0,
+            script_sig_size: 0,
+            witness_size: 0,
+            savings: 0,
+        };
+        Ok(cb)
+    }
+
+    fn on_start(&mut self, block_height: u64) -> OpResult<()> {
+        self.start_height = block_height;
+        info!(target: "callback", "Executing witnessstats with dump folder: {} ...", &self.output);
+        self.writer.write_all(
+            "txid;height;size;baseSize;scriptSigSize;witnessSize;witnessItems;weight;vsize;witnessSavings\n"
+                .as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn on_block(&mut self, block: &Block, block_height: u64) -> OpResult<()> {
+        for tx in &block.txs {
+            self.write_tx(&tx.value, &tx.hash.to_string(), block_height)?;
+        }
+        Ok(())
+    }
+
+    fn on_complete(&mut self, block_height: u64) -> OpResult<()> {
+        self.writer.finish(self.start_height, block_height)?;
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&mut buffer)?;
+        info!(target: "witnessstats", "\n\n{}", String::from_utf8_lossy(&buffer));
+        Ok(())
+    }
+
+    fn wants_addresses(&self) -> bool {
+        false
+    }
+}
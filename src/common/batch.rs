@@ -0,0 +1,307 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Instant;
+
+use toml::{Table, Value};
+
+use crate::command;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Single parser invocation of a batch config
+#[cfg_attr(test, derive(PartialEq, Debug))]
+struct Job {
+    name: String,
+    // Arguments passed to the parser, without the binary
+    args: Vec<String>,
+}
+
+impl Job {
+    /// Builds the job from a `[[run]]` table
+    fn from_table(table: &Table, n: usize) -> OpResult<Self> {
+        let name = match table.get("name") {
+            Some(name) => str_value(name, "name")?.to_string(),
+            None => format!("run-{}", n + 1),
+        };
+        let mut args = Vec::new();
+        for (key, value) in table {
+            match key.as_str() {
+                "coin" => args.extend([String::from("--coin"), str_value(value, key)?.to_string()]),
+                "blockchain-dir" => args.extend([
+                    String::from("--blockchain-dir"),
+                    str_value(value, key)?.to_string(),
+                ]),
+                "start" | "end" => {
+                    let height = value
+                        .as_integer()
+                        .filter(|height| *height >= 0)
+                        .ok_or_else(|| invalid(&name, &format!("{} must be a height", key)))?;
+                    args.extend([format!("--{}", key), height.to_string()]);
+                }
+                "name" | "args" | "callback" | "output" | "callback-args" => {}
+                _ => return Err(invalid(&name, &format!("unknown key '{}'", key))),
+            }
+        }
+        // Global options go before the callback
+        args.extend(str_array(table, "args", &name)?);
+        let callback = table
+            .get("callback")
+            .ok_or_else(|| invalid(&name, "callback is missing"))?;
+        args.push(str_value(callback, "callback")?.to_string());
+        if let Some(output) = table.get("output") {
+            args.push(str_value(output, "output")?.to_string());
+        }
+        args.extend(str_array(table, "callback-args", &name)?);
+
+        // Catch typos before anything runs
+        let mut argv = vec![String::from(env!("CARGO_PKG_NAME"))];
+        argv.extend(args.iter().cloned());
+        if let Err(err) = command().try_get_matches_from(argv) {
+            return Err(invalid(&name, &err.to_string()));
+        }
+        Ok(Job { name, args })
+    }
+
+    /// Runs the parser in a child process and waits for it to exit
+    fn run(&self) -> JobResult {
+        info!(target: "batch", "Starting {}: {}", self.name, self.args.join(" "));
+        let started_at = Instant::now();
+        let status = env::current_exe()
+            .and_then(|exe| Command::new(exe).args(&self.args).status())
+            .map_err(|err| err.to_string())
+            .and_then(|status| match status.success() {
+                true => Ok(()),
+                false => Err(status.to_string()),
+            });
+        match &status {
+            Ok(()) => info!(target: "batch", "Finished {}", self.name),
+            Err(why) => error!(target: "batch", "{} failed: {}", self.name, why),
+        }
+        JobResult {
+            status,
+            minutes: started_at.elapsed().as_secs_f32() / 60.0,
+        }
+    }
+}
+
+struct JobResult {
+    status: Result<(), String>,
+    minutes: f32,
+}
+
+/// Runs several parser jobs, e.g. to maintain dumps of multiple chains,
+/// described by a TOML config (see `--config`):
+///
+/// ```toml
+/// parallel = false  # run all jobs at once in separate processes
+///
+/// [[run]]
+/// name = "bitcoin"
+/// coin = "bitcoin"
+/// blockchain-dir = "/data/bitcoin/blocks"
+/// start = 0
+/// end = 800000
+/// args = ["--verify"]
+/// callback = "unspentcsvdump"
+/// output = "/dumps/bitcoin"
+/// callback-args = ["--labels", "labels.csv"]
+/// ```
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub struct Batch {
+    jobs: Vec<Job>,
+    parallel: bool,
+}
+
+impl Batch {
+    pub fn from_path(path: &Path) -> OpResult<Self> {
+        let content = fs::read_to_string(path)?;
+        Batch::parse(&content).map_err(|err| {
+            let msg = format!("{}: {}", path.display(), err.message);
+            OpError::new(err.kind).join_msg(&msg)
+        })
+    }
+
+    fn parse(content: &str) -> OpResult<Self> {
+        let config: Table = content.parse().map_err(|err: toml::de::Error| {
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(err.message())
+        })?;
+        let parallel = match config.get("parallel") {
+            Some(Value::Boolean(parallel)) => *parallel,
+            Some(_) => return Err(invalid("config", "parallel must be a boolean")),
+            None => false,
+        };
+        let jobs = match config.get("run") {
+            Some(Value::Array(runs)) => runs
+                .iter()
+                .enumerate()
+                .map(|(n, run)| match run {
+                    Value::Table(table) => Job::from_table(table, n),
+                    _ => Err(invalid("config", "run must be a table")),
+                })
+                .collect::<OpResult<Vec<Job>>>()?,
+            _ => Vec::new(),
+        };
+        if jobs.is_empty() {
+            return Err(invalid("config", "no [[run]] entries found"));
+        }
+        if let Some(key) = config
+            .keys()
+            .find(|key| !["parallel", "run"].contains(&key.as_str()))
+        {
+            return Err(invalid("config", &format!("unknown key '{}'", key)));
+        }
+        Ok(Batch { jobs, parallel })
+    }
+
+    /// Runs all jobs and logs a summary, returns an error if any job failed
+    pub fn run(&self) -> OpResult<()> {
+        info!(target: "batch", "Running {} jobs {} ...", self.jobs.len(), match self.parallel {
+            true => "in parallel",
+            false => "sequentially",
+        });
+        let started_at = Instant::now();
+        let results: Vec<JobResult> = match self.parallel {
+            true => thread::scope(|scope| {
+                let handles: Vec<_> = self
+                    .jobs
+                    .iter()
+                    .map(|job| scope.spawn(|| job.run()))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("batch job panicked"))
+                    .collect()
+            }),
+            false => self.jobs.iter().map(Job::run).collect(),
+        };
+
+        let mut buffer = Vec::with_capacity(4096);
+        self.print_summary(&results, started_at, &mut buffer)?;
+        info!(target: "batch", "\n\n{}", String::from_utf8_lossy(&buffer));
+
+        let n_failed = results.iter().filter(|r| r.status.is_err()).count();
+        if n_failed > 0 {
+            let msg = format!("{} of {} jobs failed", n_failed, results.len());
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+        }
+        Ok(())
+    }
+
+    fn print_summary(
+        &self,
+        results: &[JobResult],
+        started_at: Instant,
+        buffer: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        writeln!(
+            buffer,
+            "Batch summary ({:.2} minutes):",
+            started_at.elapsed().as_secs_f32() / 60.0
+        )?;
+        for (job, result) in self.jobs.iter().zip(results) {
+            writeln!(
+                buffer,
+                "   -> {}:\t{}\t({:.2} minutes)",
+                job.name,
+                Status(&result.status),
+                result.minutes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct Status<'a>(&'a Result<(), String>);
+
+impl fmt::Display for Status<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Ok(()) => write!(f, "ok"),
+            Err(why) => write!(f, "failed ({})", why),
+        }
+    }
+}
+
+fn invalid(name: &str, msg: &str) -> OpError {
+    OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!("{}: {}", name, msg))
+}
+
+fn str_value<'a>(value: &'a Value, key: &str) -> OpResult<&'a str> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid(key, "expected a string"))
+}
+
+/// Returns the string array with the given key, an empty one if not present
+fn str_array(table: &Table, key: &str, name: &str) -> OpResult<Vec<String>> {
+    match table.get(key) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| str_value(value, key).map(String::from))
+            .collect(),
+        Some(_) => Err(invalid(
+            name,
+            &format!("{} must be an array of strings", key),
+        )),
+        None => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let batch = Batch::parse(
+            r#"
+            parallel = true
+
+            [[run]]
+            name = "btc"
+            coin = "bitcoin"
+            blockchain-dir = "/data/btc"
+            end = 100
+            args = ["--verify"]
+            callback = "balances"
+            output = "/dumps/btc"
+            callback-args = ["--snapshot-height", "50"]
+
+            [[run]]
+            coin = "litecoin"
+            callback = "simplestats"
+            "#,
+        )
+        .unwrap();
+        assert!(batch.parallel);
+        assert_eq!(batch.jobs.len(), 2);
+        assert_eq!(batch.jobs[0].name, "btc");
+        assert_eq!(
+            batch.jobs[0].args.join(" "),
+            "--blockchain-dir /data/btc --coin bitcoin --end 100 --verify balances /dumps/btc --snapshot-height 50"
+        );
+        assert_eq!(
+            batch.jobs[1],
+            Job {
+                name: String::from("run-2"),
+                args: ["--coin", "litecoin", "simplestats"]
+                    .map(String::from)
+                    .to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Batch::parse("parallel = true").is_err());
+        assert!(Batch::parse("[[run]]\ncoin = \"bitcoin\"").is_err());
+        assert!(Batch::parse("[[run]]\ncallback = \"unknown\"").is_err());
+        assert!(Batch::parse("[[run]]\ncallback = \"simplestats\"\nstart = -1").is_err());
+        assert!(Batch::parse("[[run]]\ncallback = \"simplestats\"\ncoins = \"x\"").is_err());
+        assert!(Batch::parse("[[run]]\ncallback = \"csvdump\"").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use clap::{Arg, Command};
+
+/// Shells completion scripts can be generated for
+const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+pub fn build_subcommand() -> Command {
+    Command::new("completions")
+        .about("Prints a shell completion script, e.g. `rusty-blockparser completions bash > /etc/bash_completion.d/rusty-blockparser`")
+        .version("0.1")
+        .author("gcarq <egger.m@protonmail.com>")
+        .arg(
+            Arg::new("shell")
+                .help("Shell to generate the script for")
+                .index(1)
+                .required(true)
+                .value_parser(SHELLS),
+        )
+}
+
+/// Generates the completion script for the given shell from the arguments and subcommands of `command`.
+/// Global options are completed in front of the subcommand, the options of a subcommand after it.
+pub fn generate(command: &mut Command, shell: &str) -> String {
+    command.build();
+    match shell {
+        "fish" => fish(command),
+        // zsh runs the bash script through its bash compatibility layer
+        "zsh" => format!(
+            "#compdef {}\n\nautoload -U +X bashcompinit && bashcompinit\n\n{}",
+            command.get_name(),
+            bash(command)
+        ),
+        _ => bash(command),
+    }
+}
+
+/// Returns the visible options (not positionals) of the command
+fn options(command: &Command) -> impl Iterator<Item = &Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+}
+
+/// Returns the visible subcommands of the command
+fn subcommands(command: &Command) -> impl Iterator<Item = &Command> {
+    command.get_subcommands().filter(|sub| !sub.is_hide_set())
+}
+
+/// Returns all spellings of the option, e.g. `-c --coin`
+fn flags(arg: &Arg) -> Vec<String> {
+    let short = arg.get_short().map(|s| format!("-{}", s));
+    let long = arg.get_long().map(|l| format!("--{}", l));
+    short.into_iter().chain(long).collect()
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_num_args().is_some_and(|n| n.takes_values())
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+fn bash(command: &Command) -> String {
+    let name = command.get_name();
+    let function = format!("_{}", name.replace('-', "_"));
+    let subcommand_names: Vec<&str> = subcommands(command).map(Command::get_name).collect();
+
+    // Options taking a value complete their possible values or file names
+    let mut values: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for arg in options(command).chain(subcommands(command).flat_map(options)) {
+        if takes_value(arg) {
+            for flag in flags(arg) {
+                values.entry(flag).or_insert_with(|| possible_values(arg));
+            }
+        }
+    }
+
+    let mut script = String::new();
+    let _ = writeln!(script, "{}() {{", function);
+    script.push_str("    local cur prev cmd i\n");
+    script.push_str("    COMPREPLY=()\n");
+    script.push_str("    cur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+    script.push_str("    prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    script.push_str("    cmd=\"\"\n");
+    script.push_str("    for ((i = 1; i < COMP_CWORD; i++)); do\n");
+    script.push_str("        case \"${COMP_WORDS[i]}\" in\n");
+    let _ = writeln!(
+        script,
+        "            {}) cmd=\"${{COMP_WORDS[i]}}\"; break ;;",
+        subcommand_names.join("|")
+    );
+    script.push_str("        esac\n    done\n\n");
+
+    script.push_str("    case \"$prev\" in\n");
+    for (flag, possible) in &values {
+        let reply = match possible.is_empty() {
+            true => String::from("compgen -f -- \"$cur\""),
+            false => format!("compgen -W \"{}\" -- \"$cur\"", possible.join(" ")),
+        };
+        let _ = writeln!(
+            script,
+            "        {}) COMPREPLY=($({})); return 0 ;;",
+            flag, reply
+        );
+    }
+    script.push_str("    esac\n\n");
+
+    script.push_str("    case \"$cmd\" in\n");
+    let globals: Vec<String> = options(command).flat_map(flags).collect();
+    let _ = writeln!(
+        script,
+        "        \"\") COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\")) ;;",
+        globals.join(" "),
+        subcommand_names.join(" ")
+    );
+    for sub in subcommands(command) {
+        let sub_flags: Vec<String> = options(sub).flat_map(flags).collect();
+        let _ = writeln!(
+            script,
+            "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")) ;;",
+            sub.get_name(),
+            sub_flags.join(" ")
+        );
+    }
+    script.push_str("    esac\n");
+    // Positional arguments are mostly files and folders
+    script.push_str("    if [[ ${#COMPREPLY[@]} -eq 0 ]]; then\n");
+    script.push_str("        COMPREPLY=($(compgen -f -- \"$cur\"))\n");
+    script.push_str("    fi\n    return 0\n}\n\n");
+    let _ = writeln!(script, "complete -F {} {}", function, name);
+    script
+}
+
+fn fish(command: &Command) -> String {
+    let name = command.get_name();
+    let mut script = String::new();
+    let fish_option = |script: &mut String, condition: &str, arg: &Arg| {
+        let _ = write!(script, "complete -c {} -n \"{}\"", name, condition);
+        if let Some(short) = arg.get_short() {
+            let _ = write!(script, " -s {}", short);
+        }
+        if let Some(long) = arg.get_long() {
+            let _ = write!(script, " -l {}", long);
+        }
+        if takes_value(arg) {
+            script.push_str(" -r");
+            let possible = possible_values(arg);
+            if !possible.is_empty() {
+                let _ = write!(script, " -f -a \"{}\"", possible.join(" "));
+            }
+        }
+        if let Some(help) = arg.get_help() {
+            let _ = write!(script, " -d {}", fish_quote(&help.to_string()));
+        }
+        script.push('\n');
+    };
+
+    for arg in options(command) {
+        fish_option(&mut script, "__fish_use_subcommand", arg);
+    }
+    for sub in subcommands(command) {
+        let about = sub
+            .get_about()
+            .map(|about| about.to_string())
+            .unwrap_or_default();
+        let _ = writeln!(
+            script,
+            "complete -c {} -n \"__fish_use_subcommand\" -f -a {} -d {}",
+            name,
+            sub.get_name(),
+            fish_quote(&about)
+        );
+    }
+    for sub in subcommands(command) {
+        let condition = format!("__fish_seen_subcommand_from {}", sub.get_name());
+        for arg in options(sub) {
+            fish_option(&mut script, &condition, arg);
+        }
+    }
+    script
+}
+
+/// Quotes the text for fish, descriptions are cut at the first line
+fn fish_quote(text: &str) -> String {
+    let first_line = text.lines().next().unwrap_or_default();
+    format!(
+        "'{}'",
+        first_line.replace('\\', "\\\\").replace('\'', "\\'")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command;
+
+    #[test]
+    fn test_generate() {
+        let bash = generate(&mut command(), "bash");
+        assert!(bash.contains("complete -F _rusty_blockparser rusty-blockparser"));
+        assert!(bash.contains("--coin) COMPREPLY=($(compgen -W \"bitcoin testnet3"));
+        assert!(bash.contains("        csvdump) COMPREPLY=($(compgen -W \""));
+        assert!(generate(&mut command(), "zsh").starts_with("#compdef rusty-blockparser"));
+
+        let fish = generate(&mut command(), "fish");
+        assert!(fish.contains(
+            "complete -c rusty-blockparser -n \"__fish_use_subcommand\" -s c -l coin -r -f -a \"bitcoin"
+        ));
+        assert!(fish.contains("-n \"__fish_seen_subcommand_from feemarket\" -l full-threshold -r"));
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::builder::Resettable;
+use clap::Command;
+use toml::{Table, Value};
+
+use crate::blockchain::parser::types::COIN_NAMES;
+use crate::callbacks::sink;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+/// Defaults for frequently repeated arguments, read from
+/// `<config dir>/rusty-blockparser/config.toml` (`~/.config` on Linux):
+///
+/// ```toml
+/// coin = "litecoin"
+/// blockchain-dir = "/data/litecoin/blocks"
+/// verbosity = 1       # same as -v
+/// output = "/dumps"   # dump folder of callbacks which write csv files
+/// ```
+/// Arguments given on the command line always take precedence.
+#[derive(Default)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+pub struct Defaults {
+    coin: Option<String>,
+    blockchain_dir: Option<String>,
+    verbosity: Option<u8>,
+    output: Option<String>,
+}
+
+impl Defaults {
+    /// Returns the location of the config file, None if there is no config directory
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+    }
+
+    /// Reads the config file if it exists
+    pub fn load() -> OpResult<Self> {
+        match Defaults::path().filter(|path| path.is_file()) {
+            Some(path) => Defaults::from_path(&path),
+            None => Ok(Defaults::default()),
+        }
+    }
+
+    fn from_path(path: &Path) -> OpResult<Self> {
+        let content = fs::read_to_string(path)?;
+        Defaults::parse(&content).map_err(|err| {
+            let msg = format!("{}: {}", path.display(), err.message);
+            OpError::new(err.kind).join_msg(&msg)
+        })
+    }
+
+    fn parse(content: &str) -> OpResult<Self> {
+        let config: Table = content.parse().map_err(|err: toml::de::Error| {
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(err.message())
+        })?;
+        let mut defaults = Defaults::default();
+        for (key, value) in &config {
+            match key.as_str() {
+                "coin" => {
+                    let coin = str_value(value, key)?;
+                    if !COIN_NAMES.contains(&coin) {
+                        return Err(invalid(&format!("unknown coin '{}'", coin)));
+                    }
+                    defaults.coin = Some(String::from(coin));
+                }
+                "blockchain-dir" => defaults.blockchain_dir = Some(str_value(value, key)?.into()),
+                "output" => defaults.output = Some(str_value(value, key)?.into()),
+                "verbosity" => {
+                    let verbosity = value
+                        .as_integer()
+                        .and_then(|v| u8::try_from(v).ok())
+                        .ok_or_else(|| invalid("verbosity must be 0, 1 or 2"))?;
+                    defaults.verbosity = Some(verbosity);
+                }
+                _ => return Err(invalid(&format!("unknown key '{}'", key))),
+            }
+        }
+        Ok(defaults)
+    }
+
+    /// Sets the defaults as default values of the respective arguments, so clap only
+    /// falls back to them if the arguments are missing. They show up in `--help` as well.
+    pub fn apply(&self, mut command: Command) -> Command {
+        if let Some(coin) = &self.coin {
+            command = command.mut_arg("coin", |arg| arg.default_value(leak(coin)));
+        }
+        if let Some(dir) = &self.blockchain_dir {
+            command = command.mut_arg("blockchain-dir", |arg| arg.default_value(leak(dir)));
+        }
+        if let Some(verbosity) = self.verbosity {
+            command = command.mut_arg("verbosity", |arg| {
+                arg.default_value(leak(&verbosity.to_string()))
+            });
+        }
+        if let Some(output) = &self.output {
+            // A dump folder followed by other positionals (e.g. the address of balancehistory)
+            // has to stay required, the next positional would be taken as folder otherwise
+            for name in sink::dump_folder_subcommands(&command) {
+                command = command.mut_subcommand(name, |sub| {
+                    sub.mut_arg("dump-folder", |arg| {
+                        arg.required(false)
+                            .required_unless_present(Resettable::Reset)
+                            .default_value(leak(output))
+                    })
+                });
+            }
+        }
+        command
+    }
+}
+
+/// Default values of clap need to be static, the defaults are applied once per process
+fn leak(value: &str) -> &'static str {
+    Box::leak(value.to_owned().into_boxed_str())
+}
+
+fn invalid(msg: &str) -> OpError {
+    OpError::new(OpErrorKind::InvalidArgsError).join_msg(msg)
+}
+
+fn str_value<'a>(value: &'a Value, key: &str) -> OpResult<&'a str> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid(&format!("{} must be a string", key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command;
+
+    #[test]
+    fn test_parse() {
+        let defaults = Defaults::parse(
+            r#"
+            coin = "litecoin"
+            blockchain-dir = "/data/ltc"
+            verbosity = 1
+            output = "/dumps"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            defaults,
+            Defaults {
+                coin: Some(String::from("litecoin")),
+                blockchain_dir: Some(String::from("/data/ltc")),
+                verbosity: Some(1),
+                output: Some(String::from("/dumps")),
+            }
+        );
+        assert!(Defaults::parse("coin = \"unknown\"").is_err());
+        assert!(Defaults::parse("verbosity = -1").is_err());
+        assert!(Defaults::parse("coins = \"bitcoin\"").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let defaults =
+            Defaults::parse("coin = \"litecoin\"\nverbosity = 1\noutput = \"/dumps\"").unwrap();
+        let matches = defaults
+            .apply(command())
+            .get_matches_from(["rusty-blockparser", "csvdump"]);
+        assert_eq!(matches.get_one::<String>("coin").unwrap(), "litecoin");
+        assert_eq!(matches.get_count("verbosity"), 1);
+        let (_, sub) = matches.subcommand().unwrap();
+        assert_eq!(sub.get_one::<String>("dump-folder").unwrap(), "/dumps");
+
+        // Arguments on the command line take precedence
+        let matches = defaults.apply(command()).get_matches_from([
+            "rusty-blockparser",
+            "-c",
+            "dogecoin",
+            "-vv",
+            "csvdump",
+            "/tmp",
+        ]);
+        assert_eq!(matches.get_one::<String>("coin").unwrap(), "dogecoin");
+        assert_eq!(matches.get_count("verbosity"), 2);
+        let (_, sub) = matches.subcommand().unwrap();
+        assert_eq!(sub.get_one::<String>("dump-folder").unwrap(), "/tmp");
+
+        let matches = defaults.apply(command()).get_matches_from([
+            "rusty-blockparser",
+            "balancehistory",
+            "/tmp",
+            "1abc",
+        ]);
+        let (_, sub) = matches.subcommand().unwrap();
+        assert_eq!(sub.get_one::<String>("dump-folder").unwrap(), "/tmp");
+    }
+}
@@ -0,0 +1,358 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use byteorder::{LittleEndian, WriteBytesExt};
+use rusty_leveldb::{Options, DB};
+use tempfile::TempDir;
+
+use crate::blockchain::proto::block;
+use crate::blockchain::proto::varuint::VarUint;
+use crate::blockchain::proto::ToRaw;
+use crate::common::utils;
+
+const MAGIC: u32 = 0xd9b4bef9;
+const GENESIS_TIMESTAMP: u32 = 1231006505;
+const BITS: u32 = 0x207fffff;
+/// BLOCK_VALID_TRANSACTIONS | BLOCK_HAVE_DATA
+const BLOCK_STATUS: u64 = 3 | 8;
+const BLOCK_HAVE_UNDO: u64 = 16;
+/// Value of the OP_RETURN output in every spending transaction
+pub const OP_RETURN_DATA: &str = "rusty-blockparser";
+
+/// Generates a small chain with a single blk file and a block index in a temporary directory,
+/// so the parser and the callbacks can be tested end-to-end without a local copy of the blockchain.
+/// `--self-test` parses it as well, see `selftest`.
+///
+/// Each block contains a coinbase paying the block reward to a P2PKH address.
+/// From height 1 on, a second transaction spends the previous coinbase to a P2WPKH output,
+/// a P2PKH output and an OP_RETURN output.
+/// `generate_with_undo` also writes the undo data of these spends to a rev file.
+pub struct MiniChain {
+    pub dir: TempDir,
+    /// Block hashes by height
+    pub hashes: Vec<sha256d::Hash>,
+    /// Transaction ids by height
+    pub txids: Vec<Vec<sha256d::Hash>>,
+}
+
+impl MiniChain {
+    pub fn generate(n_blocks: u64) -> Self {
+        MiniChain::build(n_blocks, false)
+    }
+
+    /// Generates the chain with rev00000.dat, to be parsed with `--use-undo-files`
+    pub fn generate_with_undo(n_blocks: u64) -> Self {
+        MiniChain::build(n_blocks, true)
+    }
+
+    fn build(n_blocks: u64, with_undo: bool) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let mut blk_file = File::create(dir.path().join("blk00000.dat")).unwrap();
+        let mut rev_file = match with_undo {
+            true => Some(File::create(dir.path().join("rev00000.dat")).unwrap()),
+            false => None,
+        };
+        let mut chain = MiniChain {
+            dir,
+            hashes: Vec::new(),
+            txids: Vec::new(),
+        };
+
+        let mut index = Vec::new();
+        let mut offset = 0;
+        let mut undo_offset = 0;
+        for height in 0..n_blocks {
+            let txs = chain.build_txs(height);
+            let txids: Vec<sha256d::Hash> = txs.iter().map(|tx| sha256d::Hash::hash(tx)).collect();
+            let header = build_header(
+                chain
+                    .hashes
+                    .last()
+                    .copied()
+                    .unwrap_or(sha256d::Hash::all_zeros()),
+                utils::merkle_root(txids.clone()),
+                GENESIS_TIMESTAMP + height as u32 * 600,
+            );
+
+            let mut data = header.clone();
+            data.extend(VarUint::compact(txs.len() as u64).to_bytes());
+            txs.iter().for_each(|tx| data.extend(tx));
+            blk_file.write_u32::<LittleEndian>(MAGIC).unwrap();
+            blk_file
+                .write_u32::<LittleEndian>(data.len() as u32)
+                .unwrap();
+            blk_file.write_all(&data).unwrap();
+            offset += 8;
+
+            // The genesis coinbase can't be spent, so there is no undo data for it
+            let undo = match rev_file.as_mut() {
+                Some(rev_file) if height > 0 => {
+                    let data = build_block_undo(height);
+                    let prev_hash = chain.hashes[height as usize - 1];
+                    undo_offset += 8;
+                    let offset = undo_offset;
+                    write_undo_record(rev_file, &data, prev_hash);
+                    undo_offset += data.len() as u64 + 32;
+                    Some(offset)
+                }
+                _ => None,
+            };
+
+            let hash = sha256d::Hash::hash(&header);
+            index.push((
+                hash,
+                index_record(height, txs.len() as u64, offset, undo, &header),
+            ));
+            offset += data.len() as u64;
+            chain.hashes.push(hash);
+            chain.txids.push(txids);
+        }
+        write_index(&chain.dir.path().join("index"), &index);
+        chain
+    }
+
+    /// Returns the path to pass as `--blockchain-dir`
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    fn build_txs(&self, height: u64) -> Vec<Vec<u8>> {
+        let mut script_sig = vec![0x01, height as u8];
+        script_sig.extend(push(OP_RETURN_DATA.as_bytes()));
+        let coinbase = build_tx(
+            &[(sha256d::Hash::all_zeros(), 0xffffffff, script_sig)],
+            &[(block::get_base_reward(height), p2pkh(height as u8))],
+        );
+        if height == 0 {
+            return vec![coinbase];
+        }
+
+        // Spend the previous coinbase
+        let mut script_sig = push(&[0x30; 71]);
+        script_sig.extend(push(&[0x02; 33]));
+        let value = block::get_base_reward(height - 1);
+        let mut op_return = vec![0x6a];
+        op_return.extend(push(OP_RETURN_DATA.as_bytes()));
+        let spend = build_tx(
+            &[(self.txids[height as usize - 1][0], 0, script_sig)],
+            &[
+                (value / 2, p2wpkh(height as u8)),
+                (value / 2, p2pkh(0xff)),
+                (0, op_return),
+            ],
+        );
+        vec![coinbase, spend]
+    }
+}
+
+/// Returns the P2PKH script of the pubkey hash filled with `id`
+pub fn p2pkh(id: u8) -> Vec<u8> {
+    let mut script = vec![0x76, 0xa9, 0x14];
+    script.extend([id; 20]);
+    script.extend([0x88, 0xac]);
+    script
+}
+
+/// Returns the P2WPKH script of the pubkey hash filled with `id`
+pub fn p2wpkh(id: u8) -> Vec<u8> {
+    let mut script = vec![0x00, 0x14];
+    script.extend([id; 20]);
+    script
+}
+
+fn push(data: &[u8]) -> Vec<u8> {
+    let mut script = vec![data.len() as u8];
+    script.extend(data);
+    script
+}
+
+fn build_tx(inputs: &[(sha256d::Hash, u32, Vec<u8>)], outputs: &[(u64, Vec<u8>)]) -> Vec<u8> {
+    let mut tx = Vec::new();
+    tx.write_u32::<LittleEndian>(1).unwrap();
+    tx.extend(VarUint::compact(inputs.len() as u64).to_bytes());
+    for (txid, index, script_sig) in inputs {
+        tx.extend(txid.as_byte_array());
+        tx.write_u32::<LittleEndian>(*index).unwrap();
+        tx.extend(VarUint::compact(script_sig.len() as u64).to_bytes());
+        tx.extend(script_sig);
+        tx.write_u32::<LittleEndian>(0xffffffff).unwrap();
+    }
+    tx.extend(VarUint::compact(outputs.len() as u64).to_bytes());
+    for (value, script_pubkey) in outputs {
+        tx.write_u64::<LittleEndian>(*value).unwrap();
+        tx.extend(VarUint::compact(script_pubkey.len() as u64).to_bytes());
+        tx.extend(script_pubkey);
+    }
+    tx.write_u32::<LittleEndian>(0).unwrap();
+    tx
+}
+
+/// Builds a header and mines it, the regtest target is met by every second nonce on average
+fn build_header(prev_hash: sha256d::Hash, merkle_root: sha256d::Hash, timestamp: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(80);
+    header.write_u32::<LittleEndian>(1).unwrap();
+    header.extend(prev_hash.as_byte_array());
+    header.extend(merkle_root.as_byte_array());
+    header.write_u32::<LittleEndian>(timestamp).unwrap();
+    header.write_u32::<LittleEndian>(BITS).unwrap();
+    for nonce in 0.. {
+        header.truncate(76);
+        header.write_u32::<LittleEndian>(nonce).unwrap();
+        if utils::meets_target(sha256d::Hash::hash(&header).to_byte_array(), BITS) {
+            break;
+        }
+    }
+    header
+}
+
+/// Serializes a block index record as Bitcoin Core does (see CDiskBlockIndex)
+pub fn build_index_record(height: u64, tx_count: u64, data_offset: u64, header: &[u8]) -> Vec<u8> {
+    index_record(height, tx_count, data_offset, None, header)
+}
+
+fn index_record(
+    height: u64,
+    tx_count: u64,
+    data_offset: u64,
+    undo_offset: Option<u64>,
+    header: &[u8],
+) -> Vec<u8> {
+    let status = match undo_offset {
+        Some(_) => BLOCK_STATUS | BLOCK_HAVE_UNDO,
+        None => BLOCK_STATUS,
+    };
+    let mut record = Vec::new();
+    for value in [259900, height, status, tx_count, 0, data_offset] {
+        write_varint(&mut record, value);
+    }
+    if let Some(undo_offset) = undo_offset {
+        write_varint(&mut record, undo_offset);
+    }
+    record.extend(header);
+    record
+}
+
+/// Serializes the undo data of the block at the given height as Bitcoin Core does (see CBlockUndo).
+/// The only spent output is the P2PKH output of the previous coinbase.
+fn build_block_undo(height: u64) -> Vec<u8> {
+    let spent_height = height - 1;
+    let mut undo = Vec::new();
+    // One spending transaction with one spent output
+    undo.extend(VarUint::compact(1).to_bytes());
+    undo.extend(VarUint::compact(1).to_bytes());
+    write_varint(&mut undo, spent_height * 2 + 1);
+    if spent_height > 0 {
+        // Dummy version field
+        write_varint(&mut undo, 0);
+    }
+    write_varint(
+        &mut undo,
+        compress_amount(block::get_base_reward(spent_height)),
+    );
+    // Compressed P2PKH script
+    write_varint(&mut undo, 0x00);
+    undo.extend([spent_height as u8; 20]);
+    undo
+}
+
+/// Writes an undo record to a rev file, followed by the checksum over the previous block hash
+/// and the undo data
+fn write_undo_record(rev_file: &mut File, data: &[u8], prev_hash: sha256d::Hash) {
+    rev_file.write_u32::<LittleEndian>(MAGIC).unwrap();
+    rev_file
+        .write_u32::<LittleEndian>(data.len() as u32)
+        .unwrap();
+    rev_file.write_all(data).unwrap();
+    let mut engine = sha256d::Hash::engine();
+    engine.input(prev_hash.as_byte_array());
+    engine.input(data);
+    rev_file
+        .write_all(sha256d::Hash::from_engine(engine).as_byte_array())
+        .unwrap();
+}
+
+/// Compresses an amount as Bitcoin Core does, the inverse of `reader::decompress_amount`
+fn compress_amount(mut n: u64) -> u64 {
+    if n == 0 {
+        return 0;
+    }
+    let mut e = 0;
+    while n.is_multiple_of(10) && e < 9 {
+        n /= 10;
+        e += 1;
+    }
+    if e < 9 {
+        let d = n % 10;
+        n /= 10;
+        1 + (n * 9 + d - 1) * 10 + e
+    } else {
+        1 + (n - 1) * 10 + 9
+    }
+}
+
+fn write_index(path: &Path, records: &[(sha256d::Hash, Vec<u8>)]) {
+    let mut db = DB::open(path, Options::default()).unwrap();
+    for (hash, record) in records {
+        let mut key = vec![b'b'];
+        key.extend(hash.as_byte_array());
+        db.put(&key, record).unwrap();
+    }
+    db.flush().unwrap();
+}
+
+/// Writes a varint with the MSB base-128 encoding used in Bitcoin Core's serialize.h
+fn write_varint(buf: &mut Vec<u8>, value: u64) {
+    let mut tmp = Vec::new();
+    let mut n = value;
+    loop {
+        let mask = if tmp.is_empty() { 0x00 } else { 0x80 };
+        tmp.push((n & 0x7f) as u8 | mask);
+        if n <= 0x7f {
+            break;
+        }
+        n = (n >> 7) - 1;
+    }
+    buf.extend(tmp.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::parser::reader::{decompress_amount, BlockchainRead};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_compress_amount() {
+        for value in [
+            0,
+            1,
+            50,
+            5000000000,
+            2500000000,
+            123456789,
+            2_100_000_000_000_000,
+        ] {
+            assert_eq!(decompress_amount(compress_amount(value)), value);
+        }
+    }
+
+    #[test]
+    fn test_write_varint() {
+        for value in [
+            0,
+            0x7f,
+            0x80,
+            0x3fff,
+            0x4000,
+            259900,
+            u32::MAX as u64,
+            5 << 30,
+        ] {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            assert_eq!(Cursor::new(buf).read_varint().unwrap(), value);
+        }
+    }
+}
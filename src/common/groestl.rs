@@ -0,0 +1,171 @@
+//! Groestl-512 as specified in the final round submission to the SHA-3 competition,
+//! needed for Groestlcoin's address checksums.
+//! See: https://www.groestl.info/Groestl.pdf
+
+/// Number of 8 byte columns of the state
+const COLUMNS: usize = 16;
+/// Number of bytes of a message block
+const BLOCK_SIZE: usize = 8 * COLUMNS;
+const ROUNDS: u8 = 14;
+/// Left rotation of each row in ShiftBytes
+const SHIFT_P: [usize; 8] = [0, 1, 2, 3, 4, 5, 6, 11];
+const SHIFT_Q: [usize; 8] = [1, 3, 5, 11, 0, 2, 4, 6];
+/// First row of the circulant MixBytes matrix
+const MIX: [u8; 8] = [2, 2, 3, 4, 5, 3, 5, 7];
+
+#[rustfmt::skip]
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+/// Byte matrix with 8 rows, byte `i` of the serialized state is stored in row `i % 8` of column `i / 8`
+type State = [[u8; COLUMNS]; 8];
+
+/// Computes the Groestl-512 digest of the given data
+pub fn groestl512(data: &[u8]) -> [u8; 64] {
+    // The initial value is the digest size in bits
+    let mut chaining = [0u8; BLOCK_SIZE];
+    chaining[BLOCK_SIZE - 2..].copy_from_slice(&512u16.to_be_bytes());
+    let mut h = to_state(&chaining);
+
+    // Pad with a single bit, zeros and the number of blocks as 64-bit big-endian integer
+    let n_blocks = (data.len() + 9).div_ceil(BLOCK_SIZE);
+    let mut padded = Vec::with_capacity(n_blocks * BLOCK_SIZE);
+    padded.extend_from_slice(data);
+    padded.push(0x80);
+    padded.resize(n_blocks * BLOCK_SIZE - 8, 0);
+    padded.extend_from_slice(&(n_blocks as u64).to_be_bytes());
+
+    for block in padded.chunks_exact(BLOCK_SIZE) {
+        let m = to_state(block);
+        let mut p = xor(&h, &m);
+        let mut q = m;
+        permute(&mut p, false);
+        permute(&mut q, true);
+        h = xor(&xor(&p, &q), &h);
+    }
+
+    // Output transformation, truncated to the last 512 bits
+    let mut p = h;
+    permute(&mut p, false);
+    let out = from_state(&xor(&p, &h));
+    let mut digest = [0u8; 64];
+    digest.copy_from_slice(&out[BLOCK_SIZE - 64..]);
+    digest
+}
+
+/// Applies Groestl-512 twice and returns the first 32 bytes, as used by Groestlcoin
+pub fn groestl512d(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&groestl512(&groestl512(data))[..32]);
+    hash
+}
+
+/// Applies the permutation P or Q
+fn permute(state: &mut State, is_q: bool) {
+    let shift = if is_q { &SHIFT_Q } else { &SHIFT_P };
+    for round in 0..ROUNDS {
+        // AddRoundConstant
+        for (col, c) in (0u8..).zip(0..COLUMNS) {
+            if is_q {
+                for row in state.iter_mut().take(7) {
+                    row[c] ^= 0xff;
+                }
+                state[7][c] ^= (col << 4) ^ 0xff ^ round;
+            } else {
+                state[0][c] ^= (col << 4) ^ round;
+            }
+        }
+        // SubBytes and ShiftBytes
+        for (row, shift) in state.iter_mut().zip(shift) {
+            row.iter_mut().for_each(|b| *b = SBOX[*b as usize]);
+            row.rotate_left(*shift);
+        }
+        // MixBytes
+        for c in 0..COLUMNS {
+            let column: [u8; 8] = std::array::from_fn(|row| state[row][c]);
+            for (i, row) in state.iter_mut().enumerate() {
+                row[c] = (0..8).fold(0, |acc, k| acc ^ mul(MIX[(k + 8 - i) % 8], column[k]));
+            }
+        }
+    }
+}
+
+/// Multiplies in GF(2^8) with the AES polynomial
+fn mul(factor: u8, value: u8) -> u8 {
+    let double = |v: u8| (v << 1) ^ if v & 0x80 != 0 { 0x1b } else { 0 };
+    let mut result = 0;
+    let mut value = value;
+    let mut factor = factor;
+    while factor > 0 {
+        if factor & 1 == 1 {
+            result ^= value;
+        }
+        value = double(value);
+        factor >>= 1;
+    }
+    result
+}
+
+fn xor(a: &State, b: &State) -> State {
+    std::array::from_fn(|row| std::array::from_fn(|c| a[row][c] ^ b[row][c]))
+}
+
+fn to_state(bytes: &[u8]) -> State {
+    std::array::from_fn(|row| std::array::from_fn(|c| bytes[8 * c + row]))
+}
+
+fn from_state(state: &State) -> [u8; BLOCK_SIZE] {
+    std::array::from_fn(|i| state[i % 8][i / 8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::utils;
+
+    #[test]
+    fn test_groestl512() {
+        assert_eq!(
+            utils::arr_to_hex(&groestl512(&[])),
+            "6d3ad29d279110eef3adbd66de2a0345a77baede1557f5d099fce0c03d6dc2ba\
+             8e6d4a6633dfbd66053c20faa87d1a11f39a7fbe4a6c2f009801370308fc4ad8"
+        );
+    }
+
+    #[test]
+    fn test_groestl512d_genesis() {
+        // Header of the Groestlcoin genesis block
+        let mut header = Vec::with_capacity(80);
+        header.extend_from_slice(&112u32.to_le_bytes());
+        header.extend_from_slice(&[0u8; 32]);
+        let mut merkle_root =
+            utils::hex_to_vec("3ce968df58f9c8a752306c4b7264afab93149dbc578bd08a42c446caaa6628bb");
+        merkle_root.reverse();
+        header.extend_from_slice(&merkle_root);
+        header.extend_from_slice(&1395342829u32.to_le_bytes());
+        header.extend_from_slice(&0x1e0fffffu32.to_le_bytes());
+        header.extend_from_slice(&220035u32.to_le_bytes());
+        let mut hash = groestl512d(&header);
+        hash.reverse();
+        assert_eq!(
+            utils::arr_to_hex(&hash),
+            "00000ac5927c594d49cc0bdb81759d0da8297eb614683d3acb62f0703b639023"
+        );
+    }
+}
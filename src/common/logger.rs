@@ -1,21 +1,125 @@
-use chrono::{DateTime, Utc};
-use std::io::{stderr, stdout, Write};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, stderr, stdout, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::time::SystemTime;
 
 use log::{self, Level, LevelFilter, Metadata, Record, SetLoggerError};
 
+use crate::errors::{OpError, OpResult};
+
+/// Whether all log messages are written to stderr, e.g. if stdout is used for data
+static STDERR_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Writes all further log messages to stderr
+pub fn redirect_to_stderr() {
+    STDERR_ONLY.store(true, Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Settings of the optional log file
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogFileOptions {
+    pub path: PathBuf,
+    // The file is rotated once it would exceed this size in bytes
+    pub max_size: u64,
+    // Number of rotated files to keep (<path>.1 is the most recent one)
+    pub max_files: usize,
+    pub format: LogFormat,
+}
+
+/// Log file which is rotated by size
+struct RotatingFile {
+    options: LogFileOptions,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(options: &LogFileOptions) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&options.path)?;
+        Ok(Self {
+            size: file.metadata()?.len(),
+            file,
+            options: options.clone(),
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.options.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts <path>.1 .. <path>.n-1 to <path>.2 .. <path>.n, moves the current file to <path>.1
+    /// and starts a new one. The oldest file is overwritten.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |i: usize| {
+            let mut path = self.options.path.clone().into_os_string();
+            path.push(format!(".{}", i));
+            PathBuf::from(path)
+        };
+        if self.options.max_files > 0 {
+            for i in (1..self.options.max_files).rev() {
+                if rotated(i).exists() {
+                    fs::rename(rotated(i), rotated(i + 1))?;
+                }
+            }
+            fs::rename(&self.options.path, rotated(1))?;
+        }
+        self.file = File::create(&self.options.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
 pub struct SimpleLogger {
     level_filter: LevelFilter,
+    file: Option<Mutex<RotatingFile>>,
 }
 
 impl SimpleLogger {
     pub fn init(level_filter: LevelFilter) -> Result<(), SetLoggerError> {
-        let logger = SimpleLogger { level_filter };
+        let logger = SimpleLogger {
+            level_filter,
+            file: None,
+        };
         log::set_boxed_logger(Box::new(logger))?;
         log::set_max_level(level_filter);
         Ok(())
     }
 
+    /// Logs to the console as usual and additionally to the given file
+    pub fn init_with_file(level_filter: LevelFilter, options: &LogFileOptions) -> OpResult<()> {
+        let file = RotatingFile::open(options).map_err(|err| {
+            OpError::from(err).join_msg(&format!(
+                "Unable to open log file {}:",
+                options.path.display()
+            ))
+        })?;
+        let logger = SimpleLogger {
+            level_filter,
+            file: Some(Mutex::new(file)),
+        };
+        log::set_boxed_logger(Box::new(logger)).map_err(|err| OpError::from(err.to_string()))?;
+        log::set_max_level(level_filter);
+        Ok(())
+    }
+
     fn format_log(&self, record: &Record) -> String {
         let datetime: DateTime<Utc> = SystemTime::now().into();
         format!(
@@ -26,6 +130,42 @@ impl SimpleLogger {
             record.args()
         )
     }
+
+    /// Formats the record for the log file, which contains the date as well
+    fn format_file_log(record: &Record, format: LogFormat) -> String {
+        let datetime: DateTime<Utc> = SystemTime::now().into();
+        match format {
+            LogFormat::Text => format!(
+                "[{}] {} - {}: {}\n",
+                datetime.format("%F %T"),
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+            LogFormat::Json => format!(
+                "{{\"time\":\"{}\",\"level\":\"{}\",\"target\":\"{}\",\"message\":\"{}\"}}\n",
+                datetime.to_rfc3339_opts(SecondsFormat::Millis, true),
+                record.level(),
+                json_escape(record.target()),
+                json_escape(&record.args().to_string())
+            ),
+        }
+    }
+}
+
+/// Escapes quotes, backslashes and control characters for a JSON string
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
 }
 
 impl log::Log for SimpleLogger {
@@ -37,6 +177,9 @@ impl log::Log for SimpleLogger {
         if self.enabled(record.metadata()) {
             let line = self.format_log(record);
             match record.level() {
+                _ if STDERR_ONLY.load(Ordering::Relaxed) => {
+                    stderr().write_all(line.as_bytes()).unwrap();
+                }
                 Level::Error => {
                     stderr().write_all(line.as_bytes()).unwrap();
                 }
@@ -44,8 +187,57 @@ impl log::Log for SimpleLogger {
                     stdout().write_all(line.as_bytes()).unwrap();
                 }
             }
+            if let Some(file) = &self.file {
+                let mut file = file.lock().unwrap();
+                let line = Self::format_file_log(record, file.options.format);
+                if let Err(err) = file.write_line(&line) {
+                    stderr()
+                        .write_all(format!("Unable to write log file: {}\n", err).as_bytes())
+                        .unwrap();
+                }
+            }
         }
     }
 
     fn flush(&self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_escape() {
+        assert_eq!(json_escape("Done."), "Done.");
+        assert_eq!(
+            json_escape("\n\nInvalid \"block\"\t\\"),
+            "\\n\\nInvalid \\\"block\\\"\\u0009\\\\"
+        );
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = LogFileOptions {
+            path: dir.path().join("parser.log"),
+            max_size: 10,
+            max_files: 2,
+            format: LogFormat::Text,
+        };
+        let mut file = RotatingFile::open(&options).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_line(line).unwrap();
+        }
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("parser.log"), "fourth\n");
+        assert_eq!(read("parser.log.1"), "third\n");
+        assert_eq!(read("parser.log.2"), "second\n");
+        assert!(!dir.path().join("parser.log.3").exists());
+
+        // Appends to an existing file
+        let mut file = RotatingFile::open(&options).unwrap();
+        assert_eq!(file.size, 7);
+        file.write_line("5\n").unwrap();
+        assert_eq!(read("parser.log"), "fourth\n5\n");
+    }
+}
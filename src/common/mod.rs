@@ -1,2 +1,11 @@
+pub mod batch;
+pub mod completions;
+pub mod defaults;
+#[cfg(any(test, feature = "self-test"))]
+pub mod fixtures;
+pub mod groestl;
 pub mod logger;
+pub mod quantile;
+#[cfg(any(test, feature = "self-test"))]
+pub mod selftest;
 pub mod utils;
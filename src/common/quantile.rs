@@ -0,0 +1,166 @@
+/// Streaming estimate of a single quantile with the P² algorithm (Jain and Chlamtac, 1985).
+/// Only five markers are kept, so the memory is constant regardless of the number of values.
+/// The estimate is exact for up to five values and usually within a fraction of a percent
+/// of the true quantile for large, smooth distributions.
+pub struct P2Quantile {
+    p: f64,
+    count: u64,
+    // Marker heights, actual and desired positions, and the increments of the desired positions
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    /// Creates an estimator for the given quantile, e.g. 0.95 for the 95th percentile
+    pub fn new(p: f64) -> Self {
+        P2Quantile {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    pub fn add(&mut self, x: f64) {
+        if self.count < 5 {
+            self.heights[self.count as usize] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_unstable_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        // Find the cell of x and extend the extreme markers if needed
+        let k = if x < self.heights[0] {
+            self.heights[0] = x;
+            0
+        } else if x >= self.heights[4] {
+            self.heights[4] = x;
+            3
+        } else {
+            (1..5).find(|i| x < self.heights[*i]).unwrap() - 1
+        };
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        // Move the middle markers towards their desired positions
+        for i in 1..4 {
+            let d = self.desired[i] - self.positions[i];
+            if (d >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0)
+                || (d <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0)
+            {
+                let d = d.signum();
+                let height = self.parabolic(i, d);
+                self.heights[i] = match self.heights[i - 1] < height && height < self.heights[i + 1]
+                {
+                    true => height,
+                    false => self.linear(i, d),
+                };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let j = (i as f64 + d) as usize;
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+    }
+
+    /// Returns the estimated quantile, None if no values were added
+    pub fn estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            1..=4 => {
+                // Exact nearest-rank quantile of the few values seen so far
+                let mut values = self.heights[..self.count as usize].to_vec();
+                values.sort_unstable_by(f64::total_cmp);
+                let rank = (self.p * self.count as f64).ceil().max(1.0) as usize;
+                Some(values[rank - 1])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+}
+
+/// Streaming summary of a series of values: mean, median and 95th percentile
+pub struct Distribution {
+    count: u64,
+    sum: f64,
+    median: P2Quantile,
+    p95: P2Quantile,
+}
+
+impl Default for Distribution {
+    fn default() -> Self {
+        Distribution {
+            count: 0,
+            sum: 0.0,
+            median: P2Quantile::new(0.5),
+            p95: P2Quantile::new(0.95),
+        }
+    }
+}
+
+impl Distribution {
+    pub fn add(&mut self, x: f64) {
+        self.count += 1;
+        self.sum += x;
+        self.median.add(x);
+        self.p95.add(x);
+    }
+
+    /// Returns the mean, NaN if no values were added
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+
+    pub fn median(&self) -> f64 {
+        self.median.estimate().unwrap_or(f64::NAN)
+    }
+
+    pub fn p95(&self) -> f64 {
+        self.p95.estimate().unwrap_or(f64::NAN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_p2_quantile() {
+        let mut median = P2Quantile::new(0.5);
+        assert_eq!(median.estimate(), None);
+        for x in [3.0, 1.0, 2.0] {
+            median.add(x);
+        }
+        assert_eq!(median.estimate(), Some(2.0));
+
+        // 1..=10007 in a scrambled order
+        let mut distribution = Distribution::default();
+        for i in 1..=10007u64 {
+            distribution.add(((i * 7919) % 10007 + 1) as f64);
+        }
+        assert_eq!(distribution.mean(), 5004.0);
+        assert!((distribution.median() - 5004.0).abs() < 50.0);
+        assert!((distribution.p95() - 9507.0).abs() < 50.0);
+    }
+}
@@ -0,0 +1,163 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use crate::blockchain::parser::chain::ChainStorage;
+use crate::blockchain::parser::BlockchainParser;
+use crate::callbacks::simplestats::SimpleStats;
+use crate::callbacks::Callback;
+use crate::common::fixtures::MiniChain;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::{command, parse_args};
+
+/// Number of blocks of the generated chain the golden files were recorded with
+const N_BLOCKS: u64 = 20;
+
+/// Expected output by file name, the dumps are named `<name>-0-<N_BLOCKS - 1>.csv`
+const GOLDEN: [(&str, &str); 6] = [
+    ("balances", include_str!("golden/balances.csv")),
+    ("blocks", include_str!("golden/blocks.csv")),
+    ("transactions", include_str!("golden/transactions.csv")),
+    ("tx_in", include_str!("golden/tx_in.csv")),
+    ("tx_out", include_str!("golden/tx_out.csv")),
+    ("simplestats", include_str!("golden/simplestats.txt")),
+];
+
+/// Runs balances, csvdump and simplestats over a generated chain and compares their output
+/// byte by byte with the golden files in `src/common/golden`.
+/// Returns false if any output differs, the outputs are kept for inspection then.
+pub fn run() -> OpResult<bool> {
+    let chain = MiniChain::generate(N_BLOCKS);
+    let dump_dir = env::temp_dir().join(format!("rusty-blockparser-selftest-{}", process::id()));
+    fs::create_dir_all(&dump_dir)?;
+    dump(chain.path(), &dump_dir)?;
+
+    let mut passed = true;
+    for (name, expected) in GOLDEN {
+        let expected = normalize(name, expected);
+        let actual = normalize(name, &read_output(&dump_dir, name)?);
+        match first_difference(&expected, &actual) {
+            Some((line, expected, actual)) => {
+                error!(target: "selftest", "{}: line {} differs\n  expected: {}\n  actual:   {}",
+                       name, line, expected, actual);
+                passed = false;
+            }
+            None => debug!(target: "selftest", "{}: ok", name),
+        }
+    }
+    match passed {
+        true => fs::remove_dir_all(&dump_dir)?,
+        false => warn!(target: "selftest", "Kept the outputs in {}", dump_dir.display()),
+    }
+    Ok(passed)
+}
+
+/// Writes the outputs of all checked callbacks into `dump_folder`
+fn dump(blockchain_dir: &Path, dump_folder: &Path) -> OpResult<()> {
+    let blockchain_dir = blockchain_dir.to_str().unwrap();
+    let dump_folder_str = dump_folder.to_str().unwrap();
+    for callback in ["balances", "csvdump"] {
+        let args = [
+            "rusty-blockparser",
+            "-d",
+            blockchain_dir,
+            callback,
+            dump_folder_str,
+        ];
+        let options = parse_args(command().get_matches_from(args))?;
+        let chain_storage = ChainStorage::new(&options)?;
+        BlockchainParser::new(options, chain_storage).start()?;
+    }
+
+    // simplestats only logs its report, so the blocks are passed directly
+    let args = ["rusty-blockparser", "-d", blockchain_dir, "simplestats"];
+    let options = parse_args(command().get_matches_from(args))?;
+    let mut chain_storage = ChainStorage::new(&options)?;
+    let mut stats = SimpleStats::default();
+    stats.on_start(0)?;
+    for height in 0..N_BLOCKS {
+        match chain_storage.get_block(height) {
+            Some(Ok(block)) => stats.on_block(&block, height)?,
+            Some(Err(corrupt)) => return Err(corrupt.error),
+            None => {
+                let msg = format!("Block {} of the generated chain not found", height);
+                return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+            }
+        }
+    }
+    fs::write(dump_folder.join("simplestats.txt"), stats.report()?)?;
+    Ok(())
+}
+
+/// Reads the output with the given name
+fn read_output(dump_folder: &Path, name: &str) -> OpResult<String> {
+    let path = match name {
+        "simplestats" => dump_folder.join("simplestats.txt"),
+        _ => dump_folder.join(format!("{}-0-{}.csv", name, N_BLOCKS - 1)),
+    };
+    Ok(fs::read_to_string(path)?)
+}
+
+/// The rows of balances have no defined order, so they are sorted below the header
+fn normalize(name: &str, content: &str) -> String {
+    if name != "balances" {
+        return content.to_string();
+    }
+    let mut lines: Vec<&str> = content.lines().collect();
+    if lines.len() > 1 {
+        lines[1..].sort_unstable();
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Returns the first differing line number (starting at 1) with the expected and actual line
+fn first_difference<'a>(expected: &'a str, actual: &'a str) -> Option<(usize, &'a str, &'a str)> {
+    if expected == actual {
+        return None;
+    }
+    let mut expected_lines = expected.split_inclusive('\n');
+    let mut actual_lines = actual.split_inclusive('\n');
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => continue,
+            (e, a) => {
+                let trim = |l: Option<&'a str>| l.map_or("<end of file>", |l| l.trim_end());
+                return Some((line, trim(e), trim(a)));
+            }
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_difference() {
+        assert_eq!(first_difference("a\nb\n", "a\nb\n"), None);
+        assert_eq!(first_difference("a\nb\n", "a\nc\n"), Some((2, "b", "c")));
+        assert_eq!(
+            first_difference("a\nb\n", "a\n"),
+            Some((2, "b", "<end of file>"))
+        );
+        // A missing trailing newline is a difference as well
+        assert_eq!(first_difference("a\n", "a"), Some((1, "a", "a")));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let balances = "address;balance\nb;2\na;1\n";
+        assert_eq!(
+            normalize("balances", balances),
+            "address;balance\na;1\nb;2\n"
+        );
+        assert_eq!(normalize("blocks", balances), balances);
+    }
+
+    #[test]
+    fn test_self_test() {
+        assert!(run().unwrap());
+    }
+}
@@ -1,35 +1,127 @@
-use bitcoin::hashes::{sha256d, Hash};
-use std::path::PathBuf;
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::pow::{CompactTarget, Target, Work};
+use chrono::{NaiveDate, TimeZone, Utc};
+use rayon::prelude::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
 
 use crate::blockchain::parser::types::CoinType;
+use crate::errors::{OpError, OpErrorKind, OpResult};
 
-/// Calculates merkle root for the whole block
+/// Number of hashes per tree level from which on `merkle_root_par` hashes the level in parallel
+const PAR_MERKLE_THRESHOLD: usize = 1024;
+
+/// Calculates merkle root for the whole block.
+/// The given hashes are used as scratch buffer, so no allocations take place.
 /// See: https://en.bitcoin.it/wiki/Protocol_documentation#Merkle_Trees
 pub fn merkle_root(hashes: Vec<sha256d::Hash>) -> sha256d::Hash {
     let mut hashes = hashes;
-
     while hashes.len() > 1 {
-        // Calculates double sha hash for each pair. If len is odd, last value is ignored.
-        let mut new_hashes = hashes
-            .chunks(2)
-            .filter(|c| c.len() == 2)
-            .map(|c| sha256d::Hash::hash(&[c[0], c[1]].concat()))
-            .collect::<Vec<sha256d::Hash>>();
-
-        // If the length is odd, take the last hash twice
-        if hashes.len() % 2 == 1 {
-            let last_hash = hashes.last().unwrap();
-            new_hashes.push(sha256d::Hash::hash(
-                &[&last_hash[..], &last_hash[..]].concat(),
-            ));
-        }
-        hashes = new_hashes;
+        merkle_parents(&mut hashes);
     }
     *hashes
         .first()
         .expect("unable to calculate merkle root on empty hashes")
 }
 
+/// Same as `merkle_root`, but hashes large tree levels in parallel.
+/// Used to verify blocks with thousands of transactions.
+pub fn merkle_root_par(hashes: Vec<sha256d::Hash>) -> sha256d::Hash {
+    let mut hashes = hashes;
+    let mut parents = Vec::with_capacity(hashes.len().div_ceil(2));
+    while hashes.len() > PAR_MERKLE_THRESHOLD {
+        // If len is odd, the last chunk consists of a single hash which is taken twice
+        hashes
+            .par_chunks(2)
+            .map(|c| merkle_hash(&c[0], c.last().unwrap()))
+            .collect_into_vec(&mut parents);
+        mem::swap(&mut hashes, &mut parents);
+    }
+    merkle_root(hashes)
+}
+
+/// Calculates the merkle branch for the hash at the given index.
+/// Returns the sibling hashes from bottom to top along with a flag
+/// whether the sibling is on the right side.
+pub fn merkle_branch(hashes: &[sha256d::Hash], index: usize) -> Vec<(sha256d::Hash, bool)> {
+    let mut hashes = hashes.to_vec();
+    let mut index = index;
+    let mut branch = Vec::new();
+
+    while hashes.len() > 1 {
+        let is_left = index % 2 == 1;
+        let sibling = match is_left {
+            true => &hashes[index - 1],
+            false => hashes.get(index + 1).unwrap_or(&hashes[index]),
+        };
+        branch.push((*sibling, !is_left));
+        merkle_parents(&mut hashes);
+        index /= 2;
+    }
+    branch
+}
+
+/// Calculates the merkle root from a leaf hash and its branch
+pub fn merkle_root_from_branch(
+    hash: sha256d::Hash,
+    branch: &[(sha256d::Hash, bool)],
+) -> sha256d::Hash {
+    branch
+        .iter()
+        .fold(hash, |hash, (sibling, is_right)| match is_right {
+            true => merkle_hash(&hash, sibling),
+            false => merkle_hash(sibling, &hash),
+        })
+}
+
+/// Calculates the next level of the merkle tree in place.
+/// Each parent is written to the slot of its left child, which has already been consumed.
+fn merkle_parents(hashes: &mut Vec<sha256d::Hash>) {
+    let n_parents = hashes.len().div_ceil(2);
+    for i in 0..n_parents {
+        // If len is odd, take the last hash twice
+        let right = hashes.get(2 * i + 1).unwrap_or(&hashes[2 * i]);
+        hashes[i] = merkle_hash(&hashes[2 * i], right);
+    }
+    hashes.truncate(n_parents);
+}
+
+/// Double sha256 of the concatenation of both hashes
+fn merkle_hash(left: &sha256d::Hash, right: &sha256d::Hash) -> sha256d::Hash {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(&left[..]);
+    engine.input(&right[..]);
+    sha256d::Hash::from_engine(engine)
+}
+
+/// Converts the compact target representation (nBits) to the difficulty,
+/// relative to the maximum target 0x1d00ffff
+pub fn bits_to_difficulty(bits: u32) -> f64 {
+    let exponent = (bits >> 24) as i32;
+    let mantissa = (bits & 0x00ffffff) as f64;
+    if mantissa == 0.0 {
+        return 0.0;
+    }
+    (0xffff as f64 / mantissa) * 256f64.powi(0x1d - exponent)
+}
+
+/// Returns the expected number of hashes to find a block with the given bits
+pub fn bits_to_work(bits: u32) -> Work {
+    Target::from_compact(CompactTarget::from_consensus(bits)).to_work()
+}
+
+/// Returns true if the hash (in little endian byte order) is within the target encoded in bits
+pub fn meets_target(hash: [u8; 32], bits: u32) -> bool {
+    Target::from_le_bytes(hash) <= Target::from_compact(CompactTarget::from_consensus(bits))
+}
+
+/// Formats the work as 64 hex chars like the `chainwork` field of bitcoind's RPC
+pub fn work_to_hex(work: Work) -> String {
+    arr_to_hex(&work.to_be_bytes())
+}
+
 pub fn arr_to_hex(data: &[u8]) -> String {
     data.iter().map(|b| format!("{:02x?}", b)).collect()
 }
@@ -52,6 +144,157 @@ pub fn get_absolute_blockchain_dir(coin: &CoinType) -> PathBuf {
         .join(&coin.default_folder)
 }
 
+/// Resolves a directory given on the command line.
+/// On Windows forward slashes are converted, UNC paths (e.g. `\\server\share\blocks`) are used as is.
+/// Elsewhere paths with a drive letter (e.g. `k:\temp\blocks`) are mapped to
+/// the mount points of WSL (`/mnt/k/temp/blocks`) or Cygwin (`/cygdrive/k/temp/blocks`).
+pub fn resolve_dir(path: &str) -> PathBuf {
+    if cfg!(windows) {
+        return PathBuf::from(path.replace('/', "\\"));
+    }
+    if let Some((drive, rest)) = split_drive_path(path) {
+        for mount in ["/mnt", "/cygdrive"] {
+            let candidate = Path::new(mount).join(&drive).join(&rest);
+            if candidate.is_dir() {
+                return candidate;
+            }
+        }
+    }
+    PathBuf::from(path)
+}
+
+/// Splits a path with a drive letter into the lowercase drive and the remaining path with forward slashes
+fn split_drive_path(path: &str) -> Option<(String, String)> {
+    let mut chars = path.chars();
+    let drive = chars.next().filter(|c| c.is_ascii_alphabetic())?;
+    if chars.next() != Some(':') {
+        return None;
+    }
+    let rest = chars.as_str();
+    if !rest.is_empty() && !rest.starts_with(['\\', '/']) {
+        return None;
+    }
+    let rest = rest.trim_start_matches(['\\', '/']).replace('\\', "/");
+    Some((drive.to_ascii_lowercase().to_string(), rest))
+}
+
+/// Returns an error if the given directory does not exist
+pub fn check_dir(path: &Path) -> OpResult<()> {
+    if path.is_dir() {
+        return Ok(());
+    }
+    let mut msg = format!("Directory {} does not exist", path.display());
+    if !cfg!(windows) && path.to_string_lossy().starts_with("\\\\") {
+        msg.push_str(" (UNC paths are only supported on Windows, mount the share instead)");
+    }
+    Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg))
+}
+
+/// Parses a date in YYYY-MM-DD format and returns the unix timestamp of its start (UTC)
+pub fn parse_date(date: &str) -> Result<u32, String> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| format!("invalid date '{}', expected YYYY-MM-DD: {}", date, e))?;
+    let timestamp = Utc
+        .from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+        .timestamp();
+    u32::try_from(timestamp).map_err(|_| format!("date {} is out of range", date))
+}
+
+/// Parses an inclusive range of blk file indexes like `100-150`, a single index is also accepted
+pub fn parse_blk_range(range: &str) -> Result<(u64, u64), String> {
+    let parse = |index: &str| {
+        index
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| format!("invalid blk file index '{}': {}", index, e))
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(range)?, parse(range)?),
+    };
+    if start > end {
+        return Err(format!(
+            "invalid blk file range '{}', start is after end",
+            range
+        ));
+    }
+    Ok((start, end))
+}
+
+/// Parses a shard given as `i/N`, i.e. the i-th (starting at 1) of N shards
+pub fn parse_shard(shard: &str) -> Result<(u64, u64), String> {
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<u64>()
+            .map_err(|e| format!("invalid shard '{}': {}", shard, e))
+    };
+    let (index, count) = match shard.split_once('/') {
+        Some((index, count)) => (parse(index)?, parse(count)?),
+        None => return Err(format!("invalid shard '{}', expected i/N", shard)),
+    };
+    if index == 0 || index > count {
+        return Err(format!(
+            "invalid shard '{}', i must be between 1 and N",
+            shard
+        ));
+    }
+    Ok((index, count))
+}
+
+/// Parses the 8 byte key blk and rev files are obfuscated with, given as 16 hex characters
+pub fn parse_xor_key(hex: &str) -> Result<[u8; 8], String> {
+    if hex.len() != 16 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "invalid xor key '{}', expected 16 hex characters",
+            hex
+        ));
+    }
+    Ok(hex_to_vec(hex).try_into().unwrap())
+}
+
+/// Reads a file with one block height per line. Empty lines and lines starting with `#` are ignored.
+pub fn read_heights(path: &Path) -> OpResult<BTreeSet<u64>> {
+    let mut heights = BTreeSet::new();
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let height = line.parse::<u64>().map_err(|e| {
+            let msg = format!(
+                "{}:{}: invalid height '{}': {}",
+                path.display(),
+                i + 1,
+                line,
+                e
+            );
+            OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg)
+        })?;
+        heights.insert(height);
+    }
+    if heights.is_empty() {
+        let msg = format!("{} contains no block heights", path.display());
+        return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+    }
+    Ok(heights)
+}
+
+/// Returns the month of the given unix timestamp in YYYY-MM format (UTC)
+pub fn month_of(timestamp: u32) -> String {
+    Utc.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|t| t.format("%Y-%m").to_string())
+        .unwrap_or_default()
+}
+
+/// Returns the day of the given unix timestamp in YYYY-MM-DD format (UTC)
+pub fn day_of(timestamp: u32) -> String {
+    Utc.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
 /// Get mean value from u32 slice
 pub fn get_mean(slice: &[u32]) -> f64 {
     if slice.is_empty() {
@@ -61,10 +304,117 @@ pub fn get_mean(slice: &[u32]) -> f64 {
     sum as f64 / slice.len() as f64
 }
 
+/// Returns the value at the given percentile (0-100) of an ascending sorted slice (nearest-rank method)
+pub fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_percentile() {
+        let values = [15.0, 20.0, 35.0, 40.0, 50.0];
+        assert_eq!(percentile(&values, 0.0), Some(15.0));
+        assert_eq!(percentile(&values, 25.0), Some(20.0));
+        assert_eq!(percentile(&values, 50.0), Some(35.0));
+        assert_eq!(percentile(&values, 100.0), Some(50.0));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2009-01-03"), Ok(1230940800));
+        assert_eq!(month_of(1230940800), "2009-01");
+        assert_eq!(day_of(1231006505), "2009-01-03");
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert!(parse_date("2009-13-01").is_err());
+        assert!(parse_date("03.01.2009").is_err());
+    }
+
+    #[test]
+    fn test_parse_blk_range() {
+        assert_eq!(parse_blk_range("100-150"), Ok((100, 150)));
+        assert_eq!(parse_blk_range("7"), Ok((7, 7)));
+        assert!(parse_blk_range("150-100").is_err());
+        assert!(parse_blk_range("a-b").is_err());
+    }
+
+    #[test]
+    fn test_parse_shard() {
+        assert_eq!(parse_shard("1/4"), Ok((1, 4)));
+        assert_eq!(parse_shard("4/4"), Ok((4, 4)));
+        assert!(parse_shard("0/4").is_err());
+        assert!(parse_shard("5/4").is_err());
+        assert!(parse_shard("4").is_err());
+    }
+
+    #[test]
+    fn test_parse_xor_key() {
+        assert_eq!(
+            parse_xor_key("00ff00ff00ff00ff"),
+            Ok([0, 0xff, 0, 0xff, 0, 0xff, 0, 0xff])
+        );
+        assert!(parse_xor_key("00ff").is_err());
+        assert!(parse_xor_key("zzff00ff00ff00ff").is_err());
+    }
+
+    #[test]
+    fn test_read_heights() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heights.txt");
+        fs::write(
+            &path,
+            "# damaged
+3
+
+1
+3
+",
+        )
+        .unwrap();
+        assert_eq!(read_heights(&path).unwrap(), BTreeSet::from([1, 3]));
+
+        fs::write(
+            &path, "1
+abc
+",
+        )
+        .unwrap();
+        assert!(read_heights(&path).is_err());
+        fs::write(
+            &path,
+            "# nothing
+",
+        )
+        .unwrap();
+        assert!(read_heights(&path).is_err());
+    }
+
+    #[test]
+    fn test_split_drive_path() {
+        assert_eq!(
+            split_drive_path("k:\\temp\\rustyin"),
+            Some((String::from("k"), String::from("temp/rustyin")))
+        );
+        assert_eq!(
+            split_drive_path("C:/Users/foo/AppData"),
+            Some((String::from("c"), String::from("Users/foo/AppData")))
+        );
+        assert_eq!(
+            split_drive_path("D:"),
+            Some((String::from("d"), String::new()))
+        );
+        assert_eq!(split_drive_path("/home/foo/.bitcoin"), None);
+        assert_eq!(split_drive_path("k:temp"), None);
+        assert_eq!(split_drive_path("\\\\server\\share"), None);
+    }
+
     #[test]
     fn test_arr_to_hex() {
         let test = [
@@ -76,9 +426,8 @@ mod tests {
         assert_eq!(arr_to_hex(&test), expected);
     }
 
-    #[test]
-    fn test_merkle_root() {
-        let hashes = Vec::from([
+    fn merkle_hashes() -> Vec<sha256d::Hash> {
+        Vec::from([
             sha256d::Hash::from_byte_array([
                 0x8c, 0xb1, 0xdf, 0x74, 0xdb, 0xe9, 0x80, 0xc6, 0xb9, 0x20, 0x2e, 0x91, 0x95, 0x97,
                 0xa5, 0xea, 0xbe, 0xb2, 0xd3, 0x2e, 0x4d, 0xe0, 0x21, 0x4a, 0x39, 0xf8, 0x0c, 0x5f,
@@ -109,15 +458,76 @@ mod tests {
                 0x99, 0x6b, 0x74, 0xe3, 0xd0, 0x2f, 0xbf, 0xa1, 0xaf, 0xd3, 0x9f, 0x65, 0xfe, 0x91,
                 0xba, 0x3c, 0x05, 0x84,
             ]),
-        ]);
+        ])
+    }
 
+    #[test]
+    fn test_merkle_root() {
+        let hashes = merkle_hashes();
         let expected = sha256d::Hash::from_byte_array([
             0x52, 0xed, 0x57, 0x8c, 0xb6, 0xed, 0x9a, 0xe5, 0xf5, 0x31, 0x6d, 0x45, 0x42, 0x9b,
             0xf6, 0x9c, 0xfd, 0xde, 0x2b, 0xe3, 0x94, 0x97, 0xba, 0x31, 0x57, 0x01, 0x64, 0xeb,
             0x22, 0x77, 0xdf, 0x9c,
         ]);
 
-        let merkle_hash = merkle_root(hashes);
+        let merkle_hash = merkle_root(hashes.clone());
         assert_eq!(merkle_hash, expected);
+        assert_eq!(merkle_root_par(hashes), expected);
+
+        // Odd number of hashes on several levels above the parallel threshold
+        let hashes = (0..3001u32)
+            .map(|i| sha256d::Hash::hash(&i.to_le_bytes()))
+            .collect::<Vec<_>>();
+        assert_eq!(merkle_root_par(hashes.clone()), merkle_root(hashes));
+    }
+
+    #[test]
+    fn test_meets_target() {
+        let mut genesis_hash = [0u8; 32];
+        genesis_hash.copy_from_slice(&hex_to_vec(
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        ));
+        genesis_hash.reverse();
+        assert!(meets_target(genesis_hash, 0x1d00ffff));
+        assert!(!meets_target(genesis_hash, 0x1b0404cb));
+        genesis_hash[28] = 0x01;
+        assert!(!meets_target(genesis_hash, 0x1d00ffff));
+    }
+
+    #[test]
+    fn test_bits_to_work() {
+        let work = bits_to_work(0x1d00ffff);
+        assert_eq!(
+            work_to_hex(work),
+            "0000000000000000000000000000000000000000000000000000000100010001"
+        );
+        assert_eq!(
+            work_to_hex(work + work),
+            "0000000000000000000000000000000000000000000000000000000200020002"
+        );
+    }
+
+    #[test]
+    fn test_bits_to_difficulty() {
+        assert_eq!(bits_to_difficulty(0x1d00ffff), 1.0);
+        assert!((bits_to_difficulty(0x1b0404cb) - 16307.420938523983).abs() < 1e-6);
+        assert!((bits_to_difficulty(0x170331db) - 88104191118793.16).abs() < 1.0);
+        assert_eq!(bits_to_difficulty(0x1d000000), 0.0);
+    }
+
+    #[test]
+    fn test_merkle_branch() {
+        let hashes = merkle_hashes();
+        let root = merkle_root(hashes.clone());
+        for (index, hash) in hashes.iter().enumerate() {
+            let branch = merkle_branch(&hashes, index);
+            assert_eq!(branch.len(), 3);
+            assert_eq!(merkle_root_from_branch(*hash, &branch), root);
+        }
+        // Last hash is paired with itself
+        let branch = merkle_branch(&hashes, 5);
+        let parent = sha256d::Hash::hash(&[hashes[4], hashes[5]].concat());
+        assert_eq!(branch[1], (parent, true));
+        assert!(merkle_branch(&hashes[..1], 0).is_empty());
     }
 }
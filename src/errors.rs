@@ -84,6 +84,12 @@ pub enum OpErrorKind {
     PoisonError,
     SendError,
     LevelDBError(String),
+    #[cfg(feature = "kafka")]
+    KafkaError(rdkafka::error::KafkaError),
+    #[cfg(feature = "s3")]
+    S3Error(String),
+    #[cfg(feature = "postgres")]
+    PostgresError(postgres::Error),
 }
 
 impl fmt::Display for OpErrorKind {
@@ -94,12 +100,18 @@ impl fmt::Display for OpErrorKind {
             OpErrorKind::Utf8Error(ref err) => write!(f, "Utf8 Conversion: {}", err),
             OpErrorKind::ScriptError(ref err) => write!(f, "Script: {}", err),
             OpErrorKind::LevelDBError(ref err) => write!(f, "LevelDB: {}", err),
-            ref err @ OpErrorKind::PoisonError => write!(f, "Threading Error: {}", err),
-            ref err @ OpErrorKind::SendError => write!(f, "Sync: {}", err),
-            ref err @ OpErrorKind::InvalidArgsError => write!(f, "InvalidArgs: {}", err),
-            ref err @ OpErrorKind::CallbackError => write!(f, "Callback: {}", err),
-            ref err @ OpErrorKind::ValidationError => write!(f, "Validation: {}", err),
-            ref err @ OpErrorKind::RuntimeError => write!(f, "RuntimeError: {}", err),
+            #[cfg(feature = "kafka")]
+            OpErrorKind::KafkaError(ref err) => write!(f, "Kafka: {}", err),
+            #[cfg(feature = "s3")]
+            OpErrorKind::S3Error(ref err) => write!(f, "S3: {}", err),
+            #[cfg(feature = "postgres")]
+            OpErrorKind::PostgresError(ref err) => write!(f, "Postgres: {}", err),
+            OpErrorKind::PoisonError => write!(f, "(Threading Error)"),
+            OpErrorKind::SendError => write!(f, "(Sync Error)"),
+            OpErrorKind::InvalidArgsError => write!(f, "(InvalidArgs)"),
+            OpErrorKind::CallbackError => write!(f, "(Callback Error)"),
+            OpErrorKind::ValidationError => write!(f, "(Validation Error)"),
+            OpErrorKind::RuntimeError => write!(f, "(Runtime Error)"),
             OpErrorKind::None => write!(f, ""),
         }
     }
@@ -112,6 +124,10 @@ impl error::Error for OpErrorKind {
             OpErrorKind::ByteOrderError(ref err) => Some(err),
             OpErrorKind::Utf8Error(ref err) => Some(err),
             OpErrorKind::ScriptError(ref err) => Some(err),
+            #[cfg(feature = "kafka")]
+            OpErrorKind::KafkaError(ref err) => Some(err),
+            #[cfg(feature = "postgres")]
+            OpErrorKind::PostgresError(ref err) => Some(err),
             ref err @ OpErrorKind::PoisonError => Some(err),
             ref err @ OpErrorKind::SendError => Some(err),
             _ => None,
@@ -161,6 +177,20 @@ impl From<rusty_leveldb::Status> for OpError {
     }
 }
 
+#[cfg(feature = "kafka")]
+impl From<rdkafka::error::KafkaError> for OpError {
+    fn from(err: rdkafka::error::KafkaError) -> Self {
+        Self::new(OpErrorKind::KafkaError(err))
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<postgres::Error> for OpError {
+    fn from(err: postgres::Error) -> Self {
+        Self::new(OpErrorKind::PostgresError(err))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,21 +1,75 @@
 use clap::{Arg, Command};
 use std::boxed::Box;
+use std::collections::BTreeSet;
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Duration;
 
-use crate::blockchain::parser::chain::ChainStorage;
-use crate::blockchain::parser::types::{Bitcoin, CoinType};
+use crate::blockchain::parser::blkfile::XorKey;
+use crate::blockchain::parser::chain::{ChainStorage, TxCountCheck};
+use crate::blockchain::parser::filter::Filter;
+use crate::blockchain::parser::index::compare::CompareChains;
+use crate::blockchain::parser::index::manifest::Manifest;
+use crate::blockchain::parser::index::stats::IndexStats;
+use crate::blockchain::parser::progress::ProgressHandle;
+use crate::blockchain::parser::scanfile::ScanFile;
+use crate::blockchain::parser::types::{Bitcoin, CoinType, COIN_NAMES};
 use crate::blockchain::parser::BlockchainParser;
+use crate::blockchain::proto::script::{self, AddressFormat, ScriptTemplates};
+use crate::blockchain::proto::BlockHash;
+use crate::callbacks::auxpowstats::AuxPowStats;
+use crate::callbacks::balancehistory::BalanceHistory;
 use crate::callbacks::balances::Balances;
+use crate::callbacks::bench::Bench;
+use crate::callbacks::burns::Burns;
+use crate::callbacks::chainwork::ChainWork;
+use crate::callbacks::clusterflows::ClusterFlows;
 use crate::callbacks::csvdump::CsvDump;
+use crate::callbacks::depositpatterns::DepositPatterns;
+use crate::callbacks::difficulty::Difficulty;
+use crate::callbacks::entities::Entities;
+use crate::callbacks::extractdata::ExtractData;
+use crate::callbacks::feemarket::FeeMarket;
+use crate::callbacks::feesamples::FeeSamples;
+use crate::callbacks::getblock::{BlockRef, GetBlock};
+use crate::callbacks::inflationcheck::InflationCheck;
+#[cfg(feature = "kafka")]
+use crate::callbacks::kafka::Kafka;
+use crate::callbacks::merkleproof::MerkleProof;
+use crate::callbacks::minerstats::MinerStats;
+use crate::callbacks::namecoinnames::NamecoinNames;
 use crate::callbacks::opreturn::OpReturn;
+#[cfg(feature = "protobuf")]
+use crate::callbacks::protodump::ProtoDump;
+use crate::callbacks::scriptflows::ScriptFlows;
+use crate::callbacks::scriptstats::ScriptStats;
+use crate::callbacks::sigstats::SigStats;
 use crate::callbacks::simplestats::SimpleStats;
+use crate::callbacks::sink::{self, Output};
+use crate::callbacks::stream::Stream;
+use crate::callbacks::supply::Supply;
+use crate::callbacks::trace::Trace;
+use crate::callbacks::txshape::TxShape;
 use crate::callbacks::unspentcsvdump::UnspentCsvDump;
-use crate::callbacks::Callback;
-use crate::common::logger::SimpleLogger;
+use crate::callbacks::versionbits::VersionBits;
+use crate::callbacks::visualize::Visualize;
+use crate::callbacks::walletfingerprint::WalletFingerprint;
+use crate::callbacks::watch::Watch;
+use crate::callbacks::witnessstats::WitnessStats;
+use crate::callbacks::{Callback, CallbackContext};
+use crate::common::batch::Batch;
+use crate::common::completions;
+use crate::common::defaults::Defaults;
+use crate::common::logger::{self, LogFileOptions, LogFormat, SimpleLogger};
+#[cfg(feature = "self-test")]
+use crate::common::selftest;
 use crate::common::utils;
-use crate::errors::{OpError, OpResult};
+use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::query::balanceof::BalanceOf;
+use crate::query::diff::Diff;
+use crate::query::merge::Merge;
+use crate::query::verifybalances::VerifyBalances;
 
 #[macro_use]
 extern crate log;
@@ -31,8 +85,9 @@ extern crate seek_bufread;
 #[macro_use]
 pub mod errors;
 pub mod blockchain;
-pub mod common;
 pub mod callbacks;
+pub mod common;
+pub mod query;
 
 #[derive(Copy, Clone)]
 #[cfg_attr(test, derive(PartialEq, Debug))]
@@ -51,9 +106,73 @@ impl BlockHeightRange {
         Ok(Self { start, end })
     }
 
+    /// Limits the range to end at the given height at the latest
+    pub fn limit_end(self, height: u64) -> OpResult<Self> {
+        let end = self.end.map_or(height, |end| end.min(height));
+        Self::new(self.start, Some(end))
+    }
+
+    /// Limits the range to start at the given height at the earliest
+    pub fn limit_start(self, height: u64) -> OpResult<Self> {
+        Self::new(self.start.max(height), self.end)
+    }
+
+    /// Returns the i-th (starting at 1) of `count` contiguous parts of the range.
+    /// The sizes differ by one block at most, the first parts are the larger ones. Requires an end.
+    pub fn shard(self, index: u64, count: u64) -> OpResult<Self> {
+        let end = match self.end {
+            Some(end) => end,
+            None => return Err(OpError::from(String::from("--shard requires --end"))),
+        };
+        let n = end - self.start + 1;
+        // A range spans at least two blocks, see `new`
+        if n / count < 2 {
+            return Err(OpError::from(format!(
+                "The range {} is too small to split into {} shards",
+                self, count
+            )));
+        }
+        let (base, extra) = (n / count, n % count);
+        let start = self.start + (index - 1) * base + (index - 1).min(extra);
+        let size = base + u64::from(index <= extra);
+        Self::new(start, Some(start + size - 1))
+    }
+
     pub fn is_default(&self) -> bool {
         self.start == 0 && self.end.is_none()
     }
+
+    /// Resolves the effective range against the highest known block height.
+    /// Returns an error if the requested range is empty, and in strict mode also if it's only
+    /// partially available.
+    pub fn resolve(&self, max_known_height: u64, strict: bool) -> OpResult<Self> {
+        if self.start > max_known_height {
+            let msg = format!(
+                "--start {} is beyond the chain tip at height {}, no blocks to process",
+                self.start, max_known_height
+            );
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+        }
+        if let Some(end) = self.end.filter(|end| *end > max_known_height) {
+            let msg = format!(
+                "--end {} is beyond the chain tip, only blocks up to height {} are available",
+                end, max_known_height
+            );
+            if strict {
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+            }
+            warn!(target: "main", "{}", msg);
+        }
+
+        let end = match self.end {
+            Some(end) if end < max_known_height => end,
+            Some(_) | None => max_known_height,
+        };
+        Ok(Self {
+            start: self.start,
+            end: Some(end),
+        })
+    }
 }
 
 impl fmt::Display for BlockHeightRange {
@@ -76,46 +195,144 @@ pub struct ParserOptions {
     verify: bool,
     // Path to directory where blk.dat files are stored
     blockchain_dir: PathBuf,
+    // Key the blk and rev files are obfuscated with
+    xor_key: XorKey,
+    // Read concatenated blocks from this file instead of blockchain_dir, e.g. a bootstrap.dat
+    bootstrap: Option<PathBuf>,
+    // Verify the blk files against this manifest before parsing
+    manifest: Option<PathBuf>,
+    // Read the block index from this copy instead of blockchain_dir/index
+    index_snapshot: Option<PathBuf>,
+    // Read the block index from a temporary copy, e.g. while a node holds the lock
+    copy_index: bool,
+    // Parse even if the block index doesn't cover all blk files or refers to missing data
+    allow_partial_sync: bool,
     // Verbosity level, 0 = Error, 1 = Info, 2 = Debug, 3+ = Trace
     log_level_filter: log::LevelFilter,
+    // Optional file to write all log messages to in addition to the console
+    log_file: Option<LogFileOptions>,
     // Range which is considered for parsing
     range: BlockHeightRange,
+    // Unix timestamps the range is resolved from, if given as dates (end is exclusive)
+    start_time: Option<u32>,
+    end_time: Option<u32>,
+    // Only parse blocks stored in this inclusive range of blk file indexes
+    blk_files: Option<(u64, u64)>,
+    // Only parse these heights, e.g. to reprocess damaged blocks
+    heights: Option<BTreeSet<u64>>,
+    // Only parse the block with this hash, resolved to its height from the block index
+    block_hash: Option<BlockHash>,
+    // Number of evaluated scripts to keep in memory, 0 disables the cache
+    script_cache_size: usize,
+    // Encode addresses of altcoins in their pre-segwit formats
+    legacy_addresses: bool,
+    // Templates to label scripts the builtin rules don't recognise
+    script_templates: Option<ScriptTemplates>,
+    // Resolve spent outputs of all inputs from rev*.dat files
+    use_undo_files: bool,
+    // Abort if the requested range is not fully available
+    strict_range: bool,
+    // Count output scripts which fail classification or address extraction
+    strict_scripts: bool,
+    // Treat blocks with unknown tx versions, segwit flags or witness programs as corrupt
+    strict_parse: bool,
+    // Optional csv file to dump those scripts to
+    bad_scripts_file: Option<PathBuf>,
+    // Compare the parsed tx count of each block with the block index
+    check_tx_count: Option<TxCountCheck>,
+    // Skip blocks which can't be read or parsed instead of aborting
+    skip_corrupt: bool,
+    // Interval to log the time spent reading, decoding and in the callback in
+    heartbeat: Option<Duration>,
+    // Only pass transactions with an output matching this expression to the callback
+    filter: Option<Filter>,
 }
 
 fn command() -> Command {
-    let coins = [
-        "bitcoin",
-        "testnet3",
-        "namecoin",
-        "litecoin",
-        "dogecoin",
-        "myriadcoin",
-        "unobtanium",
-        "noteblockchain",
-    ];
-    Command::new("rusty-blockparser")
+    let command = Command::new("rusty-blockparser")
     .version(crate_version!())
     // Add flags
     .arg(Arg::new("verify")
         .long("verify")
         .action(clap::ArgAction::SetTrue)
         .value_parser(clap::value_parser!(bool))
-        .help("Verifies merkle roots and block hashes"))
+        .help("Verifies merkle roots, block hashes and the proof of work where possible"))
+    .arg(Arg::new("strict-scripts")
+        .long("strict-scripts")
+        .action(clap::ArgAction::SetTrue)
+        .help("Counts output scripts which fail classification or address extraction"))
+    .arg(Arg::new("strict-parse")
+        .long("strict-parse")
+        .action(clap::ArgAction::SetTrue)
+        .help("Aborts on unknown tx versions, segwit flags or witness programs instead of counting them"))
+    .arg(Arg::new("check-tx-count")
+        .long("check-tx-count")
+        .value_name("MODE")
+        .num_args(0..=1)
+        .require_equals(true)
+        .default_missing_value("fail")
+        .value_parser(["warn", "fail"])
+        .help("Compares the number of parsed transactions with the block index, mismatching blocks are corrupt (default) or reported with --check-tx-count=warn"))
+    .arg(Arg::new("skip-corrupt")
+        .long("skip-corrupt")
+        .action(clap::ArgAction::SetTrue)
+        .help("Skips blocks which can't be read or parsed instead of aborting and reports them at the end"))
+    .arg(Arg::new("use-undo-files")
+        .long("use-undo-files")
+        .action(clap::ArgAction::SetTrue)
+        .help("Reads spent output values from rev*.dat files (Bitcoin Core only)"))
+    .arg(Arg::new("no-xor")
+        .long("no-xor")
+        .action(clap::ArgAction::SetTrue)
+        .conflicts_with("xor-key")
+        .help("Reads blk and rev files as is, even if a xor.dat is present"))
+    .arg(Arg::new("allow-partial-sync")
+        .long("allow-partial-sync")
+        .action(clap::ArgAction::SetTrue)
+        .help("Parses the indexed blocks even if the block index looks incomplete compared to the blk files"))
+    .arg(Arg::new("copy-index-to-tmp")
+        .long("copy-index-to-tmp")
+        .action(clap::ArgAction::SetTrue)
+        .conflicts_with("index-snapshot")
+        .help("Reads the block index from a temporary copy, e.g. while bitcoind is running"))
     .arg(Arg::new("verbosity")
         .short('v')
         .action(clap::ArgAction::Count)
         .help("Increases verbosity level. Info=0, Debug=1, Trace=2 (default: 0)"))
     // Add options
+    .arg(Arg::new("config")
+        .long("config")
+        .value_name("FILE")
+        .help("Runs all jobs described in the given TOML file, see README"))
     .arg(Arg::new("coin")
         .short('c')
         .long("coin")
         .value_name("NAME")
-        .value_parser(clap::builder::PossibleValuesParser::new(coins))
+        .value_parser(clap::builder::PossibleValuesParser::new(COIN_NAMES))
         .help("Specify blockchain coin (default: bitcoin)"))
     .arg(Arg::new("blockchain-dir")
         .short('d')
         .long("blockchain-dir")
         .help("Sets blockchain directory which contains blk.dat files (default: ~/.bitcoin/blocks)"))
+    .arg(Arg::new("xor-key")
+        .long("xor-key")
+        .value_name("HEX")
+        .value_parser(utils::parse_xor_key)
+        .help("Deobfuscates blk and rev files with the given 8 byte key instead of xor.dat"))
+    .arg(Arg::new("bootstrap")
+        .long("bootstrap")
+        .value_name("FILE")
+        .conflicts_with_all(["blockchain-dir", "index-snapshot", "copy-index-to-tmp", "use-undo-files", "only-blk-files"])
+        .help("Reads blocks from a file of concatenated blocks (e.g. bootstrap.dat) instead of a node's blocks directory"))
+    .arg(Arg::new("manifest")
+        .long("manifest")
+        .value_name("FILE")
+        .conflicts_with("bootstrap")
+        .help("Checks the blk files against a manifest written by the manifest subcommand before parsing"))
+    .arg(Arg::new("index-snapshot")
+        .long("index-snapshot")
+        .value_name("DIR")
+        .help("Reads the block index from a copy of the index directory (default: <blockchain-dir>/index)"))
     .arg(Arg::new("start")
         .short('s')
         .long("start")
@@ -128,16 +345,272 @@ fn command() -> Command {
         .value_name("HEIGHT")
         .value_parser(clap::value_parser!(u64))
         .help("Specify last block for parsing (inclusive) (default: all known blocks)"))
+    .arg(Arg::new("start-date")
+        .long("start-date")
+        .value_name("YYYY-MM-DD")
+        .value_parser(utils::parse_date)
+        .conflicts_with("start")
+        .help("Starts parsing at the first block mined on or after the given date (UTC)"))
+    .arg(Arg::new("end-date")
+        .long("end-date")
+        .value_name("YYYY-MM-DD")
+        .value_parser(utils::parse_date)
+        .conflicts_with("end")
+        .help("Stops parsing at the last block mined on the given date (UTC)"))
+    .arg(Arg::new("only-blk-files")
+        .long("only-blk-files")
+        .value_name("START-END")
+        .value_parser(utils::parse_blk_range)
+        .help("Only parses blocks stored in the given range of blk files (inclusive)"))
+    .arg(Arg::new("shard")
+        .long("shard")
+        .value_name("i/N")
+        .value_parser(utils::parse_shard)
+        .requires("end")
+        .conflicts_with_all(["start-date", "only-blk-files", "heights-file"])
+        .help("Only parses the i-th of N equal parts of the range, see `merge` to combine the outputs"))
+    .arg(Arg::new("heights-file")
+        .long("heights-file")
+        .value_name("FILE")
+        .help("Only parses the block heights listed in the given file (one per line)"))
+    .arg(Arg::new("strict-range")
+        .long("strict-range")
+        .action(clap::ArgAction::SetTrue)
+        .help("Aborts if the requested range exceeds the known chain"))
+    .arg(Arg::new("script-cache-size")
+        .long("script-cache-size")
+        .value_name("ENTRIES")
+        .value_parser(clap::value_parser!(usize))
+        .help("Caches up to ENTRIES evaluated output scripts to speed up address extraction (default: 0, disabled)"))
+    .arg(Arg::new("script-templates")
+        .long("script-templates")
+        .value_name("FILE")
+        .help("Labels scripts which are not recognised with the first matching template of the TOML file"))
+    .arg(Arg::new("legacy-addresses")
+        .long("legacy-addresses")
+        .action(clap::ArgAction::SetTrue)
+        .help("Encodes addresses of coins other than Bitcoin in their legacy formats: P2SH with the former prefix and no bech32 addresses"))
+    .arg(Arg::new("log-file")
+        .long("log-file")
+        .value_name("FILE")
+        .help("Additionally writes all log messages to the given file"))
+    .arg(Arg::new("log-max-size")
+        .long("log-max-size")
+        .value_name("MB")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .requires("log-file")
+        .help("Rotates the log file once it exceeds the given size (default: 100)"))
+    .arg(Arg::new("log-max-files")
+        .long("log-max-files")
+        .value_name("COUNT")
+        .value_parser(clap::value_parser!(usize))
+        .requires("log-file")
+        .help("Number of rotated log files to keep (default: 5)"))
+    .arg(Arg::new("log-format")
+        .long("log-format")
+        .value_name("FORMAT")
+        .value_parser(["text", "json"])
+        .requires("log-file")
+        .help("Format of the log file (default: text)"))
+    .arg(Arg::new("heartbeat")
+        .long("heartbeat")
+        .value_name("SECS")
+        .value_parser(clap::value_parser!(u64).range(1..))
+        .help("Logs the time spent reading, decoding and in the callback every SECS seconds"))
+    .arg(Arg::new("filter")
+        .long("filter")
+        .value_name("EXPR")
+        .value_parser(Filter::parse)
+        .help("Only passes transactions with an output matching the expression to the callback, e.g. \"value > 100000000 && script_type == 'Pay2Taproot'\""))
+    .arg(Arg::new("dump-bad-scripts")
+        .long("dump-bad-scripts")
+        .value_name("FILE")
+        .requires("strict-scripts")
+        .help("Dumps the scripts found by --strict-scripts to a csv file"))
     // Add callbacks
     .subcommand(UnspentCsvDump::build_subcommand())
     .subcommand(CsvDump::build_subcommand())
     .subcommand(SimpleStats::build_subcommand())
     .subcommand(Balances::build_subcommand())
     .subcommand(OpReturn::build_subcommand())
+    .subcommand(AuxPowStats::build_subcommand())
+    .subcommand(MerkleProof::build_subcommand())
+    .subcommand(ClusterFlows::build_subcommand())
+    .subcommand(Entities::build_subcommand())
+    .subcommand(Difficulty::build_subcommand())
+    .subcommand(ExtractData::build_subcommand())
+    .subcommand(VersionBits::build_subcommand())
+    .subcommand(Burns::build_subcommand())
+    .subcommand(TxShape::build_subcommand())
+    .subcommand(NamecoinNames::build_subcommand())
+    .subcommand(Stream::build_subcommand())
+    .subcommand(WalletFingerprint::build_subcommand())
+    .subcommand(ScriptFlows::build_subcommand())
+    .subcommand(MinerStats::build_subcommand())
+    .subcommand(ChainWork::build_subcommand())
+    .subcommand(GetBlock::build_subcommand())
+    .subcommand(FeeSamples::build_subcommand())
+    .subcommand(FeeMarket::build_subcommand())
+    .subcommand(SigStats::build_subcommand())
+    .subcommand(DepositPatterns::build_subcommand())
+    .subcommand(InflationCheck::build_subcommand())
+    .subcommand(BalanceHistory::build_subcommand())
+    .subcommand(Supply::build_subcommand())
+    .subcommand(ScriptStats::build_subcommand())
+    .subcommand(Trace::build_subcommand())
+    .subcommand(Watch::build_subcommand())
+    .subcommand(WitnessStats::build_subcommand())
+    .subcommand(Visualize::build_subcommand())
+    .subcommand(Bench::build_subcommand());
+    // Add callbacks behind feature flags
+    #[cfg(feature = "kafka")]
+    let command = command.subcommand(Kafka::build_subcommand());
+    #[cfg(feature = "protobuf")]
+    let command = command.subcommand(ProtoDump::build_subcommand());
+    #[cfg(feature = "self-test")]
+    let command = command.arg(Arg::new("self-test")
+        .long("self-test")
+        .action(clap::ArgAction::SetTrue)
+        .help("Parses a generated chain and compares the output of some callbacks with the bundled golden files"));
+    // Add queries
+    let command = command
+        .subcommand(BalanceOf::build_subcommand())
+        .subcommand(Diff::build_subcommand())
+        .subcommand(Merge::build_subcommand())
+        .subcommand(VerifyBalances::build_subcommand())
+        .subcommand(IndexStats::build_subcommand())
+        .subcommand(CompareChains::build_subcommand())
+        .subcommand(Manifest::build_subcommand())
+        .subcommand(ScanFile::build_subcommand())
+        .subcommand(completions::build_subcommand());
+    sink::with_output_arg(command)
 }
 
 fn main() {
-    let options = match parse_args(command().get_matches()) {
+    // Defaults from the config file only fill in arguments missing on the command line
+    let defaults = Defaults::load().unwrap_or_else(|why| {
+        SimpleLogger::init(log::LevelFilter::Debug).unwrap();
+        error!(target: "main", "{}", why);
+        process::exit(1);
+    });
+    let matches = defaults.apply(command()).get_matches();
+    if let Some(matches) = matches.subcommand_matches("completions") {
+        let shell = matches.get_one::<String>("shell").unwrap();
+        print!("{}", completions::generate(&mut command(), shell));
+        return;
+    }
+    // Queries read existing dumps and write their results to stdout
+    if let Some((name, matches)) = matches
+        .subcommand()
+        .filter(|(name, _)| ["balanceof", "diff", "merge", "verify-balances"].contains(name))
+    {
+        logger::redirect_to_stderr();
+        SimpleLogger::init(log::LevelFilter::Info).expect("Unable to initialize logger!");
+        let result = match name {
+            "balanceof" => BalanceOf::run(matches),
+            "merge" => Merge::run(matches),
+            "verify-balances" => VerifyBalances::run(matches),
+            _ => Diff::run(matches),
+        };
+        if let Err(why) = result {
+            error!(target: "main", "{}", why);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Index statistics only read the block index and write to stdout
+    if matches.subcommand_name() == Some("indexstats") {
+        logger::redirect_to_stderr();
+        SimpleLogger::init(log::LevelFilter::Info).expect("Unable to initialize logger!");
+        let blockchain_dir = blockchain_dir(&matches, &coin(&matches));
+        let index_dir = index_snapshot(&matches).unwrap_or_else(|| blockchain_dir.join("index"));
+        let copy = matches.get_flag("copy-index-to-tmp");
+        if let Err(why) = IndexStats::run(&blockchain_dir, &index_dir, copy) {
+            error!(target: "main", "{}", why);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Comparing chains only reads the block indexes of both directories and writes to stdout
+    if let Some(compare_matches) = matches.subcommand_matches("comparechains") {
+        logger::redirect_to_stderr();
+        SimpleLogger::init(log::LevelFilter::Info).expect("Unable to initialize logger!");
+        let blockchain_dir = blockchain_dir(&matches, &coin(&matches));
+        let index_dir = index_snapshot(&matches).unwrap_or_else(|| blockchain_dir.join("index"));
+        let other_dir = utils::resolve_dir(compare_matches.get_one::<String>("other-dir").unwrap());
+        let copy = matches.get_flag("copy-index-to-tmp");
+        if let Err(why) = CompareChains::run(&index_dir, &other_dir.join("index"), copy) {
+            error!(target: "main", "{}", why);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // The manifest only reads the block index and the raw blk files
+    if let Some(manifest_matches) = matches.subcommand_matches("manifest") {
+        SimpleLogger::init(log::LevelFilter::Info).expect("Unable to initialize logger!");
+        let blockchain_dir = blockchain_dir(&matches, &coin(&matches));
+        let index_dir = index_snapshot(&matches).unwrap_or_else(|| blockchain_dir.join("index"));
+        let copy = matches.get_flag("copy-index-to-tmp");
+        let path = Path::new(manifest_matches.get_one::<String>("file").unwrap());
+        if let Err(why) = Manifest::run(&blockchain_dir, &index_dir, copy, path) {
+            error!(target: "main", "{}", why);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Scanning a single blk file ignores the block index and writes to stdout
+    if let Some(scan_matches) = matches.subcommand_matches("scanfile") {
+        logger::redirect_to_stderr();
+        SimpleLogger::init(log::LevelFilter::Info).expect("Unable to initialize logger!");
+        if let Err(why) = ScanFile::run(scan_matches, &coin(&matches), xor_key(&matches)) {
+            error!(target: "main", "{}", why);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // The self-test compares the output of a generated chain with the golden files
+    #[cfg(feature = "self-test")]
+    if matches.get_flag("self-test") {
+        SimpleLogger::init(log::LevelFilter::Warn).expect("Unable to initialize logger!");
+        match selftest::run() {
+            Ok(true) => println!("Self-test passed"),
+            Ok(false) => process::exit(1),
+            Err(why) => {
+                error!(target: "main", "{}", why);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Batch runs spawn a parser process per job
+    if let Some(path) = matches.get_one::<String>("config") {
+        let log_level = match matches.get_count("verbosity") {
+            0 => log::LevelFilter::Info,
+            1 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        };
+        SimpleLogger::init(log_level).expect("Unable to initialize logger!");
+        if let Err(why) = Batch::from_path(Path::new(path)).and_then(|batch| batch.run()) {
+            error!(target: "main", "{}", why);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Callbacks writing their rows to stdout need the log messages on stderr
+    if matches
+        .subcommand()
+        .is_some_and(|(_, matches)| Output::is_stdout(matches))
+    {
+        logger::redirect_to_stderr();
+    }
+    let mut options = match parse_args(matches) {
         Ok(o) => o,
         Err(desc) => {
             // Init logger to print outstanding error message
@@ -149,28 +622,58 @@ fn main() {
 
     // Apply log filter based on verbosity
     let log_level = options.log_level_filter;
-    SimpleLogger::init(log_level).expect("Unable to initialize logger!");
+    match &options.log_file {
+        Some(log_file) => {
+            if let Err(why) = SimpleLogger::init_with_file(log_level, log_file) {
+                SimpleLogger::init(log_level).unwrap();
+                error!(target: "main", "{}", why);
+                process::exit(1);
+            }
+        }
+        None => SimpleLogger::init(log_level).expect("Unable to initialize logger!"),
+    }
     info!(target: "main", "Starting rusty-blockparser v{} ...", env!("CARGO_PKG_VERSION"));
     debug!(target: "main", "Using log level {}", log_level);
     if options.verify {
         info!(target: "main", "Configured to verify merkle roots and block hashes");
     }
+    if options.use_undo_files {
+        info!(target: "main", "Configured to resolve spent outputs from undo files");
+    }
+    script::init_cache(options.script_cache_size);
+    script::init_address_format(AddressFormat::of(&options.coin, options.legacy_addresses));
+    if let Some(templates) = options.script_templates.take() {
+        info!(target: "main", "Labeling unrecognised scripts with {} templates", templates.count());
+        script::init_templates(templates);
+    }
 
     let chain_storage = match ChainStorage::new(&options) {
         Ok(storage) => storage,
         Err(e) => {
+            let source = options
+                .bootstrap
+                .as_ref()
+                .unwrap_or(&options.blockchain_dir);
             error!(
                 target: "main",
                 "Cannot load blockchain data from: '{}'. {}",
-                options.blockchain_dir.display(),
+                source.display(),
                 e
             );
             process::exit(1);
         }
     };
 
-    let mut parser = BlockchainParser::new(options, chain_storage);
-    match parser.start() {
+    // The parser is dropped before exiting, so the callback can remove its temporary files
+    // The command line interface logs the progress every 10 seconds
+    let mut progress = ProgressHandle::default().with_interval(Duration::from_secs(10));
+    if options.callback.show_progress() {
+        progress = progress.on_progress(|progress| info!(target: "parser", "Status: {}", progress));
+    }
+    let result = BlockchainParser::new(options, chain_storage)
+        .with_progress(progress)
+        .start();
+    match result {
         Ok(_) => info!(target: "main", "Fin."),
         Err(why) => {
             error!("{}", why);
@@ -179,38 +682,247 @@ fn main() {
     }
 }
 
+/// Creates callbacks which are only available with the respective feature enabled
+#[allow(unused_variables)]
+fn feature_callback(
+    matches: &clap::ArgMatches,
+    context: &CallbackContext,
+) -> OpResult<Option<Box<dyn Callback>>> {
+    #[cfg(feature = "kafka")]
+    if let Some(matches) = matches.subcommand_matches("kafka") {
+        return Ok(Some(Box::new(Kafka::new(matches, context)?)));
+    }
+    #[cfg(feature = "protobuf")]
+    if let Some(matches) = matches.subcommand_matches("protodump") {
+        return Ok(Some(Box::new(ProtoDump::new(matches, context)?)));
+    }
+    Ok(None)
+}
+
+fn coin(matches: &clap::ArgMatches) -> CoinType {
+    matches
+        .get_one::<String>("coin")
+        .map_or_else(|| CoinType::from(Bitcoin), |v| v.parse().unwrap())
+}
+
+/// Returns the `--blockchain-dir`, the default directory of the coin if not given
+fn blockchain_dir(matches: &clap::ArgMatches, coin: &CoinType) -> PathBuf {
+    match matches.get_one::<String>("blockchain-dir") {
+        Some(p) => utils::resolve_dir(p),
+        None => utils::get_absolute_blockchain_dir(coin),
+    }
+}
+
+/// Returns the key given by `--xor-key` or `--no-xor`
+fn xor_key(matches: &clap::ArgMatches) -> XorKey {
+    match matches.get_one::<[u8; 8]>("xor-key") {
+        Some(key) => XorKey::Key(*key),
+        None if matches.get_flag("no-xor") => XorKey::Disabled,
+        None => XorKey::Auto,
+    }
+}
+
+fn index_snapshot(matches: &clap::ArgMatches) -> Option<PathBuf> {
+    matches
+        .get_one::<String>("index-snapshot")
+        .map(|p| utils::resolve_dir(p))
+}
+
 /// Parses args or panics if some requirements are not met.
 fn parse_args(matches: clap::ArgMatches) -> OpResult<ParserOptions> {
     let verify = matches.get_flag("verify");
+    let use_undo_files = matches.get_flag("use-undo-files");
+    let strict_scripts = matches.get_flag("strict-scripts");
+    let strict_parse = matches.get_flag("strict-parse");
+    let skip_corrupt = matches.get_flag("skip-corrupt");
+    let check_tx_count =
+        matches
+            .get_one::<String>("check-tx-count")
+            .map(|mode| match mode.as_str() {
+                "warn" => TxCountCheck::Warn,
+                _ => TxCountCheck::Fail,
+            });
+    let xor_key = xor_key(&matches);
+    let bad_scripts_file = matches
+        .get_one::<String>("dump-bad-scripts")
+        .map(PathBuf::from);
     let log_level_filter = match matches.get_count("verbosity") {
         0 => log::LevelFilter::Info,
         1 => log::LevelFilter::Debug,
         _ => log::LevelFilter::Trace,
     };
+    let log_file = matches
+        .get_one::<String>("log-file")
+        .map(|path| LogFileOptions {
+            path: PathBuf::from(path),
+            max_size: matches
+                .get_one::<u64>("log-max-size")
+                .copied()
+                .unwrap_or(100)
+                * 1024
+                * 1024,
+            max_files: matches
+                .get_one::<usize>("log-max-files")
+                .copied()
+                .unwrap_or(5),
+            format: match matches.get_one::<String>("log-format").map(String::as_str) {
+                Some("json") => LogFormat::Json,
+                _ => LogFormat::Text,
+            },
+        });
 
-    let coin = matches
-        .get_one::<String>("coin")
-        .map_or_else(|| CoinType::from(Bitcoin), |v| v.parse().unwrap());
-    let blockchain_dir = match matches.get_one::<String>("blockchain-dir") {
-        Some(p) => PathBuf::from(p),
-        None => utils::get_absolute_blockchain_dir(&coin),
-    };
+    let coin = coin(&matches);
+    let blockchain_dir = blockchain_dir(&matches, &coin);
+    let bootstrap = matches.get_one::<String>("bootstrap").map(PathBuf::from);
+    let manifest = matches.get_one::<String>("manifest").map(PathBuf::from);
+    let index_snapshot = index_snapshot(&matches);
+    let copy_index = matches.get_flag("copy-index-to-tmp");
+    let allow_partial_sync = matches.get_flag("allow-partial-sync");
     let start = matches.get_one::<u64>("start").copied().unwrap_or(0);
     let end = matches.get_one::<u64>("end").copied();
-    let range = BlockHeightRange::new(start, end)?;
+    let mut range = BlockHeightRange::new(start, end)?;
+    if let Some((index, count)) = matches.get_one::<(u64, u64)>("shard") {
+        range = range.shard(*index, *count)?;
+    }
+    let start_time = matches.get_one::<u32>("start-date").copied();
+    // The end date is inclusive, so parse up to the start of the next day
+    let end_time = matches
+        .get_one::<u32>("end-date")
+        .map(|time| time.saturating_add(86400));
+    if let (Some(start_time), Some(end_time)) = (start_time, end_time) {
+        if start_time >= end_time {
+            return Err(OpError::from(String::from(
+                "--start-date must not be after --end-date",
+            )));
+        }
+    }
+    let blk_files = matches.get_one::<(u64, u64)>("only-blk-files").copied();
+    let mut heights = match matches.get_one::<String>("heights-file") {
+        Some(path) => Some(utils::read_heights(Path::new(path))?),
+        None => None,
+    };
+    let mut block_hash = None;
+    let strict_range = matches.get_flag("strict-range");
+    let heartbeat = matches
+        .get_one::<u64>("heartbeat")
+        .map(|secs| Duration::from_secs(*secs));
+    let filter = matches.get_one::<Filter>("filter").cloned();
+    let script_cache_size = matches
+        .get_one::<usize>("script-cache-size")
+        .copied()
+        .unwrap_or(0);
+    let legacy_addresses = matches.get_flag("legacy-addresses");
+    let script_templates = match matches.get_one::<String>("script-templates") {
+        Some(path) => Some(ScriptTemplates::from_path(Path::new(path))?),
+        None => None,
+    };
 
     // Set callback
+    let context = CallbackContext {
+        coin: coin.clone(),
+        use_undo_files,
+    };
     let callback: Box<dyn Callback>;
     if let Some(matches) = matches.subcommand_matches("simplestats") {
-        callback = Box::new(SimpleStats::new(matches)?);
+        callback = Box::new(SimpleStats::new(matches, &context)?);
     } else if let Some(matches) = matches.subcommand_matches("csvdump") {
-        callback = Box::new(CsvDump::new(matches)?);
+        callback = Box::new(CsvDump::new(matches, &context)?);
     } else if let Some(matches) = matches.subcommand_matches("unspentcsvdump") {
-        callback = Box::new(UnspentCsvDump::new(matches)?);
+        callback = Box::new(UnspentCsvDump::new(matches, &context)?);
     } else if let Some(matches) = matches.subcommand_matches("balances") {
-        callback = Box::new(Balances::new(matches)?);
+        let balances = Balances::new(matches, &context)?;
+        // Continue after the loaded unspent outputs
+        if let Some(height) = balances.loaded_height() {
+            range = range.limit_start(height + 1)?;
+        }
+        // No need to parse beyond the last snapshot
+        if let Some(height) = balances.last_snapshot_height() {
+            range = range.limit_end(height)?;
+        }
+        callback = Box::new(balances);
     } else if let Some(matches) = matches.subcommand_matches("opreturn") {
-        callback = Box::new(OpReturn::new(matches)?);
+        callback = Box::new(OpReturn::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("auxpowstats") {
+        callback = Box::new(AuxPowStats::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("merkleproof") {
+        callback = Box::new(MerkleProof::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("clusterflows") {
+        callback = Box::new(ClusterFlows::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("entities") {
+        callback = Box::new(Entities::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("difficulty") {
+        callback = Box::new(Difficulty::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("extractdata") {
+        callback = Box::new(ExtractData::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("versionbits") {
+        callback = Box::new(VersionBits::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("burns") {
+        callback = Box::new(Burns::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("txshape") {
+        callback = Box::new(TxShape::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("namecoin-names") {
+        callback = Box::new(NamecoinNames::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("stream") {
+        callback = Box::new(Stream::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("walletfingerprint") {
+        callback = Box::new(WalletFingerprint::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("scriptflows") {
+        callback = Box::new(ScriptFlows::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("minerstats") {
+        callback = Box::new(MinerStats::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("chainwork") {
+        callback = Box::new(ChainWork::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("getblock") {
+        let getblock = GetBlock::new(matches, &context)?;
+        // Only the requested block is read, using the offsets from the block index
+        match getblock.block() {
+            BlockRef::Height(height) => heights = Some(BTreeSet::from([height])),
+            BlockRef::Hash(hash) => block_hash = Some(hash),
+        }
+        callback = Box::new(getblock);
+    } else if let Some(matches) = matches.subcommand_matches("feesamples") {
+        callback = Box::new(FeeSamples::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("feemarket") {
+        callback = Box::new(FeeMarket::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("sigstats") {
+        callback = Box::new(SigStats::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("depositpatterns") {
+        callback = Box::new(DepositPatterns::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("inflationcheck") {
+        callback = Box::new(InflationCheck::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("balancehistory") {
+        callback = Box::new(BalanceHistory::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("supply") {
+        callback = Box::new(Supply::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("scriptstats") {
+        callback = Box::new(ScriptStats::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("trace") {
+        let trace = Trace::new(matches, &context)?;
+        // Descendants are created after the transaction, ancestors before it
+        if let Some(height) = trace.height() {
+            range = match trace.ancestors() {
+                true => range.limit_end(height)?,
+                false => range.limit_start(height)?,
+            };
+        }
+        callback = Box::new(trace);
+    } else if let Some(matches) = matches.subcommand_matches("watch") {
+        callback = Box::new(Watch::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("witnessstats") {
+        callback = Box::new(WitnessStats::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("bench") {
+        callback = Box::new(Bench::new(matches, &context)?);
+    } else if let Some(matches) = matches.subcommand_matches("visualize") {
+        let visualize = Visualize::new(matches, &context)?;
+        // Without --end the whole chain would be drawn
+        let max_end = range.start + visualize.max_blocks() - 1;
+        if range.end.is_none_or(|end| end > max_end) {
+            warn!(target: "main", "visualize is limited to {} blocks, stopping at height {}", visualize.max_blocks(), max_end);
+            range = range.limit_end(max_end)?;
+        }
+        callback = Box::new(visualize);
+    } else if let Some(cb) = feature_callback(&matches, &context)? {
+        callback = cb;
     } else {
         clap::error::Error::<clap::error::DefaultFormatter>::raw(
             clap::error::ErrorKind::MissingSubcommand,
@@ -224,8 +936,32 @@ fn parse_args(matches: clap::ArgMatches) -> OpResult<ParserOptions> {
         callback,
         verify,
         blockchain_dir,
+        xor_key,
+        bootstrap,
+        manifest,
+        index_snapshot,
+        copy_index,
+        allow_partial_sync,
         log_level_filter,
+        log_file,
         range,
+        start_time,
+        end_time,
+        blk_files,
+        heights,
+        block_hash,
+        script_cache_size,
+        legacy_addresses,
+        script_templates,
+        use_undo_files,
+        strict_range,
+        strict_scripts,
+        strict_parse,
+        bad_scripts_file,
+        check_tx_count,
+        skip_corrupt,
+        heartbeat,
+        filter,
     };
     Ok(options)
 }
@@ -257,6 +993,88 @@ mod tests {
         ]))
         .unwrap();
         parse_args(command().get_matches_from(["rusty-blockparser", "opreturn"])).unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "auxpowstats",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "merkleproof",
+            tmp_dir.path().to_str().unwrap(),
+            "--txid",
+            "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "clusterflows",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "difficulty",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "extractdata",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "versionbits",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "burns",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "txshape",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "namecoin-names",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from(["rusty-blockparser", "stream", "tcp:127.0.0.1:0"]))
+            .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "minerstats",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        parse_args(command().get_matches_from([
+            "rusty-blockparser",
+            "chainwork",
+            tmp_dir.path().to_str().unwrap(),
+        ]))
+        .unwrap();
+        let options =
+            parse_args(command().get_matches_from(["rusty-blockparser", "getblock", "5"])).unwrap();
+        assert_eq!(options.heights, Some(BTreeSet::from([5])));
+        let hash = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        let options =
+            parse_args(command().get_matches_from(["rusty-blockparser", "getblock", hash]))
+                .unwrap();
+        assert_eq!(options.block_hash, Some(hash.parse().unwrap()));
+        let options =
+            parse_args(command().get_matches_from(["rusty-blockparser", "dumpblock", "7"]))
+                .unwrap();
+        assert_eq!(options.heights, Some(BTreeSet::from([7])));
     }
 
     #[test]
@@ -269,9 +1087,24 @@ mod tests {
         let options = parse_args(command().get_matches_from(args)).unwrap();
         assert_eq!(options.coin.name, "TestNet3");
 
+        let args = ["rusty-blockparser", "-c", "testnet4", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.name, "TestNet4");
+        assert_eq!(options.coin.magic, 0x283f161c);
+
+        let args = ["rusty-blockparser", "-c", "signet", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.name, "Signet");
+        assert_eq!(options.coin.version_id, 0x6f);
+
         let args = ["rusty-blockparser", "--coin", "namecoin", "simplestats"];
         let options = parse_args(command().get_matches_from(args)).unwrap();
         assert_eq!(options.coin.name, "Namecoin");
+
+        let args = ["rusty-blockparser", "-c", "groestlcoin", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.coin.name, "Groestlcoin");
+        assert_eq!(options.coin.magic, 0xd4b4bef9);
     }
 
     #[test]
@@ -285,6 +1118,94 @@ mod tests {
         assert!(options.verify);
     }
 
+    #[test]
+    fn test_args_use_undo_files() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.use_undo_files);
+
+        let args = ["rusty-blockparser", "--use-undo-files", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.use_undo_files);
+    }
+
+    #[test]
+    fn test_args_skip_corrupt() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.skip_corrupt);
+
+        let args = ["rusty-blockparser", "--skip-corrupt", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.skip_corrupt);
+    }
+
+    #[test]
+    fn test_args_strict_scripts() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(!options.strict_scripts);
+        assert!(!options.strict_parse);
+        assert_eq!(options.bad_scripts_file, None);
+
+        let args = ["rusty-blockparser", "--strict-parse", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.strict_parse);
+
+        let args = ["rusty-blockparser", "--strict-scripts", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.strict_scripts);
+
+        let args = [
+            "rusty-blockparser",
+            "--strict-scripts",
+            "--dump-bad-scripts",
+            "scripts.csv",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.bad_scripts_file, Some(PathBuf::from("scripts.csv")));
+
+        let args = [
+            "rusty-blockparser",
+            "--dump-bad-scripts",
+            "scripts.csv",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_xor_key() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.xor_key, XorKey::Auto);
+
+        let args = ["rusty-blockparser", "--no-xor", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.xor_key, XorKey::Disabled);
+
+        let args = [
+            "rusty-blockparser",
+            "--xor-key",
+            "0102030405060708",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.xor_key, XorKey::Key([1, 2, 3, 4, 5, 6, 7, 8]));
+
+        let args = ["rusty-blockparser", "--xor-key", "0102", "simplestats"];
+        assert!(command().try_get_matches_from(args).is_err());
+        let args = [
+            "rusty-blockparser",
+            "--xor-key",
+            "0102030405060708",
+            "--no-xor",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
     #[test]
     fn test_args_blockchain_dir() {
         let args = ["rusty-blockparser", "simplestats"];
@@ -309,6 +1230,56 @@ mod tests {
         assert_eq!(options.blockchain_dir.to_str().unwrap(), "foo");
     }
 
+    #[test]
+    fn test_args_bootstrap() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.bootstrap, None);
+
+        let args = ["rusty-blockparser", "--bootstrap", "foo", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.bootstrap, Some(PathBuf::from("foo")));
+
+        let args = [
+            "rusty-blockparser",
+            "--bootstrap",
+            "foo",
+            "--use-undo-files",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_index_snapshot() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.index_snapshot, None);
+        assert!(!options.copy_index);
+
+        let args = [
+            "rusty-blockparser",
+            "--index-snapshot",
+            "foo",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.index_snapshot, Some(PathBuf::from("foo")));
+
+        let args = ["rusty-blockparser", "--copy-index-to-tmp", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert!(options.copy_index);
+
+        let args = [
+            "rusty-blockparser",
+            "--copy-index-to-tmp",
+            "--index-snapshot",
+            "foo",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
     #[test]
     fn test_args_log_level() {
         let args = ["rusty-blockparser", "simplestats"];
@@ -384,6 +1355,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_args_script_cache_size() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.script_cache_size, 0);
+
+        let args = [
+            "rusty-blockparser",
+            "--script-cache-size",
+            "1000000",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.script_cache_size, 1000000);
+    }
+
     #[test]
     fn test_args_start_and_end() {
         let args = ["rusty-blockparser", "-s", "1", "-e", "2", "simplestats"];
@@ -399,4 +1386,223 @@ mod tests {
         let args = ["rusty-blockparser", "-s", "2", "-e", "1", "simplestats"];
         assert!(parse_args(command().get_matches_from(args)).is_err());
     }
+
+    #[test]
+    fn test_args_snapshot_height() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let dump_folder = tmp_dir.path().to_str().unwrap();
+        let args = [
+            "rusty-blockparser",
+            "balances",
+            dump_folder,
+            "--snapshot-height",
+            "420000,210000",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(
+            options.range,
+            BlockHeightRange::new(0, Some(420000)).unwrap()
+        );
+
+        let args = [
+            "rusty-blockparser",
+            "-e",
+            "300000",
+            "balances",
+            dump_folder,
+            "--snapshot-height",
+            "420000,210000",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(
+            options.range,
+            BlockHeightRange::new(0, Some(300000)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_args_log_file() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.log_file, None);
+
+        let args = [
+            "rusty-blockparser",
+            "--log-file",
+            "parser.log",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(
+            options.log_file,
+            Some(LogFileOptions {
+                path: PathBuf::from("parser.log"),
+                max_size: 100 * 1024 * 1024,
+                max_files: 5,
+                format: LogFormat::Text,
+            })
+        );
+
+        let args = [
+            "rusty-blockparser",
+            "--log-file",
+            "parser.log",
+            "--log-max-size",
+            "10",
+            "--log-max-files",
+            "0",
+            "--log-format",
+            "json",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        let log_file = options.log_file.unwrap();
+        assert_eq!(log_file.max_size, 10 * 1024 * 1024);
+        assert_eq!(log_file.max_files, 0);
+        assert_eq!(log_file.format, LogFormat::Json);
+
+        // Rotation options require a log file
+        let args = ["rusty-blockparser", "--log-format", "json", "simplestats"];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_dates() {
+        let args = [
+            "rusty-blockparser",
+            "--start-date",
+            "2009-01-03",
+            "--end-date",
+            "2009-01-09",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.start_time, Some(1230940800));
+        assert_eq!(options.end_time, Some(1231459200 + 86400));
+
+        let args = [
+            "rusty-blockparser",
+            "--start-date",
+            "2009-01-09",
+            "--end-date",
+            "2009-01-03",
+            "simplestats",
+        ];
+        assert!(parse_args(command().get_matches_from(args)).is_err());
+
+        let args = [
+            "rusty-blockparser",
+            "-s",
+            "1",
+            "--start-date",
+            "2009-01-03",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_args_selection() {
+        let args = ["rusty-blockparser", "simplestats"];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.blk_files, None);
+        assert_eq!(options.heights, None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heights.txt");
+        std::fs::write(&path, "10\n5\n").unwrap();
+        let args = [
+            "rusty-blockparser",
+            "--only-blk-files",
+            "100-150",
+            "--heights-file",
+            path.to_str().unwrap(),
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.blk_files, Some((100, 150)));
+        assert_eq!(options.heights, Some(BTreeSet::from([5, 10])));
+
+        let args = [
+            "rusty-blockparser",
+            "--only-blk-files",
+            "150-100",
+            "simplestats",
+        ];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
+
+    #[test]
+    fn test_range_resolve() {
+        let range = BlockHeightRange::new(10, None).unwrap();
+        let resolved = range.resolve(100, true).unwrap();
+        assert_eq!(resolved, BlockHeightRange::new(10, Some(100)).unwrap());
+
+        let range = BlockHeightRange::new(10, Some(50)).unwrap();
+        let resolved = range.resolve(100, true).unwrap();
+        assert_eq!(resolved, range);
+
+        // Partially available
+        let range = BlockHeightRange::new(10, Some(200)).unwrap();
+        assert!(range.resolve(100, true).is_err());
+        let resolved = range.resolve(100, false).unwrap();
+        assert_eq!(resolved, BlockHeightRange::new(10, Some(100)).unwrap());
+
+        // Empty range, which would end before it starts
+        let range = BlockHeightRange::new(150, None).unwrap();
+        assert!(range.resolve(100, true).is_err());
+        assert!(range.resolve(100, false).is_err());
+        let range = BlockHeightRange::new(101, Some(200)).unwrap();
+        assert!(range.resolve(100, false).is_err());
+        assert!(BlockHeightRange::new(100, None)
+            .unwrap()
+            .resolve(100, false)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_range_shard() {
+        let range = BlockHeightRange::new(0, Some(99)).unwrap();
+        let shards: Vec<BlockHeightRange> = (1..=4).map(|i| range.shard(i, 4).unwrap()).collect();
+        assert_eq!(shards[0], BlockHeightRange::new(0, Some(24)).unwrap());
+        assert_eq!(shards[3], BlockHeightRange::new(75, Some(99)).unwrap());
+        // The first shards take one block more, all together cover the range exactly
+        for (start, end, count) in [(0, 9, 4), (10, 20, 3), (10, 20, 5), (0, 99, 7)] {
+            let range = BlockHeightRange::new(start, Some(end)).unwrap();
+            let mut next = start;
+            for i in 1..=count {
+                let shard = range.shard(i, count).unwrap();
+                assert_eq!(shard.start, next);
+                let size = shard.end.unwrap() - shard.start + 1;
+                assert!(size == (end - start + 1) / count || size == (end - start + 1) / count + 1);
+                next = shard.end.unwrap() + 1;
+            }
+            assert_eq!(next, end + 1);
+        }
+        let range = BlockHeightRange::new(10, Some(20)).unwrap();
+        assert_eq!(
+            range.shard(3, 3).unwrap(),
+            BlockHeightRange::new(18, Some(20)).unwrap()
+        );
+        assert_eq!(
+            range.shard(5, 5).unwrap(),
+            BlockHeightRange::new(19, Some(20)).unwrap()
+        );
+        // Every shard needs two blocks at least
+        assert!(range.shard(6, 6).is_err());
+        assert!(BlockHeightRange::new(0, None).unwrap().shard(1, 2).is_err());
+
+        let args = [
+            "rusty-blockparser",
+            "-e",
+            "99",
+            "--shard",
+            "2/4",
+            "simplestats",
+        ];
+        let options = parse_args(command().get_matches_from(args)).unwrap();
+        assert_eq!(options.range, BlockHeightRange::new(25, Some(49)).unwrap());
+        let args = ["rusty-blockparser", "--shard", "2/4", "simplestats"];
+        assert!(command().try_get_matches_from(args).is_err());
+    }
 }
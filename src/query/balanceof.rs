@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::errors::OpResult;
+use crate::query::{self, Format};
+
+/// Balance and number of rows found for an address
+#[derive(Default)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+struct Balance {
+    value: u64,
+    n_rows: u64,
+}
+
+/// Looks up the balances of the given addresses in a previously produced
+/// unspentcsvdump or balances file, without parsing the blockchain again.
+pub struct BalanceOf {
+    // Requested addresses in input order
+    addresses: Vec<String>,
+    balances: HashMap<String, Balance>,
+}
+
+impl BalanceOf {
+    pub fn build_subcommand() -> Command {
+        Command::new("balanceof")
+            .about("Looks up the balances of addresses in an existing unspent or balances CSV file")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("addresses")
+                    .help("File with one address per line")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("from")
+                    .long("from")
+                    .value_name("FILE")
+                    .required(true)
+                    .help(
+                        "CSV file written by unspentcsvdump or balances (may be gzip compressed)",
+                    ),
+            )
+    }
+
+    pub fn new(matches: &ArgMatches) -> OpResult<Self> {
+        let path = Path::new(matches.get_one::<String>("addresses").unwrap());
+        let mut addresses = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let address = line.trim();
+            if !address.is_empty() && !address.starts_with('#') {
+                addresses.push(String::from(address));
+            }
+        }
+        let balances = addresses
+            .iter()
+            .map(|address| (address.clone(), Balance::default()))
+            .collect();
+        Ok(Self {
+            addresses,
+            balances,
+        })
+    }
+
+    /// Reads the given dump and prints the balances to stdout
+    pub fn run(matches: &ArgMatches) -> OpResult<()> {
+        let mut query = BalanceOf::new(matches)?;
+        let path = Path::new(matches.get_one::<String>("from").unwrap());
+        info!(target: "balanceof", "Looking up {} addresses in {} ...", query.addresses.len(), path.display());
+        let n_rows = query.read(query::open_dump(path)?)?;
+        info!(target: "balanceof", "Scanned {} rows.", n_rows);
+
+        let mut writer = BufWriter::new(io::stdout());
+        query.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Accumulates the values of all rows of the requested addresses.
+    /// Returns the number of scanned rows.
+    fn read<R: BufRead>(&mut self, reader: R) -> OpResult<u64> {
+        let mut lines = reader.lines();
+        let (format, _) = Format::read_header(&mut lines)?;
+
+        let mut n_rows = 0;
+        for line in lines {
+            let line = line?;
+            n_rows += 1;
+            let (address, value) = match format.parse_row(&line) {
+                Some(row) => row,
+                None => {
+                    warn!(target: "balanceof", "Skipping malformed row: {}", line);
+                    continue;
+                }
+            };
+            if let Some(balance) = self.balances.get_mut(address) {
+                balance.value += value;
+                balance.n_rows += 1;
+            }
+        }
+        Ok(n_rows)
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "address;balance;rows")?;
+        for address in &self.addresses {
+            let balance = &self.balances[address];
+            writeln!(writer, "{};{};{}", address, balance.value, balance.n_rows)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let addresses = dir.path().join("addresses.txt");
+        std::fs::write(&addresses, "# watched\n1A\n\n1C\n").unwrap();
+        let matches = BalanceOf::build_subcommand().get_matches_from([
+            "balanceof",
+            addresses.to_str().unwrap(),
+            "--from",
+            "unspent.csv",
+        ]);
+        let mut query = BalanceOf::new(&matches).unwrap();
+        assert_eq!(query.addresses, ["1A", "1C"]);
+
+        let unspent = "txid;indexOut;height;value;address\n\
+                       aa;0;1;100;1A\n\
+                       bb;1;2;50;1B\n\
+                       cc;0;3;25;1A\n\
+                       broken\n";
+        assert_eq!(query.read(unspent.as_bytes()).unwrap(), 4);
+        let mut out = Vec::new();
+        query.write(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "address;balance;rows\n1A;125;2\n1C;0;0\n"
+        );
+
+        let mut query = BalanceOf::new(&matches).unwrap();
+        query
+            .read("address;balance;label\n1C;42;exchange\n".as_bytes())
+            .unwrap();
+        assert_eq!(
+            query.balances["1C"],
+            Balance {
+                value: 42,
+                n_rows: 1
+            }
+        );
+        assert!(query.read("foo;bar\n".as_bytes()).is_err());
+    }
+}
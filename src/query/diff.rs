@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::query::{self, Format};
+
+/// Number of changed rows
+#[derive(Default)]
+#[cfg_attr(test, derive(PartialEq, Debug))]
+struct Changes {
+    added: u64,
+    removed: u64,
+    changed: u64,
+}
+
+/// Compares two unspentcsvdump or balances dumps, e.g. two snapshots written with
+/// `--snapshot-height`, so downstream systems can apply the changes instead of reloading everything.
+pub struct Diff;
+
+impl Diff {
+    pub fn build_subcommand() -> Command {
+        Command::new("diff")
+            .about("Writes the created/spent outputs or balance deltas between two unspent or balances CSV files")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("old")
+                    .help("Older CSV file written by unspentcsvdump or balances (may be gzip compressed)")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("new")
+                    .help("Newer CSV file of the same kind")
+                    .index(2)
+                    .required(true),
+            )
+    }
+
+    /// Compares the given dumps and prints the changes to stdout
+    pub fn run(matches: &ArgMatches) -> OpResult<()> {
+        let old = Path::new(matches.get_one::<String>("old").unwrap());
+        let new = Path::new(matches.get_one::<String>("new").unwrap());
+        info!(target: "diff", "Comparing {} to {} ...", old.display(), new.display());
+
+        let mut writer = BufWriter::new(io::stdout());
+        let changes = diff(query::open_dump(old)?, query::open_dump(new)?, &mut writer)?;
+        writer.flush()?;
+        info!(target: "diff", "Done. {} added, {} removed, {} changed.", changes.added, changes.removed, changes.changed);
+        Ok(())
+    }
+}
+
+/// Writes the differences between both dumps:
+///   * unspent dumps: `change;<row>` with change `created` or `spent`
+///   * balances dumps: `address;old;new;delta` for each address with a different balance
+fn diff<O: BufRead, N: BufRead, W: Write>(old: O, new: N, writer: &mut W) -> OpResult<Changes> {
+    let mut old_lines = old.lines();
+    let mut new_lines = new.lines();
+    let (format, _) = Format::read_header(&mut old_lines)?;
+    let (new_format, header) = Format::read_header(&mut new_lines)?;
+    if format != new_format {
+        return Err(OpError::new(OpErrorKind::InvalidArgsError)
+            .join_msg("Both files must be either unspent or balances dumps"));
+    }
+
+    let mut changes = Changes::default();
+    match format {
+        Format::Unspent => {
+            // Outputs never change, so they are identified by txid;indexOut
+            let mut unspents = HashMap::new();
+            for line in old_lines {
+                let line = line?;
+                if let Some(outpoint) = outpoint(&line) {
+                    unspents.insert(String::from(outpoint), line);
+                }
+            }
+            writeln!(writer, "change;{}", header)?;
+            for line in new_lines {
+                let line = line?;
+                let outpoint = match outpoint(&line) {
+                    Some(outpoint) => outpoint,
+                    None => continue,
+                };
+                if unspents.remove(outpoint).is_none() {
+                    writeln!(writer, "created;{}", line)?;
+                    changes.added += 1;
+                }
+            }
+            let mut spent: Vec<String> = unspents.into_values().collect();
+            spent.sort_unstable();
+            for line in spent {
+                writeln!(writer, "spent;{}", line)?;
+                changes.removed += 1;
+            }
+        }
+        Format::Balances => {
+            let mut balances = HashMap::new();
+            for line in old_lines {
+                let line = line?;
+                if let Some((address, value)) = parse_row(format, &line) {
+                    balances.insert(String::from(address), value);
+                }
+            }
+            writeln!(writer, "address;old;new;delta")?;
+            for line in new_lines {
+                let line = line?;
+                let (address, value) = match parse_row(format, &line) {
+                    Some(row) => row,
+                    None => continue,
+                };
+                match balances.remove(address) {
+                    Some(old) if old == value => {}
+                    Some(old) => {
+                        write_delta(writer, address, old, value)?;
+                        changes.changed += 1;
+                    }
+                    None => {
+                        write_delta(writer, address, 0, value)?;
+                        changes.added += 1;
+                    }
+                }
+            }
+            let mut emptied: Vec<(String, u64)> = balances.into_iter().collect();
+            emptied.sort_unstable();
+            for (address, old) in emptied {
+                write_delta(writer, &address, old, 0)?;
+                changes.removed += 1;
+            }
+        }
+    }
+    Ok(changes)
+}
+
+fn write_delta<W: Write>(writer: &mut W, address: &str, old: u64, new: u64) -> io::Result<()> {
+    // (address, old, new, delta)
+    writeln!(
+        writer,
+        "{};{};{};{}",
+        address,
+        old,
+        new,
+        new as i128 - old as i128
+    )
+}
+
+/// Returns the `txid;indexOut` prefix of an unspent row
+fn outpoint(row: &str) -> Option<&str> {
+    let mut separators = row.match_indices(';').map(|(i, _)| i);
+    let end = separators.nth(1);
+    if end.is_none() {
+        warn!(target: "diff", "Skipping malformed row: {}", row);
+    }
+    end.map(|end| &row[..end])
+}
+
+fn parse_row(format: Format, row: &str) -> Option<(&str, u64)> {
+    let parsed = format.parse_row(row);
+    if parsed.is_none() {
+        warn!(target: "diff", "Skipping malformed row: {}", row);
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_diff(old: &str, new: &str) -> (String, Changes) {
+        let mut out = Vec::new();
+        let changes = diff(old.as_bytes(), new.as_bytes(), &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), changes)
+    }
+
+    #[test]
+    fn test_diff_unspent() {
+        let old = "txid;indexOut;height;value;address\n\
+                   aa;0;1;100;1A\n\
+                   aa;1;1;50;1B\n";
+        let new = "txid;indexOut;height;value;address\n\
+                   aa;1;1;50;1B\n\
+                   bb;0;2;90;1C\n";
+        let (out, changes) = run_diff(old, new);
+        assert_eq!(
+            out,
+            "change;txid;indexOut;height;value;address\n\
+             created;bb;0;2;90;1C\n\
+             spent;aa;0;1;100;1A\n"
+        );
+        assert_eq!(
+            changes,
+            Changes {
+                added: 1,
+                removed: 1,
+                changed: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_diff_balances() {
+        let old = "address;balance\n1A;100\n1B;50\n1C;10\n";
+        let new = "address;balance\n1B;50\n1C;25\n1D;5\n";
+        let (out, _) = run_diff(old, new);
+        assert_eq!(
+            out,
+            "address;old;new;delta\n1C;10;25;15\n1D;0;5;5\n1A;100;0;-100\n"
+        );
+
+        let unspent = "txid;indexOut;height;value;address\n";
+        assert!(diff(old.as_bytes(), unspent.as_bytes(), &mut Vec::new()).is_err());
+
+        // Rows without address are joined by their script hash
+        let old = "address;balance;scriptHash\n1A;100;\n;20;ab\n;30;cd\n";
+        let new = "address;balance;scriptHash\n1A;100;\n;30;cd\n;25;ab\n";
+        let (out, changes) = run_diff(old, new);
+        assert_eq!(out, "address;old;new;delta\nab;20;25;5\n");
+        assert_eq!(changes.changed, 1);
+    }
+}
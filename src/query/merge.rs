@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgMatches, Command};
+
+use crate::callbacks::sink::Output;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::query;
+
+/// A csv file dumped by a callback, named `<name>-<start>-<end>.csv[.gz]`
+#[cfg_attr(test, derive(Debug))]
+pub(crate) struct DumpFile {
+    path: PathBuf,
+    name: String,
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+}
+
+impl DumpFile {
+    pub(crate) fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+        let (stem, ext) = file_name.split_once('.')?;
+        if ext != "csv" && ext != "csv.gz" {
+            return None;
+        }
+        let mut parts = stem.rsplitn(3, '-');
+        let end = parts.next()?.parse().ok()?;
+        let start = parts.next()?.parse().ok()?;
+        Some(Self {
+            path: PathBuf::from(path),
+            name: String::from(parts.next()?),
+            start,
+            end,
+        })
+    }
+
+    /// Returns the first line of the file
+    fn first_line(&self) -> OpResult<Option<String>> {
+        Ok(query::open_dump(&self.path)?.lines().next().transpose()?)
+    }
+}
+
+/// How the rows of the shards are combined
+#[derive(PartialEq)]
+enum Mode {
+    /// Files without header are concatenated, they must not overlap
+    Concat,
+    /// Files with a height column are concatenated, rows of already covered heights are dropped
+    ByHeight(usize),
+    /// Rows of statistics with the same key columns are summed up
+    Aggregate,
+}
+
+/// Combines the outputs of runs with `--shard i/N` into one file per callback output,
+/// as if the whole range was parsed at once. Only callbacks which don't depend on the state
+/// of earlier blocks produce the same result when sharded.
+pub struct Merge;
+
+impl Merge {
+    pub fn build_subcommand() -> Command {
+        Command::new("merge")
+            .about("Combines the CSV files of sharded runs (see --shard) into one file per output")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("dump-folder")
+                    .help("Folder to store the merged csv files")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("inputs")
+                    .help("Dump folders or csv files of the shards")
+                    .index(2)
+                    .num_args(1..)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("key-columns")
+                    .long("key-columns")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(usize))
+                    .default_value("1")
+                    .help("Number of leading columns identifying a row of statistics without height column, e.g. the month"),
+            )
+    }
+
+    pub fn run(matches: &ArgMatches) -> OpResult<()> {
+        let output = Output::from_matches(matches)?;
+        if let Output::Stdout(_) = output {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("merge writes multiple files and can't write them to stdout"));
+        }
+        let key_columns = *matches.get_one::<usize>("key-columns").unwrap();
+
+        let mut groups: BTreeMap<String, Vec<DumpFile>> = BTreeMap::new();
+        for input in matches.get_many::<String>("inputs").unwrap() {
+            for file in collect(Path::new(input))? {
+                groups.entry(file.name.clone()).or_default().push(file);
+            }
+        }
+        if groups.is_empty() {
+            return Err(OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg("No csv files named <name>-<start>-<end>.csv found"));
+        }
+        for (name, mut files) in groups {
+            files.sort_by_key(|file| (file.start, file.end));
+            let (start, end) = (files[0].start, files.iter().map(|f| f.end).max().unwrap());
+            info!(target: "merge", "Merging {} files of {} ...", files.len(), name);
+            let mut writer = output.open(&name)?;
+            merge(&files, key_columns, &mut writer)?;
+            writer.finish(start, end)?;
+        }
+        info!(target: "merge", "Done.");
+        Ok(())
+    }
+}
+
+/// Returns the dumped csv files in the given folder, or the given file itself
+fn collect(path: &Path) -> OpResult<Vec<DumpFile>> {
+    let paths = match path.is_dir() {
+        true => fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()?,
+        false => vec![PathBuf::from(path)],
+    };
+    let files: Vec<DumpFile> = paths
+        .iter()
+        .filter_map(|p| DumpFile::from_path(p))
+        .collect();
+    if files.is_empty() {
+        warn!(target: "merge", "No csv files named <name>-<start>-<end>.csv in {}", path.display());
+    }
+    Ok(files)
+}
+
+/// Writes the rows of all files, which must be sorted by their range
+fn merge<W: Write + ?Sized>(
+    files: &[DumpFile],
+    key_columns: usize,
+    writer: &mut W,
+) -> OpResult<()> {
+    for pair in files.windows(2) {
+        if pair[1].start > pair[0].end + 1 {
+            warn!(target: "merge", "{}: heights {} to {} are missing", pair[0].name, pair[0].end + 1, pair[1].start - 1);
+        }
+    }
+    // Dumps with header start with the same line in all shards
+    let mut first_lines = Vec::with_capacity(files.len());
+    for file in files {
+        first_lines.push(file.first_line()?);
+    }
+    let header = match files.len() > 1 && first_lines.windows(2).all(|w| w[0] == w[1]) {
+        true => first_lines[0].clone(),
+        false => None,
+    };
+    let mode = match &header {
+        None => Mode::Concat,
+        Some(header) => match header.split(';').position(|column| column == "height") {
+            Some(i) => Mode::ByHeight(i),
+            None => Mode::Aggregate,
+        },
+    };
+    if let Some(header) = &header {
+        writeln!(writer, "{}", header)?;
+    }
+
+    let mut sums: BTreeMap<Vec<String>, Vec<u64>> = BTreeMap::new();
+    // Rows up to this height were already written
+    let mut covered: Option<u64> = None;
+    for file in files {
+        if mode == Mode::Concat && covered.is_some_and(|covered| file.start <= covered) {
+            let msg = format!(
+                "{} overlaps with the previous shard and has no height column",
+                file.path.display()
+            );
+            return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+        }
+        let mut lines = query::open_dump(&file.path)?.lines();
+        if header.is_some() {
+            lines.next();
+        }
+        for line in lines {
+            let line = line?;
+            match mode {
+                Mode::Concat => writeln!(writer, "{}", line)?,
+                Mode::ByHeight(i) => {
+                    let height = line.split(';').nth(i).and_then(|h| h.parse::<u64>().ok());
+                    if height.is_some_and(|height| covered.is_some_and(|c| height <= c)) {
+                        continue;
+                    }
+                    writeln!(writer, "{}", line)?;
+                }
+                Mode::Aggregate => add_row(&mut sums, &line, key_columns, file)?,
+            }
+        }
+        covered = covered.max(Some(file.end));
+    }
+
+    for (key, values) in sums {
+        let values: Vec<String> = values.iter().map(u64::to_string).collect();
+        writeln!(writer, "{};{}", key.join(";"), values.join(";"))?;
+    }
+    Ok(())
+}
+
+/// Adds the values of the given row to the sums of its key
+fn add_row(
+    sums: &mut BTreeMap<Vec<String>, Vec<u64>>,
+    line: &str,
+    key_columns: usize,
+    file: &DumpFile,
+) -> OpResult<()> {
+    let columns: Vec<&str> = line.split(';').collect();
+    let key_columns = key_columns.min(columns.len());
+    let mut values = Vec::with_capacity(columns.len() - key_columns);
+    for value in &columns[key_columns..] {
+        match value.parse::<u64>() {
+            Ok(value) => values.push(value),
+            Err(_) => {
+                let msg = format!(
+                    "{}: can't sum up '{}' in row '{}', use --key-columns to mark key columns",
+                    file.path.display(),
+                    value,
+                    line
+                );
+                return Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg));
+            }
+        }
+    }
+    let key = columns[..key_columns]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+    let sum = sums.entry(key).or_default();
+    if sum.len() < values.len() {
+        sum.resize(values.len(), 0);
+    }
+    sum.iter_mut()
+        .zip(values)
+        .for_each(|(sum, value)| *sum += value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the given shards of a dump and returns them sorted by range
+    fn write_shards(dir: &Path, name: &str, shards: &[(u64, u64, &str)]) -> Vec<DumpFile> {
+        let mut files = Vec::new();
+        for (start, end, content) in shards {
+            let path = dir.join(format!("{}-{}-{}.csv", name, start, end));
+            fs::write(&path, content).unwrap();
+            files.push(DumpFile::from_path(&path).unwrap());
+        }
+        files
+    }
+
+    fn merged(files: &[DumpFile], key_columns: usize) -> String {
+        let mut out = Vec::new();
+        merge(files, key_columns, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_dump_file() {
+        let file = DumpFile::from_path(Path::new("/tmp/tx_in-100-199.csv.gz")).unwrap();
+        assert_eq!(
+            (file.name.as_str(), file.start, file.end),
+            ("tx_in", 100, 199)
+        );
+        assert!(DumpFile::from_path(Path::new("/tmp/tx_in.csv.tmp")).is_none());
+        assert!(DumpFile::from_path(Path::new("/tmp/blocks-1-2.pb")).is_none());
+    }
+
+    #[test]
+    fn test_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        // Without header the files are concatenated
+        let files = write_shards(
+            dir.path(),
+            "blocks",
+            &[(0, 1, "a;0\nb;1\n"), (2, 3, "c;2\n")],
+        );
+        assert_eq!(merged(&files, 1), "a;0\nb;1\nc;2\n");
+        let files = write_shards(dir.path(), "blocks", &[(0, 1, "a;0\n"), (1, 3, "b;1\n")]);
+        assert!(merge(&files, 1, &mut Vec::new()).is_err());
+
+        // Rows of heights covered by the previous shard are dropped
+        let files = write_shards(
+            dir.path(),
+            "supply",
+            &[
+                (0, 2, "height;supply\n0;50\n1;100\n2;150\n"),
+                (2, 3, "height;supply\n2;150\n3;200\n"),
+            ],
+        );
+        assert_eq!(
+            merged(&files, 1),
+            "height;supply\n0;50\n1;100\n2;150\n3;200\n"
+        );
+
+        // Statistics are summed up by key
+        let files = write_shards(
+            dir.path(),
+            "scriptflows",
+            &[
+                (0, 9, "month;from;to;value\n2009-01;A;B;10\n2009-02;A;B;1\n"),
+                (
+                    10,
+                    19,
+                    "month;from;to;value\n2009-02;A;B;2\n2009-02;A;C;3\n",
+                ),
+            ],
+        );
+        assert_eq!(
+            merged(&files, 3),
+            "month;from;to;value\n2009-01;A;B;10\n2009-02;A;B;3\n2009-02;A;C;3\n"
+        );
+        assert!(merge(&files, 1, &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+
+use crate::errors::{OpError, OpErrorKind, OpResult};
+
+pub mod balanceof;
+pub mod diff;
+pub mod merge;
+pub mod verifybalances;
+
+/// Columns of the supported dumps, detected by their header
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+enum Format {
+    // txid;indexOut;height;value;address[;scriptHash][;label] (unspentcsvdump)
+    Unspent,
+    // address;balance[;scriptHash][;label] (balances)
+    Balances,
+}
+
+impl Format {
+    fn detect(header: &str) -> Option<Self> {
+        match header.split(';').next() {
+            Some("txid") => Some(Format::Unspent),
+            Some("address") => Some(Format::Balances),
+            _ => None,
+        }
+    }
+
+    /// Reads the header line and detects the format, returns both
+    fn read_header<R: BufRead>(lines: &mut Lines<R>) -> OpResult<(Self, String)> {
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let format = Format::detect(&header).ok_or_else(|| {
+            OpError::new(OpErrorKind::InvalidArgsError)
+                .join_msg(&format!("Unknown csv header: {}", header))
+        })?;
+        Ok((format, header))
+    }
+
+    /// Returns the address and value of the given row.
+    /// Rows without address are identified by their script hash.
+    fn parse_row<'a>(&self, row: &'a str) -> Option<(&'a str, u64)> {
+        let mut fields = row.split(';');
+        let (address, value) = match self {
+            Format::Unspent => {
+                let mut fields = fields.by_ref().skip(3);
+                let value = fields.next()?;
+                (fields.next()?, value)
+            }
+            Format::Balances => (fields.next()?, fields.next()?),
+        };
+        let address = match address {
+            "" => fields.next().filter(|hash| !hash.is_empty())?,
+            address => address,
+        };
+        Some((address, value.parse().ok()?))
+    }
+}
+
+/// Opens a csv dump, files ending with `.gz` are decompressed on the fly
+pub(crate) fn open_dump(path: &Path) -> OpResult<BufReader<Box<dyn Read>>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = match path.extension() {
+        Some(ext) if ext == "gz" => Box::new(GzDecoder::new(file)),
+        _ => Box::new(file),
+    };
+    Ok(BufReader::with_capacity(4000000, reader))
+}
@@ -0,0 +1,498 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::Address;
+use clap::{Arg, ArgMatches, Command};
+
+use crate::common::utils;
+use crate::errors::{OpError, OpErrorKind, OpResult};
+use crate::query::merge::DumpFile;
+use crate::query::{self, Format};
+
+/// Timeout for connecting to and reading from the server
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Result of the comparison of a sampled address
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+enum Status {
+    Ok,
+    /// The balance differs, but the address has transactions after the dumped height
+    Changed,
+    Mismatch,
+    /// The server couldn't be asked about the address
+    Error,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Changed => "changed",
+            Status::Mismatch => "mismatch",
+            Status::Error => "error",
+        }
+    }
+}
+
+/// SplitMix64, small random number generator to make samples reproducible with --seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Samples addresses of a balances dump and compares their balances
+/// with the confirmed balances reported by an Electrum or Esplora server.
+pub struct VerifyBalances;
+
+impl VerifyBalances {
+    pub fn build_subcommand() -> Command {
+        Command::new("verify-balances")
+            .about("Compares the balances of random addresses in a balances CSV file with an Electrum or Esplora server")
+            .version("0.1")
+            .author("gcarq <egger.m@protonmail.com>")
+            .arg(
+                Arg::new("balances")
+                    .help("CSV file written by balances (may be gzip compressed)")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("server")
+                    .long("server")
+                    .value_name("URL")
+                    .required(true)
+                    .help("Electrum server (tcp://host:50001) or Esplora API (http://host:3000)"),
+            )
+            .arg(
+                Arg::new("samples")
+                    .long("samples")
+                    .value_name("N")
+                    .value_parser(clap::value_parser!(usize))
+                    .default_value("100")
+                    .help("Number of addresses to check"),
+            )
+            .arg(
+                Arg::new("seed")
+                    .long("seed")
+                    .value_name("SEED")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Seed of the random sample, to check the same addresses again"),
+            )
+            .arg(
+                Arg::new("height")
+                    .long("height")
+                    .value_name("HEIGHT")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Height the balances were dumped at, taken from the file name by default"),
+            )
+    }
+
+    /// Writes the result of each sampled address to stdout,
+    /// fails if any balance differs without later transactions.
+    pub fn run(matches: &ArgMatches) -> OpResult<()> {
+        let path = Path::new(matches.get_one::<String>("balances").unwrap());
+        let height = match matches.get_one::<u64>("height") {
+            Some(height) => *height,
+            None => DumpFile::from_path(path).map(|f| f.end).ok_or_else(|| {
+                OpError::new(OpErrorKind::InvalidArgsError)
+                    .join_msg("Unable to get the height from the file name, use --height")
+            })?,
+        };
+        let seed = matches.get_one::<u64>("seed").copied().unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        });
+        let n = *matches.get_one::<usize>("samples").unwrap();
+        info!(target: "verify-balances", "Sampling {} addresses from {} (seed {}) ...", n, path.display(), seed);
+        let samples = sample(query::open_dump(path)?, n, &mut Rng(seed))?;
+
+        let mut server = Server::connect(matches.get_one::<String>("server").unwrap())?;
+        let mut writer = BufWriter::new(io::stdout());
+        writeln!(writer, "address;dump;server;status")?;
+        let mut counts = [0; 4];
+        for (address, expected) in &samples {
+            let (actual, status) = match server.check(address, *expected, height) {
+                Ok(result) => result,
+                Err(why) => {
+                    warn!(target: "verify-balances", "Unable to check {}: {}", address, why);
+                    (None, Status::Error)
+                }
+            };
+            let actual = actual.map_or(String::new(), |a| a.to_string());
+            writeln!(
+                writer,
+                "{};{};{};{}",
+                address,
+                expected,
+                actual,
+                status.as_str()
+            )?;
+            counts[status as usize] += 1;
+        }
+        writer.flush()?;
+
+        info!(target: "verify-balances", "Checked {} addresses at height {}: {} ok, {} changed since, {} mismatches, {} errors",
+              samples.len(), height, counts[0], counts[1], counts[2], counts[3]);
+        if counts[Status::Mismatch as usize] > 0 {
+            let msg = format!(
+                "{} of {} balances differ from the server",
+                counts[Status::Mismatch as usize],
+                samples.len()
+            );
+            return Err(OpError::new(OpErrorKind::ValidationError).join_msg(&msg));
+        }
+        Ok(())
+    }
+}
+
+/// Picks `n` random rows with address of a balances dump (reservoir sampling)
+fn sample<R: BufRead>(reader: R, n: usize, rng: &mut Rng) -> OpResult<Vec<(String, u64)>> {
+    let mut lines = reader.lines();
+    let (format, _) = Format::read_header(&mut lines)?;
+    if format != Format::Balances {
+        return Err(OpError::new(OpErrorKind::InvalidArgsError)
+            .join_msg("verify-balances needs a csv file written by balances"));
+    }
+    let mut samples = Vec::with_capacity(n);
+    let mut seen = 0;
+    for line in lines {
+        let line = line?;
+        // Rows without address can't be looked up
+        if line.starts_with(';') {
+            continue;
+        }
+        let row = match format.parse_row(&line) {
+            Some((address, value)) => (String::from(address), value),
+            None => continue,
+        };
+        seen += 1;
+        if samples.len() < n {
+            samples.push(row);
+        } else {
+            let i = (rng.next() % seen) as usize;
+            if i < n {
+                samples[i] = row;
+            }
+        }
+    }
+    Ok(samples)
+}
+
+enum Server {
+    Electrum(Electrum),
+    /// Host and path prefix of the API
+    Esplora(String, String),
+}
+
+impl Server {
+    fn connect(url: &str) -> OpResult<Self> {
+        match url.split_once("://") {
+            Some(("tcp", host)) => Ok(Server::Electrum(Electrum::connect(host)?)),
+            Some(("http", rest)) => {
+                let (host, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+                let prefix = prefix.trim_end_matches('/');
+                let prefix = match prefix.is_empty() {
+                    true => String::new(),
+                    false => format!("/{}", prefix),
+                };
+                Ok(Server::Esplora(String::from(host), prefix))
+            }
+            _ => {
+                let msg = format!(
+                    "Unsupported server {}, use tcp://host:port for Electrum or http://host:port for Esplora (TLS is not supported)",
+                    url
+                );
+                Err(OpError::new(OpErrorKind::InvalidArgsError).join_msg(&msg))
+            }
+        }
+    }
+
+    /// Returns the confirmed balance of the server and the status
+    fn check(
+        &mut self,
+        address: &str,
+        expected: u64,
+        height: u64,
+    ) -> OpResult<(Option<u64>, Status)> {
+        let actual = self.balance(address)?;
+        let status = if actual == expected {
+            Status::Ok
+        } else if self.last_height(address)? > Some(height) {
+            Status::Changed
+        } else {
+            Status::Mismatch
+        };
+        Ok((Some(actual), status))
+    }
+
+    fn balance(&mut self, address: &str) -> OpResult<u64> {
+        let balance = match self {
+            Server::Electrum(electrum) => {
+                let response =
+                    electrum.call("blockchain.scripthash.get_balance", &script_hash(address)?)?;
+                json_numbers(&response, "confirmed").first().copied()
+            }
+            Server::Esplora(host, prefix) => {
+                let response = http_get(host, &format!("{}/address/{}", prefix, address))?;
+                // Confirmed stats come first, followed by mempool_stats
+                let chain_stats = response.split("mempool_stats").next().unwrap();
+                let funded = json_numbers(chain_stats, "funded_txo_sum").first().copied();
+                let spent = json_numbers(chain_stats, "spent_txo_sum").first().copied();
+                funded.zip(spent).map(|(funded, spent)| funded - spent)
+            }
+        };
+        balance.and_then(|b| u64::try_from(b).ok()).ok_or_else(|| {
+            OpError::new(OpErrorKind::RuntimeError).join_msg("Unexpected response from server")
+        })
+    }
+
+    /// Returns the height of the last confirmed transaction of the address.
+    /// Esplora only returns the latest 50 transactions, which is enough to find the last one.
+    fn last_height(&mut self, address: &str) -> OpResult<Option<u64>> {
+        let heights = match self {
+            Server::Electrum(electrum) => {
+                let response =
+                    electrum.call("blockchain.scripthash.get_history", &script_hash(address)?)?;
+                json_numbers(&response, "height")
+            }
+            Server::Esplora(host, prefix) => {
+                let response = http_get(host, &format!("{}/address/{}/txs", prefix, address))?;
+                json_numbers(&response, "block_height")
+            }
+        };
+        // Unconfirmed transactions have a height <= 0 in Electrum
+        Ok(heights
+            .into_iter()
+            .filter(|h| *h > 0)
+            .map(|h| h as u64)
+            .max())
+    }
+}
+
+/// Client of the Electrum protocol, which sends JSON-RPC requests separated by newlines
+struct Electrum {
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+    id: u64,
+}
+
+impl Electrum {
+    fn connect(host: &str) -> OpResult<Self> {
+        let stream = connect(host)?;
+        let mut electrum = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            stream,
+            id: 0,
+        };
+        electrum.call("server.version", "rusty-blockparser\", \"1.4")?;
+        Ok(electrum)
+    }
+
+    /// Calls the method with a single string param and returns the response
+    fn call(&mut self, method: &str, param: &str) -> OpResult<String> {
+        self.id += 1;
+        let request = format!(
+            "{{\"jsonrpc\": \"2.0\", \"id\": {}, \"method\": \"{}\", \"params\": [\"{}\"]}}\n",
+            self.id, method, param
+        );
+        self.stream.write_all(request.as_bytes())?;
+        let mut response = String::new();
+        self.reader.read_line(&mut response)?;
+        if response.is_empty() || response.contains("\"error\"") {
+            let msg = format!("{} failed: {}", method, response.trim());
+            return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+        }
+        Ok(response)
+    }
+}
+
+fn connect(host: &str) -> OpResult<TcpStream> {
+    let stream = TcpStream::connect(host)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok(stream)
+}
+
+/// Sends a HTTP/1.0 GET request, so the body is neither chunked nor kept alive
+fn http_get(host: &str, path: &str) -> OpResult<String> {
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, host);
+    http_request(host, &request, &format!("GET {}", path))
+}
+
+/// Sends a HTTP/1.0 POST request with a JSON body, e.g. to a webhook
+pub(crate) fn http_post(host: &str, path: &str, body: &str) -> OpResult<String> {
+    let request = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    );
+    http_request(host, &request, &format!("POST {}", path))
+}
+
+/// Sends the request and returns the body of a successful (2xx) response
+fn http_request(host: &str, request: &str, name: &str) -> OpResult<String> {
+    let mut stream = connect(host)?;
+    stream.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head.lines().next().unwrap_or_default();
+    if !status
+        .split(' ')
+        .nth(1)
+        .is_some_and(|code| code.starts_with('2'))
+    {
+        let msg = format!("{} failed: {} {}", name, status, body.trim());
+        return Err(OpError::new(OpErrorKind::RuntimeError).join_msg(&msg));
+    }
+    Ok(String::from(body))
+}
+
+/// Returns the Electrum script hash of the address, the sha256 of its script_pubkey in reversed byte order
+fn script_hash(address: &str) -> OpResult<String> {
+    let address = Address::from_str(address).map_err(|e| {
+        OpError::new(OpErrorKind::InvalidArgsError).join_msg(&format!("{}: {}", address, e))
+    })?;
+    let script = address.assume_checked().script_pubkey();
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    Ok(utils::arr_to_hex(&hash))
+}
+
+/// Returns the numbers of all occurrences of `"key": <number>` in the JSON document.
+/// The responses used here are small and flat, so this saves a JSON parser.
+fn json_numbers(json: &str, key: &str) -> Vec<i64> {
+    let pattern = format!("\"{}\"", key);
+    json.match_indices(&pattern)
+        .filter_map(|(i, _)| {
+            let rest = json[i + pattern.len()..].trim_start().strip_prefix(':')?;
+            let rest = rest.trim_start();
+            let end = rest
+                .char_indices()
+                .find(|(i, c)| !(c.is_ascii_digit() || (*i == 0 && *c == '-')))
+                .map_or(rest.len(), |(i, _)| i);
+            rest[..end].parse().ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    /// Serves one connection, answering each request line with the next response
+    fn serve(responses: Vec<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut stream = stream;
+            for response in responses {
+                // HTTP requests end with an empty line
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                while request.starts_with("GET") && !request.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut request).unwrap();
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        host
+    }
+
+    #[test]
+    fn test_sample() {
+        let dump = "address;balance\n1A;1\n;2;hash\n1B;3\n1C;4\n";
+        let samples = sample(dump.as_bytes(), 2, &mut Rng(1)).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert!(samples.iter().all(|(address, _)| !address.is_empty()));
+        // Same seed, same sample
+        assert_eq!(sample(dump.as_bytes(), 2, &mut Rng(1)).unwrap(), samples);
+        assert_eq!(sample(dump.as_bytes(), 5, &mut Rng(1)).unwrap().len(), 3);
+        assert!(sample("txid;indexOut\n".as_bytes(), 1, &mut Rng(1)).is_err());
+    }
+
+    #[test]
+    fn test_json_numbers() {
+        let json = r#"{"result": {"confirmed": 103873966, "unconfirmed": -23684}}"#;
+        assert_eq!(json_numbers(json, "confirmed"), [103873966]);
+        assert_eq!(json_numbers(json, "unconfirmed"), [-23684]);
+        let json = r#"[{"height":200004,"tx_hash":"ab"},{"height": 0,"tx_hash":"cd"}]"#;
+        assert_eq!(json_numbers(json, "height"), [200004, 0]);
+        assert!(json_numbers(json, "fee").is_empty());
+    }
+
+    #[test]
+    fn test_script_hash() {
+        // Example of the Electrum protocol documentation
+        assert_eq!(
+            script_hash("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa").unwrap(),
+            "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+        );
+    }
+
+    #[test]
+    fn test_electrum() {
+        let address = "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa";
+        let responses = [
+            r#"{"jsonrpc": "2.0", "result": ["ElectrumX 1.16", "1.4"], "id": 1}"#,
+            r#"{"jsonrpc": "2.0", "result": {"confirmed": 5000000000, "unconfirmed": 0}, "id": 2}"#,
+            r#"{"jsonrpc": "2.0", "result": {"confirmed": 6000000000, "unconfirmed": 0}, "id": 3}"#,
+            r#"{"jsonrpc": "2.0", "result": [{"height": 0, "tx_hash": "aa"}, {"height": 12, "tx_hash": "bb"}], "id": 4}"#,
+            r#"{"jsonrpc": "2.0", "error": {"code": 1, "message": "unknown"}, "id": 5}"#,
+        ];
+        let host = serve(responses.iter().map(|r| format!("{}\n", r)).collect());
+        let mut server = Server::connect(&format!("tcp://{}", host)).unwrap();
+        assert_eq!(
+            server.check(address, 5000000000, 10).unwrap(),
+            (Some(5000000000), Status::Ok)
+        );
+        assert_eq!(
+            server.check(address, 5000000000, 10).unwrap(),
+            (Some(6000000000), Status::Changed)
+        );
+        assert!(server.balance(address).is_err());
+    }
+
+    #[test]
+    fn test_esplora() {
+        let body = r#"{"address":"1A","chain_stats":{"funded_txo_count":2,"funded_txo_sum":300,"spent_txo_count":1,"spent_txo_sum":100,"tx_count":3},"mempool_stats":{"funded_txo_count":0,"funded_txo_sum":0,"spent_txo_count":0,"spent_txo_sum":0,"tx_count":0}}"#;
+        let txs = r#"[{"txid":"aa","status":{"confirmed":true,"block_height":5}}]"#;
+        let host = serve(vec![format!(
+            "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n{}",
+            body
+        )]);
+        let mut server = Server::connect(&format!("http://{}/api/", host)).unwrap();
+        assert_eq!(server.balance("1A").unwrap(), 200);
+
+        let host = serve(vec![format!("HTTP/1.0 200 OK\r\n\r\n{}", txs)]);
+        let mut server = Server::connect(&format!("http://{}", host)).unwrap();
+        assert_eq!(server.last_height("1A").unwrap(), Some(5));
+
+        let host = serve(vec![String::from(
+            "HTTP/1.0 400 Bad Request\r\n\r\nInvalid address",
+        )]);
+        let mut server = Server::connect(&format!("http://{}", host)).unwrap();
+        assert!(server.balance("foo").is_err());
+        assert!(Server::connect("ssl://localhost:50002").is_err());
+    }
+}